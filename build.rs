@@ -0,0 +1,10 @@
+// Generates the gRPC ingestion service from proto/ with the protoc shipped as a build
+// dependency, so neither the Docker image nor developer machines need one installed
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut config = tonic_build::Config::new();
+    config.protoc_executable(protoc_bin_vendored::protoc_bin_path()?);
+    tonic_build::configure()
+        .build_client(false)
+        .compile_protos_with_config(config, &["proto/ingest.proto"], &["proto"])?;
+    Ok(())
+}
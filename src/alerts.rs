@@ -0,0 +1,470 @@
+//! Alert rules, managed through `/api/alerts/rules`, and the alerts they raise. `speed` and
+//! `geofence` rules are checked by the classification worker as points arrive, `inactivity`
+//! rules by a periodic task looking for silent devices. Every alert is stored in `alerts`
+//! (`GET /api/alerts`), sent to the webhooks subscribed to `alert` and to
+//! `GET /api/alerts/stream`.
+
+use chrono::{DateTime, Duration, Utc};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
+use utoipa::ToSchema;
+
+use crate::anomaly::PointSample;
+use crate::database::model::alert_rules::Model as AlertRuleModel;
+use crate::database::model::alerts::Model as AlertModel;
+use crate::database::model::geofence_events::Model as GeofenceEventModel;
+use crate::database::store::{
+    AlertFilter, AlertRuleSettings, AlertStore, BBox, DeviceFilter, DeviceStore, PublicationDelay, StoreResult, TenantScope,
+};
+use crate::feed::{Feed, FeedEvent};
+use crate::geofences::{self, Geofences};
+use crate::metrics::metrics;
+use crate::webhooks::{WebhookEvent, Webhooks};
+
+/// Name of the inactivity checks on the status page
+const JOB_NAME: &str = "inactivity alerts";
+
+/// Devices read per query of an inactivity check
+const DEVICE_PAGE: u64 = 1000;
+
+/// How often trips that stopped speeding for ALERT_TRIP_IDLE_SECS are forgotten
+const SWEEP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleKind {
+    /// A point faster than `maxSpeed`, inside `geofenceId` when given; once per trip
+    Speed,
+    /// A trip entering or leaving `geofenceId`
+    Geofence,
+    /// A device without a trip ending for `silentMinutes`; once per silence
+    Inactivity,
+}
+
+impl RuleKind {
+    pub const ALL: [RuleKind; 3] = [RuleKind::Speed, RuleKind::Geofence, RuleKind::Inactivity];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RuleKind::Speed => "speed",
+            RuleKind::Geofence => "geofence",
+            RuleKind::Inactivity => "inactivity",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|k| k.as_str() == name.trim())
+    }
+}
+
+/// Thresholds of a rule; which ones apply depends on its kind
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RuleParams {
+    /// speed: km/h a point must exceed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_speed: Option<f64>,
+    /// speed: only points inside this geofence; geofence: the geofence watched
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub geofence_id: Option<i64>,
+    /// geofence: `entry`, `exit` or both (the default)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on: Option<Vec<String>>,
+    /// inactivity: minutes without a trip ending
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub silent_minutes: Option<i64>,
+    /// inactivity: only this device instead of every device of the tenant
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<String>,
+}
+
+/// Params of a stored rule; unreadable ones are empty, which no point or device meets
+pub fn params_of(rule: &AlertRuleModel) -> RuleParams {
+    serde_json::from_value(rule.params.clone()).unwrap_or_default()
+}
+
+/// An alert as the history, the stream and the `alert` webhooks show it
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Alert {
+    /// Grows with every alert, so it doubles as the event id of the stream
+    pub id: i64,
+    pub rule_id: i64,
+    /// speed, geofence or inactivity
+    pub kind: String,
+    pub tenant_id: Option<String>,
+    pub randomized_id: Option<i64>,
+    pub device_id: Option<String>,
+    pub point_id: Option<i64>,
+    pub lat: Option<f64>,
+    pub lng: Option<f64>,
+    /// km/h for speed alerts, minutes of silence for inactivity alerts
+    pub value: Option<f64>,
+    pub message: String,
+    /// When the condition was met: the time of the point, or when the device had been silent
+    /// for the rule's minutes
+    pub timestamp: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<AlertModel> for Alert {
+    fn from(a: AlertModel) -> Self {
+        Self {
+            id: a.id,
+            rule_id: a.rule_id,
+            kind: a.kind,
+            tenant_id: a.tenant_id,
+            randomized_id: a.randomized_id,
+            device_id: a.device_id,
+            point_id: a.point_id,
+            lat: a.lat,
+            lng: a.lng,
+            value: a.value,
+            message: a.message,
+            timestamp: a.timestamp,
+            created_at: a.created_at,
+        }
+    }
+}
+
+impl FeedEvent for Alert {
+    fn event_id(&self) -> i64 {
+        self.id
+    }
+
+    fn visible_in(&self, scope: Option<&TenantScope>) -> bool {
+        scope.is_none_or(|s| s.tenant_id() == self.tenant_id.as_deref())
+    }
+
+    fn reveals(&self) -> (Option<BBox>, Option<DateTime<Utc>>) {
+        let at = self.lat.zip(self.lng).map(|(lat, lng)| BBox { lat_min: lat, lat_max: lat, lng_min: lng, lng_max: lng });
+        (at, Some(self.timestamp))
+    }
+}
+
+/// Alerts on their way to the connected streams
+pub type AlertFeed = Feed<Alert>;
+
+impl Feed<Alert> {
+    /// ALERT_STREAM_BUFFER (default 1000) alerts are kept for resuming; the heartbeat is the
+    /// one of the anomaly stream (ANOMALY_STREAM_HEARTBEAT_SECS, default 15)
+    pub fn from_env() -> Self {
+        let number = |name: &str, default: u64| env::var(name).ok().and_then(|v| v.parse::<u64>().ok()).filter(|n| *n > 0).unwrap_or(default);
+        Self::new(number("ALERT_STREAM_BUFFER", 1000) as usize, std::time::Duration::from_secs(number("ANOMALY_STREAM_HEARTBEAT_SECS", 15)))
+    }
+}
+
+/// A rule with its kind and params read out of the stored row
+struct Rule {
+    model: AlertRuleModel,
+    kind: Option<RuleKind>,
+    params: RuleParams,
+}
+
+/// The rules, read once at startup and again after every change made through the API, and
+/// what has been reported already. A trip without a point over a speed rule for
+/// ALERT_TRIP_IDLE_SECS (default 3600) is forgotten; should it speed again, the history tells
+/// it was reported.
+pub struct Alerts {
+    store: Arc<dyn AlertStore>,
+    rules: RwLock<Vec<Rule>>,
+    geofences: Arc<Geofences>,
+    webhooks: Arc<Webhooks>,
+    feed: Arc<AlertFeed>,
+    /// Applied to `history`; the checks below read the alerts live
+    delay: PublicationDelay,
+    /// (rule_id, randomized_id) of the trips a speed rule has fired for, and when the trip was
+    /// last over the rule's limit
+    speeding: Mutex<HashMap<(i64, i64), Instant>>,
+    idle: std::time::Duration,
+    swept: Mutex<Instant>,
+    /// (rule_id, device_id) and the timestamp of the alert raised for the device's last silence
+    silent: Mutex<HashMap<(i64, String), DateTime<Utc>>>,
+}
+
+impl Alerts {
+    pub async fn load(store: Arc<dyn AlertStore>, geofences: Arc<Geofences>, webhooks: Arc<Webhooks>, feed: Arc<AlertFeed>) -> Self {
+        let idle = env::var("ALERT_TRIP_IDLE_SECS").ok().and_then(|v| v.parse::<u64>().ok()).filter(|s| *s > 0).unwrap_or(3600);
+        let alerts = Self {
+            store,
+            rules: RwLock::new(Vec::new()),
+            geofences,
+            webhooks,
+            feed,
+            delay: PublicationDelay::default(),
+            speeding: Mutex::new(HashMap::new()),
+            idle: std::time::Duration::from_secs(idle),
+            swept: Mutex::new(Instant::now()),
+            silent: Mutex::new(HashMap::new()),
+        };
+        if let Err(e) = alerts.reload().await {
+            error!("Could not read alert rules, starting without any: {}", e);
+        }
+        alerts
+    }
+
+    /// Leaves alerts younger than `delay` out of `history`, like the handlers' stores do with
+    /// points
+    pub fn with_delay(mut self, delay: PublicationDelay) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    pub async fn reload(&self) -> StoreResult<()> {
+        let rules = self
+            .store
+            .find_alert_rules()
+            .await?
+            .into_iter()
+            .map(|model| {
+                let kind = RuleKind::parse(&model.kind);
+                if kind.is_none() {
+                    warn!("Alert rule {} has unknown kind {:?}; it never fires", model.id, model.kind);
+                }
+                Rule { params: params_of(&model), kind, model }
+            })
+            .collect();
+        *self.rules.write().unwrap_or_else(|e| e.into_inner()) = rules;
+        Ok(())
+    }
+
+    /// Every rule of every tenant, disabled ones included, oldest first
+    pub fn all(&self) -> Vec<AlertRuleModel> {
+        self.rules.read().unwrap_or_else(|e| e.into_inner()).iter().map(|r| r.model.clone()).collect()
+    }
+
+    pub fn find(&self, id: i64) -> Option<AlertRuleModel> {
+        self.rules.read().unwrap_or_else(|e| e.into_inner()).iter().find(|r| r.model.id == id).map(|r| r.model.clone())
+    }
+
+    pub async fn create(&self, settings: &AlertRuleSettings, tenant_id: Option<String>) -> StoreResult<AlertRuleModel> {
+        let rule = self.store.create_alert_rule(settings, tenant_id).await?;
+        self.reload().await?;
+        Ok(rule)
+    }
+
+    pub async fn update(&self, id: i64, settings: &AlertRuleSettings) -> StoreResult<Option<AlertRuleModel>> {
+        let rule = self.store.update_alert_rule(id, settings).await?;
+        self.reload().await?;
+        Ok(rule)
+    }
+
+    pub async fn delete(&self, id: i64) -> StoreResult<bool> {
+        let deleted = self.store.delete_alert_rule(id).await?;
+        self.reload().await?;
+        Ok(deleted)
+    }
+
+    /// Alerts as the API shows them, only those about published data
+    pub async fn history(&self, filter: &AlertFilter, limit: u64) -> StoreResult<Vec<AlertModel>> {
+        let mut filter = filter.clone();
+        filter.embargo.extend(self.delay.rules(Utc::now()));
+        self.store.find_alerts(&filter, limit).await
+    }
+
+    /// Enabled rules of `kinds`, of every tenant
+    fn enabled(&self, kinds: &[RuleKind]) -> Vec<(AlertRuleModel, RuleKind, RuleParams)> {
+        self.rules
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .filter(|r| r.model.enabled)
+            .filter_map(|r| r.kind.filter(|k| kinds.contains(k)).map(|k| (r.model.clone(), k, r.params.clone())))
+            .collect()
+    }
+
+    /// Checks a stored point, and the geofence boundaries its trip just crossed, against the
+    /// speed and geofence rules of its tenant
+    pub async fn on_point(&self, tenant_id: Option<&str>, randomized_id: i64, point_id: i64, sample: &PointSample, crossings: &[GeofenceEventModel]) {
+        self.sweep();
+        let rules = self.enabled(&[RuleKind::Speed, RuleKind::Geofence]).into_iter().filter(|(r, _, _)| r.tenant_id.as_deref() == tenant_id);
+        for (rule, kind, params) in rules {
+            let base = AlertModel {
+                id: 0,
+                rule_id: rule.id,
+                kind: kind.as_str().to_string(),
+                tenant_id: rule.tenant_id.clone(),
+                randomized_id: Some(randomized_id),
+                device_id: None,
+                point_id: Some(point_id),
+                lat: Some(sample.lat),
+                lng: Some(sample.lng),
+                value: None,
+                message: String::new(),
+                timestamp: sample.timestamp,
+                created_at: Utc::now(),
+            };
+            match kind {
+                RuleKind::Speed => {
+                    let speed = sample.spd * 3.6;
+                    let Some(max_speed) = params.max_speed else { continue };
+                    if speed <= max_speed || params.geofence_id.is_some_and(|g| !self.geofences.covers(g, sample.lat, sample.lng)) {
+                        continue;
+                    }
+                    if !self.first_speeding(rule.id, randomized_id).await {
+                        continue;
+                    }
+                    let message = format!("{}: trip {} at {:.0} km/h, over {:.0} km/h", rule.name, randomized_id, speed, max_speed);
+                    self.fire(AlertModel { value: Some(speed), message, ..base }).await;
+                }
+                RuleKind::Geofence => {
+                    for crossing in crossings.iter().filter(|c| Some(c.geofence_id) == params.geofence_id) {
+                        if params.on.as_ref().is_some_and(|on| !on.contains(&crossing.kind)) {
+                            continue;
+                        }
+                        let verb = if crossing.kind == geofences::ENTRY { "entered" } else { "left" };
+                        let message = format!("{}: trip {} {} geofence {}", rule.name, randomized_id, verb, crossing.geofence_id);
+                        self.fire(AlertModel { message, ..base.clone() }).await;
+                    }
+                }
+                RuleKind::Inactivity => {}
+            }
+        }
+    }
+
+    /// Whether a speed rule has not fired for the trip yet, remembering that it now has. After a
+    /// restart the history tells.
+    async fn first_speeding(&self, rule_id: i64, randomized_id: i64) -> bool {
+        let key = (rule_id, randomized_id);
+        if let Some(seen) = self.speeding.lock().unwrap_or_else(|e| e.into_inner()).get_mut(&key) {
+            *seen = Instant::now();
+            return false;
+        }
+        let filter = AlertFilter { rule_id: Some(rule_id), randomized_id: Some(randomized_id), ..Default::default() };
+        match self.store.find_alerts(&filter, 1).await {
+            Ok(found) => {
+                self.speeding.lock().unwrap_or_else(|e| e.into_inner()).insert(key, Instant::now()).is_none() && found.is_empty()
+            }
+            Err(e) => {
+                error!("Could not read alerts of rule {}: {}", rule_id, e);
+                false
+            }
+        }
+    }
+
+    /// Forgets the trips not over a speed rule's limit for `idle`; returns how many
+    pub fn forget_idle(&self, idle: std::time::Duration) -> usize {
+        let mut speeding = self.speeding.lock().unwrap_or_else(|e| e.into_inner());
+        let before = speeding.len();
+        speeding.retain(|_, seen| seen.elapsed() < idle);
+        before - speeding.len()
+    }
+
+    /// Forgets idle trips every SWEEP_INTERVAL
+    fn sweep(&self) {
+        let mut swept = self.swept.lock().unwrap_or_else(|e| e.into_inner());
+        if swept.elapsed() < SWEEP_INTERVAL {
+            return;
+        }
+        *swept = Instant::now();
+        drop(swept);
+        let forgotten = self.forget_idle(self.idle);
+        if forgotten > 0 {
+            debug!("Forgot {} speeding trips idle for {:?}", forgotten, self.idle);
+        }
+    }
+
+    /// Raises an alert for every device silent for the minutes of an inactivity rule, once per
+    /// silence; devices that never ended a trip are left out. Returns how many were raised.
+    pub async fn check_inactivity(&self, devices: &dyn DeviceStore, now: DateTime<Utc>) -> StoreResult<usize> {
+        let mut raised = 0;
+        for (rule, kind, params) in self.enabled(&[RuleKind::Inactivity]) {
+            let Some(minutes) = params.silent_minutes else { continue };
+            let tenant = Some(match &rule.tenant_id {
+                Some(t) => TenantScope::Tenant(t.clone()),
+                None => TenantScope::Shared,
+            });
+            let filter = DeviceFilter { device_id: params.device_id.clone(), silent_since: Some(now - Duration::minutes(minutes)), tenant, ..Default::default() };
+            let mut offset = 0;
+            loop {
+                let page = devices.find_devices(&filter, DEVICE_PAGE, offset).await?;
+                for device in &page {
+                    let Some(last_seen) = device.last_seen else { continue };
+                    let at = last_seen + Duration::minutes(minutes);
+                    if !self.first_silence(rule.id, &device.device_id, at).await? {
+                        continue;
+                    }
+                    let silent_for = (now - last_seen).num_seconds() as f64 / 60.0;
+                    let message = format!("{}: device {} silent for {:.0} min", rule.name, device.device_id, silent_for);
+                    self.fire(AlertModel {
+                        id: 0,
+                        rule_id: rule.id,
+                        kind: kind.as_str().to_string(),
+                        tenant_id: rule.tenant_id.clone(),
+                        randomized_id: None,
+                        device_id: Some(device.device_id.clone()),
+                        point_id: None,
+                        lat: None,
+                        lng: None,
+                        value: Some(silent_for),
+                        message,
+                        timestamp: at,
+                        created_at: Utc::now(),
+                    })
+                    .await;
+                    raised += 1;
+                }
+                if (page.len() as u64) < DEVICE_PAGE {
+                    break;
+                }
+                offset += DEVICE_PAGE;
+            }
+        }
+        Ok(raised)
+    }
+
+    /// Whether the silence of a device reaching the rule's minutes at `at` has not been
+    /// reported yet, remembering that it now has. After a restart the history tells.
+    async fn first_silence(&self, rule_id: i64, device_id: &str, at: DateTime<Utc>) -> StoreResult<bool> {
+        let key = (rule_id, device_id.to_string());
+        if self.silent.lock().unwrap_or_else(|e| e.into_inner()).get(&key) == Some(&at) {
+            return Ok(false);
+        }
+        let filter = AlertFilter {
+            rule_id: Some(rule_id),
+            device_id: Some(device_id.to_string()),
+            since: Some(at),
+            until: Some(at),
+            ..Default::default()
+        };
+        let reported = !self.store.find_alerts(&filter, 1).await?.is_empty();
+        self.silent.lock().unwrap_or_else(|e| e.into_inner()).insert(key, at);
+        Ok(!reported)
+    }
+
+    /// Stores the alert and hands it to the webhooks and the stream
+    async fn fire(&self, alert: AlertModel) {
+        let rule_id = alert.rule_id;
+        match self.store.record_alert(alert).await {
+            Ok(stored) => {
+                info!("Alert {} of rule {}: {}", stored.id, stored.rule_id, stored.message);
+                let alert = Alert::from(stored);
+                self.webhooks.notify(WebhookEvent::Alert, &alert);
+                self.feed.publish(alert);
+            }
+            Err(e) => error!("Could not record an alert of rule {}: {}", rule_id, e),
+        }
+    }
+}
+
+/// Starts the periodic inactivity checks, every ALERT_CHECK_SECS (default 60)
+pub fn spawn(alerts: Arc<Alerts>, devices: Arc<dyn DeviceStore>) {
+    let every = env::var("ALERT_CHECK_SECS").ok().and_then(|v| v.parse::<u64>().ok()).filter(|s| *s > 0).unwrap_or(60);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(std::time::Duration::from_secs(every));
+        loop {
+            ticker.tick().await;
+            match alerts.check_inactivity(devices.as_ref(), Utc::now()).await {
+                Ok(raised) => metrics().record_job(JOB_NAME, true, format!("raised {} alerts", raised)),
+                Err(e) => {
+                    error!("Inactivity check failed: {}", e);
+                    metrics().record_job(JOB_NAME, false, e.to_string());
+                }
+            }
+        }
+    });
+    info!("Inactivity alerts checked every {}s", every);
+}
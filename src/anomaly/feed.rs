@@ -0,0 +1,103 @@
+//! Trips that just got their first anomalous point, as the classification worker finds them.
+//! The `trip_anomaly` webhooks and `GET /api/anomalies/stream` are both fed from here.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::time::Duration;
+use utoipa::ToSchema;
+
+use crate::database::store::{BBox, PointFilter, PointOrder, PointStore, StoreResult, TenantScope};
+use crate::feed::{Feed, FeedEvent};
+
+/// A trip as far as it has been received when its first point was classified anomalous
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AnomalousTrip {
+    /// The anomalous point; ids grow with every event, so they double as event ids
+    pub point_id: i64,
+    pub randomized_id: i64,
+    pub tenant_id: Option<String>,
+    /// Highest score among the trip's anomalous points, and the rule or classifier behind it
+    pub score: f64,
+    pub reason: Option<String>,
+    pub bbox: TripBounds,
+    pub start: Option<DateTime<Utc>>,
+    pub end: Option<DateTime<Utc>>,
+    /// Points received so far
+    pub points: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TripBounds {
+    pub lat_min: f64,
+    pub lng_min: f64,
+    pub lat_max: f64,
+    pub lng_max: f64,
+}
+
+impl AnomalousTrip {
+    /// The trip of `point_id`, just classified anomalous, when that point is the trip's only
+    /// anomalous one; later anomalies of the same trip give None
+    pub async fn on_first_anomaly(store: &dyn PointStore, randomized_id: i64, point_id: i64) -> StoreResult<Option<Self>> {
+        let flagged = PointFilter { randomized_id: Some(randomized_id), anomaly: Some(true), ..Default::default() };
+        if store.count(&flagged).await? != 1 {
+            return Ok(None);
+        }
+        let trip = PointFilter { randomized_id: Some(randomized_id), ..Default::default() };
+        let points = store.find(&trip, PointOrder::TimestampAsc, None).await?;
+        let Some(first) = points.first() else {
+            return Ok(None);
+        };
+        let mut bbox = TripBounds { lat_min: first.lat, lng_min: first.lng, lat_max: first.lat, lng_max: first.lng };
+        for p in &points {
+            bbox.lat_min = bbox.lat_min.min(p.lat);
+            bbox.lng_min = bbox.lng_min.min(p.lng);
+            bbox.lat_max = bbox.lat_max.max(p.lat);
+            bbox.lng_max = bbox.lng_max.max(p.lng);
+        }
+        let top = points
+            .iter()
+            .filter(|p| p.anomaly == Some(true))
+            .max_by(|a, b| a.anomaly_score.unwrap_or(1.0).total_cmp(&b.anomaly_score.unwrap_or(1.0)));
+        Ok(Some(Self {
+            point_id,
+            randomized_id,
+            tenant_id: first.tenant_id.clone(),
+            score: top.and_then(|p| p.anomaly_score).unwrap_or(1.0),
+            reason: top.and_then(|p| p.anomaly_reason.clone()),
+            bbox,
+            start: points.iter().filter_map(|p| p.timestamp).min(),
+            end: points.iter().filter_map(|p| p.timestamp).max(),
+            points: points.len(),
+        }))
+    }
+}
+
+/// Anomalous trips on their way to the connected streams
+pub type AnomalyFeed = Feed<AnomalousTrip>;
+
+impl FeedEvent for AnomalousTrip {
+    fn event_id(&self) -> i64 {
+        self.point_id
+    }
+
+    fn visible_in(&self, scope: Option<&TenantScope>) -> bool {
+        scope.is_none_or(|s| s.tenant_id() == self.tenant_id.as_deref())
+    }
+
+    fn reveals(&self) -> (Option<BBox>, Option<DateTime<Utc>>) {
+        let b = &self.bbox;
+        (Some(BBox { lat_min: b.lat_min, lat_max: b.lat_max, lng_min: b.lng_min, lng_max: b.lng_max }), self.end)
+    }
+}
+
+impl Feed<AnomalousTrip> {
+    /// ANOMALY_STREAM_BUFFER (default 1000) trips are kept for resuming;
+    /// ANOMALY_STREAM_HEARTBEAT_SECS (default 15) spaces the keep-alive comments of the streams
+    pub fn from_env() -> Self {
+        let number = |name: &str, default: u64| env::var(name).ok().and_then(|v| v.parse::<u64>().ok()).filter(|n| *n > 0).unwrap_or(default);
+        Self::new(number("ANOMALY_STREAM_BUFFER", 1000) as usize, Duration::from_secs(number("ANOMALY_STREAM_HEARTBEAT_SECS", 15)))
+    }
+}
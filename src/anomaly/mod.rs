@@ -0,0 +1,144 @@
+pub mod rules;
+pub mod feed;
+mod queue;
+mod webhook;
+
+pub use feed::{AnomalousTrip, AnomalyFeed};
+pub use queue::ClassificationQueue;
+
+use chrono::{DateTime, Utc};
+use log::{debug, info, error, warn};
+use std::env;
+use std::sync::Arc;
+
+use crate::database::store::{AnomalyVerdict, PointFilter, PointOrder, PointStore};
+use crate::webhooks::{WebhookEvent, Webhooks};
+use rules::AnomalyRule;
+
+/// The subset of a point the classifiers look at.
+#[derive(Debug, Clone)]
+pub struct PointSample {
+    pub lat: f64,
+    pub lng: f64,
+    pub spd: f64,
+    pub azm: f64,
+    pub timestamp: DateTime<Utc>,
+    /// Stored distance (m) and interval (s) from the point before it in time order, when known
+    pub prev_distance_m: Option<f64>,
+    pub prev_interval_s: Option<f64>,
+}
+
+enum Classifier {
+    /// In-process rules evaluated against the previous point of the trip
+    Native(Vec<Box<dyn AnomalyRule>>),
+    /// External classifier, the first webhook subscribed to `classify`
+    Webhook(Arc<Webhooks>),
+}
+
+/// Decides the anomaly flag for incoming points. Constructed once in `main.rs` and owned by the classification worker.
+pub struct AnomalyDetector {
+    classifier: Classifier,
+}
+
+impl AnomalyDetector {
+    /// Native rules by default; `ANOMALY_CLASSIFIER=webhook` asks the `classify` webhook.
+    /// Unknown classifiers and invalid rule settings are errors, reported by the startup self-check.
+    pub fn from_env(webhooks: Arc<Webhooks>) -> Result<Self, String> {
+        let classifier = match env::var("ANOMALY_CLASSIFIER").as_deref() {
+            Ok("webhook") => {
+                match webhooks.subscribers(WebhookEvent::Classify).first() {
+                    Some(hook) => info!("Anomaly classification via webhook {} ({})", hook.id, hook.url),
+                    None => warn!("ANOMALY_CLASSIFIER=webhook, but no webhook subscribes to classify; points stay unclassified until one does"),
+                }
+                Classifier::Webhook(webhooks)
+            }
+            Err(_) | Ok("native") => {
+                let rules = rules::rules_from_env()?;
+                info!(
+                    "Native anomaly classification with rules: [{}]",
+                    rules.iter().map(|r| r.name()).collect::<Vec<_>>().join(", ")
+                );
+                Classifier::Native(rules)
+            }
+            Ok(other) => return Err(format!("unknown ANOMALY_CLASSIFIER '{}'; expected native or webhook", other)),
+        };
+        Ok(Self { classifier })
+    }
+
+    /// The given rules, whatever the environment says
+    pub fn native(rules: Vec<Box<dyn AnomalyRule>>) -> Self {
+        Self { classifier: Classifier::Native(rules) }
+    }
+
+    /// What classifies the points, for the startup self-check
+    pub fn describe(&self) -> String {
+        match &self.classifier {
+            Classifier::Native(rules) => format!("native rules: {}", rules.iter().map(|r| r.name()).collect::<Vec<_>>().join(", ")),
+            Classifier::Webhook(_) => "classify webhook".to_string(),
+        }
+    }
+
+    /// Classifies `current` against points of the same randomized_id inserted before `point_id`.
+    /// Returns None when there is no history (first point of a trip) or classification failed.
+    pub async fn classify(&self, store: &dyn PointStore, randomized_id: i64, point_id: i64, current: &PointSample) -> Option<AnomalyVerdict> {
+        match &self.classifier {
+            Classifier::Native(rules) => {
+                let filter = PointFilter { randomized_id: Some(randomized_id), before_id: Some(point_id), ..Default::default() };
+                let previous = match store.find(&filter, PointOrder::TimestampDesc, Some(1)).await {
+                    Ok(rows) => match rows.into_iter().next() {
+                        Some(m) => m,
+                        None => return None,
+                    },
+                    Err(e) => {
+                        error!("DB query failed for rid {}: {}", randomized_id, e);
+                        return None;
+                    }
+                };
+                let previous = PointSample {
+                    lat: previous.lat,
+                    lng: previous.lng,
+                    spd: previous.spd,
+                    azm: previous.azm,
+                    timestamp: previous.timestamp.unwrap_or(current.timestamp),
+                    prev_distance_m: previous.prev_distance_m,
+                    prev_interval_s: previous.prev_interval_s,
+                };
+                let fired: Vec<&'static str> = rules
+                    .iter()
+                    .filter(|r| r.check(&previous, current))
+                    .map(|r| r.name())
+                    .collect();
+                if !fired.is_empty() {
+                    debug!("Anomaly rules fired for rid {}: {:?}", randomized_id, fired);
+                }
+                // The rule furthest past its threshold scores the point: ratio r maps to r / (1 + r),
+                // so 0.5 at the threshold and approaching 1 far beyond it
+                let (ratio, top) = rules
+                    .iter()
+                    .map(|r| (r.ratio(&previous, current), r.name()))
+                    .fold((0.0, None), |best, (ratio, name)| if ratio > best.0 { (ratio, Some(name)) } else { best });
+                let anomaly = !fired.is_empty();
+                Some(AnomalyVerdict { anomaly, score: ratio / (1.0 + ratio), reason: if anomaly { top } else { None } })
+            }
+            Classifier::Webhook(webhooks) => {
+                let filter = PointFilter { randomized_id: Some(randomized_id), before_id: Some(point_id), ..Default::default() };
+                let existing = match store.find(&filter, PointOrder::TimestampDesc, None).await {
+                    Ok(rows) => rows,
+                    Err(e) => {
+                        error!("DB query failed for rid {}: {}", randomized_id, e);
+                        return None;
+                    }
+                };
+                if existing.is_empty() {
+                    // No existing points -> nothing to compare against
+                    return None;
+                }
+                webhook::classify(webhooks, randomized_id, current, &existing).await.map(|anomaly| AnomalyVerdict {
+                    anomaly,
+                    score: if anomaly { 1.0 } else { 0.0 },
+                    reason: anomaly.then_some("webhook"),
+                })
+            }
+        }
+    }
+}
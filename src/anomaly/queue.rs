@@ -0,0 +1,189 @@
+use chrono::{DateTime, Utc};
+use log::{debug, error, info, warn};
+use serde::Serialize;
+use std::env;
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot};
+
+use super::feed::{AnomalousTrip, AnomalyFeed};
+use super::{AnomalyDetector, PointSample};
+use crate::database::model::points::Model as PointModel;
+use crate::database::store::{AnomalyVerdict, NewPointRecord, PointStore, StoreResult};
+use crate::alerts::Alerts;
+use crate::geofences::Geofences;
+use crate::webhooks::{WebhookEvent, Webhooks};
+
+/// A freshly inserted point waiting for its anomaly decision.
+#[derive(Debug)]
+pub struct ClassificationJob {
+    pub point_id: i64,
+    pub randomized_id: i64,
+    pub tenant_id: Option<String>,
+    pub sample: PointSample,
+    /// Told the decision once it is stored; None when the point got none (first of its trip,
+    /// classifier failure)
+    pub done: Option<oneshot::Sender<Option<bool>>>,
+}
+
+/// Handle to the background classification worker; cheap to clone into app data.
+#[derive(Clone)]
+pub struct ClassificationQueue {
+    tx: mpsc::Sender<ClassificationJob>,
+}
+
+impl ClassificationQueue {
+    /// Spawns the worker task. Capacity comes from ANOMALY_QUEUE_CAPACITY (default 10000);
+    /// when the queue is full, producers wait for room instead of dropping jobs. Anomalous
+    /// verdicts are sent to the webhooks subscribed to them, and trips that get their first
+    /// anomalous point to `feed` as well. Every point is also checked against `geofences`, and
+    /// both against the speed and geofence rules of `alerts`.
+    pub fn spawn(
+        store: Arc<dyn PointStore>,
+        detector: Arc<AnomalyDetector>,
+        webhooks: Arc<Webhooks>,
+        feed: Arc<AnomalyFeed>,
+        geofences: Arc<Geofences>,
+        alerts: Arc<Alerts>,
+    ) -> Self {
+        let capacity = env::var("ANOMALY_QUEUE_CAPACITY")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(10_000);
+        let (tx, rx) = mpsc::channel(capacity);
+        tokio::spawn(run_worker(store, detector, webhooks, feed, geofences, alerts, rx));
+        info!("Anomaly classification worker started (capacity={})", capacity);
+        Self { tx }
+    }
+
+    pub async fn enqueue(&self, job: ClassificationJob) {
+        if let Err(e) = self.tx.send(job).await {
+            error!("Anomaly queue closed; point {} stays unclassified", e.0.point_id);
+        }
+    }
+
+    /// Stores one point and queues it for classification
+    pub async fn ingest(&self, store: &dyn PointStore, record: NewPointRecord) -> StoreResult<PointModel> {
+        self.ingest_inner(store, record, None).await
+    }
+
+    /// Like `ingest`, also returning a receiver for the anomaly decision
+    pub async fn ingest_watched(&self, store: &dyn PointStore, record: NewPointRecord) -> StoreResult<(PointModel, oneshot::Receiver<Option<bool>>)> {
+        let (tx, rx) = oneshot::channel();
+        let inserted = self.ingest_inner(store, record, Some(tx)).await?;
+        Ok((inserted, rx))
+    }
+
+    async fn ingest_inner(&self, store: &dyn PointStore, record: NewPointRecord, done: Option<oneshot::Sender<Option<bool>>>) -> StoreResult<PointModel> {
+        let inserted = store.insert(record).await?;
+        self.queue_stored(&inserted, done).await;
+        Ok(inserted)
+    }
+
+    /// Queues a point someone else has stored, e.g. the bulk inserts of `IngestBatcher`
+    pub async fn queue_stored(&self, inserted: &PointModel, done: Option<oneshot::Sender<Option<bool>>>) {
+        let sample = PointSample {
+            lat: inserted.lat,
+            lng: inserted.lng,
+            spd: inserted.spd,
+            azm: inserted.azm,
+            timestamp: inserted.timestamp.unwrap_or_else(Utc::now),
+            prev_distance_m: inserted.prev_distance_m,
+            prev_interval_s: inserted.prev_interval_s,
+        };
+        let job = ClassificationJob {
+            point_id: inserted.id,
+            randomized_id: inserted.randomized_id,
+            tenant_id: inserted.tenant_id.clone(),
+            sample,
+            done,
+        };
+        self.enqueue(job).await;
+        crate::metrics::metrics().record_ingested(1);
+    }
+
+    /// Jobs waiting for the worker
+    pub fn depth(&self) -> usize {
+        self.tx.max_capacity() - self.tx.capacity()
+    }
+}
+
+/// Body of the `anomaly` webhook event
+#[derive(Debug, Serialize)]
+struct AnomalyNotification {
+    #[serde(rename = "pointId")]
+    point_id: i64,
+    #[serde(rename = "randomizedId")]
+    randomized_id: i64,
+    lat: f64,
+    lng: f64,
+    spd: f64,
+    timestamp: DateTime<Utc>,
+    score: f64,
+    reason: Option<&'static str>,
+}
+
+impl AnomalyNotification {
+    fn new(job: &ClassificationJob, verdict: AnomalyVerdict) -> Self {
+        Self {
+            point_id: job.point_id,
+            randomized_id: job.randomized_id,
+            lat: job.sample.lat,
+            lng: job.sample.lng,
+            spd: job.sample.spd,
+            timestamp: job.sample.timestamp,
+            score: verdict.score,
+            reason: verdict.reason,
+        }
+    }
+}
+
+/// Processes jobs one at a time so points of the same trip are classified, and checked against
+/// the geofences and alert rules, in insertion order.
+async fn run_worker(
+    store: Arc<dyn PointStore>,
+    detector: Arc<AnomalyDetector>,
+    webhooks: Arc<Webhooks>,
+    feed: Arc<AnomalyFeed>,
+    geofences: Arc<Geofences>,
+    alerts: Arc<Alerts>,
+    mut rx: mpsc::Receiver<ClassificationJob>,
+) {
+    while let Some(job) = rx.recv().await {
+        let decision = match detector.classify(store.as_ref(), job.randomized_id, job.point_id, &job.sample).await {
+            Some(verdict) => match store.set_anomaly(job.point_id, verdict).await {
+                Ok(false) => {
+                    warn!("Point {} vanished before classification", job.point_id);
+                    None
+                }
+                Ok(true) => {
+                    debug!("Point {} classified anomaly={} score={:.3}", job.point_id, verdict.anomaly, verdict.score);
+                    if verdict.anomaly {
+                        webhooks.notify(WebhookEvent::Anomaly, &AnomalyNotification::new(&job, verdict));
+                        match AnomalousTrip::on_first_anomaly(store.as_ref(), job.randomized_id, job.point_id).await {
+                            Ok(Some(trip)) => {
+                                webhooks.notify(WebhookEvent::TripAnomaly, &trip);
+                                feed.publish(trip);
+                            }
+                            Ok(None) => {}
+                            Err(e) => error!("Could not describe anomalous trip {}: {}", job.randomized_id, e),
+                        }
+                    }
+                    Some(verdict.anomaly)
+                }
+                Err(e) => {
+                    error!("Anomaly update failed for point {}: {}", job.point_id, e);
+                    None
+                }
+            },
+            None => None,
+        };
+        let crossings = geofences.evaluate(job.tenant_id.as_deref(), job.randomized_id, job.point_id, &job.sample).await;
+        alerts.on_point(job.tenant_id.as_deref(), job.randomized_id, job.point_id, &job.sample, &crossings).await;
+        if let Some(done) = job.done {
+            // The client may have stopped waiting
+            let _ = done.send(decision);
+        }
+    }
+    info!("Anomaly classification worker stopped");
+}
@@ -0,0 +1,134 @@
+use std::env;
+
+use super::PointSample;
+use crate::geo::haversine_m;
+
+/// A single anomaly check comparing a new point against the previous point of the same trip.
+pub trait AnomalyRule: Send + Sync {
+    /// Short identifier used in `ANOMALY_RULES` and in logs
+    fn name(&self) -> &'static str;
+    /// How far `current` strays from `previous`, as a multiple of the rule's threshold:
+    /// the point looks anomalous above 1
+    fn ratio(&self, previous: &PointSample, current: &PointSample) -> f64;
+
+    /// Returns true when `current` looks anomalous relative to `previous`
+    fn check(&self, previous: &PointSample, current: &PointSample) -> bool {
+        self.ratio(previous, current) > 1.0
+    }
+}
+
+/// Flags an abrupt change of reported speed between consecutive points.
+pub struct SpeedSpike {
+    /// Maximum allowed |spd - prev.spd|
+    pub max_delta: f64,
+}
+
+impl AnomalyRule for SpeedSpike {
+    fn name(&self) -> &'static str { "speed_spike" }
+
+    fn ratio(&self, previous: &PointSample, current: &PointSample) -> f64 {
+        (current.spd - previous.spd).abs() / self.max_delta
+    }
+}
+
+/// Flags a position jump that would require an implausible speed to cover.
+pub struct TeleportJump {
+    /// Maximum plausible speed in m/s implied by distance / elapsed time
+    pub max_implied_speed: f64,
+}
+
+impl AnomalyRule for TeleportJump {
+    fn name(&self) -> &'static str { "teleport" }
+
+    fn ratio(&self, previous: &PointSample, current: &PointSample) -> f64 {
+        // Deltas stored at ingestion, else derived from the previous point
+        let (distance, elapsed) = match (current.prev_distance_m, current.prev_interval_s) {
+            (Some(d), Some(s)) => (d, s),
+            _ => (
+                haversine_m(previous.lat, previous.lng, current.lat, current.lng),
+                (current.timestamp - previous.timestamp).num_milliseconds() as f64 / 1000.0,
+            ),
+        };
+        // Treat simultaneous or out-of-order fixes as one second apart
+        distance / elapsed.max(1.0) / self.max_implied_speed
+    }
+}
+
+/// Flags a near-180° heading flip while the vehicle is moving.
+pub struct AzimuthReversal {
+    /// Minimum heading change in degrees considered a reversal
+    pub min_turn_deg: f64,
+    /// Both points must be at least this fast; parked vehicles spin freely
+    pub min_speed: f64,
+}
+
+impl AnomalyRule for AzimuthReversal {
+    fn name(&self) -> &'static str { "azimuth_reversal" }
+
+    fn ratio(&self, previous: &PointSample, current: &PointSample) -> f64 {
+        if previous.spd < self.min_speed || current.spd < self.min_speed {
+            return 0.0;
+        }
+        let diff = (current.azm - previous.azm).rem_euclid(360.0);
+        let turn = if diff > 180.0 { 360.0 - diff } else { diff };
+        turn / self.min_turn_deg
+    }
+
+    fn check(&self, previous: &PointSample, current: &PointSample) -> bool {
+        self.ratio(previous, current) >= 1.0
+    }
+}
+
+/// Every rule with its default threshold
+pub fn default_rules() -> Vec<Box<dyn AnomalyRule>> {
+    vec![
+        Box::new(SpeedSpike { max_delta: 15.0 }),
+        Box::new(TeleportJump { max_implied_speed: 70.0 }),
+        Box::new(AzimuthReversal { min_turn_deg: 150.0, min_speed: 2.0 }),
+    ]
+}
+
+/// Builds the enabled rule set from env:
+/// - ANOMALY_RULES: comma separated rule names (default: all)
+/// - ANOMALY_MAX_SPEED_DELTA (default 15.0)
+/// - ANOMALY_MAX_IMPLIED_SPEED (default 70.0 m/s)
+/// - ANOMALY_REVERSAL_DEGREES (default 150.0), ANOMALY_REVERSAL_MIN_SPEED (default 2.0)
+///
+/// Unknown rule names, an empty list and thresholds that are not positive numbers are errors.
+pub fn rules_from_env() -> Result<Vec<Box<dyn AnomalyRule>>, String> {
+    let enabled = env::var("ANOMALY_RULES")
+        .unwrap_or_else(|_| "speed_spike,teleport,azimuth_reversal".to_string());
+
+    let mut rules: Vec<Box<dyn AnomalyRule>> = Vec::new();
+    for name in enabled.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        match name {
+            "speed_spike" => rules.push(Box::new(SpeedSpike {
+                max_delta: env_f64("ANOMALY_MAX_SPEED_DELTA", 15.0)?,
+            })),
+            "teleport" => rules.push(Box::new(TeleportJump {
+                max_implied_speed: env_f64("ANOMALY_MAX_IMPLIED_SPEED", 70.0)?,
+            })),
+            "azimuth_reversal" => rules.push(Box::new(AzimuthReversal {
+                min_turn_deg: env_f64("ANOMALY_REVERSAL_DEGREES", 150.0)?,
+                min_speed: env_f64("ANOMALY_REVERSAL_MIN_SPEED", 2.0)?,
+            })),
+            other => return Err(format!("unknown anomaly rule '{}' in ANOMALY_RULES; expected speed_spike, teleport or azimuth_reversal", other)),
+        }
+    }
+    if rules.is_empty() {
+        return Err("ANOMALY_RULES enables no rule".to_string());
+    }
+    Ok(rules)
+}
+
+fn env_f64(key: &str, default: f64) -> Result<f64, String> {
+    match env::var(key) {
+        Ok(v) => v
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|n| n.is_finite() && *n > 0.0)
+            .ok_or_else(|| format!("{} must be a positive number, got '{}'", key, v)),
+        Err(_) => Ok(default),
+    }
+}
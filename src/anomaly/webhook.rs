@@ -0,0 +1,84 @@
+use chrono::{DateTime, Utc};
+use log::{warn, error};
+use serde::{Deserialize, Serialize};
+
+use super::PointSample;
+use crate::database::model::points::Model as PointModel;
+use crate::metrics::metrics;
+use crate::webhooks::{WebhookEvent, Webhooks};
+
+/// Name of the webhook on the status page
+const JOB_NAME: &str = "anomaly webhook";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct WebhookPoint {
+    lat: f64,
+    lng: f64,
+    azm: f64,
+    timestamp: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct WebhookPayload {
+    first: WebhookPoint,
+    second: WebhookPoint,
+    gone: Vec<WebhookPoint>,
+}
+
+/// Asks the external classifier (see ml/server.py) about `current` given the trip history
+/// ordered by descending timestamp. Returns None when there is no such webhook, it fails or it
+/// answers garbage.
+pub async fn classify(webhooks: &Webhooks, randomized_id: i64, current: &PointSample, existing: &[PointModel]) -> Option<bool> {
+    let second = WebhookPoint { lat: current.lat, lng: current.lng, azm: current.azm, timestamp: current.timestamp };
+
+    // First is the most recent point from DB
+    let first_model = &existing[0];
+    let first_ts = first_model.timestamp.unwrap_or_else(Utc::now);
+    let first = WebhookPoint { lat: first_model.lat, lng: first_model.lng, azm: first_model.azm, timestamp: first_ts };
+
+    // Gone: rest of DB points (skip first), by descending timestamp
+    let gone: Vec<WebhookPoint> = existing
+        .iter()
+        .skip(1)
+        .map(|m| WebhookPoint { lat: m.lat, lng: m.lng, azm: m.azm, timestamp: m.timestamp.unwrap_or_else(Utc::now) })
+        .collect();
+
+    let payload = WebhookPayload { first, second, gone };
+
+    let body = match webhooks.call(WebhookEvent::Classify, &payload).await {
+        Some(Ok(body)) => body,
+        Some(Err(e)) => {
+            error!("Webhook POST failed: {}", e);
+            metrics().record_job(JOB_NAME, false, e);
+            return None;
+        }
+        None => {
+            metrics().record_job(JOB_NAME, false, "no webhook subscribes to classify");
+            return None;
+        }
+    };
+    // The answer is an i32, as JSON or plain text
+    let code_opt = serde_json::from_str::<i32>(&body).ok().or_else(|| body.trim().parse::<i32>().ok());
+
+    let status = metrics();
+    match code_opt {
+        Some(-1) => {
+            status.record_job(JOB_NAME, true, "last point classified anomalous");
+            Some(true)
+        }
+        Some(1) => {
+            status.record_job(JOB_NAME, true, "last point classified normal");
+            Some(false)
+        }
+        Some(other) => {
+            warn!("Unexpected webhook response code: {}", other);
+            status.record_job(JOB_NAME, false, format!("unexpected response code {}", other));
+            None
+        }
+        None => {
+            warn!("Failed to parse webhook response for rid {}", randomized_id);
+            status.record_job(JOB_NAME, false, "unreadable response");
+            None
+        }
+    }
+}
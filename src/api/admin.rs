@@ -0,0 +1,903 @@
+use actix_web::{delete, get, http::header, post, put, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Datelike, NaiveDate, Utc, Weekday};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::env;
+use std::future::Future;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use sea_orm::DatabaseConnection;
+
+use crate::anomaly::ClassificationQueue;
+use crate::database::batch::IngestBatcher;
+use crate::database::index_advisor::{self, IndexAdvisorReport};
+use crate::database::model::quarantined_points::Model as QuarantinedModel;
+use crate::database::model::webhooks::Model as WebhookModel;
+use crate::database::store::{CircuitBreaker, PointFilter, PointOrder, PointStore, Quarantine, TileStatsStore, TripStore, WebhookSettings};
+use crate::database::tile_rollup::{self, RollupConfig};
+use crate::image_compressor::{ImageCache, ImageCacheStats};
+use crate::jobs::{Jobs, RunningJob};
+use crate::metrics::metrics;
+use crate::reports::{self, ReportConfig};
+use crate::stale::StaleCache;
+use crate::telemetry;
+use crate::tenant;
+use crate::webhooks::{self, WebhookEvent, Webhooks};
+use super::error::ApiError;
+use super::jobs::{accepted, Job};
+use super::registry::ApiScope;
+use super::reports::Report;
+use super::uploads::constant_time_eq;
+
+/// Who may call `/api/admin`, and who may review anomalies. Built once in `main.rs` and
+/// shared as app data.
+#[derive(Debug, Clone)]
+pub struct AdminConfig {
+    /// Bearer token from ADMIN_TOKEN; the admin endpoints are disabled when unset
+    token: Option<String>,
+    /// Bearer tokens of REVIEWER_TOKENS and the reviewer each one names
+    reviewers: HashMap<String, String>,
+}
+
+/// Reviewer recorded for reviews made with ADMIN_TOKEN
+const ADMIN_REVIEWER: &str = "admin";
+
+impl AdminConfig {
+    /// REVIEWER_TOKENS lists `token=name` entries separated by `,`, like TENANT_API_KEYS
+    pub fn from_env() -> Result<Self, String> {
+        let token = env::var("ADMIN_TOKEN").ok().filter(|t| !t.is_empty());
+        if token.is_none() {
+            warn!("ADMIN_TOKEN is not set; /api/admin is disabled");
+        }
+        let mut reviewers = HashMap::new();
+        for entry in env::var("REVIEWER_TOKENS").unwrap_or_default().split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            match entry.split_once('=').map(|(t, n)| (t.trim(), n.trim())) {
+                Some((token, name)) if !token.is_empty() && !name.is_empty() => reviewers.insert(token.to_string(), name.to_string()),
+                _ => return Err(format!("REVIEWER_TOKENS entry '{}': expected token=name", entry)),
+            };
+        }
+        Ok(Self { token, reviewers })
+    }
+
+    /// Admin endpoints behind `token`, or disabled without one
+    pub fn new(token: Option<String>) -> Self {
+        Self { token, reviewers: HashMap::new() }
+    }
+
+    /// Adds a REVIEWER_TOKENS entry
+    pub fn with_reviewer(mut self, token: &str, name: &str) -> Self {
+        self.reviewers.insert(token.to_string(), name.to_string());
+        self
+    }
+
+    /// Who is reviewing anomalies with this request: the reviewer of its token, or `admin` for
+    /// ADMIN_TOKEN
+    pub(super) fn reviewer(&self, req: &HttpRequest) -> Result<String, ApiError> {
+        if self.token.is_none() && self.reviewers.is_empty() {
+            return Err(ApiError::ServiceUnavailable("Reviews are disabled".to_string()));
+        }
+        let invalid = || ApiError::Unauthorized("Invalid reviewer token".to_string());
+        let provided = bearer(req).ok_or_else(invalid)?;
+        if self.token.as_deref().is_some_and(|t| constant_time_eq(provided.as_bytes(), t.as_bytes())) {
+            return Ok(ADMIN_REVIEWER.to_string());
+        }
+        self.reviewers
+            .iter()
+            .find(|(token, _)| constant_time_eq(provided.as_bytes(), token.as_bytes()))
+            .map(|(_, name)| name.clone())
+            .ok_or_else(invalid)
+    }
+
+    /// The response to send instead when the request may not proceed
+    pub(super) fn reject(&self, req: &HttpRequest) -> Option<HttpResponse> {
+        let Some(expected) = self.token.as_deref() else {
+            return Some(HttpResponse::ServiceUnavailable().body("Admin endpoints are disabled"));
+        };
+        if !bearer(req).is_some_and(|t| constant_time_eq(t.as_bytes(), expected.as_bytes())) {
+            return Some(HttpResponse::Unauthorized().body("Invalid admin token"));
+        }
+        None
+    }
+}
+
+fn bearer(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+#[utoipa::path(
+    get,
+    tag = "Admin",
+    params(
+        ("Authorization" = String, Header, description = "Bearer <ADMIN_TOKEN>"),
+    ),
+    responses(
+        (status = 200, description = "Size and hit counters of the converted image cache", body = ImageCacheStats),
+        (status = 401, description = "Missing or wrong token"),
+        (status = 503, description = "Admin endpoints are disabled"),
+    )
+)]
+
+#[get("/image-cache")]
+pub async fn image_cache_stats(
+    cfg: web::Data<AdminConfig>,
+    cache: web::Data<ImageCache>,
+    req: HttpRequest,
+) -> HttpResponse {
+    if let Some(resp) = cfg.reject(&req) {
+        return resp;
+    }
+    HttpResponse::Ok().json(cache.stats())
+}
+
+#[utoipa::path(
+    get,
+    tag = "Admin",
+    params(
+        ("Authorization" = String, Header, description = "Bearer <ADMIN_TOKEN>"),
+    ),
+    responses(
+        (status = 200, description = "Slow analytics requests since startup grouped by endpoint and parameters, checked against the indexes of points and trips, with index and rollup suggestions as migrations", body = IndexAdvisorReport),
+        (status = 401, description = "Missing or wrong token"),
+        (status = 503, description = "Admin endpoints are disabled"),
+        (status = 500, description = "Server Vzorvalsya")
+    )
+)]
+
+#[get("/index-advisor")]
+pub async fn index_advice(
+    cfg: web::Data<AdminConfig>,
+    db: web::Data<DatabaseConnection>,
+    req: HttpRequest,
+) -> HttpResponse {
+    if let Some(resp) = cfg.reject(&req) {
+        return resp;
+    }
+    let slow = telemetry::slow_queries();
+    let threshold = telemetry::slow_query_threshold().map(|d| d.as_millis() as u64);
+    match index_advisor::analyze(db.get_ref(), &slow, threshold).await {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(e) => {
+            error!("Index advisor failed: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Default and maximum page size for `GET /api/admin/quarantine`
+const DEFAULT_QUARANTINE_LIMIT: u64 = 100;
+const MAX_QUARANTINE_LIMIT: u64 = 1000;
+
+/// A point held back because its device timestamp was outside the acceptance window
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct QuarantinedPointRow {
+    pub id: i64,
+    pub randomized_id: i64,
+    pub lat: f64,
+    pub lng: f64,
+    pub alt: f64,
+    pub spd: f64,
+    pub azm: f64,
+    /// As sent by the device
+    pub timestamp: DateTime<Utc>,
+    pub uuid: Option<Uuid>,
+    pub reason: String,
+    #[serde(rename = "receivedAt")]
+    pub received_at: DateTime<Utc>,
+}
+
+impl From<QuarantinedModel> for QuarantinedPointRow {
+    fn from(m: QuarantinedModel) -> Self {
+        Self {
+            id: m.id,
+            randomized_id: m.randomized_id,
+            lat: m.lat,
+            lng: m.lng,
+            alt: m.alt,
+            spd: m.spd,
+            azm: m.azm,
+            timestamp: m.timestamp,
+            uuid: m.client_uuid,
+            reason: m.reason,
+            received_at: m.received_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct QuarantinePage {
+    /// Newest first
+    pub points: Vec<QuarantinedPointRow>,
+    /// Number of quarantined points, ignoring limit/offset
+    pub total: u64,
+    pub limit: u64,
+    pub offset: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct QuarantineQueryParams {
+    pub limit: Option<u64>,
+    pub offset: Option<u64>,
+}
+
+#[utoipa::path(
+    get,
+    tag = "Admin",
+    params(
+        ("Authorization" = String, Header, description = "Bearer <ADMIN_TOKEN>"),
+        ("limit" = u64, Query, description = "Page size, default 100, max 1000"),
+        ("offset" = u64, Query, description = "Number of points to skip, default 0"),
+    ),
+    responses(
+        (status = 200, description = "Points held back by the timestamp window, newest first", body = QuarantinePage),
+        (status = 400, description = "Invalid limit"),
+        (status = 401, description = "Missing or wrong token"),
+        (status = 503, description = "Admin endpoints are disabled"),
+        (status = 500, description = "Server Vzorvalsya")
+    )
+)]
+
+#[get("/quarantine")]
+pub async fn list_quarantine(
+    cfg: web::Data<AdminConfig>,
+    quarantine: web::Data<Quarantine>,
+    qp: web::Query<QuarantineQueryParams>,
+    req: HttpRequest,
+) -> HttpResponse {
+    if let Some(resp) = cfg.reject(&req) {
+        return resp;
+    }
+    let limit = qp.limit.unwrap_or(DEFAULT_QUARANTINE_LIMIT);
+    if limit == 0 || limit > MAX_QUARANTINE_LIMIT {
+        return HttpResponse::BadRequest().body(format!("limit must be between 1 and {}", MAX_QUARANTINE_LIMIT));
+    }
+    let offset = qp.offset.unwrap_or(0);
+
+    let total = match quarantine.store.count_quarantined().await {
+        Ok(n) => n,
+        Err(e) => {
+            error!("Quarantine count failed: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let rows = if offset >= total {
+        Vec::new()
+    } else {
+        match quarantine.store.find_quarantined(limit, offset).await {
+            Ok(r) => r,
+            Err(e) => {
+                error!("Quarantine query failed: {}", e);
+                return HttpResponse::InternalServerError().finish();
+            }
+        }
+    };
+    HttpResponse::Ok().json(QuarantinePage {
+        points: rows.into_iter().map(QuarantinedPointRow::from).collect(),
+        total,
+        limit,
+        offset,
+    })
+}
+
+#[utoipa::path(
+    delete,
+    tag = "Admin",
+    params(
+        ("Authorization" = String, Header, description = "Bearer <ADMIN_TOKEN>"),
+        ("id" = i64, Path, description = "Quarantine id"),
+    ),
+    responses(
+        (status = 204, description = "Point discarded"),
+        (status = 401, description = "Missing or wrong token"),
+        (status = 404, description = "No such quarantined point"),
+        (status = 503, description = "Admin endpoints are disabled"),
+        (status = 500, description = "Server Vzorvalsya")
+    )
+)]
+
+#[delete("/quarantine/{id}")]
+pub async fn discard_quarantined(
+    cfg: web::Data<AdminConfig>,
+    quarantine: web::Data<Quarantine>,
+    path: web::Path<i64>,
+    req: HttpRequest,
+) -> HttpResponse {
+    if let Some(resp) = cfg.reject(&req) {
+        return resp;
+    }
+    let id = path.into_inner();
+    match quarantine.store.discard_quarantined(id).await {
+        Ok(true) => {
+            info!("Discarded quarantined point {}", id);
+            HttpResponse::NoContent().finish()
+        }
+        Ok(false) => HttpResponse::NotFound().body("No such quarantined point"),
+        Err(e) => {
+            error!("Could not discard quarantined point {}: {}", id, e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// A webhook as the admin API shows it; the secret never leaves the server
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct WebhookRow {
+    pub id: i64,
+    pub url: String,
+    /// `classify`, `anomaly`, `trip_anomaly` and/or `alert`
+    pub events: Vec<String>,
+    /// Deliveries carry `X-Webhook-Signature`
+    pub signed: bool,
+    pub enabled: bool,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<Utc>,
+    #[serde(rename = "updatedAt")]
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<WebhookModel> for WebhookRow {
+    fn from(m: WebhookModel) -> Self {
+        Self {
+            id: m.id,
+            events: webhooks::events_of(&m).into_iter().map(|e| e.as_str().to_string()).collect(),
+            url: m.url,
+            signed: m.secret.is_some(),
+            enabled: m.enabled,
+            created_at: m.created_at,
+            updated_at: m.updated_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct WebhookList {
+    /// Oldest first
+    pub webhooks: Vec<WebhookRow>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct WebhookBody {
+    /// http or https
+    pub url: String,
+    /// `classify` (asked for anomaly verdicts, see ANOMALY_CLASSIFIER), `anomaly` (told about
+    /// anomalous points), `trip_anomaly` (told about each trip once it has one) and/or `alert`
+    /// (told about each alert of the rules at `/api/alerts/rules`)
+    pub events: Vec<String>,
+    /// Key of the HMAC-SHA256 signature; on update, omitted keeps the current one and an empty
+    /// string removes it
+    pub secret: Option<String>,
+    /// Default true
+    pub enabled: Option<bool>,
+}
+
+impl WebhookBody {
+    /// `current_secret` is the secret an update keeps when the body has none
+    fn settings(self, current_secret: Option<String>) -> Result<WebhookSettings, String> {
+        match reqwest::Url::parse(self.url.trim()) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+            _ => return Err(format!("url must be an http or https URL, got {:?}", self.url)),
+        }
+        let mut events: Vec<String> = Vec::new();
+        for name in &self.events {
+            let Some(event) = WebhookEvent::parse(name) else {
+                let known: Vec<_> = WebhookEvent::ALL.iter().map(|e| e.as_str()).collect();
+                return Err(format!("unknown event {:?}; webhooks subscribe to {}", name, known.join(", ")));
+            };
+            if !events.iter().any(|e| e == event.as_str()) {
+                events.push(event.as_str().to_string());
+            }
+        }
+        if events.is_empty() {
+            return Err("events must name at least one event".to_string());
+        }
+        let secret = match self.secret {
+            Some(s) => Some(s).filter(|s| !s.is_empty()),
+            None => current_secret,
+        };
+        Ok(WebhookSettings { url: self.url.trim().to_string(), events, secret, enabled: self.enabled.unwrap_or(true) })
+    }
+}
+
+#[utoipa::path(
+    get,
+    tag = "Admin",
+    params(
+        ("Authorization" = String, Header, description = "Bearer <ADMIN_TOKEN>"),
+    ),
+    responses(
+        (status = 200, description = "Configured webhooks, oldest first", body = WebhookList),
+        (status = 401, description = "Missing or wrong token"),
+        (status = 503, description = "Admin endpoints are disabled"),
+    )
+)]
+
+#[get("/webhooks")]
+pub async fn list_webhooks(cfg: web::Data<AdminConfig>, hooks: web::Data<Webhooks>, req: HttpRequest) -> HttpResponse {
+    if let Some(resp) = cfg.reject(&req) {
+        return resp;
+    }
+    HttpResponse::Ok().json(WebhookList { webhooks: hooks.all().into_iter().map(WebhookRow::from).collect() })
+}
+
+#[utoipa::path(
+    get,
+    tag = "Admin",
+    params(
+        ("Authorization" = String, Header, description = "Bearer <ADMIN_TOKEN>"),
+        ("id" = i64, Path, description = "Webhook id"),
+    ),
+    responses(
+        (status = 200, description = "The webhook", body = WebhookRow),
+        (status = 401, description = "Missing or wrong token"),
+        (status = 404, description = "No such webhook"),
+        (status = 503, description = "Admin endpoints are disabled"),
+    )
+)]
+
+#[get("/webhooks/{id}")]
+pub async fn get_webhook(cfg: web::Data<AdminConfig>, hooks: web::Data<Webhooks>, path: web::Path<i64>, req: HttpRequest) -> HttpResponse {
+    if let Some(resp) = cfg.reject(&req) {
+        return resp;
+    }
+    match hooks.find(path.into_inner()) {
+        Some(hook) => HttpResponse::Ok().json(WebhookRow::from(hook)),
+        None => HttpResponse::NotFound().body("No such webhook"),
+    }
+}
+
+#[utoipa::path(
+    post,
+    tag = "Admin",
+    params(
+        ("Authorization" = String, Header, description = "Bearer <ADMIN_TOKEN>"),
+    ),
+    request_body = WebhookBody,
+    responses(
+        (status = 201, description = "Webhook created", body = WebhookRow),
+        (status = 400, description = "Invalid url or events"),
+        (status = 401, description = "Missing or wrong token"),
+        (status = 503, description = "Admin endpoints are disabled"),
+        (status = 500, description = "Server Vzorvalsya")
+    )
+)]
+
+#[post("/webhooks")]
+pub async fn create_webhook(
+    cfg: web::Data<AdminConfig>,
+    hooks: web::Data<Webhooks>,
+    body: web::Json<WebhookBody>,
+    req: HttpRequest,
+) -> HttpResponse {
+    if let Some(resp) = cfg.reject(&req) {
+        return resp;
+    }
+    let settings = match body.into_inner().settings(None) {
+        Ok(s) => s,
+        Err(message) => return HttpResponse::BadRequest().body(message),
+    };
+    match hooks.create(&settings).await {
+        Ok(hook) => {
+            info!("Created webhook {} for {} ({})", hook.id, hook.url, hook.events);
+            HttpResponse::Created().json(WebhookRow::from(hook))
+        }
+        Err(e) => {
+            error!("Could not create webhook: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[utoipa::path(
+    put,
+    tag = "Admin",
+    params(
+        ("Authorization" = String, Header, description = "Bearer <ADMIN_TOKEN>"),
+        ("id" = i64, Path, description = "Webhook id"),
+    ),
+    request_body = WebhookBody,
+    responses(
+        (status = 200, description = "Webhook replaced", body = WebhookRow),
+        (status = 400, description = "Invalid url or events"),
+        (status = 401, description = "Missing or wrong token"),
+        (status = 404, description = "No such webhook"),
+        (status = 503, description = "Admin endpoints are disabled"),
+        (status = 500, description = "Server Vzorvalsya")
+    )
+)]
+
+#[put("/webhooks/{id}")]
+pub async fn update_webhook(
+    cfg: web::Data<AdminConfig>,
+    hooks: web::Data<Webhooks>,
+    path: web::Path<i64>,
+    body: web::Json<WebhookBody>,
+    req: HttpRequest,
+) -> HttpResponse {
+    if let Some(resp) = cfg.reject(&req) {
+        return resp;
+    }
+    let id = path.into_inner();
+    let Some(current) = hooks.find(id) else {
+        return HttpResponse::NotFound().body("No such webhook");
+    };
+    let settings = match body.into_inner().settings(current.secret) {
+        Ok(s) => s,
+        Err(message) => return HttpResponse::BadRequest().body(message),
+    };
+    match hooks.update(id, &settings).await {
+        Ok(Some(hook)) => {
+            info!("Updated webhook {}", id);
+            HttpResponse::Ok().json(WebhookRow::from(hook))
+        }
+        Ok(None) => HttpResponse::NotFound().body("No such webhook"),
+        Err(e) => {
+            error!("Could not update webhook {}: {}", id, e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[utoipa::path(
+    delete,
+    tag = "Admin",
+    params(
+        ("Authorization" = String, Header, description = "Bearer <ADMIN_TOKEN>"),
+        ("id" = i64, Path, description = "Webhook id"),
+    ),
+    responses(
+        (status = 204, description = "Webhook deleted"),
+        (status = 401, description = "Missing or wrong token"),
+        (status = 404, description = "No such webhook"),
+        (status = 503, description = "Admin endpoints are disabled"),
+        (status = 500, description = "Server Vzorvalsya")
+    )
+)]
+
+#[delete("/webhooks/{id}")]
+pub async fn delete_webhook(cfg: web::Data<AdminConfig>, hooks: web::Data<Webhooks>, path: web::Path<i64>, req: HttpRequest) -> HttpResponse {
+    if let Some(resp) = cfg.reject(&req) {
+        return resp;
+    }
+    let id = path.into_inner();
+    match hooks.delete(id).await {
+        Ok(true) => {
+            info!("Deleted webhook {}", id);
+            HttpResponse::NoContent().finish()
+        }
+        Ok(false) => HttpResponse::NotFound().body("No such webhook"),
+        Err(e) => {
+            error!("Could not delete webhook {}: {}", id, e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Totals and in-process counters behind the `/admin` page
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct AdminStats {
+    /// Stored points and trips, from the global stats rollup
+    pub points: u64,
+    pub trips: u64,
+    pub ingestion: IngestionStats,
+    pub caches: CacheStats,
+    /// Requests are failing over to the stale cache because the database is unreachable
+    #[serde(rename = "databaseDegraded")]
+    pub database_degraded: bool,
+}
+
+/// Counted by this instance since it started
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct IngestionStats {
+    /// Points stored per minute over the last minute
+    #[serde(rename = "perMinute")]
+    pub per_minute: u64,
+    /// Points stored over the last hour, or since startup when that was more recent
+    #[serde(rename = "lastHour")]
+    pub last_hour: u64,
+    #[serde(rename = "sinceStart")]
+    pub since_start: u64,
+    #[serde(rename = "startedAt")]
+    pub started_at: DateTime<Utc>,
+    /// Points waiting for the anomaly classifier
+    #[serde(rename = "classificationQueue")]
+    pub classification_queue: usize,
+    /// Points waiting for a bulk insert; null without INGEST_BATCH_ROWS
+    #[serde(rename = "batchPending")]
+    pub batch_pending: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct CacheStats {
+    pub images: ImageCacheStats,
+    #[serde(rename = "staleResponses")]
+    pub stale_responses: StaleCacheStats,
+}
+
+/// GET responses kept for database outages (STALE_CACHE_ENTRIES)
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct StaleCacheStats {
+    pub entries: usize,
+    pub capacity: usize,
+    /// Responses answered from it since startup
+    pub served: u64,
+}
+
+#[utoipa::path(
+    get,
+    tag = "Admin",
+    params(
+        ("Authorization" = String, Header, description = "Bearer <ADMIN_TOKEN>"),
+    ),
+    responses(
+        (status = 200, description = "Point and trip totals, the ingestion rate over the last minute and hour, and cache sizes", body = AdminStats),
+        (status = 401, description = "Missing or wrong token"),
+        (status = 503, description = "Admin endpoints are disabled"),
+        (status = 500, description = "Server Vzorvalsya")
+    )
+)]
+
+#[get("/stats")]
+#[allow(clippy::too_many_arguments)]
+pub async fn admin_stats(
+    cfg: web::Data<AdminConfig>,
+    trips: web::Data<dyn TripStore>,
+    queue: web::Data<ClassificationQueue>,
+    batcher: Option<web::Data<IngestBatcher>>,
+    images: web::Data<ImageCache>,
+    stale: web::Data<StaleCache>,
+    breaker: web::Data<CircuitBreaker>,
+    req: HttpRequest,
+) -> HttpResponse {
+    if let Some(resp) = cfg.reject(&req) {
+        return resp;
+    }
+    let totals = match trips.live_stats(1).await {
+        Ok(t) => t,
+        Err(e) => {
+            error!("Admin stats query failed: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let snapshot = metrics().snapshot();
+    HttpResponse::Ok().json(AdminStats {
+        points: totals.total_points,
+        trips: totals.total_trips,
+        ingestion: IngestionStats {
+            per_minute: snapshot.ingest_per_minute,
+            last_hour: snapshot.ingested_last_hour,
+            since_start: snapshot.points_ingested,
+            started_at: snapshot.started_at,
+            classification_queue: queue.depth(),
+            batch_pending: batcher.map(|b| b.pending()),
+        },
+        caches: CacheStats {
+            images: images.stats(),
+            stale_responses: StaleCacheStats { entries: stale.len(), capacity: stale.capacity(), served: snapshot.stale_responses },
+        },
+        database_degraded: breaker.is_degraded(),
+    })
+}
+
+/// Default and maximum number of points for `GET /api/admin/stats/anomalies`
+const DEFAULT_RECENT_ANOMALIES: u64 = 20;
+const MAX_RECENT_ANOMALIES: u64 = 200;
+
+/// A point the classifier or a reviewer flagged
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct RecentAnomaly {
+    pub id: i64,
+    pub randomized_id: i64,
+    pub lat: f64,
+    pub lng: f64,
+    pub spd: f64,
+    pub timestamp: Option<DateTime<Utc>>,
+    /// 0..1, null for points classified before scores were kept
+    pub score: Option<f64>,
+    /// Rule or classifier behind the flag
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct RecentAnomalies {
+    /// Newest first
+    pub anomalies: Vec<RecentAnomaly>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct RecentAnomaliesQueryParams {
+    pub limit: Option<u64>,
+}
+
+#[utoipa::path(
+    get,
+    tag = "Admin",
+    params(
+        ("Authorization" = String, Header, description = "Bearer <ADMIN_TOKEN>"),
+        ("limit" = u64, Query, description = "Number of points, default 20, max 200"),
+    ),
+    responses(
+        (status = 200, description = "The most recent anomalous points, newest first", body = RecentAnomalies),
+        (status = 400, description = "Invalid limit"),
+        (status = 401, description = "Missing or wrong token"),
+        (status = 503, description = "Admin endpoints are disabled"),
+        (status = 500, description = "Server Vzorvalsya")
+    )
+)]
+
+#[get("/stats/anomalies")]
+pub async fn recent_anomalies(
+    cfg: web::Data<AdminConfig>,
+    store: web::Data<dyn PointStore>,
+    qp: web::Query<RecentAnomaliesQueryParams>,
+    req: HttpRequest,
+) -> HttpResponse {
+    if let Some(resp) = cfg.reject(&req) {
+        return resp;
+    }
+    let limit = qp.limit.unwrap_or(DEFAULT_RECENT_ANOMALIES);
+    if limit == 0 || limit > MAX_RECENT_ANOMALIES {
+        return HttpResponse::BadRequest().body(format!("limit must be between 1 and {}", MAX_RECENT_ANOMALIES));
+    }
+    let filter = PointFilter { anomaly: Some(true), ..Default::default() };
+    match store.find(&filter, PointOrder::TimestampDesc, Some(limit)).await {
+        Ok(points) => HttpResponse::Ok().json(RecentAnomalies {
+            anomalies: points
+                .into_iter()
+                .map(|p| RecentAnomaly {
+                    id: p.id,
+                    randomized_id: p.randomized_id,
+                    lat: p.lat,
+                    lng: p.lng,
+                    spd: p.spd,
+                    timestamp: p.timestamp,
+                    score: p.anomaly_score,
+                    reason: p.anomaly_reason,
+                })
+                .collect(),
+        }),
+        Err(e) => {
+            error!("Recent anomalies query failed: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Kinds of the jobs started from here
+const REPORT_JOB: &str = "report";
+const ROLLUP_JOB: &str = "rollup";
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct ReportJobQueryParams {
+    #[serde(rename = "weekStart")] pub week_start: Option<NaiveDate>,
+    pub tenant: Option<String>,
+}
+
+#[utoipa::path(
+    post,
+    tag = "Admin",
+    params(
+        ("Authorization" = String, Header, description = "Bearer <ADMIN_TOKEN>"),
+        ("weekStart" = NaiveDate, Query, description = "Monday of the week to report on (UTC); default the last finished week"),
+        ("tenant" = String, Query, description = "Tenant to report on, when tenants are configured; default the rows without a tenant"),
+    ),
+    responses(
+        (status = 202, description = "Report job started; poll the URL in the Location header. Its result lists the files like GET /api/reports", body = Job),
+        (status = 400, description = "weekStart is not a Monday, or a tenant without tenants configured"),
+        (status = 401, description = "Missing or wrong token"),
+        (status = 503, description = "Admin endpoints or reports are disabled"),
+        (status = 500, description = "Server Vzorvalsya")
+    )
+)]
+
+/// Generates the weekly report of a week now, replacing the one on disk, e.g. after late uploads
+#[post("/reports")]
+pub async fn generate_report(
+    cfg: web::Data<AdminConfig>,
+    jobs: web::Data<Jobs>,
+    store: web::Data<dyn PointStore>,
+    config: Option<web::Data<ReportConfig>>,
+    qp: web::Query<ReportJobQueryParams>,
+    req: HttpRequest,
+) -> HttpResponse {
+    if let Some(resp) = cfg.reject(&req) {
+        return resp;
+    }
+    let Some(config) = config else {
+        return HttpResponse::ServiceUnavailable().body("Reports are disabled (REPORTS_DIR not set)");
+    };
+    let week = qp.week_start.unwrap_or_else(|| reports::last_finished_week(Utc::now()));
+    if week.weekday() != Weekday::Mon {
+        return HttpResponse::BadRequest().body("weekStart must be a Monday");
+    }
+    let name = qp.tenant.as_deref().map(str::trim);
+    if let Some(t) = name
+        && (!config.tenants || !tenant::is_valid_name(t))
+    {
+        return HttpResponse::BadRequest().body("tenant must name a tenant of TENANT_API_KEYS or TENANT_HEADER");
+    }
+    let scope = config.scope(name);
+    let work = move |_| async move {
+        let report = reports::generate(store.get_ref(), &config, week, scope.as_ref()).await?;
+        serde_json::to_value(Report::from(report)).map_err(|e| e.to_string())
+    };
+    start_job(&jobs, REPORT_JOB, json!({"weekStart": week, "tenant": name}), work).await
+}
+
+#[utoipa::path(
+    post,
+    tag = "Admin",
+    params(
+        ("Authorization" = String, Header, description = "Bearer <ADMIN_TOKEN>"),
+    ),
+    responses(
+        (status = 202, description = "Rollup job started; poll the URL in the Location header. Its result counts the tile hours computed", body = Job),
+        (status = 401, description = "Missing or wrong token"),
+        (status = 503, description = "Admin endpoints or tile rollups are disabled"),
+        (status = 500, description = "Server Vzorvalsya")
+    )
+)]
+
+/// Refreshes the tile rollups now instead of at the next TILE_ROLLUP_INTERVAL_SECS tick
+#[post("/rollups")]
+pub async fn refresh_rollups(
+    cfg: web::Data<AdminConfig>,
+    jobs: web::Data<Jobs>,
+    store: web::Data<dyn PointStore>,
+    rollups: Option<web::Data<dyn TileStatsStore>>,
+    config: Option<web::Data<RollupConfig>>,
+    req: HttpRequest,
+) -> HttpResponse {
+    if let Some(resp) = cfg.reject(&req) {
+        return resp;
+    }
+    let (Some(rollups), Some(config)) = (rollups, config) else {
+        return HttpResponse::ServiceUnavailable().body("Tile rollups are disabled (TILE_ROLLUP_DEGREES not set)");
+    };
+    let work = move |job: RunningJob| async move {
+        let hours = tile_rollup::run_once(store.get_ref(), rollups.get_ref(), &config).await.map_err(|e| e.to_string())?;
+        job.advance(hours as i64);
+        Ok(json!({"tileHours": hours}))
+    };
+    start_job(&jobs, ROLLUP_JOB, json!({}), work).await
+}
+
+/// Starts a job and answers 202 pointing at it
+async fn start_job<W, F>(jobs: &Jobs, kind: &str, params: serde_json::Value, work: W) -> HttpResponse
+where
+    W: FnOnce(RunningJob) -> F + Send + 'static,
+    F: Future<Output = Result<serde_json::Value, String>> + Send + 'static,
+{
+    match jobs.start(kind, params, work).await {
+        Ok(job) => {
+            info!("Started {} job {}", kind, job.id);
+            accepted(job)
+        }
+        Err(e) => {
+            error!("Could not start a {} job: {}", kind, e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+pub fn routes() -> ApiScope {
+    ApiScope::new("/admin")
+        .service(admin_stats)
+        .service(recent_anomalies)
+        .service(image_cache_stats)
+        .service(index_advice)
+        .service(list_quarantine)
+        .service(discard_quarantined)
+        .service(list_webhooks)
+        .service(create_webhook)
+        .service(get_webhook)
+        .service(update_webhook)
+        .service(delete_webhook)
+        .service(generate_report)
+        .service(refresh_rollups)
+}
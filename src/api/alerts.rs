@@ -0,0 +1,405 @@
+//! Alert rules and the history of the alerts they raised (see `crate::alerts`), with a stream of
+//! new alerts as Server-Sent Events. Each tenant sees only its own rules and alerts, and only
+//! those older than the publication delay; changing rules takes the admin token.
+
+use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::alerts::{self, Alert, AlertFeed, Alerts, RuleKind, RuleParams};
+use crate::database::model::alert_rules::Model as AlertRuleModel;
+use crate::database::store::{AlertFilter, AlertRuleSettings};
+use crate::geofences::{self, Geofences};
+use crate::tenant;
+use super::admin::AdminConfig;
+use super::error::{ApiError, ApiErrorBody};
+use super::points::MAX_DEVICE_ID_LEN;
+use super::registry::ApiScope;
+use super::{geofences as geofence_api, sse, validate};
+
+const MAX_NAME_LEN: usize = 200;
+/// Longest silence an inactivity rule may wait for, a year of minutes
+const MAX_SILENT_MINUTES: i64 = 366 * 24 * 60;
+/// Default and maximum page size for `GET /api/alerts`
+const DEFAULT_LIMIT: u64 = 100;
+const MAX_LIMIT: u64 = 1000;
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct AlertRule {
+    pub id: i64,
+    pub name: String,
+    /// speed, geofence or inactivity
+    pub kind: String,
+    pub enabled: bool,
+    #[serde(flatten)]
+    pub params: RuleParams,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<Utc>,
+    #[serde(rename = "updatedAt")]
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<AlertRuleModel> for AlertRule {
+    fn from(rule: AlertRuleModel) -> Self {
+        Self {
+            params: alerts::params_of(&rule),
+            id: rule.id,
+            name: rule.name,
+            kind: rule.kind,
+            enabled: rule.enabled,
+            created_at: rule.created_at,
+            updated_at: rule.updated_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct AlertRuleList {
+    /// Oldest first
+    pub rules: Vec<AlertRule>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct AlertRuleBody {
+    pub name: String,
+    /// `speed`: a point faster than `maxSpeed` km/h, inside `geofenceId` when given, once per
+    /// trip; `geofence`: a trip entering or leaving `geofenceId` (`on` picks `entry`, `exit` or
+    /// both); `inactivity`: a device without a trip ending for `silentMinutes`, or just
+    /// `deviceId`, once per silence
+    pub kind: String,
+    /// Default true
+    pub enabled: Option<bool>,
+    #[serde(flatten)]
+    pub params: RuleParams,
+}
+
+impl AlertRuleBody {
+    /// The settings to store, keeping only the params of the kind
+    fn settings(self, fences: &Geofences) -> Result<AlertRuleSettings, ApiError> {
+        let name = self.name.trim();
+        if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+            return Err(ApiError::bad_param("name", format!("name must be 1 to {} characters", MAX_NAME_LEN)));
+        }
+        let Some(kind) = RuleKind::parse(&self.kind) else {
+            let known: Vec<_> = RuleKind::ALL.iter().map(|k| k.as_str()).collect();
+            return Err(ApiError::bad_param("kind", format!("unknown kind {:?}; rules are {}", self.kind, known.join(", "))));
+        };
+        let p = self.params;
+        let params = match kind {
+            RuleKind::Speed => {
+                let max_speed = p.max_speed.filter(|s| s.is_finite() && *s > 0.0).ok_or_else(|| ApiError::bad_param("maxSpeed", "maxSpeed must be a positive number of km/h"))?;
+                if let Some(id) = p.geofence_id {
+                    geofence(fences, id)?;
+                }
+                RuleParams { max_speed: Some(max_speed), geofence_id: p.geofence_id, ..Default::default() }
+            }
+            RuleKind::Geofence => {
+                let id = p.geofence_id.ok_or_else(|| ApiError::bad_param("geofenceId", "geofenceId is required"))?;
+                geofence(fences, id)?;
+                if let Some(on) = &p.on
+                    && (on.is_empty() || on.iter().any(|k| k != geofences::ENTRY && k != geofences::EXIT))
+                {
+                    return Err(ApiError::bad_param("on", "on must list entry, exit or both"));
+                }
+                RuleParams { geofence_id: Some(id), on: p.on, ..Default::default() }
+            }
+            RuleKind::Inactivity => {
+                let minutes = p
+                    .silent_minutes
+                    .filter(|m| (1..=MAX_SILENT_MINUTES).contains(m))
+                    .ok_or_else(|| ApiError::bad_param("silentMinutes", format!("silentMinutes must be between 1 and {}", MAX_SILENT_MINUTES)))?;
+                let device_id = p.device_id.map(|d| d.trim().to_string());
+                if device_id.as_ref().is_some_and(|d| d.is_empty() || d.chars().count() > MAX_DEVICE_ID_LEN) {
+                    return Err(ApiError::bad_param("deviceId", format!("deviceId must be 1 to {} characters", MAX_DEVICE_ID_LEN)));
+                }
+                RuleParams { silent_minutes: Some(minutes), device_id, ..Default::default() }
+            }
+        };
+        let params = serde_json::to_value(params).map_err(|e| {
+            error!("Alert rule params serialization failed: {}", e);
+            ApiError::Internal
+        })?;
+        Ok(AlertRuleSettings { name: name.to_string(), kind: kind.as_str().to_string(), params, enabled: self.enabled.unwrap_or(true) })
+    }
+}
+
+/// A geofence the rule may watch: one the current request sees
+fn geofence(fences: &Geofences, id: i64) -> Result<(), ApiError> {
+    geofence_api::visible(fences, id).map(|_| ()).map_err(|_| ApiError::bad_param("geofenceId", format!("No geofence {}", id)))
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct AlertsPage {
+    /// Newest first
+    pub alerts: Vec<Alert>,
+    /// Pass as `cursor` to get the next page; null on the last one
+    #[serde(rename = "nextCursor")]
+    pub next_cursor: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct AlertsQueryParams {
+    #[serde(rename = "ruleId")]
+    pub rule_id: Option<i64>,
+    pub kind: Option<String>,
+    #[serde(rename = "randomizedId")]
+    pub randomized_id: Option<i64>,
+    #[serde(rename = "deviceId")]
+    pub device_id: Option<String>,
+    #[serde(rename = "dateStart")]
+    pub date_start: Option<DateTime<Utc>>,
+    #[serde(rename = "dateEnd")]
+    pub date_end: Option<DateTime<Utc>>,
+    pub limit: Option<u64>,
+    pub cursor: Option<i64>,
+}
+
+/// The rule if the current request may see it; another tenant's rules do not exist for it
+fn visible(rules: &Alerts, id: i64) -> Result<AlertRuleModel, ApiError> {
+    rules
+        .find(id)
+        .filter(|r| tenant::current().is_none_or(|scope| r.tenant_id.as_deref() == scope.tenant_id()))
+        .ok_or_else(|| ApiError::NotFound(format!("No alert rule {}", id)))
+}
+
+#[utoipa::path(
+    get,
+    tag = "Alerts",
+    params(
+        ("ruleId" = i64, Query, description = "Only alerts of this rule. Optional"),
+        ("kind" = String, Query, description = "Only alerts of rules of this kind: speed, geofence or inactivity. Optional"),
+        ("randomizedId" = i64, Query, description = "Only alerts of this trip. Optional"),
+        ("deviceId" = String, Query, description = "Only alerts of this device. Optional"),
+        ("dateStart" = DateTime<chrono::Utc>, Query, description = "Only alerts whose condition was met at or after this time. Optional"),
+        ("dateEnd" = DateTime<chrono::Utc>, Query, description = "Only alerts whose condition was met at or before this time. Optional"),
+        ("limit" = u64, Query, description = "Page size, default 100, max 1000"),
+        ("cursor" = i64, Query, description = "Only alerts raised before this one; pass nextCursor of the previous page. Optional"),
+    ),
+    responses(
+        (status = 200, description = "Alerts raised by the rules, newest first; deleted rules keep theirs. Alerts younger than the publication delay are left out", body = AlertsPage),
+        (status = 400, description = "Invalid parameters", body = ApiErrorBody),
+        (status = 500, description = "Server Vzorvalsya", body = ApiErrorBody)
+    )
+)]
+
+/// History of the alerts raised by the rules of `/api/alerts/rules`
+#[get("")]
+pub async fn list_alerts(rules: web::Data<Alerts>, qp: web::Query<AlertsQueryParams>) -> Result<HttpResponse, ApiError> {
+    validate::date_range(qp.date_start, qp.date_end)?;
+    if let Some(kind) = &qp.kind
+        && RuleKind::parse(kind).is_none()
+    {
+        return Err(ApiError::bad_param("kind", "kind must be speed, geofence or inactivity"));
+    }
+    let limit = qp.limit.unwrap_or(DEFAULT_LIMIT);
+    if limit == 0 || limit > MAX_LIMIT {
+        return Err(ApiError::bad_param("limit", format!("limit must be between 1 and {}", MAX_LIMIT)));
+    }
+    let filter = AlertFilter {
+        rule_id: qp.rule_id,
+        kind: qp.kind.as_deref().map(|k| k.trim().to_string()),
+        randomized_id: qp.randomized_id,
+        device_id: qp.device_id.clone(),
+        since: qp.date_start,
+        until: qp.date_end,
+        before_id: qp.cursor,
+        tenant: tenant::current(),
+        ..Default::default()
+    };
+    let mut found = rules.history(&filter, limit + 1).await.map_err(|e| {
+        error!("Alerts query failed: {}", e);
+        ApiError::Internal
+    })?;
+    let more = found.len() as u64 > limit;
+    found.truncate(limit as usize);
+    let next_cursor = if more { found.last().map(|a| a.id) } else { None };
+    Ok(HttpResponse::Ok().json(AlertsPage { alerts: found.into_iter().map(Alert::from).collect(), next_cursor }))
+}
+
+#[utoipa::path(
+    get,
+    tag = "Alerts",
+    params(
+        ("Last-Event-ID" = Option<i64>, Header, description = "Id of the last alert received; the alerts raised since, as far as the server still keeps them, are sent first"),
+    ),
+    responses(
+        (status = 200, description = "Server-Sent Events: each alert as `data` with its id as `id`, once the publication delay of its point has passed, and a `: heartbeat` comment every ANOMALY_STREAM_HEARTBEAT_SECS", body = Alert, content_type = "text/event-stream"),
+    )
+)]
+
+/// Alerts as the rules raise them
+#[get("/stream")]
+pub async fn stream_alerts(feed: web::Data<AlertFeed>, req: HttpRequest) -> HttpResponse {
+    sse::respond(feed.into_inner(), sse::last_event_id(&req), tenant::current())
+}
+
+#[utoipa::path(
+    post,
+    tag = "Alerts",
+    params(
+        ("Authorization" = String, Header, description = "Bearer <ADMIN_TOKEN>"),
+    ),
+    request_body = AlertRuleBody,
+    responses(
+        (status = 201, description = "Rule created", body = AlertRule),
+        (status = 400, description = "Invalid name, kind or params", body = ApiErrorBody),
+        (status = 401, description = "Missing or wrong token"),
+        (status = 503, description = "Admin endpoints are disabled"),
+        (status = 500, description = "Server Vzorvalsya", body = ApiErrorBody)
+    )
+)]
+
+#[post("/rules")]
+pub async fn create_rule(
+    cfg: web::Data<AdminConfig>,
+    rules: web::Data<Alerts>,
+    fences: web::Data<Geofences>,
+    body: web::Json<AlertRuleBody>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    if let Some(resp) = cfg.reject(&req) {
+        return Ok(resp);
+    }
+    let settings = body.into_inner().settings(&fences)?;
+    let rule = rules.create(&settings, tenant::current_id()).await.map_err(|e| {
+        error!("Could not create alert rule: {}", e);
+        ApiError::Internal
+    })?;
+    info!("Created alert rule {} ({}, {})", rule.id, rule.name, rule.kind);
+    Ok(HttpResponse::Created().json(AlertRule::from(rule)))
+}
+
+#[utoipa::path(
+    get,
+    tag = "Alerts",
+    responses(
+        (status = 200, description = "Rules of the tenant, disabled ones included", body = AlertRuleList),
+    )
+)]
+
+#[get("/rules")]
+pub async fn list_rules(rules: web::Data<Alerts>) -> HttpResponse {
+    let scope = tenant::current();
+    let rules = rules
+        .all()
+        .into_iter()
+        .filter(|r| scope.as_ref().is_none_or(|scope| r.tenant_id.as_deref() == scope.tenant_id()))
+        .map(AlertRule::from)
+        .collect();
+    HttpResponse::Ok().json(AlertRuleList { rules })
+}
+
+#[utoipa::path(
+    get,
+    tag = "Alerts",
+    params(
+        ("id" = i64, Path, description = "Rule id"),
+    ),
+    responses(
+        (status = 200, description = "The rule", body = AlertRule),
+        (status = 404, description = "No such rule", body = ApiErrorBody),
+    )
+)]
+
+#[get("/rules/{id}")]
+pub async fn get_rule(rules: web::Data<Alerts>, path: web::Path<i64>) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(AlertRule::from(visible(&rules, path.into_inner())?)))
+}
+
+#[utoipa::path(
+    put,
+    tag = "Alerts",
+    params(
+        ("id" = i64, Path, description = "Rule id"),
+        ("Authorization" = String, Header, description = "Bearer <ADMIN_TOKEN>"),
+    ),
+    request_body = AlertRuleBody,
+    responses(
+        (status = 200, description = "Rule replaced", body = AlertRule),
+        (status = 400, description = "Invalid name, kind or params", body = ApiErrorBody),
+        (status = 401, description = "Missing or wrong token"),
+        (status = 503, description = "Admin endpoints are disabled"),
+        (status = 404, description = "No such rule", body = ApiErrorBody),
+        (status = 500, description = "Server Vzorvalsya", body = ApiErrorBody)
+    )
+)]
+
+#[put("/rules/{id}")]
+pub async fn update_rule(
+    cfg: web::Data<AdminConfig>,
+    rules: web::Data<Alerts>,
+    fences: web::Data<Geofences>,
+    path: web::Path<i64>,
+    body: web::Json<AlertRuleBody>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    if let Some(resp) = cfg.reject(&req) {
+        return Ok(resp);
+    }
+    let id = visible(&rules, path.into_inner())?.id;
+    let settings = body.into_inner().settings(&fences)?;
+    match rules.update(id, &settings).await {
+        Ok(Some(rule)) => {
+            info!("Updated alert rule {}", id);
+            Ok(HttpResponse::Ok().json(AlertRule::from(rule)))
+        }
+        Ok(None) => Err(ApiError::NotFound(format!("No alert rule {}", id))),
+        Err(e) => {
+            error!("Could not update alert rule {}: {}", id, e);
+            Err(ApiError::Internal)
+        }
+    }
+}
+
+#[utoipa::path(
+    delete,
+    tag = "Alerts",
+    params(
+        ("id" = i64, Path, description = "Rule id"),
+        ("Authorization" = String, Header, description = "Bearer <ADMIN_TOKEN>"),
+    ),
+    responses(
+        (status = 204, description = "Rule deleted; the alerts it raised stay in the history"),
+        (status = 401, description = "Missing or wrong token"),
+        (status = 503, description = "Admin endpoints are disabled"),
+        (status = 404, description = "No such rule", body = ApiErrorBody),
+        (status = 500, description = "Server Vzorvalsya", body = ApiErrorBody)
+    )
+)]
+
+#[delete("/rules/{id}")]
+pub async fn delete_rule(
+    cfg: web::Data<AdminConfig>,
+    rules: web::Data<Alerts>,
+    path: web::Path<i64>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    if let Some(resp) = cfg.reject(&req) {
+        return Ok(resp);
+    }
+    let id = visible(&rules, path.into_inner())?.id;
+    match rules.delete(id).await {
+        Ok(true) => {
+            info!("Deleted alert rule {}", id);
+            Ok(HttpResponse::NoContent().finish())
+        }
+        Ok(false) => Err(ApiError::NotFound(format!("No alert rule {}", id))),
+        Err(e) => {
+            error!("Could not delete alert rule {}: {}", id, e);
+            Err(ApiError::Internal)
+        }
+    }
+}
+
+pub fn routes() -> ApiScope {
+    ApiScope::new("/alerts")
+        .service(list_alerts)
+        .service(stream_alerts)
+        .service(create_rule)
+        .service(list_rules)
+        .service(get_rule)
+        .service(update_rule)
+        .service(delete_rule)
+}
@@ -1,89 +1,229 @@
-use actix_web::{get, web, HttpResponse};
-use chrono::DateTime;
-use log::{debug, error};
-use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder};
+use actix_web::{get, patch, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use log::{debug, error, info};
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
-use crate::database::model::points::{self, Entity as Points};
+use crate::telemetry::QueryStats;
+use crate::database::model::points::Model as PointModel;
+use std::collections::HashSet;
+use crate::database::store::{PointFilter, PointOrder, PointStore, StoreResult, TripFilter, TripOrder, TripStore};
+use super::admin::AdminConfig;
+use super::defaults::defaults;
+use super::error::{ApiError, ApiErrorBody};
+use super::validate;
+use super::registry::ApiScope;
+use super::sample;
+use super::sse;
+use crate::anomaly::{AnomalousTrip, AnomalyFeed};
+use crate::geocoding::{Geocoder, Place};
 
 #[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
 pub struct MapPointTs {
 	pub lat: f64,
 	pub lng: f64,
 	pub timestamp: Option<DateTime<chrono::Utc>>,
+	/// Set only with `mode=trips`, to tell the anomalous segment from the rest of the route
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub anomaly: Option<bool>,
+	/// 0..1, see `GET /api/points`; null for points classified before scores existed
+	pub score: Option<f64>,
+	/// Rule or classifier that flagged the point
+	pub reason: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
 pub struct AnomalyRoute {
 	pub randomized_id: i64,
 	pub points: Vec<MapPointTs>,
+	/// With `geocode=true`: where the first anomalous point of the route lies; missing when the
+	/// geocoder knows no name there or was not asked yet
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub place: Option<Place>,
 }
 
 #[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
 pub struct AnomaliesResponse {
+	/// Ordered by randomized_id
 	pub anomalies: Vec<AnomalyRoute>,
+	/// Number of routes matching the filters, ignoring limit/offset/cursor
+	pub total: u64,
+	pub limit: Option<u64>,
+	pub offset: u64,
+	/// Pass as `cursor` to get the next page; null on the last one
+	#[serde(rename = "nextCursor")]
+	pub next_cursor: Option<i64>,
+}
+
+/// What `GET /api/anomalies` returns for each trip
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AnomaliesMode {
+	/// Only the anomalous points inside the bbox and date range
+	#[default]
+	Points,
+	/// Every point of each trip with an anomalous one, wherever and whenever it was recorded
+	Trips,
 }
 
 #[derive(Debug, Deserialize, Serialize, ToSchema)]
 pub struct AnomaliesQueryParams {
-	#[serde(rename = "lat1")] pub lat1: f64,
-	#[serde(rename = "lng1")] pub lng1: f64,
-	#[serde(rename = "lat2")] pub lat2: f64,
-	#[serde(rename = "lng2")] pub lng2: f64,
+	#[serde(rename = "lat1")] pub lat1: Option<f64>,
+	#[serde(rename = "lng1")] pub lng1: Option<f64>,
+	#[serde(rename = "lat2")] pub lat2: Option<f64>,
+	#[serde(rename = "lng2")] pub lng2: Option<f64>,
 	#[serde(rename = "dateStart")] pub date_start: Option<DateTime<chrono::Utc>>, // inclusive
 	#[serde(rename = "dateEnd")] pub date_end: Option<DateTime<chrono::Utc>>,     // inclusive
+	/// Keep at most this many anomalous points, spread over the bbox
+	pub sample: Option<usize>,
+	/// Only trips with at least this share of anomalous points, 0..1
+	#[serde(rename = "minAnomalyRatio")] pub min_anomaly_ratio: Option<f64>,
+	/// Only trips with at least this many anomalous points
+	#[serde(rename = "minAnomalyPoints")] pub min_anomaly_points: Option<i64>,
+	pub mode: Option<AnomaliesMode>,
+	/// Only anomalous points scored at least this high, 0..1
+	#[serde(rename = "minScore")] pub min_score: Option<f64>,
+	/// Routes per page; all of them when omitted
+	pub limit: Option<u64>,
+	/// Routes to skip, counted after `cursor`
+	pub offset: Option<u64>,
+	/// Only routes after this randomized_id (`nextCursor` of the previous page)
+	pub cursor: Option<i64>,
+	/// Thin every route down to this many points, evenly spaced, first and last kept
+	#[serde(rename = "maxPointsPerRoute")] pub max_points_per_route: Option<usize>,
+	/// Douglas–Peucker tolerance for every route, meters
+	pub tolerance: Option<f64>,
+	/// Name where each route turned anomalous (needs GEOCODER_URL)
+	pub geocode: Option<bool>,
 }
 
+/// Trip ids per lookup in the trip summary table
+const TRIP_LOOKUP_CHUNK: usize = 10_000;
+/// Largest page of routes
+const MAX_LIMIT: u64 = 1000;
+
 #[utoipa::path(
 	get,
-	path = "/api/anomalies",
 	tag = "Anomalies",
 	params(
-		("lat1" = f64, Query, description = "First latitude (corner)"),
+		("lat1" = f64, Query, description = "First latitude (corner). All four corners may be omitted when the deployment sets DEFAULT_BBOX"),
 		("lng1" = f64, Query, description = "First longitude (corner)"),
 		("lat2" = f64, Query, description = "Second latitude (opposite corner)"),
 		("lng2" = f64, Query, description = "Second longitude (opposite corner)"),
 		("dateStart" = DateTime<chrono::Utc>, Query, description = "Start of the date/time range (inclusive). Optional"),
 		("dateEnd" = DateTime<chrono::Utc>, Query, description = "End of the date/time range (inclusive). Optional"),
+		("sample" = usize, Query, description = "Return up to N anomalous points spread over the whole bbox, still grouped by trip. Max 10000. Optional"),
+		("minAnomalyRatio" = f64, Query, description = "Only trips where at least this share (0..1) of all their points is anomalous. Optional"),
+		("minAnomalyPoints" = i64, Query, description = "Only trips with at least this many anomalous points. Optional"),
+		("mode" = AnomaliesMode, Query, description = "points (default): only the anomalous points; trips: the whole ordered route of every trip with an anomalous point, each point carrying its anomaly flag. Cannot be combined with sample"),
+		("minScore" = f64, Query, description = "Only anomalous points with anomaly_score at least this high (0..1; a rule flags from 0.5). In trips mode it picks the trips, whose routes stay whole. Optional"),
+		("limit" = u64, Query, description = "Routes per page, max 1000. Optional; all routes when omitted"),
+		("offset" = u64, Query, description = "Number of routes to skip, counted after cursor, default 0"),
+		("cursor" = i64, Query, description = "Only routes with a greater randomized_id; pass nextCursor of the previous page. Optional"),
+		("maxPointsPerRoute" = usize, Query, description = "Thin each route to at most N evenly spaced points, keeping its first and last. Optional"),
+		("tolerance" = f64, Query, description = "Simplify each route (Ramer–Douglas–Peucker), dropping points within this many meters (up to 100000) of the simplified line; first and last points stay, and in trips mode so do the ends of every anomalous stretch. Applied before maxPointsPerRoute. Optional"),
+		("geocode" = bool, Query, description = "Add `place` to each route: street, district and city of its first anomalous point, from the reverse geocoder (GEOCODER_URL). Ignored when no geocoder is configured. Optional"),
 	),
 	responses(
 		(status = 200, description = "Anomalous routes", body = AnomaliesResponse),
-		(status = 500, description = "Server error"),
+		(status = 400, description = "Invalid parameters", body = ApiErrorBody),
+		(status = 500, description = "Server error", body = ApiErrorBody),
 	)
 )]
 #[get("")]
 pub async fn get_anomalies(
-	db: web::Data<DatabaseConnection>,
+	store: web::Data<dyn PointStore>,
+	trips: web::Data<dyn TripStore>,
+	geocoder: Option<web::Data<Geocoder>>,
 	qp: web::Query<AnomaliesQueryParams>,
-) -> HttpResponse {
-	let (lat_min, lat_max) = if qp.lat1 <= qp.lat2 { (qp.lat1, qp.lat2) } else { (qp.lat2, qp.lat1) };
-	let (lng_min, lng_max) = if qp.lng1 <= qp.lng2 { (qp.lng1, qp.lng2) } else { (qp.lng2, qp.lng1) };
-
-	let mut query = Points::find()
-		.filter(points::Column::Lat.between(lat_min, lat_max))
-		.filter(points::Column::Lng.between(lng_min, lng_max))
-		.filter(points::Column::Anomaly.eq(Some(true)));
-
-	if let Some(start) = qp.date_start {
-		query = query.filter(points::Column::Timestamp.gte(start));
+) -> Result<HttpResponse, ApiError> {
+	if let Some(n) = qp.sample
+		&& (n == 0 || n > sample::MAX_SAMPLE)
+	{
+		return Err(ApiError::bad_param("sample", format!("sample must be between 1 and {}", sample::MAX_SAMPLE)));
+	}
+	if qp.limit.is_some_and(|l| l == 0 || l > MAX_LIMIT) {
+		return Err(ApiError::bad_param("limit", format!("limit must be between 1 and {}", MAX_LIMIT)));
+	}
+	if qp.max_points_per_route == Some(0) {
+		return Err(ApiError::bad_param("maxPointsPerRoute", "maxPointsPerRoute must be positive"));
+	}
+	validate::tolerance(qp.tolerance)?;
+	let mode = qp.mode.unwrap_or_default();
+	if mode == AnomaliesMode::Trips && qp.sample.is_some() {
+		return Err(ApiError::bad_param("sample", "sample cannot be combined with mode=trips"));
 	}
-	if let Some(end) = qp.date_end {
-		query = query.filter(points::Column::Timestamp.lte(end));
+	if qp.min_anomaly_ratio.is_some_and(|r| !(0.0..=1.0).contains(&r)) {
+		return Err(ApiError::bad_param("minAnomalyRatio", "minAnomalyRatio must be between 0 and 1"));
 	}
+	if qp.min_score.is_some_and(|s| !(0.0..=1.0).contains(&s)) {
+		return Err(ApiError::bad_param("minScore", "minScore must be between 0 and 1"));
+	}
+	if qp.min_anomaly_points.is_some_and(|n| n < 0) {
+		return Err(ApiError::bad_param("minAnomalyPoints", "minAnomalyPoints must not be negative"));
+	}
+	validate::area(qp.lat1, qp.lng1, qp.lat2, qp.lng2, qp.date_start, qp.date_end)?;
+	let bbox = match defaults().bbox(qp.lat1, qp.lng1, qp.lat2, qp.lng2) {
+		Ok(b) => b,
+		Err(msg) => return Err(ApiError::bad_request(msg)),
+	};
+	let (date_start, date_end) = defaults().date_range(qp.date_start, qp.date_end);
+	let filter = PointFilter {
+		bbox: Some(bbox),
+		since: date_start,
+		until: date_end,
+		anomaly: Some(true),
+		min_anomaly_score: qp.min_score,
+		..Default::default()
+	};
 
-	let rows = match query
-		.order_by_asc(points::Column::RandomizedId)
-		.order_by_asc(points::Column::Timestamp)
-		.all(db.get_ref())
-		.await
-	{
+	let rows = match store.find(&filter, PointOrder::TripThenTimestamp, None).await {
 		Ok(r) => r,
 		Err(e) => {
 			error!("Anomalies query failed: {}", e);
-			return HttpResponse::InternalServerError().finish();
+			return Err(ApiError::Internal);
 		}
 	};
 
+	let rows_scanned = rows.len();
+	let rows = if qp.min_anomaly_ratio.is_some() || qp.min_anomaly_points.is_some() {
+		let qualifying = match qualifying_trips(trips.get_ref(), &rows, qp.min_anomaly_ratio, qp.min_anomaly_points).await {
+			Ok(ids) => ids,
+			Err(e) => {
+				error!("Trip summary lookup failed: {}", e);
+				return Err(ApiError::Internal);
+			}
+		};
+		rows.into_iter().filter(|r| qualifying.contains(&r.randomized_id)).collect()
+	} else {
+		rows
+	};
+	// Sampling keeps row order, so trips stay contiguous and ascending for the paging below
+	let rows = match qp.sample {
+		Some(n) => sample::stratified(rows, n, |p| (p.lat, p.lng)),
+		None => rows,
+	};
+
+	let mut ids: Vec<i64> = rows.iter().map(|r| r.randomized_id).collect();
+	ids.dedup();
+	let offset = qp.offset.unwrap_or(0);
+	let after_cursor = qp.cursor.map_or(0, |c| ids.partition_point(|&id| id <= c));
+	let start = after_cursor.saturating_add(usize::try_from(offset).unwrap_or(usize::MAX)).min(ids.len());
+	let end = qp.limit.map_or(ids.len(), |l| start.saturating_add(l as usize).min(ids.len()));
+	let page = &ids[start..end];
+	let next_cursor = if end < ids.len() && end > start { Some(ids[end - 1]) } else { None };
+
+	let rows = match mode {
+		AnomaliesMode::Trips => match full_trips(store.get_ref(), page).await {
+			Ok(r) => r,
+			Err(e) => {
+				error!("Anomalous trips query failed: {}", e);
+				return Err(ApiError::Internal);
+			}
+		},
+		AnomaliesMode::Points => rows.into_iter().filter(|r| page.binary_search(&r.randomized_id).is_ok()).collect(),
+	};
+	let flags = mode == AnomaliesMode::Trips;
+
 	// Group rows by randomized_id into routes
 	let mut routes: Vec<AnomalyRoute> = Vec::new();
 	let mut cur_id: Option<i64> = None;
@@ -92,15 +232,47 @@ pub async fn get_anomalies(
 	for row in rows.into_iter() {
 		if cur_id != Some(row.randomized_id) {
 			if let Some(id) = cur_id {
-				routes.push(AnomalyRoute { randomized_id: id, points: cur_points });
+				routes.push(AnomalyRoute { randomized_id: id, points: cur_points, place: None });
 				cur_points = Vec::new();
 			}
 			cur_id = Some(row.randomized_id);
 		}
-		cur_points.push(MapPointTs { lat: row.lat, lng: row.lng, timestamp: row.timestamp });
+		cur_points.push(MapPointTs {
+			lat: row.lat,
+			lng: row.lng,
+			timestamp: row.timestamp,
+			anomaly: if flags { Some(row.anomaly.unwrap_or(false)) } else { None },
+			score: row.anomaly_score,
+			reason: row.anomaly_reason,
+		});
 	}
 	if let Some(id) = cur_id {
-		routes.push(AnomalyRoute { randomized_id: id, points: cur_points });
+		routes.push(AnomalyRoute { randomized_id: id, points: cur_points, place: None });
+	}
+	// Named before simplification may drop the point
+	if let (Some(geocoder), Some(true)) = (&geocoder, qp.geocode) {
+		let positions: Vec<(f64, f64)> = routes
+			.iter()
+			.filter_map(|r| r.points.iter().find(|p| p.anomaly != Some(false)).or(r.points.first()))
+			.map(|p| (p.lat, p.lng))
+			.collect();
+		for (route, place) in routes.iter_mut().zip(geocoder.places(&positions).await) {
+			route.place = place;
+		}
+	}
+	if let Some(tolerance) = qp.tolerance {
+		for route in &mut routes {
+			let points = std::mem::take(&mut route.points);
+			// Where the flag changes, both sides stay so the anomalous stretch keeps its ends
+			let flags: Vec<Option<bool>> = points.iter().map(|p| p.anomaly).collect();
+			let edge = |i: usize| flags[i] != flags[i - 1] || flags.get(i + 1).is_some_and(|f| *f != flags[i]);
+			route.points = sample::simplified(points, tolerance, |p| (p.lat, p.lng), edge);
+		}
+	}
+	if let Some(n) = qp.max_points_per_route {
+		for route in &mut routes {
+			route.points = sample::evenly(std::mem::take(&mut route.points), n);
+		}
 	}
 
 	debug!(
@@ -108,9 +280,142 @@ pub async fn get_anomalies(
 		routes.len(),
 		routes.iter().map(|r| r.points.len()).sum::<usize>()
 	);
-	HttpResponse::Ok().json(AnomaliesResponse { anomalies: routes })
+	let stats = QueryStats { rows_scanned, tiles: routes.len() };
+	Ok(stats.attach(HttpResponse::Ok().json(AnomaliesResponse {
+		anomalies: routes,
+		total: ids.len() as u64,
+		limit: qp.limit,
+		offset,
+		next_cursor,
+	})))
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReviewDecision {
+	/// The trip is anomalous, every point of it
+	Confirm,
+	/// False positive: none of the trip is anomalous
+	Dismiss,
 }
 
-pub fn init_routes(cfg: &mut web::ServiceConfig) {
-	cfg.service(web::scope("/anomalies").service(get_anomalies));
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct AnomalyReviewRequest {
+	pub decision: ReviewDecision,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct AnomalyReviewResponse {
+	pub randomized_id: i64,
+	pub anomaly: bool,
+	/// Number of points of the trip
+	pub points: u64,
+	/// Reviewer named by the token of the request
+	pub reviewed_by: String,
+	pub reviewed_at: DateTime<Utc>,
+}
+
+#[utoipa::path(
+	patch,
+	tag = "Anomalies",
+	params(
+		("randomized_id" = i64, Path, description = "Trip to review"),
+		("Authorization" = String, Header, description = "Bearer <token of REVIEWER_TOKENS or ADMIN_TOKEN>"),
+	),
+	request_body = AnomalyReviewRequest,
+	responses(
+		(status = 200, description = "Decision recorded on the trip; the classifier's flags on its points are kept", body = AnomalyReviewResponse),
+		(status = 401, description = "Missing or unknown token", body = ApiErrorBody),
+		(status = 404, description = "No points for this trip", body = ApiErrorBody),
+		(status = 500, description = "Server error", body = ApiErrorBody),
+		(status = 503, description = "Neither REVIEWER_TOKENS nor ADMIN_TOKEN is set", body = ApiErrorBody),
+	)
+)]
+#[patch("/{randomized_id}")]
+pub async fn review_anomaly(
+	cfg: web::Data<AdminConfig>,
+	store: web::Data<dyn PointStore>,
+	path: web::Path<i64>,
+	body: web::Json<AnomalyReviewRequest>,
+	req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+	let reviewer = cfg.reviewer(&req)?;
+	let randomized_id = path.into_inner();
+	let anomaly = body.decision == ReviewDecision::Confirm;
+
+	let points = match store.review_trip(randomized_id, anomaly, &reviewer).await {
+		Ok(0) => return Err(ApiError::NotFound("Trip not found".to_string())),
+		Ok(n) => n,
+		Err(e) => {
+			error!("Anomaly review failed for trip {}: {}", randomized_id, e);
+			return Err(ApiError::Internal);
+		}
+	};
+	info!("Trip {} reviewed as {} ({} points) by {}", randomized_id, if anomaly { "anomalous" } else { "normal" }, points, reviewer);
+	Ok(HttpResponse::Ok().json(AnomalyReviewResponse {
+		randomized_id,
+		anomaly,
+		points,
+		reviewed_by: reviewer,
+		reviewed_at: Utc::now(),
+	}))
+}
+
+#[utoipa::path(
+	get,
+	tag = "Anomalies",
+	params(
+		("Last-Event-ID" = Option<i64>, Header, description = "Id of the last event received; the trips found since, as far as the server still keeps them, are sent first"),
+	),
+	responses(
+		(status = 200, description = "Server-Sent Events: each trip that gets its first anomalous point, as `data` with the point id as `id`, and a `: heartbeat` comment every ANOMALY_STREAM_HEARTBEAT_SECS", body = AnomalousTrip, content_type = "text/event-stream"),
+	)
+)]
+#[get("/stream")]
+pub async fn stream_anomalies(feed: web::Data<AnomalyFeed>, req: HttpRequest) -> HttpResponse {
+	sse::respond(feed.into_inner(), sse::last_event_id(&req), crate::tenant::current())
+}
+
+pub fn routes() -> ApiScope {
+	ApiScope::new("/anomalies")
+		.service(get_anomalies)
+		.service(stream_anomalies)
+		.service(review_anomaly)
+}
+
+// --- Helpers ---
+
+/// Trips among `rows` whose summary passes the anomaly thresholds. The ratio is over all points
+/// of the trip, not only those inside the bbox and date range.
+async fn qualifying_trips(
+	trips: &dyn TripStore,
+	rows: &[PointModel],
+	min_ratio: Option<f64>,
+	min_points: Option<i64>,
+) -> StoreResult<HashSet<i64>> {
+	// Rows are grouped by trip
+	let mut ids: Vec<i64> = rows.iter().map(|r| r.randomized_id).collect();
+	ids.dedup();
+	let mut qualifying = HashSet::new();
+	for chunk in ids.chunks(TRIP_LOOKUP_CHUNK) {
+		let filter = TripFilter {
+			randomized_ids: Some(chunk.to_vec()),
+			min_anomaly_ratio: min_ratio,
+			min_anomaly_points: min_points,
+			..Default::default()
+		};
+		let found = trips.find_trips(&filter, TripOrder::default(), chunk.len() as u64, 0).await?;
+		qualifying.extend(found.into_iter().map(|t| t.randomized_id));
+	}
+	Ok(qualifying)
+}
+
+/// Every point of the trips `ids`, ordered by trip and time
+async fn full_trips(store: &dyn PointStore, ids: &[i64]) -> StoreResult<Vec<PointModel>> {
+	let mut points = Vec::new();
+	for chunk in ids.chunks(TRIP_LOOKUP_CHUNK) {
+		let filter = PointFilter { randomized_ids: Some(chunk.to_vec()), ..Default::default() };
+		points.extend(store.find(&filter, PointOrder::TripThenTimestamp, None).await?);
+	}
+	Ok(points)
 }
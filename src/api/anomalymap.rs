@@ -0,0 +1,220 @@
+use actix_web::{get, web, HttpResponse};
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use utoipa::ToSchema;
+use log::{info, error, debug};
+use std::time::Instant;
+use crate::telemetry::QueryStats;
+use crate::database::store::{PointFilter, PointOrder, PointStore};
+use super::defaults::defaults;
+use super::error::{ApiError, ApiErrorBody};
+use super::fields::TileShape;
+use super::grid::{distinct_trips, MapMeta};
+use super::heatmap::MapPoint;
+use super::registry::ApiScope;
+use super::validate::{self, BinSize};
+
+// Flat query parameters for GET requests (external names in camelCase)
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct AnomalymapQueryParams {
+    /// First latitude (corner)
+    #[serde(rename = "lat1")]
+    pub lat1: Option<f64>,
+    /// First longitude (corner)
+    #[serde(rename = "lng1")]
+    pub lng1: Option<f64>,
+    /// Second latitude (opposite corner)
+    #[serde(rename = "lat2")]
+    pub lat2: Option<f64>,
+    /// Second longitude (opposite corner)
+    #[serde(rename = "lng2")]
+    pub lng2: Option<f64>,
+    /// Optional date range start (inclusive)
+    #[serde(rename = "dateStart")]
+    pub date_start: Option<DateTime<chrono::Utc>>,
+    /// Optional date range end (inclusive)
+    #[serde(rename = "dateEnd")]
+    pub date_end: Option<DateTime<chrono::Utc>>,
+    #[serde(rename = "tileWidth")]
+    pub tile_width: Option<f64>,
+    #[serde(rename = "tileHeight")]
+    pub tile_height: Option<f64>,
+    /// Square tile side in meters, instead of tileWidth/tileHeight
+    #[serde(rename = "tileSizeMeters")]
+    pub tile_size_meters: Option<f64>,
+    /// `grid` (default) or `h3`
+    pub binning: Option<String>,
+    /// H3 resolution with binning=h3 (0..=15, default 8)
+    pub resolution: Option<u8>,
+    /// Comma separated tile fields to send, e.g. `rate,topLeft`
+    pub fields: Option<String>,
+    /// `objects` (default) or `rows`
+    pub encoding: Option<String>,
+}
+
+/// Fields of `AnomalyTile` for `fields`, in the column order of `encoding=rows`
+const ANOMALY_TILE_FIELDS: [&str; 8] = ["count", "incidents", "total", "rate", "neighborCount", "topLeft", "bottomRight", "h3"];
+
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct AnomalyTile {
+    /// Anomalous points in the tile
+    pub count: usize,
+    /// Distinct trips with at least one anomalous point in the tile
+    pub incidents: usize,
+    /// Classified points in the tile (anomalous or not)
+    pub total: usize,
+    /// count / total, 0 when nothing in the tile has been classified yet
+    pub rate: f64,
+    #[serde(rename = "neighborCount")]
+    pub neighbor_count: usize,
+    #[serde(rename = "topLeft")]
+    pub top_left: MapPoint,
+    #[serde(rename = "bottomRight")]
+    pub bottom_right: MapPoint,
+    /// H3 index of the cell with binning=h3
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub h3: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct AnomalymapData {
+    pub data: Vec<AnomalyTile>,
+    /// `tileSize` of the grid or `hexSize` of the hexagons the points were binned into
+    #[serde(flatten)]
+    pub size: BinSize,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct AnomalymapResponse {
+    pub anomalymap: AnomalymapData,
+    /// All points read, classified or not
+    pub meta: MapMeta,
+}
+
+#[utoipa::path(
+    get,
+    tag = "Anomalymap",
+    params(
+    ("lat1" = f64, Query, description = "First latitude (corner). All four corners may be omitted when the deployment sets DEFAULT_BBOX"),
+    ("lng1" = f64, Query, description = "First longitude (corner)"),
+    ("lat2" = f64, Query, description = "Second latitude (opposite corner)"),
+    ("lng2" = f64, Query, description = "Second longitude (opposite corner)"),
+    ("dateStart" = DateTime<chrono::Utc>, Query, description = "Start of the date/time range (inclusive). Optional"),
+    ("dateEnd" = DateTime<chrono::Utc>, Query, description = "End of the date/time range (inclusive). Optional"),
+    ("tileWidth" = f64, Query, description = "Width of each tile in degrees. Required unless tileSizeMeters is given"),
+    ("tileHeight" = f64, Query, description = "Height of each tile in degrees. Required unless tileSizeMeters is given"),
+    ("tileSizeMeters" = f64, Query, description = "Side of each tile in meters, converted to degrees at the middle latitude of the area. Replaces tileWidth/tileHeight"),
+    ("binning" = String, Query, description = "Cell shape: grid (default) or h3 for H3 hexagons, which take no tile size"),
+    ("resolution" = u8, Query, description = "H3 resolution (0..15) with binning=h3. Optional, defaults to 8"),
+    ("fields" = String, Query, description = "Comma separated tile fields to send: count, incidents, total, rate, neighborCount, topLeft, bottomRight, h3. Optional, defaults to all"),
+    ("encoding" = String, Query, description = "objects (default) or rows: every tile becomes an array in the order of `columns`, next to `data`, with corners as [lat, lng]"),
+    ),
+    responses(
+        (status = 200, description = "Anomaly density per tile", body = AnomalymapResponse),
+        (status = 400, description = "Invalid parameters; `details.param` names the offending one when known", body = ApiErrorBody),
+        (status = 413, description = "Grid over GRID_MAX_CELLS with GRID_OVERSIZE=reject; the message names the smallest tile size or the finest resolution that fits", body = ApiErrorBody),
+        (status = 500, description = "Server Vzorvalsya", body = ApiErrorBody),
+    )
+)]
+
+#[get("")]
+pub async fn get_anomalymap(
+    store: web::Data<dyn PointStore>,
+    qp: web::Query<AnomalymapQueryParams>,
+) -> Result<HttpResponse, ApiError> {
+    let started = Instant::now();
+    debug!(
+        "Anomalymap request: corners=({:?}, {:?}), ({:?}, {:?}), date=[{:?}..{:?}], tile=({:?}, {:?}, {:?}m)",
+        qp.lat1, qp.lng1, qp.lat2, qp.lng2, qp.date_start, qp.date_end, qp.tile_width, qp.tile_height, qp.tile_size_meters
+    );
+    // Basic validation
+    // Allow any two opposite corners; compute bounds. Omitted parameters fall back to the deployment defaults
+    validate::area(qp.lat1, qp.lng1, qp.lat2, qp.lng2, qp.date_start, qp.date_end)?;
+    let bbox = defaults().bbox(qp.lat1, qp.lng1, qp.lat2, qp.lng2).map_err(ApiError::bad_request)?;
+    let (date_start, date_end) = defaults().date_range(qp.date_start, qp.date_end);
+    let tiled = qp.tile_width.is_some() || qp.tile_height.is_some() || qp.tile_size_meters.is_some();
+    let binning = validate::binning(qp.binning.as_deref(), qp.resolution, tiled)?;
+    let tile = defaults().tile_size(&bbox, qp.tile_width, qp.tile_height, qp.tile_size_meters);
+    let shape = TileShape::parse(qp.fields.as_deref(), qp.encoding.as_deref(), &ANOMALY_TILE_FIELDS)?;
+
+    let (bins, size) = validate::fit_bins(bbox, binning, tile, qp.tile_size_meters)?;
+
+    // Early return if degenerate
+    if bins.is_empty() {
+        let resp = AnomalymapResponse { anomalymap: AnomalymapData { data: vec![], size }, meta: MapMeta::new(&bins, 0, 0, started) };
+        info!("Anomalymap degenerate area (no cells), returning empty. took={:?}", started.elapsed());
+        return shape.respond(&resp, "/anomalymap/data");
+    }
+
+    // All points, not only anomalous ones: the rate needs the classified total per tile
+    let filter = PointFilter {
+        bbox: Some(bbox),
+        since: date_start,
+        until: date_end,
+        ..Default::default()
+    };
+    let all_points = match store.find(&filter, PointOrder::TimestampAsc, None).await {
+        Ok(p) => p,
+        Err(e) => {
+            error!("Anomalymap query failed: {}", e);
+            return Err(ApiError::Internal);
+        }
+    };
+    let rows_scanned = all_points.len();
+    let trips = distinct_trips(&all_points);
+
+    // Bucket points into tiles; unclassified points count towards nothing
+    let mut counts = vec![0usize; bins.len()];
+    let mut totals = vec![0usize; bins.len()];
+    let mut incidents: HashSet<(usize, i64)> = HashSet::new();
+    for p in all_points {
+        let Some(anomaly) = p.anomaly else { continue; };
+        let Some(idx) = bins.index_of(p.lat, p.lng) else { continue; };
+        totals[idx] += 1;
+        if anomaly {
+            counts[idx] += 1;
+            incidents.insert((idx, p.randomized_id));
+        }
+    }
+    let mut incident_counts = vec![0usize; bins.len()];
+    for (idx, _) in &incidents {
+        incident_counts[*idx] += 1;
+    }
+
+    // Build response tiles (row-major from lat_min/lng_min increasing, H3 index order for hexagons)
+    // Include tiles with count > 0 OR neighbor_count > 0
+    let mut data = Vec::new();
+    for idx in bins.cells() {
+        let count = counts[idx];
+        let neighbor_count = bins.neighbor_sum(&counts, idx);
+        if count > 0 || neighbor_count > 0 {
+            let total = totals[idx];
+            let cell = bins.cell_bbox(idx);
+            data.push(AnomalyTile {
+                count,
+                incidents: incident_counts[idx],
+                total,
+                rate: if total > 0 { count as f64 / total as f64 } else { 0.0 },
+                neighbor_count,
+                top_left: MapPoint { lat: cell.lat_min, lng: cell.lng_min },
+                bottom_right: MapPoint { lat: cell.lat_max, lng: cell.lng_max },
+                h3: bins.cell_id(idx),
+            });
+        }
+    }
+
+    let meta = MapMeta::new(&bins, rows_scanned, trips, started);
+    let resp = AnomalymapResponse { anomalymap: AnomalymapData { data, size }, meta };
+    info!(
+        "Anomalymap response: tiles={} (non-zero only) from grid={} anomalies={} took={:?}",
+        resp.anomalymap.data.len(), bins, counts.iter().sum::<usize>(), started.elapsed()
+    );
+    let stats = QueryStats { rows_scanned, tiles: resp.anomalymap.data.len() };
+    Ok(stats.attach(shape.respond(&resp, "/anomalymap/data")?))
+}
+
+pub fn routes() -> ApiScope {
+    ApiScope::new("/anomalymap")
+        .service(get_anomalymap)
+}
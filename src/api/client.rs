@@ -0,0 +1,336 @@
+use actix_web::{get, http::header, HttpResponse};
+use once_cell::sync::Lazy;
+use serde_json::{Map, Value};
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use super::registry::ApiScope;
+
+/// Generated once per process; the document only changes with the build
+static CLIENT_TS: Lazy<String> = Lazy::new(|| generate(&serde_json::to_value(super::openapi()).unwrap_or_default()));
+
+#[utoipa::path(
+    get,
+    tag = "Meta",
+    responses(
+        (status = 200, description = "TypeScript fetch client generated from the OpenAPI document of this build", content_type = "application/typescript"),
+    )
+)]
+
+#[get("")]
+pub async fn client_ts() -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header((header::CONTENT_TYPE, "application/typescript; charset=utf-8"))
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .body(CLIENT_TS.as_str())
+}
+
+pub fn routes() -> ApiScope {
+    ApiScope::new("/client.ts")
+        .service(client_ts)
+}
+
+/// The client served at `/api/client.ts`, for `emit-client`
+pub fn source() -> &'static str {
+    CLIENT_TS.as_str()
+}
+
+const RUNTIME: &str = r#"// Generated from the OpenAPI document by the server (`GET /api/client.ts`,
+// or `cargo run -- emit-client <path>`, which `npm run build` runs). Do not edit by hand.
+
+interface ErrorBody {
+	code?: string;
+	message?: string;
+	details?: unknown;
+}
+
+function parseErrorBody(body: string): ErrorBody | undefined {
+	try {
+		const parsed = JSON.parse(body);
+		return typeof parsed === "object" && parsed !== null ? parsed : undefined;
+	} catch {
+		return undefined;
+	}
+}
+
+export class ApiError extends Error {
+	/** `code` and `details` of a JSON error body, when the endpoint sends one */
+	public code?: string;
+	public details?: unknown;
+
+	constructor(public status: number, public body: string) {
+		super(`HTTP ${status}: ${parseErrorBody(body)?.message ?? body}`);
+		const parsed = parseErrorBody(body);
+		this.code = parsed?.code;
+		this.details = parsed?.details;
+	}
+}
+
+export interface ClientOptions {
+	/** Prefix for every path, e.g. `https://example.org`; same origin by default */
+	baseUrl?: string;
+	headers?: Record<string, string>;
+}
+
+let options: ClientOptions = {};
+
+export function configure(o: ClientOptions): void {
+	options = { ...options, ...o };
+}
+
+type QueryValue = string | number | boolean | null | undefined | Array<string | number>;
+
+async function request<T>(
+	method: string,
+	path: string,
+	query: Record<string, QueryValue>,
+	headerParams: Record<string, string | undefined>,
+	body: unknown,
+	json: boolean,
+	init?: RequestInit
+): Promise<T> {
+	const params = new URLSearchParams();
+	for (const [k, v] of Object.entries(query)) {
+		if (v === undefined || v === null) continue;
+		params.set(k, Array.isArray(v) ? v.join(",") : String(v));
+	}
+	const qs = params.toString();
+	const headers: Record<string, string> = { ...options.headers };
+	for (const [k, v] of Object.entries(headerParams)) {
+		if (v !== undefined) headers[k] = v;
+	}
+	let payload: BodyInit | undefined;
+	if (body !== undefined) {
+		if (body instanceof FormData || body instanceof Blob || typeof body === "string") {
+			payload = body;
+		} else {
+			headers["Content-Type"] = "application/json";
+			payload = JSON.stringify(body);
+		}
+	}
+	const response = await fetch(`${options.baseUrl ?? ""}${path}${qs ? "?" + qs : ""}`, {
+		...init,
+		method,
+		headers: { ...headers, ...(init?.headers as Record<string, string> | undefined) },
+		body: payload,
+	});
+	if (!response.ok) {
+		throw new ApiError(response.status, await response.text());
+	}
+	return (json ? await response.json() : response) as T;
+}
+"#;
+
+/// Renders `components.schemas` as TypeScript types and every operation as a function named
+/// after its handler
+fn generate(doc: &Value) -> String {
+    let mut out = String::from(RUNTIME);
+
+    if let Some(schemas) = doc.pointer("/components/schemas").and_then(Value::as_object) {
+        out.push_str("\n// --- Schemas ---\n");
+        for (name, schema) in schemas {
+            out.push('\n');
+            doc_comment(&mut out, schema.get("description"), "");
+            match schema.get("properties").and_then(Value::as_object) {
+                Some(props) if schema.get("allOf").is_none() => {
+                    let _ = writeln!(out, "export interface {} {}", type_name(name), object_body(props, &required(schema), ""));
+                }
+                _ => {
+                    let _ = writeln!(out, "export type {} = {};", type_name(name), ts_type(schema));
+                }
+            }
+        }
+    }
+
+    out.push_str("\n// --- Operations ---\n");
+    let mut taken = BTreeSet::new();
+    if let Some(paths) = doc.get("paths").and_then(Value::as_object) {
+        for (path, item) in paths {
+            let Some(item) = item.as_object() else { continue };
+            for (method, op) in item {
+                // Deprecated aliases would only duplicate the canonical operations
+                if !matches!(method.as_str(), "get" | "post" | "put" | "patch" | "delete") || op.get("deprecated") == Some(&Value::Bool(true)) {
+                    continue;
+                }
+                operation(&mut out, &mut taken, path, method, op);
+            }
+        }
+    }
+    out
+}
+
+fn operation(out: &mut String, taken: &mut BTreeSet<String>, path: &str, method: &str, op: &Value) {
+    let base = camel(op.get("operationId").and_then(Value::as_str).unwrap_or(method));
+    let mut name = base.clone();
+    let mut n = 2;
+    while !taken.insert(name.clone()) {
+        name = format!("{}{}", base, n);
+        n += 1;
+    }
+
+    let params: Vec<&Value> = op.get("parameters").and_then(Value::as_array).map(|p| p.iter().collect()).unwrap_or_default();
+    let mut args = Vec::new();
+    if !params.is_empty() {
+        let fields: Vec<String> = params
+            .iter()
+            .map(|p| {
+                let pname = p.get("name").and_then(Value::as_str).unwrap_or_default();
+                let optional = if is_required(p) { "" } else { "?" };
+                let ty = p.get("schema").map(ts_type).unwrap_or_else(|| "string".to_string());
+                format!("{}{}: {}", property_key(pname), optional, ty)
+            })
+            .collect();
+        let all_optional = !params.iter().any(|p| is_required(p));
+        args.push(format!("params: {{ {} }}{}", fields.join("; "), if all_optional { " = {}" } else { "" }));
+    }
+    if let Some(body) = op.get("requestBody") {
+        let ty = match body.pointer("/content/application~1json/schema") {
+            Some(schema) => ts_type(schema),
+            None if body.pointer("/content/multipart~1form-data").is_some() => "FormData".to_string(),
+            None => "string | Blob".to_string(),
+        };
+        args.push(format!("body: {}", ty));
+    }
+    args.push("init?: RequestInit".to_string());
+
+    let json_response = op
+        .get("responses")
+        .and_then(Value::as_object)
+        .and_then(|r| r.iter().find(|(code, _)| code.starts_with('2')))
+        .and_then(|(_, r)| r.pointer("/content/application~1json/schema"));
+    let ret = json_response.map(ts_type).unwrap_or_else(|| "Response".to_string());
+
+    let mut url = format!("`{}`", path);
+    for p in params.iter().filter(|p| p.get("in").and_then(Value::as_str) == Some("path")) {
+        let pname = p.get("name").and_then(Value::as_str).unwrap_or_default();
+        url = url.replace(&format!("{{{}}}", pname), &format!("${{encodeURIComponent(String(params[{:?}]))}}", pname));
+    }
+    let passed_in = |location: &str| -> Vec<String> {
+        params
+            .iter()
+            .filter(|p| p.get("in").and_then(Value::as_str) == Some(location))
+            .map(|p| {
+                let pname = p.get("name").and_then(Value::as_str).unwrap_or_default();
+                format!("{}: params[{:?}]", property_key(pname), pname)
+            })
+            .collect()
+    };
+    let (query, headers) = (passed_in("query"), passed_in("header"));
+
+    out.push('\n');
+    doc_comment(out, op.get("summary").or(op.get("description")), "");
+    let _ = writeln!(out, "export function {}({}): Promise<{}> {{", name, args.join(", "), ret);
+    let _ = writeln!(
+        out,
+        "\treturn request<{}>({:?}, {}, {{ {} }}, {{ {} }}, {}, {}, init);",
+        ret,
+        method.to_uppercase(),
+        url,
+        query.join(", "),
+        headers.join(", "),
+        if op.get("requestBody").is_some() { "body" } else { "undefined" },
+        json_response.is_some(),
+    );
+    out.push_str("}\n");
+}
+
+/// TypeScript for one schema; anything the generator does not understand is `unknown`
+fn ts_type(schema: &Value) -> String {
+    if let Some(r) = schema.get("$ref").and_then(Value::as_str) {
+        return type_name(r.rsplit('/').next().unwrap_or(r));
+    }
+    for (key, sep) in [("oneOf", " | "), ("anyOf", " | "), ("allOf", " & ")] {
+        if let Some(parts) = schema.get(key).and_then(Value::as_array) {
+            let parts: Vec<String> = parts.iter().map(ts_type).collect();
+            return format!("({})", parts.join(sep));
+        }
+    }
+    if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+        let values: Vec<String> = values.iter().map(|v| if v.is_null() { "null".to_string() } else { v.to_string() }).collect();
+        return values.join(" | ");
+    }
+    let types: Vec<&str> = match schema.get("type") {
+        Some(Value::String(t)) => vec![t.as_str()],
+        Some(Value::Array(ts)) => ts.iter().filter_map(Value::as_str).collect(),
+        _ => return "unknown".to_string(),
+    };
+    let rendered: Vec<String> = types
+        .iter()
+        .map(|t| match *t {
+            "integer" | "number" => "number".to_string(),
+            "string" => "string".to_string(),
+            "boolean" => "boolean".to_string(),
+            "null" => "null".to_string(),
+            "array" => {
+                let item = schema.get("items").map(ts_type).unwrap_or_else(|| "unknown".to_string());
+                if item.contains(' ') { format!("Array<{}>", item) } else { format!("{}[]", item) }
+            }
+            "object" => match (schema.get("properties").and_then(Value::as_object), schema.get("additionalProperties")) {
+                (Some(props), _) => object_body(props, &required(schema), "\t"),
+                (None, Some(extra)) if extra.is_object() => format!("Record<string, {}>", ts_type(extra)),
+                _ => "Record<string, unknown>".to_string(),
+            },
+            _ => "unknown".to_string(),
+        })
+        .collect();
+    rendered.join(" | ")
+}
+
+fn object_body(props: &Map<String, Value>, required: &BTreeSet<String>, indent: &str) -> String {
+    let mut body = String::from("{\n");
+    for (key, prop) in props {
+        doc_comment(&mut body, prop.get("description"), &format!("{}\t", indent));
+        let optional = if required.contains(key) { "" } else { "?" };
+        let _ = writeln!(body, "{}\t{}{}: {};", indent, property_key(key), optional, ts_type(prop));
+    }
+    body.push_str(indent);
+    body.push('}');
+    body
+}
+
+/// Tuple-style `params(...)` are documented as required whatever their type, and the handlers
+/// treat most of them as optional, so only path parameters are required in the client
+fn is_required(param: &Value) -> bool {
+    param.get("in").and_then(Value::as_str) == Some("path")
+}
+
+fn required(schema: &Value) -> BTreeSet<String> {
+    schema
+        .get("required")
+        .and_then(Value::as_array)
+        .map(|r| r.iter().filter_map(Value::as_str).map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+fn doc_comment(out: &mut String, text: Option<&Value>, indent: &str) {
+    let Some(text) = text.and_then(Value::as_str).map(str::trim).filter(|t| !t.is_empty()) else { return };
+    let _ = writeln!(out, "{}/** {} */", indent, text.replace("*/", "*\\/").replace('\n', " "));
+}
+
+/// Schema names may carry generics or module paths
+fn type_name(name: &str) -> String {
+    name.chars().map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' }).collect()
+}
+
+fn property_key(key: &str) -> String {
+    let ident = key.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '$')
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$');
+    if ident { key.to_string() } else { format!("{:?}", key) }
+}
+
+/// `get_heatmap` -> `getHeatmap`
+fn camel(id: &str) -> String {
+    let mut out = String::new();
+    let mut upper = false;
+    for c in id.chars() {
+        if c == '_' || c == '-' {
+            upper = !out.is_empty();
+        } else if upper {
+            out.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
@@ -0,0 +1,134 @@
+use chrono::{DateTime, Duration, FixedOffset, Utc};
+use log::warn;
+use once_cell::sync::Lazy;
+use std::env;
+
+use crate::database::store::BBox;
+use super::grid::tile_size_degrees;
+
+/// Deployment-wide fallbacks for query parameters a request leaves out.
+/// Each one is unset by default, which keeps the parameter required (or unbounded).
+#[derive(Debug, Clone)]
+pub struct QueryDefaults {
+    /// DEFAULT_BBOX: `lat1,lng1,lat2,lng2`
+    pub bbox: Option<BBox>,
+    /// DEFAULT_DATE_RANGE_DAYS: without dateStart/dateEnd, only the last N days
+    pub date_range_days: Option<i64>,
+    /// DEFAULT_TIMEZONE: offset such as `+05:00` for weekday and time-of-day filters (default UTC)
+    pub timezone: FixedOffset,
+    /// DEFAULT_TILE_SIZE_METERS: used when neither tileWidth/tileHeight nor tileSizeMeters is given
+    pub tile_size_meters: Option<f64>,
+}
+
+static DEFAULTS: Lazy<QueryDefaults> = Lazy::new(QueryDefaults::from_env);
+
+pub fn defaults() -> &'static QueryDefaults {
+    &DEFAULTS
+}
+
+impl QueryDefaults {
+    pub fn from_env() -> Self {
+        let bbox = env::var("DEFAULT_BBOX").ok().and_then(|s| {
+            let parsed = parse_bbox(&s);
+            if parsed.is_none() {
+                warn!("Ignoring invalid DEFAULT_BBOX '{}'; expected lat1,lng1,lat2,lng2", s);
+            }
+            parsed
+        });
+        let date_range_days = env::var("DEFAULT_DATE_RANGE_DAYS").ok().and_then(|s| match s.trim().parse::<i64>() {
+            Ok(d) if d > 0 => Some(d),
+            _ => {
+                warn!("Ignoring invalid DEFAULT_DATE_RANGE_DAYS '{}'", s);
+                None
+            }
+        });
+        let timezone = env::var("DEFAULT_TIMEZONE")
+            .ok()
+            .and_then(|s| {
+                let parsed = parse_timezone(&s);
+                if parsed.is_none() {
+                    warn!("Ignoring invalid DEFAULT_TIMEZONE '{}'; expected UTC or an offset like +05:00", s);
+                }
+                parsed
+            })
+            .unwrap_or_else(utc);
+        let tile_size_meters = env::var("DEFAULT_TILE_SIZE_METERS").ok().and_then(|s| match s.trim().parse::<f64>() {
+            Ok(m) if m > 0.0 => Some(m),
+            _ => {
+                warn!("Ignoring invalid DEFAULT_TILE_SIZE_METERS '{}'", s);
+                None
+            }
+        });
+        Self { bbox, date_range_days, timezone, tile_size_meters }
+    }
+
+    /// Request corners if all four are given, else the deployment bbox
+    pub fn bbox(&self, lat1: Option<f64>, lng1: Option<f64>, lat2: Option<f64>, lng2: Option<f64>) -> Result<BBox, &'static str> {
+        match (lat1, lng1, lat2, lng2) {
+            (Some(lat1), Some(lng1), Some(lat2), Some(lng2)) => Ok(BBox::from_corners(lat1, lng1, lat2, lng2)),
+            (None, None, None, None) => self.bbox.ok_or("lat1, lng1, lat2 and lng2 are required"),
+            _ => Err("lat1, lng1, lat2 and lng2 must be given together"),
+        }
+    }
+
+    /// Like `bbox`, for endpoints where the area may be left unbounded
+    pub fn optional_bbox(&self, lat1: Option<f64>, lng1: Option<f64>, lat2: Option<f64>, lng2: Option<f64>) -> Result<Option<BBox>, &'static str> {
+        match (lat1, lng1, lat2, lng2) {
+            (None, None, None, None) => Ok(self.bbox),
+            _ => self.bbox(lat1, lng1, lat2, lng2).map(Some),
+        }
+    }
+
+    /// The default window only applies when both ends are omitted
+    pub fn date_range(&self, start: Option<DateTime<Utc>>, end: Option<DateTime<Utc>>) -> (Option<DateTime<Utc>>, Option<DateTime<Utc>>) {
+        match (start, end, self.date_range_days) {
+            (None, None, Some(days)) => (Some(Utc::now() - Duration::days(days)), None),
+            _ => (start, end),
+        }
+    }
+
+    /// Request timezone if given, else the deployment one
+    pub fn timezone(&self, tz: Option<&str>) -> Result<FixedOffset, &'static str> {
+        match tz {
+            Some(s) => parse_timezone(s).ok_or("timezone must be UTC or an offset like +05:00"),
+            None => Ok(self.timezone),
+        }
+    }
+
+    /// Tile size from the request, falling back to DEFAULT_TILE_SIZE_METERS when none is given
+    pub fn tile_size(
+        &self,
+        bbox: &BBox,
+        tile_width: Option<f64>,
+        tile_height: Option<f64>,
+        tile_size_meters: Option<f64>,
+    ) -> Result<(f64, f64), &'static str> {
+        let meters = match (tile_width, tile_height, tile_size_meters) {
+            (None, None, None) => self.tile_size_meters,
+            _ => tile_size_meters,
+        };
+        tile_size_degrees(bbox, tile_width, tile_height, meters)
+    }
+}
+
+// --- Helpers ---
+
+fn utc() -> FixedOffset {
+    FixedOffset::east_opt(0).expect("zero offset is valid")
+}
+
+pub(crate) fn parse_bbox(s: &str) -> Option<BBox> {
+    let v: Vec<f64> = s.split(',').map(|p| p.trim().parse().ok()).collect::<Option<_>>()?;
+    match v[..] {
+        [lat1, lng1, lat2, lng2] => Some(BBox::from_corners(lat1, lng1, lat2, lng2)),
+        _ => None,
+    }
+}
+
+fn parse_timezone(s: &str) -> Option<FixedOffset> {
+    let s = s.trim();
+    if s.eq_ignore_ascii_case("utc") || s == "Z" {
+        return Some(utc());
+    }
+    s.parse().ok()
+}
@@ -0,0 +1,220 @@
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Duration, Utc};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::database::store::{DeviceFilter, DeviceStats, DeviceStore};
+use super::admin::AdminConfig;
+use super::error::{ApiError, ApiErrorBody};
+use super::points::MAX_DEVICE_ID_LEN;
+use super::registry::ApiScope;
+
+/// Default and maximum page size for `GET /api/devices`
+const DEFAULT_LIMIT: u64 = 100;
+const MAX_LIMIT: u64 = 1000;
+const MAX_NAME_LEN: usize = 200;
+
+/// A tracker and what it has reported, summed over its published trips
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct Device {
+    #[serde(rename = "deviceId")]
+    pub device_id: String,
+    pub name: Option<String>,
+    /// When the device was registered, or first named by a point
+    #[serde(rename = "registeredAt")]
+    pub registered_at: DateTime<Utc>,
+    /// End of its latest published trip; null before the first one
+    #[serde(rename = "lastSeen")]
+    pub last_seen: Option<DateTime<Utc>>,
+    pub trips: u64,
+    #[serde(rename = "totalPoints")]
+    pub total_points: u64,
+    /// Meters along the recorded points of all its trips
+    #[serde(rename = "totalDistance")]
+    pub total_distance: f64,
+}
+
+impl From<DeviceStats> for Device {
+    fn from(s: DeviceStats) -> Self {
+        Self {
+            device_id: s.device_id,
+            name: s.name,
+            registered_at: s.registered_at,
+            last_seen: s.last_seen,
+            trips: s.trips,
+            total_points: s.points,
+            total_distance: s.distance_m,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct DevicesPage {
+    /// Ordered by device id
+    pub devices: Vec<Device>,
+    /// Number of devices matching the filters, ignoring limit/offset
+    pub total: u64,
+    pub limit: u64,
+    pub offset: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct RegisterDeviceRequest {
+    #[serde(rename = "deviceId")]
+    pub device_id: String,
+    /// Replaces the current name when the device is registered already
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct DevicesQueryParams {
+    /// Seconds without a trip ending
+    #[serde(rename = "silentFor")] pub silent_for: Option<i64>,
+    pub limit: Option<u64>,
+    pub offset: Option<u64>,
+}
+
+#[utoipa::path(
+    post,
+    tag = "Devices",
+    params(
+        ("Authorization" = String, Header, description = "Bearer <ADMIN_TOKEN>"),
+    ),
+    request_body = RegisterDeviceRequest,
+    responses(
+        (status = 201, description = "Device registered", body = Device),
+        (status = 200, description = "Device known already; renamed if a name was given", body = Device),
+        (status = 400, description = "Missing or too long deviceId or name", body = ApiErrorBody),
+        (status = 401, description = "Missing or wrong token"),
+        (status = 500, description = "Server Vzorvalsya", body = ApiErrorBody),
+        (status = 503, description = "Admin endpoints are disabled")
+    )
+)]
+
+#[post("")]
+pub async fn register_device(
+    store: web::Data<dyn DeviceStore>,
+    cfg: web::Data<AdminConfig>,
+    http: HttpRequest,
+    req: web::Json<RegisterDeviceRequest>,
+) -> Result<HttpResponse, ApiError> {
+    if let Some(resp) = cfg.reject(&http) {
+        return Ok(resp);
+    }
+    let device_id = req.device_id.trim();
+    if device_id.is_empty() || device_id.chars().count() > MAX_DEVICE_ID_LEN {
+        return Err(ApiError::bad_param("deviceId", format!("deviceId must be 1 to {} characters", MAX_DEVICE_ID_LEN)));
+    }
+    let name = req.name.as_deref().map(str::trim).filter(|n| !n.is_empty());
+    if name.is_some_and(|n| n.chars().count() > MAX_NAME_LEN) {
+        return Err(ApiError::bad_param("name", format!("name must be at most {} characters", MAX_NAME_LEN)));
+    }
+
+    let created = store.register_device(device_id, name, None).await.map_err(|e| {
+        error!("Device registration failed for {}: {}", device_id, e);
+        ApiError::Internal
+    })?;
+    if created {
+        info!("Device {} registered", device_id);
+    }
+    let device = find_one(store.get_ref(), device_id).await?.ok_or_else(|| {
+        error!("Device {} not found right after registration", device_id);
+        ApiError::Internal
+    })?;
+    Ok(if created { HttpResponse::Created() } else { HttpResponse::Ok() }.json(device))
+}
+
+#[utoipa::path(
+    get,
+    tag = "Devices",
+    params(
+        ("silentFor" = i64, Query, description = "Only devices without a trip ending in the last N seconds, including those that never reported one. Optional"),
+        ("limit" = u64, Query, description = "Page size, default 100, max 1000"),
+        ("offset" = u64, Query, description = "Number of matching devices to skip, default 0"),
+    ),
+    responses(
+        (status = 200, description = "Page of devices with their last activity and totals", body = DevicesPage),
+        (status = 400, description = "Invalid parameters", body = ApiErrorBody),
+        (status = 500, description = "Server Vzorvalsya", body = ApiErrorBody)
+    )
+)]
+
+#[get("")]
+pub async fn list_devices(
+    store: web::Data<dyn DeviceStore>,
+    qp: web::Query<DevicesQueryParams>,
+) -> Result<HttpResponse, ApiError> {
+    let silent_since = match qp.silent_for {
+        Some(s) if s < 0 => return Err(ApiError::bad_param("silentFor", "silentFor must not be negative")),
+        Some(s) => Some(Utc::now() - Duration::seconds(s)),
+        None => None,
+    };
+    let limit = qp.limit.unwrap_or(DEFAULT_LIMIT);
+    if limit == 0 || limit > MAX_LIMIT {
+        return Err(ApiError::bad_param("limit", format!("limit must be between 1 and {}", MAX_LIMIT)));
+    }
+    let offset = qp.offset.unwrap_or(0);
+
+    let filter = DeviceFilter { silent_since, ..Default::default() };
+    let total = store.count_devices(&filter).await.map_err(|e| {
+        error!("Devices count failed: {}", e);
+        ApiError::Internal
+    })?;
+    let devices = if offset >= total {
+        Vec::new()
+    } else {
+        store.find_devices(&filter, limit, offset).await.map_err(|e| {
+            error!("Devices query failed: {}", e);
+            ApiError::Internal
+        })?
+    };
+    Ok(HttpResponse::Ok().json(DevicesPage {
+        devices: devices.into_iter().map(Device::from).collect(),
+        total,
+        limit,
+        offset,
+    }))
+}
+
+#[utoipa::path(
+    get,
+    tag = "Devices",
+    params(
+        ("device_id" = String, Path, description = "Device to look up"),
+    ),
+    responses(
+        (status = 200, description = "The device with its last activity and totals", body = Device),
+        (status = 404, description = "Unknown device", body = ApiErrorBody),
+        (status = 500, description = "Server Vzorvalsya", body = ApiErrorBody)
+    )
+)]
+
+#[get("/{device_id}")]
+pub async fn get_device(
+    store: web::Data<dyn DeviceStore>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    match find_one(store.get_ref(), &path).await? {
+        Some(device) => Ok(HttpResponse::Ok().json(device)),
+        None => Err(ApiError::NotFound("Device not found".to_string())),
+    }
+}
+
+async fn find_one(store: &dyn DeviceStore, device_id: &str) -> Result<Option<Device>, ApiError> {
+    let filter = DeviceFilter { device_id: Some(device_id.to_string()), ..Default::default() };
+    match store.find_devices(&filter, 1, 0).await {
+        Ok(rows) => Ok(rows.into_iter().next().map(Device::from)),
+        Err(e) => {
+            error!("Device lookup failed for {}: {}", device_id, e);
+            Err(ApiError::Internal)
+        }
+    }
+}
+
+pub fn routes() -> ApiScope {
+    ApiScope::new("/devices")
+        .service(register_device)
+        .service(list_devices)
+        .service(get_device)
+}
@@ -0,0 +1,108 @@
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+use utoipa::ToSchema;
+
+/// Body of every error response from the handlers that return `ApiError`
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct ApiErrorBody {
+    /// Stable machine-readable kind, e.g. `bad_request`
+    pub code: String,
+    /// Human-readable explanation
+    pub message: String,
+    /// Extra context, e.g. the offending parameter; null when there is none
+    pub details: Option<Value>,
+}
+
+/// Failure of an `/api` handler. The cause of an `Internal` error is logged by the handler and
+/// not sent to the client.
+#[derive(Debug)]
+pub enum ApiError {
+    BadRequest { message: String, details: Option<Value> },
+    Unauthorized(String),
+    NotFound(String),
+    PayloadTooLarge(String),
+    RangeNotSatisfiable(String),
+    ServiceUnavailable(String),
+    Internal,
+}
+
+impl ApiError {
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::BadRequest { message: message.into(), details: None }
+    }
+
+    /// A bad request naming the parameter at fault in `details.param`
+    pub fn bad_param(param: &str, message: impl Into<String>) -> Self {
+        Self::BadRequest { message: message.into(), details: Some(serde_json::json!({ "param": param })) }
+    }
+
+    /// Stable machine-readable kind, the `code` of the body
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::BadRequest { .. } => "bad_request",
+            Self::Unauthorized(_) => "unauthorized",
+            Self::NotFound(_) => "not_found",
+            Self::PayloadTooLarge(_) => "payload_too_large",
+            Self::RangeNotSatisfiable(_) => "range_not_satisfiable",
+            Self::ServiceUnavailable(_) => "service_unavailable",
+            Self::Internal => "internal",
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadRequest { message, .. }
+            | Self::Unauthorized(message)
+            | Self::NotFound(message)
+            | Self::PayloadTooLarge(message)
+            | Self::RangeNotSatisfiable(message)
+            | Self::ServiceUnavailable(message) => f.write_str(message),
+            Self::Internal => f.write_str("Internal server error"),
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::BadRequest { .. } => StatusCode::BAD_REQUEST,
+            Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::RangeNotSatisfiable(_) => StatusCode::RANGE_NOT_SATISFIABLE,
+            Self::ServiceUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Self::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let details = match self {
+            Self::BadRequest { details, .. } => details.clone(),
+            _ => None,
+        };
+        HttpResponse::build(self.status_code()).json(ApiErrorBody {
+            code: self.code().to_string(),
+            message: self.to_string(),
+            details,
+        })
+    }
+}
+
+/// Malformed query strings and JSON bodies get the same error body as handler errors
+pub fn query_error(err: actix_web::error::QueryPayloadError, _: &actix_web::HttpRequest) -> actix_web::Error {
+    ApiError::bad_request(err.to_string()).into()
+}
+
+pub fn json_error(err: actix_web::error::JsonPayloadError, _: &actix_web::HttpRequest) -> actix_web::Error {
+    match err {
+        actix_web::error::JsonPayloadError::OverflowKnownLength { .. } | actix_web::error::JsonPayloadError::Overflow { .. } => {
+            ApiError::PayloadTooLarge(err.to_string()).into()
+        }
+        _ => ApiError::bad_request(err.to_string()).into(),
+    }
+}
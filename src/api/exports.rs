@@ -0,0 +1,170 @@
+use actix_web::{get, http::header, post, web, HttpResponse};
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::database::model::jobs::Model as JobModel;
+use crate::database::store::{BBox, PointFilter};
+use crate::exports::{Exporter, FileFormat};
+use crate::tenant;
+use super::error::{ApiError, ApiErrorBody};
+use super::jobs::visible;
+use super::registry::ApiScope;
+use super::validate;
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct ExportRequest {
+    /// csv (default), ndjson or parquet
+    pub format: Option<String>,
+    #[serde(rename = "lat1")] pub lat1: Option<f64>,
+    #[serde(rename = "lng1")] pub lng1: Option<f64>,
+    #[serde(rename = "lat2")] pub lat2: Option<f64>,
+    #[serde(rename = "lng2")] pub lng2: Option<f64>,
+    #[serde(rename = "dateStart")] pub date_start: Option<DateTime<Utc>>, // inclusive
+    #[serde(rename = "dateEnd")] pub date_end: Option<DateTime<Utc>>,     // inclusive
+    #[serde(rename = "randomizedId")] pub randomized_id: Option<i64>,
+    pub anomaly: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct ExportJob {
+    pub id: Uuid,
+    /// queued, running, done or failed
+    pub status: String,
+    pub format: String,
+    /// Rows written so far
+    pub rows: i64,
+    /// Size of the uploaded object, once done
+    pub bytes: i64,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<Utc>,
+    #[serde(rename = "updatedAt")]
+    pub updated_at: DateTime<Utc>,
+    #[serde(rename = "finishedAt")]
+    pub finished_at: Option<DateTime<Utc>>,
+    /// `s3://bucket/key` of the result, once done
+    pub location: Option<String>,
+    /// Presigned link to the result, valid for EXPORT_S3_URL_TTL_SECS from this response
+    #[serde(rename = "downloadUrl")]
+    pub download_url: Option<String>,
+    pub error: Option<String>,
+    /// The request the job was started with, format included
+    #[schema(value_type = Object)]
+    pub query: serde_json::Value,
+}
+
+impl ExportJob {
+    fn new(exporter: &Exporter, job: JobModel) -> Self {
+        Self {
+            location: exporter.location(&job),
+            download_url: exporter.download_url(&job),
+            id: job.id,
+            status: job.status,
+            format: job.params["format"].as_str().unwrap_or_default().to_string(),
+            rows: job.progress,
+            bytes: job.result.as_ref().and_then(|r| r["bytes"].as_i64()).unwrap_or(0),
+            created_at: job.created_at,
+            updated_at: job.updated_at,
+            finished_at: job.finished_at,
+            error: job.error,
+            query: job.params,
+        }
+    }
+}
+
+fn enabled(exporter: Option<web::Data<Exporter>>) -> Result<web::Data<Exporter>, ApiError> {
+    exporter.ok_or_else(|| ApiError::ServiceUnavailable("Exports are disabled (EXPORT_S3_BUCKET not set)".to_string()))
+}
+
+#[utoipa::path(
+    post,
+    tag = "Exports",
+    request_body = ExportRequest,
+    responses(
+        (status = 202, description = "Job started; poll the URL in the Location header", body = ExportJob),
+        (status = 400, description = "Invalid parameters", body = ApiErrorBody),
+        (status = 503, description = "Exports are disabled", body = ApiErrorBody),
+        (status = 500, description = "Server Vzorvalsya", body = ApiErrorBody)
+    )
+)]
+
+/// Exports the matching points to the configured S3 bucket in the background
+#[post("")]
+pub async fn start_export(
+    exporter: Option<web::Data<Exporter>>,
+    req: web::Json<ExportRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let exporter = enabled(exporter)?;
+    validate::corners(req.lat1, req.lng1, req.lat2, req.lng2)?;
+    let bbox = match (req.lat1, req.lng1, req.lat2, req.lng2) {
+        (Some(lat1), Some(lng1), Some(lat2), Some(lng2)) => Some(BBox::from_corners(lat1, lng1, lat2, lng2)),
+        (None, None, None, None) => None,
+        _ => return Err(ApiError::bad_request("lat1, lng1, lat2 and lng2 must be given together")),
+    };
+    validate::date_range(req.date_start, req.date_end)?;
+    let Some(format) = FileFormat::parse(req.format.as_deref().unwrap_or("csv")) else {
+        return Err(ApiError::bad_param("format", "format must be csv, ndjson or parquet"));
+    };
+
+    let filter = PointFilter {
+        bbox,
+        since: req.date_start,
+        until: req.date_end,
+        randomized_id: req.randomized_id,
+        anomaly: req.anomaly,
+        // The job runs long after the request's tenant scope ended
+        tenant: tenant::current(),
+        ..Default::default()
+    };
+    let query = serde_json::to_value(&*req).map_err(|e| {
+        error!("Serializing an export request failed: {}", e);
+        ApiError::Internal
+    })?;
+    let job = exporter.start(format, filter, query).await.map_err(|e| {
+        error!("Starting an export failed: {}", e);
+        ApiError::Internal
+    })?;
+    info!("Export {} queued: format={}", job.id, format.as_str());
+    Ok(HttpResponse::Accepted()
+        .insert_header((header::LOCATION, format!("/api/exports/{}", job.id)))
+        .json(ExportJob::new(&exporter, job)))
+}
+
+#[utoipa::path(
+    get,
+    tag = "Exports",
+    params(
+        ("id" = Uuid, Path, description = "Job id from POST /api/exports"),
+    ),
+    responses(
+        (status = 200, description = "Status of the job, with a download link once done; also at /api/jobs/{id}", body = ExportJob),
+        (status = 404, description = "No such job", body = ApiErrorBody),
+        (status = 503, description = "Exports are disabled", body = ApiErrorBody),
+        (status = 500, description = "Server Vzorvalsya", body = ApiErrorBody)
+    )
+)]
+
+#[get("/{id}")]
+pub async fn get_export(
+    exporter: Option<web::Data<Exporter>>,
+    path: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let exporter = enabled(exporter)?;
+    let id = path.into_inner();
+    let job = exporter.find(id).await.map_err(|e| {
+        error!("Reading export job {} failed: {}", id, e);
+        ApiError::Internal
+    })?;
+    match job {
+        Some(job) if visible(&job) => Ok(HttpResponse::Ok().json(ExportJob::new(&exporter, job))),
+        _ => Err(ApiError::NotFound(format!("No export job {}", id))),
+    }
+}
+
+pub fn routes() -> ApiScope {
+    ApiScope::new("/exports")
+        .service(start_export)
+        .service(get_export)
+}
@@ -0,0 +1,133 @@
+//! `fields` and `encoding` of the map endpoints: clients that draw only part of a tile ask for
+//! just those fields, and `encoding=rows` turns every tile into an array under a shared
+//! `columns` list, with the corners as `[lat, lng]` pairs.
+
+use actix_web::HttpResponse;
+use log::error;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use super::error::ApiError;
+use super::json_stream;
+use super::validate::Invalid;
+
+/// Corner objects that `encoding=rows` writes as `[lat, lng]`
+const CORNERS: [&str; 2] = ["topLeft", "bottomRight"];
+
+#[derive(Debug, Clone)]
+pub struct TileShape {
+    /// Selected fields in the order asked for; None keeps every field
+    fields: Option<Vec<&'static str>>,
+    /// Column order of `encoding=rows`
+    columns: Vec<&'static str>,
+    rows: bool,
+}
+
+impl TileShape {
+    /// `known` lists the tile's fields in the order `encoding=rows` uses when `fields` is omitted
+    pub fn parse(fields: Option<&str>, encoding: Option<&str>, known: &[&'static str]) -> Result<Self, Invalid> {
+        let rows = match encoding.map(str::trim) {
+            None | Some("") | Some("objects") => false,
+            Some("rows") => true,
+            Some(other) => {
+                return Err(Invalid { param: "encoding", message: format!("encoding must be objects or rows, got {:?}", other) });
+            }
+        };
+        let fields = match fields.map(str::trim).filter(|f| !f.is_empty()) {
+            None => None,
+            Some(list) => {
+                let mut selected = Vec::new();
+                for name in list.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+                    let Some(field) = known.iter().find(|k| **k == name) else {
+                        return Err(Invalid {
+                            param: "fields",
+                            message: format!("unknown field {:?}; tiles have {}", name, known.join(", ")),
+                        });
+                    };
+                    if !selected.contains(field) {
+                        selected.push(*field);
+                    }
+                }
+                Some(selected)
+            }
+        };
+        let columns = fields.clone().unwrap_or_else(|| known.to_vec());
+        Ok(Self { fields, columns, rows })
+    }
+
+    /// Tiles are sent as they are
+    pub fn is_full(&self) -> bool {
+        self.fields.is_none() && !self.rows
+    }
+
+    /// One tile as requested: an object of the selected fields or a row of `columns`
+    pub fn tile(&self, tile: &impl Serialize) -> Value {
+        let Ok(Value::Object(mut object)) = serde_json::to_value(tile) else {
+            return Value::Null;
+        };
+        if self.rows {
+            return Value::Array(self.columns.iter().map(|c| row_value(c, object.remove(*c))).collect());
+        }
+        match &self.fields {
+            Some(fields) => Value::Object(fields.iter().filter_map(|f| object.remove(*f).map(|v| (f.to_string(), v))).collect::<Map<_, _>>()),
+            None => Value::Object(object),
+        }
+    }
+
+    /// `resp` as JSON; with `encoding=rows` the object holding the array at `pointer` gets the
+    /// `columns` of its rows
+    pub fn envelope(&self, resp: &impl Serialize, pointer: &str) -> Result<Value, ApiError> {
+        let mut value = serde_json::to_value(resp).map_err(|e| {
+            error!("Response serialization failed: {}", e);
+            ApiError::Internal
+        })?;
+        if self.rows {
+            let parent = pointer.rsplit_once('/').map_or("", |(p, _)| p);
+            let Some(Value::Object(holder)) = value.pointer_mut(parent) else {
+                error!("Response has no object around {}", pointer);
+                return Err(ApiError::Internal);
+            };
+            holder.insert("columns".to_string(), self.columns.iter().map(|c| Value::from(*c)).collect());
+        }
+        Ok(value)
+    }
+
+    /// The whole response with its tiles at `pointer` reshaped
+    pub fn respond(&self, resp: &impl Serialize, pointer: &str) -> Result<HttpResponse, ApiError> {
+        if self.is_full() {
+            return Ok(HttpResponse::Ok().json(resp));
+        }
+        let mut value = self.envelope(resp, pointer)?;
+        if let Some(Value::Array(tiles)) = value.pointer_mut(pointer) {
+            for tile in tiles.iter_mut() {
+                *tile = self.tile(tile);
+            }
+        }
+        Ok(HttpResponse::Ok().json(value))
+    }
+
+    /// Like `respond`, with the tiles taken from `items` while the body is sent (see `json_stream`)
+    pub fn stream<T, I>(self, resp: &impl Serialize, pointer: &str, items: I) -> Result<HttpResponse, ApiError>
+    where
+        T: Serialize + 'static,
+        I: Iterator<Item = T> + 'static,
+    {
+        let envelope = self.envelope(resp, pointer)?;
+        if self.is_full() {
+            json_stream::with_array(&envelope, pointer, items)
+        } else {
+            json_stream::with_array(&envelope, pointer, items.map(move |t| self.tile(&t)))
+        }
+    }
+}
+
+/// Corners become `[lat, lng]`, missing optional fields null
+fn row_value(column: &str, value: Option<Value>) -> Value {
+    match value {
+        Some(Value::Object(corner)) if CORNERS.contains(&column) => {
+            Value::Array(vec![corner.get("lat").cloned().unwrap_or(Value::Null), corner.get("lng").cloned().unwrap_or(Value::Null)])
+        }
+        Some(v) => v,
+        None => Value::Null,
+    }
+}
@@ -0,0 +1,273 @@
+use actix_web::{get, web, HttpResponse};
+use chrono::{DateTime, DurationRound, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use log::{info, warn, error, debug};
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
+use crate::api::heatmap::MapPoint;
+use crate::telemetry::QueryStats;
+use crate::database::store::{BBox, PointFilter, PointOrder, PointStore};
+use super::error::{ApiError, ApiErrorBody};
+use super::grid::MapMeta;
+use super::registry::ApiScope;
+use super::validate::{self, BinSize};
+
+const MAX_HOURS: u32 = 24;
+const MAX_WEEKS: u32 = 12;
+/// z-score for the reported confidence range (~80% two-sided)
+const CONFIDENCE_Z: f64 = 1.28;
+
+// Flat query parameters for GET requests (external names in camelCase)
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct ForecastQueryParams {
+    /// First latitude (corner)
+    #[serde(rename = "lat1")]
+    pub lat1: f64,
+    /// First longitude (corner)
+    #[serde(rename = "lng1")]
+    pub lng1: f64,
+    /// Second latitude (opposite corner)
+    #[serde(rename = "lat2")]
+    pub lat2: f64,
+    /// Second longitude (opposite corner)
+    #[serde(rename = "lng2")]
+    pub lng2: f64,
+    /// Required unless binning=h3
+    #[serde(rename = "tileWidth")]
+    pub tile_width: Option<f64>,
+    #[serde(rename = "tileHeight")]
+    pub tile_height: Option<f64>,
+    /// `grid` (default) or `h3`
+    pub binning: Option<String>,
+    /// H3 resolution with binning=h3 (0..=15, default 8)
+    pub resolution: Option<u8>,
+    /// Optional first forecast hour; defaults to the next full hour
+    #[serde(rename = "from")]
+    pub from: Option<DateTime<Utc>>,
+    /// Optional number of hourly frames to forecast (default 3, max 24)
+    #[serde(rename = "hours")]
+    pub hours: Option<u32>,
+    /// Optional number of past weeks used by the seasonal-naive model (default 4, max 12)
+    #[serde(rename = "weeks")]
+    pub weeks: Option<u32>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct ForecastTile {
+    /// Expected number of points in the tile during the hour
+    pub count: f64,
+    #[serde(rename = "countLow")]
+    pub count_low: f64,
+    #[serde(rename = "countHigh")]
+    pub count_high: f64,
+    /// Expected average speed; absent when no history week had points in the tile
+    pub speed: Option<f64>,
+    #[serde(rename = "speedLow")]
+    pub speed_low: Option<f64>,
+    #[serde(rename = "speedHigh")]
+    pub speed_high: Option<f64>,
+    #[serde(rename = "topLeft")]
+    pub top_left: MapPoint,
+    #[serde(rename = "bottomRight")]
+    pub bottom_right: MapPoint,
+    /// H3 index of the cell with binning=h3
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub h3: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct ForecastFrame {
+    /// Start of the forecast hour (UTC)
+    pub time: DateTime<Utc>,
+    pub tiles: Vec<ForecastTile>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct ForecastData {
+    pub weeks: u32,
+    /// `tileSize` of the grid or `hexSize` of the hexagons the points were binned into
+    #[serde(flatten)]
+    pub size: BinSize,
+    pub data: Vec<ForecastFrame>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct ForecastResponse {
+    pub forecast: ForecastData,
+    /// History points the forecast was built from
+    pub meta: MapMeta,
+}
+
+#[utoipa::path(
+    get,
+    tag = "Forecast",
+    params(
+    ("lat1" = f64, Query, description = "First latitude (corner)"),
+    ("lng1" = f64, Query, description = "First longitude (corner)"),
+    ("lat2" = f64, Query, description = "Second latitude (opposite corner)"),
+    ("lng2" = f64, Query, description = "Second longitude (opposite corner)"),
+    ("tileWidth" = f64, Query, description = "Width of each tile in degrees. Required unless binning=h3"),
+    ("tileHeight" = f64, Query, description = "Height of each tile in degrees. Required unless binning=h3"),
+    ("binning" = String, Query, description = "Cell shape: grid (default) or h3 for H3 hexagons, which take no tile size"),
+    ("resolution" = u8, Query, description = "H3 resolution (0..15) with binning=h3. Optional, defaults to 8"),
+    ("from" = DateTime<chrono::Utc>, Query, description = "First forecast hour. Optional, defaults to the next full hour"),
+    ("hours" = u32, Query, description = "Number of hourly frames to forecast (1..24). Optional, defaults to 3"),
+    ("weeks" = u32, Query, description = "Past weeks used by the seasonal-naive model (1..12). Optional, defaults to 4"),
+    ),
+    responses(
+        (status = 200, description = "Predicted tiles per hour with confidence ranges", body = ForecastResponse),
+        (status = 400, description = "Invalid parameters; `details.param` names the offending one when known", body = ApiErrorBody),
+        (status = 413, description = "Grid over GRID_MAX_CELLS with GRID_OVERSIZE=reject; the message names the smallest tile size or the finest resolution that fits", body = ApiErrorBody),
+        (status = 500, description = "Server Vzorvalsya", body = ApiErrorBody),
+    )
+)]
+
+#[get("")]
+pub async fn get_forecast(
+    store: web::Data<dyn PointStore>,
+    qp: web::Query<ForecastQueryParams>,
+) -> Result<HttpResponse, ApiError> {
+    let started = Instant::now();
+    debug!(
+        "Forecast request: corners=({}, {}), ({}, {}), tile=({:?}, {:?}), binning={:?}/{:?}, from={:?}, hours={:?}, weeks={:?}",
+        qp.lat1, qp.lng1, qp.lat2, qp.lng2, qp.tile_width, qp.tile_height, qp.binning, qp.resolution, qp.from, qp.hours, qp.weeks
+    );
+    // Basic validation
+    let binning = validate::binning(qp.binning.as_deref(), qp.resolution, qp.tile_width.is_some() || qp.tile_height.is_some())?;
+    let tile = match (qp.tile_width, qp.tile_height) {
+        (Some(w), Some(h)) if w > 0.0 && h > 0.0 => Ok((w, h)),
+        (Some(w), Some(h)) => {
+            warn!("Invalid tile size: width={}, height={}", w, h);
+            Err("tileWidth and tileHeight must be > 0")
+        }
+        _ => Err("tileWidth and tileHeight are required"),
+    };
+    let hours = qp.hours.unwrap_or(3);
+    if hours == 0 || hours > MAX_HOURS {
+        return Err(ApiError::bad_param("hours", format!("hours must be between 1 and {}", MAX_HOURS)));
+    }
+    let weeks = qp.weeks.unwrap_or(4);
+    if weeks == 0 || weeks > MAX_WEEKS {
+        return Err(ApiError::bad_param("weeks", format!("weeks must be between 1 and {}", MAX_WEEKS)));
+    }
+
+    let hour = TimeDelta::hours(1);
+    let week = TimeDelta::weeks(1);
+    let from = match qp.from {
+        Some(ts) => ts.duration_trunc(hour),
+        None => Utc::now().duration_trunc(hour).map(|ts| ts + hour),
+    };
+    let from = match from {
+        Ok(ts) => ts,
+        Err(_) => return Err(ApiError::bad_param("from", "from cannot be truncated to an hour")),
+    };
+
+    validate::corners(Some(qp.lat1), Some(qp.lng1), Some(qp.lat2), Some(qp.lng2))?;
+    // Allow any two opposite corners; compute bounds
+    let bbox = BBox::from_corners(qp.lat1, qp.lng1, qp.lat2, qp.lng2);
+    let (bins, size) = validate::fit_bins(bbox, binning, tile, None)?;
+
+    // Early return if degenerate
+    if bins.is_empty() {
+        let resp = ForecastResponse { forecast: ForecastData { weeks, size, data: vec![] }, meta: MapMeta::new(&bins, 0, 0, started) };
+        info!("Forecast degenerate area (no cells), returning empty. took={:?}", started.elapsed());
+        return Ok(HttpResponse::Ok().json(resp));
+    }
+
+    // History is the same hours shifted back by each of 1..=weeks weeks, read one window at a
+    // time so that the rest of those weeks is never loaded
+    let horizon = hour * hours as i32;
+    let mut history = Vec::with_capacity(weeks as usize);
+    for w in 1..=weeks as i32 {
+        let start = from - week * w;
+        let filter = PointFilter {
+            bbox: Some(bbox),
+            since: Some(start),
+            until: Some(start + horizon - TimeDelta::nanoseconds(1)),
+            ..Default::default()
+        };
+        match store.find(&filter, PointOrder::TimestampAsc, None).await {
+            Ok(points) => history.push(points),
+            Err(e) => {
+                error!("Forecast query failed: {}", e);
+                return Err(ApiError::Internal);
+            }
+        }
+    }
+
+    // (frame, tile) -> per-week (count, speed sum)
+    let mut cells: HashMap<(usize, usize), Vec<(usize, f64)>> = HashMap::new();
+    let history_len = history.iter().map(Vec::len).sum();
+    let trips = history.iter().flatten().map(|p| p.randomized_id).collect::<HashSet<_>>().len();
+
+    for (w, points) in history.into_iter().enumerate() {
+        let start = from - week * (w as i32 + 1);
+        for p in points {
+            let Some(ts) = p.timestamp else { continue };
+            let frame = ((ts - start).num_seconds() / 3600) as usize;
+            if ts < start || frame >= hours as usize {
+                continue;
+            }
+            let Some(idx) = bins.index_of(p.lat, p.lng) else { continue };
+            let per_week = cells.entry((frame, idx)).or_insert_with(|| vec![(0, 0.0); weeks as usize]);
+            per_week[w].0 += 1;
+            per_week[w].1 += p.spd;
+        }
+    }
+
+    let mut frames: Vec<ForecastFrame> = (0..hours as usize)
+        .map(|i| ForecastFrame { time: from + hour * i as i32, tiles: Vec::new() })
+        .collect();
+    // Keep tile order stable (row-major from lat_min/lng_min increasing, H3 index order for hexagons)
+    let mut cells: Vec<_> = cells.into_iter().collect();
+    cells.sort_unstable_by_key(|(key, _)| *key);
+    for ((frame, idx), per_week) in cells {
+        let counts: Vec<f64> = per_week.iter().map(|(n, _)| *n as f64).collect();
+        let speeds: Vec<f64> = per_week.iter().filter(|(n, _)| *n > 0).map(|(n, s)| s / *n as f64).collect();
+        let (count, count_sd) = mean_sd(&counts);
+        let speed = (!speeds.is_empty()).then(|| mean_sd(&speeds));
+        let cell = bins.cell_bbox(idx);
+
+        frames[frame].tiles.push(ForecastTile {
+            count,
+            count_low: (count - CONFIDENCE_Z * count_sd).max(0.0),
+            count_high: count + CONFIDENCE_Z * count_sd,
+            speed: speed.map(|(m, _)| m),
+            speed_low: speed.map(|(m, sd)| (m - CONFIDENCE_Z * sd).max(0.0)),
+            speed_high: speed.map(|(m, sd)| m + CONFIDENCE_Z * sd),
+            top_left: MapPoint { lat: cell.lat_min, lng: cell.lng_min },
+            bottom_right: MapPoint { lat: cell.lat_max, lng: cell.lng_max },
+            h3: bins.cell_id(idx),
+        });
+    }
+
+    let meta = MapMeta::new(&bins, history_len, trips, started);
+    let resp = ForecastResponse { forecast: ForecastData { weeks, size, data: frames }, meta };
+    info!(
+        "Forecast response: frames={} tiles={} from grid={} history_points={} took={:?}",
+        resp.forecast.data.len(),
+        resp.forecast.data.iter().map(|f| f.tiles.len()).sum::<usize>(),
+        bins, history_len, started.elapsed()
+    );
+    let stats = QueryStats {
+        rows_scanned: history_len,
+        tiles: resp.forecast.data.iter().map(|f| f.tiles.len()).sum(),
+    };
+    Ok(stats.attach(HttpResponse::Ok().json(resp)))
+}
+
+pub fn routes() -> ApiScope {
+    ApiScope::new("/forecast")
+        .service(get_forecast)
+}
+
+// --- Helpers ---
+
+/// Mean and population standard deviation
+fn mean_sd(values: &[f64]) -> (f64, f64) {
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let var = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n;
+    (mean, var.sqrt())
+}
@@ -0,0 +1,81 @@
+use actix_web::{post, web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use log::debug;
+
+use crate::geo::{bearing_deg, haversine_m};
+use super::heatmap::MapPoint;
+use super::registry::ApiScope;
+
+/// Most pairs accepted by one `POST /api/geo/distance`
+const MAX_PAIRS: usize = 10_000;
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct CoordinatePair {
+    pub from: MapPoint,
+    pub to: MapPoint,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct DistanceRequest {
+    pub pairs: Vec<CoordinatePair>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct DistanceResult {
+    /// Great-circle distance in meters, as used for trip length and jump detection
+    pub distance: f64,
+    /// Initial bearing from `from` to `to`, degrees clockwise from north in [0, 360)
+    pub bearing: f64,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct DistanceResponse {
+    /// Same order as the request's `pairs`
+    pub results: Vec<DistanceResult>,
+}
+
+#[utoipa::path(
+    post,
+    tag = "Geo",
+    request_body = DistanceRequest,
+    responses(
+        (status = 200, description = "Distance and bearing per pair, in request order", body = DistanceResponse),
+        (status = 400, description = "Empty or oversized batch, or coordinates out of range"),
+    )
+)]
+
+#[post("/distance")]
+pub async fn distance(req: web::Json<DistanceRequest>) -> HttpResponse {
+    let pairs = req.into_inner().pairs;
+    if pairs.is_empty() {
+        return HttpResponse::BadRequest().body("Empty pairs list");
+    }
+    if pairs.len() > MAX_PAIRS {
+        return HttpResponse::BadRequest().body(format!("at most {} pairs per request", MAX_PAIRS));
+    }
+    if let Some(i) = pairs.iter().position(|p| !in_range(&p.from) || !in_range(&p.to)) {
+        return HttpResponse::BadRequest().body(format!("pair {}: lat must be within [-90, 90] and lng within [-180, 180]", i));
+    }
+
+    let results: Vec<DistanceResult> = pairs
+        .iter()
+        .map(|p| DistanceResult {
+            distance: haversine_m(p.from.lat, p.from.lng, p.to.lat, p.to.lng),
+            bearing: bearing_deg(p.from.lat, p.from.lng, p.to.lat, p.to.lng),
+        })
+        .collect();
+    debug!("Computed {} distances", results.len());
+    HttpResponse::Ok().json(DistanceResponse { results })
+}
+
+pub fn routes() -> ApiScope {
+    ApiScope::new("/geo")
+        .service(distance)
+}
+
+// --- Helpers ---
+
+fn in_range(p: &MapPoint) -> bool {
+    (-90.0..=90.0).contains(&p.lat) && (-180.0..=180.0).contains(&p.lng)
+}
@@ -0,0 +1,381 @@
+//! Named polygon geofences and the entries into and exits out of them recorded as points arrive
+//! (see `crate::geofences`). Each tenant sees only its own geofences, and only events older than
+//! the publication delay; changing geofences takes the admin token.
+
+use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::database::model::geofence_events::Model as GeofenceEventModel;
+use crate::database::model::geofences::Model as GeofenceModel;
+use crate::database::store::{GeofenceEventFilter, GeofenceSettings};
+use crate::geofences::{self, Geofences};
+use crate::tenant;
+use super::admin::AdminConfig;
+use super::error::{ApiError, ApiErrorBody};
+use super::registry::ApiScope;
+use super::validate;
+
+const MAX_NAME_LEN: usize = 200;
+const MAX_VERTICES: usize = 1000;
+/// Default and maximum page size for `GET /api/geofences/{id}/events`
+const DEFAULT_LIMIT: u64 = 100;
+const MAX_LIMIT: u64 = 1000;
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, ToSchema)]
+pub struct Vertex {
+    pub lat: f64,
+    pub lng: f64,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct Geofence {
+    pub id: i64,
+    pub name: String,
+    pub polygon: Vec<Vertex>,
+    /// Points are only checked against active geofences
+    pub active: bool,
+    /// Trips with recent points whose latest point was inside (see GEOFENCE_IDLE_SECS)
+    #[serde(rename = "tripsInside")]
+    pub trips_inside: usize,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<Utc>,
+    #[serde(rename = "updatedAt")]
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Geofence {
+    fn new(fence: GeofenceModel, fences: &Geofences) -> Self {
+        Self {
+            trips_inside: fences.trips_inside(fence.id),
+            polygon: geofences::vertices_of(&fence).into_iter().map(|(lat, lng)| Vertex { lat, lng }).collect(),
+            id: fence.id,
+            name: fence.name,
+            active: fence.active,
+            created_at: fence.created_at,
+            updated_at: fence.updated_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct GeofenceList {
+    /// Oldest first
+    pub geofences: Vec<Geofence>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct GeofenceBody {
+    pub name: String,
+    /// 3 to 1000 vertices in order; the ring closes back on the first one by itself. It must not
+    /// cross the antimeridian.
+    pub polygon: Vec<Vertex>,
+    /// Default true
+    pub active: Option<bool>,
+}
+
+impl GeofenceBody {
+    fn settings(self) -> Result<GeofenceSettings, ApiError> {
+        let name = self.name.trim();
+        if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+            return Err(ApiError::bad_param("name", format!("name must be 1 to {} characters", MAX_NAME_LEN)));
+        }
+        let mut polygon: Vec<(f64, f64)> = self.polygon.iter().map(|v| (v.lat, v.lng)).collect();
+        // A ring given closed loses its repeated first vertex
+        if polygon.len() > 1 && polygon.first() == polygon.last() {
+            polygon.pop();
+        }
+        if polygon.len() < 3 || polygon.len() > MAX_VERTICES {
+            return Err(ApiError::bad_param("polygon", format!("polygon must have 3 to {} distinct vertices", MAX_VERTICES)));
+        }
+        if let Some(&(lat, lng)) = polygon.iter().find(|(lat, lng)| !(-90.0..=90.0).contains(lat) || !(-180.0..=180.0).contains(lng)) {
+            return Err(ApiError::bad_param("polygon", format!("vertex ({}, {}) is not a coordinate: lat in [-90, 90], lng in [-180, 180]", lat, lng)));
+        }
+        let (lng_min, lng_max) = polygon.iter().fold((f64::MAX, f64::MIN), |(lo, hi), &(_, lng)| (lo.min(lng), hi.max(lng)));
+        if lng_max - lng_min > 180.0 {
+            return Err(ApiError::bad_param("polygon", "polygon spans more than 180 degrees of longitude; geofences across the antimeridian are not supported"));
+        }
+        Ok(GeofenceSettings { name: name.to_string(), polygon, active: self.active.unwrap_or(true) })
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct GeofenceEvent {
+    pub id: i64,
+    #[serde(rename = "geofenceId")]
+    pub geofence_id: i64,
+    #[serde(rename = "randomizedId")]
+    pub randomized_id: i64,
+    /// The point found on the other side of the boundary
+    #[serde(rename = "pointId")]
+    pub point_id: i64,
+    /// entry or exit
+    pub kind: String,
+    pub lat: f64,
+    pub lng: f64,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl From<GeofenceEventModel> for GeofenceEvent {
+    fn from(e: GeofenceEventModel) -> Self {
+        Self {
+            id: e.id,
+            geofence_id: e.geofence_id,
+            randomized_id: e.randomized_id,
+            point_id: e.point_id,
+            kind: e.kind,
+            lat: e.lat,
+            lng: e.lng,
+            timestamp: e.timestamp,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct GeofenceEventsPage {
+    /// In the order they were recorded
+    pub events: Vec<GeofenceEvent>,
+    /// Pass as `cursor` to get the next page; null on the last one
+    #[serde(rename = "nextCursor")]
+    pub next_cursor: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct GeofenceEventsQueryParams {
+    #[serde(rename = "randomizedId")]
+    pub randomized_id: Option<i64>,
+    #[serde(rename = "dateStart")]
+    pub date_start: Option<DateTime<Utc>>,
+    #[serde(rename = "dateEnd")]
+    pub date_end: Option<DateTime<Utc>>,
+    pub limit: Option<u64>,
+    pub cursor: Option<i64>,
+}
+
+/// The geofence if the current request may see it; another tenant's geofences do not exist for it
+pub(super) fn visible(fences: &Geofences, id: i64) -> Result<GeofenceModel, ApiError> {
+    fences
+        .find(id)
+        .filter(|f| tenant::current().is_none_or(|scope| f.tenant_id.as_deref() == scope.tenant_id()))
+        .ok_or_else(|| ApiError::NotFound(format!("No geofence {}", id)))
+}
+
+#[utoipa::path(
+    post,
+    tag = "Geofences",
+    params(
+        ("Authorization" = String, Header, description = "Bearer <ADMIN_TOKEN>"),
+    ),
+    request_body = GeofenceBody,
+    responses(
+        (status = 201, description = "Geofence created; points stored from now on are checked against it", body = Geofence),
+        (status = 400, description = "Invalid name or polygon", body = ApiErrorBody),
+        (status = 401, description = "Missing or wrong token"),
+        (status = 503, description = "Admin endpoints are disabled"),
+        (status = 500, description = "Server Vzorvalsya", body = ApiErrorBody)
+    )
+)]
+
+#[post("")]
+pub async fn create_geofence(
+    cfg: web::Data<AdminConfig>,
+    fences: web::Data<Geofences>,
+    body: web::Json<GeofenceBody>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    if let Some(resp) = cfg.reject(&req) {
+        return Ok(resp);
+    }
+    let settings = body.into_inner().settings()?;
+    let fence = fences.create(&settings, tenant::current_id()).await.map_err(|e| {
+        error!("Could not create geofence: {}", e);
+        ApiError::Internal
+    })?;
+    info!("Created geofence {} ({}, {} vertices)", fence.id, fence.name, settings.polygon.len());
+    Ok(HttpResponse::Created().json(Geofence::new(fence, &fences)))
+}
+
+#[utoipa::path(
+    get,
+    tag = "Geofences",
+    responses(
+        (status = 200, description = "Geofences of the tenant, inactive ones included", body = GeofenceList),
+    )
+)]
+
+#[get("")]
+pub async fn list_geofences(fences: web::Data<Geofences>) -> HttpResponse {
+    let scope = tenant::current();
+    let geofences = fences
+        .all()
+        .into_iter()
+        .filter(|f| scope.as_ref().is_none_or(|scope| f.tenant_id.as_deref() == scope.tenant_id()))
+        .map(|f| Geofence::new(f, &fences))
+        .collect();
+    HttpResponse::Ok().json(GeofenceList { geofences })
+}
+
+#[utoipa::path(
+    get,
+    tag = "Geofences",
+    params(
+        ("id" = i64, Path, description = "Geofence id"),
+    ),
+    responses(
+        (status = 200, description = "The geofence", body = Geofence),
+        (status = 404, description = "No such geofence", body = ApiErrorBody),
+    )
+)]
+
+#[get("/{id}")]
+pub async fn get_geofence(fences: web::Data<Geofences>, path: web::Path<i64>) -> Result<HttpResponse, ApiError> {
+    let fence = visible(&fences, path.into_inner())?;
+    Ok(HttpResponse::Ok().json(Geofence::new(fence, &fences)))
+}
+
+#[utoipa::path(
+    put,
+    tag = "Geofences",
+    params(
+        ("id" = i64, Path, description = "Geofence id"),
+        ("Authorization" = String, Header, description = "Bearer <ADMIN_TOKEN>"),
+    ),
+    request_body = GeofenceBody,
+    responses(
+        (status = 200, description = "Geofence replaced; trips inside stay so until a point of theirs falls outside the new polygon", body = Geofence),
+        (status = 400, description = "Invalid name or polygon", body = ApiErrorBody),
+        (status = 401, description = "Missing or wrong token"),
+        (status = 503, description = "Admin endpoints are disabled"),
+        (status = 404, description = "No such geofence", body = ApiErrorBody),
+        (status = 500, description = "Server Vzorvalsya", body = ApiErrorBody)
+    )
+)]
+
+#[put("/{id}")]
+pub async fn update_geofence(
+    cfg: web::Data<AdminConfig>,
+    fences: web::Data<Geofences>,
+    path: web::Path<i64>,
+    body: web::Json<GeofenceBody>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    if let Some(resp) = cfg.reject(&req) {
+        return Ok(resp);
+    }
+    let id = visible(&fences, path.into_inner())?.id;
+    let settings = body.into_inner().settings()?;
+    match fences.update(id, &settings).await {
+        Ok(Some(fence)) => {
+            info!("Updated geofence {}", id);
+            Ok(HttpResponse::Ok().json(Geofence::new(fence, &fences)))
+        }
+        Ok(None) => Err(ApiError::NotFound(format!("No geofence {}", id))),
+        Err(e) => {
+            error!("Could not update geofence {}: {}", id, e);
+            Err(ApiError::Internal)
+        }
+    }
+}
+
+#[utoipa::path(
+    delete,
+    tag = "Geofences",
+    params(
+        ("id" = i64, Path, description = "Geofence id"),
+        ("Authorization" = String, Header, description = "Bearer <ADMIN_TOKEN>"),
+    ),
+    responses(
+        (status = 204, description = "Geofence deleted with its events"),
+        (status = 401, description = "Missing or wrong token"),
+        (status = 503, description = "Admin endpoints are disabled"),
+        (status = 404, description = "No such geofence", body = ApiErrorBody),
+        (status = 500, description = "Server Vzorvalsya", body = ApiErrorBody)
+    )
+)]
+
+#[delete("/{id}")]
+pub async fn delete_geofence(
+    cfg: web::Data<AdminConfig>,
+    fences: web::Data<Geofences>,
+    path: web::Path<i64>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    if let Some(resp) = cfg.reject(&req) {
+        return Ok(resp);
+    }
+    let id = visible(&fences, path.into_inner())?.id;
+    match fences.delete(id).await {
+        Ok(true) => {
+            info!("Deleted geofence {}", id);
+            Ok(HttpResponse::NoContent().finish())
+        }
+        Ok(false) => Err(ApiError::NotFound(format!("No geofence {}", id))),
+        Err(e) => {
+            error!("Could not delete geofence {}: {}", id, e);
+            Err(ApiError::Internal)
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    tag = "Geofences",
+    params(
+        ("id" = i64, Path, description = "Geofence id"),
+        ("randomizedId" = i64, Query, description = "Only events of this trip. Optional"),
+        ("dateStart" = DateTime<chrono::Utc>, Query, description = "Only events of points at or after this time. Optional"),
+        ("dateEnd" = DateTime<chrono::Utc>, Query, description = "Only events of points at or before this time. Optional"),
+        ("limit" = u64, Query, description = "Page size, default 100, max 1000"),
+        ("cursor" = i64, Query, description = "Only events recorded after this one; pass nextCursor of the previous page. Optional"),
+    ),
+    responses(
+        (status = 200, description = "Entries and exits of the geofence in the order they were recorded; those younger than the publication delay are left out", body = GeofenceEventsPage),
+        (status = 400, description = "Invalid parameters", body = ApiErrorBody),
+        (status = 404, description = "No such geofence", body = ApiErrorBody),
+        (status = 500, description = "Server Vzorvalsya", body = ApiErrorBody)
+    )
+)]
+
+/// Trips entering and leaving a geofence, each at the first point found on the other side of
+/// its boundary
+#[get("/{id}/events")]
+pub async fn list_geofence_events(
+    fences: web::Data<Geofences>,
+    path: web::Path<i64>,
+    qp: web::Query<GeofenceEventsQueryParams>,
+) -> Result<HttpResponse, ApiError> {
+    let id = visible(&fences, path.into_inner())?.id;
+    validate::date_range(qp.date_start, qp.date_end)?;
+    let limit = qp.limit.unwrap_or(DEFAULT_LIMIT);
+    if limit == 0 || limit > MAX_LIMIT {
+        return Err(ApiError::bad_param("limit", format!("limit must be between 1 and {}", MAX_LIMIT)));
+    }
+    let filter = GeofenceEventFilter {
+        geofence_id: id,
+        randomized_id: qp.randomized_id,
+        since: qp.date_start,
+        until: qp.date_end,
+        after_id: qp.cursor,
+        ..Default::default()
+    };
+    let mut events = fences.events(&filter, limit + 1).await.map_err(|e| {
+        error!("Geofence {} events query failed: {}", id, e);
+        ApiError::Internal
+    })?;
+    let more = events.len() as u64 > limit;
+    events.truncate(limit as usize);
+    let next_cursor = if more { events.last().map(|e| e.id) } else { None };
+    Ok(HttpResponse::Ok().json(GeofenceEventsPage { events: events.into_iter().map(GeofenceEvent::from).collect(), next_cursor }))
+}
+
+pub fn routes() -> ApiScope {
+    ApiScope::new("/geofences")
+        .service(create_geofence)
+        .service(list_geofences)
+        .service(get_geofence)
+        .service(update_geofence)
+        .service(delete_geofence)
+        .service(list_geofence_events)
+}
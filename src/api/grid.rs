@@ -0,0 +1,314 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use std::ops::AddAssign;
+use std::time::Instant;
+use utoipa::ToSchema;
+
+use crate::database::model::points::Model as PointModel;
+use crate::database::store::BBox;
+use crate::geo;
+use super::hexgrid::HexGrid;
+
+/// Regular grid of `tile_width` x `tile_height` degree cells over a bbox, row-major from
+/// (lat_min, lng_min). Edge cells are clipped to the bbox; points outside are clamped in.
+/// Over a bbox across the antimeridian, columns run east from lng_min through ±180.
+#[derive(Debug, Clone, Copy)]
+pub struct Grid {
+    pub bbox: BBox,
+    pub tile_width: f64,
+    pub tile_height: f64,
+    pub rows: usize,
+    pub cols: usize,
+}
+
+impl Grid {
+    /// Tile sizes must be > 0 (validated by the handlers). A zero-height or zero-width bbox
+    /// gives an empty grid.
+    pub fn new(bbox: BBox, tile_width: f64, tile_height: f64) -> Self {
+        let lat_span = (bbox.lat_max - bbox.lat_min).max(0.0);
+        let lng_span = bbox.lng_span();
+        let rows = if lat_span == 0.0 { 0 } else { ((lat_span / tile_height).ceil() as usize).max(1) };
+        let cols = if lng_span == 0.0 { 0 } else { ((lng_span / tile_width).ceil() as usize).max(1) };
+        Self { bbox, tile_width, tile_height, rows, cols }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows == 0 || self.cols == 0
+    }
+
+    /// Number of cells
+    pub fn len(&self) -> usize {
+        self.rows * self.cols
+    }
+
+    /// Index of the cell containing the point; clamps to [0, rows-1] / [0, cols-1]
+    pub fn index_of(&self, lat: f64, lng: f64) -> usize {
+        let r = ((lat - self.bbox.lat_min) / self.tile_height).floor().max(0.0) as usize;
+        let c = ((self.bbox.unwrap_lng(lng) - self.bbox.lng_min) / self.tile_width).floor().max(0.0) as usize;
+        r.min(self.rows - 1) * self.cols + c.min(self.cols - 1)
+    }
+
+    /// Bounds of one cell, clipped to the grid bbox; the cell on the antimeridian itself
+    /// comes back with lng_min > lng_max like any wrap-around bbox
+    pub fn cell_bbox(&self, r: usize, c: usize) -> BBox {
+        let lat_min = self.bbox.lat_min + (r as f64) * self.tile_height;
+        let lng_min = self.bbox.lng_min + (c as f64) * self.tile_width;
+        let lng_end = self.bbox.lng_min + self.bbox.lng_span();
+        BBox {
+            lat_min,
+            lat_max: (lat_min + self.tile_height).min(self.bbox.lat_max),
+            lng_min: geo::wrap_lng(lng_min),
+            lng_max: geo::wrap_lng((lng_min + self.tile_width).min(lng_end)),
+        }
+    }
+
+    /// Sum of `values` over the (up to) 8 cells surrounding (r, c)
+    pub fn neighbor_sum<T: Copy + Default + AddAssign>(&self, values: &[T], r: usize, c: usize) -> T {
+        let mut sum = T::default();
+        for dr in -1..=1isize {
+            for dc in -1..=1isize {
+                // Skip the center cell (the current tile itself)
+                if dr == 0 && dc == 0 {
+                    continue;
+                }
+                let nr = r as isize + dr;
+                let nc = c as isize + dc;
+                if nr >= 0 && nr < self.rows as isize && nc >= 0 && nc < self.cols as isize {
+                    sum += values[(nr as usize) * self.cols + (nc as usize)];
+                }
+            }
+        }
+        sum
+    }
+
+    /// `values` averaged over the cells within `radius` rows/columns of each cell, weighted by
+    /// `kernel`; cells past the edge of the grid are left out of the average
+    pub fn smooth(&self, values: &[usize], kernel: Kernel, radius: u32) -> Vec<f64> {
+        let k = radius as isize;
+        let mut out = vec![0.0; values.len()];
+        for r in 0..self.rows as isize {
+            for c in 0..self.cols as isize {
+                let (mut sum, mut weights) = (0.0, 0.0);
+                for nr in (r - k).max(0)..=(r + k).min(self.rows as isize - 1) {
+                    for nc in (c - k).max(0)..=(c + k).min(self.cols as isize - 1) {
+                        let (dr, dc) = ((nr - r) as f64, (nc - c) as f64);
+                        let w = kernel.weight((dr * dr + dc * dc).sqrt(), radius);
+                        sum += w * values[(nr as usize) * self.cols + nc as usize] as f64;
+                        weights += w;
+                    }
+                }
+                out[(r as usize) * self.cols + c as usize] = if weights > 0.0 { sum / weights } else { 0.0 };
+            }
+        }
+        out
+    }
+}
+
+/// Weighting of `smoothing=...` by distance from the cell, in cells
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kernel {
+    /// Plain mean over the square (hexagonal) window
+    Box,
+    /// Bell curve with sigma = radius / 2
+    Gaussian,
+    /// 1 / (1 + distance)
+    Distance,
+}
+
+impl Kernel {
+    /// Grid cells in the corners of the window are further than `radius` and only count for `Box`
+    pub fn weight(self, distance: f64, radius: u32) -> f64 {
+        match self {
+            Kernel::Box => 1.0,
+            _ if distance > radius as f64 => 0.0,
+            Kernel::Gaussian => {
+                let sigma = radius as f64 / 2.0;
+                (-distance * distance / (2.0 * sigma * sigma)).exp()
+            }
+            Kernel::Distance => 1.0 / (1.0 + distance),
+        }
+    }
+}
+
+/// Cells an aggregation endpoint bins points into: the rectangular grid (`binning=grid`, the
+/// default) or H3 hexagons (`binning=h3`). Cells are addressed by index, row-major for the
+/// grid and in H3 index order for hexagons.
+#[derive(Debug, Clone)]
+pub enum Bins {
+    Rect(Grid),
+    Hex(HexGrid),
+}
+
+impl Bins {
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Self::Rect(grid) => grid.is_empty(),
+            Self::Hex(hex) => hex.is_empty(),
+        }
+    }
+
+    /// Number of cells
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Rect(grid) => grid.len(),
+            Self::Hex(hex) => hex.len(),
+        }
+    }
+
+    /// Area the cells were built over
+    pub fn bbox(&self) -> BBox {
+        match self {
+            Self::Rect(grid) => grid.bbox,
+            Self::Hex(hex) => hex.bbox,
+        }
+    }
+
+    /// Index of the cell containing the point; always one for the grid, which clamps
+    pub fn index_of(&self, lat: f64, lng: f64) -> Option<usize> {
+        match self {
+            Self::Rect(grid) => Some(grid.index_of(lat, lng)),
+            Self::Hex(hex) => hex.index_of(lat, lng),
+        }
+    }
+
+    /// Every cell index in order
+    pub fn cells(&self) -> std::ops::Range<usize> {
+        0..self.len()
+    }
+
+    /// Bounds of one cell, see `Grid::cell_bbox` and `HexGrid::cell_bbox`
+    pub fn cell_bbox(&self, idx: usize) -> BBox {
+        match self {
+            Self::Rect(grid) => grid.cell_bbox(idx / grid.cols, idx % grid.cols),
+            Self::Hex(hex) => hex.cell_bbox(idx),
+        }
+    }
+
+    /// H3 index of a hexagon; grid cells have none
+    pub fn cell_id(&self, idx: usize) -> Option<String> {
+        match self {
+            Self::Rect(_) => None,
+            Self::Hex(hex) => Some(hex.cell_id(idx)),
+        }
+    }
+
+    /// Sum of `values` over the cells adjacent to `idx`: 8 around a grid cell, 6 around a hexagon
+    pub fn neighbor_sum<T: Copy + Default + AddAssign>(&self, values: &[T], idx: usize) -> T {
+        match self {
+            Self::Rect(grid) => grid.neighbor_sum(values, idx / grid.cols, idx % grid.cols),
+            Self::Hex(hex) => hex.neighbor_sum(values, idx),
+        }
+    }
+
+    /// Kernel-weighted mean of `values` around every cell, see `Grid::smooth` and `HexGrid::smooth`
+    pub fn smooth(&self, values: &[usize], kernel: Kernel, radius: u32) -> Vec<f64> {
+        match self {
+            Self::Rect(grid) => grid.smooth(values, kernel, radius),
+            Self::Hex(hex) => hex.smooth(values, kernel, radius),
+        }
+    }
+}
+
+/// Shape for the logs: `rows x cols` of the grid, the cell count and resolution of hexagons
+impl fmt::Display for Bins {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Rect(grid) => write!(f, "{}x{}", grid.rows, grid.cols),
+            Self::Hex(hex) => write!(f, "{} h3 cells at res {}", hex.len(), hex.resolution),
+        }
+    }
+}
+
+/// `meta` block of the map responses, so clients can draw legends and diagnostics without
+/// reverse-engineering them from the tiles
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MapMeta {
+    /// Grid rows (south to north) and columns (west to east); null for hexagons
+    pub rows: Option<usize>,
+    pub cols: Option<usize>,
+    /// Cells of the grid or hexagons, empty ones included
+    pub cells: usize,
+    /// Area the cells cover once the deployment defaults are applied
+    pub bounds: MapBounds,
+    /// Points the map was computed from
+    pub points: usize,
+    /// Distinct trips among them; null when the map was added up from the hourly tile rollups
+    pub trips: Option<usize>,
+    /// The counts come from the hourly tile rollups (TILE_ROLLUP_DEGREES) instead of the points
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub rollups: bool,
+    /// Time spent on the request so far, in milliseconds
+    pub took_ms: f64,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct MapBounds {
+    pub lat_min: f64,
+    pub lng_min: f64,
+    pub lat_max: f64,
+    /// Below lngMin when the area crosses the antimeridian
+    pub lng_max: f64,
+}
+
+impl MapMeta {
+    pub fn new(bins: &Bins, points: usize, trips: usize, started: Instant) -> Self {
+        let (rows, cols) = match bins {
+            Bins::Rect(grid) => (Some(grid.rows), Some(grid.cols)),
+            Bins::Hex(_) => (None, None),
+        };
+        let b = bins.bbox();
+        Self {
+            rows,
+            cols,
+            cells: bins.len(),
+            bounds: MapBounds { lat_min: b.lat_min, lng_min: b.lng_min, lat_max: b.lat_max, lng_max: b.lng_max },
+            points,
+            trips: Some(trips),
+            rollups: false,
+            took_ms: started.elapsed().as_secs_f64() * 1000.0,
+        }
+    }
+
+    /// Marks a map added up from the tile rollups, which do not know the trips
+    pub fn from_rollups(mut self) -> Self {
+        self.trips = None;
+        self.rollups = true;
+        self
+    }
+}
+
+/// Number of different trips among `points`
+pub fn distinct_trips(points: &[PointModel]) -> usize {
+    points.iter().map(|p| p.randomized_id).collect::<HashSet<_>>().len()
+}
+
+/// Tile size in degrees from the map endpoint parameters: either `tileWidth`/`tileHeight` in
+/// degrees or `tileSizeMeters`, converted at the bbox's middle latitude so that tiles stay
+/// roughly square on the ground.
+pub fn tile_size_degrees(
+    bbox: &BBox,
+    tile_width: Option<f64>,
+    tile_height: Option<f64>,
+    tile_size_meters: Option<f64>,
+) -> Result<(f64, f64), &'static str> {
+    match (tile_width, tile_height, tile_size_meters) {
+        (Some(w), Some(h), None) => {
+            if w <= 0.0 || h <= 0.0 {
+                return Err("tileWidth and tileHeight must be > 0");
+            }
+            Ok((w, h))
+        }
+        (None, None, Some(m)) => {
+            if m.is_nan() || m <= 0.0 {
+                return Err("tileSizeMeters must be > 0");
+            }
+            let mid_lat = (bbox.lat_min + bbox.lat_max) / 2.0;
+            Ok((geo::meters_to_lng_deg(m, mid_lat), geo::meters_to_lat_deg(m)))
+        }
+        (_, _, Some(_)) => Err("tileSizeMeters cannot be combined with tileWidth/tileHeight"),
+        _ => Err("tileWidth and tileHeight (or tileSizeMeters) are required"),
+    }
+}
@@ -1,12 +1,19 @@
 use actix_web::{get, web, HttpResponse};
-use chrono::{DateTime, NaiveTime, Weekday, Datelike};
-use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use chrono::{DateTime, FixedOffset, NaiveTime, Utc, Weekday, Datelike};
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 use log::{info, warn, error, debug};
+use std::collections::HashSet;
 use std::time::Instant;
-use sea_orm::QueryOrder;
-use crate::database::model::points::{self, Entity as Points};
+use crate::telemetry::QueryStats;
+use crate::database::store::{PointFilter, PointOrder, PointStore};
+use super::defaults::defaults;
+use super::grid::{Bins, MapMeta};
+use super::error::{ApiError, ApiErrorBody};
+use super::fields::TileShape;
+use super::json_stream;
+use super::validate::{self, BinSize};
+use super::registry::ApiScope;
 
 #[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
 pub struct MapPoint {
@@ -14,36 +21,21 @@ pub struct MapPoint {
     pub lng: f64,
 }
 
-#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
-pub struct MapRectangle {
-    pub top_left: MapPoint,
-    pub bottom_right: MapPoint,
-}
-
-#[derive(Debug, Deserialize, Serialize, ToSchema)]
-pub struct HeatmapRequest {
-    pub area: MapRectangle,
-    pub time_start: DateTime<chrono::Utc>,
-    pub time_end: DateTime<chrono::Utc>,
-    pub tile_width: f64,
-    pub tile_height: f64,
-}
-
 // Flat query parameters for GET requests (external names in camelCase)
 #[derive(Debug, Deserialize, Serialize, ToSchema)]
 pub struct HeatmapQueryParams {
     /// First latitude (corner)
     #[serde(rename = "lat1")]
-    pub lat1: f64,
+    pub lat1: Option<f64>,
     /// First longitude (corner)
     #[serde(rename = "lng1")]
-    pub lng1: f64,
+    pub lng1: Option<f64>,
     /// Second latitude (opposite corner)
     #[serde(rename = "lat2")]
-    pub lat2: f64,
+    pub lat2: Option<f64>,
     /// Second longitude (opposite corner)
     #[serde(rename = "lng2")]
-    pub lng2: f64,
+    pub lng2: Option<f64>,
     /// Optional date range start (inclusive)
     #[serde(rename = "dateStart")]
     pub date_start: Option<DateTime<chrono::Utc>>,
@@ -51,9 +43,16 @@ pub struct HeatmapQueryParams {
     #[serde(rename = "dateEnd")]
     pub date_end: Option<DateTime<chrono::Utc>>,
     #[serde(rename = "tileWidth")]
-    pub tile_width: f64,
+    pub tile_width: Option<f64>,
     #[serde(rename = "tileHeight")]
-    pub tile_height: f64,
+    pub tile_height: Option<f64>,
+    /// Square tile side in meters, instead of tileWidth/tileHeight
+    #[serde(rename = "tileSizeMeters")]
+    pub tile_size_meters: Option<f64>,
+    /// `grid` (default) or `h3`
+    pub binning: Option<String>,
+    /// H3 resolution with binning=h3 (0..=15, default 8)
+    pub resolution: Option<u8>,
     /// Optional list of weekdays 1..7, comma/space separated
     #[serde(rename = "days")]
     pub days: Option<String>,
@@ -63,8 +62,22 @@ pub struct HeatmapQueryParams {
     /// Optional time-of-day end in HH or HH:MM (exclusive)
     #[serde(rename = "timeEnd")]
     pub time_end_tod: Option<String>,
+    /// Offset for days/timeStart/timeEnd, e.g. +05:00 (default DEFAULT_TIMEZONE or UTC)
+    pub timezone: Option<String>,
+    /// `box`, `gaussian` or `distance`: adds `smoothed` to every tile
+    pub smoothing: Option<String>,
+    /// Cells around each tile the smoothing reaches (1..=5, default 1)
+    #[serde(rename = "smoothingRadius")]
+    pub smoothing_radius: Option<u32>,
+    /// Comma separated tile fields to send, e.g. `count,topLeft`
+    pub fields: Option<String>,
+    /// `objects` (default) or `rows`
+    pub encoding: Option<String>,
 }
 
+/// Fields of `HeatTile` for `fields`, in the column order of `encoding=rows`
+const HEAT_TILE_FIELDS: [&str; 6] = ["count", "neighborCount", "topLeft", "bottomRight", "h3", "smoothed"];
+
 #[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
 pub struct HeatTile {
     pub count: usize,
@@ -74,252 +87,444 @@ pub struct HeatTile {
     pub top_left: MapPoint,
     #[serde(rename = "bottomRight")]
     pub bottom_right: MapPoint,
+    /// H3 index of the cell with binning=h3
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub h3: Option<String>,
+    /// With `smoothing`: kernel-weighted mean of `count` over the tile and those around it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub smoothed: Option<f64>,
 }
 
 #[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
 pub struct HeatmapData {
     pub data: Vec<HeatTile>,
+    /// `tileSize` of the grid or `hexSize` of the hexagons the points were binned into
+    #[serde(flatten)]
+    pub size: BinSize,
 }
 
 #[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
 pub struct HeatmapResponse {
     pub heatmap: HeatmapData,
+    /// Trips counted in the tiles, out of the points read
+    pub meta: MapMeta,
 }
 
 #[utoipa::path(
     get,
-    path = "/api/heatmap",
     tag = "Heatmap",
     params(
-    ("lat1" = f64, Query, description = "First latitude (corner)"),
+    ("lat1" = f64, Query, description = "First latitude (corner). All four corners may be omitted when the deployment sets DEFAULT_BBOX"),
     ("lng1" = f64, Query, description = "First longitude (corner)"),
     ("lat2" = f64, Query, description = "Second latitude (opposite corner)"),
     ("lng2" = f64, Query, description = "Second longitude (opposite corner)"),
     ("dateStart" = DateTime<chrono::Utc>, Query, description = "Start of the date/time range (inclusive). Optional"),
     ("dateEnd" = DateTime<chrono::Utc>, Query, description = "End of the date/time range (inclusive). Optional"),
-    ("tileWidth" = f64, Query, description = "Width of each tile in degrees"),
-    ("tileHeight" = f64, Query, description = "Height of each tile in degrees"),
+    ("tileWidth" = f64, Query, description = "Width of each tile in degrees. Required unless tileSizeMeters is given"),
+    ("tileHeight" = f64, Query, description = "Height of each tile in degrees. Required unless tileSizeMeters is given"),
+    ("tileSizeMeters" = f64, Query, description = "Side of each tile in meters, converted to degrees at the middle latitude of the area. Replaces tileWidth/tileHeight"),
+    ("binning" = String, Query, description = "Cell shape: grid (default) or h3 for H3 hexagons, which take no tile size"),
+    ("resolution" = u8, Query, description = "H3 resolution (0..15) with binning=h3. Optional, defaults to 8"),
     ("days" = String, Query, description = "Optional list of weekdays to include (1=Mon..7=Sun). Comma or space separated"),
     ("timeStart" = String, Query, description = "Optional time-of-day start in HH or HH:MM (inclusive)"),
     ("timeEnd" = String, Query, description = "Optional time-of-day end in HH or HH:MM (exclusive)"),
+    ("timezone" = String, Query, description = "UTC offset the days and time-of-day filters are evaluated in, e.g. +05:00. Defaults to DEFAULT_TIMEZONE, else UTC"),
+    ("smoothing" = String, Query, description = "Kernel for the `smoothed` value of each tile: box (plain mean), gaussian (sigma = radius / 2) or distance (weight 1 / (1 + cells away)). Optional; neighborCount stays the 8-cell sum either way"),
+    ("smoothingRadius" = u32, Query, description = "Reach of the smoothing in cells (grid) or rings (h3), 1..5. Optional, defaults to 1"),
+    ("fields" = String, Query, description = "Comma separated tile fields to send: count, neighborCount, topLeft, bottomRight, h3, smoothed. Optional, defaults to all"),
+    ("encoding" = String, Query, description = "objects (default) or rows: every tile becomes an array in the order of `columns`, next to `data`, with corners as [lat, lng]"),
     ),
     responses(
         (status = 200, description = "Heatmap data", body = HeatmapResponse),
-        (status = 500, description = "Server Vzorvalsya"),
+        (status = 400, description = "Invalid parameters; `details.param` names the offending one when known", body = ApiErrorBody),
+        (status = 413, description = "Grid over GRID_MAX_CELLS with GRID_OVERSIZE=reject; the message names the smallest tile size or the finest resolution that fits", body = ApiErrorBody),
+        (status = 500, description = "Server Vzorvalsya", body = ApiErrorBody),
     )
 )]
 
 #[get("")]
 pub async fn get_heatmap(
-    db: web::Data<DatabaseConnection>,
+    store: web::Data<dyn PointStore>,
     qp: web::Query<HeatmapQueryParams>,
-) -> HttpResponse {
+) -> Result<HttpResponse, ApiError> {
     let started = Instant::now();
     debug!(
-    "Heatmap request: corners=({}, {}), ({}, {}), date=[{:?}..{:?}], tile=({}, {}), days={:?}, tod=[{:?}..{:?}]",
-    qp.lat1, qp.lng1, qp.lat2, qp.lng2, qp.date_start, qp.date_end, qp.tile_width, qp.tile_height,
+    "Heatmap request: corners=({:?}, {:?}), ({:?}, {:?}), date=[{:?}..{:?}], tile=({:?}, {:?}, {:?}m), days={:?}, tod=[{:?}..{:?}]",
+    qp.lat1, qp.lng1, qp.lat2, qp.lng2, qp.date_start, qp.date_end, qp.tile_width, qp.tile_height, qp.tile_size_meters,
         qp.days, qp.time_start_tod, qp.time_end_tod
     );
     // Basic validation
-    if qp.tile_width <= 0.0 || qp.tile_height <= 0.0 {
-        warn!("Invalid tile size: width={}, height={}", qp.tile_width, qp.tile_height);
-        return HttpResponse::BadRequest().body("tileWidth and tileHeight must be > 0");
-    }
-
-    // Parse optional weekday/time-of-day filters
-    let day_set = match &qp.days {
-        Some(s) => match parse_days_of_week(s) {
-            Ok(set) => Some(set),
-            Err(e) => {
-                warn!("Invalid daysOfWeek parameter '{}': {}", s, e);
-                return HttpResponse::BadRequest().body("daysOfWeek must contain numbers 1..7 separated by comma/space");
-            }
-        },
-        None => None,
+    // Allow any two opposite corners; compute bounds. Omitted parameters fall back to the deployment defaults
+    validate::area(qp.lat1, qp.lng1, qp.lat2, qp.lng2, qp.date_start, qp.date_end)?;
+    let bbox = match defaults().bbox(qp.lat1, qp.lng1, qp.lat2, qp.lng2) {
+        Ok(b) => b,
+        Err(msg) => return Err(ApiError::bad_request(msg)),
     };
-    let (tod_start, tod_end) = match (&qp.time_start_tod, &qp.time_end_tod) {
-        (Some(a), Some(b)) => {
-            let a = match parse_time_of_day(a) { Ok(t) => t, Err(_) => {
-                return HttpResponse::BadRequest().body("timeOfDayStart must be HH or HH:MM");
-            }};
-            let b = match parse_time_of_day(b) { Ok(t) => t, Err(_) => {
-                return HttpResponse::BadRequest().body("timeOfDayEnd must be HH or HH:MM");
-            }};
-            if b <= a {
-                warn!("Invalid time-of-day window: start={:?} end={:?}", a, b);
-                return HttpResponse::BadRequest().body("timeOfDayEnd must be greater than timeOfDayStart (same-day window)");
-            }
-            (Some(a), Some(b))
-        }
-        (None, None) => (None, None),
-        _ => {
-            return HttpResponse::BadRequest().body("Both timeOfDayStart and timeOfDayEnd must be provided together");
-        }
+    let (date_start, date_end) = defaults().date_range(qp.date_start, qp.date_end);
+    let tz = match defaults().timezone(qp.timezone.as_deref()) {
+        Ok(tz) => tz,
+        Err(msg) => return Err(ApiError::bad_request(msg)),
     };
+    let tiled = qp.tile_width.is_some() || qp.tile_height.is_some() || qp.tile_size_meters.is_some();
+    let binning = validate::binning(qp.binning.as_deref(), qp.resolution, tiled)?;
+    let tile = defaults().tile_size(&bbox, qp.tile_width, qp.tile_height, qp.tile_size_meters);
 
-    // Allow any two opposite corners; compute bounds
-    let (lat_min, lat_max) = if qp.lat1 <= qp.lat2 { (qp.lat1, qp.lat2) } else { (qp.lat2, qp.lat1) };
-    let (lon_min, lon_max) = if qp.lng1 <= qp.lng2 { (qp.lng1, qp.lng2) } else { (qp.lng2, qp.lng1) };
+    let time = TimeFilter::parse(qp.days.as_deref(), qp.time_start_tod.as_deref(), qp.time_end_tod.as_deref(), tz)?;
+    let smoothing = validate::smoothing(qp.smoothing.as_deref(), qp.smoothing_radius)?;
+    let shape = TileShape::parse(qp.fields.as_deref(), qp.encoding.as_deref(), &HEAT_TILE_FIELDS)?;
 
-    let lat_span = (lat_max - lat_min).max(0.0);
-    let lon_span = (lon_max - lon_min).max(0.0);
-
-    let rows = if lat_span == 0.0 { 0 } else { ((lat_span / qp.tile_height).ceil() as usize).max(1) };
-    let cols = if lon_span == 0.0 { 0 } else { ((lon_span / qp.tile_width).ceil() as usize).max(1) };
+    let (bins, size) = validate::fit_bins(bbox, binning, tile, qp.tile_size_meters)?;
 
     // Early return if degenerate
-    if rows == 0 || cols == 0 {
-        let resp = HeatmapResponse { heatmap: HeatmapData { data: vec![] } };
-    info!("Heatmap degenerate area (rows=0 or cols=0), returning empty. took={:?}", started.elapsed());
-        return HttpResponse::Ok().json(resp);
+    if bins.is_empty() {
+        let resp = HeatmapResponse { heatmap: HeatmapData { data: vec![], size }, meta: MapMeta::new(&bins, 0, 0, started) };
+    info!("Heatmap degenerate area (no cells), returning empty. took={:?}", started.elapsed());
+        return shape.respond(&resp, "/heatmap/data");
     }
 
-    // First, get all points within bounds and optional time range, ordered by timestamp
-    let mut query = Points::find()
-        .filter(points::Column::Lat.between(lat_min, lat_max))
-        .filter(points::Column::Lng.between(lon_min, lon_max));
-    if let Some(ts_start) = qp.date_start {
-        query = query.filter(points::Column::Timestamp.gte(ts_start));
-    }
-    if let Some(ts_end) = qp.date_end {
-        query = query.filter(points::Column::Timestamp.lte(ts_end));
-    }
-    let all_points = match query
-        .order_by_asc(points::Column::Timestamp)
-        .all(db.get_ref()).await {
-        Ok(p) => p,
-        Err(e) => {
-            error!("Heatmap query failed: {}", e);
-            return HttpResponse::InternalServerError().finish();
+    let (counts, total_points_count) = origin_counts(store.get_ref(), &bins, date_start, date_end, &time).await?;
+    let smoothed = smoothing.map(|(kernel, radius)| bins.smooth(&counts, kernel, radius));
+
+    let points_count = counts.iter().sum::<usize>();
+    let meta = MapMeta::new(&bins, total_points_count, points_count, started);
+    let cells = bins.cells();
+    let streamed = json_stream::should_stream(bins.len());
+
+    // Response tiles (row-major from lat_min/lng_min increasing, H3 index order for hexagons)
+    let tile_at = {
+        let bins = bins.clone();
+        move |idx: usize| {
+            let count = counts[idx];
+            // Calculate neighbor count (8 surrounding cells, 6 around a hexagon)
+            let neighbor_count = bins.neighbor_sum(&counts, idx);
+            let smoothed = smoothed.as_ref().map(|s| s[idx]);
+
+            // Include tiles with points, with non-zero neighbors or within reach of the smoothing
+            (count > 0 || neighbor_count > 0 || smoothed.is_some_and(|s| s > 0.0)).then(|| {
+                let cell = bins.cell_bbox(idx);
+                HeatTile {
+                    count,
+                    neighbor_count,
+                    top_left: MapPoint { lat: cell.lat_min, lng: cell.lng_min },
+                    bottom_right: MapPoint { lat: cell.lat_max, lng: cell.lng_max },
+                    h3: bins.cell_id(idx),
+                    smoothed,
+                }
+            })
         }
     };
 
-    // Filter to keep only the first point for each randomized_id, then apply weekday/time-of-day filters
-    let total_points_count = all_points.len();
-    let mut seen_trips = std::collections::HashSet::new();
-    let points: Vec<_> = all_points
-        .into_iter()
-        .filter(|point| seen_trips.insert(point.randomized_id))
-        .filter(|point| {
-            // Weekday filter (1=Mon..7=Sun)
-            if let Some(ref set) = day_set {
-                if let Some(ts) = point.timestamp {
-                    let wd = ts.weekday();
-                    let day_num: u8 = match wd {
-                        Weekday::Mon => 1,
-                        Weekday::Tue => 2,
-                        Weekday::Wed => 3,
-                        Weekday::Thu => 4,
-                        Weekday::Fri => 5,
-                        Weekday::Sat => 6,
-                        Weekday::Sun => 7,
-                    };
-                    if !set.contains(&day_num) { return false; }
-                } else {
-                    return false; // no timestamp -> cannot match filter
-                }
-            }
-            true
-        })
-        .filter(|point| {
-            // Time-of-day filter [start, end)
-            match (tod_start, tod_end) {
-                (Some(s), Some(e)) => {
-                    if let Some(ts) = point.timestamp { let t = ts.time(); t >= s && t < e } else { false }
-                }
-                _ => true,
-            }
-        })
-        .collect();
-    debug!(
-        "Heatmap DB returned {} total points, filtered to {} first-per-trip and {} after weekday/time filters in {:?}",
-        total_points_count,
-        seen_trips.len(),
-        points.len(),
-        started.elapsed()
+    let mut resp = HeatmapResponse { heatmap: HeatmapData { data: vec![], size }, meta };
+    let tiles = if streamed {
+        cells.clone().filter(|&idx| tile_at(idx).is_some()).count()
+    } else {
+        resp.heatmap.data = cells.clone().filter_map(&tile_at).collect();
+        resp.heatmap.data.len()
+    };
+    info!(
+    "Heatmap response: tiles={} (non-zero only) from grid={} points_count={} streamed={} took={:?}",
+    tiles, bins, points_count, streamed, started.elapsed()
     );
+    let stats = QueryStats { rows_scanned: total_points_count, tiles };
+    if streamed {
+        return Ok(stats.attach(shape.stream(&resp, "/heatmap/data", cells.filter_map(tile_at))?));
+    }
+    Ok(stats.attach(shape.respond(&resp, "/heatmap/data")?))
+}
+
+// Query parameters of the diff: the heatmap's area and filters with two date ranges
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct HeatmapDiffQueryParams {
+    pub lat1: Option<f64>,
+    pub lng1: Option<f64>,
+    pub lat2: Option<f64>,
+    pub lng2: Option<f64>,
+    /// Start of the earlier window (inclusive)
+    #[serde(rename = "beforeStart")]
+    pub before_start: DateTime<Utc>,
+    /// End of the earlier window (inclusive)
+    #[serde(rename = "beforeEnd")]
+    pub before_end: DateTime<Utc>,
+    /// Start of the later window (inclusive)
+    #[serde(rename = "afterStart")]
+    pub after_start: DateTime<Utc>,
+    /// End of the later window (inclusive)
+    #[serde(rename = "afterEnd")]
+    pub after_end: DateTime<Utc>,
+    #[serde(rename = "tileWidth")]
+    pub tile_width: Option<f64>,
+    #[serde(rename = "tileHeight")]
+    pub tile_height: Option<f64>,
+    #[serde(rename = "tileSizeMeters")]
+    pub tile_size_meters: Option<f64>,
+    pub binning: Option<String>,
+    pub resolution: Option<u8>,
+    pub days: Option<String>,
+    #[serde(rename = "timeStart")]
+    pub time_start_tod: Option<String>,
+    #[serde(rename = "timeEnd")]
+    pub time_end_tod: Option<String>,
+    pub timezone: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct HeatDiffTile {
+    /// Trips starting in the tile during the earlier window
+    pub before: usize,
+    /// Trips starting in the tile during the later window
+    pub after: usize,
+    /// after - before
+    pub delta: i64,
+    /// Change relative to `before` in percent; null when the tile had no trips before
+    #[serde(rename = "percentChange")]
+    pub percent_change: Option<f64>,
+    #[serde(rename = "topLeft")]
+    pub top_left: MapPoint,
+    #[serde(rename = "bottomRight")]
+    pub bottom_right: MapPoint,
+    /// H3 index of the cell with binning=h3
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub h3: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct HeatmapDiffData {
+    /// Tiles with trips in either window
+    pub data: Vec<HeatDiffTile>,
+    /// Trips over the whole area per window, to scale by when the windows differ in length
+    #[serde(rename = "beforeTotal")]
+    pub before_total: usize,
+    #[serde(rename = "afterTotal")]
+    pub after_total: usize,
+    #[serde(flatten)]
+    pub size: BinSize,
+}
 
-    // Bucket points into tiles
-    let mut counts = vec![0usize; rows * cols];
-    let inv_h = 1.0 / qp.tile_height;
-    let inv_w = 1.0 / qp.tile_width;
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct HeatmapDiffResponse {
+    pub diff: HeatmapDiffData,
+    /// Points read and trips counted over both windows
+    pub meta: MapMeta,
+}
 
-    for p in points {
-        // Compute indices; clamp to [0, rows-1] / [0, cols-1]
-        let mut r = ((p.lat - lat_min) * inv_h).floor() as isize;
-        let mut c = ((p.lng - lon_min) * inv_w).floor() as isize;
+#[utoipa::path(
+    get,
+    tag = "Heatmap",
+    params(
+    ("lat1" = f64, Query, description = "First latitude (corner). All four corners may be omitted when the deployment sets DEFAULT_BBOX"),
+    ("lng1" = f64, Query, description = "First longitude (corner)"),
+    ("lat2" = f64, Query, description = "Second latitude (opposite corner)"),
+    ("lng2" = f64, Query, description = "Second longitude (opposite corner)"),
+    ("beforeStart" = DateTime<chrono::Utc>, Query, description = "Start of the earlier window (inclusive)"),
+    ("beforeEnd" = DateTime<chrono::Utc>, Query, description = "End of the earlier window (inclusive)"),
+    ("afterStart" = DateTime<chrono::Utc>, Query, description = "Start of the later window (inclusive)"),
+    ("afterEnd" = DateTime<chrono::Utc>, Query, description = "End of the later window (inclusive)"),
+    ("tileWidth" = f64, Query, description = "Width of each tile in degrees. Required unless tileSizeMeters is given"),
+    ("tileHeight" = f64, Query, description = "Height of each tile in degrees. Required unless tileSizeMeters is given"),
+    ("tileSizeMeters" = f64, Query, description = "Side of each tile in meters. Replaces tileWidth/tileHeight"),
+    ("binning" = String, Query, description = "Cell shape: grid (default) or h3 for H3 hexagons, which take no tile size"),
+    ("resolution" = u8, Query, description = "H3 resolution (0..15) with binning=h3. Optional, defaults to 8"),
+    ("days" = String, Query, description = "Optional list of weekdays to include in both windows (1=Mon..7=Sun)"),
+    ("timeStart" = String, Query, description = "Optional time-of-day start in HH or HH:MM (inclusive), applied to both windows"),
+    ("timeEnd" = String, Query, description = "Optional time-of-day end in HH or HH:MM (exclusive)"),
+    ("timezone" = String, Query, description = "UTC offset the days and time-of-day filters are evaluated in, e.g. +05:00. Defaults to DEFAULT_TIMEZONE, else UTC"),
+    ),
+    responses(
+        (status = 200, description = "Per-tile trip counts of both windows, their difference and percent change", body = HeatmapDiffResponse),
+        (status = 400, description = "Invalid parameters; `details.param` names the offending one when known", body = ApiErrorBody),
+        (status = 413, description = "Grid over GRID_MAX_CELLS with GRID_OVERSIZE=reject; the message names the smallest tile size or the finest resolution that fits", body = ApiErrorBody),
+        (status = 500, description = "Server Vzorvalsya", body = ApiErrorBody),
+    )
+)]
 
-        if r < 0 { r = 0; }
-        if c < 0 { c = 0; }
-        if r as usize >= rows { r = rows as isize - 1; }
-        if c as usize >= cols { c = cols as isize - 1; }
+/// Heatmaps of two windows over the same grid and filters, compared tile by tile, e.g.
+/// before and after a road closure
+#[get("/diff")]
+pub async fn get_heatmap_diff(
+    store: web::Data<dyn PointStore>,
+    qp: web::Query<HeatmapDiffQueryParams>,
+) -> Result<HttpResponse, ApiError> {
+    let started = Instant::now();
+    validate::corners(qp.lat1, qp.lng1, qp.lat2, qp.lng2)?;
+    if qp.before_end < qp.before_start {
+        return Err(ApiError::bad_param("beforeEnd", "beforeEnd must not be before beforeStart"));
+    }
+    if qp.after_end < qp.after_start {
+        return Err(ApiError::bad_param("afterEnd", "afterEnd must not be before afterStart"));
+    }
+    let bbox = defaults().bbox(qp.lat1, qp.lng1, qp.lat2, qp.lng2).map_err(ApiError::bad_request)?;
+    let tz = defaults().timezone(qp.timezone.as_deref()).map_err(ApiError::bad_request)?;
+    let tiled = qp.tile_width.is_some() || qp.tile_height.is_some() || qp.tile_size_meters.is_some();
+    let binning = validate::binning(qp.binning.as_deref(), qp.resolution, tiled)?;
+    let tile = defaults().tile_size(&bbox, qp.tile_width, qp.tile_height, qp.tile_size_meters);
+    let time = TimeFilter::parse(qp.days.as_deref(), qp.time_start_tod.as_deref(), qp.time_end_tod.as_deref(), tz)?;
 
-        let idx = (r as usize) * cols + (c as usize);
-        counts[idx] += 1;
+    let (bins, size) = validate::fit_bins(bbox, binning, tile, qp.tile_size_meters)?;
+    if bins.is_empty() {
+        let resp = HeatmapDiffResponse {
+            diff: HeatmapDiffData { data: vec![], before_total: 0, after_total: 0, size },
+            meta: MapMeta::new(&bins, 0, 0, started),
+        };
+        return Ok(HttpResponse::Ok().json(resp));
     }
 
-    // Build response tiles (row-major from lat_min/lon_min increasing)
-    // Include tiles with count > 0 OR neighbor_count > 0
-    let mut data = Vec::new();
-    for r in 0..rows {
-        let tile_lat_min = lat_min + (r as f64) * qp.tile_height;
-        let tile_lat_max = (tile_lat_min + qp.tile_height).min(lat_max);
-        for c in 0..cols {
-            let tile_lon_min = lon_min + (c as f64) * qp.tile_width;
-            let tile_lon_max = (tile_lon_min + qp.tile_width).min(lon_max);
-
-            let count = counts[r * cols + c];
-            // Calculate neighbor count (8 surrounding cells)
-            let mut neighbor_count = 0;
-            for dr in -1..=1 {
-                for dc in -1..=1 {
-                    // Skip the center cell (the current tile itself)
-                    if dr == 0 && dc == 0 {
-                        continue;
-                    }
-
-                    let nr = r as isize + dr;
-                    let nc = c as isize + dc;
-
-                    // Check bounds
-                    if nr >= 0 && nr < rows as isize && nc >= 0 && nc < cols as isize {
-                        let neighbor_idx = (nr as usize) * cols + (nc as usize);
-                        neighbor_count += counts[neighbor_idx];
-                    }
-                }
-            }
+    let (before, rows_before) = origin_counts(store.get_ref(), &bins, Some(qp.before_start), Some(qp.before_end), &time).await?;
+    let (after, rows_after) = origin_counts(store.get_ref(), &bins, Some(qp.after_start), Some(qp.after_end), &time).await?;
 
-            // Include tiles with points or with non-zero neighbors
-            if count > 0 || neighbor_count > 0 {
-                data.push(HeatTile {
-                    count,
-                    neighbor_count,
-                    top_left: MapPoint { lat: tile_lat_min, lng: tile_lon_min },
-                    bottom_right: MapPoint { lat: tile_lat_max, lng: tile_lon_max },
-                });
-            }
+    let mut data = Vec::new();
+    for idx in bins.cells() {
+        let (b, a) = (before[idx], after[idx]);
+        if b == 0 && a == 0 {
+            continue;
         }
+        let delta = a as i64 - b as i64;
+        let cell = bins.cell_bbox(idx);
+        data.push(HeatDiffTile {
+            before: b,
+            after: a,
+            delta,
+            percent_change: (b > 0).then(|| delta as f64 * 100.0 / b as f64),
+            top_left: MapPoint { lat: cell.lat_min, lng: cell.lng_min },
+            bottom_right: MapPoint { lat: cell.lat_max, lng: cell.lng_max },
+            h3: bins.cell_id(idx),
+        });
     }
 
-    let resp = HeatmapResponse { heatmap: HeatmapData { data } };
+    let (before_total, after_total) = (before.iter().sum(), after.iter().sum());
+    let resp = HeatmapDiffResponse {
+        diff: HeatmapDiffData { data, before_total, after_total, size },
+        meta: MapMeta::new(&bins, rows_before + rows_after, before_total + after_total, started),
+    };
     info!(
-    "Heatmap response: tiles={} (non-zero only) from grid={}x{} points_count={} took={:?}",
-    resp.heatmap.data.len(), rows, cols, counts.iter().sum::<usize>(), started.elapsed()
+        "Heatmap diff response: tiles={} from grid={} trips before={} after={} took={:?}",
+        resp.diff.data.len(), bins, resp.diff.before_total, resp.diff.after_total, started.elapsed()
     );
-    HttpResponse::Ok().json(resp)
+    let stats = QueryStats { rows_scanned: rows_before + rows_after, tiles: resp.diff.data.len() };
+    Ok(stats.attach(HttpResponse::Ok().json(resp)))
 }
 
-pub fn init_routes(cfg: &mut web::ServiceConfig) {
-    cfg.service(
-        web::scope("/heatmap")
-            .service(get_heatmap)
-    );
+pub fn routes() -> ApiScope {
+    ApiScope::new("/heatmap")
+        .service(get_heatmap)
+        .service(get_heatmap_diff)
 }
 
 // --- Helpers ---
 
-fn parse_days_of_week(input: &str) -> Result<std::collections::HashSet<u8>, String> {
-    let mut set = std::collections::HashSet::new();
+/// Optional weekday and time-of-day selection, evaluated in `tz`
+pub(super) struct TimeFilter {
+    days: Option<HashSet<u8>>,
+    time_of_day: Option<(NaiveTime, NaiveTime)>,
+    tz: FixedOffset,
+}
+
+impl TimeFilter {
+    pub(super) fn parse(days: Option<&str>, time_start: Option<&str>, time_end: Option<&str>, tz: FixedOffset) -> Result<Self, ApiError> {
+        let days = match days {
+            Some(s) => match parse_days_of_week(s) {
+                Ok(set) => Some(set),
+                Err(e) => {
+                    warn!("Invalid days parameter '{}': {}", s, e);
+                    return Err(ApiError::bad_param("days", "days must contain numbers 1..7 separated by comma/space"));
+                }
+            },
+            None => None,
+        };
+        let time_of_day = match (time_start, time_end) {
+            (Some(a), Some(b)) => {
+                let a = parse_time_of_day(a).map_err(|_| ApiError::bad_param("timeStart", "timeStart must be HH or HH:MM"))?;
+                let b = parse_time_of_day(b).map_err(|_| ApiError::bad_param("timeEnd", "timeEnd must be HH or HH:MM"))?;
+                if b <= a {
+                    warn!("Invalid time-of-day window: start={:?} end={:?}", a, b);
+                    return Err(ApiError::bad_param("timeEnd", "timeEnd must be greater than timeStart (same-day window)"));
+                }
+                Some((a, b))
+            }
+            (None, None) => None,
+            _ => return Err(ApiError::bad_request("Both timeStart and timeEnd must be provided together")),
+        };
+        Ok(Self { days, time_of_day, tz })
+    }
+
+    /// Points without a timestamp only pass when nothing is filtered
+    fn matches(&self, timestamp: Option<DateTime<Utc>>) -> bool {
+        if self.days.is_none() && self.time_of_day.is_none() {
+            return true;
+        }
+        let Some(ts) = timestamp else { return false };
+        let local = ts.with_timezone(&self.tz);
+        if let Some(set) = &self.days {
+            // 1=Mon..7=Sun
+            let day_num: u8 = match local.weekday() {
+                Weekday::Mon => 1,
+                Weekday::Tue => 2,
+                Weekday::Wed => 3,
+                Weekday::Thu => 4,
+                Weekday::Fri => 5,
+                Weekday::Sat => 6,
+                Weekday::Sun => 7,
+            };
+            if !set.contains(&day_num) {
+                return false;
+            }
+        }
+        match self.time_of_day {
+            // [start, end)
+            Some((s, e)) => {
+                let t = local.time();
+                t >= s && t < e
+            }
+            None => true,
+        }
+    }
+}
+
+/// Trips per tile, each counted once at its first point in the range; that point must then
+/// pass the weekday/time-of-day filters. Also returns the number of rows read.
+pub(super) async fn origin_counts(
+    store: &dyn PointStore,
+    bins: &Bins,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    time: &TimeFilter,
+) -> Result<(Vec<usize>, usize), ApiError> {
+    let filter = PointFilter {
+        bbox: Some(bins.bbox()),
+        since,
+        until,
+        ..Default::default()
+    };
+    let all_points = match store.find(&filter, PointOrder::TimestampAsc, None).await {
+        Ok(p) => p,
+        Err(e) => {
+            error!("Heatmap query failed: {}", e);
+            return Err(ApiError::Internal);
+        }
+    };
+
+    let total_points_count = all_points.len();
+    let mut seen_trips = HashSet::new();
+    let mut counts = vec![0usize; bins.len()];
+    let mut kept = 0;
+    for point in all_points {
+        if seen_trips.insert(point.randomized_id) && time.matches(point.timestamp)
+            && let Some(idx) = bins.index_of(point.lat, point.lng)
+        {
+            counts[idx] += 1;
+            kept += 1;
+        }
+    }
+    debug!(
+        "Heatmap DB returned {} total points, filtered to {} first-per-trip and {} after weekday/time filters",
+        total_points_count,
+        seen_trips.len(),
+        kept
+    );
+    Ok((counts, total_points_count))
+}
+
+fn parse_days_of_week(input: &str) -> Result<HashSet<u8>, String> {
+    let mut set = HashSet::new();
     for token in input.split(|c: char| c == ',' || c.is_whitespace()) {
         let t = token.trim();
         if t.is_empty() { continue; }
@@ -0,0 +1,237 @@
+use actix_web::{get, web, HttpResponse};
+use chrono::DateTime;
+use image::{ImageFormat, Rgba, RgbaImage};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+use std::time::Instant;
+use utoipa::ToSchema;
+use crate::database::store::{BBox, PointStore};
+use crate::geo;
+use crate::telemetry::QueryStats;
+use super::defaults::defaults;
+use super::error::{ApiError, ApiErrorBody};
+use super::grid::Bins;
+use super::heatmap::{origin_counts, TimeFilter};
+use super::registry::ApiScope;
+use super::validate;
+
+const DEFAULT_WIDTH: u32 = 512;
+/// Longest side of a rendered image; every pixel is looked up in the bins
+const MAX_SIDE: u32 = 2048;
+const DEFAULT_ALPHA: f64 = 0.8;
+
+/// Color stops of a palette, from the lowest value to the highest
+type Stops = &'static [(f64, [u8; 3])];
+
+const HEAT: Stops = &[(0.0, [0, 0, 255]), (0.25, [0, 255, 255]), (0.5, [0, 255, 0]), (0.75, [255, 255, 0]), (1.0, [255, 0, 0])];
+const VIRIDIS: Stops = &[(0.0, [68, 1, 84]), (0.25, [59, 82, 139]), (0.5, [33, 145, 140]), (0.75, [94, 201, 98]), (1.0, [253, 231, 37])];
+const MAGMA: Stops = &[(0.0, [0, 0, 4]), (0.25, [81, 18, 124]), (0.5, [183, 55, 121]), (0.75, [252, 137, 97]), (1.0, [252, 253, 191])];
+const GREYS: Stops = &[(0.0, [224, 224, 224]), (1.0, [0, 0, 0])];
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct HeatmapPngQueryParams {
+    pub lat1: Option<f64>,
+    pub lng1: Option<f64>,
+    pub lat2: Option<f64>,
+    pub lng2: Option<f64>,
+    #[serde(rename = "dateStart")]
+    pub date_start: Option<DateTime<chrono::Utc>>,
+    #[serde(rename = "dateEnd")]
+    pub date_end: Option<DateTime<chrono::Utc>>,
+    #[serde(rename = "tileWidth")]
+    pub tile_width: Option<f64>,
+    #[serde(rename = "tileHeight")]
+    pub tile_height: Option<f64>,
+    #[serde(rename = "tileSizeMeters")]
+    pub tile_size_meters: Option<f64>,
+    pub binning: Option<String>,
+    pub resolution: Option<u8>,
+    pub days: Option<String>,
+    #[serde(rename = "timeStart")]
+    pub time_start_tod: Option<String>,
+    #[serde(rename = "timeEnd")]
+    pub time_end_tod: Option<String>,
+    pub timezone: Option<String>,
+    pub smoothing: Option<String>,
+    #[serde(rename = "smoothingRadius")]
+    pub smoothing_radius: Option<u32>,
+    /// Image size in pixels; the missing one follows the shape of the area
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// `heat` (default), `viridis`, `magma` or `greys`
+    pub palette: Option<String>,
+    /// Opacity of the painted tiles, 0..1 (default 0.8); empty tiles stay transparent
+    pub alpha: Option<f64>,
+    /// `linear` (default) or `log`
+    pub scale: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    tag = "Heatmap",
+    params(
+    ("lat1" = f64, Query, description = "First latitude (corner). All four corners may be omitted when the deployment sets DEFAULT_BBOX"),
+    ("lng1" = f64, Query, description = "First longitude (corner)"),
+    ("lat2" = f64, Query, description = "Second latitude (opposite corner)"),
+    ("lng2" = f64, Query, description = "Second longitude (opposite corner)"),
+    ("dateStart" = DateTime<chrono::Utc>, Query, description = "Start of the date/time range (inclusive). Optional"),
+    ("dateEnd" = DateTime<chrono::Utc>, Query, description = "End of the date/time range (inclusive). Optional"),
+    ("tileWidth" = f64, Query, description = "Width of each tile in degrees. Required unless tileSizeMeters is given"),
+    ("tileHeight" = f64, Query, description = "Height of each tile in degrees. Required unless tileSizeMeters is given"),
+    ("tileSizeMeters" = f64, Query, description = "Side of each tile in meters. Replaces tileWidth/tileHeight"),
+    ("binning" = String, Query, description = "Cell shape: grid (default) or h3 for H3 hexagons, which take no tile size"),
+    ("resolution" = u8, Query, description = "H3 resolution (0..15) with binning=h3. Optional, defaults to 8"),
+    ("days" = String, Query, description = "Optional list of weekdays to include (1=Mon..7=Sun). Comma or space separated"),
+    ("timeStart" = String, Query, description = "Optional time-of-day start in HH or HH:MM (inclusive)"),
+    ("timeEnd" = String, Query, description = "Optional time-of-day end in HH or HH:MM (exclusive)"),
+    ("timezone" = String, Query, description = "UTC offset the days and time-of-day filters are evaluated in, e.g. +05:00. Defaults to DEFAULT_TIMEZONE, else UTC"),
+    ("smoothing" = String, Query, description = "Paint the smoothed value of each tile instead of its count: box, gaussian or distance, as in GET /api/heatmap. Optional"),
+    ("smoothingRadius" = u32, Query, description = "Reach of the smoothing in cells (grid) or rings (h3), 1..5. Optional, defaults to 1"),
+    ("width" = u32, Query, description = "Image width in pixels, up to 2048. Optional; defaults to 512, or follows height and the shape of the area when only height is given"),
+    ("height" = u32, Query, description = "Image height in pixels, up to 2048. Optional; follows width and the shape of the area"),
+    ("palette" = String, Query, description = "heat (default, blue to red), viridis, magma or greys"),
+    ("alpha" = f64, Query, description = "Opacity of painted tiles, 0..1. Optional, defaults to 0.8; tiles without trips are transparent"),
+    ("scale" = String, Query, description = "linear (default) or log: how tile values map onto the palette, the busiest tile taking its last color"),
+    ),
+    responses(
+        (status = 200, description = "The heatmap as a PNG covering exactly the area, north up; X-Heatmap-Max carries the value painted in the last color of the palette", content_type = "image/png"),
+        (status = 400, description = "Invalid parameters; `details.param` names the offending one when known", body = ApiErrorBody),
+        (status = 413, description = "Grid over GRID_MAX_CELLS with GRID_OVERSIZE=reject", body = ApiErrorBody),
+        (status = 500, description = "Server Vzorvalsya", body = ApiErrorBody),
+    )
+)]
+
+/// The trip origins of `GET /api/heatmap` painted server-side, for reports and for clients
+/// that cannot draw thousands of tiles
+#[get("")]
+pub async fn get_heatmap_png(
+    store: web::Data<dyn PointStore>,
+    qp: web::Query<HeatmapPngQueryParams>,
+) -> Result<HttpResponse, ApiError> {
+    let started = Instant::now();
+    validate::area(qp.lat1, qp.lng1, qp.lat2, qp.lng2, qp.date_start, qp.date_end)?;
+    let bbox = defaults().bbox(qp.lat1, qp.lng1, qp.lat2, qp.lng2).map_err(ApiError::bad_request)?;
+    let (date_start, date_end) = defaults().date_range(qp.date_start, qp.date_end);
+    let tz = defaults().timezone(qp.timezone.as_deref()).map_err(ApiError::bad_request)?;
+    let tiled = qp.tile_width.is_some() || qp.tile_height.is_some() || qp.tile_size_meters.is_some();
+    let binning = validate::binning(qp.binning.as_deref(), qp.resolution, tiled)?;
+    let tile = defaults().tile_size(&bbox, qp.tile_width, qp.tile_height, qp.tile_size_meters);
+    let time = TimeFilter::parse(qp.days.as_deref(), qp.time_start_tod.as_deref(), qp.time_end_tod.as_deref(), tz)?;
+    let smoothing = validate::smoothing(qp.smoothing.as_deref(), qp.smoothing_radius)?;
+
+    let palette = match qp.palette.as_deref().map(str::trim) {
+        None | Some("") | Some("heat") => HEAT,
+        Some("viridis") => VIRIDIS,
+        Some("magma") => MAGMA,
+        Some("greys") => GREYS,
+        Some(other) => return Err(ApiError::bad_param("palette", format!("palette must be heat, viridis, magma or greys, got {:?}", other))),
+    };
+    let log_scale = match qp.scale.as_deref().map(str::trim) {
+        None | Some("") | Some("linear") => false,
+        Some("log") => true,
+        Some(_) => return Err(ApiError::bad_param("scale", "scale must be linear or log")),
+    };
+    let alpha = qp.alpha.unwrap_or(DEFAULT_ALPHA);
+    if !(0.0..=1.0).contains(&alpha) {
+        return Err(ApiError::bad_param("alpha", "alpha must be between 0 and 1"));
+    }
+    for (param, side) in [("width", qp.width), ("height", qp.height)] {
+        if side.is_some_and(|s| s == 0 || s > MAX_SIDE) {
+            return Err(ApiError::bad_param(param, format!("{} must be between 1 and {}", param, MAX_SIDE)));
+        }
+    }
+    let (width, height) = image_size(&bbox, qp.width, qp.height);
+
+    let (bins, _) = validate::fit_bins(bbox, binning, tile, qp.tile_size_meters)?;
+    let (values, rows_scanned) = if bins.is_empty() {
+        (Vec::new(), 0)
+    } else {
+        let (counts, rows_scanned) = origin_counts(store.get_ref(), &bins, date_start, date_end, &time).await?;
+        let values = match smoothing {
+            Some((kernel, radius)) => bins.smooth(&counts, kernel, radius),
+            None => counts.iter().map(|&c| c as f64).collect(),
+        };
+        (values, rows_scanned)
+    };
+    let max = values.iter().copied().fold(0.0, f64::max);
+
+    let img = render(&bins, &values, max, width, height, palette, alpha, log_scale);
+    let mut png = Vec::new();
+    if let Err(e) = img.write_to(&mut Cursor::new(&mut png), ImageFormat::Png) {
+        error!("Heatmap PNG encoding failed: {}", e);
+        return Err(ApiError::Internal);
+    }
+    info!("Heatmap PNG: {}x{} px from grid={} max={} bytes={} took={:?}", width, height, bins, max, png.len(), started.elapsed());
+    let stats = QueryStats { rows_scanned, tiles: values.iter().filter(|&&v| v > 0.0).count() };
+    Ok(stats.attach(
+        HttpResponse::Ok()
+            .content_type("image/png")
+            .insert_header(("X-Heatmap-Max", max.to_string()))
+            .body(png),
+    ))
+}
+
+pub fn routes() -> ApiScope {
+    ApiScope::new("/heatmap.png")
+        .service(get_heatmap_png)
+}
+
+// --- Helpers ---
+
+/// The requested size, the missing side taken from the shape of `bbox` in meters
+fn image_size(bbox: &BBox, width: Option<u32>, height: Option<u32>) -> (u32, u32) {
+    let mid_lat = ((bbox.lat_min + bbox.lat_max) / 2.0).to_radians();
+    let wide = bbox.lng_span() * mid_lat.cos().max(1e-6);
+    let tall = bbox.lat_max - bbox.lat_min;
+    let follow = |side: u32, ratio: f64| {
+        let other = (side as f64 * ratio).round();
+        if other.is_finite() { (other as u32).clamp(1, MAX_SIDE) } else { side }
+    };
+    match (width, height) {
+        (Some(w), Some(h)) => (w, h),
+        (None, Some(h)) => (follow(h, wide / tall), h),
+        (w, None) => {
+            let w = w.unwrap_or(DEFAULT_WIDTH);
+            (w, follow(w, tall / wide))
+        }
+    }
+}
+
+/// Paints every pixel with the value of the bin under its center
+#[allow(clippy::too_many_arguments)]
+fn render(bins: &Bins, values: &[f64], max: f64, width: u32, height: u32, palette: Stops, alpha: f64, log_scale: bool) -> RgbaImage {
+    let mut img = RgbaImage::new(width, height);
+    if bins.is_empty() || max <= 0.0 {
+        return img;
+    }
+    let bbox = bins.bbox();
+    let (lat_span, lng_span) = (bbox.lat_max - bbox.lat_min, bbox.lng_span());
+    let a = (alpha * 255.0).round() as u8;
+    for y in 0..height {
+        // Row 0 is the northern edge
+        let lat = bbox.lat_max - (y as f64 + 0.5) / height as f64 * lat_span;
+        for x in 0..width {
+            let lng = geo::wrap_lng(bbox.lng_min + (x as f64 + 0.5) / width as f64 * lng_span);
+            let Some(v) = bins.index_of(lat, lng).map(|idx| values[idx]).filter(|v| *v > 0.0) else {
+                continue;
+            };
+            let t = if log_scale { v.ln_1p() / max.ln_1p() } else { v / max };
+            let [r, g, b] = color(palette, t);
+            img.put_pixel(x, y, Rgba([r, g, b, a]));
+        }
+    }
+    img
+}
+
+/// Color at `t` (0..1) along the palette, interpolated between its stops
+fn color(palette: Stops, t: f64) -> [u8; 3] {
+    let t = t.clamp(0.0, 1.0);
+    let upper = palette.iter().position(|(at, _)| *at >= t).unwrap_or(palette.len() - 1);
+    if upper == 0 {
+        return palette[0].1;
+    }
+    let ((t0, c0), (t1, c1)) = (palette[upper - 1], palette[upper]);
+    let f = (t - t0) / (t1 - t0);
+    std::array::from_fn(|i| (c0[i] as f64 + (c1[i] as f64 - c0[i] as f64) * f).round() as u8)
+}
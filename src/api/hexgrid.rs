@@ -0,0 +1,125 @@
+use geo_types::{coord, Rect};
+use h3o::geom::{ContainmentMode, TilerBuilder};
+use h3o::{CellIndex, LatLng, Resolution};
+use std::collections::HashMap;
+use std::ops::AddAssign;
+
+use crate::database::store::BBox;
+use super::grid::Kernel;
+
+/// H3 cells covering a bbox at one resolution, sorted by cell index. Cells along the edges
+/// stick out of the bbox; points outside every cell (none, when the points were selected by
+/// the same bbox) are not binned.
+#[derive(Debug, Clone)]
+pub struct HexGrid {
+    pub bbox: BBox,
+    pub resolution: Resolution,
+    cells: Vec<CellIndex>,
+    index: HashMap<CellIndex, usize>,
+}
+
+impl HexGrid {
+    /// A zero-height or zero-width bbox gives an empty grid
+    pub fn new(bbox: BBox, resolution: Resolution) -> Self {
+        let mut cells = Vec::new();
+        if bbox.lat_max > bbox.lat_min && bbox.lng_span() > 0.0 {
+            // Each side of the antimeridian is tiled on its own, so no polygon needs the
+            // tiler's guess at which way a ring goes around the globe
+            let mut tiler = TilerBuilder::new(resolution)
+                .containment_mode(ContainmentMode::Covers)
+                .disable_transmeridian_heuristic()
+                .build();
+            for (lng_min, lng_max) in bbox.lng_ranges() {
+                let rect = Rect::new(coord! { x: lng_min, y: bbox.lat_min }, coord! { x: lng_max, y: bbox.lat_max });
+                // Bounds were validated by the handlers, so the rings are always valid
+                let _ = tiler.add(rect.to_polygon());
+            }
+            cells = tiler.into_coverage().collect();
+            cells.sort_unstable();
+            cells.dedup();
+        }
+        let index = cells.iter().enumerate().map(|(i, cell)| (*cell, i)).collect();
+        Self { bbox, resolution, cells, index }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+
+    /// Number of cells
+    pub fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    /// Index of the cell containing the point, if that cell is part of the grid
+    pub fn index_of(&self, lat: f64, lng: f64) -> Option<usize> {
+        let cell = LatLng::new(lat, lng).ok()?.to_cell(self.resolution);
+        self.index.get(&cell).copied()
+    }
+
+    /// H3 index of one cell in its usual hexadecimal form, e.g. `8843a13687fffff`
+    pub fn cell_id(&self, idx: usize) -> String {
+        self.cells[idx].to_string()
+    }
+
+    /// Bounds of one cell's boundary; a cell on the antimeridian comes back with
+    /// lng_min > lng_max like any wrap-around bbox
+    pub fn cell_bbox(&self, idx: usize) -> BBox {
+        let boundary = self.cells[idx].boundary();
+        let (mut lat_min, mut lat_max) = (f64::MAX, f64::MIN);
+        let (mut lng_min, mut lng_max) = (f64::MAX, f64::MIN);
+        // Easternmost of the western vertices and westernmost of the eastern ones, for the
+        // cells whose vertices lie on both sides of ±180
+        let (mut west_max, mut east_min) = (f64::MIN, f64::MAX);
+        for vertex in boundary.iter() {
+            let (lat, lng) = (vertex.lat(), vertex.lng());
+            lat_min = lat_min.min(lat);
+            lat_max = lat_max.max(lat);
+            lng_min = lng_min.min(lng);
+            lng_max = lng_max.max(lng);
+            if lng < 0.0 {
+                west_max = west_max.max(lng);
+            } else {
+                east_min = east_min.min(lng);
+            }
+        }
+        if lng_max - lng_min > 180.0 {
+            (lng_min, lng_max) = (east_min, west_max);
+        }
+        BBox { lat_min, lat_max, lng_min, lng_max }
+    }
+
+    /// Sum of `values` over the (up to) 6 cells around `idx`, 5 around a pentagon
+    pub fn neighbor_sum<T: Copy + Default + AddAssign>(&self, values: &[T], idx: usize) -> T {
+        let center = self.cells[idx];
+        let mut sum = T::default();
+        for cell in center.grid_disk::<Vec<_>>(1) {
+            if cell == center {
+                continue;
+            }
+            if let Some(&i) = self.index.get(&cell) {
+                sum += values[i];
+            }
+        }
+        sum
+    }
+
+    /// `values` averaged over the cells up to `radius` steps from each cell, weighted by
+    /// `kernel`; cells outside the grid are left out of the average
+    pub fn smooth(&self, values: &[usize], kernel: Kernel, radius: u32) -> Vec<f64> {
+        self.cells
+            .iter()
+            .map(|center| {
+                let (mut sum, mut weights) = (0.0, 0.0);
+                for (cell, distance) in center.grid_disk_distances::<Vec<_>>(radius) {
+                    if let Some(&i) = self.index.get(&cell) {
+                        let w = kernel.weight(distance as f64, radius);
+                        sum += w * values[i] as f64;
+                        weights += w;
+                    }
+                }
+                if weights > 0.0 { sum / weights } else { 0.0 }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,469 @@
+use actix_web::{post, web, HttpResponse};
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
+
+use crate::anomaly::ClassificationQueue;
+use crate::database::journal::Journal;
+use crate::database::store::{NewPointRecord, PointFilter, PointStore, Quarantine, StoreResult};
+use crate::geo;
+use crate::jobs::{Jobs, RunningJob};
+use crate::tenant;
+use super::jobs::{accepted, Job};
+use super::points::{screen_timestamps, Ingestion};
+
+/// Largest track file accepted by the import endpoints
+const IMPORT_MAX_BYTES: usize = 20 * 1024 * 1024;
+
+/// Kind of the jobs of background imports
+const IMPORT_JOB: &str = "import";
+
+/// One position from a track file; missing motion fields are derived before storing
+#[derive(Debug, Clone)]
+struct Fix {
+    lat: f64,
+    lng: f64,
+    alt: Option<f64>,
+    time: Option<DateTime<Utc>>,
+    spd: Option<f64>,
+    azm: Option<f64>,
+}
+
+/// Consecutive fixes of one trip
+type Segment = Vec<Fix>;
+
+/// Reads the track segments of one file format
+type TrackParser = fn(&[u8]) -> Result<Vec<Segment>, String>;
+
+impl Fix {
+    fn new(lat: f64, lng: f64) -> Self {
+        Self { lat, lng, alt: None, time: None, spd: None, azm: None }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct ImportedTrack {
+    /// Fresh randomized_id assigned to this segment
+    pub randomized_id: i64,
+    pub points: usize,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct ImportResponse {
+    /// One entry per track segment, in file order
+    pub tracks: Vec<ImportedTrack>,
+    /// Fixes held back for review because their timestamp is outside the acceptance window;
+    /// they are still counted in `tracks`
+    pub quarantined: usize,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct ImportQueryParams {
+    /// Store the file in a background job instead of within the request
+    #[serde(rename = "async")] pub background: Option<bool>,
+}
+
+/// The stores an import writes to, cloned into the job of a background import
+#[derive(Clone)]
+struct Sinks {
+    store: web::Data<dyn PointStore>,
+    queue: web::Data<ClassificationQueue>,
+    journal: Option<Arc<Journal>>,
+    quarantine: web::Data<Quarantine>,
+}
+
+/// Why a file was not stored
+enum ImportError {
+    /// The file is at fault; told to the client
+    Rejected(String),
+    /// Logged, and only reported as a server error
+    Internal(String),
+}
+
+#[utoipa::path(
+    post,
+    tag = "Points",
+    params(
+        ("async" = bool, Query, description = "Answer 202 at once and store the file in a background job, polled at the /api/jobs/{id} of the Location header; its result is the 200 body. Default false"),
+    ),
+    request_body(content = String, content_type = "application/gpx+xml", description = "GPX 1.0/1.1 file; every trkseg becomes one trip"),
+    responses(
+        (status = 200, description = "Stored track segments", body = ImportResponse),
+        (status = 202, description = "With async=true: import job started; poll the URL in the Location header", body = Job),
+        (status = 400, description = "Unreadable GPX, no track points, or a timestamp outside the acceptance window with POINTS_TIMESTAMP_ACTION=reject"),
+        (status = 413, description = "File larger than 20 MiB"),
+        (status = 429, description = "Too many requests from this API key or address; see Retry-After"),
+        (status = 500, description = "Server Vzorvalsya")
+    )
+)]
+
+#[post("/import/gpx")]
+pub async fn import_gpx(
+    store: web::Data<dyn PointStore>,
+    queue: web::Data<ClassificationQueue>,
+    ingestion: web::Data<Ingestion>,
+    quarantine: web::Data<Quarantine>,
+    jobs: web::Data<Jobs>,
+    query: web::Query<ImportQueryParams>,
+    payload: web::Payload,
+) -> HttpResponse {
+    let sinks = Sinks { store, queue, journal: ingestion.journal.clone(), quarantine };
+    import(sinks, &jobs, query.background.unwrap_or(false), payload, "GPX", parse_gpx).await
+}
+
+#[utoipa::path(
+    post,
+    tag = "Points",
+    params(
+        ("async" = bool, Query, description = "Answer 202 at once and store the file in a background job, polled at the /api/jobs/{id} of the Location header; its result is the 200 body. Default false"),
+    ),
+    request_body(content = String, content_type = "application/vnd.google-earth.kml+xml", description = "KML file; every LineString and gx:Track becomes one trip"),
+    responses(
+        (status = 200, description = "Stored track segments", body = ImportResponse),
+        (status = 202, description = "With async=true: import job started; poll the URL in the Location header", body = Job),
+        (status = 400, description = "Unreadable KML, no track points, or a timestamp outside the acceptance window with POINTS_TIMESTAMP_ACTION=reject"),
+        (status = 413, description = "File larger than 20 MiB"),
+        (status = 429, description = "Too many requests from this API key or address; see Retry-After"),
+        (status = 500, description = "Server Vzorvalsya")
+    )
+)]
+
+#[post("/import/kml")]
+pub async fn import_kml(
+    store: web::Data<dyn PointStore>,
+    queue: web::Data<ClassificationQueue>,
+    ingestion: web::Data<Ingestion>,
+    quarantine: web::Data<Quarantine>,
+    jobs: web::Data<Jobs>,
+    query: web::Query<ImportQueryParams>,
+    payload: web::Payload,
+) -> HttpResponse {
+    let sinks = Sinks { store, queue, journal: ingestion.journal.clone(), quarantine };
+    import(sinks, &jobs, query.background.unwrap_or(false), payload, "KML", parse_kml).await
+}
+
+// --- Helpers ---
+
+/// Reads the file within the request, then stores it there or in a job
+async fn import(sinks: Sinks, jobs: &Jobs, background: bool, payload: web::Payload, kind: &'static str, parse: TrackParser) -> HttpResponse {
+    let bytes = match payload.to_bytes_limited(IMPORT_MAX_BYTES).await {
+        Ok(Ok(b)) => b,
+        Ok(Err(_)) => return HttpResponse::PayloadTooLarge().body(format!("{} file exceeds {} bytes", kind, IMPORT_MAX_BYTES)),
+        Err(e) => {
+            warn!("{} import body read failed: {}", kind, e);
+            return HttpResponse::BadRequest().body("Could not read request body");
+        }
+    };
+
+    if background {
+        let params = json!({"format": kind.to_ascii_lowercase(), "bytes": bytes.len()});
+        let work = move |job: RunningJob| async move {
+            match store_file(&sinks, &bytes, kind, parse, Some(&job)).await {
+                Ok(response) => serde_json::to_value(response).map_err(|e| e.to_string()),
+                Err(ImportError::Rejected(message)) => Err(message),
+                Err(ImportError::Internal(message)) => Err(message),
+            }
+        };
+        return match jobs.start(IMPORT_JOB, params, work).await {
+            Ok(job) => {
+                info!("{} import {} queued ({} bytes)", kind, job.id, job.params["bytes"]);
+                accepted(job)
+            }
+            Err(e) => {
+                error!("Starting a {} import failed: {}", kind, e);
+                HttpResponse::InternalServerError().finish()
+            }
+        };
+    }
+
+    match store_file(&sinks, &bytes, kind, parse, None).await {
+        Ok(response) => HttpResponse::Ok().json(response),
+        Err(ImportError::Rejected(message)) => HttpResponse::BadRequest().body(message),
+        Err(ImportError::Internal(message)) => {
+            error!("{}", message);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Parses the file and stores its segments as fresh trips; `job` counts the stored points
+async fn store_file(sinks: &Sinks, bytes: &[u8], kind: &str, parse: TrackParser, job: Option<&RunningJob>) -> Result<ImportResponse, ImportError> {
+    let started = Instant::now();
+    let store = sinks.store.get_ref();
+    let mut segments = parse(bytes).map_err(|e| {
+        warn!("Rejected {} import: {}", kind, e);
+        ImportError::Rejected(format!("Invalid {}: {}", kind, e))
+    })?;
+    segments.retain(|s| !s.is_empty());
+    if segments.is_empty() {
+        return Err(ImportError::Rejected("No track points found".to_string()));
+    }
+
+    let mut tracks = Vec::with_capacity(segments.len());
+    let mut records = Vec::new();
+    for mut segment in segments {
+        fill_motion(&mut segment);
+        let randomized_id = fresh_randomized_id(store)
+            .await
+            .map_err(|e| ImportError::Internal(format!("Trip id lookup failed during {} import: {}", kind, e)))?;
+        for fix in &segment {
+            records.push(NewPointRecord {
+                randomized_id,
+                lat: fix.lat,
+                lng: fix.lng,
+                alt: fix.alt.unwrap_or(0.0),
+                spd: fix.spd.unwrap_or(0.0),
+                azm: fix.azm.unwrap_or(0.0),
+                timestamp: fix.time,
+                anomaly: None,
+                client_uuid: None,
+                device_id: None,
+                tenant_id: tenant::current_id(),
+            });
+        }
+        tracks.push(ImportedTrack { randomized_id, points: segment.len() });
+    }
+
+    let (records, quarantined) = match screen_timestamps(&sinks.quarantine, records).await {
+        Ok(Ok((records, quarantined))) => (records, quarantined.len()),
+        Ok(Err(rejection)) => return Err(ImportError::Rejected(rejection)),
+        Err(e) => return Err(ImportError::Internal(format!("Could not quarantine fixes during {} import: {}", kind, e))),
+    };
+
+    // Persist the whole file before the first insert
+    let mut entry = match &sinks.journal {
+        Some(journal) => Some(
+            journal
+                .begin(&records)
+                .await
+                .map_err(|e| ImportError::Internal(format!("Could not journal {} import of {} points: {}", kind, records.len(), e)))?,
+        ),
+        None => None,
+    };
+    for record in records {
+        let randomized_id = record.randomized_id;
+        if let Err(e) = sinks.queue.ingest(store, record).await {
+            if let Some(entry) = entry {
+                entry.finish().await;
+            }
+            return Err(ImportError::Internal(format!("Insert failed during {} import for rid {}: {}", kind, randomized_id, e)));
+        }
+        if let Some(entry) = entry.as_mut() {
+            entry.mark_done().await;
+        }
+        if let Some(job) = job {
+            job.advance(1);
+        }
+    }
+    if let Some(entry) = entry {
+        entry.finish().await;
+    }
+
+    info!(
+        "Imported {} {} segments ({} points) in {:?}",
+        tracks.len(), kind, tracks.iter().map(|t| t.points).sum::<usize>(), started.elapsed()
+    );
+    Ok(ImportResponse { tracks, quarantined })
+}
+
+/// Positive random id not used by any stored trip yet
+async fn fresh_randomized_id(store: &dyn PointStore) -> StoreResult<i64> {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    loop {
+        let mut hasher = RandomState::new().build_hasher();
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos().hash(&mut hasher);
+        COUNTER.fetch_add(1, Ordering::Relaxed).hash(&mut hasher);
+        let id = (hasher.finish() >> 1) as i64;
+        if id == 0 {
+            continue;
+        }
+        let filter = PointFilter { randomized_id: Some(id), ..Default::default() };
+        if store.find(&filter, Default::default(), Some(1)).await?.is_empty() {
+            return Ok(id);
+        }
+    }
+}
+
+/// Derives heading from the neighbouring fix and speed (m/s) from distance over elapsed time,
+/// for the fixes whose file did not carry them
+fn fill_motion(segment: &mut [Fix]) {
+    let n = segment.len();
+    if n < 2 {
+        return;
+    }
+    for i in 0..n {
+        // Pair with the next fix, or the previous one for the last fix
+        let (a, b) = if i + 1 < n { (i, i + 1) } else { (i - 1, i) };
+        let (from, to) = (&segment[a], &segment[b]);
+        let azm = geo::bearing_deg(from.lat, from.lng, to.lat, to.lng);
+        let spd = match (from.time, to.time) {
+            (Some(t1), Some(t2)) if t2 > t1 => {
+                let secs = (t2 - t1).num_milliseconds() as f64 / 1000.0;
+                geo::haversine_m(from.lat, from.lng, to.lat, to.lng) / secs
+            }
+            _ => 0.0,
+        };
+        let fix = &mut segment[i];
+        fix.azm.get_or_insert(azm);
+        fix.spd.get_or_insert(spd);
+    }
+}
+
+fn attr_f64(e: &BytesStart, name: &str) -> Result<f64, String> {
+    let attr = e
+        .try_get_attribute(name)
+        .map_err(|err| err.to_string())?
+        .ok_or_else(|| format!("missing {} attribute", name))?;
+    let value = attr.unescape_value().map_err(|err| err.to_string())?;
+    value.trim().parse().map_err(|_| format!("bad {} '{}'", name, value))
+}
+
+fn parse_time(s: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(s.trim()).ok().map(|t| t.with_timezone(&Utc))
+}
+
+/// Track segments of a GPX file. Speed and course are read from GPX 1.0 elements or
+/// same-named extensions (e.g. Garmin TrackPointExtension).
+fn parse_gpx(data: &[u8]) -> Result<Vec<Segment>, String> {
+    let mut reader = Reader::from_reader(data);
+    reader.config_mut().trim_text(true);
+    let mut buf = Vec::new();
+    let mut segments = Vec::new();
+    let mut segment: Option<Segment> = None;
+    let mut fix: Option<Fix> = None;
+    let mut tag: Vec<u8> = Vec::new();
+
+    loop {
+        let event = reader.read_event_into(&mut buf).map_err(|e| format!("at byte {}: {}", reader.buffer_position(), e))?;
+        match event {
+            Event::Start(e) => {
+                match e.local_name().as_ref() {
+                    b"trkseg" => segment = Some(Vec::new()),
+                    b"trkpt" => fix = Some(Fix::new(attr_f64(&e, "lat")?, attr_f64(&e, "lon")?)),
+                    _ => {}
+                }
+                tag = e.local_name().as_ref().to_vec();
+            }
+            Event::Empty(e) => {
+                if e.local_name().as_ref() == b"trkpt"
+                    && let Some(seg) = segment.as_mut()
+                {
+                    seg.push(Fix::new(attr_f64(&e, "lat")?, attr_f64(&e, "lon")?));
+                }
+            }
+            Event::Text(t) => {
+                if let Some(f) = fix.as_mut() {
+                    let text = t.unescape().map_err(|e| e.to_string())?;
+                    match tag.as_slice() {
+                        b"ele" => f.alt = text.trim().parse().ok(),
+                        b"time" => f.time = parse_time(&text),
+                        b"speed" => f.spd = text.trim().parse().ok(),
+                        b"course" => f.azm = text.trim().parse().ok(),
+                        _ => {}
+                    }
+                }
+            }
+            Event::End(e) => {
+                match e.local_name().as_ref() {
+                    b"trkpt" => {
+                        if let (Some(f), Some(seg)) = (fix.take(), segment.as_mut()) {
+                            seg.push(f);
+                        }
+                    }
+                    b"trkseg" => segments.extend(segment.take()),
+                    _ => {}
+                }
+                tag.clear();
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(segments)
+}
+
+/// Track segments of a KML file: `LineString` coordinates (no timestamps) and
+/// `gx:Track` when/coord pairs
+fn parse_kml(data: &[u8]) -> Result<Vec<Segment>, String> {
+    let mut reader = Reader::from_reader(data);
+    reader.config_mut().trim_text(true);
+    let mut buf = Vec::new();
+    let mut segments = Vec::new();
+    let mut line: Option<Segment> = None;
+    // gx:Track lists all <when> elements, then all <gx:coord> elements
+    let mut track: Option<(Vec<Option<DateTime<Utc>>>, Segment)> = None;
+    let mut tag: Vec<u8> = Vec::new();
+
+    loop {
+        let event = reader.read_event_into(&mut buf).map_err(|e| format!("at byte {}: {}", reader.buffer_position(), e))?;
+        let text = match event {
+            Event::Start(e) => {
+                match e.local_name().as_ref() {
+                    b"LineString" => line = Some(Vec::new()),
+                    b"Track" => track = Some((Vec::new(), Vec::new())),
+                    _ => {}
+                }
+                tag = e.local_name().as_ref().to_vec();
+                None
+            }
+            Event::Text(t) => Some(t.unescape().map_err(|e| e.to_string())?.into_owned()),
+            Event::CData(c) => Some(String::from_utf8_lossy(&c).into_owned()),
+            Event::End(e) => {
+                match e.local_name().as_ref() {
+                    b"LineString" => segments.extend(line.take()),
+                    b"Track" => {
+                        if let Some((whens, mut fixes)) = track.take() {
+                            for (f, when) in fixes.iter_mut().zip(whens) {
+                                f.time = when;
+                            }
+                            segments.push(fixes);
+                        }
+                    }
+                    _ => {}
+                }
+                tag.clear();
+                None
+            }
+            Event::Eof => break,
+            _ => None,
+        };
+
+        if let Some(text) = text {
+            match (tag.as_slice(), line.as_mut(), track.as_mut()) {
+                (b"coordinates", Some(fixes), _) => {
+                    for tuple in text.split_whitespace() {
+                        fixes.push(parse_kml_coord(&tuple.split(',').collect::<Vec<_>>())?);
+                    }
+                }
+                (b"when", _, Some((whens, _))) => whens.push(parse_time(&text)),
+                (b"coord", _, Some((_, fixes))) => {
+                    fixes.push(parse_kml_coord(&text.split_whitespace().collect::<Vec<_>>())?);
+                }
+                _ => {}
+            }
+        }
+        buf.clear();
+    }
+    Ok(segments)
+}
+
+/// `lng, lat[, alt]` as already split KML tuple parts
+fn parse_kml_coord(parts: &[&str]) -> Result<Fix, String> {
+    let num = |i: usize| parts.get(i).and_then(|p| p.trim().parse::<f64>().ok());
+    let (Some(lng), Some(lat)) = (num(0), num(1)) else {
+        return Err(format!("bad coordinate '{}'", parts.join(",")));
+    };
+    let mut fix = Fix::new(lat, lng);
+    fix.alt = num(2);
+    Ok(fix)
+}
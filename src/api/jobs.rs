@@ -0,0 +1,102 @@
+use actix_web::{get, http::header, web, HttpResponse};
+use chrono::{DateTime, Utc};
+use log::error;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::database::model::jobs::Model as JobModel;
+use crate::jobs::Jobs;
+use crate::tenant;
+use super::error::{ApiError, ApiErrorBody};
+use super::registry::ApiScope;
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct Job {
+    pub id: Uuid,
+    /// export, import, report or rollup
+    pub kind: String,
+    /// queued, running, done or failed
+    pub status: String,
+    /// Units of work done so far (rows for exports, points for imports), updated every 30 s
+    pub progress: i64,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<Utc>,
+    #[serde(rename = "updatedAt")]
+    pub updated_at: DateTime<Utc>,
+    #[serde(rename = "startedAt")]
+    pub started_at: Option<DateTime<Utc>>,
+    #[serde(rename = "finishedAt")]
+    pub finished_at: Option<DateTime<Utc>>,
+    /// What the job was started with
+    #[schema(value_type = Object)]
+    pub params: serde_json::Value,
+    /// What the job produced, once done; the body the synchronous endpoint would have answered
+    /// with where there is one
+    #[schema(value_type = Option<Object>)]
+    pub result: Option<serde_json::Value>,
+    pub error: Option<String>,
+}
+
+impl From<JobModel> for Job {
+    fn from(job: JobModel) -> Self {
+        Self {
+            id: job.id,
+            kind: job.kind,
+            status: job.status,
+            progress: job.progress,
+            created_at: job.created_at,
+            updated_at: job.updated_at,
+            started_at: job.started_at,
+            finished_at: job.finished_at,
+            params: job.params,
+            result: job.result,
+            error: job.error,
+        }
+    }
+}
+
+/// Whether the current request may see `job`; another tenant's jobs do not exist for it
+pub(super) fn visible(job: &JobModel) -> bool {
+    tenant::current().is_none_or(|scope| job.tenant_id.as_deref() == scope.tenant_id())
+}
+
+/// 202 for a started job, pointing at its status
+pub(super) fn accepted(job: JobModel) -> HttpResponse {
+    HttpResponse::Accepted()
+        .insert_header((header::LOCATION, format!("/api/jobs/{}", job.id)))
+        .json(Job::from(job))
+}
+
+#[utoipa::path(
+    get,
+    tag = "Jobs",
+    params(
+        ("id" = Uuid, Path, description = "Job id from the Location header of the request that started it"),
+    ),
+    responses(
+        (status = 200, description = "Status of the job, with its result once done", body = Job),
+        (status = 404, description = "No such job", body = ApiErrorBody),
+        (status = 500, description = "Server Vzorvalsya", body = ApiErrorBody)
+    )
+)]
+
+/// Progress and outcome of a background job: an export, an import started with `async=true`,
+/// or a report or rollup refresh started from `/api/admin`
+#[get("/{id}")]
+pub async fn get_job(jobs: web::Data<Jobs>, path: web::Path<Uuid>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let job = jobs.find(id).await.map_err(|e| {
+        error!("Reading job {} failed: {}", id, e);
+        ApiError::Internal
+    })?;
+    match job {
+        Some(job) if visible(&job) => Ok(HttpResponse::Ok().json(Job::from(job))),
+        _ => Err(ApiError::NotFound(format!("No job {}", id))),
+    }
+}
+
+pub fn routes() -> ApiScope {
+    ApiScope::new("/jobs")
+        .service(get_job)
+}
@@ -0,0 +1,83 @@
+//! Map responses whose tile array is serialized while the client reads it, instead of into one
+//! buffer first. The Compress middleware compresses the chunks as they come, so memory stays
+//! bounded by a chunk and the first bytes go out before the last tile is built.
+
+use actix_web::web::Bytes;
+use actix_web::HttpResponse;
+use futures_util::stream;
+use log::error;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use serde_json::Value;
+use std::env;
+
+use super::error::ApiError;
+
+/// Tiles serialized per chunk of the body are added until it reaches this size
+const CHUNK_BYTES: usize = 64 * 1024;
+/// Stands in for the array while the rest of the response is serialized
+const PLACEHOLDER: &str = "\u{1}tiles\u{1}";
+
+/// Grids with more cells than JSON_STREAM_MIN_CELLS (default 100000, 0 streams every map)
+/// are streamed; smaller responses keep a Content-Length and stay eligible for the stale cache.
+static STREAM_MIN_CELLS: Lazy<usize> =
+    Lazy::new(|| env::var("JSON_STREAM_MIN_CELLS").ok().and_then(|v| v.parse().ok()).unwrap_or(100_000));
+
+pub fn should_stream(cells: usize) -> bool {
+    cells > *STREAM_MIN_CELLS
+}
+
+/// Answers with `envelope`, the array at `pointer` (a JSON pointer such as `/heatmap/data`)
+/// being filled from `items` as the body is sent
+pub fn with_array<T, I>(envelope: &impl Serialize, pointer: &str, items: I) -> Result<HttpResponse, ApiError>
+where
+    T: Serialize + 'static,
+    I: Iterator<Item = T> + 'static,
+{
+    let mut value = serde_json::to_value(envelope).map_err(|e| {
+        error!("Response serialization failed: {}", e);
+        ApiError::Internal
+    })?;
+    let Some(slot) = value.pointer_mut(pointer) else {
+        error!("Streamed response has no array at {}", pointer);
+        return Err(ApiError::Internal);
+    };
+    *slot = Value::String(PLACEHOLDER.to_string());
+    let text = value.to_string();
+    let placeholder = Value::String(PLACEHOLDER.to_string()).to_string();
+    let Some((head, tail)) = text.split_once(&placeholder) else {
+        error!("Streamed response lost its placeholder at {}", pointer);
+        return Err(ApiError::Internal);
+    };
+    let head = Bytes::from(format!("{}[", head));
+    let tail = Bytes::from(format!("]{}", tail));
+
+    let chunks = Chunks { items, first: true };
+    let body = stream::iter(std::iter::once(Ok(head)).chain(chunks).chain(std::iter::once(Ok(tail))));
+    Ok(HttpResponse::Ok().content_type("application/json").streaming(body))
+}
+
+/// Comma-separated items, CHUNK_BYTES at a time
+struct Chunks<I> {
+    items: I,
+    first: bool,
+}
+
+impl<T: Serialize, I: Iterator<Item = T>> Iterator for Chunks<I> {
+    type Item = Result<Bytes, serde_json::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut buf = Vec::with_capacity(CHUNK_BYTES + 1024);
+        while buf.len() < CHUNK_BYTES {
+            let Some(item) = self.items.next() else { break };
+            if !std::mem::take(&mut self.first) {
+                buf.push(b',');
+            }
+            if let Err(e) = serde_json::to_writer(&mut buf, &item) {
+                error!("Streamed response aborted: {}", e);
+                return Some(Err(e));
+            }
+        }
+        (!buf.is_empty()).then(|| Ok(Bytes::from(buf)))
+    }
+}
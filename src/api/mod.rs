@@ -1,6 +1,89 @@
 pub mod points;
 pub mod heatmap;
+pub mod heatmap_png;
 pub mod traficmap;
 pub mod velocitymap;
 pub mod zaglushka;
-pub mod anomalies;
\ No newline at end of file
+pub mod anomalies;
+pub mod timeseries;
+pub mod forecast;
+pub mod tiles;
+pub mod uploads;
+pub mod anomalymap;
+pub mod grid;
+pub mod hexgrid;
+pub mod defaults;
+pub mod sample;
+pub mod json_stream;
+pub mod sse;
+pub mod fields;
+pub mod trips;
+pub mod devices;
+pub mod import;
+pub mod stats;
+pub mod tile_metrics;
+pub mod tile_rollups;
+pub mod tile_timeseries;
+pub mod stops;
+pub mod reports;
+pub mod exports;
+pub mod jobs;
+pub mod geofences;
+pub mod alerts;
+pub mod replay;
+pub mod geo;
+pub mod client;
+pub mod admin;
+pub mod version;
+pub mod error;
+pub mod validate;
+pub mod registry;
+
+use actix_web::web;
+use utoipa::openapi::OpenApi;
+use registry::ApiScope;
+
+/// Every `/api` module. Routes and the OpenAPI document are both built from this list,
+/// so a handler cannot be mounted without being documented.
+fn scopes() -> Vec<ApiScope> {
+    vec![
+        points::routes(),
+        heatmap::routes(),
+        heatmap_png::routes(),
+        traficmap::routes(),
+        velocitymap::routes(),
+        zaglushka::routes(),
+        anomalies::routes(),
+        timeseries::routes(),
+        forecast::routes(),
+        tiles::routes(),
+        uploads::routes(),
+        anomalymap::routes(),
+        trips::routes(),
+        devices::routes(),
+        stats::routes(),
+        tile_metrics::routes(),
+        tile_timeseries::routes(),
+        stops::routes(),
+        reports::routes(),
+        exports::routes(),
+        jobs::routes(),
+        geofences::routes(),
+        alerts::routes(),
+        replay::routes(),
+        geo::routes(),
+        client::routes(),
+        admin::routes(),
+        version::routes(),
+    ]
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.app_data(web::QueryConfig::default().error_handler(error::query_error))
+        .app_data(web::JsonConfig::default().error_handler(error::json_error));
+    registry::configure(cfg, scopes());
+}
+
+pub fn openapi() -> OpenApi {
+    registry::openapi(scopes())
+}
@@ -1,28 +1,45 @@
-use actix_web::{post, web, HttpResponse};
-use sea_orm::{DatabaseConnection, Set, EntityTrait, ColumnTrait, QueryOrder, QueryFilter, ActiveModelTrait};
+use actix_web::{delete, get, http::header, post, web, HttpRequest, HttpResponse};
+use actix_web::web::Bytes;
+use futures_util::{future, stream, Stream, StreamExt, TryStreamExt};
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
-use log::{info, warn, error};
-use std::time::Instant;
+use log::{debug, info, error, warn};
+use std::fmt::Write;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use chrono::{DateTime, Utc};
-use std::env;
+use uuid::Uuid;
 
-use crate::database::model::points::{Entity as Points, Column as PointsColumn, Model as PointModel, ActiveModel as PointActiveModel};
+use crate::anomaly::ClassificationQueue;
+use crate::database::model::points::Model as PointModel;
+use crate::database::batch::{BatchError, Decision, IngestBatcher};
+use crate::database::journal::{Journal, JournalEntry};
+use crate::database::wal::Wal;
+use crate::database::store::{BBox, NewPointRecord, PointFilter, PointOrder, PointStore, Quarantine, StoreError, StoreResult, TileStatsStore, WindowAction};
+use crate::database::tile_rollup;
+use crate::telemetry::QueryStats;
+use crate::exports::parquet::{ParquetRow, ParquetWriter, ROW_GROUP};
+use crate::tenant;
+use super::admin::AdminConfig;
+use super::error::{ApiError, ApiErrorBody};
+use super::validate;
+use super::registry::ApiScope;
+use super::sample;
+use super::import;
 
-#[derive(Debug, Serialize, Deserialize)]
-struct WebhookPoint {
-    lat: f64,
-    lng: f64,
-    azm: f64,
-    timestamp: DateTime<Utc>,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct WebhookPayload {
-    first: WebhookPoint,
-    second: WebhookPoint,
-    gone: Vec<WebhookPoint>,
-}
+/// Default and maximum page size for `GET /api/points`
+const DEFAULT_LIMIT: u64 = 100;
+const MAX_LIMIT: u64 = 1000;
+/// Rows fetched per round trip by `GET /api/points/export` and `indrive export`
+pub const EXPORT_BATCH: u64 = 1000;
+/// Rows per zstd frame of `GET /api/points/dump`; a cut-off download loses at most one frame
+const DUMP_BATCH: u64 = 5000;
+const DUMP_ZSTD_LEVEL: i32 = 3;
+/// Longest `device_id` a point may name
+pub const MAX_DEVICE_ID_LEN: usize = 64;
+/// How long `ack=classified` waits for the anomaly decisions of a batch
+const CLASSIFY_ACK_TIMEOUT: Duration = Duration::from_secs(30);
 
 #[derive(Debug, Deserialize, Serialize, ToSchema)]
 pub struct NewPoint {
@@ -35,6 +52,10 @@ pub struct NewPoint {
     pub azm: f64,
     /// Optional timestamp in RFC3339/ISO8601 with timezone, e.g. "2025-09-14T12:34:56+06:00"
     pub timestamp: Option<DateTime<Utc>>,
+    /// Optional client-generated UUID. Resending a point with a known UUID does not insert it again.
+    pub uuid: Option<Uuid>,
+    /// Optional id of the tracker (up to 64 characters); the trip is counted for it in `/api/devices`
+    pub device_id: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize, ToSchema)]
@@ -42,144 +63,1167 @@ pub struct PointListRequest {
     pub points: Vec<NewPoint>,
 }
 
+/// Server-side identity of one inserted point
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct InsertedPoint {
+    pub id: i64,
+    /// As stored: the client timestamp, or the insertion time when none was sent
+    pub timestamp: Option<DateTime<Utc>>,
+    /// Echo of the client UUID, if one was sent
+    pub uuid: Option<Uuid>,
+    /// true when the UUID was already stored and nothing was inserted
+    pub duplicate: bool,
+    /// Anomaly decision, only with `ack=classified`; null when the point got none
+    /// (first point of a trip, classifier failure or timeout)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anomaly: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct PushPointsResponse {
+    /// Same order as the request's `points`, without the quarantined ones
+    pub points: Vec<InsertedPoint>,
+    /// Trailing points that went to the local buffer because the database is unreachable,
+    /// or every point with `ack=received`. They are stored later and have no ids yet;
+    /// the status is 202 when non-zero.
+    pub buffered: usize,
+    /// Points held back for review because their timestamp is outside the acceptance window
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub quarantined: Vec<QuarantinedPoint>,
+}
+
+/// A point that went to `quarantined_points` instead of `points`
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct QuarantinedPoint {
+    /// Position in the request's `points`
+    pub index: usize,
+    /// Id in the quarantine, see `GET /api/admin/quarantine`
+    pub id: i64,
+    pub reason: String,
+}
+
+/// When `POST /api/points` answers
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Ack {
+    /// As soon as the batch is in the WAL (or handed to a background task when the WAL is off)
+    Received,
+    /// After the points are committed to the database
+    #[default]
+    Persisted,
+    /// After the anomaly worker has decided on every point
+    Classified,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct PushPointsParams {
+    pub ack: Option<Ack>,
+}
+
 #[utoipa::path(
     post,
-    path = "/api/points",
     tag = "Points",
-    
+    params(
+        ("ack" = inline(Ack), Query, description = "received: answer once the batch is buffered, before it is stored (202); persisted: after the database commit (default); classified: also wait up to 30 s for the anomaly decisions"),
+    ),
+    request_body = PointListRequest,
     responses(
-        (status = 200, description = "List of points", body = PointListRequest),
-        (status = 500, description = "Incorrect point list format")
+        (status = 200, description = "Ids and timestamps of the inserted points, in request order", body = PushPointsResponse),
+        (status = 202, description = "ack=received, or the database is unreachable; the points not listed were buffered locally", body = PushPointsResponse),
+        (status = 400, description = "Empty points list, unknown ack, ack=received with neither a WAL nor a journal, or a timestamp outside the acceptance window with POINTS_TIMESTAMP_ACTION=reject", body = ApiErrorBody),
+        (status = 429, description = "Too many requests from this API key or address; see Retry-After"),
+        (status = 500, description = "Incorrect point list format", body = ApiErrorBody),
+        (status = 503, description = "Batched ingestion is enabled and its buffer is full, or the database is down without a WAL; retry later", body = ApiErrorBody)
     )
 )]
 
 #[post("")]
 pub async fn push_points (
-    db: web::Data<DatabaseConnection>,
+    ingestion: web::Data<Ingestion>,
+    qp: web::Query<PushPointsParams>,
     req: web::Json<PointListRequest>,
-) -> HttpResponse {
+) -> Result<HttpResponse, ApiError> {
     let started = Instant::now();
+    let ack = qp.ack.unwrap_or_default();
     let points = req.into_inner().points;
-    info!("Received {} points to insert", points.len());
-
-    if points.is_empty() {
-        return HttpResponse::BadRequest().body("Empty points list");
-    }
-
-    // Resolve webhook URL from env; if missing, we still insert without webhook/anomaly
-    let webhook_url = env::var("POINTS_WEBHOOK_URL").ok();
-
-    // Process points one-by-one to follow the described pipeline
-    for p in points {
-        // Build ActiveModel with defaults
-        let mut active = PointActiveModel {
-            randomized_id: Set(p.randomized_id),
-            lat: Set(p.lat),
-            lng: Set(p.lng),
-            alt: Set(p.alt.unwrap_or(0.0)),
-            spd: Set(p.spd),
-            azm: Set(p.azm),
-            ..Default::default()
+    info!("Received {} points to insert (ack={:?})", points.len(), ack);
+
+    let res = ingestion.ingest(points.into_iter().map(NewPointRecord::from).collect(), ack).await;
+    info!("Processed points in {:?}", started.elapsed());
+    res.map(respond)
+}
+
+impl From<NewPoint> for NewPointRecord {
+    fn from(p: NewPoint) -> Self {
+        NewPointRecord {
+            randomized_id: p.randomized_id,
+            lat: p.lat,
+            lng: p.lng,
+            alt: p.alt.unwrap_or(0.0),
+            spd: p.spd,
+            azm: p.azm,
+            timestamp: p.timestamp,
+            anomaly: None,
+            client_uuid: p.uuid,
+            device_id: p.device_id,
+            tenant_id: None,
+        }
+    }
+}
+
+/// Points of one broker message (MQTT, Kafka): a point, an array of points or a
+/// `POST /api/points` body. Points without `randomized_id` or `device_id` take `device`, the
+/// id the broker names the sender by (topic level, message key).
+pub fn decode_message(payload: &[u8], device: Option<i64>) -> Result<Vec<NewPoint>, String> {
+    let value: serde_json::Value = serde_json::from_slice(payload).map_err(|e| format!("not JSON: {}", e))?;
+    let items = match value {
+        serde_json::Value::Array(items) => items,
+        serde_json::Value::Object(mut fields) if fields.contains_key("points") => match fields.remove("points") {
+            Some(serde_json::Value::Array(items)) => items,
+            _ => return Err("`points` is not an array".to_string()),
+        },
+        point => vec![point],
+    };
+    items
+        .into_iter()
+        .enumerate()
+        .map(|(i, mut item)| {
+            if let (Some(device), serde_json::Value::Object(fields)) = (device, &mut item) {
+                fields.entry("randomized_id").or_insert(device.into());
+                fields.entry("device_id").or_insert(device.to_string().into());
+            }
+            serde_json::from_value(item).map_err(|e| format!("point {}: {}", i, e))
+        })
+        .collect()
+}
+
+/// Namespace of the point UUIDs derived from (randomized_id, timestamp)
+const DEDUP_NAMESPACE: Uuid = Uuid::from_u128(0x6c1f_2b8e_94d3_4a57_b0e6_3d7a_51c9_e2f4);
+
+/// UUID for a broker-delivered point that brought none, so a redelivery is caught by the unique
+/// index on `client_uuid`. Same point, same UUID: name-based on the trip and the timestamp at
+/// the database's microsecond precision; none without a timestamp.
+pub fn dedup_uuid(record: &NewPointRecord) -> Option<Uuid> {
+    let timestamp = record.timestamp?;
+    let mut name = [0u8; 16];
+    name[..8].copy_from_slice(&record.randomized_id.to_be_bytes());
+    name[8..].copy_from_slice(&timestamp.timestamp_micros().to_be_bytes());
+    Some(Uuid::new_v5(&DEDUP_NAMESPACE, &name))
+}
+
+/// The ingestion pipeline behind `POST /api/points`: timestamp screening, WAL, journal, batcher
+/// and classification queue. Built once in `main.rs` and shared with the other transports
+/// (gRPC), so every way in stores points the same way.
+#[derive(Clone)]
+pub struct Ingestion {
+    pub store: Arc<dyn PointStore>,
+    pub queue: ClassificationQueue,
+    pub wal: Option<Arc<Wal>>,
+    pub journal: Option<Arc<Journal>>,
+    pub batcher: Option<Arc<IngestBatcher>>,
+    pub quarantine: Arc<Quarantine>,
+}
+
+impl Ingestion {
+    /// Stores one batch; `buffered` of the result is non-zero when part of it was only accepted
+    pub async fn ingest(&self, mut records: Vec<NewPointRecord>, ack: Ack) -> Result<PushPointsResponse, ApiError> {
+        if records.is_empty() {
+            return Err(ApiError::bad_request("Empty points list"));
+        }
+        // Nothing would keep the batch should the background insert fail
+        if ack == Ack::Received && self.wal.is_none() && self.journal.is_none() {
+            return Err(ApiError::bad_request("ack=received needs INGEST_WAL_PATH or INGEST_JOURNAL_DIR to be configured"));
+        }
+        // Tagged before buffering: the WAL, the batcher and background inserts outlive the request
+        if let Some(tenant_id) = tenant::current_id() {
+            for record in &mut records {
+                record.tenant_id = Some(tenant_id.clone());
+            }
+        }
+        if let Some(i) = records.iter().position(|r| r.device_id.as_ref().is_some_and(|d| d.is_empty() || d.chars().count() > MAX_DEVICE_ID_LEN)) {
+            return Err(ApiError::bad_request(format!("point {}: device_id must be 1 to {} characters", i, MAX_DEVICE_ID_LEN)));
+        }
+
+        // Screened before anything is buffered, so WAL and journal only hold points for `points`
+        let (records, quarantined) = match screen_timestamps(&self.quarantine, records).await {
+            Ok(Ok(screened)) => screened,
+            Ok(Err(rejection)) => return Err(ApiError::bad_request(rejection)),
+            Err(e) => {
+                error!("Could not quarantine points: {}", e);
+                return Err(ApiError::Internal);
+            }
         };
 
-        // Only set timestamp if provided; otherwise, leave NotSet to use DB default
-        if let Some(ts) = p.timestamp {
-            active.timestamp = Set(Some(ts));
+        if ack == Ack::Received {
+            return self.accept(records, quarantined).await;
+        }
+
+        // Persist the batch before touching the database
+        let mut entry = match &self.journal {
+            Some(journal) => match journal.begin(&records).await {
+                Ok(entry) => Some(entry),
+                Err(e) => {
+                    error!("Could not journal {} points: {}", records.len(), e);
+                    return Err(ApiError::Internal);
+                }
+            },
+            None => None,
+        };
+        let res = insert_points(self.store.as_ref(), &self.queue, self.batcher.as_deref(), self.wal.as_deref(), &records, entry.as_mut(), ack, quarantined).await;
+        if let Some(entry) = entry {
+            entry.finish().await;
         }
+        res
+    }
 
-        let mut anomaly_value: Option<bool> = None;
+    /// `ack=received`: the batch goes to the WAL, which the replay task stores within seconds.
+    /// Without a WAL it is journaled and stored by a background task.
+    async fn accept(&self, records: Vec<NewPointRecord>, quarantined: Vec<QuarantinedPoint>) -> Result<PushPointsResponse, ApiError> {
+        let count = records.len();
+        if let Some(wal) = &self.wal {
+            if let Err(e) = wal.append(&stamp_now(records)).await {
+                error!("Could not buffer {} points: {}", count, e);
+                return Err(ApiError::Internal);
+            }
+            return Ok(PushPointsResponse { points: Vec::new(), buffered: count, quarantined });
+        }
 
-        if let Some(url) = &webhook_url {
-            // Query existing points with same randomized_id
-            match Points::find()
-                .filter(PointsColumn::RandomizedId.eq(p.randomized_id))
-                .order_by_desc(PointsColumn::Timestamp)
-                .all(db.get_ref())
-                .await
+        // Checked now: once the request is answered a full buffer can no longer be reported
+        if let Some(batcher) = &self.batcher
+            && !batcher.has_room(count)
+        {
+            warn!("Ingest batch buffer full; turning away {} points", count);
+            return Err(ApiError::ServiceUnavailable("Ingestion buffer is full, retry shortly".to_string()));
+        }
+        let mut entry = match &self.journal {
+            Some(journal) => match journal.begin(&records).await {
+                Ok(entry) => Some(entry),
+                Err(e) => {
+                    error!("Could not journal {} points: {}", count, e);
+                    return Err(ApiError::Internal);
+                }
+            },
+            None => None,
+        };
+        // Failures are logged by insert_points; a journaled batch is retried at the next start
+        let records = stamp_now(records);
+        let this = self.clone();
+        tokio::spawn(crate::request_id::inherit(async move {
+            let res = insert_points(this.store.as_ref(), &this.queue, this.batcher.as_deref(), None, &records, entry.as_mut(), Ack::Persisted, Vec::new()).await;
+            if res.is_ok()
+                && let Some(entry) = entry
             {
-                Ok(existing) => {
-                    if existing.is_empty() {
-                        // Case 1: no existing points -> just insert (no webhook)
-                    } else {
-                        // Build payload according to rules
-                        let second_ts = p.timestamp.unwrap_or_else(|| Utc::now());
-                        let second = WebhookPoint { lat: p.lat, lng: p.lng, azm: p.azm, timestamp: second_ts };
-
-                        // First is either the only one or the most recent from DB
-                        let first_model: &PointModel = &existing[0];
-                        // Convert DB model to webhook point; fallback timestamp to now if missing
-                        let first_ts = first_model.timestamp.unwrap_or_else(|| Utc::now());
-                        let first = WebhookPoint { lat: first_model.lat, lng: first_model.lng, azm: first_model.azm, timestamp: first_ts };
-
-                        // Gone: rest of DB points (skip first), by descending timestamp
-                        let mut gone: Vec<WebhookPoint> = Vec::new();
-                        if existing.len() > 1 {
-                            for m in existing.iter().skip(1) {
-                                let ts = m.timestamp.unwrap_or_else(|| Utc::now());
-                                gone.push(WebhookPoint { lat: m.lat, lng: m.lng, azm: m.azm, timestamp: ts });
-                            }
-                        }
-
-                        let payload = WebhookPayload { first, second, gone };
-
-                        // Send POST
-                        let client = reqwest::Client::new();
-                        match client.post(url).json(&payload).send().await {
-                            Ok(resp) => {
-                                // Read response body as text and try to parse into i32 either as JSON or plain text
-                                let code_opt: Option<i32> = match resp.text().await {
-                                    Ok(body) => {
-                                        serde_json::from_str::<i32>(&body).ok()
-                                            .or_else(|| body.trim().parse::<i32>().ok())
-                                    }
-                                    Err(_) => None,
-                                };
-
-                                match code_opt {
-                                    Some(-1) => anomaly_value = Some(true),
-                                    Some(1) => anomaly_value = Some(false),
-                                    Some(other) => {
-                                        warn!("Unexpected webhook response code: {}", other);
-                                    }
-                                    None => {
-                                        warn!("Failed to parse webhook response for rid {}", p.randomized_id);
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                error!("Webhook POST failed: {}", e);
-                            }
-                        }
+                entry.finish().await;
+            }
+        }));
+        Ok(PushPointsResponse { points: Vec::new(), buffered: count, quarantined })
+    }
+}
+
+/// 202 while part of the batch is only buffered, else 200
+fn respond(resp: PushPointsResponse) -> HttpResponse {
+    if resp.buffered > 0 {
+        HttpResponse::Accepted().json(resp)
+    } else {
+        HttpResponse::Ok().json(resp)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct StoredPoint {
+    pub id: i64,
+    pub randomized_id: i64,
+    pub lat: f64,
+    pub lng: f64,
+    pub alt: f64,
+    pub spd: f64,
+    pub azm: f64,
+    pub timestamp: Option<DateTime<Utc>>,
+    /// null until the classifier has looked at the point
+    pub anomaly: Option<bool>,
+    /// 0..1, 0.5 where a rule starts to flag; null like `anomaly`
+    pub anomaly_score: Option<f64>,
+    /// speed_spike, teleport, azimuth_reversal, webhook or review; null unless flagged
+    pub anomaly_reason: Option<String>,
+    pub uuid: Option<Uuid>,
+    /// Meters, seconds and m/s from the previous point of the trip; null for its first point
+    pub prev_distance_m: Option<f64>,
+    pub prev_interval_s: Option<f64>,
+    pub derived_speed: Option<f64>,
+}
+
+impl From<PointModel> for StoredPoint {
+    fn from(m: PointModel) -> Self {
+        Self {
+            id: m.id,
+            randomized_id: m.randomized_id,
+            lat: m.lat,
+            lng: m.lng,
+            alt: m.alt,
+            spd: m.spd,
+            azm: m.azm,
+            timestamp: m.timestamp,
+            anomaly: m.anomaly,
+            anomaly_score: m.anomaly_score,
+            anomaly_reason: m.anomaly_reason,
+            uuid: m.client_uuid,
+            prev_distance_m: m.prev_distance_m,
+            prev_interval_s: m.prev_interval_s,
+            derived_speed: m.derived_speed,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct PointsPage {
+    pub points: Vec<StoredPoint>,
+    /// Number of points matching the filters, ignoring limit/offset
+    pub total: u64,
+    pub limit: u64,
+    pub offset: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct PointsQueryParams {
+    #[serde(rename = "lat1")] pub lat1: Option<f64>,
+    #[serde(rename = "lng1")] pub lng1: Option<f64>,
+    #[serde(rename = "lat2")] pub lat2: Option<f64>,
+    #[serde(rename = "lng2")] pub lng2: Option<f64>,
+    #[serde(rename = "dateStart")] pub date_start: Option<DateTime<Utc>>, // inclusive
+    #[serde(rename = "dateEnd")] pub date_end: Option<DateTime<Utc>>,     // inclusive
+    #[serde(rename = "randomizedId")] pub randomized_id: Option<i64>,
+    pub anomaly: Option<bool>,
+    pub limit: Option<u64>,
+    pub offset: Option<u64>,
+    /// id | -id | timestamp | -timestamp
+    pub sort: Option<String>,
+    /// Spatially spread sample of this many matching points instead of a page
+    pub sample: Option<usize>,
+}
+
+#[utoipa::path(
+    get,
+    tag = "Points",
+    params(
+        ("lat1" = f64, Query, description = "First latitude (corner). Optional; all four corners or none"),
+        ("lng1" = f64, Query, description = "First longitude (corner). Optional"),
+        ("lat2" = f64, Query, description = "Second latitude (opposite corner). Optional"),
+        ("lng2" = f64, Query, description = "Second longitude (opposite corner). Optional"),
+        ("dateStart" = DateTime<chrono::Utc>, Query, description = "Start of the date/time range (inclusive). Optional"),
+        ("dateEnd" = DateTime<chrono::Utc>, Query, description = "End of the date/time range (inclusive). Optional"),
+        ("randomizedId" = i64, Query, description = "Only points of this trip. Optional"),
+        ("anomaly" = bool, Query, description = "Only anomalous (true) or normal (false) points. Optional"),
+        ("limit" = u64, Query, description = "Page size, default 100, max 1000"),
+        ("offset" = u64, Query, description = "Number of matching points to skip, default 0"),
+        ("sort" = String, Query, description = "id | -id | timestamp | -timestamp (default id)"),
+        ("sample" = usize, Query, description = "Return up to N points spread over the whole matching area instead of a page; limit/offset are ignored. Max 10000. Optional"),
+    ),
+    responses(
+        (status = 200, description = "Page of stored points", body = PointsPage),
+        (status = 400, description = "Invalid parameters", body = ApiErrorBody),
+        (status = 500, description = "Server Vzorvalsya", body = ApiErrorBody)
+    )
+)]
+
+#[get("")]
+pub async fn list_points(
+    store: web::Data<dyn PointStore>,
+    qp: web::Query<PointsQueryParams>,
+) -> Result<HttpResponse, ApiError> {
+    let started = Instant::now();
+
+    validate::corners(qp.lat1, qp.lng1, qp.lat2, qp.lng2)?;
+    let bbox = match (qp.lat1, qp.lng1, qp.lat2, qp.lng2) {
+        (Some(lat1), Some(lng1), Some(lat2), Some(lng2)) => Some(BBox::from_corners(lat1, lng1, lat2, lng2)),
+        (None, None, None, None) => None,
+        _ => return Err(ApiError::bad_request("lat1, lng1, lat2 and lng2 must be given together")),
+    };
+    validate::date_range(qp.date_start, qp.date_end)?;
+    let order = match qp.sort.as_deref().unwrap_or("id") {
+        "id" => PointOrder::IdAsc,
+        "-id" => PointOrder::IdDesc,
+        "timestamp" => PointOrder::TimestampAsc,
+        "-timestamp" => PointOrder::TimestampDesc,
+        _ => return Err(ApiError::bad_param("sort", "sort must be one of id, -id, timestamp, -timestamp")),
+    };
+    let limit = qp.limit.unwrap_or(DEFAULT_LIMIT);
+    if limit == 0 || limit > MAX_LIMIT {
+        return Err(ApiError::bad_param("limit", format!("limit must be between 1 and {}", MAX_LIMIT)));
+    }
+    let offset = qp.offset.unwrap_or(0);
+
+    let filter = PointFilter {
+        bbox,
+        since: qp.date_start,
+        until: qp.date_end,
+        randomized_id: qp.randomized_id,
+        anomaly: qp.anomaly,
+        ..Default::default()
+    };
+
+    if let Some(n) = qp.sample {
+        return sample_points(store.get_ref(), &filter, order, n, started).await;
+    }
+
+    let total = match store.count(&filter).await {
+        Ok(n) => n,
+        Err(e) => {
+            error!("Points count failed: {}", e);
+            return Err(ApiError::Internal);
+        }
+    };
+    let rows = if offset >= total {
+        Vec::new()
+    } else {
+        match store.find_page(&filter, order, Some(limit), offset).await {
+            Ok(r) => r,
+            Err(e) => {
+                error!("Points query failed: {}", e);
+                return Err(ApiError::Internal);
+            }
+        }
+    };
+
+    let rows_scanned = rows.len();
+    let resp = PointsPage {
+        points: rows.into_iter().map(StoredPoint::from).collect(),
+        total,
+        limit,
+        offset,
+    };
+
+    debug!("Points page: {} of {} (offset {}) in {:?}", resp.points.len(), total, offset, started.elapsed());
+    Ok(QueryStats { rows_scanned, tiles: resp.points.len() }.attach(HttpResponse::Ok().json(resp)))
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct DeletePointsQueryParams {
+    #[serde(rename = "lat1")] pub lat1: Option<f64>,
+    #[serde(rename = "lng1")] pub lng1: Option<f64>,
+    #[serde(rename = "lat2")] pub lat2: Option<f64>,
+    #[serde(rename = "lng2")] pub lng2: Option<f64>,
+    #[serde(rename = "dateStart")] pub date_start: Option<DateTime<Utc>>, // inclusive
+    #[serde(rename = "dateEnd")] pub date_end: Option<DateTime<Utc>>,     // inclusive
+    #[serde(rename = "randomizedId")] pub randomized_id: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct DeletePointsResponse {
+    pub deleted: u64,
+}
+
+#[utoipa::path(
+    delete,
+    tag = "Points",
+    params(
+        ("lat1" = f64, Query, description = "First latitude (corner). Optional; all four corners or none"),
+        ("lng1" = f64, Query, description = "First longitude (corner). Optional"),
+        ("lat2" = f64, Query, description = "Second latitude (opposite corner). Optional"),
+        ("lng2" = f64, Query, description = "Second longitude (opposite corner). Optional"),
+        ("dateStart" = DateTime<chrono::Utc>, Query, description = "Start of the date/time range (inclusive). Optional"),
+        ("dateEnd" = DateTime<chrono::Utc>, Query, description = "End of the date/time range (inclusive). Optional"),
+        ("randomizedId" = i64, Query, description = "Delete the points of this trip. Optional"),
+        ("Authorization" = String, Header, description = "Bearer <ADMIN_TOKEN>"),
+    ),
+    responses(
+        (status = 200, description = "Number of deleted points", body = DeletePointsResponse),
+        (status = 400, description = "No filter given or invalid parameters", body = ApiErrorBody),
+        (status = 401, description = "Missing or wrong token"),
+        (status = 503, description = "Admin endpoints are disabled"),
+        (status = 500, description = "Server Vzorvalsya", body = ApiErrorBody)
+    )
+)]
+
+#[delete("")]
+pub async fn delete_points(
+    cfg: web::Data<AdminConfig>,
+    store: web::Data<dyn PointStore>,
+    rollups: Option<web::Data<dyn TileStatsStore>>,
+    qp: web::Query<DeletePointsQueryParams>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    if let Some(resp) = cfg.reject(&req) {
+        return Ok(resp);
+    }
+    let started = Instant::now();
+
+    validate::corners(qp.lat1, qp.lng1, qp.lat2, qp.lng2)?;
+    let bbox = match (qp.lat1, qp.lng1, qp.lat2, qp.lng2) {
+        (Some(lat1), Some(lng1), Some(lat2), Some(lng2)) => Some(BBox::from_corners(lat1, lng1, lat2, lng2)),
+        (None, None, None, None) => None,
+        _ => return Err(ApiError::bad_request("lat1, lng1, lat2 and lng2 must be given together")),
+    };
+    // Refuse to wipe the whole table by accident
+    if bbox.is_none() && qp.randomized_id.is_none() && qp.date_start.is_none() && qp.date_end.is_none() {
+        return Err(ApiError::bad_request("At least one of randomizedId, dateStart, dateEnd or a bbox is required"));
+    }
+    validate::date_range(qp.date_start, qp.date_end)?;
+
+    let filter = PointFilter {
+        bbox,
+        since: qp.date_start,
+        until: qp.date_end,
+        randomized_id: qp.randomized_id,
+        ..Default::default()
+    };
+    // The hours the deleted points lie in, for the tile rollups to recompute
+    let span = match &rollups {
+        Some(_) => time_span(store.get_ref(), &filter).await.map_err(|e| {
+            error!("Points delete failed: {}", e);
+            ApiError::Internal
+        })?,
+        None => None,
+    };
+    let deleted = match store.delete(&filter).await {
+        Ok(n) => n,
+        Err(e) => {
+            error!("Points delete failed: {}", e);
+            return Err(ApiError::Internal);
+        }
+    };
+    if let (Some(rollups), Some((since, until))) = (rollups, span) {
+        // In a task of its own, outside the tenant's scope: the rollups add up every tenant
+        let store = store.into_inner();
+        let refreshed = tokio::spawn(async move { tile_rollup::invalidate(store.as_ref(), rollups.get_ref(), since, until).await }).await;
+        if !matches!(refreshed, Ok(Ok(_))) {
+            error!("Tile rollups not updated after deleting points from {} to {}: {:?}", since, until, refreshed);
+        }
+    }
+
+    info!("Deleted {} points ({:?}) in {:?}", deleted, filter, started.elapsed());
+    Ok(HttpResponse::Ok().json(DeletePointsResponse { deleted }))
+}
+
+/// Timestamps of the first and last point matching `filter`
+async fn time_span(store: &dyn PointStore, filter: &PointFilter) -> StoreResult<Option<(DateTime<Utc>, DateTime<Utc>)>> {
+    let first = store.find(filter, PointOrder::TimestampAsc, Some(1)).await?;
+    let last = store.find(filter, PointOrder::TimestampDesc, Some(1)).await?;
+    Ok(first.first().and_then(|p| p.timestamp).zip(last.first().and_then(|p| p.timestamp)))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Ndjson,
+    Csv,
+}
+
+impl ExportFormat {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "ndjson" => Some(ExportFormat::Ndjson),
+            "csv" => Some(ExportFormat::Csv),
+            _ => None,
+        }
+    }
+
+    /// Written once before the rows
+    pub fn header(self) -> &'static str {
+        match self {
+            ExportFormat::Ndjson => "",
+            ExportFormat::Csv => "id,randomized_id,lat,lng,alt,spd,azm,timestamp,anomaly,uuid,anomaly_score,anomaly_reason,prev_distance_m,prev_interval_s,derived_speed\n",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct ExportPointsQueryParams {
+    #[serde(rename = "lat1")] pub lat1: Option<f64>,
+    #[serde(rename = "lng1")] pub lng1: Option<f64>,
+    #[serde(rename = "lat2")] pub lat2: Option<f64>,
+    #[serde(rename = "lng2")] pub lng2: Option<f64>,
+    #[serde(rename = "dateStart")] pub date_start: Option<DateTime<Utc>>, // inclusive
+    #[serde(rename = "dateEnd")] pub date_end: Option<DateTime<Utc>>,     // inclusive
+    #[serde(rename = "randomizedId")] pub randomized_id: Option<i64>,
+    pub anomaly: Option<bool>,
+    /// ndjson | csv
+    pub format: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    tag = "Points",
+    params(
+        ("lat1" = f64, Query, description = "First latitude (corner). Optional; all four corners or none"),
+        ("lng1" = f64, Query, description = "First longitude (corner). Optional"),
+        ("lat2" = f64, Query, description = "Second latitude (opposite corner). Optional"),
+        ("lng2" = f64, Query, description = "Second longitude (opposite corner). Optional"),
+        ("dateStart" = DateTime<chrono::Utc>, Query, description = "Start of the date/time range (inclusive). Optional"),
+        ("dateEnd" = DateTime<chrono::Utc>, Query, description = "End of the date/time range (inclusive). Optional"),
+        ("randomizedId" = i64, Query, description = "Only points of this trip. Optional"),
+        ("anomaly" = bool, Query, description = "Only anomalous (true) or normal (false) points. Optional"),
+        ("format" = String, Query, description = "ndjson (default, one StoredPoint per line), csv or parquet (columns named like the csv header, zstd-compressed)"),
+    ),
+    responses(
+        (status = 200, description = "Every matching point ascending by id, streamed", content(
+            (StoredPoint = "application/x-ndjson"),
+            (String = "text/csv"),
+            (Vec<u8> = "application/vnd.apache.parquet"),
+        )),
+        (status = 400, description = "Invalid parameters", body = ApiErrorBody),
+        (status = 500, description = "Server Vzorvalsya", body = ApiErrorBody)
+    )
+)]
+
+#[get("/export")]
+pub async fn export_points(
+    store: web::Data<dyn PointStore>,
+    qp: web::Query<ExportPointsQueryParams>,
+) -> Result<HttpResponse, ApiError> {
+    validate::corners(qp.lat1, qp.lng1, qp.lat2, qp.lng2)?;
+    let bbox = match (qp.lat1, qp.lng1, qp.lat2, qp.lng2) {
+        (Some(lat1), Some(lng1), Some(lat2), Some(lng2)) => Some(BBox::from_corners(lat1, lng1, lat2, lng2)),
+        (None, None, None, None) => None,
+        _ => return Err(ApiError::bad_request("lat1, lng1, lat2 and lng2 must be given together")),
+    };
+    validate::date_range(qp.date_start, qp.date_end)?;
+    let requested = qp.format.as_deref().unwrap_or("ndjson");
+    let format = ExportFormat::parse(requested);
+    if format.is_none() && requested != "parquet" {
+        return Err(ApiError::bad_param("format", "format must be ndjson, csv or parquet"));
+    }
+
+    let filter = PointFilter {
+        bbox,
+        since: qp.date_start,
+        until: qp.date_end,
+        randomized_id: qp.randomized_id,
+        anomaly: qp.anomaly,
+        // Pages are read while the body streams, after the request's tenant scope ended
+        tenant: tenant::current(),
+        ..Default::default()
+    };
+    info!("Points export started: format={} ({:?})", requested, filter);
+    let store = store.into_inner();
+    let Some(format) = format else {
+        let pages = parquet_pages(0i64, move |after_id| {
+            let (store, filter) = (store.clone(), filter.clone());
+            async move {
+                let rows = store.find_after(&filter, after_id, EXPORT_BATCH).await?;
+                let next = rows.last().map(|r| r.id).filter(|_| rows.len() as u64 == EXPORT_BATCH);
+                Ok((rows, next))
+            }
+        })
+        .inspect_err(|e| error!("Points export aborted: {}", e));
+        return Ok(parquet_response("points.parquet", pages));
+    };
+
+    // Keyset pages of EXPORT_BATCH rows; only one page is held in memory at a time
+    let pages = stream::try_unfold(Some(0i64), move |cursor| {
+        let store = store.clone();
+        let filter = filter.clone();
+        async move {
+            let Some(after_id) = cursor else { return Ok(None) };
+            let rows = store.find_after(&filter, after_id, EXPORT_BATCH).await?;
+            let Some(last_id) = rows.last().map(|r| r.id) else { return Ok(None) };
+            let next = (rows.len() as u64 == EXPORT_BATCH).then_some(last_id);
+            let mut buf = String::new();
+            for row in rows {
+                write_export_row(&mut buf, format, &StoredPoint::from(row))?;
+            }
+            Ok::<_, StoreError>(Some((Bytes::from(buf), next)))
+        }
+    })
+    .inspect_err(|e| error!("Points export aborted: {}", e));
+
+    let header = Bytes::from_static(format.header().as_bytes());
+    let (content_type, file_name) = match format {
+        ExportFormat::Ndjson => ("application/x-ndjson", "points.ndjson"),
+        ExportFormat::Csv => ("text/csv", "points.csv"),
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header(("Content-Disposition", format!("attachment; filename=\"{}\"", file_name)))
+        .streaming(stream::once(future::ready(Ok(header))).chain(pages)))
+}
+
+/// A Parquet file of the pages `page` reads, streamed a row group at a time. `page` gets the
+/// cursor it returned last (`first` at the start) and returns no cursor with the last page.
+pub(super) fn parquet_pages<R, C, F, Fut>(first: C, page: F) -> impl Stream<Item = StoreResult<Bytes>>
+where
+    R: ParquetRow + Send + 'static,
+    C: Send + 'static,
+    F: Fn(C) -> Fut + Send + 'static,
+    Fut: Future<Output = StoreResult<(Vec<R>, Option<C>)>> + Send,
+{
+    let parquet = |e: parquet::errors::ParquetError| StoreError::Backend(format!("parquet: {}", e));
+    let writer = ParquetWriter::<Vec<u8>, R>::new(Vec::new()).map_err(parquet);
+    stream::try_unfold(Some((writer, Some(first), page)), move |state| async move {
+        let Some((writer, mut cursor, page)) = state else { return Ok(None) };
+        let mut writer = writer?;
+        let mut group = Vec::new();
+        while group.len() < ROW_GROUP
+            && let Some(at) = cursor.take()
+        {
+            let (rows, next) = page(at).await?;
+            group.extend(rows);
+            cursor = next;
+        }
+        if !group.is_empty() {
+            writer.write(&group).map_err(parquet)?;
+        }
+        if cursor.is_some() {
+            let written = writer.take_written().map_err(parquet)?;
+            return Ok(Some((Bytes::from(written), Some((Ok(writer), cursor, page)))));
+        }
+        Ok(Some((Bytes::from(writer.finish().map_err(parquet)?), None)))
+    })
+}
+
+pub(super) fn parquet_response<S>(file_name: &str, pages: S) -> HttpResponse
+where
+    S: Stream<Item = StoreResult<Bytes>> + 'static,
+{
+    HttpResponse::Ok()
+        .content_type("application/vnd.apache.parquet")
+        .insert_header(("Content-Disposition", format!("attachment; filename=\"{}\"", file_name)))
+        .streaming(pages)
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct DumpPointsQueryParams {
+    #[serde(rename = "lat1")] pub lat1: Option<f64>,
+    #[serde(rename = "lng1")] pub lng1: Option<f64>,
+    #[serde(rename = "lat2")] pub lat2: Option<f64>,
+    #[serde(rename = "lng2")] pub lng2: Option<f64>,
+    #[serde(rename = "dateStart")] pub date_start: Option<DateTime<Utc>>, // inclusive
+    #[serde(rename = "dateEnd")] pub date_end: Option<DateTime<Utc>>,     // inclusive
+    /// Last id already received; the dump continues after it
+    pub cursor: Option<i64>,
+    /// Last id to include, from `X-Dump-Until-Id` of the first response
+    #[serde(rename = "untilId")] pub until_id: Option<i64>,
+}
+
+#[utoipa::path(
+    get,
+    tag = "Points",
+    params(
+        ("lat1" = f64, Query, description = "First latitude (corner). Optional; all four corners or none"),
+        ("lng1" = f64, Query, description = "First longitude (corner). Optional"),
+        ("lat2" = f64, Query, description = "Second latitude (opposite corner). Optional"),
+        ("lng2" = f64, Query, description = "Second longitude (opposite corner). Optional"),
+        ("dateStart" = DateTime<chrono::Utc>, Query, description = "Start of the date/time range (inclusive). Optional"),
+        ("dateEnd" = DateTime<chrono::Utc>, Query, description = "End of the date/time range (inclusive). Optional"),
+        ("cursor" = i64, Query, description = "Resume after this id (the last one received). Optional"),
+        ("untilId" = i64, Query, description = "Stop at this id; pass X-Dump-Until-Id of the first response when resuming. Optional"),
+        ("Range" = String, Header, description = "Alternative to cursor/untilId: `id=<first>-[<last>]`, ids inclusive. Optional"),
+    ),
+    responses(
+        (status = 200, description = "Matching points ascending by id as zstd-compressed NDJSON (one StoredPoint per line), one zstd frame per 5000 rows", content_type = "application/zstd"),
+        (status = 206, description = "The requested id range of the dump; Content-Range: id <first>-<last>/*", content_type = "application/zstd"),
+        (status = 400, description = "Invalid parameters", body = ApiErrorBody),
+        (status = 416, description = "Unsupported Range", body = ApiErrorBody),
+        (status = 500, description = "Server Vzorvalsya", body = ApiErrorBody)
+    )
+)]
+
+#[get("/dump")]
+pub async fn dump_points(
+    store: web::Data<dyn PointStore>,
+    req: HttpRequest,
+    qp: web::Query<DumpPointsQueryParams>,
+) -> Result<HttpResponse, ApiError> {
+    validate::corners(qp.lat1, qp.lng1, qp.lat2, qp.lng2)?;
+    let bbox = match (qp.lat1, qp.lng1, qp.lat2, qp.lng2) {
+        (Some(lat1), Some(lng1), Some(lat2), Some(lng2)) => Some(BBox::from_corners(lat1, lng1, lat2, lng2)),
+        (None, None, None, None) => None,
+        _ => return Err(ApiError::bad_request("lat1, lng1, lat2 and lng2 must be given together")),
+    };
+    validate::date_range(qp.date_start, qp.date_end)?;
+    let range = match req.headers().get(header::RANGE) {
+        Some(h) => match h.to_str().ok().and_then(parse_id_range) {
+            Some(r) => Some(r),
+            None => return Err(ApiError::RangeNotSatisfiable("Range must be id=<first>-[<last>]".to_string())),
+        },
+        None => None,
+    };
+    if range.is_some() && (qp.cursor.is_some() || qp.until_id.is_some()) {
+        return Err(ApiError::bad_request("Use either Range or cursor/untilId"));
+    }
+    let (after_id, until_id) = match range {
+        Some((first, last)) => (first.saturating_sub(1), last),
+        None => (qp.cursor.unwrap_or(0), qp.until_id),
+    };
+
+    let mut filter = PointFilter { bbox, since: qp.date_start, until: qp.date_end, tenant: tenant::current(), ..Default::default() };
+    // Pin the end of the dump, so a resumed download stops where the first one would have
+    let until_id = match until_id {
+        Some(id) => id,
+        None => match store.find(&filter, PointOrder::IdDesc, Some(1)).await {
+            Ok(rows) => rows.first().map(|r| r.id).unwrap_or(0),
+            Err(e) => {
+                error!("Points dump failed: {}", e);
+                return Err(ApiError::Internal);
+            }
+        },
+    };
+    filter.before_id = Some(until_id.saturating_add(1));
+    info!("Points dump started: ids {}..={} ({:?})", after_id + 1, until_id, filter);
+
+    // Each page is its own zstd frame; concatenated frames decode as one stream
+    let store = store.into_inner();
+    let frames = stream::try_unfold(Some(after_id), move |cursor| {
+        let store = store.clone();
+        let filter = filter.clone();
+        async move {
+            let Some(after_id) = cursor else { return Ok(None) };
+            let rows = store.find_after(&filter, after_id, DUMP_BATCH).await?;
+            let Some(last_id) = rows.last().map(|r| r.id) else { return Ok(None) };
+            let next = (rows.len() as u64 == DUMP_BATCH).then_some(last_id);
+            let mut buf = String::new();
+            for row in rows {
+                write_export_row(&mut buf, ExportFormat::Ndjson, &StoredPoint::from(row))?;
+            }
+            let frame = zstd::bulk::compress(buf.as_bytes(), DUMP_ZSTD_LEVEL).map_err(|e| StoreError::Backend(e.to_string()))?;
+            Ok::<_, StoreError>(Some((Bytes::from(frame), next)))
+        }
+    })
+    .inspect_err(|e| error!("Points dump aborted: {}", e));
+
+    let mut resp = if range.is_some() { HttpResponse::PartialContent() } else { HttpResponse::Ok() };
+    if range.is_some() {
+        resp.insert_header((header::CONTENT_RANGE, format!("id {}-{}/*", after_id + 1, until_id)));
+    }
+    Ok(resp.content_type("application/zstd")
+        // Already compressed; keeps the Compress middleware off it
+        .insert_header((header::CONTENT_ENCODING, "identity"))
+        .insert_header((header::ACCEPT_RANGES, "id"))
+        .insert_header(("X-Dump-Until-Id", until_id.to_string()))
+        .insert_header(("Content-Disposition", "attachment; filename=\"points.ndjson.zst\""))
+        .streaming(frames))
+}
+
+pub fn routes() -> ApiScope {
+    ApiScope::new("/points")
+        .service(push_points)
+        .service(list_points)
+        .service(delete_points)
+        .service(export_points)
+        .service(dump_points)
+        .service(import::import_gpx)
+        .service(import::import_kml)
+}
+
+// --- Helpers ---
+
+/// Stores a batch through the batcher when batched ingestion is on, else point by point
+#[allow(clippy::too_many_arguments)]
+async fn insert_points(
+    store: &dyn PointStore,
+    queue: &ClassificationQueue,
+    batcher: Option<&IngestBatcher>,
+    wal: Option<&Wal>,
+    records: &[NewPointRecord],
+    entry: Option<&mut JournalEntry>,
+    ack: Ack,
+    quarantined: Vec<QuarantinedPoint>,
+) -> Result<PushPointsResponse, ApiError> {
+    match batcher {
+        Some(batcher) => insert_batched(store, queue, batcher, wal, records, entry, ack, quarantined).await,
+        None => insert_each(store, queue, wal, records, entry, ack, quarantined).await,
+    }
+}
+
+/// Inserts points one-by-one and hands each to the classification queue
+async fn insert_each(
+    store: &dyn PointStore,
+    queue: &ClassificationQueue,
+    wal: Option<&Wal>,
+    records: &[NewPointRecord],
+    mut entry: Option<&mut JournalEntry>,
+    ack: Ack,
+    quarantined: Vec<QuarantinedPoint>,
+) -> Result<PushPointsResponse, ApiError> {
+    let classified = ack == Ack::Classified;
+    let mut inserted_points = Vec::with_capacity(records.len());
+    // (index in inserted_points, decision) for ack=classified
+    let mut decisions = Vec::new();
+    for (i, record) in records.iter().enumerate() {
+        // A retry of something we already have: echo the stored row
+        if let Some(uuid) = record.client_uuid {
+            match find_by_uuid(store, uuid).await {
+                Ok(Some(existing)) => {
+                    let anomaly = existing.anomaly.filter(|_| classified);
+                    inserted_points.push(InsertedPoint { id: existing.id, timestamp: existing.timestamp, uuid: Some(uuid), duplicate: true, anomaly });
+                    if let Some(entry) = entry.as_deref_mut() {
+                        entry.mark_done().await;
                     }
+                    continue;
+                }
+                Ok(None) => {}
+                Err(e) if e.is_connectivity() && wal.is_some() => {
+                    return buffer_points(wal, &records[i..], inserted_points, quarantined).await;
                 }
                 Err(e) => {
-                    error!("DB query failed for rid {}: {}", p.randomized_id, e);
+                    error!("UUID lookup failed for {}: {}", uuid, e);
+                    return Err(ApiError::Internal);
                 }
             }
+        }
+
+        // Insert the point; the anomaly flag is filled in later by the background worker
+        let res = if classified {
+            queue.ingest_watched(store, record.clone()).await.map(|(m, rx)| (m, Some(rx)))
         } else {
-            // No webhook configured
-            warn!("POINTS_WEBHOOK_URL is not set; skipping webhook calls");
+            queue.ingest(store, record.clone()).await.map(|m| (m, None))
+        };
+        let inserted = match res {
+            Ok((m, decision)) => {
+                if let Some(rx) = decision {
+                    decisions.push((inserted_points.len(), rx));
+                }
+                m
+            }
+            Err(e) if e.is_connectivity() && wal.is_some() => {
+                return buffer_points(wal, &records[i..], inserted_points, quarantined).await;
+            }
+            Err(e) => {
+                // A concurrent retry may have won the unique index
+                if let Some(uuid) = record.client_uuid
+                    && let Ok(Some(existing)) = find_by_uuid(store, uuid).await
+                {
+                    let anomaly = existing.anomaly.filter(|_| classified);
+                    inserted_points.push(InsertedPoint { id: existing.id, timestamp: existing.timestamp, uuid: Some(uuid), duplicate: true, anomaly });
+                    if let Some(entry) = entry.as_deref_mut() {
+                        entry.mark_done().await;
+                    }
+                    continue;
+                }
+                error!("Insert failed for rid {}: {}", record.randomized_id, e);
+                return Err(ApiError::Internal);
+            }
+        };
+        inserted_points.push(InsertedPoint { id: inserted.id, timestamp: inserted.timestamp, uuid: inserted.client_uuid, duplicate: false, anomaly: None });
+        if let Some(entry) = entry.as_deref_mut() {
+            entry.mark_done().await;
+        }
+    }
+
+    await_decisions(&mut inserted_points, decisions).await;
+    Ok(PushPointsResponse { points: inserted_points, buffered: 0, quarantined })
+}
+
+/// Batched ingestion: points already stored under their UUID are echoed, the rest wait for the
+/// batcher's next bulk insert. A full buffer answers 503; a failed bulk insert falls back to
+/// `insert_each`, which resolves UUID races and names the bad point.
+#[allow(clippy::too_many_arguments)]
+async fn insert_batched(
+    store: &dyn PointStore,
+    queue: &ClassificationQueue,
+    batcher: &IngestBatcher,
+    wal: Option<&Wal>,
+    records: &[NewPointRecord],
+    mut entry: Option<&mut JournalEntry>,
+    ack: Ack,
+    quarantined: Vec<QuarantinedPoint>,
+) -> Result<PushPointsResponse, ApiError> {
+    let classified = ack == Ack::Classified;
+    // Request order; None for the points that go into the batch
+    let mut duplicates: Vec<Option<InsertedPoint>> = Vec::with_capacity(records.len());
+    let mut fresh = Vec::with_capacity(records.len());
+    for record in records {
+        let existing = match record.client_uuid {
+            Some(uuid) => match find_by_uuid(store, uuid).await {
+                Ok(existing) => existing,
+                Err(e) if e.is_connectivity() && wal.is_some() => {
+                    return buffer_points(wal, records, Vec::new(), quarantined).await;
+                }
+                Err(e) => {
+                    error!("UUID lookup failed for {}: {}", uuid, e);
+                    return Err(ApiError::Internal);
+                }
+            },
+            None => None,
+        };
+        match existing {
+            Some(existing) => {
+                let anomaly = existing.anomaly.filter(|_| classified);
+                duplicates.push(Some(InsertedPoint { id: existing.id, timestamp: existing.timestamp, uuid: existing.client_uuid, duplicate: true, anomaly }));
+            }
+            None => {
+                duplicates.push(None);
+                fresh.push(record.clone());
+            }
+        }
+    }
+
+    let stored = match batcher.submit(fresh, classified).await {
+        Ok(stored) => stored,
+        Err(BatchError::Full) => {
+            warn!("Ingest batch buffer full; turning away {} points", records.len());
+            return Err(ApiError::ServiceUnavailable("Ingestion buffer is full, retry shortly".to_string()));
+        }
+        Err(BatchError::Store(e)) if e.is_connectivity() && wal.is_some() => {
+            return buffer_points(wal, records, Vec::new(), quarantined).await;
+        }
+        Err(BatchError::Store(e)) => {
+            warn!("Batched insert of {} points failed, storing them one by one: {}", records.len(), e);
+            return insert_each(store, queue, wal, records, entry, ack, quarantined).await;
+        }
+    };
+
+    let mut stored = stored.into_iter();
+    let mut inserted_points = Vec::with_capacity(records.len());
+    let mut decisions = Vec::new();
+    for duplicate in duplicates {
+        let point = match duplicate {
+            Some(point) => point,
+            None => {
+                let Some((m, decision)) = stored.next() else {
+                    error!("Batcher returned fewer rows than it was given");
+                    return Err(ApiError::Internal);
+                };
+                if let Some(rx) = decision {
+                    decisions.push((inserted_points.len(), rx));
+                }
+                InsertedPoint { id: m.id, timestamp: m.timestamp, uuid: m.client_uuid, duplicate: false, anomaly: None }
+            }
+        };
+        inserted_points.push(point);
+        if let Some(entry) = entry.as_deref_mut() {
+            entry.mark_done().await;
         }
+    }
+
+    await_decisions(&mut inserted_points, decisions).await;
+    Ok(PushPointsResponse { points: inserted_points, buffered: 0, quarantined })
+}
 
-        // Set anomaly if determined
-        if anomaly_value.is_some() {
-            active.anomaly = Set(anomaly_value);
+/// Fills in the anomaly decisions of `ack=classified`, giving up after CLASSIFY_ACK_TIMEOUT.
+/// The worker goes in insertion order, so waiting on each in turn costs no extra time.
+async fn await_decisions(inserted_points: &mut [InsertedPoint], decisions: Vec<(usize, Decision)>) {
+    let deadline = tokio::time::Instant::now() + CLASSIFY_ACK_TIMEOUT;
+    for (i, rx) in decisions {
+        match tokio::time::timeout_at(deadline, rx).await {
+            Ok(Ok(decision)) => inserted_points[i].anomaly = decision,
+            Ok(Err(_)) => {}
+            Err(_) => {
+                warn!("Classification of batch not finished within {:?}; answering without it", CLASSIFY_ACK_TIMEOUT);
+                break;
+            }
         }
+    }
+}
+
+/// Stamps points without a timestamp with the time they were accepted, not stored
+fn stamp_now(records: Vec<NewPointRecord>) -> Vec<NewPointRecord> {
+    let now = Utc::now();
+    records
+        .into_iter()
+        .map(|mut r| {
+            r.timestamp.get_or_insert(now);
+            r
+        })
+        .collect()
+}
+
+/// Database outage: park the rest of the batch in the WAL and answer 202
+async fn buffer_points(wal: Option<&Wal>, rest: &[NewPointRecord], inserted_points: Vec<InsertedPoint>, quarantined: Vec<QuarantinedPoint>) -> Result<PushPointsResponse, ApiError> {
+    let Some(wal) = wal else {
+        return Err(ApiError::ServiceUnavailable("Database unavailable".to_string()));
+    };
+    // Stamp now, not at replay time
+    let rest = stamp_now(rest.to_vec());
+    if let Err(e) = wal.append(&rest).await {
+        error!("Could not buffer {} points during database outage: {}", rest.len(), e);
+        return Err(ApiError::ServiceUnavailable("Database unavailable".to_string()));
+    }
+    warn!("Database unreachable; buffered {} points locally", rest.len());
+    Ok(PushPointsResponse { points: inserted_points, buffered: rest.len(), quarantined })
+}
 
-        // Insert the point
-        if let Err(e) = active.insert(db.get_ref()).await {
-            error!("Insert failed for rid {}: {}", p.randomized_id, e);
-            return HttpResponse::InternalServerError().finish();
+/// Applies the timestamp window: with `reject` the first offending point fails the request
+/// (inner Err, the 400 body); with `quarantine` offending points are written to the side
+/// table and left out of the returned records
+pub(super) async fn screen_timestamps(
+    quarantine: &Quarantine,
+    records: Vec<NewPointRecord>,
+) -> StoreResult<Result<(Vec<NewPointRecord>, Vec<QuarantinedPoint>), String>> {
+    let now = Utc::now();
+    let mut kept = Vec::with_capacity(records.len());
+    let mut quarantined = Vec::new();
+    for (index, record) in records.into_iter().enumerate() {
+        let Some(reason) = quarantine.window.check(record.timestamp, now) else {
+            kept.push(record);
+            continue;
+        };
+        if quarantine.window.action == WindowAction::Reject {
+            return Ok(Err(format!("point {}: {}", index, reason)));
         }
+        let row = quarantine.store.quarantine(&record, &reason).await?;
+        quarantined.push(QuarantinedPoint { index, id: row.id, reason });
+    }
+    if !quarantined.is_empty() {
+        warn!("Quarantined {} points with out-of-window timestamps", quarantined.len());
+    }
+    Ok(Ok((kept, quarantined)))
+}
+
+async fn find_by_uuid(store: &dyn PointStore, uuid: Uuid) -> StoreResult<Option<PointModel>> {
+    let filter = PointFilter { client_uuid: Some(uuid), ..Default::default() };
+    Ok(store.find(&filter, PointOrder::IdAsc, Some(1)).await?.pop())
+}
+
+/// `GET /api/points?sample=N`: every matching row is read, then thinned out spatially
+async fn sample_points(store: &dyn PointStore, filter: &PointFilter, order: PointOrder, n: usize, started: Instant) -> Result<HttpResponse, ApiError> {
+    if n == 0 || n > sample::MAX_SAMPLE {
+        return Err(ApiError::bad_param("sample", format!("sample must be between 1 and {}", sample::MAX_SAMPLE)));
     }
+    let rows = match store.find(filter, order, None).await {
+        Ok(r) => r,
+        Err(e) => {
+            error!("Points query failed: {}", e);
+            return Err(ApiError::Internal);
+        }
+    };
+    let rows_scanned = rows.len();
+    let picked = sample::stratified(rows, n, |p| (p.lat, p.lng));
+    let resp = PointsPage {
+        points: picked.into_iter().map(StoredPoint::from).collect(),
+        total: rows_scanned as u64,
+        limit: n as u64,
+        offset: 0,
+    };
 
-    info!("Processed and inserted points in {:?}", started.elapsed());
-    HttpResponse::Ok().finish()
+    debug!("Points sample: {} of {} in {:?}", resp.points.len(), rows_scanned, started.elapsed());
+    Ok(QueryStats { rows_scanned, tiles: resp.points.len() }.attach(HttpResponse::Ok().json(resp)))
+}
+
+/// `id=<first>-` or `id=<first>-<last>`
+fn parse_id_range(value: &str) -> Option<(i64, Option<i64>)> {
+    let (first, last) = value.trim().strip_prefix("id=")?.split_once('-')?;
+    let first = first.trim().parse::<i64>().ok()?;
+    let last = match last.trim() {
+        "" => None,
+        l => Some(l.parse::<i64>().ok().filter(|l| *l >= first)?),
+    };
+    Some((first, last))
+}
+
+/// Appends one line of `format` for `p`
+pub fn write_export_row(buf: &mut String, format: ExportFormat, p: &StoredPoint) -> StoreResult<()> {
+    match format {
+        ExportFormat::Ndjson => {
+            let line = serde_json::to_string(p).map_err(|e| StoreError::Backend(e.to_string()))?;
+            buf.push_str(&line);
+        }
+        ExportFormat::Csv => {
+            let opt = |v: Option<String>| v.unwrap_or_default();
+            let _ = write!(
+                buf,
+                "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+                p.id, p.randomized_id, p.lat, p.lng, p.alt, p.spd, p.azm,
+                opt(p.timestamp.map(|t| t.to_rfc3339())),
+                opt(p.anomaly.map(|a| a.to_string())),
+                opt(p.uuid.map(|u| u.to_string())),
+                opt(p.anomaly_score.map(|s| s.to_string())),
+                opt(p.anomaly_reason.clone()),
+                opt(p.prev_distance_m.map(|d| d.to_string())),
+                opt(p.prev_interval_s.map(|s| s.to_string())),
+                opt(p.derived_speed.map(|s| s.to_string())),
+            );
+        }
+    }
+    buf.push('\n');
+    Ok(())
 }
 
-pub fn init_routes(cfg: &mut web::ServiceConfig) {
-    cfg.service(
-        web::scope("/points")
-            .service(push_points)
-    );
-}
\ No newline at end of file
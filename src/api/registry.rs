@@ -0,0 +1,198 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{HttpServiceFactory, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::middleware::{self, Next};
+use actix_web::{web, Error, HttpRequest, HttpResponse, Scope};
+use log::warn;
+use once_cell::sync::Lazy;
+use std::env;
+use utoipa::openapi::path::{Operation, PathItem, Paths};
+use utoipa::openapi::schema::{Components, Schema};
+use utoipa::openapi::{Deprecated, Info, OpenApi, OpenApiBuilder, RefOr};
+
+/// Everything in this registry is mounted under this prefix (see `main.rs`)
+pub const API_PREFIX: &str = "/api";
+
+/// Version of the /api contract, reported by `/api/version`.
+/// Bumped on breaking changes; renames keep the old path as a deprecated alias instead.
+pub const API_VERSION: &str = "1";
+
+/// A `web::scope` that records the OpenAPI operation of every handler it mounts.
+/// Handlers need `#[utoipa::path]` above their actix method attribute and must not set `path`:
+/// the documented path is the scope prefix plus the actix route, so docs cannot drift from mounts.
+pub struct ApiScope {
+    prefix: String,
+    scope: Scope,
+    paths: Paths,
+    schemas: Vec<(String, RefOr<Schema>)>,
+    aliases: Vec<(String, AliasServices)>,
+}
+
+/// Mounts a scope's handlers again on an alias scope
+pub type AliasServices = fn(Scope) -> Scope;
+
+/// What happens on a deprecated alias, from API_DEPRECATED_ALIASES (serve|redirect, default serve)
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AliasMode {
+    /// Answer like the canonical path, plus Deprecation/Link headers
+    Serve,
+    /// 308 to the canonical path (method and body are preserved)
+    Redirect,
+}
+
+static ALIAS_MODE: Lazy<AliasMode> = Lazy::new(|| match env::var("API_DEPRECATED_ALIASES").as_deref() {
+    Ok("redirect") => AliasMode::Redirect,
+    _ => AliasMode::Serve,
+});
+
+impl ApiScope {
+    pub fn new(prefix: &str) -> Self {
+        Self {
+            prefix: prefix.to_string(),
+            scope: web::scope(prefix),
+            paths: Paths::new(),
+            schemas: Vec::new(),
+            aliases: Vec::new(),
+        }
+    }
+
+    pub fn service<H>(mut self, handler: H) -> Self
+    where
+        H: HttpServiceFactory + utoipa::Path + utoipa::__dev::SchemaReferences + for<'t> utoipa::__dev::Tags<'t> + 'static,
+    {
+        let path = format!("{}{}{}", API_PREFIX, self.prefix, H::path());
+        let mut operation = H::operation();
+        let tags: Vec<String> = H::tags().into_iter().map(str::to_string).collect();
+        if !tags.is_empty() {
+            operation.tags = Some(tags);
+        }
+        let item = PathItem::from_http_methods(H::methods(), operation);
+        match self.paths.paths.get_mut(&path) {
+            Some(existing) => existing.merge_operations(item),
+            None => {
+                self.paths.paths.insert(path, item);
+            }
+        }
+        H::schemas(&mut self.schemas);
+        self.scope = self.scope.service(handler);
+        self
+    }
+
+    /// Keeps an old prefix working for clients that still use it. `services` mounts the same
+    /// handlers as the canonical scope; the docs list the alias as deprecated copies of them.
+    pub fn deprecated_alias(mut self, prefix: &str, services: AliasServices) -> Self {
+        self.aliases.push((prefix.to_string(), services));
+        self
+    }
+
+    pub fn mount(self, cfg: &mut web::ServiceConfig) {
+        let canonical = format!("{}{}", API_PREFIX, self.prefix);
+        for (alias, services) in self.aliases {
+            let target = AliasTarget { from: format!("{}{}", API_PREFIX, alias), to: canonical.clone() };
+            let scope = web::scope(&alias);
+            let scope = match *ALIAS_MODE {
+                AliasMode::Serve => services(scope),
+                AliasMode::Redirect => {
+                    let target = target.clone();
+                    scope.default_service(web::to(move |req: HttpRequest| redirect_to_canonical(target.clone(), req)))
+                }
+            };
+            cfg.service(scope.wrap(middleware::from_fn(move |req, next| mark_deprecated(target.clone(), req, next))));
+        }
+        cfg.service(self.scope);
+    }
+
+    /// The documented paths: canonical ones, then each alias with its operations marked deprecated
+    fn documented_paths(&self) -> Paths {
+        let mut paths = self.paths.clone();
+        let canonical = format!("{}{}", API_PREFIX, self.prefix);
+        for (alias, _) in &self.aliases {
+            let from = format!("{}{}", API_PREFIX, alias);
+            for (path, item) in &self.paths.paths {
+                let Some(rest) = path.strip_prefix(&canonical) else { continue };
+                let mut item = item.clone();
+                for op in operations(&mut item) {
+                    op.deprecated = Some(Deprecated::True);
+                    op.operation_id = op.operation_id.take().map(|id| format!("{}_deprecated", id));
+                    op.description = Some(format!(
+                        "Deprecated alias of `{}{}`; with API_DEPRECATED_ALIASES=redirect it answers 308 to it",
+                        canonical, rest
+                    ));
+                }
+                paths.paths.insert(format!("{}{}", from, rest), item);
+            }
+        }
+        paths
+    }
+}
+
+fn operations(item: &mut PathItem) -> impl Iterator<Item = &mut Operation> {
+    [&mut item.get, &mut item.put, &mut item.post, &mut item.delete, &mut item.options, &mut item.head, &mut item.patch, &mut item.trace]
+        .into_iter()
+        .flatten()
+}
+
+#[derive(Clone)]
+struct AliasTarget {
+    /// Full alias prefix, e.g. /api/traficmap
+    from: String,
+    /// Full canonical prefix
+    to: String,
+}
+
+impl AliasTarget {
+    fn canonical_uri(&self, req_path: &str, query: &str) -> String {
+        let rest = req_path.strip_prefix(&self.from).unwrap_or("");
+        if query.is_empty() {
+            format!("{}{}", self.to, rest)
+        } else {
+            format!("{}{}?{}", self.to, rest, query)
+        }
+    }
+}
+
+async fn redirect_to_canonical(target: AliasTarget, req: HttpRequest) -> HttpResponse {
+    let location = target.canonical_uri(req.path(), req.query_string());
+    HttpResponse::PermanentRedirect()
+        .insert_header((header::LOCATION, location))
+        .finish()
+}
+
+/// Tags alias responses per RFC 9745 (Deprecation) with a successor-version link
+async fn mark_deprecated(
+    target: AliasTarget,
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let successor = target.canonical_uri(req.path(), "");
+    warn!("Deprecated path {} used by {}; clients should move to {}", req.path(), crate::client_ip::of_request(&req), successor);
+    let mut res = next.call(req).await?;
+    let headers = res.headers_mut();
+    headers.insert(header::HeaderName::from_static("deprecation"), HeaderValue::from_static("true"));
+    if let Ok(link) = HeaderValue::from_str(&format!("<{}>; rel=\"successor-version\"", successor)) {
+        headers.insert(header::LINK, link);
+    }
+    Ok(res)
+}
+
+/// Mounts every scope; called per worker from the `/api` scope in `main.rs`
+pub fn configure(cfg: &mut web::ServiceConfig, scopes: Vec<ApiScope>) {
+    for scope in scopes {
+        scope.mount(cfg);
+    }
+}
+
+/// Builds the OpenAPI document from the same scopes that `configure` mounts
+pub fn openapi(scopes: Vec<ApiScope>) -> OpenApi {
+    let mut paths = Paths::new();
+    let mut components = Components::new();
+    for scope in scopes {
+        paths.merge(scope.documented_paths());
+        components.schemas.extend(scope.schemas);
+    }
+    OpenApiBuilder::new()
+        .info(Info::new(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")))
+        .paths(paths)
+        .components(Some(components))
+        .build()
+}
@@ -0,0 +1,264 @@
+//! Historical points cut into fixed time frames, for animating past traffic on the map:
+//! `GET /api/replay` answers every frame at once, `GET /api/replay/ws` sends them one by one
+//! over a WebSocket at a multiple of real time.
+
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use actix_ws::{CloseCode, CloseReason, Message, MessageStream, Session};
+use chrono::{DateTime, TimeDelta, Utc};
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use utoipa::ToSchema;
+
+use crate::database::model::points::Model as PointModel;
+use crate::database::store::{BBox, PointFilter, PointOrder, PointStore, TenantScope};
+use crate::tenant;
+use super::error::{ApiError, ApiErrorBody};
+use super::registry::ApiScope;
+use super::validate;
+
+const DEFAULT_STEP_SECONDS: i64 = 60;
+/// Frames one replay may cover, a week of minutes
+const MAX_FRAMES: i64 = 7 * 24 * 60;
+/// Points `GET /api/replay` answers with at once; the WebSocket reads a frame at a time
+const MAX_POINTS: u64 = 200_000;
+const DEFAULT_SPEED: f64 = 60.0;
+const MAX_SPEED: f64 = 86_400.0;
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct ReplayQueryParams {
+    pub lat1: f64,
+    pub lng1: f64,
+    pub lat2: f64,
+    pub lng2: f64,
+    #[serde(rename = "dateStart")]
+    pub date_start: DateTime<Utc>,
+    #[serde(rename = "dateEnd")]
+    pub date_end: DateTime<Utc>,
+    /// Seconds of history per frame (default 60)
+    pub step: Option<i64>,
+    /// Seconds of history played per second, WebSocket only (default 60)
+    pub speed: Option<f64>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct ReplayPoint {
+    #[serde(rename = "randomizedId")]
+    pub randomized_id: i64,
+    pub lat: f64,
+    pub lng: f64,
+    pub spd: f64,
+    pub azm: f64,
+    pub timestamp: Option<DateTime<Utc>>,
+    pub anomaly: Option<bool>,
+}
+
+impl From<PointModel> for ReplayPoint {
+    fn from(p: PointModel) -> Self {
+        Self { randomized_id: p.randomized_id, lat: p.lat, lng: p.lng, spd: p.spd, azm: p.azm, timestamp: p.timestamp, anomaly: p.anomaly }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct ReplayFrame {
+    /// Start of the frame; it holds the points up to the next frame's start
+    pub start: DateTime<Utc>,
+    /// In time order; empty frames are kept so that the animation keeps its pace
+    pub points: Vec<ReplayPoint>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct ReplayResponse {
+    #[serde(rename = "stepSeconds")]
+    pub step_seconds: i64,
+    pub frames: Vec<ReplayFrame>,
+}
+
+/// The validated request: area, frame starts and the pace of the WebSocket
+struct Replay {
+    bbox: BBox,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    step: TimeDelta,
+    frames: i64,
+    /// Wall time between WebSocket frames
+    pace: Duration,
+    /// Scope of the request, for the WebSocket task that outlives it
+    tenant: Option<TenantScope>,
+}
+
+impl Replay {
+    fn parse(qp: &ReplayQueryParams) -> Result<Self, ApiError> {
+        validate::area(Some(qp.lat1), Some(qp.lng1), Some(qp.lat2), Some(qp.lng2), Some(qp.date_start), Some(qp.date_end))?;
+        let step = qp.step.unwrap_or(DEFAULT_STEP_SECONDS);
+        if step <= 0 {
+            return Err(ApiError::bad_param("step", "step must be a positive number of seconds"));
+        }
+        let frames = ((qp.date_end - qp.date_start).num_seconds() / step) + 1;
+        if frames > MAX_FRAMES {
+            return Err(ApiError::bad_param("step", format!("{} frames of {} s exceed {}; raise step or shorten the range", frames, step, MAX_FRAMES)));
+        }
+        let speed = qp.speed.unwrap_or(DEFAULT_SPEED);
+        if !(speed > 0.0 && speed <= MAX_SPEED) {
+            return Err(ApiError::bad_param("speed", format!("speed must be within (0, {}]", MAX_SPEED)));
+        }
+        Ok(Self {
+            bbox: BBox::from_corners(qp.lat1, qp.lng1, qp.lat2, qp.lng2),
+            start: qp.date_start,
+            end: qp.date_end,
+            step: TimeDelta::seconds(step),
+            frames,
+            pace: Duration::from_secs_f64(step as f64 / speed),
+            tenant: tenant::current(),
+        })
+    }
+
+    /// Points of the replay from `since` through `until`, inclusive
+    fn filter(&self, since: DateTime<Utc>, until: DateTime<Utc>) -> PointFilter {
+        PointFilter {
+            bbox: Some(self.bbox),
+            since: Some(since),
+            until: Some(until.min(self.end)),
+            tenant: self.tenant.clone(),
+            ..Default::default()
+        }
+    }
+
+    fn frame_start(&self, n: i64) -> DateTime<Utc> {
+        self.start + self.step * n as i32
+    }
+}
+
+#[utoipa::path(
+    get,
+    tag = "Replay",
+    params(
+        ("lat1" = f64, Query, description = "First latitude (corner)"),
+        ("lng1" = f64, Query, description = "First longitude (corner)"),
+        ("lat2" = f64, Query, description = "Second latitude (opposite corner)"),
+        ("lng2" = f64, Query, description = "Second longitude (opposite corner)"),
+        ("dateStart" = DateTime<chrono::Utc>, Query, description = "Start of the replay (inclusive)"),
+        ("dateEnd" = DateTime<chrono::Utc>, Query, description = "End of the replay (inclusive)"),
+        ("step" = i64, Query, description = "Seconds of history per frame, default 60; at most 10080 frames"),
+    ),
+    responses(
+        (status = 200, description = "Every frame of the range, empty ones included", body = ReplayResponse),
+        (status = 400, description = "Invalid parameters, or more than 200000 points; narrow the request or use /api/replay/ws", body = ApiErrorBody),
+        (status = 500, description = "Server Vzorvalsya", body = ApiErrorBody)
+    )
+)]
+
+/// Points of an area grouped into fixed time frames, for animating past traffic
+#[get("")]
+pub async fn get_replay(
+    store: web::Data<dyn PointStore>,
+    qp: web::Query<ReplayQueryParams>,
+) -> Result<HttpResponse, ApiError> {
+    let started = Instant::now();
+    let replay = Replay::parse(&qp)?;
+    let rows = store
+        .find(&replay.filter(replay.start, replay.end), PointOrder::TimestampAsc, Some(MAX_POINTS + 1))
+        .await
+        .map_err(|e| {
+            error!("Replay query failed: {}", e);
+            ApiError::Internal
+        })?;
+    if rows.len() as u64 > MAX_POINTS {
+        return Err(ApiError::bad_request(format!(
+            "More than {} points; narrow the area or range, or stream them from /api/replay/ws",
+            MAX_POINTS
+        )));
+    }
+
+    let mut frames: Vec<ReplayFrame> = (0..replay.frames).map(|n| ReplayFrame { start: replay.frame_start(n), points: Vec::new() }).collect();
+    let step = replay.step.num_seconds();
+    let points = rows.len();
+    for row in rows {
+        let Some(ts) = row.timestamp else { continue };
+        let n = ((ts - replay.start).num_seconds() / step) as usize;
+        if let Some(frame) = frames.get_mut(n) {
+            frame.points.push(ReplayPoint::from(row));
+        }
+    }
+    info!("Replay: {} points in {} frames of {} s, took={:?}", points, frames.len(), step, started.elapsed());
+    Ok(HttpResponse::Ok().json(ReplayResponse { step_seconds: step, frames }))
+}
+
+#[utoipa::path(
+    get,
+    tag = "Replay",
+    params(
+        ("lat1" = f64, Query, description = "First latitude (corner)"),
+        ("lng1" = f64, Query, description = "First longitude (corner)"),
+        ("lat2" = f64, Query, description = "Second latitude (opposite corner)"),
+        ("lng2" = f64, Query, description = "Second longitude (opposite corner)"),
+        ("dateStart" = DateTime<chrono::Utc>, Query, description = "Start of the replay (inclusive)"),
+        ("dateEnd" = DateTime<chrono::Utc>, Query, description = "End of the replay (inclusive)"),
+        ("step" = i64, Query, description = "Seconds of history per frame, default 60; at most 10080 frames"),
+        ("speed" = f64, Query, description = "Seconds of history played per second, default 60 (a frame of 60 s every second); at most 86400"),
+    ),
+    responses(
+        (status = 101, description = "WebSocket sending each frame as a text message with a ReplayFrame, then closing normally after the last one", body = ReplayFrame),
+        (status = 400, description = "Invalid parameters, or not a WebSocket handshake", body = ApiErrorBody),
+    )
+)]
+
+/// The frames of `GET /api/replay` as they play, read from the store one frame at a time
+#[get("/ws")]
+pub async fn replay_socket(
+    store: web::Data<dyn PointStore>,
+    qp: web::Query<ReplayQueryParams>,
+    req: HttpRequest,
+    body: web::Payload,
+) -> Result<HttpResponse, ApiError> {
+    let replay = Replay::parse(&qp)?;
+    let (response, session, messages) = actix_ws::handle(&req, body).map_err(|e| ApiError::bad_request(e.to_string()))?;
+    debug!("Replay socket: {} frames every {:?}", replay.frames, replay.pace);
+    actix_web::rt::spawn(play(store.into_inner(), replay, session, messages));
+    Ok(response)
+}
+
+/// Sends a frame per tick until the last one or until the client goes away
+async fn play(store: std::sync::Arc<dyn PointStore>, replay: Replay, mut session: Session, mut messages: MessageStream) {
+    let mut ticker = tokio::time::interval(replay.pace);
+    let mut next = 0;
+    let reason = loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                if next == replay.frames {
+                    break Some(CloseReason { code: CloseCode::Normal, description: Some("end of replay".to_string()) });
+                }
+                let start = replay.frame_start(next);
+                let filter = replay.filter(start, start + replay.step - TimeDelta::microseconds(1));
+                let frame = match store.find(&filter, PointOrder::TimestampAsc, None).await {
+                    Ok(rows) => ReplayFrame { start, points: rows.into_iter().map(ReplayPoint::from).collect() },
+                    Err(e) => {
+                        error!("Replay frame query failed: {}", e);
+                        break Some(CloseReason { code: CloseCode::Error, description: Some("query failed".to_string()) });
+                    }
+                };
+                let text = serde_json::to_string(&frame).unwrap_or_default();
+                if session.text(text).await.is_err() {
+                    return;
+                }
+                next += 1;
+            }
+            message = messages.recv() => match message {
+                Some(Ok(Message::Ping(bytes))) => {
+                    if session.pong(&bytes).await.is_err() {
+                        return;
+                    }
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break None,
+                Some(Ok(_)) => {}
+            },
+        }
+    };
+    let _ = session.close(reason).await;
+}
+
+pub fn routes() -> ApiScope {
+    ApiScope::new("/replay")
+        .service(get_replay)
+        .service(replay_socket)
+}
@@ -0,0 +1,133 @@
+use actix_web::{get, web, HttpResponse};
+use chrono::{DateTime, NaiveDate, Utc};
+use log::error;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use crate::reports::{self, ReportConfig, StoredReport};
+use crate::tenant;
+use super::error::{ApiError, ApiErrorBody};
+use super::registry::ApiScope;
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct ReportFile {
+    /// File name, to fetch from `GET /api/reports/{name}`
+    pub name: String,
+    /// `html` or `pdf`
+    pub format: String,
+    pub url: String,
+    pub bytes: u64,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct Report {
+    /// Monday the week starts on (UTC)
+    #[serde(rename = "weekStart")]
+    pub week_start: NaiveDate,
+    /// Sunday the week ends on, inclusive
+    #[serde(rename = "weekEnd")]
+    pub week_end: NaiveDate,
+    /// HTML first, then the PDF when REPORTS_PDF_COMMAND printed one
+    pub files: Vec<ReportFile>,
+}
+
+impl From<StoredReport> for Report {
+    fn from(r: StoredReport) -> Self {
+        Self {
+            week_start: r.week_start,
+            week_end: r.week_start + chrono::Duration::days(6),
+            files: r
+                .files
+                .into_iter()
+                .map(|f| ReportFile {
+                    url: format!("/api/reports/{}", f.name),
+                    name: f.name,
+                    format: f.format.to_string(),
+                    bytes: f.bytes,
+                    created_at: f.created_at,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct ReportList {
+    /// Newest week first
+    pub reports: Vec<Report>,
+}
+
+fn enabled(config: Option<web::Data<ReportConfig>>) -> Result<web::Data<ReportConfig>, ApiError> {
+    config.ok_or_else(|| ApiError::ServiceUnavailable("Reports are disabled (REPORTS_DIR not set)".to_string()))
+}
+
+#[utoipa::path(
+    get,
+    tag = "Reports",
+    responses(
+        (status = 200, description = "Weekly traffic reports on disk; with tenants, those of the request's tenant", body = ReportList),
+        (status = 503, description = "Reports are disabled", body = ApiErrorBody),
+        (status = 500, description = "Server Vzorvalsya", body = ApiErrorBody),
+    )
+)]
+
+/// Weekly reports the report job has written to REPORTS_DIR, those of the request's tenant
+#[get("")]
+pub async fn list_reports(config: Option<web::Data<ReportConfig>>) -> Result<HttpResponse, ApiError> {
+    let config = enabled(config)?;
+    let dir = config.dir_of(tenant::current().as_ref());
+    let stored = match reports::list(&dir) {
+        Ok(stored) => stored,
+        // A tenant's directory appears with its first report
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => {
+            error!("Listing reports in {} failed: {}", dir.display(), e);
+            return Err(ApiError::Internal);
+        }
+    };
+    let reports = stored.into_iter().map(Report::from).collect();
+    Ok(HttpResponse::Ok().json(ReportList { reports }))
+}
+
+#[utoipa::path(
+    get,
+    tag = "Reports",
+    params(
+        ("name" = String, Path, description = "File name from the list, e.g. weekly-2025-01-06.html"),
+    ),
+    responses(
+        (status = 200, description = "The report file: standalone HTML, or PDF for a `.pdf` name", content_type = "text/html"),
+        (status = 404, description = "No such report for the request's tenant", body = ApiErrorBody),
+        (status = 503, description = "Reports are disabled", body = ApiErrorBody),
+    )
+)]
+
+#[get("/{name}")]
+pub async fn get_report(
+    config: Option<web::Data<ReportConfig>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let config = enabled(config)?;
+    let name = path.into_inner();
+    // Only names shaped like a report reach the file system
+    let Some((_, format)) = reports::parse_name(&name) else {
+        return Err(ApiError::NotFound(format!("No report named {}", name)));
+    };
+    let body = match tokio::fs::read(config.dir_of(tenant::current().as_ref()).join(&name)).await {
+        Ok(body) => body,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err(ApiError::NotFound(format!("No report named {}", name))),
+        Err(e) => {
+            error!("Reading report {} failed: {}", name, e);
+            return Err(ApiError::Internal);
+        }
+    };
+    let content_type = if format == "pdf" { "application/pdf" } else { "text/html; charset=utf-8" };
+    Ok(HttpResponse::Ok().content_type(content_type).body(body))
+}
+
+pub fn routes() -> ApiScope {
+    ApiScope::new("/reports")
+        .service(list_reports)
+        .service(get_report)
+}
@@ -0,0 +1,122 @@
+use crate::geo;
+
+/// Largest `sample=N` accepted by the listing endpoints
+pub const MAX_SAMPLE: usize = 10_000;
+
+/// Picks up to `n` items spread over space: the extent of the items is cut into about `n`
+/// cells, every non-empty cell gets an equal share (cells with fewer items give the rest
+/// away), and each cell contributes items evenly spaced through its own order.
+/// The result keeps the input order, so sorted or trip-grouped rows stay that way.
+pub fn stratified<T>(items: Vec<T>, n: usize, pos: impl Fn(&T) -> (f64, f64)) -> Vec<T> {
+    if items.len() <= n {
+        return items;
+    }
+    if n == 0 {
+        return Vec::new();
+    }
+
+    let coords: Vec<(f64, f64)> = items.iter().map(&pos).collect();
+    let (mut lat_min, mut lat_max, mut lng_min, mut lng_max) = (f64::MAX, f64::MIN, f64::MAX, f64::MIN);
+    for &(lat, lng) in &coords {
+        lat_min = lat_min.min(lat);
+        lat_max = lat_max.max(lat);
+        lng_min = lng_min.min(lng);
+        lng_max = lng_max.max(lng);
+    }
+    let side = (n as f64).sqrt().ceil() as usize;
+    let cell_of = |span_min: f64, span_max: f64, v: f64| -> usize {
+        let span = span_max - span_min;
+        if span <= 0.0 { 0 } else { (((v - span_min) / span) * side as f64).floor().min((side - 1) as f64) as usize }
+    };
+
+    // Item indices per cell, in input order
+    let mut cells: Vec<Vec<usize>> = vec![Vec::new(); side * side];
+    for (i, &(lat, lng)) in coords.iter().enumerate() {
+        cells[cell_of(lat_min, lat_max, lat) * side + cell_of(lng_min, lng_max, lng)].push(i);
+    }
+    cells.retain(|c| !c.is_empty());
+
+    // Equal quota per cell, handing out what small cells cannot use
+    let mut quotas = vec![0usize; cells.len()];
+    let mut left = n;
+    while left > 0 {
+        let open: Vec<usize> = (0..cells.len()).filter(|&c| quotas[c] < cells[c].len()).collect();
+        let share = (left / open.len()).max(1);
+        for c in open {
+            if left == 0 {
+                break;
+            }
+            let take = share.min(cells[c].len() - quotas[c]).min(left);
+            quotas[c] += take;
+            left -= take;
+        }
+    }
+
+    let mut keep = vec![false; items.len()];
+    for (cell, q) in cells.iter().zip(quotas) {
+        let m = cell.len();
+        for j in 0..q {
+            keep[cell[(2 * j + 1) * m / (2 * q)]] = true;
+        }
+    }
+    items.into_iter().zip(keep).filter_map(|(item, k)| k.then_some(item)).collect()
+}
+
+/// Up to `n` items evenly spaced through `items`, keeping the first and the last
+pub fn evenly<T>(items: Vec<T>, n: usize) -> Vec<T> {
+    let len = items.len();
+    if len <= n {
+        return items;
+    }
+    let mut keep = vec![false; len];
+    match n {
+        0 => {}
+        1 => keep[0] = true,
+        _ => (0..n).for_each(|i| keep[i * (len - 1) / (n - 1)] = true),
+    }
+    items.into_iter().zip(keep).filter_map(|(item, k)| k.then_some(item)).collect()
+}
+
+/// Ramer–Douglas–Peucker: drops the points of a route that lie within `tolerance_m` meters of
+/// the line through the points kept around them. The first and last points stay, and so does
+/// every point `anchor` picks, each stretch between two of them being simplified on its own.
+pub fn simplified<T>(items: Vec<T>, tolerance_m: f64, pos: impl Fn(&T) -> (f64, f64), anchor: impl Fn(usize) -> bool) -> Vec<T> {
+    let len = items.len();
+    if len <= 2 {
+        return items;
+    }
+    // Meters on a plane tangent at the first point, plenty for the length of a trip
+    let (lat0, lng0) = pos(&items[0]);
+    let scale = lat0.to_radians().cos();
+    let xy: Vec<(f64, f64)> = items
+        .iter()
+        .map(|item| {
+            let (lat, lng) = pos(item);
+            (geo::wrap_lng(lng - lng0) * scale * geo::METERS_PER_DEGREE, (lat - lat0) * geo::METERS_PER_DEGREE)
+        })
+        .collect();
+
+    let anchors: Vec<usize> = std::iter::once(0).chain((1..len - 1).filter(|&i| anchor(i))).chain(std::iter::once(len - 1)).collect();
+    let mut keep = vec![false; len];
+    for &i in &anchors {
+        keep[i] = true;
+    }
+    let mut stack: Vec<(usize, usize)> = anchors.windows(2).map(|w| (w[0], w[1])).collect();
+    while let Some((a, b)) = stack.pop() {
+        let (far, dist) = (a + 1..b).map(|i| (i, segment_distance(xy[i], xy[a], xy[b]))).fold((a, 0.0), |best, d| if d.1 > best.1 { d } else { best });
+        if dist > tolerance_m {
+            keep[far] = true;
+            stack.push((a, far));
+            stack.push((far, b));
+        }
+    }
+    items.into_iter().zip(keep).filter_map(|(item, k)| k.then_some(item)).collect()
+}
+
+/// Distance from `p` to the segment `a`–`b`, plane coordinates
+fn segment_distance(p: (f64, f64), a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let len2 = dx * dx + dy * dy;
+    let t = if len2 == 0.0 { 0.0 } else { (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / len2).clamp(0.0, 1.0) };
+    ((p.0 - a.0 - t * dx).powi(2) + (p.1 - a.1 - t * dy).powi(2)).sqrt()
+}
@@ -0,0 +1,79 @@
+//! Server-Sent Events of a `Feed` for `GET /api/anomalies/stream` and `GET /api/alerts/stream`:
+//! one `id:`/`data:` event per anomalous trip or alert and a comment line as heartbeat, so that
+//! proxies keep the connection open and browsers notice a dead one.
+
+use actix_web::http::header::{self, ContentEncoding};
+use actix_web::web::Bytes;
+use actix_web::{Error, HttpRequest, HttpResponse};
+use futures_util::stream;
+use log::error;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::Interval;
+
+use crate::database::store::TenantScope;
+use crate::feed::{Feed, FeedEvent};
+
+const HEARTBEAT: &[u8] = b": heartbeat\n\n";
+
+/// Event id the browser sends when it reconnects
+pub fn last_event_id(req: &HttpRequest) -> Option<i64> {
+    req.headers().get("last-event-id").and_then(|v| v.to_str().ok()).and_then(|v| v.trim().parse().ok())
+}
+
+struct State<T> {
+    feed: Arc<Feed<T>>,
+    rx: broadcast::Receiver<Arc<T>>,
+    pending: VecDeque<Arc<T>>,
+    ticker: Interval,
+    /// Id of the last event sent, so replays after a lag never repeat one
+    last: Option<i64>,
+    scope: Option<TenantScope>,
+}
+
+/// The stream of events after `after` visible in `scope`, until the client goes away
+pub fn respond<T: FeedEvent>(feed: Arc<Feed<T>>, after: Option<i64>, scope: Option<TenantScope>) -> HttpResponse {
+    let (replay, rx) = feed.subscribe(after);
+    let ticker = tokio::time::interval(feed.heartbeat());
+    let state = State { feed, rx, pending: replay.into(), ticker, last: after, scope };
+    let body = stream::unfold(state, |mut s| async move {
+        loop {
+            if let Some(next) = s.pending.pop_front() {
+                if s.last.is_some_and(|l| next.event_id() <= l) || !next.visible_in(s.scope.as_ref()) {
+                    continue;
+                }
+                s.last = Some(next.event_id());
+                // The heartbeat only has to cover silences
+                s.ticker.reset();
+                return Some((Ok::<_, Error>(event(next.as_ref())), s));
+            }
+            tokio::select! {
+                _ = s.ticker.tick() => return Some((Ok(Bytes::from_static(HEARTBEAT)), s)),
+                received = s.rx.recv() => match received {
+                    Ok(next) => s.pending.push_back(next),
+                    // Fell behind the channel: pick up from the kept events instead
+                    Err(RecvError::Lagged(_)) => s.pending.extend(s.feed.since(s.last)),
+                    Err(RecvError::Closed) => return None,
+                },
+            }
+        }
+    });
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        // Compression and proxy buffering would hold events back
+        .insert_header(ContentEncoding::Identity)
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(body)
+}
+
+fn event<T: FeedEvent>(next: &T) -> Bytes {
+    match serde_json::to_string(next) {
+        Ok(data) => Bytes::from(format!("id: {}\ndata: {}\n\n", next.event_id(), data)),
+        Err(e) => {
+            error!("Stream event serialization failed: {}", e);
+            Bytes::from_static(HEARTBEAT)
+        }
+    }
+}
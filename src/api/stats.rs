@@ -0,0 +1,219 @@
+use actix_web::{get, web, HttpResponse};
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use log::{info, error, debug};
+use std::time::Instant;
+use crate::telemetry::QueryStats;
+use crate::database::store::{DailyIngest, PointFilter, PointStore, TimeBucket, TimelineRow, TripStore};
+use super::defaults::defaults;
+use super::error::{ApiError, ApiErrorBody};
+use super::heatmap::MapPoint;
+use super::registry::ApiScope;
+use super::validate;
+
+/// Upper bound on the number of buckets in one timeline, empty ones included
+const MAX_BUCKETS: i64 = 100_000;
+
+/// Days of ingest history in `/api/stats/global`, today included
+const INGEST_HISTORY_DAYS: u32 = 90;
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct TimelineQueryParams {
+    #[serde(rename = "lat1")] pub lat1: Option<f64>,
+    #[serde(rename = "lng1")] pub lng1: Option<f64>,
+    #[serde(rename = "lat2")] pub lat2: Option<f64>,
+    #[serde(rename = "lng2")] pub lng2: Option<f64>,
+    #[serde(rename = "dateStart")] pub date_start: Option<DateTime<Utc>>, // inclusive
+    #[serde(rename = "dateEnd")] pub date_end: Option<DateTime<Utc>>,     // inclusive
+    /// hour, day (default) or week
+    pub bucket: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct TimelineBucket {
+    /// Bucket start (UTC)
+    pub timestamp: DateTime<Utc>,
+    pub points: u64,
+    /// Distinct trips with at least one point in the bucket
+    pub trips: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct TimelineResponse {
+    pub bucket: String,
+    /// Consecutive buckets from the first to the last one with data; gaps are zero-filled
+    pub timeline: Vec<TimelineBucket>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct DataBounds {
+    #[serde(rename = "topLeft")]
+    pub top_left: MapPoint,
+    #[serde(rename = "bottomRight")]
+    pub bottom_right: MapPoint,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct DailyIngestCount {
+    /// UTC day
+    pub date: NaiveDate,
+    pub points: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct GlobalStatsResponse {
+    #[serde(rename = "totalPoints")]
+    pub total_points: u64,
+    #[serde(rename = "totalTrips")]
+    pub total_trips: u64,
+    /// Area covered by all stored points, null when there are none
+    pub bbox: Option<DataBounds>,
+    #[serde(rename = "firstTimestamp")]
+    pub first_timestamp: Option<DateTime<Utc>>,
+    #[serde(rename = "lastTimestamp")]
+    pub last_timestamp: Option<DateTime<Utc>>,
+    /// Points stored per day over the last 90 days, oldest first, days without ingestion as 0
+    #[serde(rename = "dailyIngest")]
+    pub daily_ingest: Vec<DailyIngestCount>,
+    /// When the totals last changed
+    #[serde(rename = "updatedAt")]
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+#[utoipa::path(
+    get,
+    tag = "Stats",
+    params(
+        ("lat1" = f64, Query, description = "First latitude (corner). Optional: without corners the deployment DEFAULT_BBOX, or every point, is counted"),
+        ("lng1" = f64, Query, description = "First longitude (corner)"),
+        ("lat2" = f64, Query, description = "Second latitude (opposite corner)"),
+        ("lng2" = f64, Query, description = "Second longitude (opposite corner)"),
+        ("dateStart" = DateTime<chrono::Utc>, Query, description = "Start of the date/time range (inclusive). Optional"),
+        ("dateEnd" = DateTime<chrono::Utc>, Query, description = "End of the date/time range (inclusive). Optional"),
+        ("bucket" = String, Query, description = "Bucket size: hour, day or week (weeks start on Monday, UTC). Optional, defaults to day"),
+    ),
+    responses(
+        (status = 200, description = "Point and trip counts per time bucket", body = TimelineResponse),
+        (status = 400, description = "Invalid parameters or too many buckets; `details.param` names the offending parameter when known", body = ApiErrorBody),
+        (status = 500, description = "Server Vzorvalsya", body = ApiErrorBody),
+    )
+)]
+
+#[get("/timeline")]
+pub async fn get_timeline(
+    store: web::Data<dyn PointStore>,
+    qp: web::Query<TimelineQueryParams>,
+) -> Result<HttpResponse, ApiError> {
+    let started = Instant::now();
+    let Some(bucket) = TimeBucket::parse(qp.bucket.as_deref().unwrap_or("day")) else {
+        return Err(ApiError::bad_param("bucket", "bucket must be 'hour', 'day' or 'week'"));
+    };
+    validate::area(qp.lat1, qp.lng1, qp.lat2, qp.lng2, qp.date_start, qp.date_end)?;
+    let bbox = defaults().optional_bbox(qp.lat1, qp.lng1, qp.lat2, qp.lng2).map_err(ApiError::bad_request)?;
+    let (date_start, date_end) = defaults().date_range(qp.date_start, qp.date_end);
+    debug!("Timeline request: bbox={:?} date=[{:?}..{:?}] bucket={}", bbox, date_start, date_end, bucket.as_str());
+
+    let filter = PointFilter { bbox, since: date_start, until: date_end, ..Default::default() };
+    let rows = match store.timeline(&filter, bucket).await {
+        Ok(r) => r,
+        Err(e) => {
+            error!("Timeline query failed: {}", e);
+            return Err(ApiError::Internal);
+        }
+    };
+
+    let timeline = fill_gaps(&rows, bucket).map_err(ApiError::bad_request)?;
+    info!(
+        "Timeline response: bucket={} buckets={} non_empty={} took={:?}",
+        bucket.as_str(), timeline.len(), rows.len(), started.elapsed()
+    );
+    let stats = QueryStats { rows_scanned: rows.len(), tiles: timeline.len() };
+    Ok(stats.attach(HttpResponse::Ok().json(TimelineResponse { bucket: bucket.as_str().to_string(), timeline })))
+}
+
+#[utoipa::path(
+    get,
+    tag = "Stats",
+    responses(
+        (status = 200, description = "Totals over the whole dataset, maintained on ingestion; behind a publication delay, computed from the points older than it", body = GlobalStatsResponse),
+        (status = 500, description = "Server Vzorvalsya"),
+    )
+)]
+
+#[get("/global")]
+pub async fn get_global_stats(store: web::Data<dyn TripStore>) -> HttpResponse {
+    let started = Instant::now();
+    let stats = match store.global_stats(INGEST_HISTORY_DAYS, None).await {
+        Ok(s) => s,
+        Err(e) => {
+            error!("Global stats query failed: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    debug!("Global stats: points={} trips={} took={:?}", stats.total_points, stats.total_trips, started.elapsed());
+    HttpResponse::Ok().json(GlobalStatsResponse {
+        total_points: stats.total_points,
+        total_trips: stats.total_trips,
+        bbox: stats.bbox.map(|b| DataBounds {
+            top_left: MapPoint { lat: b.lat_min, lng: b.lng_min },
+            bottom_right: MapPoint { lat: b.lat_max, lng: b.lng_max },
+        }),
+        first_timestamp: stats.first_ts,
+        last_timestamp: stats.last_ts,
+        daily_ingest: daily_series(&stats.daily_ingest, Utc::now().date_naive()),
+        updated_at: stats.updated_at,
+    })
+}
+
+pub fn routes() -> ApiScope {
+    ApiScope::new("/stats")
+        .service(get_timeline)
+        .service(get_global_stats)
+}
+
+// --- Helpers ---
+
+/// Expands the non-empty buckets from the database into a continuous series
+fn fill_gaps(rows: &[TimelineRow], bucket: TimeBucket) -> Result<Vec<TimelineBucket>, String> {
+    let (Some(first), Some(last)) = (rows.first(), rows.last()) else {
+        return Ok(Vec::new());
+    };
+    let step = match bucket {
+        TimeBucket::Hour => TimeDelta::hours(1),
+        TimeBucket::Day => TimeDelta::days(1),
+        TimeBucket::Week => TimeDelta::weeks(1),
+    };
+    let count = (last.bucket - first.bucket).num_seconds() / step.num_seconds() + 1;
+    if count > MAX_BUCKETS {
+        return Err(format!("date range produces more than {} buckets", MAX_BUCKETS));
+    }
+
+    let mut out = Vec::with_capacity(count as usize);
+    let mut rows = rows.iter().peekable();
+    let mut ts = first.bucket;
+    while ts <= last.bucket {
+        let (points, trips) = match rows.next_if(|r| r.bucket == ts) {
+            Some(r) => (r.points, r.trips),
+            None => (0, 0),
+        };
+        out.push(TimelineBucket { timestamp: ts, points, trips });
+        ts += step;
+    }
+    Ok(out)
+}
+
+/// The last INGEST_HISTORY_DAYS days ending at `today`, zero-filled
+fn daily_series(days: &[DailyIngest], today: NaiveDate) -> Vec<DailyIngestCount> {
+    let mut days = days.iter().peekable();
+    (0..INGEST_HISTORY_DAYS)
+        .rev()
+        .map(|back| {
+            let date = today - TimeDelta::days(back as i64);
+            // Skip anything older than the window, then take this day's row if present
+            while days.next_if(|d| d.day < date).is_some() {}
+            let points = days.next_if(|d| d.day == date).map_or(0, |d| d.points);
+            DailyIngestCount { date, points }
+        })
+        .collect()
+}
@@ -0,0 +1,428 @@
+use actix_web::{get, web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use log::{info, error, debug};
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
+use crate::database::model::points::Model as PointModel;
+use crate::database::store::{BBox, PointFilter, PointOrder, PointStore};
+use crate::geo;
+use crate::geocoding::{Geocoder, Place};
+use crate::telemetry::QueryStats;
+use super::defaults::defaults;
+use super::error::{ApiError, ApiErrorBody};
+use super::heatmap::MapPoint;
+use super::registry::ApiScope;
+use super::validate::{self, BinSize};
+
+/// Default speed (m/s) below which a point counts as standing, about 3.6 km/h
+const DEFAULT_MAX_SPEED: f64 = 1.0;
+/// Default seconds a trip must stand for the wait to count as a stop
+const DEFAULT_MIN_DURATION: f64 = 120.0;
+const DEFAULT_CLUSTER_RADIUS: f64 = 150.0;
+/// Points further apart in time do not make one stop: the tracker was off, so nothing says
+/// the vehicle stood still in between
+const MAX_POINT_GAP_SECONDS: i64 = 600;
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct StopsQueryParams {
+    pub lat1: Option<f64>,
+    pub lng1: Option<f64>,
+    pub lat2: Option<f64>,
+    pub lng2: Option<f64>,
+    #[serde(rename = "dateStart")]
+    pub date_start: Option<DateTime<Utc>>,
+    #[serde(rename = "dateEnd")]
+    pub date_end: Option<DateTime<Utc>>,
+    /// Speed in m/s below which a point counts as standing (default 1)
+    #[serde(rename = "maxSpeed")]
+    pub max_speed: Option<f64>,
+    /// Seconds a trip must stand for the wait to count as a stop (default 120)
+    #[serde(rename = "minDuration")]
+    pub min_duration: Option<f64>,
+    /// `tiles` (default) or `clusters`
+    pub aggregate: Option<String>,
+    #[serde(rename = "tileWidth")]
+    pub tile_width: Option<f64>,
+    #[serde(rename = "tileHeight")]
+    pub tile_height: Option<f64>,
+    #[serde(rename = "tileSizeMeters")]
+    pub tile_size_meters: Option<f64>,
+    pub binning: Option<String>,
+    pub resolution: Option<u8>,
+    /// Distance in meters within which stops join a cluster (default 150)
+    #[serde(rename = "clusterRadiusMeters")]
+    pub cluster_radius_meters: Option<f64>,
+    /// Name every tile or cluster (needs GEOCODER_URL)
+    pub geocode: Option<bool>,
+}
+
+/// One wait of one trip: consecutive points below `maxSpeed`
+#[derive(Debug, Clone, Copy)]
+struct Stop {
+    randomized_id: i64,
+    lat: f64,
+    lng: f64,
+    seconds: f64,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct StopTile {
+    /// Stops located in the tile
+    pub count: usize,
+    /// Distinct trips that stopped in the tile
+    pub trips: usize,
+    /// Seconds stood in the tile, all stops together
+    #[serde(rename = "totalDwell")]
+    pub total_dwell: f64,
+    #[serde(rename = "avgDwell")]
+    pub avg_dwell: f64,
+    #[serde(rename = "topLeft")]
+    pub top_left: MapPoint,
+    #[serde(rename = "bottomRight")]
+    pub bottom_right: MapPoint,
+    /// H3 index of the cell with binning=h3
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub h3: Option<String>,
+    /// With `geocode=true`: names at the middle of the tile, when the geocoder knows them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub place: Option<Place>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct StopCluster {
+    /// Mean position of the stops in the cluster
+    pub center: MapPoint,
+    /// Meters from the center to its farthest stop
+    pub radius: f64,
+    pub count: usize,
+    pub trips: usize,
+    #[serde(rename = "totalDwell")]
+    pub total_dwell: f64,
+    #[serde(rename = "avgDwell")]
+    pub avg_dwell: f64,
+    /// With `geocode=true`: names at the center, when the geocoder knows them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub place: Option<Place>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct StopsData {
+    /// Stops found in the area and period
+    #[serde(rename = "totalStops")]
+    pub total_stops: usize,
+    /// With aggregate=tiles: tiles with at least one stop
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tiles: Option<Vec<StopTile>>,
+    /// With aggregate=clusters: clusters by stop count, largest first
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clusters: Option<Vec<StopCluster>>,
+    /// With aggregate=tiles: `tileSize` of the grid or `hexSize` of the hexagons
+    #[serde(flatten)]
+    pub size: BinSize,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct StopsResponse {
+    pub stops: StopsData,
+}
+
+#[utoipa::path(
+    get,
+    tag = "Stops",
+    params(
+    ("lat1" = f64, Query, description = "First latitude (corner). All four corners may be omitted when the deployment sets DEFAULT_BBOX"),
+    ("lng1" = f64, Query, description = "First longitude (corner)"),
+    ("lat2" = f64, Query, description = "Second latitude (opposite corner)"),
+    ("lng2" = f64, Query, description = "Second longitude (opposite corner)"),
+    ("dateStart" = DateTime<chrono::Utc>, Query, description = "Start of the date/time range (inclusive). Optional"),
+    ("dateEnd" = DateTime<chrono::Utc>, Query, description = "End of the date/time range (inclusive). Optional"),
+    ("maxSpeed" = f64, Query, description = "Speed in m/s below which a point counts as standing. Optional, defaults to 1"),
+    ("minDuration" = f64, Query, description = "Seconds a trip must stand for the wait to count as a stop. Optional, defaults to 120"),
+    ("aggregate" = String, Query, description = "tiles (default): stops per tile of the grid or H3 cell; clusters: stops grouped by distance"),
+    ("tileWidth" = f64, Query, description = "Width of each tile in degrees, with aggregate=tiles. Required unless tileSizeMeters is given"),
+    ("tileHeight" = f64, Query, description = "Height of each tile in degrees, with aggregate=tiles. Required unless tileSizeMeters is given"),
+    ("tileSizeMeters" = f64, Query, description = "Side of each tile in meters. Replaces tileWidth/tileHeight"),
+    ("binning" = String, Query, description = "Cell shape with aggregate=tiles: grid (default) or h3 for H3 hexagons, which take no tile size"),
+    ("resolution" = u8, Query, description = "H3 resolution (0..15) with binning=h3. Optional, defaults to 8"),
+    ("clusterRadiusMeters" = f64, Query, description = "With aggregate=clusters: stops within this distance of a cluster's center join it. Optional, defaults to 150"),
+    ("geocode" = bool, Query, description = "Add `place` to each tile or cluster: street, district and city from the reverse geocoder (GEOCODER_URL), busiest first while its lookup budget lasts. Ignored when no geocoder is configured. Optional"),
+    ),
+    responses(
+        (status = 200, description = "Stops per tile or per cluster", body = StopsResponse),
+        (status = 400, description = "Invalid parameters; `details.param` names the offending one when known", body = ApiErrorBody),
+        (status = 413, description = "Grid over GRID_MAX_CELLS with GRID_OVERSIZE=reject; the message names the smallest tile size or the finest resolution that fits", body = ApiErrorBody),
+        (status = 500, description = "Server Vzorvalsya", body = ApiErrorBody),
+    )
+)]
+
+/// Places where trips wait: runs of consecutive points slower than `maxSpeed` lasting at
+/// least `minDuration`, e.g. informal taxi ranks and delivery hotspots
+#[get("")]
+pub async fn get_stops(
+    store: web::Data<dyn PointStore>,
+    geocoder: Option<web::Data<Geocoder>>,
+    qp: web::Query<StopsQueryParams>,
+) -> Result<HttpResponse, ApiError> {
+    let started = Instant::now();
+    validate::area(qp.lat1, qp.lng1, qp.lat2, qp.lng2, qp.date_start, qp.date_end)?;
+    let bbox = defaults().bbox(qp.lat1, qp.lng1, qp.lat2, qp.lng2).map_err(ApiError::bad_request)?;
+    let (date_start, date_end) = defaults().date_range(qp.date_start, qp.date_end);
+
+    let max_speed = qp.max_speed.unwrap_or(DEFAULT_MAX_SPEED);
+    if max_speed.is_nan() || max_speed <= 0.0 {
+        return Err(ApiError::bad_param("maxSpeed", "maxSpeed must be > 0"));
+    }
+    let min_duration = qp.min_duration.unwrap_or(DEFAULT_MIN_DURATION);
+    if min_duration.is_nan() || min_duration < 0.0 {
+        return Err(ApiError::bad_param("minDuration", "minDuration must be >= 0"));
+    }
+    let clusters = match qp.aggregate.as_deref() {
+        None | Some("tiles") => false,
+        Some("clusters") => true,
+        Some(_) => return Err(ApiError::bad_param("aggregate", "aggregate must be tiles or clusters")),
+    };
+    let radius = qp.cluster_radius_meters.unwrap_or(DEFAULT_CLUSTER_RADIUS);
+    if radius.is_nan() || radius <= 0.0 {
+        return Err(ApiError::bad_param("clusterRadiusMeters", "clusterRadiusMeters must be > 0"));
+    }
+
+    // The grid is checked before the query so an oversized one fails fast
+    let bins = if clusters {
+        None
+    } else {
+        let tiled = qp.tile_width.is_some() || qp.tile_height.is_some() || qp.tile_size_meters.is_some();
+        let binning = validate::binning(qp.binning.as_deref(), qp.resolution, tiled)?;
+        let tile = defaults().tile_size(&bbox, qp.tile_width, qp.tile_height, qp.tile_size_meters);
+        Some(validate::fit_bins(bbox, binning, tile, qp.tile_size_meters)?)
+    };
+
+    let filter = PointFilter { bbox: Some(bbox), since: date_start, until: date_end, ..Default::default() };
+    let points = match store.find(&filter, PointOrder::TripThenTimestamp, None).await {
+        Ok(p) => p,
+        Err(e) => {
+            error!("Stops query failed: {}", e);
+            return Err(ApiError::Internal);
+        }
+    };
+    let rows_scanned = points.len();
+    let stops = detect_stops(&points, max_speed, min_duration);
+    debug!("Stops: {} points gave {} stops (maxSpeed={} minDuration={})", rows_scanned, stops.len(), max_speed, min_duration);
+
+    let mut data = match bins {
+        None => StopsData {
+            total_stops: stops.len(),
+            tiles: None,
+            clusters: Some(cluster(&stops, radius, &bbox)),
+            size: BinSize { tile_size: None, hex_size: None },
+        },
+        Some((bins, size)) => {
+            let mut counts = vec![0usize; bins.len()];
+            let mut dwell = vec![0f64; bins.len()];
+            let mut trips: HashSet<(usize, i64)> = HashSet::new();
+            for stop in &stops {
+                if let Some(idx) = bins.index_of(stop.lat, stop.lng) {
+                    counts[idx] += 1;
+                    dwell[idx] += stop.seconds;
+                    trips.insert((idx, stop.randomized_id));
+                }
+            }
+            let mut trip_counts = vec![0usize; bins.len()];
+            for (idx, _) in &trips {
+                trip_counts[*idx] += 1;
+            }
+            let tiles = bins
+                .cells()
+                .filter(|&idx| counts[idx] > 0)
+                .map(|idx| {
+                    let cell = bins.cell_bbox(idx);
+                    StopTile {
+                        count: counts[idx],
+                        trips: trip_counts[idx],
+                        total_dwell: dwell[idx],
+                        avg_dwell: dwell[idx] / counts[idx] as f64,
+                        top_left: MapPoint { lat: cell.lat_min, lng: cell.lng_min },
+                        bottom_right: MapPoint { lat: cell.lat_max, lng: cell.lng_max },
+                        h3: bins.cell_id(idx),
+                        place: None,
+                    }
+                })
+                .collect();
+            StopsData { total_stops: stops.len(), tiles: Some(tiles), clusters: None, size }
+        }
+    };
+
+    if let (Some(geocoder), Some(true)) = (&geocoder, qp.geocode) {
+        name_hotspots(geocoder, &mut data).await;
+    }
+
+    let groups = data.tiles.as_ref().map(Vec::len).or(data.clusters.as_ref().map(Vec::len)).unwrap_or(0);
+    info!(
+        "Stops response: stops={} {}={} points={} took={:?}",
+        data.total_stops, if clusters { "clusters" } else { "tiles" }, groups, rows_scanned, started.elapsed()
+    );
+    let stats = QueryStats { rows_scanned, tiles: groups };
+    Ok(stats.attach(HttpResponse::Ok().json(StopsResponse { stops: data })))
+}
+
+pub fn routes() -> ApiScope {
+    ApiScope::new("/stops")
+        .service(get_stops)
+}
+
+// --- Helpers ---
+
+/// Sets `place` on the tiles or clusters, asking about those with the most stops first
+async fn name_hotspots(geocoder: &Geocoder, data: &mut StopsData) {
+    if let Some(clusters) = &mut data.clusters {
+        // Already largest first
+        let positions: Vec<(f64, f64)> = clusters.iter().map(|c| (c.center.lat, c.center.lng)).collect();
+        for (cluster, place) in clusters.iter_mut().zip(geocoder.places(&positions).await) {
+            cluster.place = place;
+        }
+    }
+    if let Some(tiles) = &mut data.tiles {
+        let mut order: Vec<usize> = (0..tiles.len()).collect();
+        order.sort_by_key(|&i| std::cmp::Reverse(tiles[i].count));
+        let positions: Vec<(f64, f64)> = order
+            .iter()
+            .map(|&i| {
+                let t = &tiles[i];
+                ((t.top_left.lat + t.bottom_right.lat) / 2.0, (t.top_left.lng + t.bottom_right.lng) / 2.0)
+            })
+            .collect();
+        for (i, place) in order.into_iter().zip(geocoder.places(&positions).await) {
+            tiles[i].place = place;
+        }
+    }
+}
+
+/// Consecutive standing points of one trip
+struct Run {
+    randomized_id: i64,
+    first: DateTime<Utc>,
+    last: DateTime<Utc>,
+    lat_sum: f64,
+    lng_sum: f64,
+    points: usize,
+}
+
+impl Run {
+    /// The stop the run makes, if it lasted long enough
+    fn stop(self, min_duration: f64) -> Option<Stop> {
+        let seconds = (self.last - self.first).num_milliseconds() as f64 / 1000.0;
+        (seconds > 0.0 && seconds >= min_duration).then(|| Stop {
+            randomized_id: self.randomized_id,
+            lat: self.lat_sum / self.points as f64,
+            lng: self.lng_sum / self.points as f64,
+            seconds,
+        })
+    }
+}
+
+/// Stops of every trip in `points` (grouped by trip, then by time). A stop ends at the first
+/// point at or above `max_speed`, at a gap over MAX_POINT_GAP_SECONDS or at the trip's end;
+/// points without a timestamp are skipped.
+fn detect_stops(points: &[PointModel], max_speed: f64, min_duration: f64) -> Vec<Stop> {
+    let mut stops = Vec::new();
+    let mut run: Option<Run> = None;
+    for p in points {
+        let Some(ts) = p.timestamp else { continue };
+        let broken = run
+            .as_ref()
+            .is_some_and(|r| r.randomized_id != p.randomized_id || (ts - r.last).num_seconds() > MAX_POINT_GAP_SECONDS);
+        if broken || p.spd >= max_speed {
+            stops.extend(run.take().and_then(|r| r.stop(min_duration)));
+        }
+        if p.spd >= max_speed {
+            continue;
+        }
+        match run.as_mut() {
+            Some(r) => {
+                r.last = ts;
+                r.lat_sum += p.lat;
+                r.lng_sum += p.lng;
+                r.points += 1;
+            }
+            None => {
+                run = Some(Run { randomized_id: p.randomized_id, first: ts, last: ts, lat_sum: p.lat, lng_sum: p.lng, points: 1 });
+            }
+        }
+    }
+    stops.extend(run.and_then(|r| r.stop(min_duration)));
+    stops
+}
+
+/// Greedy clustering: each stop joins the cluster with the nearest center within `radius`
+/// meters, else starts one. Centers move as stops join, so the result depends on the order of
+/// the stops, which is stable for the same data. `bbox` is the area the stops were found in.
+fn cluster(stops: &[Stop], radius: f64, bbox: &BBox) -> Vec<StopCluster> {
+    struct Acc {
+        lat: f64,
+        lng: f64,
+        members: Vec<usize>,
+        trips: HashSet<i64>,
+        dwell: f64,
+    }
+    // Clusters by the radius-sized cell of the stop that started them; neighbors of a stop can
+    // only be in its own cell or the 8 around it. The cells share one width in longitude, that
+    // of `radius` at the edge of the area farthest from the equator, where it spans the most
+    // degrees: sized per stop, the cells of two stops a few meters apart would not line up.
+    let cell_lat = geo::meters_to_lat_deg(radius);
+    let cell_lng = geo::meters_to_lng_deg(radius, bbox.lat_min.abs().max(bbox.lat_max.abs()));
+    let cell_of = |lat: f64, lng: f64| ((lat / cell_lat).floor() as i64, (lng / cell_lng).floor() as i64);
+    let mut cells: HashMap<(i64, i64), Vec<usize>> = HashMap::new();
+    let mut clusters: Vec<Acc> = Vec::new();
+
+    for (i, stop) in stops.iter().enumerate() {
+        let (r, c) = cell_of(stop.lat, stop.lng);
+        let nearest = (-1..=1)
+            .flat_map(|dr| (-1..=1).map(move |dc| (r + dr, c + dc)))
+            .filter_map(|key| cells.get(&key))
+            .flatten()
+            .map(|&k| (k, geo::haversine_m(stop.lat, stop.lng, clusters[k].lat, clusters[k].lng)))
+            .filter(|(_, d)| *d <= radius)
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+        match nearest {
+            Some((k, _)) => {
+                let acc = &mut clusters[k];
+                let n = acc.members.len() as f64;
+                acc.lat = (acc.lat * n + stop.lat) / (n + 1.0);
+                acc.lng = (acc.lng * n + stop.lng) / (n + 1.0);
+                acc.members.push(i);
+                acc.trips.insert(stop.randomized_id);
+                acc.dwell += stop.seconds;
+            }
+            None => {
+                cells.entry((r, c)).or_default().push(clusters.len());
+                clusters.push(Acc {
+                    lat: stop.lat,
+                    lng: stop.lng,
+                    members: vec![i],
+                    trips: HashSet::from([stop.randomized_id]),
+                    dwell: stop.seconds,
+                });
+            }
+        }
+    }
+
+    let mut out: Vec<StopCluster> = clusters
+        .into_iter()
+        .map(|acc| StopCluster {
+            center: MapPoint { lat: acc.lat, lng: acc.lng },
+            radius: acc
+                .members
+                .iter()
+                .map(|&i| geo::haversine_m(acc.lat, acc.lng, stops[i].lat, stops[i].lng))
+                .fold(0.0, f64::max),
+            count: acc.members.len(),
+            trips: acc.trips.len(),
+            total_dwell: acc.dwell,
+            avg_dwell: acc.dwell / acc.members.len() as f64,
+            place: None,
+        })
+        .collect();
+    out.sort_by(|a, b| b.count.cmp(&a.count).then(b.total_dwell.total_cmp(&a.total_dwell)));
+    out
+}
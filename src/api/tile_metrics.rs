@@ -0,0 +1,364 @@
+use actix_web::{get, web, HttpResponse};
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use log::{info, warn, error, debug};
+use std::collections::{BTreeMap, HashSet};
+use std::thread;
+use std::time::Instant;
+use crate::telemetry::QueryStats;
+use crate::database::model::points::Model as PointModel;
+use crate::database::store::{PointFilter, PointOrder, PointStore};
+use super::defaults::defaults;
+use super::error::{ApiError, ApiErrorBody};
+use super::grid::{distinct_trips, Bins, MapMeta};
+use super::heatmap::MapPoint;
+use super::registry::ApiScope;
+use super::validate::{self, BinSize};
+
+/// Below this many points per worker, aggregation stays on one thread
+const MIN_POINTS_PER_WORKER: usize = 50_000;
+
+/// A value computed per grid cell by `/api/grid`. Each cell gets its own `State`; points are
+/// accumulated in parallel chunks whose states are merged before `finalize`.
+///
+/// To add a metric, implement this trait and register it in `registry()` under the name
+/// clients pass as `metric=`.
+pub trait TileMetric: Send + Sync + 'static {
+    type State: Default + Send;
+
+    /// One line for `GET /api/grid/metrics`
+    fn description(&self) -> &'static str;
+
+    fn accumulate(&self, state: &mut Self::State, point: &PointModel);
+
+    /// Folds a state built from another chunk of points into `state`
+    fn merge(&self, state: &mut Self::State, other: Self::State);
+
+    /// Cell value; None leaves the cell out of the response
+    fn finalize(&self, state: Self::State) -> Option<f64>;
+}
+
+/// Object-safe face of a `TileMetric`, so metrics with different states share one registry
+trait GridAggregator: Send + Sync {
+    fn description(&self) -> &'static str;
+    fn aggregate(&self, bins: &Bins, points: &[PointModel]) -> Vec<Option<f64>>;
+}
+
+impl<M: TileMetric> GridAggregator for M {
+    fn description(&self) -> &'static str {
+        TileMetric::description(self)
+    }
+
+    fn aggregate(&self, bins: &Bins, points: &[PointModel]) -> Vec<Option<f64>> {
+        let workers = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        let workers = workers.min(points.len() / MIN_POINTS_PER_WORKER).max(1);
+        let chunk = points.len().div_ceil(workers).max(1);
+
+        let accumulate = |chunk: &[PointModel]| {
+            let mut states: Vec<M::State> = (0..bins.len()).map(|_| M::State::default()).collect();
+            for p in chunk {
+                if let Some(idx) = bins.index_of(p.lat, p.lng) {
+                    self.accumulate(&mut states[idx], p);
+                }
+            }
+            states
+        };
+        let mut partials: Vec<Vec<M::State>> = if workers == 1 {
+            vec![accumulate(points)]
+        } else {
+            thread::scope(|s| {
+                let handles: Vec<_> = points.chunks(chunk).map(|c| s.spawn(move || accumulate(c))).collect();
+                handles.into_iter().map(|h| h.join().expect("tile metric worker panicked")).collect()
+            })
+        };
+
+        let mut total = partials.pop().unwrap_or_else(|| accumulate(&[]));
+        for partial in partials {
+            for (state, other) in total.iter_mut().zip(partial) {
+                self.merge(state, other);
+            }
+        }
+        total.into_iter().map(|s| self.finalize(s)).collect()
+    }
+}
+
+/// Metrics `/api/grid` can compute, by name
+#[derive(Default)]
+pub struct MetricRegistry {
+    metrics: BTreeMap<&'static str, Box<dyn GridAggregator>>,
+}
+
+impl MetricRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register<M: TileMetric>(mut self, name: &'static str, metric: M) -> Self {
+        if self.metrics.insert(name, Box::new(metric)).is_some() {
+            warn!("Tile metric '{}' registered twice; keeping the last one", name);
+        }
+        self
+    }
+}
+
+/// Built-in metrics; extensions add theirs here
+pub fn registry() -> MetricRegistry {
+    MetricRegistry::new()
+        .register("count", Count)
+        .register("uniqueTrips", UniqueTrips)
+        .register("avgSpeed", AvgSpeed)
+}
+
+/// Points in the cell
+pub struct Count;
+
+impl TileMetric for Count {
+    type State = u64;
+
+    fn description(&self) -> &'static str {
+        "Number of points in the tile"
+    }
+
+    fn accumulate(&self, state: &mut u64, _point: &PointModel) {
+        *state += 1;
+    }
+
+    fn merge(&self, state: &mut u64, other: u64) {
+        *state += other;
+    }
+
+    fn finalize(&self, state: u64) -> Option<f64> {
+        (state > 0).then_some(state as f64)
+    }
+}
+
+/// Distinct trips with a point in the cell
+pub struct UniqueTrips;
+
+impl TileMetric for UniqueTrips {
+    type State = HashSet<i64>;
+
+    fn description(&self) -> &'static str {
+        "Number of distinct trips (randomized_id) with a point in the tile"
+    }
+
+    fn accumulate(&self, state: &mut HashSet<i64>, point: &PointModel) {
+        state.insert(point.randomized_id);
+    }
+
+    fn merge(&self, state: &mut HashSet<i64>, other: HashSet<i64>) {
+        state.extend(other);
+    }
+
+    fn finalize(&self, state: HashSet<i64>) -> Option<f64> {
+        (!state.is_empty()).then_some(state.len() as f64)
+    }
+}
+
+/// Mean reported speed of the cell's points
+pub struct AvgSpeed;
+
+impl TileMetric for AvgSpeed {
+    /// (sum of speeds, number of points)
+    type State = (f64, u64);
+
+    fn description(&self) -> &'static str {
+        "Mean reported speed of the points in the tile"
+    }
+
+    fn accumulate(&self, state: &mut (f64, u64), point: &PointModel) {
+        state.0 += point.spd;
+        state.1 += 1;
+    }
+
+    fn merge(&self, state: &mut (f64, u64), other: (f64, u64)) {
+        state.0 += other.0;
+        state.1 += other.1;
+    }
+
+    fn finalize(&self, (sum, n): (f64, u64)) -> Option<f64> {
+        (n > 0).then(|| sum / n as f64)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct GridQueryParams {
+    #[serde(rename = "lat1")] pub lat1: Option<f64>,
+    #[serde(rename = "lng1")] pub lng1: Option<f64>,
+    #[serde(rename = "lat2")] pub lat2: Option<f64>,
+    #[serde(rename = "lng2")] pub lng2: Option<f64>,
+    #[serde(rename = "dateStart")] pub date_start: Option<DateTime<chrono::Utc>>, // inclusive
+    #[serde(rename = "dateEnd")] pub date_end: Option<DateTime<chrono::Utc>>,     // inclusive
+    #[serde(rename = "tileWidth")] pub tile_width: Option<f64>,
+    #[serde(rename = "tileHeight")] pub tile_height: Option<f64>,
+    /// Square tile side in meters, instead of tileWidth/tileHeight
+    #[serde(rename = "tileSizeMeters")] pub tile_size_meters: Option<f64>,
+    /// `grid` (default) or `h3`
+    pub binning: Option<String>,
+    /// H3 resolution with binning=h3 (0..=15, default 8)
+    pub resolution: Option<u8>,
+    /// Registered metric name (default count)
+    pub metric: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct GridTile {
+    pub value: f64,
+    #[serde(rename = "topLeft")]
+    pub top_left: MapPoint,
+    #[serde(rename = "bottomRight")]
+    pub bottom_right: MapPoint,
+    /// H3 index of the cell with binning=h3
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub h3: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct GridResponse {
+    pub metric: String,
+    /// Tiles with a value, row-major from the south-west corner (H3 index order for hexagons)
+    pub data: Vec<GridTile>,
+    /// `tileSize` of the grid or `hexSize` of the hexagons the points were binned into
+    #[serde(flatten)]
+    pub size: BinSize,
+    pub meta: MapMeta,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct GridMetricInfo {
+    pub name: String,
+    pub description: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct GridMetricsResponse {
+    pub metrics: Vec<GridMetricInfo>,
+}
+
+#[utoipa::path(
+    get,
+    tag = "Grid",
+    params(
+        ("lat1" = f64, Query, description = "First latitude (corner). All four corners may be omitted when the deployment sets DEFAULT_BBOX"),
+        ("lng1" = f64, Query, description = "First longitude (corner)"),
+        ("lat2" = f64, Query, description = "Second latitude (opposite corner)"),
+        ("lng2" = f64, Query, description = "Second longitude (opposite corner)"),
+        ("dateStart" = DateTime<chrono::Utc>, Query, description = "Start of the date/time range (inclusive). Optional"),
+        ("dateEnd" = DateTime<chrono::Utc>, Query, description = "End of the date/time range (inclusive). Optional"),
+        ("tileWidth" = f64, Query, description = "Width of each tile in degrees. Required unless tileSizeMeters is given"),
+        ("tileHeight" = f64, Query, description = "Height of each tile in degrees. Required unless tileSizeMeters is given"),
+        ("tileSizeMeters" = f64, Query, description = "Side of each tile in meters, converted to degrees at the middle latitude of the area. Replaces tileWidth/tileHeight"),
+        ("binning" = String, Query, description = "Cell shape: grid (default) or h3 for H3 hexagons, which take no tile size"),
+        ("resolution" = u8, Query, description = "H3 resolution (0..15) with binning=h3. Optional, defaults to 8"),
+        ("metric" = String, Query, description = "Metric to compute per tile, see /api/grid/metrics. Optional, defaults to count"),
+    ),
+    responses(
+        (status = 200, description = "Metric value per tile", body = GridResponse),
+        (status = 400, description = "Invalid parameters or unknown metric; `details.param` names the offending parameter when known", body = ApiErrorBody),
+        (status = 413, description = "Grid over GRID_MAX_CELLS with GRID_OVERSIZE=reject; the message names the smallest tile size or the finest resolution that fits", body = ApiErrorBody),
+        (status = 500, description = "Server Vzorvalsya", body = ApiErrorBody),
+    )
+)]
+
+#[get("")]
+pub async fn get_grid(
+    store: web::Data<dyn PointStore>,
+    registry: web::Data<MetricRegistry>,
+    qp: web::Query<GridQueryParams>,
+) -> Result<HttpResponse, ApiError> {
+    let started = Instant::now();
+    let name = qp.metric.clone().unwrap_or_else(|| "count".to_string());
+    if !registry.metrics.contains_key(name.as_str()) {
+        let known: Vec<&str> = registry.metrics.keys().copied().collect();
+        return Err(ApiError::bad_param("metric", format!("unknown metric '{}'; available: {}", name, known.join(", "))));
+    }
+    debug!(
+        "Grid request: metric={} corners=({:?}, {:?}), ({:?}, {:?}), date=[{:?}..{:?}], tile=({:?}, {:?}, {:?}m)",
+        name, qp.lat1, qp.lng1, qp.lat2, qp.lng2, qp.date_start, qp.date_end, qp.tile_width, qp.tile_height, qp.tile_size_meters
+    );
+    validate::area(qp.lat1, qp.lng1, qp.lat2, qp.lng2, qp.date_start, qp.date_end)?;
+    let bbox = defaults().bbox(qp.lat1, qp.lng1, qp.lat2, qp.lng2).map_err(ApiError::bad_request)?;
+    let (date_start, date_end) = defaults().date_range(qp.date_start, qp.date_end);
+    let tiled = qp.tile_width.is_some() || qp.tile_height.is_some() || qp.tile_size_meters.is_some();
+    let binning = validate::binning(qp.binning.as_deref(), qp.resolution, tiled)?;
+    let tile = defaults().tile_size(&bbox, qp.tile_width, qp.tile_height, qp.tile_size_meters);
+
+    let (bins, size) = validate::fit_bins(bbox, binning, tile, qp.tile_size_meters)?;
+    if bins.is_empty() {
+        info!("Grid degenerate area (no cells), returning empty. took={:?}", started.elapsed());
+        let meta = MapMeta::new(&bins, 0, 0, started);
+        return Ok(HttpResponse::Ok().json(GridResponse { metric: name, data: vec![], size, meta }));
+    }
+
+    let filter = PointFilter { bbox: Some(bbox), since: date_start, until: date_end, ..Default::default() };
+    let points = match store.find(&filter, PointOrder::TimestampAsc, None).await {
+        Ok(p) => p,
+        Err(e) => {
+            error!("Grid query failed: {}", e);
+            return Err(ApiError::Internal);
+        }
+    };
+    let rows_scanned = points.len();
+    let trips = distinct_trips(&points);
+
+    // CPU-bound for big areas; keep it off the async workers
+    let registry = registry.into_inner();
+    let metric_name = name.clone();
+    let aggregated = web::block(move || {
+        let values = registry.metrics[metric_name.as_str()].aggregate(&bins, &points);
+        (values, bins)
+    });
+    let (values, bins) = match aggregated.await {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Grid aggregation failed: {}", e);
+            return Err(ApiError::Internal);
+        }
+    };
+
+    let data: Vec<GridTile> = bins
+        .cells()
+        .filter_map(|idx| {
+            let value = values[idx]?;
+            let cell = bins.cell_bbox(idx);
+            Some(GridTile {
+                value,
+                top_left: MapPoint { lat: cell.lat_min, lng: cell.lng_min },
+                bottom_right: MapPoint { lat: cell.lat_max, lng: cell.lng_max },
+                h3: bins.cell_id(idx),
+            })
+        })
+        .collect();
+    info!(
+        "Grid response: metric={} tiles={} from grid={} points={} took={:?}",
+        name, data.len(), bins, rows_scanned, started.elapsed()
+    );
+    let stats = QueryStats { rows_scanned, tiles: data.len() };
+    let meta = MapMeta::new(&bins, rows_scanned, trips, started);
+    Ok(stats.attach(HttpResponse::Ok().json(GridResponse { metric: name, data, size, meta })))
+}
+
+#[utoipa::path(
+    get,
+    tag = "Grid",
+    responses(
+        (status = 200, description = "Metrics accepted by /api/grid", body = GridMetricsResponse),
+    )
+)]
+
+#[get("/metrics")]
+pub async fn get_grid_metrics(registry: web::Data<MetricRegistry>) -> HttpResponse {
+    let metrics = registry
+        .metrics
+        .iter()
+        .map(|(name, m)| GridMetricInfo { name: name.to_string(), description: m.description().to_string() })
+        .collect();
+    HttpResponse::Ok().json(GridMetricsResponse { metrics })
+}
+
+pub fn routes() -> ApiScope {
+    ApiScope::new("/grid")
+        .service(get_grid_metrics)
+        .service(get_grid)
+}
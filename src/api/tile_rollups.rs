@@ -0,0 +1,156 @@
+//! Answers from the hourly tile rollups (see `database::tile_rollup`) for map requests that line
+//! up with them: square tiles of a rolled-up size, an area on that grid and whole hours the
+//! rollup covers. Anything else, a request scoped to a tenant or a failing rollup query falls
+//! back to scanning the points.
+
+use actix_web::web;
+use chrono::{DateTime, Duration, Timelike, Utc};
+use log::warn;
+
+use super::grid::Bins;
+use crate::database::store::{BBox, TileHour, TileStatsStore};
+use crate::database::tile_rollup::{floor_hour, MICRODEGREES};
+
+/// How far a tile size or corner may be off the rollup grid, in its units
+const TOLERANCE: f64 = 1e-6;
+
+/// Per-cell totals of an aligned request, indexed like `bins`
+#[derive(Debug)]
+pub struct RollupCells {
+    pub counts: Vec<usize>,
+    pub speed_sums: Vec<f64>,
+}
+
+/// Per-hour stats of one rollup tile over the hours `[start, end)`, in time order; hours
+/// without points are left out
+#[derive(Debug)]
+pub struct RollupHours {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub hours: Vec<TileHour>,
+}
+
+/// The cells of `bins` for `[since, until]` from the rollups, or None when the request does not
+/// line up with one. `until` must be the `end_of_hour` of the last second before a whole hour.
+pub async fn read(
+    rollups: Option<&web::Data<dyn TileStatsStore>>,
+    bins: &Bins,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+) -> Option<RollupCells> {
+    let rollups = rollups?;
+    let Bins::Rect(grid) = bins else { return None };
+    if grid.bbox.crosses_antimeridian() || (grid.tile_width - grid.tile_height).abs() * MICRODEGREES > TOLERANCE {
+        return None;
+    }
+    let side = whole(grid.tile_width * MICRODEGREES)? as i32;
+    let row0 = whole(grid.bbox.lat_min / grid.tile_height)?;
+    let col0 = whole(grid.bbox.lng_min / grid.tile_width)?;
+    // A last row or column cut short by the area would take whole rollup tiles
+    if whole(grid.bbox.lat_max / grid.tile_height)? != row0 + grid.rows as i64
+        || whole(grid.bbox.lng_max / grid.tile_width)? != col0 + grid.cols as i64
+    {
+        return None;
+    }
+    let (start, end) = window(rollups, side, since, until).await?;
+
+    let (row0, col0) = (row0 as i32, col0 as i32);
+    let rows = (row0, row0 + grid.rows as i32 - 1);
+    let cols = (col0, col0 + grid.cols as i32 - 1);
+    let stats = match rollups.sum_tile_stats(side, rows, cols, start, end).await {
+        Ok(s) => s,
+        Err(e) => {
+            warn!("Tile rollup query failed, scanning points: {}", e);
+            return None;
+        }
+    };
+    let mut cells = RollupCells { counts: vec![0; bins.len()], speed_sums: vec![0.0; bins.len()] };
+    for s in stats {
+        let idx = (s.row - row0) as usize * grid.cols + (s.col - col0) as usize;
+        cells.counts[idx] = s.points as usize;
+        cells.speed_sums[idx] = s.speed_sum;
+    }
+    Some(cells)
+}
+
+/// The hours of the single rollup tile `bbox` in `[since, until]`, or None when it is not one.
+/// `until` must be the `end_of_hour` of the last second before a whole hour.
+pub async fn read_tile(
+    rollups: Option<&web::Data<dyn TileStatsStore>>,
+    bbox: &BBox,
+    since: DateTime<Utc>,
+    until: DateTime<Utc>,
+) -> Option<RollupHours> {
+    let rollups = rollups?;
+    let (width, height) = (bbox.lng_max - bbox.lng_min, bbox.lat_max - bbox.lat_min);
+    if bbox.crosses_antimeridian() || (width - height).abs() * MICRODEGREES > TOLERANCE {
+        return None;
+    }
+    let side = whole(width * MICRODEGREES)?;
+    if side <= 0 {
+        return None;
+    }
+    let row = whole(bbox.lat_min * MICRODEGREES / side as f64)? as i32;
+    let col = whole(bbox.lng_min * MICRODEGREES / side as f64)? as i32;
+    let (start, end) = window(rollups, side as i32, Some(since), Some(until)).await?;
+
+    match rollups.tile_hours(side as i32, row, col, start, end).await {
+        Ok(hours) => Some(RollupHours { start, end, hours }),
+        Err(e) => {
+            warn!("Tile rollup query failed, scanning points: {}", e);
+            None
+        }
+    }
+}
+
+/// The hours `[start, end)` grid `side` answers for `[since, until]`: whole hours it covers,
+/// outside of any tenant's scope
+async fn window(
+    rollups: &web::Data<dyn TileStatsStore>,
+    side: i32,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    // Rollups add up every tenant's points
+    if crate::tenant::current().is_some() {
+        return None;
+    }
+    // A whole-hour `until` takes in the points of its first instant, which the rollup of that
+    // hour cannot tell from the rest of it
+    let end = until.map(|t| floor_hour(t) + Duration::hours(1)).filter(|end| until == Some(*end - Duration::nanoseconds(1)))?;
+    if since.is_some_and(|s| s != floor_hour(s)) {
+        return None;
+    }
+
+    let coverage = match rollups.rollup_coverage().await {
+        Ok(c) => c.into_iter().find(|c| c.resolution == side)?,
+        Err(e) => {
+            warn!("Tile rollup coverage lookup failed, scanning points: {}", e);
+            return None;
+        }
+    };
+    // Before a rollup that reaches back to the first point there is nothing to add
+    let start = match since {
+        Some(s) if s >= coverage.from => s,
+        Some(s) if coverage.backfilled => s,
+        None if coverage.backfilled => coverage.from,
+        _ => return None,
+    };
+    (end <= coverage.through && start < end).then_some((start, end))
+}
+
+/// The `until` of a map request, read the same way whether a rollup answers it or the points
+/// are scanned: hh:59:59 takes in the rest of its hour, the points of its last second included
+pub fn end_of_hour(until: DateTime<Utc>) -> DateTime<Utc> {
+    if until.minute() == 59 && until.second() == 59 && until.nanosecond() == 0 {
+        floor_hour(until) + Duration::hours(1) - Duration::nanoseconds(1)
+    } else {
+        until
+    }
+}
+
+/// `x` when it is a whole number, give or take rounding
+fn whole(x: f64) -> Option<i64> {
+    let n = x.round();
+    ((x - n).abs() <= TOLERANCE).then_some(n as i64)
+}
@@ -0,0 +1,151 @@
+//! The temporal profile of one map tile: points, distinct trips and average speed per hour, for
+//! the chart shown when a tile of the heatmap is clicked.
+
+use actix_web::{get, web, HttpResponse};
+use chrono::{DateTime, Duration, Utc};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::time::Instant;
+use utoipa::ToSchema;
+
+use crate::database::store::{BBox, PointFilter, PointOrder, PointStore, TileStatsStore};
+use crate::database::tile_rollup::floor_hour;
+use crate::telemetry::QueryStats;
+use super::error::{ApiError, ApiErrorBody};
+use super::registry::ApiScope;
+use super::tile_rollups;
+use super::validate;
+
+/// Hours one series may cover, a leap year
+const MAX_HOURS: i64 = 366 * 24;
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct TileTimeseriesQueryParams {
+    pub lat1: f64,
+    pub lng1: f64,
+    pub lat2: f64,
+    pub lng2: f64,
+    #[serde(rename = "dateStart")]
+    pub date_start: DateTime<Utc>,
+    #[serde(rename = "dateEnd")]
+    pub date_end: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct TileHourStats {
+    /// Start of the hour (UTC)
+    pub timestamp: DateTime<Utc>,
+    pub count: usize,
+    /// Distinct trips among the points; null when the series comes from the tile rollups
+    pub trips: Option<usize>,
+    /// Null for an hour without points
+    #[serde(rename = "avgSpeed")]
+    pub avg_speed: Option<f64>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct TileTimeseriesResponse {
+    /// Every hour of the range in time order, empty ones included
+    pub data: Vec<TileHourStats>,
+    /// The series comes from the hourly tile rollups (TILE_ROLLUP_DEGREES) instead of the points
+    pub rollups: bool,
+}
+
+/// Running totals of one hour
+#[derive(Default)]
+struct Hour {
+    count: usize,
+    speed_sum: f64,
+    trips: HashSet<i64>,
+}
+
+#[utoipa::path(
+    get,
+    tag = "Timeseries",
+    params(
+        ("lat1" = f64, Query, description = "First latitude (corner of the tile)"),
+        ("lng1" = f64, Query, description = "First longitude (corner of the tile)"),
+        ("lat2" = f64, Query, description = "Second latitude (opposite corner)"),
+        ("lng2" = f64, Query, description = "Second longitude (opposite corner)"),
+        ("dateStart" = DateTime<chrono::Utc>, Query, description = "Start of the series (inclusive)"),
+        ("dateEnd" = DateTime<chrono::Utc>, Query, description = "End of the series (inclusive; hh:59:59 takes in the rest of that hour); at most 8784 hours after dateStart"),
+    ),
+    responses(
+        (status = 200, description = "Points, trips and average speed per hour of the tile", body = TileTimeseriesResponse),
+        (status = 400, description = "Invalid parameters", body = ApiErrorBody),
+        (status = 500, description = "Server Vzorvalsya", body = ApiErrorBody)
+    )
+)]
+
+/// Points, distinct trips and average speed in one tile for every hour of a range. A tile of the
+/// tile rollups over whole hours they cover is read from them, without trips.
+#[get("/timeseries")]
+pub async fn get_tile_timeseries(
+    store: web::Data<dyn PointStore>,
+    rollups: Option<web::Data<dyn TileStatsStore>>,
+    qp: web::Query<TileTimeseriesQueryParams>,
+) -> Result<HttpResponse, ApiError> {
+    let started = Instant::now();
+    validate::area(Some(qp.lat1), Some(qp.lng1), Some(qp.lat2), Some(qp.lng2), Some(qp.date_start), Some(qp.date_end))?;
+    let first = floor_hour(qp.date_start);
+    let hours = (floor_hour(qp.date_end) - first).num_hours() + 1;
+    if hours > MAX_HOURS {
+        return Err(ApiError::bad_param("dateEnd", format!("{} hours exceed {}; shorten the range", hours, MAX_HOURS)));
+    }
+    let bbox = BBox::from_corners(qp.lat1, qp.lng1, qp.lat2, qp.lng2);
+    let date_end = tile_rollups::end_of_hour(qp.date_end);
+
+    if let Some(rolled) = tile_rollups::read_tile(rollups.as_ref(), &bbox, qp.date_start, date_end).await {
+        let mut data: Vec<TileHourStats> = (0..(rolled.end - rolled.start).num_hours())
+            .map(|n| TileHourStats { timestamp: rolled.start + Duration::hours(n), count: 0, trips: None, avg_speed: None })
+            .collect();
+        for h in &rolled.hours {
+            if let Some(slot) = data.get_mut((h.hour - rolled.start).num_hours() as usize) {
+                slot.count = h.points as usize;
+                slot.avg_speed = (h.points > 0).then(|| h.speed_sum / h.points as f64);
+            }
+        }
+        info!("Tile timeseries from rollups: {} hours, took={:?}", data.len(), started.elapsed());
+        let stats = QueryStats { rows_scanned: rolled.hours.len(), tiles: data.len() };
+        return Ok(stats.attach(HttpResponse::Ok().json(TileTimeseriesResponse { data, rollups: true })));
+    }
+
+    let filter = PointFilter {
+        bbox: Some(bbox),
+        since: Some(qp.date_start),
+        until: Some(date_end),
+        ..Default::default()
+    };
+    let rows = store.find(&filter, PointOrder::TimestampAsc, None).await.map_err(|e| {
+        error!("Tile timeseries query failed: {}", e);
+        ApiError::Internal
+    })?;
+    let mut totals: Vec<Hour> = (0..hours).map(|_| Hour::default()).collect();
+    for row in &rows {
+        let Some(ts) = row.timestamp else { continue };
+        if let Some(hour) = totals.get_mut((ts - first).num_hours() as usize) {
+            hour.count += 1;
+            hour.speed_sum += row.spd;
+            hour.trips.insert(row.randomized_id);
+        }
+    }
+    let data: Vec<TileHourStats> = totals
+        .into_iter()
+        .enumerate()
+        .map(|(n, h)| TileHourStats {
+            timestamp: first + Duration::hours(n as i64),
+            count: h.count,
+            trips: Some(h.trips.len()),
+            avg_speed: (h.count > 0).then(|| h.speed_sum / h.count as f64),
+        })
+        .collect();
+    info!("Tile timeseries: {} points in {} hours, took={:?}", rows.len(), data.len(), started.elapsed());
+    let stats = QueryStats { rows_scanned: rows.len(), tiles: data.len() };
+    Ok(stats.attach(HttpResponse::Ok().json(TileTimeseriesResponse { data, rollups: false })))
+}
+
+pub fn routes() -> ApiScope {
+    ApiScope::new("/tile")
+        .service(get_tile_timeseries)
+}
@@ -0,0 +1,155 @@
+use actix_web::{get, web, HttpResponse};
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use log::{info, warn, error, debug};
+use std::f64::consts::PI;
+use std::time::Instant;
+use crate::mvt::{self, Layer, RectFeature, Value};
+use crate::telemetry::QueryStats;
+use crate::database::store::{BBox, PointFilter, PointOrder, PointStore};
+use super::registry::ApiScope;
+
+const MAX_ZOOM: u32 = 22;
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct TileQueryParams {
+    /// Optional date range start (inclusive)
+    #[serde(rename = "dateStart")]
+    pub date_start: Option<DateTime<chrono::Utc>>,
+    /// Optional date range end (inclusive)
+    #[serde(rename = "dateEnd")]
+    pub date_end: Option<DateTime<chrono::Utc>>,
+    /// Optional aggregation cell size in tile extent units (default 256 → 16x16 cells)
+    #[serde(rename = "cellSize")]
+    pub cell_size: Option<u32>,
+}
+
+#[utoipa::path(
+    get,
+    tag = "Heatmap",
+    params(
+    ("z" = u32, Path, description = "Zoom level (0..22)"),
+    ("x" = u32, Path, description = "Tile column"),
+    ("y" = u32, Path, description = "Tile row (XYZ scheme, origin top-left)"),
+    ("dateStart" = DateTime<chrono::Utc>, Query, description = "Start of the date/time range (inclusive). Optional"),
+    ("dateEnd" = DateTime<chrono::Utc>, Query, description = "End of the date/time range (inclusive). Optional"),
+    ("cellSize" = u32, Query, description = "Aggregation cell size in tile units (extent 4096); 16..4096. Optional, defaults to 256"),
+    ),
+    responses(
+        (status = 200, description = "Mapbox Vector Tile with a `heatmap` layer of count/avgSpeed cells", content_type = "application/vnd.mapbox-vector-tile"),
+        (status = 400, description = "Invalid tile coordinates"),
+        (status = 500, description = "Server Vzorvalsya"),
+    )
+)]
+
+#[get("/{z}/{x}/{y:\\d+}.mvt")]
+pub async fn get_tile(
+    store: web::Data<dyn PointStore>,
+    path: web::Path<(u32, u32, u32)>,
+    qp: web::Query<TileQueryParams>,
+) -> HttpResponse {
+    let started = Instant::now();
+    let (z, x, y) = path.into_inner();
+    debug!("MVT request: z={} x={} y={} date=[{:?}..{:?}] cell={:?}", z, x, y, qp.date_start, qp.date_end, qp.cell_size);
+
+    if z > MAX_ZOOM {
+        return HttpResponse::BadRequest().body(format!("z must be <= {}", MAX_ZOOM));
+    }
+    let n = 1u32 << z;
+    if x >= n || y >= n {
+        warn!("MVT tile out of range: z={} x={} y={}", z, x, y);
+        return HttpResponse::BadRequest().body("x and y must be < 2^z");
+    }
+    let extent = mvt::DEFAULT_EXTENT;
+    let cell = qp.cell_size.unwrap_or(256);
+    if !(16..=extent).contains(&cell) || !extent.is_multiple_of(cell) {
+        return HttpResponse::BadRequest().body("cellSize must divide 4096 and be between 16 and 4096");
+    }
+    let cells_per_side = (extent / cell) as usize;
+
+    let (lat_max, lng_min) = tile_corner(x, y, z);
+    let (lat_min, lng_max) = tile_corner(x + 1, y + 1, z);
+    let filter = PointFilter {
+        bbox: Some(BBox { lat_min, lat_max, lng_min, lng_max }),
+        since: qp.date_start,
+        until: qp.date_end,
+        ..Default::default()
+    };
+    let points = match store.find(&filter, PointOrder::TimestampAsc, None).await {
+        Ok(p) => p,
+        Err(e) => {
+            error!("MVT query failed: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let rows_scanned = points.len();
+
+    // Bin by position inside the tile in Web Mercator space
+    let mut counts = vec![0u64; cells_per_side * cells_per_side];
+    let mut speed_sums = vec![0f64; cells_per_side * cells_per_side];
+    let scale = n as f64;
+    for p in points {
+        let (tx, ty) = mercator_tile_xy(p.lat, p.lng, scale);
+        let px = ((tx - x as f64) * extent as f64).floor();
+        let py = ((ty - y as f64) * extent as f64).floor();
+        let c = ((px.max(0.0) as u32) / cell).min(cells_per_side as u32 - 1) as usize;
+        let r = ((py.max(0.0) as u32) / cell).min(cells_per_side as u32 - 1) as usize;
+        counts[r * cells_per_side + c] += 1;
+        speed_sums[r * cells_per_side + c] += p.spd;
+    }
+
+    let mut features = Vec::new();
+    for r in 0..cells_per_side {
+        for c in 0..cells_per_side {
+            let count = counts[r * cells_per_side + c];
+            if count == 0 { continue; }
+            let (x0, y0) = ((c as u32 * cell) as i32, (r as u32 * cell) as i32);
+            features.push(RectFeature {
+                x0,
+                y0,
+                x1: x0 + cell as i32,
+                y1: y0 + cell as i32,
+                properties: vec![
+                    ("count", Value::Uint(count)),
+                    ("avgSpeed", Value::Double(speed_sums[r * cells_per_side + c] / count as f64)),
+                ],
+            });
+        }
+    }
+    let tiles = features.len();
+    let body = mvt::encode_tile(&[Layer { name: "heatmap".to_string(), extent, features }]);
+
+    info!(
+        "MVT response: z={} x={} y={} cells={} points_count={} bytes={} took={:?}",
+        z, x, y, tiles, rows_scanned, body.len(), started.elapsed()
+    );
+    QueryStats { rows_scanned, tiles }.attach(
+        HttpResponse::Ok()
+            .content_type("application/vnd.mapbox-vector-tile")
+            .body(body),
+    )
+}
+
+pub fn routes() -> ApiScope {
+    ApiScope::new("/tiles")
+        .service(get_tile)
+}
+
+// --- Helpers ---
+
+/// (lat, lng) of the top-left corner of XYZ tile (x, y) at zoom z
+fn tile_corner(x: u32, y: u32, z: u32) -> (f64, f64) {
+    let n = (1u64 << z) as f64;
+    let lng = x as f64 / n * 360.0 - 180.0;
+    let lat = (PI * (1.0 - 2.0 * y as f64 / n)).sinh().atan().to_degrees();
+    (lat, lng)
+}
+
+/// Fractional XYZ tile coordinates of a point at `scale` = 2^z
+fn mercator_tile_xy(lat: f64, lng: f64, scale: f64) -> (f64, f64) {
+    let lat_rad = lat.to_radians();
+    let tx = (lng + 180.0) / 360.0 * scale;
+    let ty = (1.0 - (lat_rad.tan() + 1.0 / lat_rad.cos()).ln() / PI) / 2.0 * scale;
+    (tx, ty)
+}
@@ -0,0 +1,236 @@
+use actix_web::{get, web, HttpResponse};
+use chrono::{DateTime, DurationRound, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use log::{info, warn, error, debug};
+use std::time::Instant;
+use crate::telemetry::QueryStats;
+use crate::database::store::{BBox, PointFilter, PointStore, TimeBucket};
+use super::error::{ApiError, ApiErrorBody};
+use super::registry::ApiScope;
+use super::validate;
+
+/// Upper bound on the number of buckets a single decomposition may produce
+const MAX_BUCKETS: i64 = 100_000;
+
+// Flat query parameters for GET requests (external names in camelCase)
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct DecomposeQueryParams {
+    /// First latitude (corner)
+    #[serde(rename = "lat1")]
+    pub lat1: f64,
+    /// First longitude (corner)
+    #[serde(rename = "lng1")]
+    pub lng1: f64,
+    /// Second latitude (opposite corner)
+    #[serde(rename = "lat2")]
+    pub lat2: f64,
+    /// Second longitude (opposite corner)
+    #[serde(rename = "lng2")]
+    pub lng2: f64,
+    /// Optional date range start (inclusive)
+    #[serde(rename = "dateStart")]
+    pub date_start: Option<DateTime<Utc>>,
+    /// Optional date range end (inclusive)
+    #[serde(rename = "dateEnd")]
+    pub date_end: Option<DateTime<Utc>>,
+    /// Optional bucket size: "day" (default, weekly period of 7) or "hour" (weekly period of 168)
+    #[serde(rename = "bucket")]
+    pub bucket: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct DecomposedBucket {
+    /// Bucket start (UTC)
+    pub timestamp: DateTime<Utc>,
+    /// Observed number of points in the bucket
+    pub value: f64,
+    /// Centered moving average; absent near the edges of the series
+    pub trend: Option<f64>,
+    /// Weekly seasonal component for this bucket's phase
+    pub seasonal: f64,
+    /// value - trend - seasonal; absent where trend is absent
+    pub residual: Option<f64>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct DecompositionData {
+    pub bucket: String,
+    pub period: usize,
+    pub data: Vec<DecomposedBucket>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct DecomposeResponse {
+    pub decomposition: DecompositionData,
+}
+
+#[utoipa::path(
+    get,
+    tag = "Timeseries",
+    params(
+    ("lat1" = f64, Query, description = "First latitude (corner)"),
+    ("lng1" = f64, Query, description = "First longitude (corner)"),
+    ("lat2" = f64, Query, description = "Second latitude (opposite corner)"),
+    ("lng2" = f64, Query, description = "Second longitude (opposite corner)"),
+    ("dateStart" = DateTime<chrono::Utc>, Query, description = "Start of the date/time range (inclusive). Optional, defaults to the first point in the area"),
+    ("dateEnd" = DateTime<chrono::Utc>, Query, description = "End of the date/time range (inclusive). Optional, defaults to the last point in the area"),
+    ("bucket" = String, Query, description = "Bucket size: day (period 7) or hour (period 168). Optional, defaults to day"),
+    ),
+    responses(
+        (status = 200, description = "Trend, seasonal and residual components of point counts", body = DecomposeResponse),
+        (status = 400, description = "Invalid parameters or range too short for a weekly model; `details.param` names the offending parameter when known", body = ApiErrorBody),
+        (status = 500, description = "Server Vzorvalsya", body = ApiErrorBody),
+    )
+)]
+
+#[get("/decompose")]
+pub async fn get_decompose(
+    store: web::Data<dyn PointStore>,
+    qp: web::Query<DecomposeQueryParams>,
+) -> Result<HttpResponse, ApiError> {
+    let started = Instant::now();
+    debug!(
+        "Decompose request: corners=({}, {}), ({}, {}), date=[{:?}..{:?}], bucket={:?}",
+        qp.lat1, qp.lng1, qp.lat2, qp.lng2, qp.date_start, qp.date_end, qp.bucket
+    );
+
+    let (bucket, step, period) = match qp.bucket.as_deref().unwrap_or("day") {
+        "day" => (TimeBucket::Day, TimeDelta::days(1), 7usize),
+        "hour" => (TimeBucket::Hour, TimeDelta::hours(1), 168usize),
+        other => {
+            warn!("Invalid bucket parameter '{}'", other);
+            return Err(ApiError::bad_param("bucket", "bucket must be 'day' or 'hour'"));
+        }
+    };
+
+    validate::area(Some(qp.lat1), Some(qp.lng1), Some(qp.lat2), Some(qp.lng2), qp.date_start, qp.date_end)?;
+    // Allow any two opposite corners; compute bounds
+    let bbox = BBox::from_corners(qp.lat1, qp.lng1, qp.lat2, qp.lng2);
+
+    let filter = PointFilter {
+        bbox: Some(bbox),
+        since: qp.date_start,
+        until: qp.date_end,
+        ..Default::default()
+    };
+    // Counted per bucket by the database
+    let rows = match store.timeline(&filter, bucket).await {
+        Ok(rows) => rows,
+        Err(e) => {
+            error!("Decompose query failed: {}", e);
+            return Err(ApiError::Internal);
+        }
+    };
+
+    // Series bounds default to the observed data when the range is left open
+    let range_start = qp.date_start.or_else(|| rows.first().map(|r| r.bucket));
+    let range_end = qp.date_end.or_else(|| rows.last().map(|r| r.bucket));
+    let (range_start, range_end) = match (range_start, range_end) {
+        (Some(s), Some(e)) if e >= s => (s, e),
+        (Some(_), Some(_)) => {
+            return Err(ApiError::bad_param("dateEnd", "dateEnd must not be before dateStart"));
+        }
+        _ => {
+            let resp = DecomposeResponse {
+                decomposition: DecompositionData { bucket: bucket.as_str().to_string(), period, data: vec![] },
+            };
+            info!("Decompose found no points, returning empty. took={:?}", started.elapsed());
+            return Ok(HttpResponse::Ok().json(resp));
+        }
+    };
+
+    let (first_bucket, last_bucket) = match (range_start.duration_trunc(step), range_end.duration_trunc(step)) {
+        (Ok(a), Ok(b)) => (a, b),
+        _ => return Err(ApiError::bad_request("date range cannot be bucketed")),
+    };
+    let step_secs = step.num_seconds();
+    let bucket_count = (last_bucket - first_bucket).num_seconds() / step_secs + 1;
+    if bucket_count > MAX_BUCKETS {
+        warn!("Decompose range too large: {} buckets", bucket_count);
+        return Err(ApiError::bad_request(format!("date range produces more than {} buckets", MAX_BUCKETS)));
+    }
+    let bucket_count = bucket_count as usize;
+    if bucket_count < 2 * period {
+        return Err(ApiError::bad_request(format!(
+            "date range must cover at least {} {} buckets (two weekly periods)", 2 * period, bucket.as_str()
+        )));
+    }
+
+    let mut values = vec![0f64; bucket_count];
+    for row in &rows {
+        let idx = (row.bucket - first_bucket).num_seconds().div_euclid(step_secs);
+        if idx >= 0 && (idx as usize) < bucket_count {
+            values[idx as usize] = row.points as f64;
+        }
+    }
+    let points: u64 = rows.iter().map(|r| r.points).sum();
+
+    let (trend, seasonal) = decompose_additive(&values, period);
+    let data = values
+        .iter()
+        .enumerate()
+        .map(|(i, &value)| DecomposedBucket {
+            timestamp: first_bucket + step * i as i32,
+            value,
+            trend: trend[i],
+            seasonal: seasonal[i % period],
+            residual: trend[i].map(|t| value - t - seasonal[i % period]),
+        })
+        .collect();
+
+    let resp = DecomposeResponse {
+        decomposition: DecompositionData { bucket: bucket.as_str().to_string(), period, data },
+    };
+    info!(
+        "Decompose response: buckets={} period={} points_count={} took={:?}",
+        bucket_count, period, points, started.elapsed()
+    );
+    let stats = QueryStats { rows_scanned: rows.len(), tiles: bucket_count };
+    Ok(stats.attach(HttpResponse::Ok().json(resp)))
+}
+
+pub fn routes() -> ApiScope {
+    ApiScope::new("/timeseries")
+        .service(get_decompose)
+}
+
+// --- Helpers ---
+
+/// Classical additive decomposition: the trend is a centered moving average over one
+/// period (2xP for even periods), the seasonal component is the mean detrended value per
+/// phase, normalized to sum to zero. Returns (trend per bucket, seasonal per phase).
+fn decompose_additive(values: &[f64], period: usize) -> (Vec<Option<f64>>, Vec<f64>) {
+    let n = values.len();
+    let half = period / 2;
+    let mut trend = vec![None; n];
+    for (i, slot) in trend.iter_mut().enumerate() {
+        if i < half || i + half >= n { continue; }
+        let window = &values[i - half..=i + half];
+        let sum: f64 = if period.is_multiple_of(2) {
+            // Even period: weight the two outermost samples by 1/2
+            window.iter().sum::<f64>() - 0.5 * (window[0] + window[window.len() - 1])
+        } else {
+            window.iter().sum()
+        };
+        *slot = Some(sum / period as f64);
+    }
+
+    let mut phase_sums = vec![0f64; period];
+    let mut phase_counts = vec![0usize; period];
+    for (i, t) in trend.iter().enumerate() {
+        if let Some(t) = t {
+            phase_sums[i % period] += values[i] - t;
+            phase_counts[i % period] += 1;
+        }
+    }
+    let mut seasonal: Vec<f64> = phase_sums
+        .iter()
+        .zip(&phase_counts)
+        .map(|(s, &c)| if c > 0 { s / c as f64 } else { 0.0 })
+        .collect();
+    let mean = seasonal.iter().sum::<f64>() / period as f64;
+    for s in seasonal.iter_mut() { *s -= mean; }
+
+    (trend, seasonal)
+}
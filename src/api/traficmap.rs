@@ -1,12 +1,20 @@
 use actix_web::{get, web, HttpResponse};
 use chrono::{DateTime, NaiveTime, Weekday, Datelike};
-use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 use log::{info, warn, error, debug};
+use std::collections::HashSet;
 use std::time::Instant;
-use sea_orm::QueryOrder;
-use crate::database::model::points::{self, Entity as Points};
+use crate::telemetry::QueryStats;
+use crate::database::store::{PointFilter, PointOrder, PointStore, TileStatsStore};
+use super::defaults::defaults;
+use super::grid::{distinct_trips, MapMeta};
+use super::error::{ApiError, ApiErrorBody};
+use super::fields::TileShape;
+use super::json_stream;
+use super::validate::{self, BinSize};
+use super::registry::ApiScope;
+use super::tile_rollups;
 
 #[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
 pub struct MapPoint {
@@ -14,36 +22,21 @@ pub struct MapPoint {
     pub lng: f64,
 }
 
-#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
-pub struct MapRectangle {
-    pub top_left: MapPoint,
-    pub bottom_right: MapPoint,
-}
-
-#[derive(Debug, Deserialize, Serialize, ToSchema)]
-pub struct TraficmapRequest {
-    pub area: MapRectangle,
-    pub time_start: DateTime<chrono::Utc>,
-    pub time_end: DateTime<chrono::Utc>,
-    pub tile_width: f64,
-    pub tile_height: f64,
-}
-
 // Flat query parameters for GET requests (external names in camelCase)
 #[derive(Debug, Deserialize, Serialize, ToSchema)]
 pub struct TraficmapQueryParams {
     /// First latitude (corner)
     #[serde(rename = "lat1")]
-    pub lat1: f64,
+    pub lat1: Option<f64>,
     /// First longitude (corner)
     #[serde(rename = "lng1")]
-    pub lng1: f64,
+    pub lng1: Option<f64>,
     /// Second latitude (opposite corner)
     #[serde(rename = "lat2")]
-    pub lat2: f64,
+    pub lat2: Option<f64>,
     /// Second longitude (opposite corner)
     #[serde(rename = "lng2")]
-    pub lng2: f64,
+    pub lng2: Option<f64>,
     /// Optional date range start (inclusive)
     #[serde(rename = "dateStart")]
     pub date_start: Option<DateTime<chrono::Utc>>,
@@ -51,9 +44,16 @@ pub struct TraficmapQueryParams {
     #[serde(rename = "dateEnd")]
     pub date_end: Option<DateTime<chrono::Utc>>,
     #[serde(rename = "tileWidth")]
-    pub tile_width: f64,
+    pub tile_width: Option<f64>,
     #[serde(rename = "tileHeight")]
-    pub tile_height: f64,
+    pub tile_height: Option<f64>,
+    /// Square tile side in meters, instead of tileWidth/tileHeight
+    #[serde(rename = "tileSizeMeters")]
+    pub tile_size_meters: Option<f64>,
+    /// `grid` (default) or `h3`
+    pub binning: Option<String>,
+    /// H3 resolution with binning=h3 (0..=15, default 8)
+    pub resolution: Option<u8>,
     /// Optional list of weekdays 1..7, comma/space separated
     #[serde(rename = "days")]
     pub days: Option<String>,
@@ -63,10 +63,22 @@ pub struct TraficmapQueryParams {
     /// Optional time-of-day end in HH or HH:MM (exclusive)
     #[serde(rename = "timeEnd")]
     pub time_end_tod: Option<String>,
+    /// Offset for days/timeStart/timeEnd, e.g. +05:00 (default DEFAULT_TIMEZONE or UTC)
+    pub timezone: Option<String>,
+    /// `points` (default) or `uniqueTrips`
+    pub mode: Option<String>,
+    /// Comma separated tile fields to send, e.g. `count,topLeft`
+    pub fields: Option<String>,
+    /// `objects` (default) or `rows`
+    pub encoding: Option<String>,
 }
 
+/// Fields of `TraficTile` for `fields`, in the column order of `encoding=rows`
+const TRAFIC_TILE_FIELDS: [&str; 5] = ["count", "neighborCount", "topLeft", "bottomRight", "h3"];
+
 #[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
 pub struct TraficTile {
+    /// Points in the tile, or distinct trips through it with mode=uniqueTrips
     pub count: usize,
     #[serde(rename = "neighborCount")]
     pub neighbor_count: usize,
@@ -74,187 +86,217 @@ pub struct TraficTile {
     pub top_left: MapPoint,
     #[serde(rename = "bottomRight")]
     pub bottom_right: MapPoint,
+    /// H3 index of the cell with binning=h3
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub h3: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
 pub struct TraficmapData {
     pub data: Vec<TraficTile>,
+    /// `tileSize` of the grid or `hexSize` of the hexagons the points were binned into
+    #[serde(flatten)]
+    pub size: BinSize,
 }
 
 #[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
 pub struct TraficmapResponse {
     pub traficmap: TraficmapData,
+    /// Points left after the weekday/time-of-day filters
+    pub meta: MapMeta,
 }
 
 #[utoipa::path(
     get,
-    path = "/api/traficmap",
     tag = "Traficmap",
     params(
-    ("lat1" = f64, Query, description = "First latitude (corner)"),
+    ("lat1" = f64, Query, description = "First latitude (corner). All four corners may be omitted when the deployment sets DEFAULT_BBOX"),
     ("lng1" = f64, Query, description = "First longitude (corner)"),
     ("lat2" = f64, Query, description = "Second latitude (opposite corner)"),
     ("lng2" = f64, Query, description = "Second longitude (opposite corner)"),
     ("dateStart" = DateTime<chrono::Utc>, Query, description = "Start of the date/time range (inclusive). Optional"),
-    ("dateEnd" = DateTime<chrono::Utc>, Query, description = "End of the date/time range (inclusive). Optional"),
-    ("tileWidth" = f64, Query, description = "Width of each tile in degrees"),
-    ("tileHeight" = f64, Query, description = "Height of each tile in degrees"),
+    ("dateEnd" = DateTime<chrono::Utc>, Query, description = "End of the date/time range (inclusive; hh:59:59 takes in the rest of that hour). Optional"),
+    ("tileWidth" = f64, Query, description = "Width of each tile in degrees. Required unless tileSizeMeters is given"),
+    ("tileHeight" = f64, Query, description = "Height of each tile in degrees. Required unless tileSizeMeters is given"),
+    ("tileSizeMeters" = f64, Query, description = "Side of each tile in meters, converted to degrees at the middle latitude of the area. Replaces tileWidth/tileHeight"),
+    ("binning" = String, Query, description = "Cell shape: grid (default) or h3 for H3 hexagons, which take no tile size"),
+    ("resolution" = u8, Query, description = "H3 resolution (0..15) with binning=h3. Optional, defaults to 8"),
     ("days" = String, Query, description = "Optional list of weekdays to include (1=Mon..7=Sun). Comma or space separated"),
     ("timeStart" = String, Query, description = "Optional time-of-day start in HH or HH:MM (inclusive)"),
     ("timeEnd" = String, Query, description = "Optional time-of-day end in HH or HH:MM (exclusive)"),
+    ("timezone" = String, Query, description = "UTC offset the days and time-of-day filters are evaluated in, e.g. +05:00. Defaults to DEFAULT_TIMEZONE, else UTC"),
+    ("mode" = String, Query, description = "What count holds: points (default) recorded in the tile, or uniqueTrips, the distinct trips with a point in it"),
+    ("fields" = String, Query, description = "Comma separated tile fields to send: count, neighborCount, topLeft, bottomRight, h3. Optional, defaults to all"),
+    ("encoding" = String, Query, description = "objects (default) or rows: every tile becomes an array in the order of `columns`, next to `data`, with corners as [lat, lng]"),
     ),
     responses(
         (status = 200, description = "Traficmap data", body = TraficmapResponse),
-        (status = 500, description = "Server Vzorvalsya"),
+        (status = 400, description = "Invalid parameters; `details.param` names the offending one when known", body = ApiErrorBody),
+        (status = 413, description = "Grid over GRID_MAX_CELLS with GRID_OVERSIZE=reject; the message names the smallest tile size or the finest resolution that fits", body = ApiErrorBody),
+        (status = 500, description = "Server Vzorvalsya", body = ApiErrorBody),
     )
 )]
 
 #[get("")]
 pub async fn get_traficmap(
-    db: web::Data<DatabaseConnection>,
+    store: web::Data<dyn PointStore>,
+    rollups: Option<web::Data<dyn TileStatsStore>>,
     qp: web::Query<TraficmapQueryParams>,
-) -> HttpResponse {
+) -> Result<HttpResponse, ApiError> {
     let started = Instant::now();
     debug!(
-        "Traficmap request: corners=({}, {}), ({}, {}), date=[{:?}..{:?}], tile=({}, {}), days={:?}, tod=[{:?}..{:?}]",
-        qp.lat1, qp.lng1, qp.lat2, qp.lng2, qp.date_start, qp.date_end, qp.tile_width, qp.tile_height, qp.days, qp.time_start_tod, qp.time_end_tod
+        "Traficmap request: corners=({:?}, {:?}), ({:?}, {:?}), date=[{:?}..{:?}], tile=({:?}, {:?}, {:?}m), days={:?}, tod=[{:?}..{:?}]",
+        qp.lat1, qp.lng1, qp.lat2, qp.lng2, qp.date_start, qp.date_end, qp.tile_width, qp.tile_height, qp.tile_size_meters, qp.days, qp.time_start_tod, qp.time_end_tod
     );
     // Basic validation
-    if qp.tile_width <= 0.0 || qp.tile_height <= 0.0 {
-        warn!("Invalid tile size: width={}, height={}", qp.tile_width, qp.tile_height);
-        return HttpResponse::BadRequest().body("tileWidth and tileHeight must be > 0");
-    }
-
-    // Allow any two opposite corners; compute bounds
-    let (lat_min, lat_max) = if qp.lat1 <= qp.lat2 { (qp.lat1, qp.lat2) } else { (qp.lat2, qp.lat1) };
-    let (lon_min, lon_max) = if qp.lng1 <= qp.lng2 { (qp.lng1, qp.lng2) } else { (qp.lng2, qp.lng1) };
-
-    let lat_span = (lat_max - lat_min).max(0.0);
-    let lon_span = (lon_max - lon_min).max(0.0);
+    // Allow any two opposite corners; compute bounds. Omitted parameters fall back to the deployment defaults
+    validate::area(qp.lat1, qp.lng1, qp.lat2, qp.lng2, qp.date_start, qp.date_end)?;
+    let bbox = match defaults().bbox(qp.lat1, qp.lng1, qp.lat2, qp.lng2) {
+        Ok(b) => b,
+        Err(msg) => return Err(ApiError::bad_request(msg)),
+    };
+    let (date_start, date_end) = defaults().date_range(qp.date_start, qp.date_end);
+    let date_end = date_end.map(tile_rollups::end_of_hour);
+    let tz = match defaults().timezone(qp.timezone.as_deref()) {
+        Ok(tz) => tz,
+        Err(msg) => return Err(ApiError::bad_request(msg)),
+    };
+    let unique_trips = match qp.mode.as_deref() {
+        None | Some("points") => false,
+        Some("uniqueTrips") => true,
+        Some(_) => return Err(ApiError::bad_param("mode", "mode must be points or uniqueTrips")),
+    };
+    let shape = TileShape::parse(qp.fields.as_deref(), qp.encoding.as_deref(), &TRAFIC_TILE_FIELDS)?;
+    let tiled = qp.tile_width.is_some() || qp.tile_height.is_some() || qp.tile_size_meters.is_some();
+    let binning = validate::binning(qp.binning.as_deref(), qp.resolution, tiled)?;
+    let tile = defaults().tile_size(&bbox, qp.tile_width, qp.tile_height, qp.tile_size_meters);
 
-    let rows = if lat_span == 0.0 { 0 } else { ((lat_span / qp.tile_height).ceil() as usize).max(1) };
-    let cols = if lon_span == 0.0 { 0 } else { ((lon_span / qp.tile_width).ceil() as usize).max(1) };
+    let (bins, size) = validate::fit_bins(bbox, binning, tile, qp.tile_size_meters)?;
 
     // Early return if degenerate
-    if rows == 0 || cols == 0 {
-        let resp = TraficmapResponse { traficmap: TraficmapData { data: vec![] } };
-    info!("Traficmap degenerate area (rows=0 or cols=0), returning empty. took={:?}", started.elapsed());
-        return HttpResponse::Ok().json(resp);
+    if bins.is_empty() {
+        let resp = TraficmapResponse { traficmap: TraficmapData { data: vec![], size }, meta: MapMeta::new(&bins, 0, 0, started) };
+    info!("Traficmap degenerate area (no cells), returning empty. took={:?}", started.elapsed());
+        return shape.respond(&resp, "/traficmap/data");
     }
 
+    // Point counts on a rolled-up grid over whole hours are added up from the hourly tile rollups
+    let plain = !unique_trips && qp.days.is_none() && qp.time_start_tod.is_none() && qp.time_end_tod.is_none();
+    let rolled_up = if plain { tile_rollups::read(rollups.as_ref(), &bins, date_start, date_end).await } else { None };
+    let from_rollups = rolled_up.is_some();
+
     // First, get all points within bounds and optional time range, ordered by timestamp
-    let mut query = Points::find()
-        .filter(points::Column::Lat.between(lat_min, lat_max))
-        .filter(points::Column::Lng.between(lon_min, lon_max));
-    if let Some(ts_start) = qp.date_start { query = query.filter(points::Column::Timestamp.gte(ts_start)); }
-    if let Some(ts_end) = qp.date_end { query = query.filter(points::Column::Timestamp.lte(ts_end)); }
-    let mut all_points = match query
-        .order_by_asc(points::Column::Timestamp)
-        .all(db.get_ref()).await {
+    let filter = PointFilter {
+        bbox: Some(bbox),
+        since: date_start,
+        until: date_end,
+        ..Default::default()
+    };
+    let found = match rolled_up {
+        Some(_) => Ok(Vec::new()),
+        None => store.find(&filter, PointOrder::TimestampAsc, None).await,
+    };
+    let mut all_points = match found {
         Ok(p) => p,
         Err(e) => {
             error!("Traficmap query failed: {}", e);
-            return HttpResponse::InternalServerError().finish();
+            return Err(ApiError::Internal);
         }
     };
 
+    let rows_scanned = all_points.len();
+
     // Apply optional weekday and time-of-day filters
     let day_set = match &qp.days {
         Some(s) => match parse_days_of_week(s) { Ok(set) => Some(set), Err(e) => {
             warn!("Invalid days parameter '{}': {}", s, e);
-            return HttpResponse::BadRequest().body("days must contain numbers 1..7 separated by comma/space");
+            return Err(ApiError::bad_param("days", "days must contain numbers 1..7 separated by comma/space"));
         }},
         None => None,
     };
     let (tod_start, tod_end) = match (&qp.time_start_tod, &qp.time_end_tod) {
         (Some(a), Some(b)) => {
-            let a = match parse_time_of_day(a) { Ok(t) => t, Err(_) => { return HttpResponse::BadRequest().body("timeStart must be HH or HH:MM"); }};
-            let b = match parse_time_of_day(b) { Ok(t) => t, Err(_) => { return HttpResponse::BadRequest().body("timeEnd must be HH or HH:MM"); }};
-            if b <= a { warn!("Invalid time-of-day window: start={:?} end={:?}", a, b); return HttpResponse::BadRequest().body("timeEnd must be greater than timeStart (same-day window)"); }
+            let a = match parse_time_of_day(a) { Ok(t) => t, Err(_) => { return Err(ApiError::bad_param("timeStart", "timeStart must be HH or HH:MM")); }};
+            let b = match parse_time_of_day(b) { Ok(t) => t, Err(_) => { return Err(ApiError::bad_param("timeEnd", "timeEnd must be HH or HH:MM")); }};
+            if b <= a { warn!("Invalid time-of-day window: start={:?} end={:?}", a, b); return Err(ApiError::bad_param("timeEnd", "timeEnd must be greater than timeStart (same-day window)")); }
             (Some(a), Some(b))
         }
         (None, None) => (None, None),
-        _ => { return HttpResponse::BadRequest().body("Both timeStart and timeEnd must be provided together"); }
+        _ => { return Err(ApiError::bad_request("Both timeStart and timeEnd must be provided together")); }
     };
     if day_set.is_some() || tod_start.is_some() {
-        all_points = all_points.into_iter().filter(|p| {
+        all_points.retain(|p| {
             if let Some(ref set) = day_set {
-                if let Some(ts) = p.timestamp { let wd = ts.weekday(); let day_num = match wd { Weekday::Mon=>1,Weekday::Tue=>2,Weekday::Wed=>3,Weekday::Thu=>4,Weekday::Fri=>5,Weekday::Sat=>6,Weekday::Sun=>7 }; if !set.contains(&day_num) { return false; } } else { return false; }
+                if let Some(ts) = p.timestamp { let wd = ts.with_timezone(&tz).weekday(); let day_num = match wd { Weekday::Mon=>1,Weekday::Tue=>2,Weekday::Wed=>3,Weekday::Thu=>4,Weekday::Fri=>5,Weekday::Sat=>6,Weekday::Sun=>7 }; if !set.contains(&day_num) { return false; } } else { return false; }
             }
-            match (tod_start, tod_end) { (Some(s), Some(e)) => { if let Some(ts) = p.timestamp { let t = ts.time(); t >= s && t < e } else { false } } _ => true }
-        }).collect();
+            match (tod_start, tod_end) { (Some(s), Some(e)) => { if let Some(ts) = p.timestamp { let t = ts.with_timezone(&tz).time(); t >= s && t < e } else { false } } _ => true }
+        });
     }
-    let total_points_count = all_points.len();
+    let mut total_points_count = all_points.len();
+    let trips = distinct_trips(&all_points);
     debug!("Traficmap DB returned {} points after filters in {:?}", total_points_count, started.elapsed());
 
-    // Bucket points into tiles
-    let mut counts = vec![0usize; rows * cols];
-    let inv_h = 1.0 / qp.tile_height;
-    let inv_w = 1.0 / qp.tile_width;
-
+    // Bucket points into tiles; with uniqueTrips a trip counts once per tile it passes
+    let mut counts = vec![0usize; bins.len()];
+    let mut seen: HashSet<(usize, i64)> = HashSet::new();
     for p in all_points {
-        // Compute indices; clamp to [0, rows-1] / [0, cols-1]
-        let mut r = ((p.lat - lat_min) * inv_h).floor() as isize;
-        let mut c = ((p.lng - lon_min) * inv_w).floor() as isize;
-
-        if r < 0 { r = 0; }
-        if c < 0 { c = 0; }
-        if r as usize >= rows { r = rows as isize - 1; }
-        if c as usize >= cols { c = cols as isize - 1; }
-
-        let idx = (r as usize) * cols + (c as usize);
-        counts[idx] += 1;
+        if let Some(idx) = bins.index_of(p.lat, p.lng)
+            && (!unique_trips || seen.insert((idx, p.randomized_id)))
+        {
+            counts[idx] += 1;
+        }
+    }
+    if let Some(cells) = rolled_up {
+        counts = cells.counts;
+        total_points_count = counts.iter().sum();
     }
 
-    // Build response tiles (row-major from lat_min/lon_min increasing)
-    // Include tiles with count > 0 OR neighbor_count > 0
-    let mut data = Vec::new();
-    for r in 0..rows {
-        let tile_lat_min = lat_min + (r as f64) * qp.tile_height;
-        let tile_lat_max = (tile_lat_min + qp.tile_height).min(lat_max);
-        for c in 0..cols {
-            let tile_lon_min = lon_min + (c as f64) * qp.tile_width;
-            let tile_lon_max = (tile_lon_min + qp.tile_width).min(lon_max);
-
-            let count = counts[r * cols + c];
-            // Calculate neighbor count (8 surrounding cells)
-            let mut neighbor_count = 0;
-            for dr in -1..=1 {
-                for dc in -1..=1 {
-                    // Skip the center cell (the current tile itself)
-                    if dr == 0 && dc == 0 {
-                        continue;
-                    }
-
-                    let nr = r as isize + dr;
-                    let nc = c as isize + dc;
+    let points_count = counts.iter().sum::<usize>();
+    let mut meta = MapMeta::new(&bins, total_points_count, trips, started);
+    if from_rollups {
+        meta = meta.from_rollups();
+    }
+    let cells = bins.cells();
+    let streamed = json_stream::should_stream(bins.len());
 
-                    // Check bounds
-                    if nr >= 0 && nr < rows as isize && nc >= 0 && nc < cols as isize {
-                        let neighbor_idx = (nr as usize) * cols + (nc as usize);
-                        neighbor_count += counts[neighbor_idx];
-                    }
-                }
-            }
+    // Response tiles (row-major from lat_min/lng_min increasing, H3 index order for hexagons)
+    let tile_at = {
+        let bins = bins.clone();
+        move |idx: usize| {
+            let count = counts[idx];
+            // Calculate neighbor count (8 surrounding cells, 6 around a hexagon)
+            let neighbor_count = bins.neighbor_sum(&counts, idx);
 
             // Include tiles with points or with non-zero neighbors
-            if count > 0 || neighbor_count > 0 {
-                data.push(TraficTile {
+            (count > 0 || neighbor_count > 0).then(|| {
+                let cell = bins.cell_bbox(idx);
+                TraficTile {
                     count,
                     neighbor_count,
-                    top_left: MapPoint { lat: tile_lat_min, lng: tile_lon_min },
-                    bottom_right: MapPoint { lat: tile_lat_max, lng: tile_lon_max },
-                });
-            }
+                    top_left: MapPoint { lat: cell.lat_min, lng: cell.lng_min },
+                    bottom_right: MapPoint { lat: cell.lat_max, lng: cell.lng_max },
+                    h3: bins.cell_id(idx),
+                }
+            })
         }
-    }
+    };
 
-    let resp = TraficmapResponse { traficmap: TraficmapData { data } };
+    let mut resp = TraficmapResponse { traficmap: TraficmapData { data: vec![], size }, meta };
+    let tiles = if streamed {
+        cells.clone().filter(|&idx| tile_at(idx).is_some()).count()
+    } else {
+        resp.traficmap.data = cells.clone().filter_map(&tile_at).collect();
+        resp.traficmap.data.len()
+    };
     info!(
-        "Traficmap response: tiles={} (non-zero only) from grid={}x{} points_count={} took={:?}",
-        resp.traficmap.data.len(), rows, cols, counts.iter().sum::<usize>(), started.elapsed()
+        "Traficmap response: tiles={} (non-zero only) from grid={} points_count={} streamed={} took={:?}",
+        tiles, bins, points_count, streamed, started.elapsed()
     );
-    HttpResponse::Ok().json(resp)
+    let stats = QueryStats { rows_scanned, tiles };
+    if streamed {
+        return Ok(stats.attach(shape.stream(&resp, "/traficmap/data", cells.filter_map(tile_at))?));
+    }
+    Ok(stats.attach(shape.respond(&resp, "/traficmap/data")?))
 }
 
 // --- Helpers ---
@@ -280,9 +322,9 @@ fn parse_time_of_day(input: &str) -> Result<NaiveTime, String> {
     Err("invalid time format".to_string())
 }
 
-pub fn init_routes(cfg: &mut web::ServiceConfig) {
-    cfg.service(
-        web::scope("/trafficmap")
-            .service(get_traficmap)
-    );
+pub fn routes() -> ApiScope {
+    ApiScope::new("/trafficmap")
+        .service(get_traficmap)
+        // Spelling the docs used before the mount was fixed
+        .deprecated_alias("/traficmap", |s| s.service(get_traficmap))
 }
\ No newline at end of file
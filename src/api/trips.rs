@@ -0,0 +1,379 @@
+use actix_web::{get, web, HttpResponse};
+use actix_web::web::Bytes;
+use futures_util::{stream, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use log::{debug, error, info};
+use std::time::Instant;
+use chrono::{DateTime, Utc};
+
+use crate::database::model::trips::Model as TripModel;
+use crate::database::model::matched_trips::Model as MatchedTripModel;
+use crate::database::store::{StoreError, TripFilter, TripOrder, TripStore};
+use crate::telemetry::QueryStats;
+use crate::tenant;
+use super::anomalies::ReviewDecision;
+use super::error::{ApiError, ApiErrorBody};
+use super::heatmap::MapPoint;
+use super::points::{parquet_pages, parquet_response};
+use super::registry::ApiScope;
+use super::sample;
+use super::validate;
+
+/// Default and maximum page size for `GET /api/trips`
+const DEFAULT_LIMIT: u64 = 100;
+const MAX_LIMIT: u64 = 1000;
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct Trip {
+    #[serde(rename = "randomizedId")]
+    pub randomized_id: i64,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    #[serde(rename = "startPoint")]
+    pub start_point: MapPoint,
+    #[serde(rename = "endPoint")]
+    pub end_point: MapPoint,
+    /// Meters along the recorded points
+    pub distance: f64,
+    /// Seconds between the first and the last point
+    pub duration: f64,
+    #[serde(rename = "avgSpeed")]
+    pub avg_speed: f64,
+    #[serde(rename = "maxSpeed")]
+    pub max_speed: f64,
+    #[serde(rename = "pointCount")]
+    pub point_count: i64,
+    /// true when any point of the trip is classified anomalous, unless a review decided
+    pub anomaly: bool,
+    /// Number of points classified anomalous; all or none after a review
+    #[serde(rename = "anomalyPoints")]
+    pub anomaly_points: i64,
+    /// `confirm` or `dismiss` when the trip was reviewed
+    pub review: Option<ReviewDecision>,
+    /// Operator who last confirmed or dismissed the flag via `PATCH /api/anomalies/{randomized_id}`
+    #[serde(rename = "reviewedBy")]
+    pub reviewed_by: Option<String>,
+    #[serde(rename = "reviewedAt")]
+    pub reviewed_at: Option<DateTime<Utc>>,
+    /// Tracker that reported the trip, when its points named one
+    #[serde(rename = "deviceId")]
+    pub device_id: Option<String>,
+}
+
+impl From<TripModel> for Trip {
+    fn from(m: TripModel) -> Self {
+        Self {
+            randomized_id: m.randomized_id,
+            start: m.start_ts,
+            end: m.end_ts,
+            start_point: MapPoint { lat: m.start_lat, lng: m.start_lng },
+            end_point: MapPoint { lat: m.end_lat, lng: m.end_lng },
+            distance: m.distance_m,
+            duration: m.duration_s,
+            avg_speed: m.avg_speed,
+            max_speed: m.max_speed,
+            point_count: m.point_count,
+            anomaly: m.anomaly,
+            anomaly_points: m.anomaly_count,
+            review: m.anomaly_review.map(|a| if a { ReviewDecision::Confirm } else { ReviewDecision::Dismiss }),
+            reviewed_by: m.anomaly_reviewed_by,
+            reviewed_at: m.anomaly_reviewed_at,
+            device_id: m.device_id,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct TripsPage {
+    pub trips: Vec<Trip>,
+    /// Number of trips matching the filters, ignoring limit/offset
+    pub total: u64,
+    pub limit: u64,
+    pub offset: u64,
+}
+
+/// A trip snapped to the road network (see MAP_MATCHING_URL)
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct MatchedTrip {
+    #[serde(rename = "randomizedId")]
+    pub randomized_id: i64,
+    #[serde(rename = "matchedAt")]
+    pub matched_at: DateTime<Utc>,
+    /// Points the trip had when it was matched
+    #[serde(rename = "pointCount")]
+    pub point_count: i64,
+    /// 0..1, 0 when the trace could not be placed on any road
+    pub confidence: f64,
+    /// Meters along the matched route
+    pub distance: f64,
+    /// Matched route, empty when nothing matched
+    pub geometry: Vec<MapPoint>,
+    /// OSM node ids along the route; consecutive pairs identify road segments
+    #[serde(rename = "roadNodeIds")]
+    pub road_node_ids: Vec<i64>,
+}
+
+impl From<MatchedTripModel> for MatchedTrip {
+    fn from(m: MatchedTripModel) -> Self {
+        let coordinates: Vec<[f64; 2]> = serde_json::from_value(m.geometry).unwrap_or_default();
+        Self {
+            randomized_id: m.randomized_id,
+            matched_at: m.matched_at,
+            point_count: m.source_point_count,
+            confidence: m.confidence,
+            distance: m.distance_m,
+            geometry: coordinates.into_iter().map(|[lng, lat]| MapPoint { lat, lng }).collect(),
+            road_node_ids: serde_json::from_value(m.road_node_ids).unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct TripsQueryParams {
+    #[serde(rename = "dateStart")] pub date_start: Option<DateTime<Utc>>, // inclusive
+    #[serde(rename = "dateEnd")] pub date_end: Option<DateTime<Utc>>,     // inclusive
+    #[serde(rename = "randomizedId")] pub randomized_id: Option<i64>,
+    pub anomaly: Option<bool>,
+    #[serde(rename = "minDistance")] pub min_distance: Option<f64>,
+    #[serde(rename = "deviceId")] pub device_id: Option<String>,
+    pub limit: Option<u64>,
+    pub offset: Option<u64>,
+    /// start | distance | duration | maxSpeed, `-` prefix for descending
+    pub sort: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    tag = "Trips",
+    params(
+        ("dateStart" = DateTime<chrono::Utc>, Query, description = "Only trips still running at or after this time. Optional"),
+        ("dateEnd" = DateTime<chrono::Utc>, Query, description = "Only trips started at or before this time. Optional"),
+        ("randomizedId" = i64, Query, description = "Only this trip. Optional"),
+        ("anomaly" = bool, Query, description = "Only trips with (true) or without (false) anomalous points. Optional"),
+        ("minDistance" = f64, Query, description = "Minimum trip distance in meters. Optional"),
+        ("deviceId" = String, Query, description = "Only trips of this device. Optional"),
+        ("limit" = u64, Query, description = "Page size, default 100, max 1000"),
+        ("offset" = u64, Query, description = "Number of matching trips to skip, default 0"),
+        ("sort" = String, Query, description = "start | -start | distance | -distance | duration | -duration | maxSpeed | -maxSpeed (default -start)"),
+    ),
+    responses(
+        (status = 200, description = "Page of trip summaries", body = TripsPage),
+        (status = 400, description = "Invalid parameters; `details.param` names the offending one", body = ApiErrorBody),
+        (status = 500, description = "Server Vzorvalsya", body = ApiErrorBody)
+    )
+)]
+
+#[get("")]
+pub async fn list_trips(
+    store: web::Data<dyn TripStore>,
+    qp: web::Query<TripsQueryParams>,
+) -> Result<HttpResponse, ApiError> {
+    let started = Instant::now();
+
+    validate::date_range(qp.date_start, qp.date_end)?;
+    let order = match qp.sort.as_deref().unwrap_or("-start") {
+        "start" => TripOrder::StartAsc,
+        "-start" => TripOrder::StartDesc,
+        "distance" => TripOrder::DistanceAsc,
+        "-distance" => TripOrder::DistanceDesc,
+        "duration" => TripOrder::DurationAsc,
+        "-duration" => TripOrder::DurationDesc,
+        "maxSpeed" => TripOrder::MaxSpeedAsc,
+        "-maxSpeed" => TripOrder::MaxSpeedDesc,
+        _ => return Err(ApiError::bad_param("sort", "sort must be one of start, distance, duration, maxSpeed, optionally prefixed with -")),
+    };
+    let limit = qp.limit.unwrap_or(DEFAULT_LIMIT);
+    if limit == 0 || limit > MAX_LIMIT {
+        return Err(ApiError::bad_param("limit", format!("limit must be between 1 and {}", MAX_LIMIT)));
+    }
+    let offset = qp.offset.unwrap_or(0);
+
+    let filter = TripFilter {
+        since: qp.date_start,
+        until: qp.date_end,
+        randomized_id: qp.randomized_id,
+        anomaly: qp.anomaly,
+        min_distance_m: qp.min_distance,
+        device_id: qp.device_id.clone(),
+        ..Default::default()
+    };
+
+    let total = match store.count_trips(&filter).await {
+        Ok(n) => n,
+        Err(e) => {
+            error!("Trips count failed: {}", e);
+            return Err(ApiError::Internal);
+        }
+    };
+    let rows = if offset >= total {
+        Vec::new()
+    } else {
+        match store.find_trips(&filter, order, limit, offset).await {
+            Ok(r) => r,
+            Err(e) => {
+                error!("Trips query failed: {}", e);
+                return Err(ApiError::Internal);
+            }
+        }
+    };
+
+    let rows_scanned = rows.len();
+    let resp = TripsPage {
+        trips: rows.into_iter().map(Trip::from).collect(),
+        total,
+        limit,
+        offset,
+    };
+
+    debug!("Trips page: {} of {} (offset {}) in {:?}", resp.trips.len(), total, offset, started.elapsed());
+    Ok(QueryStats { rows_scanned, tiles: resp.trips.len() }.attach(HttpResponse::Ok().json(resp)))
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct MatchedTripQueryParams {
+    /// Douglas–Peucker tolerance for the geometry, meters
+    pub tolerance: Option<f64>,
+}
+
+#[utoipa::path(
+    get,
+    tag = "Trips",
+    params(
+        ("randomized_id" = i64, Path, description = "Trip id"),
+        ("tolerance" = f64, Query, description = "Simplify the geometry (Ramer–Douglas–Peucker), dropping points within this many meters (up to 100000) of the simplified line; the ends stay. Optional"),
+    ),
+    responses(
+        (status = 200, description = "Trip route snapped to the road network", body = MatchedTrip),
+        (status = 400, description = "Invalid tolerance", body = ApiErrorBody),
+        (status = 404, description = "Trip not matched yet, or map matching is disabled", body = ApiErrorBody),
+        (status = 500, description = "Server Vzorvalsya", body = ApiErrorBody)
+    )
+)]
+
+#[get("/{randomized_id}/matched")]
+pub async fn get_matched_trip(
+    store: web::Data<dyn TripStore>,
+    path: web::Path<i64>,
+    qp: web::Query<MatchedTripQueryParams>,
+) -> Result<HttpResponse, ApiError> {
+    validate::tolerance(qp.tolerance)?;
+    let randomized_id = path.into_inner();
+    match store.find_matched(randomized_id).await {
+        Ok(Some(m)) => {
+            let mut trip = MatchedTrip::from(m);
+            if let Some(tolerance) = qp.tolerance {
+                trip.geometry = sample::simplified(trip.geometry, tolerance, |p| (p.lat, p.lng), |_| false);
+            }
+            Ok(HttpResponse::Ok().json(trip))
+        }
+        Ok(None) => Err(ApiError::NotFound("Trip has not been map-matched".to_string())),
+        Err(e) => {
+            error!("Matched trip lookup failed for {}: {}", randomized_id, e);
+            Err(ApiError::Internal)
+        }
+    }
+}
+
+/// Trips read per round trip by `GET /api/trips/export`
+const EXPORT_BATCH: u64 = 1000;
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct ExportTripsQueryParams {
+    #[serde(rename = "dateStart")] pub date_start: Option<DateTime<Utc>>, // inclusive
+    #[serde(rename = "dateEnd")] pub date_end: Option<DateTime<Utc>>,     // inclusive
+    #[serde(rename = "randomizedId")] pub randomized_id: Option<i64>,
+    pub anomaly: Option<bool>,
+    #[serde(rename = "minDistance")] pub min_distance: Option<f64>,
+    #[serde(rename = "deviceId")] pub device_id: Option<String>,
+    /// ndjson | parquet
+    pub format: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    tag = "Trips",
+    params(
+        ("dateStart" = DateTime<chrono::Utc>, Query, description = "Only trips still running at or after this time. Optional"),
+        ("dateEnd" = DateTime<chrono::Utc>, Query, description = "Only trips started at or before this time. Optional"),
+        ("randomizedId" = i64, Query, description = "Only this trip. Optional"),
+        ("anomaly" = bool, Query, description = "Only trips with (true) or without (false) anomalous points. Optional"),
+        ("minDistance" = f64, Query, description = "Minimum trip distance in meters. Optional"),
+        ("deviceId" = String, Query, description = "Only trips of this device. Optional"),
+        ("format" = String, Query, description = "ndjson (default, one Trip per line) or parquet (columns named like the trips table, zstd-compressed)"),
+    ),
+    responses(
+        (status = 200, description = "Every matching trip ascending by randomizedId, streamed", content(
+            (Trip = "application/x-ndjson"),
+            (Vec<u8> = "application/vnd.apache.parquet"),
+        )),
+        (status = 400, description = "Invalid parameters", body = ApiErrorBody),
+        (status = 500, description = "Server Vzorvalsya", body = ApiErrorBody)
+    )
+)]
+
+#[get("/export")]
+pub async fn export_trips(
+    store: web::Data<dyn TripStore>,
+    qp: web::Query<ExportTripsQueryParams>,
+) -> Result<HttpResponse, ApiError> {
+    validate::date_range(qp.date_start, qp.date_end)?;
+    let parquet = match qp.format.as_deref().unwrap_or("ndjson") {
+        "ndjson" => false,
+        "parquet" => true,
+        _ => return Err(ApiError::bad_param("format", "format must be ndjson or parquet")),
+    };
+    let filter = TripFilter {
+        since: qp.date_start,
+        until: qp.date_end,
+        randomized_id: qp.randomized_id,
+        anomaly: qp.anomaly,
+        min_distance_m: qp.min_distance,
+        device_id: qp.device_id.clone(),
+        // Pages are read while the body streams, after the request's tenant scope ended
+        tenant: tenant::current(),
+        ..Default::default()
+    };
+    info!("Trips export started: parquet={} ({:?})", parquet, filter);
+
+    // Keyset pages by randomized_id; a trip appearing mid-export is neither repeated nor skips others
+    let store = store.into_inner();
+    let page = move |after: Option<i64>| {
+        let store = store.clone();
+        let filter = TripFilter { after_randomized_id: after, ..filter.clone() };
+        async move {
+            let rows = store.find_trips(&filter, TripOrder::IdAsc, EXPORT_BATCH, 0).await?;
+            let next = rows.last().map(|t| Some(t.randomized_id)).filter(|_| rows.len() as u64 == EXPORT_BATCH);
+            Ok::<_, StoreError>((rows, next))
+        }
+    };
+    if parquet {
+        let pages = parquet_pages(None, page).inspect_err(|e| error!("Trips export aborted: {}", e));
+        return Ok(parquet_response("trips.parquet", pages));
+    }
+    let pages = stream::try_unfold(Some(None), move |cursor| {
+        let next_page = page.clone();
+        async move {
+            let Some(after) = cursor else { return Ok(None) };
+            let (rows, next) = next_page(after).await?;
+            let mut buf = String::new();
+            for row in rows {
+                buf.push_str(&serde_json::to_string(&Trip::from(row)).map_err(|e| StoreError::Backend(e.to_string()))?);
+                buf.push('\n');
+            }
+            Ok::<_, StoreError>(Some((Bytes::from(buf), next)))
+        }
+    })
+    .inspect_err(|e| error!("Trips export aborted: {}", e));
+    Ok(HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .insert_header(("Content-Disposition", "attachment; filename=\"trips.ndjson\""))
+        .streaming(pages))
+}
+
+pub fn routes() -> ApiScope {
+    ApiScope::new("/trips")
+        .service(list_trips)
+        .service(export_trips)
+        .service(get_matched_trip)
+}
@@ -0,0 +1,150 @@
+use actix_web::{http::header, post, web, HttpRequest, HttpResponse};
+use image::ImageFormat;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::hash::{BuildHasher, Hash, Hasher, RandomState};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
+use super::registry::ApiScope;
+
+/// URL prefix uploaded files are served under, through the image optimizer
+pub const UPLOADS_MOUNT: &str = "/uploads";
+
+/// Where attachments go and who may write them. Built once in `main.rs` and shared as app data.
+#[derive(Debug, Clone)]
+pub struct UploadConfig {
+    pub dir: PathBuf,
+    /// Bearer token from UPLOAD_TOKEN; uploads are disabled when unset
+    token: Option<String>,
+    max_bytes: usize,
+}
+
+impl UploadConfig {
+    /// Reads UPLOADS_DIR (default "data/uploads"), UPLOAD_TOKEN and UPLOAD_MAX_BYTES (default 5 MiB)
+    /// and creates the directory so it can be mounted as an image root.
+    pub fn from_env() -> std::io::Result<Self> {
+        let dir = PathBuf::from(env::var("UPLOADS_DIR").unwrap_or_else(|_| "data/uploads".to_string()));
+        std::fs::create_dir_all(&dir)?;
+        let token = env::var("UPLOAD_TOKEN").ok().filter(|t| !t.is_empty());
+        if token.is_none() {
+            warn!("UPLOAD_TOKEN is not set; image uploads are disabled");
+        }
+        let max_bytes = env::var("UPLOAD_MAX_BYTES")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(5 * 1024 * 1024);
+        Ok(Self { dir, token, max_bytes })
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct UploadResponse {
+    pub name: String,
+    /// Path to fetch the image from (served as WebP when the client accepts it)
+    pub url: String,
+}
+
+#[utoipa::path(
+    post,
+    tag = "Uploads",
+    request_body(content = Vec<u8>, description = "Raw PNG, JPEG or WebP bytes", content_type = "application/octet-stream"),
+    params(
+        ("Authorization" = String, Header, description = "Bearer <UPLOAD_TOKEN>"),
+    ),
+    responses(
+        (status = 201, description = "Stored image", body = UploadResponse),
+        (status = 400, description = "Not a supported image"),
+        (status = 401, description = "Missing or wrong token"),
+        (status = 413, description = "Image larger than UPLOAD_MAX_BYTES"),
+        (status = 503, description = "Uploads are disabled"),
+        (status = 500, description = "Server Vzorvalsya")
+    )
+)]
+
+#[post("")]
+pub async fn upload_image(
+    cfg: web::Data<UploadConfig>,
+    req: HttpRequest,
+    payload: web::Payload,
+) -> HttpResponse {
+    let started = Instant::now();
+
+    let Some(expected) = cfg.token.as_deref() else {
+        return HttpResponse::ServiceUnavailable().body("Uploads are disabled");
+    };
+    let provided = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim);
+    if !provided.is_some_and(|t| constant_time_eq(t.as_bytes(), expected.as_bytes())) {
+        return HttpResponse::Unauthorized().body("Invalid upload token");
+    }
+
+    let bytes = match payload.to_bytes_limited(cfg.max_bytes).await {
+        Ok(Ok(b)) => b,
+        Ok(Err(_)) => return HttpResponse::PayloadTooLarge().body(format!("Image exceeds {} bytes", cfg.max_bytes)),
+        Err(e) => {
+            warn!("Upload body read failed: {}", e);
+            return HttpResponse::BadRequest().body("Could not read request body");
+        }
+    };
+
+    // Trust the bytes, not the declared content type, and make sure the image actually decodes
+    let ext = match image::guess_format(&bytes) {
+        Ok(ImageFormat::Png) => "png",
+        Ok(ImageFormat::Jpeg) => "jpg",
+        Ok(ImageFormat::WebP) => "webp",
+        _ => return HttpResponse::BadRequest().body("Unsupported image format; expected PNG, JPEG or WebP"),
+    };
+    let data = bytes.to_vec();
+    let decoded = web::block(move || image::load_from_memory(&data).map(|_| data)).await;
+    let data = match decoded {
+        Ok(Ok(d)) => d,
+        Ok(Err(_)) => return HttpResponse::BadRequest().body("Image could not be decoded"),
+        Err(e) => {
+            error!("Upload decode task failed: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let name = format!("{}.{}", unique_stem(&data), ext);
+    if let Err(e) = tokio::fs::write(cfg.dir.join(&name), &data).await {
+        error!("Failed to store upload {}: {}", name, e);
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    info!("Stored upload {} ({} bytes) in {:?}", name, data.len(), started.elapsed());
+    let url = format!("{}/{}", UPLOADS_MOUNT, name);
+    HttpResponse::Created()
+        .insert_header((header::LOCATION, url.clone()))
+        .json(UploadResponse { name, url })
+}
+
+pub fn routes() -> ApiScope {
+    ApiScope::new("/uploads")
+        .service(upload_image)
+}
+
+// --- Helpers ---
+
+pub(super) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Time plus a randomly keyed hash of content and a counter: unique without a shared sequence,
+/// and not guessable from a neighbouring upload's name.
+fn unique_stem(data: &[u8]) -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+    let mut hasher = RandomState::new().build_hasher();
+    data.hash(&mut hasher);
+    nanos.hash(&mut hasher);
+    COUNTER.fetch_add(1, Ordering::Relaxed).hash(&mut hasher);
+    format!("{:x}-{:016x}", nanos, hasher.finish())
+}
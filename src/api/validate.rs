@@ -0,0 +1,334 @@
+use chrono::{DateTime, Utc};
+use h3o::Resolution;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::{env, fmt};
+use utoipa::ToSchema;
+
+use crate::database::store::BBox;
+use crate::geo;
+use super::error::ApiError;
+use super::grid::{Bins, Grid, Kernel};
+use super::hexgrid::HexGrid;
+
+/// H3 resolution of `binning=h3` without `resolution`: cells of about 0.7 km²
+const DEFAULT_RESOLUTION: Resolution = Resolution::Eight;
+
+/// Most cells a tiled endpoint builds for one request (GRID_MAX_CELLS, default 1 000 000); a
+/// tiny tile over a large area would otherwise allocate gigabytes before the first row is read
+static GRID_MAX_CELLS: Lazy<f64> = Lazy::new(|| {
+    env::var("GRID_MAX_CELLS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .filter(|&n| n > 0)
+        .unwrap_or(1_000_000) as f64
+});
+
+/// What to do with a grid over the budget (GRID_OVERSIZE): `coarsen` (default) enlarges the
+/// tiles until the grid fits, `reject` answers 413
+static GRID_REJECT_OVERSIZE: Lazy<bool> =
+    Lazy::new(|| env::var("GRID_OVERSIZE").map(|v| v.eq_ignore_ascii_case("reject")).unwrap_or(false));
+
+/// Tile size a grid endpoint actually used, in degrees
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct TileSize {
+    pub width: f64,
+    pub height: f64,
+    /// The requested tiles would have exceeded GRID_MAX_CELLS and were enlarged
+    pub coarsened: bool,
+}
+
+/// H3 resolution a hexagon endpoint actually used
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct HexSize {
+    pub resolution: u8,
+    /// The requested resolution would have exceeded GRID_MAX_CELLS and was lowered
+    pub coarsened: bool,
+}
+
+/// Cell size of a binned response: `tileSize` for the grid, `hexSize` for H3 hexagons
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct BinSize {
+    /// Tile size the grid was built with; larger than requested when `coarsened`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tile_size: Option<TileSize>,
+    /// Resolution the hexagons were built at; lower than requested when `coarsened`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hex_size: Option<HexSize>,
+}
+
+/// How an aggregation endpoint bins points (`binning`, `resolution`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Binning {
+    Grid,
+    H3(Resolution),
+}
+
+/// Why a grid cannot be built as requested
+#[derive(Debug, Clone)]
+pub enum GridError {
+    Invalid(Invalid),
+    /// Over the cell budget with GRID_OVERSIZE=reject; the message names the smallest tiles
+    /// that would fit
+    TooLarge(String),
+}
+
+impl fmt::Display for GridError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Invalid(e) => e.fmt(f),
+            Self::TooLarge(message) => f.write_str(message),
+        }
+    }
+}
+
+impl From<GridError> for ApiError {
+    fn from(e: GridError) -> Self {
+        match e {
+            GridError::Invalid(e) => e.into(),
+            GridError::TooLarge(message) => ApiError::PayloadTooLarge(message),
+        }
+    }
+}
+
+/// A query parameter rejected by the shared checks
+#[derive(Debug, Clone)]
+pub struct Invalid {
+    pub param: &'static str,
+    pub message: String,
+}
+
+impl fmt::Display for Invalid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl From<Invalid> for ApiError {
+    fn from(e: Invalid) -> Self {
+        ApiError::bad_param(e.param, e.message)
+    }
+}
+
+/// Each given corner must be a real coordinate: lat in [-90, 90], lng in [-360, 360]. Longitudes
+/// past ±180 are how a map panned across the antimeridian reports its bounds (see
+/// `BBox::from_corners`), so they are accepted as long as both corners lie within one turn.
+pub fn corners(lat1: Option<f64>, lng1: Option<f64>, lat2: Option<f64>, lng2: Option<f64>) -> Result<(), Invalid> {
+    for (param, value) in [("lat1", lat1), ("lat2", lat2)] {
+        if let Some(v) = value
+            && !(-90.0..=90.0).contains(&v)
+        {
+            return Err(Invalid { param, message: format!("{} must be within [-90, 90], got {}", param, v) });
+        }
+    }
+    for (param, value) in [("lng1", lng1), ("lng2", lng2)] {
+        if let Some(v) = value
+            && !(-360.0..=360.0).contains(&v)
+        {
+            return Err(Invalid { param, message: format!("{} must be within [-360, 360], got {}", param, v) });
+        }
+    }
+    if let (Some(a), Some(b)) = (lng1, lng2)
+        && (a - b).abs() > 360.0
+    {
+        return Err(Invalid { param: "lng2", message: format!("lng1 and lng2 must be at most 360 degrees apart, got {} and {}", a, b) });
+    }
+    Ok(())
+}
+
+pub fn date_range(start: Option<DateTime<Utc>>, end: Option<DateTime<Utc>>) -> Result<(), Invalid> {
+    match (start, end) {
+        (Some(s), Some(e)) if e < s => Err(Invalid { param: "dateEnd", message: "dateEnd must not be before dateStart".to_string() }),
+        _ => Ok(()),
+    }
+}
+
+/// Corners and date range together, as most area endpoints take both
+pub fn area(
+    lat1: Option<f64>,
+    lng1: Option<f64>,
+    lat2: Option<f64>,
+    lng2: Option<f64>,
+    start: Option<DateTime<Utc>>,
+    end: Option<DateTime<Utc>>,
+) -> Result<(), Invalid> {
+    corners(lat1, lng1, lat2, lng2)?;
+    date_range(start, end)
+}
+
+/// Largest `tolerance` of the route endpoints, meters
+pub const MAX_TOLERANCE_M: f64 = 100_000.0;
+
+/// `tolerance` of route simplification: meters in (0, MAX_TOLERANCE_M]
+pub fn tolerance(tolerance: Option<f64>) -> Result<(), Invalid> {
+    match tolerance {
+        Some(t) if !(t > 0.0 && t <= MAX_TOLERANCE_M) => Err(Invalid {
+            param: "tolerance",
+            message: format!("tolerance must be within (0, {}] meters, got {}", MAX_TOLERANCE_M, t),
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Tile size to build a grid over `bbox` with, keeping the cell count within GRID_MAX_CELLS.
+/// An oversized request has both tile sides scaled by the same factor (so the tile shape is
+/// kept) or is rejected, depending on GRID_OVERSIZE.
+pub fn fit_grid(bbox: &BBox, tile_width: f64, tile_height: f64, tile_size_meters: Option<f64>) -> Result<TileSize, GridError> {
+    let param = if tile_size_meters.is_some() { "tileSizeMeters" } else { "tileWidth" };
+    if !tile_width.is_finite() || !tile_height.is_finite() {
+        return Err(GridError::Invalid(Invalid { param, message: "tile size must be a finite number".to_string() }));
+    }
+    let max = *GRID_MAX_CELLS;
+    let cells = grid_cells(bbox, tile_width, tile_height);
+    if cells <= max {
+        return Ok(TileSize { width: tile_width, height: tile_height, coarsened: false });
+    }
+
+    // Area scales with the square of the factor; ceil() at the edges can still leave a few
+    // cells too many, so nudge up until it fits
+    let mut factor = (cells / max).sqrt();
+    while grid_cells(bbox, tile_width * factor, tile_height * factor) > max {
+        factor *= 1.01;
+    }
+    let (width, height) = (tile_width * factor, tile_height * factor);
+
+    if *GRID_REJECT_OVERSIZE {
+        return Err(GridError::TooLarge(format!(
+            "tile size gives {:.0} cells over this area; at most {:.0} are allowed. Use tiles of at least {:.6} x {:.6} degrees{} or a smaller area",
+            cells,
+            max,
+            width,
+            height,
+            tile_size_meters.map(|m| format!(" (tileSizeMeters >= {:.0})", (m * factor).ceil())).unwrap_or_default(),
+        )));
+    }
+    Ok(TileSize { width, height, coarsened: true })
+}
+
+/// Cell count of a grid over `bbox`, counted the way `Grid::new` does but in floating point,
+/// so it cannot overflow before it is checked
+fn grid_cells(bbox: &BBox, tile_width: f64, tile_height: f64) -> f64 {
+    let rows = ((bbox.lat_max - bbox.lat_min).max(0.0) / tile_height).ceil().max(1.0);
+    let cols = (bbox.lng_span() / tile_width).ceil().max(1.0);
+    rows * cols
+}
+
+/// `binning` is `grid` (default) or `h3`; `resolution` (0..=15, default 8) only goes with h3,
+/// and the tile parameters (`tiled`) only with the grid
+pub fn binning(binning: Option<&str>, resolution: Option<u8>, tiled: bool) -> Result<Binning, Invalid> {
+    match binning.map(str::trim) {
+        None | Some("") | Some("grid") => match resolution {
+            Some(_) => Err(Invalid { param: "resolution", message: "resolution only applies to binning=h3".to_string() }),
+            None => Ok(Binning::Grid),
+        },
+        Some("h3") => {
+            if tiled {
+                return Err(Invalid {
+                    param: "binning",
+                    message: "tileWidth, tileHeight and tileSizeMeters do not apply to binning=h3".to_string(),
+                });
+            }
+            match resolution {
+                None => Ok(Binning::H3(DEFAULT_RESOLUTION)),
+                Some(r) => Resolution::try_from(r).map(Binning::H3).map_err(|_| Invalid {
+                    param: "resolution",
+                    message: format!("resolution must be within [0, 15], got {}", r),
+                }),
+            }
+        }
+        Some(other) => Err(Invalid { param: "binning", message: format!("binning must be grid or h3, got {:?}", other) }),
+    }
+}
+
+/// Largest `smoothingRadius`, in cells
+pub const MAX_SMOOTHING_RADIUS: u32 = 5;
+
+/// `smoothing` is `box`, `gaussian` or `distance` over `smoothingRadius` cells (default 1);
+/// None when the map is not smoothed
+pub fn smoothing(kernel: Option<&str>, radius: Option<u32>) -> Result<Option<(Kernel, u32)>, Invalid> {
+    let kernel = match kernel.map(str::trim) {
+        None | Some("") => {
+            return match radius {
+                Some(_) => Err(Invalid { param: "smoothingRadius", message: "smoothingRadius only applies with smoothing".to_string() }),
+                None => Ok(None),
+            };
+        }
+        Some("box") => Kernel::Box,
+        Some("gaussian") => Kernel::Gaussian,
+        Some("distance") => Kernel::Distance,
+        Some(other) => {
+            return Err(Invalid { param: "smoothing", message: format!("smoothing must be box, gaussian or distance, got {:?}", other) });
+        }
+    };
+    match radius.unwrap_or(1) {
+        r @ 1..=MAX_SMOOTHING_RADIUS => Ok(Some((kernel, r))),
+        r => Err(Invalid {
+            param: "smoothingRadius",
+            message: format!("smoothingRadius must be within [1, {}], got {}", MAX_SMOOTHING_RADIUS, r),
+        }),
+    }
+}
+
+/// Bins over `bbox` as requested: the grid with the tile size from the request (`tile`) or
+/// the hexagons at the requested resolution, both kept within GRID_MAX_CELLS
+pub fn fit_bins(
+    bbox: BBox,
+    binning: Binning,
+    tile: Result<(f64, f64), &'static str>,
+    tile_size_meters: Option<f64>,
+) -> Result<(Bins, BinSize), GridError> {
+    match binning {
+        Binning::Grid => {
+            let param = if tile_size_meters.is_some() { "tileSizeMeters" } else { "tileWidth" };
+            let (tile_width, tile_height) = tile.map_err(|message| GridError::Invalid(Invalid { param, message: message.to_string() }))?;
+            let tile_size = fit_grid(&bbox, tile_width, tile_height, tile_size_meters)?;
+            let grid = Grid::new(bbox, tile_size.width, tile_size.height);
+            Ok((Bins::Rect(grid), BinSize { tile_size: Some(tile_size), hex_size: None }))
+        }
+        Binning::H3(resolution) => {
+            let (resolution, hex_size) = fit_hex(&bbox, resolution)?;
+            Ok((Bins::Hex(HexGrid::new(bbox, resolution)), BinSize { tile_size: None, hex_size: Some(hex_size) }))
+        }
+    }
+}
+
+/// Resolution to cover `bbox` with hexagons at, keeping the estimated cell count within
+/// GRID_MAX_CELLS. An oversized request is moved to coarser resolutions until it fits or is
+/// rejected, depending on GRID_OVERSIZE.
+pub fn fit_hex(bbox: &BBox, resolution: Resolution) -> Result<(Resolution, HexSize), GridError> {
+    let max = *GRID_MAX_CELLS;
+    let cells = hex_cells(bbox, resolution);
+    if cells <= max {
+        return Ok((resolution, HexSize { resolution: resolution.into(), coarsened: false }));
+    }
+
+    let mut fitting = resolution;
+    while hex_cells(bbox, fitting) > max {
+        match fitting.pred() {
+            Some(coarser) => fitting = coarser,
+            None => break,
+        }
+    }
+
+    if *GRID_REJECT_OVERSIZE {
+        return Err(GridError::TooLarge(format!(
+            "resolution {} gives about {:.0} cells over this area; at most {:.0} are allowed. Use resolution {} or lower, or a smaller area",
+            resolution, cells, max, fitting,
+        )));
+    }
+    Ok((fitting, HexSize { resolution: fitting.into(), coarsened: true }))
+}
+
+/// Estimated cell count of the hexagons covering `bbox`: its area on the sphere over the
+/// average cell area, plus a ring of cells along the edges that stick out of it
+fn hex_cells(bbox: &BBox, resolution: Resolution) -> f64 {
+    let radius_km = geo::EARTH_RADIUS_M / 1000.0;
+    let (lat_min, lat_max) = (bbox.lat_min.to_radians(), bbox.lat_max.to_radians());
+    let lng_span = bbox.lng_span().to_radians();
+    let area = radius_km * radius_km * lng_span * (lat_max.sin() - lat_min.sin()).max(0.0);
+    let perimeter = radius_km * (2.0 * (lat_max - lat_min).max(0.0) + lng_span * (lat_min.cos() + lat_max.cos()));
+    area / resolution.area_km2() + perimeter / resolution.edge_length_km()
+}
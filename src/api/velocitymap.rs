@@ -1,12 +1,59 @@
 use actix_web::{get, web, HttpResponse};
-use chrono::{DateTime, NaiveTime, Weekday, Datelike};
-use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use chrono::{DateTime, Duration, FixedOffset, NaiveTime, Timelike, Utc, Weekday, Datelike};
+use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 use log::{info, warn, error, debug};
+use std::env;
 use std::time::Instant;
-use sea_orm::QueryOrder;
-use crate::database::model::points::{self, Entity as Points};
+use crate::telemetry::QueryStats;
+use crate::database::store::{BBox, DayWindow, PointFilter, PointOrder, PointStore, RoadSegmentStore, TileGrid, TileStatsStore};
+use crate::roads::SegmentIndex;
+use super::defaults::defaults;
+use super::grid::{distinct_trips, Bins, MapMeta};
+use super::error::{ApiError, ApiErrorBody};
+use super::fields::TileShape;
+use super::validate::{self, BinSize};
+use super::registry::ApiScope;
+use super::tile_rollups;
+
+/// Local hours whose average speed counts as a tile's free-flow speed, `HH:MM-HH:MM`
+/// (SPEEDMAP_FREEFLOW_HOURS, default 01:00-05:00; may wrap past midnight)
+static FREEFLOW_HOURS: Lazy<(NaiveTime, NaiveTime)> = Lazy::new(|| {
+    let default = (NaiveTime::from_hms_opt(1, 0, 0).unwrap(), NaiveTime::from_hms_opt(5, 0, 0).unwrap());
+    let Ok(v) = env::var("SPEEDMAP_FREEFLOW_HOURS") else { return default };
+    match v.split_once('-').map(|(a, b)| (parse_time_of_day(a), parse_time_of_day(b))) {
+        Some((Ok(a), Ok(b))) if a != b => (a, b),
+        _ => {
+            warn!("Ignoring SPEEDMAP_FREEFLOW_HOURS={:?}: expected HH:MM-HH:MM", v);
+            default
+        }
+    }
+});
+
+/// Days of history before the end of the requested range the free-flow speed is taken from
+/// (SPEEDMAP_FREEFLOW_DAYS, default 28)
+static FREEFLOW_DAYS: Lazy<i64> = Lazy::new(|| {
+    env::var("SPEEDMAP_FREEFLOW_DAYS").ok().and_then(|v| v.parse::<i64>().ok()).filter(|d| *d > 0).unwrap_or(28)
+});
+
+/// Fewer night-time points than this leave a tile without a free-flow speed
+const MIN_FREEFLOW_POINTS: u64 = 5;
+
+/// km/h over a segment's limit a point may go before it counts as speeding
+/// (SPEED_LIMIT_MARGIN_KMH, default 10; `margin` overrides it per request)
+static SPEED_LIMIT_MARGIN: Lazy<f64> = Lazy::new(|| {
+    env::var("SPEED_LIMIT_MARGIN_KMH").ok().and_then(|v| v.parse::<f64>().ok()).filter(|m| m.is_finite() && *m >= 0.0).unwrap_or(10.0)
+});
+
+/// Points farther than this from every road segment are not checked against a limit
+/// (SPEED_LIMIT_SNAP_METERS, default 20)
+static SPEED_LIMIT_SNAP_M: Lazy<f64> = Lazy::new(|| {
+    env::var("SPEED_LIMIT_SNAP_METERS").ok().and_then(|v| v.parse::<f64>().ok()).filter(|m| m.is_finite() && *m > 0.0).unwrap_or(20.0)
+});
+
+const DEFAULT_VIOLATION_TRIPS: usize = 100;
+const MAX_VIOLATION_TRIPS: usize = 1000;
 
 #[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
 pub struct MapPoint {
@@ -14,36 +61,21 @@ pub struct MapPoint {
     pub lng: f64,
 }
 
-#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
-pub struct MapRectangle {
-    pub top_left: MapPoint,
-    pub bottom_right: MapPoint,
-}
-
-#[derive(Debug, Deserialize, Serialize, ToSchema)]
-pub struct SpeedmapRequest {
-    pub area: MapRectangle,
-    pub time_start: DateTime<chrono::Utc>,
-    pub time_end: DateTime<chrono::Utc>,
-    pub tile_width: f64,
-    pub tile_height: f64,
-}
-
 // Flat query parameters for GET requests (external names in camelCase)
 #[derive(Debug, Deserialize, Serialize, ToSchema)]
 pub struct SpeedmapQueryParams {
     /// First latitude (corner)
     #[serde(rename = "lat1")]
-    pub lat1: f64,
+    pub lat1: Option<f64>,
     /// First longitude (corner)
     #[serde(rename = "lng1")]
-    pub lng1: f64,
+    pub lng1: Option<f64>,
     /// Second latitude (opposite corner)
     #[serde(rename = "lat2")]
-    pub lat2: f64,
+    pub lat2: Option<f64>,
     /// Second longitude (opposite corner)
     #[serde(rename = "lng2")]
-    pub lng2: f64,
+    pub lng2: Option<f64>,
     /// Optional date range start (inclusive)
     #[serde(rename = "dateStart")]
     pub date_start: Option<DateTime<chrono::Utc>>,
@@ -51,9 +83,16 @@ pub struct SpeedmapQueryParams {
     #[serde(rename = "dateEnd")]
     pub date_end: Option<DateTime<chrono::Utc>>,
     #[serde(rename = "tileWidth")]
-    pub tile_width: f64,
+    pub tile_width: Option<f64>,
     #[serde(rename = "tileHeight")]
-    pub tile_height: f64,
+    pub tile_height: Option<f64>,
+    /// Square tile side in meters, instead of tileWidth/tileHeight
+    #[serde(rename = "tileSizeMeters")]
+    pub tile_size_meters: Option<f64>,
+    /// `grid` (default) or `h3`
+    pub binning: Option<String>,
+    /// H3 resolution with binning=h3 (0..=15, default 8)
+    pub resolution: Option<u8>,
     /// Optional list of weekdays 1..7, comma/space separated
     #[serde(rename = "days")]
     pub days: Option<String>,
@@ -63,8 +102,22 @@ pub struct SpeedmapQueryParams {
     /// Optional time-of-day end in HH or HH:MM (exclusive)
     #[serde(rename = "timeEnd")]
     pub time_end_tod: Option<String>,
+    /// Offset for days/timeStart/timeEnd, e.g. +05:00 (default DEFAULT_TIMEZONE or UTC)
+    pub timezone: Option<String>,
+    /// `full` adds speed percentiles and min/max per tile
+    #[serde(rename = "stats")]
+    pub stats: Option<String>,
+    /// `freeflow` adds a congestion index relative to each tile's night-time speed
+    pub baseline: Option<String>,
+    /// Comma separated tile fields to send, e.g. `count,topLeft`
+    pub fields: Option<String>,
+    /// `objects` (default) or `rows`
+    pub encoding: Option<String>,
 }
 
+/// Fields of `SpeedTile` for `fields`, in the column order of `encoding=rows`
+const SPEED_TILE_FIELDS: [&str; 8] = ["count", "neighborCount", "topLeft", "bottomRight", "stats", "congestion", "freeflowSpeed", "h3"];
+
 #[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
 pub struct SpeedTile {
     pub count: f64,
@@ -74,202 +127,579 @@ pub struct SpeedTile {
     pub top_left: MapPoint,
     #[serde(rename = "bottomRight")]
     pub bottom_right: MapPoint,
+    /// Present with `stats=full` on tiles that have points of their own
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats: Option<SpeedStats>,
+    /// With `baseline=freeflow`: average speed over the free-flow speed, capped at 1. 1 is
+    /// traffic moving as freely as at night, values near 0 a standstill. Absent on tiles
+    /// without points of their own or without enough night-time history.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub congestion: Option<f64>,
+    /// With `baseline=freeflow`: the tile's average night-time speed the index is relative to
+    #[serde(rename = "freeflowSpeed", skip_serializing_if = "Option::is_none")]
+    pub freeflow_speed: Option<f64>,
+    /// H3 index of the cell with binning=h3
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub h3: Option<String>,
+}
+
+/// Speed distribution of the points in one tile (linear interpolation between ranks)
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct SpeedStats {
+    pub p15: f64,
+    pub p50: f64,
+    pub p85: f64,
+    pub min: f64,
+    pub max: f64,
 }
 
 #[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
 pub struct SpeedmapData {
     pub data: Vec<SpeedTile>,
+    /// `tileSize` of the grid or `hexSize` of the hexagons the points were binned into
+    #[serde(flatten)]
+    pub size: BinSize,
 }
 
 #[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
 pub struct SpeedmapResponse {
     pub speedmap: SpeedmapData,
+    /// Points left after the weekday/time-of-day filters
+    pub meta: MapMeta,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct ViolationsQueryParams {
+    pub lat1: Option<f64>,
+    pub lng1: Option<f64>,
+    pub lat2: Option<f64>,
+    pub lng2: Option<f64>,
+    #[serde(rename = "dateStart")]
+    pub date_start: Option<DateTime<Utc>>,
+    #[serde(rename = "dateEnd")]
+    pub date_end: Option<DateTime<Utc>>,
+    #[serde(rename = "tileWidth")]
+    pub tile_width: Option<f64>,
+    #[serde(rename = "tileHeight")]
+    pub tile_height: Option<f64>,
+    #[serde(rename = "tileSizeMeters")]
+    pub tile_size_meters: Option<f64>,
+    pub binning: Option<String>,
+    pub resolution: Option<u8>,
+    /// `tiles` (default) or `trips`
+    pub mode: Option<String>,
+    /// km/h over the limit that counts as speeding (default SPEED_LIMIT_MARGIN_KMH)
+    pub margin: Option<f64>,
+    /// Trips sent with mode=trips (default 100, at most 1000)
+    pub limit: Option<usize>,
+}
+
+/// A tile with points on roads of known speed limit. Speeds are in km/h, like the limits.
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct ViolationTile {
+    #[serde(rename = "topLeft")]
+    pub top_left: MapPoint,
+    #[serde(rename = "bottomRight")]
+    pub bottom_right: MapPoint,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub h3: Option<String>,
+    /// Points matched to a road segment
+    pub checked: usize,
+    /// Of those, points over their segment's limit by more than the margin
+    pub violations: usize,
+    #[serde(rename = "avgSpeed")]
+    pub avg_speed: f64,
+    /// Average limit of the segments the points were matched to
+    #[serde(rename = "speedLimit")]
+    pub speed_limit: f64,
+    /// The average speed is over the average limit by more than the margin
+    pub exceeds: bool,
+}
+
+/// The point of a trip furthest over its limit
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct SpeedingPoint {
+    pub lat: f64,
+    pub lng: f64,
+    pub timestamp: Option<DateTime<Utc>>,
+    /// km/h
+    pub speed: f64,
+    #[serde(rename = "speedLimit")]
+    pub speed_limit: f64,
+    /// Name of the road, when OSM has one
+    pub road: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct ViolationTrip {
+    #[serde(rename = "randomizedId")]
+    pub randomized_id: i64,
+    pub checked: usize,
+    pub violations: usize,
+    /// km/h over the limit at the worst point
+    #[serde(rename = "maxExcess")]
+    pub max_excess: f64,
+    pub worst: SpeedingPoint,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema, Clone)]
+pub struct ViolationsResponse {
+    /// km/h over the limit that counted as speeding
+    pub margin: f64,
+    /// Points in the area and period
+    pub points: usize,
+    /// Of those, points within SPEED_LIMIT_SNAP_METERS of a road segment with a limit
+    pub checked: usize,
+    /// Road segments of the area
+    pub segments: usize,
+    /// mode=tiles: tiles with checked points
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tiles: Option<Vec<ViolationTile>>,
+    /// mode=trips: trips with violations, the most first
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trips: Option<Vec<ViolationTrip>>,
 }
 
 #[utoipa::path(
     get,
-    path = "/api/speedmap",
     tag = "Speedmap",
     params(
-    ("lat1" = f64, Query, description = "First latitude (corner)"),
+    ("lat1" = f64, Query, description = "First latitude (corner). All four corners may be omitted when the deployment sets DEFAULT_BBOX"),
     ("lng1" = f64, Query, description = "First longitude (corner)"),
     ("lat2" = f64, Query, description = "Second latitude (opposite corner)"),
     ("lng2" = f64, Query, description = "Second longitude (opposite corner)"),
     ("dateStart" = DateTime<chrono::Utc>, Query, description = "Start of the date/time range (inclusive). Optional"),
-    ("dateEnd" = DateTime<chrono::Utc>, Query, description = "End of the date/time range (inclusive). Optional"),
-    ("tileWidth" = f64, Query, description = "Width of each tile in degrees"),
-    ("tileHeight" = f64, Query, description = "Height of each tile in degrees"),
+    ("dateEnd" = DateTime<chrono::Utc>, Query, description = "End of the date/time range (inclusive; hh:59:59 takes in the rest of that hour). Optional"),
+    ("tileWidth" = f64, Query, description = "Width of each tile in degrees. Required unless tileSizeMeters is given"),
+    ("tileHeight" = f64, Query, description = "Height of each tile in degrees. Required unless tileSizeMeters is given"),
+    ("tileSizeMeters" = f64, Query, description = "Side of each tile in meters, converted to degrees at the middle latitude of the area. Replaces tileWidth/tileHeight"),
+    ("binning" = String, Query, description = "Cell shape: grid (default) or h3 for H3 hexagons, which take no tile size"),
+    ("resolution" = u8, Query, description = "H3 resolution (0..15) with binning=h3. Optional, defaults to 8"),
     ("days" = String, Query, description = "Optional list of weekdays to include (1=Mon..7=Sun). Comma or space separated"),
     ("timeStart" = String, Query, description = "Optional time-of-day start in HH or HH:MM (inclusive)"),
     ("timeEnd" = String, Query, description = "Optional time-of-day end in HH or HH:MM (exclusive)"),
+    ("timezone" = String, Query, description = "UTC offset the days and time-of-day filters are evaluated in, e.g. +05:00. Defaults to DEFAULT_TIMEZONE, else UTC"),
+    ("stats" = String, Query, description = "Optional. `full` adds p15/p50/p85 and min/max speed per tile"),
+    ("baseline" = String, Query, description = "Optional. `freeflow` adds `congestion`, the tile's average speed over its night-time average (SPEEDMAP_FREEFLOW_HOURS, local to `timezone`) across the SPEEDMAP_FREEFLOW_DAYS before dateEnd, capped at 1, and that `freeflowSpeed`"),
+    ("fields" = String, Query, description = "Comma separated tile fields to send: count, neighborCount, topLeft, bottomRight, stats, congestion, freeflowSpeed, h3. Optional, defaults to all"),
+    ("encoding" = String, Query, description = "objects (default) or rows: every tile becomes an array in the order of `columns`, next to `data`, with corners as [lat, lng]"),
     ),
     responses(
         (status = 200, description = "Speedmap data", body = SpeedmapResponse),
-        (status = 500, description = "Server Vzorvalsya"),
+        (status = 400, description = "Invalid parameters; `details.param` names the offending one when known", body = ApiErrorBody),
+        (status = 413, description = "Grid over GRID_MAX_CELLS with GRID_OVERSIZE=reject; the message names the smallest tile size or the finest resolution that fits", body = ApiErrorBody),
+        (status = 500, description = "Server Vzorvalsya", body = ApiErrorBody),
     )
 )]
 
 #[get("")]
 pub async fn get_speedmap(
-    db: web::Data<DatabaseConnection>,
+    store: web::Data<dyn PointStore>,
+    rollups: Option<web::Data<dyn TileStatsStore>>,
     qp: web::Query<SpeedmapQueryParams>,
-) -> HttpResponse {
+) -> Result<HttpResponse, ApiError> {
     let started = Instant::now();
     debug!(
-        "Speedmap request: corners=({}, {}), ({}, {}), date=[{:?}..{:?}], tile=({}, {}), days={:?}, tod=[{:?}..{:?}]",
-        qp.lat1, qp.lng1, qp.lat2, qp.lng2, qp.date_start, qp.date_end, qp.tile_width, qp.tile_height, qp.days, qp.time_start_tod, qp.time_end_tod
+        "Speedmap request: corners=({:?}, {:?}), ({:?}, {:?}), date=[{:?}..{:?}], tile=({:?}, {:?}, {:?}m), days={:?}, tod=[{:?}..{:?}]",
+        qp.lat1, qp.lng1, qp.lat2, qp.lng2, qp.date_start, qp.date_end, qp.tile_width, qp.tile_height, qp.tile_size_meters, qp.days, qp.time_start_tod, qp.time_end_tod
     );
     // Basic validation
-    if qp.tile_width <= 0.0 || qp.tile_height <= 0.0 {
-        warn!("Invalid tile size: width={}, height={}", qp.tile_width, qp.tile_height);
-        return HttpResponse::BadRequest().body("tileWidth and tileHeight must be > 0");
-    }
-
-    // Allow any two opposite corners; compute bounds
-    let (lat_min, lat_max) = if qp.lat1 <= qp.lat2 { (qp.lat1, qp.lat2) } else { (qp.lat2, qp.lat1) };
-    let (lon_min, lon_max) = if qp.lng1 <= qp.lng2 { (qp.lng1, qp.lng2) } else { (qp.lng2, qp.lng1) };
+    // Allow any two opposite corners; compute bounds. Omitted parameters fall back to the deployment defaults
+    validate::area(qp.lat1, qp.lng1, qp.lat2, qp.lng2, qp.date_start, qp.date_end)?;
+    let bbox = match defaults().bbox(qp.lat1, qp.lng1, qp.lat2, qp.lng2) {
+        Ok(b) => b,
+        Err(msg) => return Err(ApiError::bad_request(msg)),
+    };
+    let (date_start, date_end) = defaults().date_range(qp.date_start, qp.date_end);
+    let date_end = date_end.map(tile_rollups::end_of_hour);
+    let tz = match defaults().timezone(qp.timezone.as_deref()) {
+        Ok(tz) => tz,
+        Err(msg) => return Err(ApiError::bad_request(msg)),
+    };
+    let tiled = qp.tile_width.is_some() || qp.tile_height.is_some() || qp.tile_size_meters.is_some();
+    let binning = validate::binning(qp.binning.as_deref(), qp.resolution, tiled)?;
+    let tile = defaults().tile_size(&bbox, qp.tile_width, qp.tile_height, qp.tile_size_meters);
 
-    let lat_span = (lat_max - lat_min).max(0.0);
-    let lon_span = (lon_max - lon_min).max(0.0);
+    let full_stats = match qp.stats.as_deref() {
+        None | Some("basic") => false,
+        Some("full") => true,
+        Some(_) => return Err(ApiError::bad_param("stats", "stats must be basic or full")),
+    };
+    let freeflow = match qp.baseline.as_deref() {
+        None => false,
+        Some("freeflow") => true,
+        Some(_) => return Err(ApiError::bad_param("baseline", "baseline must be freeflow")),
+    };
+    let shape = TileShape::parse(qp.fields.as_deref(), qp.encoding.as_deref(), &SPEED_TILE_FIELDS)?;
 
-    let rows = if lat_span == 0.0 { 0 } else { ((lat_span / qp.tile_height).ceil() as usize).max(1) };
-    let cols = if lon_span == 0.0 { 0 } else { ((lon_span / qp.tile_width).ceil() as usize).max(1) };
+    let (bins, size) = validate::fit_bins(bbox, binning, tile, qp.tile_size_meters)?;
 
     // Early return if degenerate
-    if rows == 0 || cols == 0 {
-        let resp = SpeedmapResponse { speedmap: SpeedmapData { data: vec![] } };
-    info!("Speedmap degenerate area (rows=0 or cols=0), returning empty. took={:?}", started.elapsed());
-        return HttpResponse::Ok().json(resp);
+    if bins.is_empty() {
+        let resp = SpeedmapResponse { speedmap: SpeedmapData { data: vec![], size }, meta: MapMeta::new(&bins, 0, 0, started) };
+    info!("Speedmap degenerate area (no cells), returning empty. took={:?}", started.elapsed());
+        return shape.respond(&resp, "/speedmap/data");
     }
 
+    // Counts and speed sums on a rolled-up grid over whole hours are added up from the hourly
+    // tile rollups; percentiles need the speeds themselves
+    let plain = !full_stats && qp.days.is_none() && qp.time_start_tod.is_none() && qp.time_end_tod.is_none();
+    let rolled_up = if plain { tile_rollups::read(rollups.as_ref(), &bins, date_start, date_end).await } else { None };
+    let from_rollups = rolled_up.is_some();
+
     // First, get all points within bounds and optional time range, ordered by timestamp
-    let mut query = Points::find()
-        .filter(points::Column::Lat.between(lat_min, lat_max))
-        .filter(points::Column::Lng.between(lon_min, lon_max));
-    if let Some(ts_start) = qp.date_start { query = query.filter(points::Column::Timestamp.gte(ts_start)); }
-    if let Some(ts_end) = qp.date_end { query = query.filter(points::Column::Timestamp.lte(ts_end)); }
-    let mut all_points = match query
-        .order_by_asc(points::Column::Timestamp)
-        .all(db.get_ref()).await {
+    let filter = PointFilter {
+        bbox: Some(bbox),
+        since: date_start,
+        until: date_end,
+        ..Default::default()
+    };
+    let found = match rolled_up {
+        Some(_) => Ok(Vec::new()),
+        None => store.find(&filter, PointOrder::TimestampAsc, None).await,
+    };
+    let mut all_points = match found {
         Ok(p) => p,
         Err(e) => {
             error!("Speedmap query failed: {}", e);
-            return HttpResponse::InternalServerError().finish();
+            return Err(ApiError::Internal);
         }
     };
 
+    let rows_scanned = all_points.len();
+
     // Apply optional weekday and time-of-day filters
     let day_set = match &qp.days {
         Some(s) => match parse_days_of_week(s) { Ok(set) => Some(set), Err(e) => {
             warn!("Invalid days parameter '{}': {}", s, e);
-            return HttpResponse::BadRequest().body("days must contain numbers 1..7 separated by comma/space");
+            return Err(ApiError::bad_param("days", "days must contain numbers 1..7 separated by comma/space"));
         }},
         None => None,
     };
     let (tod_start, tod_end) = match (&qp.time_start_tod, &qp.time_end_tod) {
         (Some(a), Some(b)) => {
-            let a = match parse_time_of_day(a) { Ok(t) => t, Err(_) => { return HttpResponse::BadRequest().body("timeStart must be HH or HH:MM"); }};
-            let b = match parse_time_of_day(b) { Ok(t) => t, Err(_) => { return HttpResponse::BadRequest().body("timeEnd must be HH or HH:MM"); }};
-            if b <= a { warn!("Invalid time-of-day window: start={:?} end={:?}", a, b); return HttpResponse::BadRequest().body("timeEnd must be greater than timeStart (same-day window)"); }
+            let a = match parse_time_of_day(a) { Ok(t) => t, Err(_) => { return Err(ApiError::bad_param("timeStart", "timeStart must be HH or HH:MM")); }};
+            let b = match parse_time_of_day(b) { Ok(t) => t, Err(_) => { return Err(ApiError::bad_param("timeEnd", "timeEnd must be HH or HH:MM")); }};
+            if b <= a { warn!("Invalid time-of-day window: start={:?} end={:?}", a, b); return Err(ApiError::bad_param("timeEnd", "timeEnd must be greater than timeStart (same-day window)")); }
             (Some(a), Some(b))
         }
         (None, None) => (None, None),
-        _ => { return HttpResponse::BadRequest().body("Both timeStart and timeEnd must be provided together"); }
+        _ => { return Err(ApiError::bad_request("Both timeStart and timeEnd must be provided together")); }
     };
     if day_set.is_some() || tod_start.is_some() {
-        all_points = all_points.into_iter().filter(|p| {
+        all_points.retain(|p| {
             if let Some(ref set) = day_set {
-                if let Some(ts) = p.timestamp { let wd = ts.weekday(); let day_num = match wd { Weekday::Mon=>1,Weekday::Tue=>2,Weekday::Wed=>3,Weekday::Thu=>4,Weekday::Fri=>5,Weekday::Sat=>6,Weekday::Sun=>7 }; if !set.contains(&day_num) { return false; } } else { return false; }
+                if let Some(ts) = p.timestamp { let wd = ts.with_timezone(&tz).weekday(); let day_num = match wd { Weekday::Mon=>1,Weekday::Tue=>2,Weekday::Wed=>3,Weekday::Thu=>4,Weekday::Fri=>5,Weekday::Sat=>6,Weekday::Sun=>7 }; if !set.contains(&day_num) { return false; } } else { return false; }
             }
-            match (tod_start, tod_end) { (Some(s), Some(e)) => { if let Some(ts) = p.timestamp { let t = ts.time(); t >= s && t < e } else { false } } _ => true }
-        }).collect();
+            match (tod_start, tod_end) { (Some(s), Some(e)) => { if let Some(ts) = p.timestamp { let t = ts.with_timezone(&tz).time(); t >= s && t < e } else { false } } _ => true }
+        });
     }
-    let total_points_count = all_points.len();
+    let mut total_points_count = all_points.len();
+    let trips = distinct_trips(&all_points);
     debug!("Speedmap DB returned {} points after filters in {:?}", total_points_count, started.elapsed());
 
     // Bucket points into tiles: keep counts and sum of speeds for averaging
-    let mut counts = vec![0usize; rows * cols];
-    let mut speed_sums = vec![0f64; rows * cols];
-    let inv_h = 1.0 / qp.tile_height;
-    let inv_w = 1.0 / qp.tile_width;
+    let mut counts = vec![0usize; bins.len()];
+    let mut speed_sums = vec![0f64; bins.len()];
+    // Raw speeds per tile, only kept when percentiles were asked for
+    let mut speeds: Vec<Vec<f64>> = if full_stats { vec![Vec::new(); bins.len()] } else { Vec::new() };
 
     for p in all_points {
-        // Compute indices; clamp to [0, rows-1] / [0, cols-1]
-        let mut r = ((p.lat - lat_min) * inv_h).floor() as isize;
-        let mut c = ((p.lng - lon_min) * inv_w).floor() as isize;
-
-        if r < 0 { r = 0; }
-        if c < 0 { c = 0; }
-        if r as usize >= rows { r = rows as isize - 1; }
-        if c as usize >= cols { c = cols as isize - 1; }
-
-    let idx = (r as usize) * cols + (c as usize);
-    counts[idx] += 1;
-    // accumulate speed for average velocity
-    speed_sums[idx] += p.spd;
+        let Some(idx) = bins.index_of(p.lat, p.lng) else { continue };
+        counts[idx] += 1;
+        // accumulate speed for average velocity
+        speed_sums[idx] += p.spd;
+        if full_stats { speeds[idx].push(p.spd); }
+    }
+    if let Some(cells) = rolled_up {
+        counts = cells.counts;
+        speed_sums = cells.speed_sums;
+        total_points_count = counts.iter().sum();
     }
 
-    // Build response tiles (row-major from lat_min/lon_min increasing)
+    let baseline = if freeflow {
+        match freeflow_speeds(store.get_ref(), &bins, date_end, tz).await {
+            Ok(b) => Some(b),
+            Err(e) => {
+                error!("Speedmap free-flow baseline query failed: {}", e);
+                return Err(ApiError::Internal);
+            }
+        }
+    } else {
+        None
+    };
+
+    // Build response tiles (row-major from lat_min/lng_min increasing, H3 index order for hexagons)
     // Include tiles with data if tile has points or neighbors have points
     let mut data = Vec::new();
-    for r in 0..rows {
-        let tile_lat_min = lat_min + (r as f64) * qp.tile_height;
-        let tile_lat_max = (tile_lat_min + qp.tile_height).min(lat_max);
-        for c in 0..cols {
-            let tile_lon_min = lon_min + (c as f64) * qp.tile_width;
-            let tile_lon_max = (tile_lon_min + qp.tile_width).min(lon_max);
-
-            let idx = r * cols + c;
-            let point_count = counts[idx];
-            let sum = speed_sums[idx];
-            let avg_velocity = if point_count > 0 { sum / (point_count as f64) } else { 0.0 };
-
-            // Calculate neighbor average velocity (8 surrounding cells)
-            let mut neighbor_sum = 0.0f64;
-            let mut neighbor_points = 0usize;
-            for dr in -1..=1 {
-                for dc in -1..=1 {
-                    // Skip the center cell (the current tile itself)
-                    if dr == 0 && dc == 0 {
-                        continue;
-                    }
+    for idx in bins.cells() {
+        let point_count = counts[idx];
+        let sum = speed_sums[idx];
+        let avg_velocity = if point_count > 0 { sum / (point_count as f64) } else { 0.0 };
 
-                    let nr = r as isize + dr;
-                    let nc = c as isize + dc;
+        // Calculate neighbor average velocity (8 surrounding cells, 6 around a hexagon)
+        let neighbor_sum = bins.neighbor_sum(&speed_sums, idx);
+        let neighbor_points = bins.neighbor_sum(&counts, idx);
+        let neighbor_avg_velocity = if neighbor_points > 0 { neighbor_sum / (neighbor_points as f64) } else { 0.0 };
 
-                    // Check bounds
-                    if nr >= 0 && nr < rows as isize && nc >= 0 && nc < cols as isize {
-                        let neighbor_idx = (nr as usize) * cols + (nc as usize);
-                        neighbor_sum += speed_sums[neighbor_idx];
-                        neighbor_points += counts[neighbor_idx];
-                    }
-                }
+        // Include tiles with own data or neighbor data
+        if point_count > 0 || neighbor_points > 0 {
+            let stats = if full_stats { speed_stats(&mut speeds[idx]) } else { None };
+            let freeflow_speed = baseline.as_ref().and_then(|b| b[idx]);
+            let congestion = freeflow_speed.filter(|_| point_count > 0).map(|free| (avg_velocity / free).clamp(0.0, 1.0));
+            let cell = bins.cell_bbox(idx);
+            data.push(SpeedTile {
+                // naming requirement: return average velocities under 'count' fields
+                count: avg_velocity,
+                neighbor_count: neighbor_avg_velocity,
+                top_left: MapPoint { lat: cell.lat_min, lng: cell.lng_min },
+                bottom_right: MapPoint { lat: cell.lat_max, lng: cell.lng_max },
+                stats,
+                congestion,
+                freeflow_speed,
+                h3: bins.cell_id(idx),
+            });
+        }
+    }
+
+    let mut meta = MapMeta::new(&bins, total_points_count, trips, started);
+    if from_rollups {
+        meta = meta.from_rollups();
+    }
+    let resp = SpeedmapResponse { speedmap: SpeedmapData { data, size }, meta };
+    info!(
+        "Speedmap response: tiles={} (non-zero only) from grid={} total_points={} took={:?}",
+        resp.speedmap.data.len(), bins, counts.iter().sum::<usize>(), started.elapsed()
+    );
+    let stats = QueryStats { rows_scanned, tiles: resp.speedmap.data.len() };
+    Ok(stats.attach(shape.respond(&resp, "/speedmap/data")?))
+}
+
+#[utoipa::path(
+    get,
+    tag = "Speedmap",
+    params(
+    ("lat1" = f64, Query, description = "First latitude (corner). All four corners may be omitted when the deployment sets DEFAULT_BBOX"),
+    ("lng1" = f64, Query, description = "First longitude (corner)"),
+    ("lat2" = f64, Query, description = "Second latitude (opposite corner)"),
+    ("lng2" = f64, Query, description = "Second longitude (opposite corner)"),
+    ("dateStart" = DateTime<chrono::Utc>, Query, description = "Start of the date/time range (inclusive). Optional"),
+    ("dateEnd" = DateTime<chrono::Utc>, Query, description = "End of the date/time range (inclusive). Optional"),
+    ("tileWidth" = f64, Query, description = "Width of each tile in degrees, with mode=tiles. Required unless tileSizeMeters is given"),
+    ("tileHeight" = f64, Query, description = "Height of each tile in degrees, with mode=tiles. Required unless tileSizeMeters is given"),
+    ("tileSizeMeters" = f64, Query, description = "Side of each tile in meters. Replaces tileWidth/tileHeight"),
+    ("binning" = String, Query, description = "Cell shape: grid (default) or h3"),
+    ("resolution" = u8, Query, description = "H3 resolution (0..15) with binning=h3. Optional, defaults to 8"),
+    ("mode" = String, Query, description = "tiles (default): checked points, violations and average speed against the average limit per tile; trips: trips with violations, the most first, with their worst point"),
+    ("margin" = f64, Query, description = "km/h over a segment's limit that counts as speeding. Optional, defaults to SPEED_LIMIT_MARGIN_KMH (10)"),
+    ("limit" = usize, Query, description = "Trips sent with mode=trips. Optional, defaults to 100, at most 1000"),
+    ),
+    responses(
+        (status = 200, description = "Speeding by tile or by trip; empty without road segments in the area", body = ViolationsResponse),
+        (status = 400, description = "Invalid parameters; `details.param` names the offending one when known", body = ApiErrorBody),
+        (status = 413, description = "Grid over GRID_MAX_CELLS with GRID_OVERSIZE=reject", body = ApiErrorBody),
+        (status = 500, description = "Server Vzorvalsya", body = ApiErrorBody),
+    )
+)]
+
+/// Points compared with the speed limit of the road segment they lie on (`road_segments`,
+/// loaded by `indrive import-roads`), to flag the tiles and trips where traffic speeds
+#[get("/violations")]
+pub async fn get_violations(
+    store: web::Data<dyn PointStore>,
+    roads: web::Data<dyn RoadSegmentStore>,
+    qp: web::Query<ViolationsQueryParams>,
+) -> Result<HttpResponse, ApiError> {
+    let started = Instant::now();
+    validate::area(qp.lat1, qp.lng1, qp.lat2, qp.lng2, qp.date_start, qp.date_end)?;
+    let bbox = defaults().bbox(qp.lat1, qp.lng1, qp.lat2, qp.lng2).map_err(ApiError::bad_request)?;
+    let (date_start, date_end) = defaults().date_range(qp.date_start, qp.date_end);
+    let margin = qp.margin.unwrap_or(*SPEED_LIMIT_MARGIN);
+    if !(margin.is_finite() && margin >= 0.0) {
+        return Err(ApiError::bad_param("margin", "margin must be a non-negative number of km/h"));
+    }
+    let by_trip = match qp.mode.as_deref() {
+        None | Some("tiles") => false,
+        Some("trips") => true,
+        Some(_) => return Err(ApiError::bad_param("mode", "mode must be tiles or trips")),
+    };
+    let limit = qp.limit.unwrap_or(DEFAULT_VIOLATION_TRIPS);
+    if limit == 0 || limit > MAX_VIOLATION_TRIPS {
+        return Err(ApiError::bad_param("limit", format!("limit must be within 1..={}", MAX_VIOLATION_TRIPS)));
+    }
+    let bins = if by_trip {
+        None
+    } else {
+        let tiled = qp.tile_width.is_some() || qp.tile_height.is_some() || qp.tile_size_meters.is_some();
+        let binning = validate::binning(qp.binning.as_deref(), qp.resolution, tiled)?;
+        let tile = defaults().tile_size(&bbox, qp.tile_width, qp.tile_height, qp.tile_size_meters);
+        Some(validate::fit_bins(bbox, binning, tile, qp.tile_size_meters)?.0)
+    };
+
+    // Segments just outside the area still match the points along its edge
+    let snap = *SPEED_LIMIT_SNAP_M;
+    let pad_lat = crate::geo::meters_to_lat_deg(snap);
+    let pad_lng = crate::geo::meters_to_lng_deg(snap, bbox.lat_min.abs().max(bbox.lat_max.abs()));
+    let (lng_min, lng_max) = if bbox.lng_span() + 2.0 * pad_lng >= 360.0 {
+        (-180.0, 180.0)
+    } else {
+        (crate::geo::wrap_lng(bbox.lng_min - pad_lng), crate::geo::wrap_lng(bbox.lng_max + pad_lng))
+    };
+    let padded = BBox { lat_min: bbox.lat_min - pad_lat, lat_max: bbox.lat_max + pad_lat, lng_min, lng_max };
+    let segments = roads.road_segments_in(&padded).await.map_err(|e| {
+        error!("Road segment query failed: {}", e);
+        ApiError::Internal
+    })?;
+    let index = SegmentIndex::new(segments, padded, snap);
+    let points = if index.is_empty() {
+        Vec::new()
+    } else {
+        let filter = PointFilter { bbox: Some(bbox), since: date_start, until: date_end, ..Default::default() };
+        store.find(&filter, PointOrder::TimestampAsc, None).await.map_err(|e| {
+            error!("Violations query failed: {}", e);
+            ApiError::Internal
+        })?
+    };
+
+    let mut resp = ViolationsResponse { margin, points: points.len(), checked: 0, segments: index.len(), tiles: None, trips: None };
+    match bins {
+        Some(bins) => {
+            // Checked points, violations, speed sum and limit sum per cell
+            let mut cells = vec![(0usize, 0usize, 0f64, 0f64); bins.len()];
+            for p in &points {
+                let (Some(segment), Some(idx)) = (index.nearest(p.lat, p.lng), bins.index_of(p.lat, p.lng)) else { continue };
+                let speed = p.spd * 3.6;
+                let cell = &mut cells[idx];
+                cell.0 += 1;
+                cell.1 += usize::from(speed > segment.speed_limit + margin);
+                cell.2 += speed;
+                cell.3 += segment.speed_limit;
             }
-            let neighbor_avg_velocity = if neighbor_points > 0 { neighbor_sum / (neighbor_points as f64) } else { 0.0 };
-
-            // Include tiles with own data or neighbor data
-            if point_count > 0 || neighbor_points > 0 {
-                data.push(SpeedTile {
-                    // naming requirement: return average velocities under 'count' fields
-                    count: avg_velocity,
-                    neighbor_count: neighbor_avg_velocity,
-                    top_left: MapPoint { lat: tile_lat_min, lng: tile_lon_min },
-                    bottom_right: MapPoint { lat: tile_lat_max, lng: tile_lon_max },
+            resp.checked = cells.iter().map(|c| c.0).sum();
+            let tiles = bins
+                .cells()
+                .filter(|&idx| cells[idx].0 > 0)
+                .map(|idx| {
+                    let (checked, violations, speeds, limits) = cells[idx];
+                    let (avg_speed, speed_limit) = (speeds / checked as f64, limits / checked as f64);
+                    let cell = bins.cell_bbox(idx);
+                    ViolationTile {
+                        top_left: MapPoint { lat: cell.lat_min, lng: cell.lng_min },
+                        bottom_right: MapPoint { lat: cell.lat_max, lng: cell.lng_max },
+                        h3: bins.cell_id(idx),
+                        checked,
+                        violations,
+                        avg_speed,
+                        speed_limit,
+                        exceeds: avg_speed > speed_limit + margin,
+                    }
+                })
+                .collect();
+            resp.tiles = Some(tiles);
+        }
+        None => {
+            let mut trips: std::collections::HashMap<i64, ViolationTrip> = std::collections::HashMap::new();
+            for p in &points {
+                let Some(segment) = index.nearest(p.lat, p.lng) else { continue };
+                resp.checked += 1;
+                let speed = p.spd * 3.6;
+                let excess = speed - segment.speed_limit;
+                let worst = SpeedingPoint { lat: p.lat, lng: p.lng, timestamp: p.timestamp, speed, speed_limit: segment.speed_limit, road: segment.name.clone() };
+                let trip = trips.entry(p.randomized_id).or_insert_with(|| ViolationTrip {
+                    randomized_id: p.randomized_id,
+                    checked: 0,
+                    violations: 0,
+                    max_excess: excess,
+                    worst: worst.clone(),
                 });
+                trip.checked += 1;
+                trip.violations += usize::from(excess > margin);
+                if excess > trip.max_excess {
+                    trip.max_excess = excess;
+                    trip.worst = worst;
+                }
             }
+            let mut trips: Vec<ViolationTrip> = trips.into_values().filter(|t| t.violations > 0).collect();
+            trips.sort_by(|a, b| b.violations.cmp(&a.violations).then(b.max_excess.total_cmp(&a.max_excess)).then(a.randomized_id.cmp(&b.randomized_id)));
+            trips.truncate(limit);
+            resp.trips = Some(trips);
         }
     }
-
-    let resp = SpeedmapResponse { speedmap: SpeedmapData { data } };
     info!(
-        "Speedmap response: tiles={} (non-zero only) from grid={}x{} total_points={} took={:?}",
-        resp.speedmap.data.len(), rows, cols, counts.iter().sum::<usize>(), started.elapsed()
+        "Violations response: {} of {} points checked against {} segments, margin={} km/h, took={:?}",
+        resp.checked, resp.points, resp.segments, margin, started.elapsed()
     );
-    HttpResponse::Ok().json(resp)
+    let stats = QueryStats { rows_scanned: points.len(), tiles: resp.tiles.as_ref().map_or(0, Vec::len) + resp.trips.as_ref().map_or(0, Vec::len) };
+    Ok(stats.attach(HttpResponse::Ok().json(resp)))
 }
 
 // --- Helpers ---
 
+/// Average night-time speed per tile over the free-flow history ending at `until` (now when
+/// open); None where a tile has fewer than MIN_FREEFLOW_POINTS or they average no speed. The
+/// database sums the points per grid tile; hexagons take squares a quarter of their edge long,
+/// each counted in the cell holding the mean position of its points.
+async fn freeflow_speeds(
+    store: &dyn PointStore,
+    bins: &Bins,
+    until: Option<DateTime<Utc>>,
+    tz: FixedOffset,
+) -> crate::database::store::StoreResult<Vec<Option<f64>>> {
+    let until = until.unwrap_or_else(Utc::now);
+    let bbox = bins.bbox();
+    let filter = PointFilter {
+        bbox: Some(bbox),
+        since: Some(until - Duration::days(*FREEFLOW_DAYS)),
+        until: Some(until),
+        ..Default::default()
+    };
+    let (start, end) = *FREEFLOW_HOURS;
+    let window = DayWindow { start: start.num_seconds_from_midnight(), end: end.num_seconds_from_midnight(), offset: tz.local_minus_utc() };
+    let (width, height) = match bins {
+        Bins::Rect(grid) => (grid.tile_width, grid.tile_height),
+        Bins::Hex(hex) => {
+            let side = hex.resolution.edge_length_km() * 1000.0 / 4.0;
+            (crate::geo::meters_to_lng_deg(side, bbox.lat_min.abs().max(bbox.lat_max.abs())), crate::geo::meters_to_lat_deg(side))
+        }
+    };
+    let grid = TileGrid { lat0: bbox.lat_min, lng0: bbox.lng_min, width, height };
+    let mut counts = vec![0u64; bins.len()];
+    let mut sums = vec![0f64; bins.len()];
+    for tile in store.tile_speeds(&filter, grid, window).await? {
+        if let Some(idx) = bins.index_of(tile.lat, tile.lng) {
+            counts[idx] += tile.points;
+            sums[idx] += tile.speed_sum;
+        }
+    }
+    Ok(counts
+        .into_iter()
+        .zip(sums)
+        .map(|(n, sum)| Some(sum / n as f64).filter(|avg| n >= MIN_FREEFLOW_POINTS && *avg > 0.0))
+        .collect())
+}
+
+/// Percentiles of `values` (sorted in place); None for an empty tile
+fn speed_stats(values: &mut [f64]) -> Option<SpeedStats> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(|a, b| a.total_cmp(b));
+    Some(SpeedStats {
+        p15: percentile(values, 0.15),
+        p50: percentile(values, 0.50),
+        p85: percentile(values, 0.85),
+        min: values[0],
+        max: values[values.len() - 1],
+    })
+}
+
+fn percentile(sorted: &[f64], q: f64) -> f64 {
+    let pos = q * (sorted.len() - 1) as f64;
+    let lo = pos.floor() as usize;
+    let hi = pos.ceil() as usize;
+    sorted[lo] + (sorted[hi] - sorted[lo]) * (pos - lo as f64)
+}
+
 fn parse_days_of_week(input: &str) -> Result<std::collections::HashSet<u8>, String> {
     let mut set = std::collections::HashSet::new();
     for token in input.split(|c: char| c == ',' || c.is_whitespace()) {
@@ -291,9 +721,8 @@ fn parse_time_of_day(input: &str) -> Result<NaiveTime, String> {
     Err("invalid time format".to_string())
 }
 
-pub fn init_routes(cfg: &mut web::ServiceConfig) {
-    cfg.service(
-        web::scope("/speedmap")
-            .service(get_speedmap)
-    );
+pub fn routes() -> ApiScope {
+    ApiScope::new("/speedmap")
+        .service(get_speedmap)
+        .service(get_violations)
 }
\ No newline at end of file
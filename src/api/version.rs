@@ -0,0 +1,120 @@
+use actix_web::{get, web, HttpResponse};
+use log::warn;
+use sea_orm::DatabaseConnection;
+use sea_orm_migration::{MigrationStatus, MigratorTrait};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::migration::Migrator;
+use super::registry::{ApiScope, API_VERSION};
+
+/// Optional subsystems of this deployment, decided at startup. Built in `main.rs` and shared as app data.
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct Features {
+    /// Point store behind the analytics endpoints, e.g. `postgres` or `postgres+clickhouse`
+    #[serde(rename = "storeBackend")]
+    pub store_backend: String,
+    /// Points are buffered on disk while the database is down (INGEST_WAL_PATH)
+    #[serde(rename = "ingestWal")]
+    pub ingest_wal: bool,
+    /// Accepted batches are journaled until stored (INGEST_JOURNAL_DIR)
+    #[serde(rename = "ingestJournal")]
+    pub ingest_journal: bool,
+    /// Points are collected into bulk inserts (INGEST_BATCH_ROWS)
+    #[serde(rename = "ingestBatch")]
+    pub ingest_batch: bool,
+    /// Points are also taken over gRPC (GRPC_PORT)
+    pub grpc: bool,
+    /// Device messages are taken from an MQTT broker (MQTT_URL)
+    pub mqtt: bool,
+    /// Point batches are consumed from Kafka (KAFKA_BROKERS, builds with `--features kafka`)
+    pub kafka: bool,
+    /// Public reads lag behind ingestion (PUBLICATION_DELAY_SECS / PUBLICATION_DELAY_REGIONS)
+    #[serde(rename = "publicationDelay")]
+    pub publication_delay: bool,
+    /// Trips are snapped to roads (MAP_MATCHING_URL)
+    #[serde(rename = "mapMatching")]
+    pub map_matching: bool,
+    /// Anomaly routes and stop hotspots can be named (GEOCODER_URL)
+    pub geocoding: bool,
+    /// Weekly traffic reports are written to disk (REPORTS_DIR)
+    pub reports: bool,
+    /// Points can be exported to S3-compatible storage in the background (EXPORT_S3_BUCKET)
+    pub exports: bool,
+    /// Converted images survive restarts (IMAGE_DISK_CACHE_DIR)
+    #[serde(rename = "imageDiskCache")]
+    pub image_disk_cache: bool,
+    /// Aligned map requests are answered from hourly tile rollups (TILE_ROLLUP_DEGREES)
+    #[serde(rename = "tileRollups")]
+    pub tile_rollups: bool,
+    /// Data is kept apart per tenant (TENANT_API_KEYS / TENANT_HEADER)
+    pub tenants: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct SchemaVersion {
+    /// Newest migration applied to the database; None on an empty database or when unknown
+    pub applied: Option<String>,
+    /// Newest migration this build knows
+    pub latest: String,
+    /// Migrations of this build not applied yet
+    pub pending: Vec<String>,
+    /// Database schema and build agree: nothing pending and no migration unknown to this build
+    pub compatible: bool,
+    /// Why the migration status could not be read, e.g. the database is newer than this build
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct VersionResponse {
+    /// Application version (Cargo package version)
+    pub version: String,
+    pub schema: SchemaVersion,
+    /// Versions of the /api contract this server speaks
+    #[serde(rename = "apiVersions")]
+    pub api_versions: Vec<String>,
+    pub features: Features,
+}
+
+#[utoipa::path(
+    get,
+    tag = "Meta",
+    responses(
+        (status = 200, description = "Application, schema and API versions plus enabled features", body = VersionResponse),
+    )
+)]
+
+#[get("")]
+pub async fn get_version(
+    db: web::Data<DatabaseConnection>,
+    features: web::Data<Features>,
+) -> HttpResponse {
+    let latest = Migrator::migrations().last().map(|m| m.name().to_string()).unwrap_or_default();
+    let schema = match Migrator::get_migration_with_status(db.get_ref()).await {
+        Ok(migrations) => {
+            let applied = migrations.iter().rfind(|m| m.status() == MigrationStatus::Applied).map(|m| m.name().to_string());
+            let pending: Vec<String> = migrations
+                .iter()
+                .filter(|m| m.status() == MigrationStatus::Pending)
+                .map(|m| m.name().to_string())
+                .collect();
+            SchemaVersion { applied, latest, compatible: pending.is_empty(), pending, error: None }
+        }
+        Err(e) => {
+            warn!("Migration status unavailable for /api/version: {}", e);
+            SchemaVersion { applied: None, latest, pending: Vec::new(), compatible: false, error: Some(e.to_string()) }
+        }
+    };
+
+    HttpResponse::Ok().json(VersionResponse {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        schema,
+        api_versions: vec![API_VERSION.to_string()],
+        features: features.get_ref().clone(),
+    })
+}
+
+pub fn routes() -> ApiScope {
+    ApiScope::new("/version")
+        .service(get_version)
+}
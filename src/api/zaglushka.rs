@@ -1,14 +1,22 @@
 use actix_web::{post, web, HttpResponse};
+use super::registry::ApiScope;
 
 // Temporary stub endpoint: responds with integer 1 to any POST payload
+#[utoipa::path(
+    post,
+    tag = "Zaglushka",
+    request_body(content = Vec<u8>, description = "Ignored", content_type = "application/octet-stream"),
+    responses(
+        (status = 200, description = "Always 1", body = i32)
+    )
+)]
+
 #[post("")]
 pub async fn stub_always_one(_body: web::Bytes) -> HttpResponse {
     HttpResponse::Ok().json(1)
 }
 
-pub fn init_routes(cfg: &mut web::ServiceConfig) {
-    cfg.service(
-        web::scope("/zaglushka")
-            .service(stub_always_one)
-    );
+pub fn routes() -> ApiScope {
+    ApiScope::new("/zaglushka")
+        .service(stub_always_one)
 }
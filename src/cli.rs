@@ -0,0 +1,281 @@
+//! Command line of the `indrive` binary. Without a subcommand it serves, as before; `migrate`
+//! lets a deploy apply the schema once before starting several replicas with `serve --no-migrate`.
+
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::{Args, Parser, Subcommand};
+use log::info;
+use sea_orm::{DatabaseConnection, DbErr};
+use sea_orm_migration::{MigrationStatus, MigratorTrait};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::api;
+use crate::api::defaults::parse_bbox;
+use crate::api::points::{ExportFormat, StoredPoint, EXPORT_BATCH};
+use crate::exports::parquet::{PointsParquet, ROW_GROUP};
+use crate::database::store::{self, BBox, CircuitBreaker, PointFilter, PointStore, RoadSegmentStore, SeaOrmPointStore};
+use crate::migration::Migrator;
+use crate::roads;
+use crate::seed;
+
+#[derive(Debug, Parser)]
+#[command(name = "indrive", version, about = "Trip analytics server")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Apply pending migrations and start the HTTP server (the default)
+    Serve(ServeArgs),
+    /// Apply, roll back or list database migrations
+    Migrate {
+        #[command(subcommand)]
+        action: MigrateAction,
+    },
+    /// Write stored points as NDJSON, CSV or Parquet, like GET /api/points/export
+    Export(ExportArgs),
+    /// Fill the database with synthetic trips for development and demos
+    Seed(SeedArgs),
+    /// Replace the speed limits of /api/speedmap/violations with the roads of an OSM extract
+    ImportRoads {
+        /// OSM XML (`.osm`); convert a `.osm.pbf` with osmium first
+        path: PathBuf,
+        /// Country code (KZ, RU, ...) whose implicit limits apply to roads without a
+        /// `maxspeed`, by their highway class
+        #[arg(long, value_parser = country_arg)]
+        country: Option<String>,
+    },
+    /// Write the TypeScript API client for the frontend build
+    EmitClient {
+        path: PathBuf,
+    },
+}
+
+impl Default for Command {
+    fn default() -> Self {
+        Command::Serve(ServeArgs::default())
+    }
+}
+
+#[derive(Debug, Default, Args)]
+pub struct ServeArgs {
+    /// Do not migrate; only check that no migration is pending (run `indrive migrate up` first)
+    #[arg(long)]
+    pub no_migrate: bool,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum MigrateAction {
+    /// Apply pending migrations
+    Up {
+        /// At most this many; all when omitted
+        #[arg(long)]
+        steps: Option<u32>,
+    },
+    /// Roll back applied migrations, newest first
+    Down {
+        #[arg(long, default_value_t = 1)]
+        steps: u32,
+    },
+    /// List migrations and whether each one is applied
+    Status,
+}
+
+#[derive(Debug, Args)]
+pub struct ExportArgs {
+    #[arg(long, default_value = "ndjson", value_parser = ["ndjson", "csv", "parquet"])]
+    pub format: String,
+    /// File to write; standard output when omitted
+    #[arg(long, short)]
+    pub output: Option<PathBuf>,
+    /// Area as lat1,lng1,lat2,lng2
+    #[arg(long, value_parser = bbox_arg)]
+    pub bbox: Option<BBox>,
+    /// Start of the period (inclusive), a date or an RFC 3339 time
+    #[arg(long, value_parser = time_arg)]
+    pub from: Option<DateTime<Utc>>,
+    /// End of the period (inclusive)
+    #[arg(long, value_parser = time_arg)]
+    pub to: Option<DateTime<Utc>>,
+    /// Only points of this trip
+    #[arg(long)]
+    pub trip: Option<i64>,
+    /// Only anomalous (true) or normal (false) points
+    #[arg(long)]
+    pub anomaly: Option<bool>,
+}
+
+#[derive(Debug, Args)]
+pub struct SeedArgs {
+    #[arg(long, default_value_t = 200)]
+    pub trips: u32,
+    /// Area as lat1,lng1,lat2,lng2; DEFAULT_BBOX when omitted
+    #[arg(long, value_parser = bbox_arg)]
+    pub bbox: Option<BBox>,
+    /// Start of the period, a date or an RFC 3339 time; 7 days ago when omitted
+    #[arg(long, value_parser = time_arg)]
+    pub from: Option<DateTime<Utc>>,
+    /// End of the period; now when omitted
+    #[arg(long, value_parser = time_arg)]
+    pub to: Option<DateTime<Utc>>,
+    /// The same seed gives the same trips
+    #[arg(long)]
+    pub rng_seed: Option<u64>,
+}
+
+/// Runs every command except `serve`, which `main.rs` wires up itself
+pub async fn run(command: Command) -> io::Result<()> {
+    match command {
+        Command::Serve(_) => unreachable!("serve is started by main"),
+        Command::EmitClient { path } => {
+            std::fs::write(&path, api::client::source())?;
+            info!("TypeScript client written to {}", path.display());
+            Ok(())
+        }
+        Command::Migrate { action } => {
+            let (db, _) = crate::database::pool::connect_from_env().await;
+            migrate(&db, action).await.map_err(io::Error::other)
+        }
+        Command::Export(args) => {
+            let (db, replica) = crate::database::pool::connect_from_env().await;
+            let store = store::from_env(db, replica, Arc::new(CircuitBreaker::from_env())).await.map_err(io::Error::other)?;
+            let written = export(store.as_ref(), &args).await?;
+            info!("Exported {} points", written);
+            Ok(())
+        }
+        Command::Seed(args) => {
+            let config = seed::SeedConfig::new(args.trips, args.bbox, args.from, args.to, args.rng_seed).map_err(io::Error::other)?;
+            let (db, replica) = crate::database::pool::connect_from_env().await;
+            // A fresh development database is seeded right away
+            Migrator::up(&db, None).await.map_err(io::Error::other)?;
+            let store = store::from_env(db, replica, Arc::new(CircuitBreaker::from_env())).await.map_err(io::Error::other)?;
+            let stored = seed::run(store.as_ref(), &config).await.map_err(io::Error::other)?;
+            info!("Seeded {} trips ({} points)", config.trips, stored);
+            Ok(())
+        }
+        Command::ImportRoads { path, country } => {
+            let roads = tokio::task::spawn_blocking(move || roads::read_osm(&path, country.as_deref())).await.map_err(io::Error::other)?.map_err(io::Error::other)?;
+            let (db, _) = crate::database::pool::connect_from_env().await;
+            let stored = SeaOrmPointStore::new(db).replace_road_segments(roads.segments).await.map_err(io::Error::other)?;
+            info!(
+                "Imported {} road segments of {} ways ({} with an implicit limit); {} roads without a known limit skipped",
+                stored, roads.ways, roads.implicit, roads.skipped
+            );
+            Ok(())
+        }
+    }
+}
+
+async fn migrate(db: &DatabaseConnection, action: MigrateAction) -> Result<(), DbErr> {
+    match action {
+        MigrateAction::Up { steps } => {
+            let pending = Migrator::get_pending_migrations(db).await?.len();
+            Migrator::up(db, steps).await?;
+            info!("Applied {} of {} pending migrations", steps.map_or(pending, |s| pending.min(s as usize)), pending);
+        }
+        MigrateAction::Down { steps } => {
+            Migrator::down(db, Some(steps)).await?;
+            info!("Rolled back up to {} migrations", steps);
+        }
+        MigrateAction::Status => {
+            for m in Migrator::get_migration_with_status(db).await? {
+                let status = match m.status() {
+                    MigrationStatus::Applied => "applied",
+                    MigrationStatus::Pending => "pending",
+                };
+                println!("{:<8} {}", status, m.name());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Same rows and format as `GET /api/points/export`, ascending by id; returns the number written
+async fn export(store: &dyn PointStore, args: &ExportArgs) -> io::Result<u64> {
+    let filter = PointFilter {
+        bbox: args.bbox,
+        since: args.from,
+        until: args.to,
+        randomized_id: args.trip,
+        anomaly: args.anomaly,
+        ..Default::default()
+    };
+    let out: Box<dyn Write + Send> = match &args.output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout())),
+    };
+    match ExportFormat::parse(&args.format) {
+        Some(format) => export_lines(store, &filter, format, out).await,
+        None => export_parquet(store, &filter, out).await,
+    }
+}
+
+async fn export_lines(store: &dyn PointStore, filter: &PointFilter, format: ExportFormat, mut out: Box<dyn Write + Send>) -> io::Result<u64> {
+    out.write_all(format.header().as_bytes())?;
+    let (mut after_id, mut written) = (0, 0);
+    loop {
+        let rows = store.find_after(filter, after_id, EXPORT_BATCH).await.map_err(io::Error::other)?;
+        let Some(last_id) = rows.last().map(|r| r.id) else { break };
+        let full = rows.len() as u64 == EXPORT_BATCH;
+        let mut buf = String::new();
+        for row in rows {
+            api::points::write_export_row(&mut buf, format, &StoredPoint::from(row)).map_err(io::Error::other)?;
+            written += 1;
+        }
+        out.write_all(buf.as_bytes())?;
+        if !full {
+            break;
+        }
+        after_id = last_id;
+    }
+    out.flush()?;
+    Ok(written)
+}
+
+/// A row group per ROW_GROUP rows, so memory stays bounded however many points match
+async fn export_parquet(store: &dyn PointStore, filter: &PointFilter, out: Box<dyn Write + Send>) -> io::Result<u64> {
+    let mut columns = PointsParquet::new(out).map_err(io::Error::other)?;
+    let (mut group, mut after_id, mut written) = (Vec::new(), 0, 0);
+    loop {
+        let rows = store.find_after(filter, after_id, EXPORT_BATCH).await.map_err(io::Error::other)?;
+        let Some(last_id) = rows.last().map(|r| r.id) else { break };
+        let full = rows.len() as u64 == EXPORT_BATCH;
+        written += rows.len() as u64;
+        group.extend(rows);
+        if group.len() >= ROW_GROUP {
+            columns.write(&group).map_err(io::Error::other)?;
+            group.clear();
+        }
+        if !full {
+            break;
+        }
+        after_id = last_id;
+    }
+    if !group.is_empty() {
+        columns.write(&group).map_err(io::Error::other)?;
+    }
+    columns.finish().map_err(io::Error::other)?.flush()?;
+    Ok(written)
+}
+
+fn bbox_arg(s: &str) -> Result<BBox, String> {
+    parse_bbox(s).ok_or_else(|| "expected lat1,lng1,lat2,lng2".to_string())
+}
+
+/// `YYYY-MM-DD` (midnight UTC) or an RFC 3339 time
+fn time_arg(s: &str) -> Result<DateTime<Utc>, String> {
+    s.parse::<DateTime<Utc>>()
+        .ok()
+        .or_else(|| NaiveDate::parse_from_str(s, "%Y-%m-%d").ok().map(|d| d.and_hms_opt(0, 0, 0).unwrap().and_utc()))
+        .ok_or_else(|| "expected a date (YYYY-MM-DD) or an RFC 3339 time".to_string())
+}
+
+/// A country with implicit speed limits known to `roads`
+fn country_arg(s: &str) -> Result<String, String> {
+    let country = s.trim().to_ascii_uppercase();
+    if roads::is_known_country(&country) { Ok(country) } else { Err(format!("no implicit speed limits known for '{}'", s)) }
+}
@@ -0,0 +1,96 @@
+use actix_web::dev::ServiceRequest;
+use actix_web::http::header::HeaderMap;
+use log::warn;
+use once_cell::sync::Lazy;
+use std::env;
+use std::net::{IpAddr, SocketAddr};
+
+/// Network prefix such as 10.0.0.0/8 or a single address
+#[derive(Debug, Clone, Copy)]
+struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    fn parse(s: &str) -> Option<Self> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((a, p)) => (a.trim().parse::<IpAddr>().ok()?, Some(p.trim().parse::<u8>().ok()?)),
+            None => (s.trim().parse::<IpAddr>().ok()?, None),
+        };
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(max);
+        (prefix <= max).then_some(Self { addr, prefix })
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = if self.prefix == 0 { 0 } else { u32::MAX << (32 - self.prefix) };
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = if self.prefix == 0 { 0 } else { u128::MAX << (128 - self.prefix) };
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Proxies whose X-Forwarded-For / X-Real-IP headers we believe, from TRUSTED_PROXIES
+/// (comma separated addresses or CIDRs). Empty means forwarding headers are ignored.
+static TRUSTED_PROXIES: Lazy<Vec<Cidr>> = Lazy::new(|| {
+    env::var("TRUSTED_PROXIES")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .filter_map(|s| {
+            let cidr = Cidr::parse(s);
+            if cidr.is_none() {
+                warn!("Ignoring invalid TRUSTED_PROXIES entry '{}'", s);
+            }
+            cidr
+        })
+        .collect()
+});
+
+fn is_trusted(ip: IpAddr) -> bool {
+    TRUSTED_PROXIES.iter().any(|c| c.contains(ip))
+}
+
+/// Resolves the real client address. Forwarding headers are only honored when the direct peer
+/// is a trusted proxy; X-Forwarded-For is walked right to left, skipping further trusted hops.
+pub fn resolve(peer: Option<SocketAddr>, headers: &HeaderMap) -> Option<IpAddr> {
+    let peer_ip = peer.map(|p| p.ip())?;
+    if !is_trusted(peer_ip) {
+        return Some(peer_ip);
+    }
+
+    let forwarded: Vec<IpAddr> = headers
+        .get_all("x-forwarded-for")
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|s| s.trim().parse::<IpAddr>().ok())
+        .collect();
+    if let Some(ip) = forwarded.iter().rev().find(|ip| !is_trusted(**ip)) {
+        return Some(*ip);
+    }
+    if let Some(ip) = headers
+        .get("x-real-ip")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|v| v.trim().parse::<IpAddr>().ok())
+    {
+        return Some(ip);
+    }
+    // Every hop is trusted: the leftmost one is the closest thing to a client we have
+    forwarded.first().copied().or(Some(peer_ip))
+}
+
+/// Client address of a request as a string, "-" when unknown
+pub fn of_request(req: &ServiceRequest) -> String {
+    resolve(req.peer_addr(), req.headers())
+        .map(|ip| ip.to_string())
+        .unwrap_or_else(|| "-".to_string())
+}
@@ -0,0 +1,114 @@
+use actix_cors::Cors;
+use actix_web::http::header::HeaderName;
+use actix_web::http::{Method, Uri};
+use log::{info, warn};
+use std::env;
+
+const DEFAULT_METHODS: &str = "GET,POST,PUT,PATCH,DELETE";
+const DEFAULT_HEADERS: &str = "content-type,authorization,x-api-key,x-request-id";
+const DEFAULT_MAX_AGE_SECS: usize = 3600;
+/// Response headers a cross-origin script may read besides the CORS-safelisted ones
+const EXPOSED_HEADERS: [&str; 5] = ["x-request-id", "deprecation", "link", "retry-after", "location"];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Origins {
+    Any,
+    List(Vec<String>),
+}
+
+/// Cross-origin access to `/api` for frontends served from another domain.
+/// CORS_ALLOWED_ORIGINS is a comma separated list of origins such as `https://app.example.com`,
+/// or `*` for any; unset or empty keeps CORS off, as before. CORS_ALLOWED_METHODS and
+/// CORS_ALLOWED_HEADERS override the defaults above, CORS_ALLOW_CREDENTIALS=true lets browsers
+/// send cookies and Authorization (not with `*`), CORS_MAX_AGE_SECS is how long a preflight is cached.
+#[derive(Debug, Clone)]
+pub struct CorsConfig {
+    origins: Option<Origins>,
+    methods: Vec<Method>,
+    headers: Vec<HeaderName>,
+    credentials: bool,
+    max_age: usize,
+}
+
+impl CorsConfig {
+    pub fn from_env() -> Self {
+        let origins = env::var("CORS_ALLOWED_ORIGINS").unwrap_or_default();
+        let origins = if origins.trim() == "*" {
+            Some(Origins::Any)
+        } else {
+            let list: Vec<String> = list(&origins)
+                .filter(|o| {
+                    let valid = valid_origin(o);
+                    if !valid {
+                        warn!("Ignoring invalid CORS_ALLOWED_ORIGINS entry '{}'; expected scheme://host[:port]", o);
+                    }
+                    valid
+                })
+                .map(|o| o.trim_end_matches('/').to_string())
+                .collect();
+            (!list.is_empty()).then_some(Origins::List(list))
+        };
+        let methods = list(&env::var("CORS_ALLOWED_METHODS").unwrap_or_else(|_| DEFAULT_METHODS.to_string()))
+            .filter_map(|m| {
+                let method = Method::from_bytes(m.to_ascii_uppercase().as_bytes()).ok();
+                if method.is_none() {
+                    warn!("Ignoring invalid CORS_ALLOWED_METHODS entry '{}'", m);
+                }
+                method
+            })
+            .collect();
+        let headers = list(&env::var("CORS_ALLOWED_HEADERS").unwrap_or_else(|_| DEFAULT_HEADERS.to_string()))
+            .filter_map(|h| {
+                let header = HeaderName::from_bytes(h.to_ascii_lowercase().as_bytes()).ok();
+                if header.is_none() {
+                    warn!("Ignoring invalid CORS_ALLOWED_HEADERS entry '{}'", h);
+                }
+                header
+            })
+            .collect();
+        let mut credentials = env::var("CORS_ALLOW_CREDENTIALS").is_ok_and(|v| v.eq_ignore_ascii_case("true") || v == "1");
+        if credentials && origins == Some(Origins::Any) {
+            warn!("CORS_ALLOW_CREDENTIALS is ignored with CORS_ALLOWED_ORIGINS=*; list the origins instead");
+            credentials = false;
+        }
+        let max_age = env::var("CORS_MAX_AGE_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(DEFAULT_MAX_AGE_SECS);
+        let config = Self { origins, methods, headers, credentials, max_age };
+        match &config.origins {
+            Some(Origins::Any) => info!("CORS enabled for any origin"),
+            Some(Origins::List(list)) => info!("CORS enabled for {}", list.join(", ")),
+            None => {}
+        }
+        config
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.origins.is_some()
+    }
+
+    /// The middleware for one worker; answers preflight requests itself and adds the
+    /// Access-Control-* headers to responses for allowed origins
+    pub fn middleware(&self) -> Cors {
+        let mut cors = match &self.origins {
+            Some(Origins::Any) => Cors::default().allow_any_origin().send_wildcard(),
+            Some(Origins::List(list)) => list.iter().fold(Cors::default(), |cors, o| cors.allowed_origin(o)),
+            None => return Cors::default(),
+        };
+        cors = cors
+            .allowed_methods(self.methods.clone())
+            .allowed_headers(self.headers.clone())
+            .expose_headers(EXPOSED_HEADERS)
+            .max_age(self.max_age);
+        if self.credentials {
+            cors = cors.supports_credentials();
+        }
+        cors
+    }
+}
+
+fn list(s: &str) -> impl Iterator<Item = &str> {
+    s.split(',').map(str::trim).filter(|s| !s.is_empty())
+}
+
+fn valid_origin(s: &str) -> bool {
+    s.parse::<Uri>().is_ok_and(|u| u.scheme().is_some() && u.host().is_some() && u.path_and_query().is_none_or(|p| p.as_str() == "/"))
+}
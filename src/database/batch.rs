@@ -0,0 +1,183 @@
+use log::{debug, info, warn};
+use std::env;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::anomaly::ClassificationQueue;
+use crate::database::model::points::Model as PointModel;
+use crate::database::store::{NewPointRecord, PointStore, StoreError, StoreResult};
+
+/// Anomaly decision of one point, for `ack=classified`
+pub type Decision = oneshot::Receiver<Option<bool>>;
+
+/// Points of one request waiting for the next bulk insert
+struct Pending {
+    records: Vec<NewPointRecord>,
+    /// One sender per record when the request waits for the anomaly decisions
+    watchers: Option<Vec<oneshot::Sender<Option<bool>>>>,
+    done: oneshot::Sender<StoreResult<Vec<PointModel>>>,
+}
+
+#[derive(Debug)]
+pub enum BatchError {
+    /// More than INGEST_BATCH_MAX_PENDING rows are already waiting; the caller answers 503
+    Full,
+    Store(StoreError),
+}
+
+/// In-memory ingestion buffer for high-rate telemetry, enabled by INGEST_BATCH_ROWS. Requests
+/// hand their points to one flusher task, which stores everything collected in a single
+/// transaction once INGEST_BATCH_ROWS rows are waiting or INGEST_BATCH_FLUSH_MS (default 50)
+/// have passed since the first of them. With more than INGEST_BATCH_MAX_PENDING rows waiting
+/// (default ten batches) new requests are turned away instead of queueing without bound.
+pub struct IngestBatcher {
+    tx: mpsc::UnboundedSender<Pending>,
+    /// Rows submitted and not yet flushed
+    pending: Arc<AtomicUsize>,
+    max_pending: usize,
+}
+
+impl IngestBatcher {
+    /// Spawns the flusher when INGEST_BATCH_ROWS is set above 0
+    pub fn from_env(store: Arc<dyn PointStore>, queue: ClassificationQueue) -> Option<Self> {
+        let number = |name: &str| env::var(name).ok().and_then(|v| v.trim().parse::<u64>().ok()).filter(|v| *v > 0);
+        let max_rows = number("INGEST_BATCH_ROWS")? as usize;
+        let interval = Duration::from_millis(number("INGEST_BATCH_FLUSH_MS").unwrap_or(50));
+        let max_pending = number("INGEST_BATCH_MAX_PENDING").map(|v| v as usize).unwrap_or(max_rows * 10);
+        Some(Self::new(store, queue, max_rows, interval, max_pending))
+    }
+
+    /// Spawns the flusher with explicit limits
+    pub fn new(store: Arc<dyn PointStore>, queue: ClassificationQueue, max_rows: usize, interval: Duration, max_pending: usize) -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let pending = Arc::new(AtomicUsize::new(0));
+        tokio::spawn(run_flusher(rx, store, queue, max_rows, interval, pending.clone()));
+        info!(
+            "Batched ingestion enabled: up to {} rows every {:?}, at most {} rows waiting",
+            max_rows, interval, max_pending
+        );
+        Self { tx, pending, max_pending }
+    }
+
+    /// Waits for the bulk insert that stores `records` and returns the stored rows in the same
+    /// order, each with its anomaly decision when `watch` is set. A request larger than
+    /// INGEST_BATCH_MAX_PENDING is still taken when nothing else is waiting.
+    pub async fn submit(&self, records: Vec<NewPointRecord>, watch: bool) -> Result<Vec<(PointModel, Option<Decision>)>, BatchError> {
+        let n = records.len();
+        let waiting = self.pending.fetch_add(n, Ordering::AcqRel);
+        if !self.fits(waiting, n) {
+            self.pending.fetch_sub(n, Ordering::AcqRel);
+            return Err(BatchError::Full);
+        }
+
+        let (watchers, decisions): (Option<Vec<_>>, Vec<Option<Decision>>) = if watch {
+            let (senders, receivers): (Vec<_>, Vec<_>) = (0..n).map(|_| oneshot::channel()).unzip();
+            (Some(senders), receivers.into_iter().map(Some).collect())
+        } else {
+            (None, (0..n).map(|_| None).collect())
+        };
+        let (done, stored) = oneshot::channel();
+        if self.tx.send(Pending { records, watchers, done }).is_err() {
+            self.pending.fetch_sub(n, Ordering::AcqRel);
+            return Err(BatchError::Store(stopped()));
+        }
+        let stored = stored.await.map_err(|_| BatchError::Store(stopped()))?.map_err(BatchError::Store)?;
+        Ok(stored.into_iter().zip(decisions).collect())
+    }
+
+    /// Whether `n` more rows would be taken right now
+    pub fn has_room(&self, n: usize) -> bool {
+        self.fits(self.pending(), n)
+    }
+
+    /// Rows waiting for the next flush
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::Acquire)
+    }
+
+    fn fits(&self, waiting: usize, n: usize) -> bool {
+        waiting == 0 || waiting + n <= self.max_pending
+    }
+}
+
+fn stopped() -> StoreError {
+    StoreError::Backend("ingest batcher stopped".to_string())
+}
+
+/// Collects requests until the batch is full or its first request has waited one interval
+async fn run_flusher(
+    mut rx: mpsc::UnboundedReceiver<Pending>,
+    store: Arc<dyn PointStore>,
+    queue: ClassificationQueue,
+    max_rows: usize,
+    interval: Duration,
+    pending: Arc<AtomicUsize>,
+) {
+    while let Some(first) = rx.recv().await {
+        let deadline = tokio::time::Instant::now() + interval;
+        let mut rows = first.records.len();
+        let mut batch = vec![first];
+        while rows < max_rows {
+            match tokio::time::timeout_at(deadline, rx.recv()).await {
+                Ok(Some(next)) => {
+                    rows += next.records.len();
+                    batch.push(next);
+                }
+                Ok(None) | Err(_) => break,
+            }
+        }
+        flush(store.as_ref(), &queue, batch).await;
+        pending.fetch_sub(rows, Ordering::AcqRel);
+    }
+    info!("Ingest batcher stopped");
+}
+
+async fn flush(store: &dyn PointStore, queue: &ClassificationQueue, mut batch: Vec<Pending>) {
+    let started = Instant::now();
+    let records: Vec<NewPointRecord> = batch.iter().flat_map(|p| p.records.iter().cloned()).collect();
+    let count = records.len();
+    match store.insert_many(records).await {
+        Ok(mut stored) => {
+            debug!("Flushed {} points from {} requests in {:?}", count, batch.len(), started.elapsed());
+            // Ids follow the order the rows were sent in, whatever order the backend returned
+            // them in, so each request gets its own rows back
+            stored.sort_by_key(|p| p.id);
+            let mut stored = stored.into_iter();
+            for pending in batch {
+                let mine: Vec<PointModel> = stored.by_ref().take(pending.records.len()).collect();
+                finish(queue, pending, Ok(mine)).await;
+            }
+        }
+        // One bad row (a duplicate UUID, say) rolls back everyone's points; retried per
+        // request, it only fails its own
+        Err(e) if batch.len() > 1 => {
+            warn!("Bulk insert of {} points from {} requests failed, retrying per request: {}", count, batch.len(), e);
+            for pending in batch {
+                let res = store.insert_many(pending.records.clone()).await.map(|mut stored| {
+                    stored.sort_by_key(|p| p.id);
+                    stored
+                });
+                finish(queue, pending, res).await;
+            }
+        }
+        Err(e) => {
+            if let Some(pending) = batch.pop() {
+                finish(queue, pending, Err(e)).await;
+            }
+        }
+    }
+}
+
+/// Queues the stored points for classification in insertion order and answers the request
+async fn finish(queue: &ClassificationQueue, pending: Pending, res: StoreResult<Vec<PointModel>>) {
+    if let Ok(stored) = &res {
+        let mut watchers = pending.watchers.map(Vec::into_iter);
+        for model in stored {
+            queue.queue_stored(model, watchers.as_mut().and_then(Iterator::next)).await;
+        }
+    }
+    // The client may have gone away
+    let _ = pending.done.send(res);
+}
@@ -0,0 +1,339 @@
+use chrono::{DateTime, Utc};
+use sea_orm::{ConnectionTrait, DatabaseBackend, DatabaseConnection, DbErr, FromQueryResult, Statement};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use utoipa::ToSchema;
+
+use crate::telemetry::SlowQuery;
+
+/// Aggregating endpoints reading at least this many rows per request on average get a rollup
+/// suggestion; an index can narrow what they read, not how much of it they aggregate
+const ROLLUP_MIN_ROWS: f64 = 100_000.0;
+
+/// Endpoints that aggregate points into tiles, buckets or routes
+const AGGREGATE_PREFIXES: &[&str] = &[
+    "/api/heatmap", "/api/trafficmap", "/api/speedmap", "/api/anomalymap", "/api/grid",
+    "/api/tiles", "/api/timeseries", "/api/stats", "/api/forecast",
+];
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct IndexAdvisorReport {
+    #[serde(rename = "generatedAt")]
+    pub generated_at: DateTime<Utc>,
+    /// SLOW_QUERY_MS; None when the slow query log is disabled
+    #[serde(rename = "slowQueryMs")]
+    pub slow_query_ms: Option<u64>,
+    /// Slow requests in the log (the last 1000 at most)
+    #[serde(rename = "analyzedQueries")]
+    pub analyzed_queries: usize,
+    /// When the oldest of them was made
+    pub since: Option<DateTime<Utc>>,
+    /// One entry per endpoint and parameter combination, most total time first
+    pub patterns: Vec<QueryPattern>,
+    /// Distinct suggestions over all patterns; `migration` is a complete file for `src/migration`
+    pub suggestions: Vec<Suggestion>,
+    #[serde(rename = "existingIndexes")]
+    pub existing_indexes: Vec<ExistingIndex>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct QueryPattern {
+    /// Route pattern of the endpoint
+    pub endpoint: String,
+    /// Query parameters the requests used
+    pub params: Vec<String>,
+    pub table: String,
+    /// Columns the store filters on for these parameters
+    pub columns: Vec<String>,
+    pub count: usize,
+    #[serde(rename = "avgMs")]
+    pub avg_ms: f64,
+    #[serde(rename = "maxMs")]
+    pub max_ms: f64,
+    #[serde(rename = "avgRowsScanned")]
+    pub avg_rows_scanned: f64,
+    /// Existing index that already serves the filter
+    #[serde(rename = "coveredBy")]
+    pub covered_by: Option<String>,
+    /// Names of the suggestions that apply, see `suggestions`
+    pub suggestions: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Suggestion {
+    /// index | rollup
+    pub kind: String,
+    pub name: String,
+    pub table: String,
+    pub rationale: String,
+    /// For a live database: builds without blocking writes
+    pub sql: String,
+    /// Suggested file name under `src/migration`; register it in `src/migration/mod.rs`
+    #[serde(rename = "migrationFile")]
+    pub migration_file: String,
+    pub migration: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ExistingIndex {
+    pub table: String,
+    pub name: String,
+    pub columns: Vec<String>,
+    pub definition: String,
+}
+
+/// Columns one slow request filtered on
+struct Access {
+    table: &'static str,
+    /// Equality filters, in index order
+    eq: Vec<&'static str>,
+    /// Range filters, most selective first
+    range: Vec<&'static str>,
+    aggregate: bool,
+}
+
+/// Builds the report from the slow query log and the indexes of `points` and `trips`
+pub async fn analyze(db: &DatabaseConnection, slow: &[SlowQuery], slow_query_ms: Option<u64>) -> Result<IndexAdvisorReport, DbErr> {
+    let existing = existing_indexes(db).await?;
+    // Bbox filters go through the PostGIS geometry index when the column exists
+    let postgis = existing.iter().any(|i| i.table == "points" && i.columns.iter().any(|c| c == "geom"));
+
+    let mut groups: BTreeMap<(String, Vec<String>), Vec<&SlowQuery>> = BTreeMap::new();
+    for q in slow {
+        groups.entry((q.pattern.clone(), q.params.iter().cloned().collect())).or_default().push(q);
+    }
+
+    let mut patterns = Vec::new();
+    let mut suggestions: BTreeMap<String, Suggestion> = BTreeMap::new();
+    let mut rollup_endpoints = BTreeSet::new();
+    for ((endpoint, params), queries) in groups {
+        let Some(access) = access_for(&endpoint, &params) else { continue };
+        let count = queries.len();
+        let avg_ms = queries.iter().map(|q| q.took_ms).sum::<f64>() / count as f64;
+        let max_ms = queries.iter().map(|q| q.took_ms).fold(0.0, f64::max);
+        let avg_rows_scanned = queries.iter().map(|q| q.rows_scanned as f64).sum::<f64>() / count as f64;
+
+        let columns = index_columns(&access, postgis);
+        let covered_by = columns.as_ref().and_then(|cols| {
+            existing
+                .iter()
+                .find(|i| i.table == access.table && i.columns.starts_with(cols))
+                .map(|i| i.name.clone())
+        });
+        let mut applies = Vec::new();
+        if let Some(cols) = columns.as_ref().filter(|_| covered_by.is_none()) {
+            let s = index_suggestion(access.table, cols);
+            applies.push(s.name.clone());
+            suggestions.entry(s.name.clone()).or_insert(s);
+        }
+        if access.aggregate && avg_rows_scanned >= ROLLUP_MIN_ROWS {
+            applies.push(ROLLUP_NAME.to_string());
+            rollup_endpoints.insert(endpoint.clone());
+        }
+
+        let filtered = access.eq.iter().chain(&access.range).map(|c| c.to_string()).collect();
+        patterns.push(QueryPattern {
+            endpoint,
+            params,
+            table: access.table.to_string(),
+            columns: filtered,
+            count,
+            avg_ms,
+            max_ms,
+            avg_rows_scanned,
+            covered_by,
+            suggestions: applies,
+        });
+    }
+    patterns.sort_by(|a, b| (b.avg_ms * b.count as f64).total_cmp(&(a.avg_ms * a.count as f64)));
+
+    let mut suggestions: Vec<Suggestion> = suggestions.into_values().collect();
+    if !rollup_endpoints.is_empty() {
+        suggestions.push(rollup_suggestion(&rollup_endpoints));
+    }
+
+    Ok(IndexAdvisorReport {
+        generated_at: Utc::now(),
+        slow_query_ms,
+        analyzed_queries: slow.len(),
+        since: slow.iter().map(|q| q.at).min(),
+        patterns,
+        suggestions,
+        existing_indexes: existing,
+    })
+}
+
+/// What the handlers behind `endpoint` filter on, from the parameters they were called with
+fn access_for(endpoint: &str, params: &[String]) -> Option<Access> {
+    let has = |name: &str| params.iter().any(|p| p == name);
+    if endpoint == "/api/trips" {
+        let mut access = Access { table: "trips", eq: Vec::new(), range: Vec::new(), aggregate: false };
+        if has("randomizedId") { access.eq.push("randomized_id"); }
+        if has("anomaly") { access.eq.push("anomaly"); }
+        // A trip overlaps the range when it ends after dateStart and starts before dateEnd
+        if has("dateStart") { access.range.push("end_ts"); }
+        if has("dateEnd") { access.range.push("start_ts"); }
+        if has("minDistance") { access.range.push("distance_m"); }
+        return Some(access);
+    }
+
+    let aggregate = AGGREGATE_PREFIXES.iter().any(|p| endpoint.starts_with(p));
+    if !aggregate && endpoint != "/api/points" && endpoint != "/api/anomalies" {
+        return None;
+    }
+    let mut access = Access { table: "points", eq: Vec::new(), range: Vec::new(), aggregate };
+    if has("randomizedId") { access.eq.push("randomized_id"); }
+    // The anomaly list always filters on the flag
+    if has("anomaly") || endpoint == "/api/anomalies" { access.eq.push("anomaly"); }
+    if has("dateStart") || has("dateEnd") { access.range.push("timestamp"); }
+    // Vector tiles always carry their own bbox
+    if has("lat1") || endpoint.starts_with("/api/tiles") { access.range.push("bbox"); }
+    Some(access)
+}
+
+/// B-tree columns for the access: equality columns first, then the first range column. None
+/// when the existing bbox index is the best available, or nothing is filtered.
+fn index_columns(access: &Access, postgis: bool) -> Option<Vec<String>> {
+    let mut cols: Vec<String> = access.eq.iter().map(|c| c.to_string()).collect();
+    match access.range.first() {
+        Some(&"bbox") if postgis && cols.is_empty() => return None,
+        Some(&"bbox") if postgis => {}
+        Some(&"bbox") => cols.extend(["lat".to_string(), "lng".to_string()]),
+        Some(c) => cols.push(c.to_string()),
+        None => {}
+    }
+    (!cols.is_empty()).then_some(cols)
+}
+
+fn index_suggestion(table: &str, cols: &[String]) -> Suggestion {
+    let name = format!("idx_{}_{}", table, cols.join("_"));
+    let quoted: Vec<String> = cols.iter().map(|c| format!("\"{}\"", c)).collect();
+    let mut migration = String::from(
+        "use sea_orm_migration::prelude::*;\n\n#[derive(DeriveMigrationName)]\npub struct Migration;\n\n\
+         #[async_trait::async_trait]\nimpl MigrationTrait for Migration {\n    \
+         async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {\n        manager\n            .create_index(\n                Index::create()\n",
+    );
+    let _ = writeln!(migration, "                    .name({:?})", name);
+    let _ = writeln!(migration, "                    .table(Alias::new({:?}))", table);
+    for c in cols {
+        let _ = writeln!(migration, "                    .col(Alias::new({:?}))", c);
+    }
+    migration.push_str("                    .if_not_exists()\n                    .to_owned(),\n            )\n            .await\n    }\n\n");
+    migration.push_str("    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {\n        manager\n");
+    let _ = writeln!(
+        migration,
+        "            .drop_index(Index::drop().name({:?}).table(Alias::new({:?})).to_owned())",
+        name, table
+    );
+    migration.push_str("            .await\n    }\n}\n");
+
+    Suggestion {
+        kind: "index".to_string(),
+        rationale: if cols.len() > 1 {
+            format!("Composite index on {} ({}): equality filters first, then the range filter", table, cols.join(", "))
+        } else {
+            format!("No index starts with {}.{}, which these requests filter on", table, cols[0])
+        },
+        sql: format!("CREATE INDEX CONCURRENTLY IF NOT EXISTS {} ON {} ({});", name, table, quoted.join(", ")),
+        migration_file: format!("{}_000001_add_{}.rs", Utc::now().format("m%Y%m%d"), name.trim_start_matches("idx_")),
+        name,
+        table: table.to_string(),
+        migration,
+    }
+}
+
+const ROLLUP_NAME: &str = "points_hourly_cells";
+
+/// Hourly per-cell totals (0.01° cells, about 1 km) the map endpoints could read instead of raw
+/// points; refreshed with `REFRESH MATERIALIZED VIEW CONCURRENTLY points_hourly_cells`
+const ROLLUP_SQL: &str = r#"CREATE MATERIALIZED VIEW IF NOT EXISTS points_hourly_cells AS
+SELECT date_trunc('hour', "timestamp", 'UTC') AS hour,
+       floor(lat / 0.01)::integer AS cell_lat,
+       floor(lng / 0.01)::integer AS cell_lng,
+       COUNT(*) AS points,
+       COUNT(DISTINCT randomized_id) AS trips,
+       AVG(spd) AS avg_spd,
+       COUNT(*) FILTER (WHERE anomaly) AS anomalies
+FROM points
+WHERE "timestamp" IS NOT NULL
+GROUP BY 1, 2, 3;
+CREATE UNIQUE INDEX IF NOT EXISTS idx_points_hourly_cells ON points_hourly_cells (hour, cell_lat, cell_lng);"#;
+
+fn rollup_suggestion(endpoints: &BTreeSet<String>) -> Suggestion {
+    let endpoints: Vec<&str> = endpoints.iter().map(String::as_str).collect();
+    let migration = format!(
+        "use sea_orm_migration::prelude::*;\n\n#[derive(DeriveMigrationName)]\npub struct Migration;\n\n\
+         #[async_trait::async_trait]\nimpl MigrationTrait for Migration {{\n    \
+         async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {{\n        \
+         manager.get_connection().execute_unprepared(UP_SQL).await?;\n        Ok(())\n    }}\n\n    \
+         async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {{\n        \
+         manager.get_connection().execute_unprepared(\"DROP MATERIALIZED VIEW IF EXISTS {}\").await?;\n        Ok(())\n    }}\n}}\n\n\
+         const UP_SQL: &str = r#\"\n{}\n\"#;\n",
+        ROLLUP_NAME, ROLLUP_SQL
+    );
+    Suggestion {
+        kind: "rollup".to_string(),
+        name: ROLLUP_NAME.to_string(),
+        table: "points".to_string(),
+        rationale: format!(
+            "{} aggregate more than {} rows per request on average; pre-aggregating per hour and cell bounds that by area and time range instead of point count",
+            endpoints.join(", "), ROLLUP_MIN_ROWS
+        ),
+        sql: ROLLUP_SQL.to_string(),
+        migration_file: format!("{}_000001_create_{}.rs", Utc::now().format("m%Y%m%d"), ROLLUP_NAME),
+        migration,
+    }
+}
+
+async fn existing_indexes(db: &DatabaseConnection) -> Result<Vec<ExistingIndex>, DbErr> {
+    #[derive(FromQueryResult)]
+    struct Row { tablename: String, indexname: String, indexdef: String }
+    if db.get_database_backend() != DatabaseBackend::Postgres {
+        return Ok(Vec::new());
+    }
+    let rows = Row::find_by_statement(Statement::from_string(
+        DatabaseBackend::Postgres,
+        "SELECT tablename, indexname, indexdef FROM pg_indexes \
+         WHERE schemaname = current_schema() AND tablename IN ('points', 'trips') ORDER BY tablename, indexname",
+    ))
+    .all(db)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|r| ExistingIndex { columns: index_def_columns(&r.indexdef), table: r.tablename, name: r.indexname, definition: r.indexdef })
+        .collect())
+}
+
+/// `CREATE INDEX i ON public.points USING gist (((geom)::geometry))` -> `["geom"]`
+fn index_def_columns(def: &str) -> Vec<String> {
+    let Some(start) = def.find(" USING ").and_then(|u| def[u..].find('(').map(|p| u + p + 1)) else {
+        return Vec::new();
+    };
+    let mut depth = 0;
+    let mut columns = Vec::new();
+    let mut current = String::new();
+    for c in def[start..].chars() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => break,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                columns.push(std::mem::take(&mut current));
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    columns.push(current);
+    columns
+        .iter()
+        .map(|c| {
+            let c = c.trim().split(' ').next().unwrap_or_default();
+            let c = c.split("::").next().unwrap_or_default();
+            c.trim_matches(|ch| ch == '(' || ch == ')' || ch == '"').to_string()
+        })
+        .filter(|c| !c.is_empty())
+        .collect()
+}
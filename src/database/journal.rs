@@ -0,0 +1,147 @@
+use chrono::Utc;
+use log::{error, info, warn};
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+use crate::anomaly::ClassificationQueue;
+use crate::database::store::{NewPointRecord, PointStore};
+use crate::database::wal;
+
+/// Progress line appended after each stored record of a batch
+const DONE_MARK: &str = "done";
+
+/// Accepted ingestion batches, one file per batch in INGEST_JOURNAL_DIR (unset disables).
+/// A batch is synced to disk before its first insert and removed once the request is answered,
+/// so whatever is left at startup was cut short by a crash and gets stored then.
+pub struct Journal {
+    dir: PathBuf,
+    seq: AtomicU64,
+}
+
+/// Journal file of one in-flight batch. Dropping it without `finish` keeps the file for recovery.
+pub struct JournalEntry {
+    path: PathBuf,
+    file: fs::File,
+}
+
+impl Journal {
+    pub fn from_env() -> io::Result<Option<Self>> {
+        let Some(dir) = env::var("INGEST_JOURNAL_DIR").ok().filter(|d| !d.is_empty()) else {
+            return Ok(None);
+        };
+        Self::open(PathBuf::from(dir)).map(Some)
+    }
+
+    /// A journal in `dir`, creating it
+    pub fn open(dir: PathBuf) -> io::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self { dir, seq: AtomicU64::new(0) })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Persists a batch before any of it is inserted. Missing timestamps are stamped now,
+    /// so a recovered point keeps the time it was accepted at.
+    pub async fn begin(&self, records: &[NewPointRecord]) -> io::Result<JournalEntry> {
+        let now = Utc::now();
+        let mut out = String::new();
+        for record in records {
+            let mut record = record.clone();
+            record.timestamp.get_or_insert(now);
+            out.push_str(&serde_json::to_string(&record)?);
+            out.push('\n');
+        }
+        let name = format!(
+            "{}-{:06}.ndjson",
+            now.timestamp_nanos_opt().unwrap_or_default(),
+            self.seq.fetch_add(1, Ordering::Relaxed)
+        );
+        let path = self.dir.join(name);
+        let mut file = fs::OpenOptions::new().create_new(true).append(true).open(&path).await?;
+        file.write_all(out.as_bytes()).await?;
+        file.sync_data().await?;
+        Ok(JournalEntry { path, file })
+    }
+
+    /// Stores what interrupted batches did not get to, oldest batch first. A batch that still
+    /// fails stays on disk for the next start. Returns the number of records stored.
+    pub async fn recover(&self, store: &dyn PointStore, queue: &ClassificationQueue) -> io::Result<usize> {
+        let mut paths = Vec::new();
+        let mut dir = fs::read_dir(&self.dir).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_some_and(|e| e == "ndjson") {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+
+        let (mut stored, mut failed) = (0, 0);
+        for path in paths {
+            match recover_batch(&path, store, queue).await {
+                Ok(n) => {
+                    stored += n;
+                    fs::remove_file(&path).await?;
+                }
+                Err(e) => {
+                    error!("Journal batch {} not recovered: {}", path.display(), e);
+                    failed += 1;
+                }
+            }
+        }
+        if stored > 0 {
+            info!("Recovered {} points from interrupted batches in {}", stored, self.dir.display());
+        }
+        crate::metrics::metrics().record_job(
+            "journal recovery",
+            failed == 0,
+            format!("recovered {} points at startup, {} batches left on disk", stored, failed),
+        );
+        Ok(stored)
+    }
+}
+
+impl JournalEntry {
+    /// Notes that the next record of the batch is stored or was already there. Not synced:
+    /// a power loss may replay a few stored points, a process crash will not.
+    pub async fn mark_done(&mut self) {
+        if let Err(e) = self.file.write_all(format!("{}\n", DONE_MARK).as_bytes()).await {
+            warn!("Journal progress write failed on {}: {}", self.path.display(), e);
+        }
+    }
+
+    /// Trims the batch after the request was answered
+    pub async fn finish(self) {
+        drop(self.file);
+        if let Err(e) = fs::remove_file(&self.path).await {
+            error!("Could not trim journal batch {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+async fn recover_batch(path: &Path, store: &dyn PointStore, queue: &ClassificationQueue) -> Result<usize, String> {
+    let text = fs::read_to_string(path).await.map_err(|e| e.to_string())?;
+    let done = text.lines().filter(|l| *l == DONE_MARK).count();
+    let mut stored = 0;
+    for line in text.lines().filter(|l| !l.trim().is_empty() && *l != DONE_MARK).skip(done) {
+        let record: NewPointRecord = match serde_json::from_str(line) {
+            Ok(r) => r,
+            Err(e) => {
+                warn!("Dropping unreadable journal line in {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        if wal::is_stored(store, &record).await.map_err(|e| e.to_string())? {
+            continue;
+        }
+        queue.ingest(store, record).await.map_err(|e| e.to_string())?;
+        stored += 1;
+    }
+    Ok(stored)
+}
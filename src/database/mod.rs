@@ -1,3 +1,11 @@
 pub mod model;
+pub mod store;
+pub mod pool;
+pub mod retention;
+pub mod tile_rollup;
+pub mod wal;
+pub mod journal;
+pub mod batch;
+pub mod index_advisor;
 
-// Database module placeholder: models live under `model`. Connection is initialized in `main.rs` and passed via Actix app data.
\ No newline at end of file
+// Models live under `model`; handlers go through the `store::PointStore` trait. Connection is initialized in `main.rs` and passed via Actix app data.
\ No newline at end of file
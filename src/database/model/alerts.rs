@@ -0,0 +1,34 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+
+/// An alert raised by a rule; kept when the rule is deleted
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "alerts")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+    pub rule_id: i64,
+    /// Kind of the rule: `speed`, `geofence` or `inactivity`
+    pub kind: String,
+    pub tenant_id: Option<String>,
+    /// The trip, for alerts raised by a point
+    pub randomized_id: Option<i64>,
+    /// The device, for inactivity alerts
+    pub device_id: Option<String>,
+    pub point_id: Option<i64>,
+    pub lat: Option<f64>,
+    pub lng: Option<f64>,
+    /// km/h for speed alerts, minutes of silence for inactivity alerts
+    pub value: Option<f64>,
+    pub message: String,
+    /// When the condition was met: the time of the point, or when the device had been silent
+    /// for the rule's minutes
+    pub timestamp: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
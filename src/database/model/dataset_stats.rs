@@ -0,0 +1,29 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+
+/// Whole-dataset totals kept in step with `points` on every insert, split over a few rows so
+/// that concurrent inserts do not all wait for one lock: each insert adds to the shard of its
+/// trip, and the totals add the shards up.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "dataset_stats")]
+pub struct Model {
+    /// Shard, from 1
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: i16,
+    pub total_points: i64,
+    pub total_trips: i64,
+    /// Bounds of every stored point, None while the table is empty
+    pub lat_min: Option<f64>,
+    pub lat_max: Option<f64>,
+    pub lng_min: Option<f64>,
+    pub lng_max: Option<f64>,
+    pub first_ts: Option<DateTime<Utc>>,
+    pub last_ts: Option<DateTime<Utc>>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,24 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+
+/// A tracker known to the fleet: registered through `POST /api/devices`, or added on the
+/// first trip its points name it in. Each tenant has device ids of its own.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "devices")]
+pub struct Model {
+    /// `tenant_id`, or '' for the shared scope, as a primary key cannot hold NULLs
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub tenant_key: String,
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub device_id: String,
+    pub name: Option<String>,
+    pub registered_at: DateTime<Utc>,
+    /// Tenant that registered the device or first reported a trip for it
+    pub tenant_id: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
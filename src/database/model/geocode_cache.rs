@@ -0,0 +1,25 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+
+/// What the reverse geocoder answered for a cell of 0.0001° (about 11 m), row
+/// `round(lat * 10000)` and column `round(lng * 10000)`. Maintained by `geocoding::Geocoder`.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "geocode_cache")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub cell_lat: i32,
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub cell_lng: i32,
+    pub street: Option<String>,
+    pub district: Option<String>,
+    pub city: Option<String>,
+    /// The names joined for display; None when the geocoder knows no place there
+    pub label: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
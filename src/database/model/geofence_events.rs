@@ -0,0 +1,27 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+
+/// A trip entering or leaving a geofence, at the first point found on the other side
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "geofence_events")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+    pub geofence_id: i64,
+    pub randomized_id: i64,
+    /// The point that crossed
+    pub point_id: i64,
+    /// `entry` or `exit`
+    pub kind: String,
+    pub lat: f64,
+    pub lng: f64,
+    /// Of the point; the time it was stored when it had none
+    pub timestamp: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,25 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+
+/// A named polygon whose entries and exits are recorded in `geofence_events` (`geofences::Geofences`)
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "geofences")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+    pub name: String,
+    /// Vertices as `[{"lat": .., "lng": ..}, ..]`, the ring closing back on the first one
+    pub polygon: Json,
+    /// Inactive geofences are kept but points are not checked against them
+    pub active: bool,
+    /// Tenant that created it; only its own points and requests see it
+    pub tenant_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,33 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+
+/// A long-running operation started by a request and run in the background (`jobs::Jobs`)
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "jobs")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    /// `export`, `import`, `report` or `rollup`
+    pub kind: String,
+    /// Tenant the job was started for; only its own requests see it
+    pub tenant_id: Option<String>,
+    /// `queued`, `running`, `done` or `failed`
+    pub status: String,
+    /// What the job was asked to do
+    pub params: Json,
+    /// Units of work done so far, such as rows written; what a unit is depends on the kind
+    pub progress: i64,
+    /// What the job produced, once done
+    pub result: Option<Json>,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
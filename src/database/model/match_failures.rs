@@ -0,0 +1,20 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+
+/// A trip the map-matching worker failed to match, left out until `retry_at`
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "match_failures")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub randomized_id: i64,
+    /// Failures in a row
+    pub attempts: i32,
+    pub last_error: String,
+    pub retry_at: DateTime<Utc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,29 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+
+/// A trip snapped to the road network by the map-matching worker
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "matched_trips")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub randomized_id: i64,
+    pub matched_at: DateTime<Utc>,
+    /// `trips.point_count` when matched; the trip is matched again once it grows
+    pub source_point_count: i64,
+    /// OSRM confidence in [0, 1], weighted by matched distance; 0 when nothing matched
+    pub confidence: f64,
+    /// Length of the matched route, meters
+    pub distance_m: f64,
+    /// `[lng, lat]` pairs of the matched route, empty when nothing matched
+    #[sea_orm(column_type = "JsonBinary")]
+    pub geometry: Json,
+    /// OSM node ids along the route, consecutive pairs identify road segments
+    #[sea_orm(column_type = "JsonBinary")]
+    pub road_node_ids: Json,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -1 +1,18 @@
-pub mod points;
\ No newline at end of file
+pub mod points;
+pub mod trips;
+pub mod matched_trips;
+pub mod match_failures;
+pub mod dataset_stats;
+pub mod ingest_daily;
+pub mod quarantined_points;
+pub mod devices;
+pub mod tile_stats;
+pub mod tile_rollups;
+pub mod webhooks;
+pub mod geocode_cache;
+pub mod jobs;
+pub mod road_segments;
+pub mod geofences;
+pub mod geofence_events;
+pub mod alert_rules;
+pub mod alerts;
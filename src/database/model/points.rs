@@ -16,6 +16,21 @@ pub struct Model {
     #[sea_orm(default_expr = "Expr::current_timestamp()")]
     pub timestamp: Option<DateTime<Utc>>,
     pub anomaly: Option<bool>,
+    /// How anomalous the classifier found the point, 0..1; 0.5 is where a rule starts to flag
+    pub anomaly_score: Option<f64>,
+    /// Rule that scored highest (`speed_spike`, `teleport`, `azimuth_reversal`), `webhook` or `review`
+    pub anomaly_reason: Option<String>,
+    /// Client-generated id, unique when present; lets retried uploads be recognized
+    #[sea_orm(unique)]
+    pub client_uuid: Option<Uuid>,
+    /// Deployment the point belongs to (see `tenant`); None for points shared by the instance
+    pub tenant_id: Option<String>,
+    /// Meters from the previous point of the trip in time order; None for the first point
+    pub prev_distance_m: Option<f64>,
+    /// Seconds since that point
+    pub prev_interval_s: Option<f64>,
+    /// `prev_distance_m / prev_interval_s` in m/s; None when both fixes share a timestamp
+    pub derived_speed: Option<f64>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
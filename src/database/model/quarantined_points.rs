@@ -0,0 +1,29 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+/// A point held back because its timestamp was outside the acceptance window
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "quarantined_points")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+    pub randomized_id: i64,
+    pub lat: f64,
+    pub lng: f64,
+    pub alt: f64,
+    pub spd: f64,
+    pub azm: f64,
+    /// As sent by the device
+    pub timestamp: DateTime<Utc>,
+    pub client_uuid: Option<Uuid>,
+    /// Why the point was held back, e.g. "timestamp more than 30 days in the past"
+    pub reason: String,
+    pub received_at: DateTime<Utc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
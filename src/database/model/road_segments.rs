@@ -0,0 +1,26 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// The stretch of an OSM way between two consecutive nodes, with the way's speed limit.
+/// Replaced as a whole by `indrive import-roads`.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "road_segments")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+    pub way_id: i64,
+    pub name: Option<String>,
+    /// The way's `highway` tag, e.g. `primary` or `residential`
+    pub highway: String,
+    /// km/h, from the way's `maxspeed` tag
+    pub speed_limit: f64,
+    pub lat1: f64,
+    pub lng1: f64,
+    pub lat2: f64,
+    pub lng2: f64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
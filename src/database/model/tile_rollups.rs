@@ -0,0 +1,21 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+
+/// Hours `[covered_from, covered_through)` that `tile_stats` holds for one rollup grid
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "tile_rollups")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub resolution: i32,
+    pub covered_from: DateTime<Utc>,
+    pub covered_through: DateTime<Utc>,
+    /// Whether `covered_from` reaches back to the oldest point
+    pub backfilled: bool,
+    pub refreshed_at: DateTime<Utc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
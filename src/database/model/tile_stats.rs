@@ -0,0 +1,26 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+
+/// Points and their summed speeds per tile of a rollup grid and UTC hour. Tiles are
+/// `resolution` micro-degrees square, counted from 0° N 0° E: row `floor(lat / side)`,
+/// column `floor(lng / side)`. Maintained by `database::tile_rollup`.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "tile_stats")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub resolution: i32,
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub hour: DateTime<Utc>,
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub tile_row: i32,
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub tile_col: i32,
+    pub points: i64,
+    pub speed_sum: f64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,42 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+
+/// Per-trip summary kept in step with `points` on every insert
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "trips")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub randomized_id: i64,
+    pub start_ts: DateTime<Utc>,
+    pub end_ts: DateTime<Utc>,
+    pub start_lat: f64,
+    pub start_lng: f64,
+    pub end_lat: f64,
+    pub end_lng: f64,
+    /// Sum of great-circle distances between consecutive points, meters
+    pub distance_m: f64,
+    pub duration_s: f64,
+    pub avg_speed: f64,
+    pub max_speed: f64,
+    pub point_count: i64,
+    /// true when any point of the trip is classified anomalous, unless `anomaly_review` says
+    pub anomaly: bool,
+    /// Points of the trip classified anomalous
+    pub anomaly_count: i64,
+    /// Operator's decision on the trip, which `anomaly` and `anomaly_count` follow over the
+    /// classifier's flags on the points: true confirmed, false dismissed, None not reviewed
+    pub anomaly_review: Option<bool>,
+    /// Operator who last confirmed or dismissed the anomaly flag by hand
+    pub anomaly_reviewed_by: Option<String>,
+    pub anomaly_reviewed_at: Option<DateTime<Utc>>,
+    /// Tracker that reported the trip, when its points named one
+    pub device_id: Option<String>,
+    /// Tenant of the trip's first point
+    pub tenant_id: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
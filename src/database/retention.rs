@@ -0,0 +1,52 @@
+use chrono::{DateTime, Duration, Utc};
+use log::{error, info};
+use std::env;
+use std::sync::Arc;
+
+use super::store::{PointFilter, PointStore, TileStatsStore};
+use super::tile_rollup;
+use crate::metrics::metrics;
+
+/// Starts the periodic purge of old points when POINTS_RETENTION_DAYS is set (> 0).
+/// The first run happens right after startup, then every POINTS_RETENTION_INTERVAL_HOURS (default 24).
+/// The tile rollups, when there are any, drop the purged hours.
+pub fn spawn(store: Arc<dyn PointStore>, rollups: Option<Arc<dyn TileStatsStore>>) {
+    let Some(days) = env::var("POINTS_RETENTION_DAYS")
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .filter(|d| *d > 0)
+    else {
+        info!("Point retention disabled (POINTS_RETENTION_DAYS not set)");
+        return;
+    };
+    let interval_hours = env::var("POINTS_RETENTION_INTERVAL_HOURS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .filter(|h| *h > 0)
+        .unwrap_or(24);
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(std::time::Duration::from_secs(interval_hours * 3600));
+        loop {
+            ticker.tick().await;
+            let cutoff = Utc::now() - Duration::days(days);
+            let filter = PointFilter { until: Some(cutoff), ..Default::default() };
+            match store.delete(&filter).await {
+                Ok(n) => {
+                    info!("Retention purge removed {} points older than {}", n, cutoff);
+                    metrics().record_job("retention purge", true, format!("removed {} points older than {}", n, cutoff));
+                    if let Some(rollups) = &rollups
+                        && let Err(e) = tile_rollup::invalidate(store.as_ref(), rollups.as_ref(), DateTime::<Utc>::MIN_UTC, cutoff).await
+                    {
+                        error!("Tile rollups not updated after the retention purge: {}", e);
+                    }
+                }
+                Err(e) => {
+                    error!("Retention purge failed: {}", e);
+                    metrics().record_job("retention purge", false, e.to_string());
+                }
+            }
+        }
+    });
+    info!("Point retention: keeping {} days, purging every {}h", days, interval_hours);
+}
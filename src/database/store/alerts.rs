@@ -0,0 +1,109 @@
+use chrono::Utc;
+use sea_orm::prelude::async_trait;
+use sea_orm::{ActiveModelTrait, ColumnTrait, Condition, EntityTrait, QueryFilter, QueryOrder, QuerySelect, Set};
+
+use super::postgres::outside_lng;
+use super::{AlertFilter, AlertRuleSettings, AlertStore, SeaOrmPointStore, StoreResult, TenantScope};
+use crate::database::model::alert_rules::{self, ActiveModel as AlertRuleActiveModel, Entity as AlertRules, Model as AlertRuleModel};
+use crate::database::model::alerts::{self, ActiveModel as AlertActiveModel, Entity as Alerts, Model as AlertModel};
+
+#[async_trait::async_trait]
+impl AlertStore for SeaOrmPointStore {
+    async fn find_alert_rules(&self) -> StoreResult<Vec<AlertRuleModel>> {
+        Ok(AlertRules::find().order_by_asc(alert_rules::Column::Id).all(&self.db).await?)
+    }
+
+    async fn create_alert_rule(&self, settings: &AlertRuleSettings, tenant_id: Option<String>) -> StoreResult<AlertRuleModel> {
+        let now = Utc::now();
+        let active = AlertRuleActiveModel {
+            name: Set(settings.name.clone()),
+            kind: Set(settings.kind.clone()),
+            params: Set(settings.params.clone()),
+            enabled: Set(settings.enabled),
+            tenant_id: Set(tenant_id),
+            created_at: Set(now),
+            updated_at: Set(now),
+            ..Default::default()
+        };
+        Ok(active.insert(&self.db).await?)
+    }
+
+    async fn update_alert_rule(&self, id: i64, settings: &AlertRuleSettings) -> StoreResult<Option<AlertRuleModel>> {
+        let Some(existing) = AlertRules::find_by_id(id).one(&self.db).await? else {
+            return Ok(None);
+        };
+        let mut active: AlertRuleActiveModel = existing.into();
+        active.name = Set(settings.name.clone());
+        active.kind = Set(settings.kind.clone());
+        active.params = Set(settings.params.clone());
+        active.enabled = Set(settings.enabled);
+        active.updated_at = Set(Utc::now());
+        Ok(Some(active.update(&self.db).await?))
+    }
+
+    async fn delete_alert_rule(&self, id: i64) -> StoreResult<bool> {
+        Ok(AlertRules::delete_by_id(id).exec(&self.db).await?.rows_affected > 0)
+    }
+
+    async fn record_alert(&self, alert: AlertModel) -> StoreResult<AlertModel> {
+        let active = AlertActiveModel {
+            rule_id: Set(alert.rule_id),
+            kind: Set(alert.kind),
+            tenant_id: Set(alert.tenant_id),
+            randomized_id: Set(alert.randomized_id),
+            device_id: Set(alert.device_id),
+            point_id: Set(alert.point_id),
+            lat: Set(alert.lat),
+            lng: Set(alert.lng),
+            value: Set(alert.value),
+            message: Set(alert.message),
+            timestamp: Set(alert.timestamp),
+            created_at: Set(alert.created_at),
+            ..Default::default()
+        };
+        Ok(active.insert(&self.db).await?)
+    }
+
+    async fn find_alerts(&self, filter: &AlertFilter, limit: u64) -> StoreResult<Vec<AlertModel>> {
+        let mut query = Alerts::find();
+        if let Some(rule_id) = filter.rule_id {
+            query = query.filter(alerts::Column::RuleId.eq(rule_id));
+        }
+        if let Some(kind) = &filter.kind {
+            query = query.filter(alerts::Column::Kind.eq(kind.as_str()));
+        }
+        if let Some(randomized_id) = filter.randomized_id {
+            query = query.filter(alerts::Column::RandomizedId.eq(randomized_id));
+        }
+        if let Some(device_id) = &filter.device_id {
+            query = query.filter(alerts::Column::DeviceId.eq(device_id.as_str()));
+        }
+        if let Some(since) = filter.since {
+            query = query.filter(alerts::Column::Timestamp.gte(since));
+        }
+        if let Some(until) = filter.until {
+            query = query.filter(alerts::Column::Timestamp.lte(until));
+        }
+        if let Some(before_id) = filter.before_id {
+            query = query.filter(alerts::Column::Id.lt(before_id));
+        }
+        match &filter.tenant {
+            Some(TenantScope::Shared) => query = query.filter(alerts::Column::TenantId.is_null()),
+            Some(TenantScope::Tenant(t)) => query = query.filter(alerts::Column::TenantId.eq(t.as_str())),
+            None => {}
+        }
+        for rule in &filter.embargo {
+            // Published when old enough or outside the rule's region; without a position the
+            // comparisons are NULL, so only age lets the alert through
+            let mut published = Condition::any().add(alerts::Column::Timestamp.lte(rule.cutoff));
+            if let Some(b) = rule.region {
+                published = published
+                    .add(alerts::Column::Lat.lt(b.lat_min))
+                    .add(alerts::Column::Lat.gt(b.lat_max))
+                    .add(outside_lng(alerts::Column::Lng, &b));
+            }
+            query = query.filter(published);
+        }
+        Ok(query.order_by_desc(alerts::Column::Id).limit(limit).all(&self.db).await?)
+    }
+}
@@ -0,0 +1,158 @@
+use log::{debug, info, warn};
+use sea_orm::prelude::async_trait;
+use std::env;
+use std::future::Future;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::{AnomalyVerdict, NewPointRecord, PointFilter, PointOrder, PointStore, StoreError, StoreResult, DayWindow, TileGrid, TileSpeeds, TimeBucket, TimelineRow};
+use crate::database::model::points::Model as PointModel;
+
+/// Trips after DB_BREAKER_THRESHOLD consecutive connectivity failures (default 3) and then
+/// fails fast for DB_BREAKER_COOLDOWN_SECS (default 10). After the cooldown, calls go through
+/// again: one success closes the breaker, another connectivity failure reopens it.
+pub struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    failures: AtomicU32,
+    open_until: Mutex<Option<Instant>>,
+}
+
+impl CircuitBreaker {
+    pub fn from_env() -> Self {
+        let threshold = env::var("DB_BREAKER_THRESHOLD").ok().and_then(|v| v.parse().ok()).filter(|v| *v > 0).unwrap_or(3);
+        let cooldown = env::var("DB_BREAKER_COOLDOWN_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(10);
+        Self {
+            threshold,
+            cooldown: Duration::from_secs(cooldown),
+            failures: AtomicU32::new(0),
+            open_until: Mutex::new(None),
+        }
+    }
+
+    /// True while calls are being short-circuited
+    pub fn is_open(&self) -> bool {
+        self.open_until.lock().unwrap().is_some_and(|until| Instant::now() < until)
+    }
+
+    /// True when the last calls could not reach the database, including the cooldown trial
+    pub fn is_degraded(&self) -> bool {
+        self.failures.load(Ordering::Relaxed) >= self.threshold
+    }
+
+    fn record<T>(&self, res: &StoreResult<T>) {
+        match res {
+            Err(e) if e.is_connectivity() => {
+                let failures = self.failures.fetch_add(1, Ordering::Relaxed) + 1;
+                if failures >= self.threshold {
+                    let mut open_until = self.open_until.lock().unwrap();
+                    if open_until.is_none() {
+                        warn!("Database unreachable after {} failures; failing fast for {:?}", failures, self.cooldown);
+                    }
+                    *open_until = Some(Instant::now() + self.cooldown);
+                }
+            }
+            // Query errors prove the database is reachable
+            _ => {
+                if self.failures.swap(0, Ordering::Relaxed) >= self.threshold {
+                    info!("Database reachable again; circuit breaker closed");
+                }
+                *self.open_until.lock().unwrap() = None;
+            }
+        }
+    }
+}
+
+/// Wait before the first retry of a query that lost its connection; doubled after each retry
+const QUERY_RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Wraps the configured store with a circuit breaker, so a lost database costs one quick
+/// `StoreError::Unavailable` per call instead of a pool timeout.
+pub struct GuardedStore {
+    inner: Arc<dyn PointStore>,
+    breaker: Arc<CircuitBreaker>,
+    /// Extra attempts at reads and idempotent updates that hit a connectivity error
+    retries: u32,
+}
+
+impl GuardedStore {
+    pub fn new(inner: Arc<dyn PointStore>, breaker: Arc<CircuitBreaker>, retries: u32) -> Self {
+        Self { inner, breaker, retries }
+    }
+
+    async fn guard<T>(&self, call: impl Future<Output = StoreResult<T>>) -> StoreResult<T> {
+        if self.breaker.is_open() {
+            return Err(StoreError::Unavailable);
+        }
+        let res = call.await;
+        self.breaker.record(&res);
+        res
+    }
+
+    /// `guard` with retries, for calls that are safe to repeat: a dropped connection or a
+    /// pool timeout is tried again after a short backoff until the breaker opens. Inserts and
+    /// deletes are not retried, since the lost reply may belong to a committed transaction.
+    async fn guard_retry<T, F: Future<Output = StoreResult<T>>>(&self, call: impl Fn() -> F) -> StoreResult<T> {
+        let mut backoff = QUERY_RETRY_BACKOFF;
+        let mut attempt = 0;
+        loop {
+            match self.guard(call()).await {
+                Err(e) if e.is_connectivity() && attempt < self.retries && !self.breaker.is_open() => {
+                    attempt += 1;
+                    debug!("Retrying query after {}; attempt {}/{} in {:?}", e, attempt, self.retries, backoff);
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                }
+                res => return res,
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl PointStore for GuardedStore {
+    fn name(&self) -> &'static str { self.inner.name() }
+
+    async fn insert(&self, point: NewPointRecord) -> StoreResult<PointModel> {
+        self.guard(self.inner.insert(point)).await
+    }
+
+    async fn insert_many(&self, points: Vec<NewPointRecord>) -> StoreResult<Vec<PointModel>> {
+        self.guard(self.inner.insert_many(points)).await
+    }
+
+    async fn find_page(&self, filter: &PointFilter, order: PointOrder, limit: Option<u64>, offset: u64) -> StoreResult<Vec<PointModel>> {
+        self.guard_retry(|| self.inner.find_page(filter, order, limit, offset)).await
+    }
+
+    async fn find_after(&self, filter: &PointFilter, after_id: i64, limit: u64) -> StoreResult<Vec<PointModel>> {
+        self.guard_retry(|| self.inner.find_after(filter, after_id, limit)).await
+    }
+
+    async fn count(&self, filter: &PointFilter) -> StoreResult<u64> {
+        self.guard_retry(|| self.inner.count(filter)).await
+    }
+
+    async fn timeline(&self, filter: &PointFilter, bucket: TimeBucket) -> StoreResult<Vec<TimelineRow>> {
+        self.guard_retry(|| self.inner.timeline(filter, bucket)).await
+    }
+
+    async fn tile_speeds(&self, filter: &PointFilter, grid: TileGrid, window: DayWindow) -> StoreResult<Vec<TileSpeeds>> {
+        self.guard_retry(|| self.inner.tile_speeds(filter, grid, window)).await
+    }
+
+    async fn set_anomaly(&self, id: i64, verdict: AnomalyVerdict) -> StoreResult<bool> {
+        self.guard_retry(|| self.inner.set_anomaly(id, verdict)).await
+    }
+
+    async fn review_trip(&self, randomized_id: i64, anomaly: bool, reviewed_by: &str) -> StoreResult<u64> {
+        self.guard_retry(|| self.inner.review_trip(randomized_id, anomaly, reviewed_by)).await
+    }
+
+    // Not retried: after a lost reply the repeated delete finds nothing and reports 0 rows
+    // for a delete that did commit
+    async fn delete(&self, filter: &PointFilter) -> StoreResult<u64> {
+        self.guard(self.inner.delete(filter)).await
+    }
+}
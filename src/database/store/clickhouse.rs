@@ -0,0 +1,333 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::{error, info};
+use sea_orm::prelude::async_trait;
+use std::collections::BTreeMap;
+use std::env;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::{AnomalyVerdict, NewPointRecord, PointFilter, PointOrder, PointStore, DayWindow, StoreError, StoreResult, TenantScope, TileGrid, TileSpeeds, TimeBucket, TimelineRow};
+use crate::database::model::points::Model as PointModel;
+
+/// How long classifier verdicts gather before they go out as one mutation
+const VERDICT_FLUSH_DELAY: Duration = Duration::from_secs(5);
+/// Verdicts that go out at once without waiting, keeping the statement a reasonable size
+const MAX_VERDICTS_PER_MUTATION: usize = 10_000;
+
+/// Column-oriented analytics backend talking to ClickHouse over its HTTP interface.
+/// Rows keep the ids assigned by the primary store, so it only accepts fully formed models.
+/// Clones share the connection settings and the verdicts waiting to be written.
+#[derive(Clone)]
+pub struct ClickHouseStore {
+    client: reqwest::Client,
+    url: String,
+    database: String,
+    user: Option<String>,
+    password: Option<String>,
+    verdicts: Arc<Mutex<BTreeMap<i64, AnomalyVerdict>>>,
+    flush_scheduled: Arc<AtomicBool>,
+}
+
+impl ClickHouseStore {
+    /// Reads CLICKHOUSE_URL (required), CLICKHOUSE_DATABASE (default "default"),
+    /// CLICKHOUSE_USER and CLICKHOUSE_PASSWORD.
+    pub fn from_env() -> Result<Self, String> {
+        let url = env::var("CLICKHOUSE_URL").map_err(|_| "CLICKHOUSE_URL must be set for the ClickHouse backend".to_string())?;
+        Ok(Self {
+            client: reqwest::Client::new(),
+            url: url.trim_end_matches('/').to_string(),
+            database: env::var("CLICKHOUSE_DATABASE").unwrap_or_else(|_| "default".to_string()),
+            user: env::var("CLICKHOUSE_USER").ok(),
+            password: env::var("CLICKHOUSE_PASSWORD").ok(),
+            verdicts: Arc::default(),
+            flush_scheduled: Arc::default(),
+        })
+    }
+
+    async fn execute(&self, sql: &str, body: Option<String>) -> StoreResult<String> {
+        let params = [
+            ("database", self.database.as_str()),
+            ("query", sql),
+            ("date_time_input_format", "best_effort"),
+            ("date_time_output_format", "iso"),
+            ("output_format_json_quote_64bit_integers", "0"),
+        ];
+        let mut req = self.client.post(&self.url).query(&params);
+        if let Some(user) = &self.user {
+            req = req.basic_auth(user, self.password.as_deref());
+        }
+        if let Some(body) = body {
+            req = req.body(body);
+        }
+        let resp = req.send().await.map_err(|e| StoreError::Backend(format!("ClickHouse request failed: {}", e)))?;
+        let status = resp.status();
+        let text = resp.text().await.map_err(|e| StoreError::Backend(format!("ClickHouse response unreadable: {}", e)))?;
+        if !status.is_success() {
+            return Err(StoreError::Backend(format!("ClickHouse returned {}: {}", status, text.trim())));
+        }
+        Ok(text)
+    }
+
+    /// Creates the points table when missing; safe to call on every start.
+    pub async fn ensure_schema(&self) -> StoreResult<()> {
+        self.execute(
+            "CREATE TABLE IF NOT EXISTS points (\
+                id Int64, randomized_id Int64, lat Float64, lng Float64, alt Float64, spd Float64, azm Float64, \
+                timestamp Nullable(DateTime64(6, 'UTC')), anomaly Nullable(Bool), client_uuid Nullable(UUID), \
+                anomaly_score Nullable(Float64), anomaly_reason Nullable(String), tenant_id Nullable(String), \
+                prev_distance_m Nullable(Float64), prev_interval_s Nullable(Float64), derived_speed Nullable(Float64), \
+                anomaly_review Nullable(Bool)\
+            ) ENGINE = ReplacingMergeTree ORDER BY (randomized_id, id)",
+            None,
+        ).await?;
+        // Tables created before client UUIDs existed
+        self.execute("ALTER TABLE points ADD COLUMN IF NOT EXISTS client_uuid Nullable(UUID)", None).await?;
+        // ... and before anomaly scores
+        self.execute("ALTER TABLE points ADD COLUMN IF NOT EXISTS anomaly_score Nullable(Float64)", None).await?;
+        self.execute("ALTER TABLE points ADD COLUMN IF NOT EXISTS anomaly_reason Nullable(String)", None).await?;
+        // ... and before tenants
+        self.execute("ALTER TABLE points ADD COLUMN IF NOT EXISTS tenant_id Nullable(String)", None).await?;
+        // ... and before point deltas
+        for column in ["prev_distance_m", "prev_interval_s", "derived_speed"] {
+            self.execute(&format!("ALTER TABLE points ADD COLUMN IF NOT EXISTS {} Nullable(Float64)", column), None).await?;
+        }
+        // ... and before reviews were kept apart from the classifier's flags
+        self.execute("ALTER TABLE points ADD COLUMN IF NOT EXISTS anomaly_review Nullable(Bool)", None).await?;
+        info!("ClickHouse schema ready at {} (database {})", self.url, self.database);
+        Ok(())
+    }
+
+    /// Appends rows produced by the primary store
+    pub async fn insert_models(&self, rows: &[PointModel]) -> StoreResult<()> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let mut body = String::new();
+        for row in rows {
+            let line = serde_json::to_string(row).map_err(|e| StoreError::Backend(e.to_string()))?;
+            body.push_str(&line);
+            body.push('\n');
+        }
+        self.execute("INSERT INTO points FORMAT JSONEachRow", Some(body)).await?;
+        Ok(())
+    }
+
+    /// Writes the verdicts gathered so far in one mutation, each row taking its own from
+    /// the arrays; ClickHouse runs every mutation as a rewrite of the parts it touches
+    async fn flush_verdicts(&self) -> StoreResult<()> {
+        let verdicts = std::mem::take(&mut *self.verdicts.lock().unwrap());
+        if verdicts.is_empty() {
+            return Ok(());
+        }
+        let ids: Vec<String> = verdicts.keys().map(i64::to_string).collect();
+        let ids = ids.join(", ");
+        let column = |values: Vec<String>, name: &str| format!("{name} = transform(id, [{ids}], [{}], {name})", values.join(", "));
+        let anomalies = column(verdicts.values().map(|v| v.anomaly.to_string()).collect(), "anomaly");
+        let scores = column(verdicts.values().map(|v| v.score.to_string()).collect(), "anomaly_score");
+        // Reasons are rule names, never user input
+        let reasons = column(verdicts.values().map(|v| v.reason.map_or("NULL".to_string(), |r| format!("'{}'", r))).collect(), "anomaly_reason");
+        self.execute(&format!("ALTER TABLE points UPDATE {}, {}, {} WHERE id IN ({})", anomalies, scores, reasons, ids), None).await?;
+        Ok(())
+    }
+
+    /// Runs a `... FORMAT JSONEachRow` select over the points table
+    async fn select_points(&self, sql: &str) -> StoreResult<Vec<PointModel>> {
+        let text = self.execute(sql, None).await?;
+        text.lines()
+            .filter(|l| !l.trim().is_empty())
+            .map(|l| serde_json::from_str::<PointModel>(l).map_err(|e| StoreError::Backend(format!("bad ClickHouse row: {}", e))))
+            .collect()
+    }
+}
+
+fn ts_literal(ts: DateTime<Utc>) -> String {
+    format!("parseDateTime64BestEffort('{}', 6, 'UTC')", ts.to_rfc3339_opts(SecondsFormat::Micros, true))
+}
+
+fn where_clause(filter: &PointFilter) -> String {
+    let mut conds: Vec<String> = Vec::new();
+    if let Some(b) = filter.bbox {
+        conds.push(format!("lat BETWEEN {} AND {}", b.lat_min, b.lat_max));
+        let lngs: Vec<String> = b.lng_ranges().iter().map(|(min, max)| format!("lng BETWEEN {} AND {}", min, max)).collect();
+        conds.push(format!("({})", lngs.join(" OR ")));
+    }
+    if let Some(ts) = filter.since { conds.push(format!("timestamp >= {}", ts_literal(ts))); }
+    if let Some(ts) = filter.until { conds.push(format!("timestamp <= {}", ts_literal(ts))); }
+    if let Some(rid) = filter.randomized_id { conds.push(format!("randomized_id = {}", rid)); }
+    if let Some(ids) = &filter.randomized_ids {
+        let ids: Vec<String> = ids.iter().map(i64::to_string).collect();
+        conds.push(format!("randomized_id IN ({})", if ids.is_empty() { "NULL".to_string() } else { ids.join(", ") }));
+    }
+    // An operator's decision on the trip wins over the classifier
+    if let Some(a) = filter.anomaly { conds.push(format!("coalesce(anomaly_review, anomaly) = {}", a)); }
+    if let Some(s) = filter.min_anomaly_score { conds.push(format!("anomaly_score >= {}", s)); }
+    if let Some(id) = filter.before_id { conds.push(format!("id < {}", id)); }
+    if let Some(uuid) = filter.client_uuid { conds.push(format!("client_uuid = toUUID('{}')", uuid)); }
+    // Tenant names are validated identifiers (see `tenant::is_valid_name`)
+    match &filter.tenant {
+        Some(TenantScope::Shared) => conds.push("tenant_id IS NULL".to_string()),
+        Some(TenantScope::Tenant(t)) => conds.push(format!("tenant_id = '{}'", t)),
+        None => {}
+    }
+    for rule in &filter.embargo {
+        let mut published = vec![format!("timestamp <= {}", ts_literal(rule.cutoff))];
+        if let Some(b) = rule.region {
+            let outside_lng = if b.crosses_antimeridian() { "AND" } else { "OR" };
+            published.push(format!(
+                "lat < {} OR lat > {} OR (lng < {} {} lng > {})",
+                b.lat_min, b.lat_max, b.lng_min, outside_lng, b.lng_max
+            ));
+        }
+        conds.push(format!("({})", published.join(" OR ")));
+    }
+    if conds.is_empty() { String::new() } else { format!(" WHERE {}", conds.join(" AND ")) }
+}
+
+#[async_trait::async_trait]
+impl PointStore for ClickHouseStore {
+    fn name(&self) -> &'static str { "clickhouse" }
+
+    async fn insert(&self, _point: NewPointRecord) -> StoreResult<PointModel> {
+        Err(StoreError::Backend("ClickHouse store is read-only; ingest through the primary store".to_string()))
+    }
+
+    async fn find_page(&self, filter: &PointFilter, order: PointOrder, limit: Option<u64>, offset: u64) -> StoreResult<Vec<PointModel>> {
+        let order_by = match order {
+            PointOrder::TimestampAsc => "timestamp ASC",
+            PointOrder::TimestampDesc => "timestamp DESC",
+            PointOrder::TripThenTimestamp => "randomized_id ASC, timestamp ASC",
+            PointOrder::IdAsc => "id ASC",
+            PointOrder::IdDesc => "id DESC",
+        };
+        let mut sql = format!("SELECT * FROM points FINAL{} ORDER BY {}", where_clause(filter), order_by);
+        match (limit, offset) {
+            (Some(n), _) => sql.push_str(&format!(" LIMIT {} OFFSET {}", n, offset)),
+            (None, 0) => {}
+            (None, _) => sql.push_str(&format!(" LIMIT 18446744073709551615 OFFSET {}", offset)),
+        }
+        sql.push_str(" FORMAT JSONEachRow");
+        self.select_points(&sql).await
+    }
+
+    async fn find_after(&self, filter: &PointFilter, after_id: i64, limit: u64) -> StoreResult<Vec<PointModel>> {
+        let mut clause = where_clause(filter);
+        clause.push_str(if clause.is_empty() { " WHERE " } else { " AND " });
+        clause.push_str(&format!("id > {}", after_id));
+        let sql = format!("SELECT * FROM points FINAL{} ORDER BY id ASC LIMIT {} FORMAT JSONEachRow", clause, limit);
+        self.select_points(&sql).await
+    }
+
+    async fn count(&self, filter: &PointFilter) -> StoreResult<u64> {
+        #[derive(serde::Deserialize)]
+        struct CountRow { n: u64 }
+        let sql = format!("SELECT count() AS n FROM points FINAL{} FORMAT JSONEachRow", where_clause(filter));
+        let text = self.execute(&sql, None).await?;
+        let row: CountRow = serde_json::from_str(text.trim())
+            .map_err(|e| StoreError::Backend(format!("bad ClickHouse count: {}", e)))?;
+        Ok(row.n)
+    }
+
+    async fn timeline(&self, filter: &PointFilter, bucket: TimeBucket) -> StoreResult<Vec<TimelineRow>> {
+        #[derive(serde::Deserialize)]
+        struct Row { bucket: i64, points: u64, trips: u64 }
+        let start = match bucket {
+            TimeBucket::Hour => "toStartOfHour(timestamp)",
+            TimeBucket::Day => "toStartOfDay(timestamp)",
+            TimeBucket::Week => "toDateTime(toMonday(timestamp), 'UTC')",
+        };
+        let clause = where_clause(filter);
+        let clause = if clause.is_empty() { " WHERE timestamp IS NOT NULL".to_string() } else { format!("{} AND timestamp IS NOT NULL", clause) };
+        let sql = format!(
+            "SELECT toUnixTimestamp({}) AS bucket, count() AS points, uniqExact(randomized_id) AS trips \
+             FROM points FINAL{} GROUP BY bucket ORDER BY bucket FORMAT JSONEachRow",
+            start, clause
+        );
+        let text = self.execute(&sql, None).await?;
+        text.lines()
+            .filter(|l| !l.trim().is_empty())
+            .map(|l| {
+                let row: Row = serde_json::from_str(l).map_err(|e| StoreError::Backend(format!("bad ClickHouse timeline row: {}", e)))?;
+                let bucket = DateTime::from_timestamp(row.bucket, 0)
+                    .ok_or_else(|| StoreError::Backend(format!("bad ClickHouse bucket {}", row.bucket)))?;
+                Ok(TimelineRow { bucket, points: row.points, trips: row.trips })
+            })
+            .collect()
+    }
+
+    async fn tile_speeds(&self, filter: &PointFilter, grid: TileGrid, window: DayWindow) -> StoreResult<Vec<TileSpeeds>> {
+        #[derive(serde::Deserialize)]
+        struct Row { tile_row: i64, tile_col: i64, points: u64, speed_sum: f64, mean_lat: f64, mean_lng: f64 }
+        let t = format!("((toUnixTimestamp(timestamp) + {}) % 86400 + 86400) % 86400", window.offset);
+        let within = if window.start < window.end {
+            format!("{t} >= {} AND {t} < {}", window.start, window.end)
+        } else {
+            format!("({t} >= {} OR {t} < {})", window.start, window.end)
+        };
+        let clause = where_clause(filter);
+        let clause = if clause.is_empty() {
+            format!(" WHERE timestamp IS NOT NULL AND {}", within)
+        } else {
+            format!("{} AND timestamp IS NOT NULL AND {}", clause, within)
+        };
+        // Aliases are kept apart from the column names, which ClickHouse would otherwise
+        // substitute into the WHERE clause
+        let sql = format!(
+            "SELECT toInt64(floor((lat - ({})) / {})) AS tile_row, toInt64(floor((lng - ({})) / {})) AS tile_col, \
+             count() AS points, sum(spd) AS speed_sum, avg(lat) AS mean_lat, avg(lng) AS mean_lng \
+             FROM points FINAL{} GROUP BY tile_row, tile_col FORMAT JSONEachRow",
+            grid.lat0, grid.height, grid.lng0, grid.width, clause
+        );
+        let text = self.execute(&sql, None).await?;
+        text.lines()
+            .filter(|l| !l.trim().is_empty())
+            .map(|l| {
+                let row: Row = serde_json::from_str(l).map_err(|e| StoreError::Backend(format!("bad ClickHouse tile row: {}", e)))?;
+                Ok(TileSpeeds { row: row.tile_row, col: row.tile_col, points: row.points, speed_sum: row.speed_sum, lat: row.mean_lat, lng: row.mean_lng })
+            })
+            .collect()
+    }
+
+    /// Queues the verdict for the next mutation, a few seconds away at most, instead of one
+    /// mutation per point; ClickHouse does not report affected rows
+    async fn set_anomaly(&self, id: i64, verdict: AnomalyVerdict) -> StoreResult<bool> {
+        let full = {
+            let mut verdicts = self.verdicts.lock().unwrap();
+            verdicts.insert(id, verdict);
+            verdicts.len() >= MAX_VERDICTS_PER_MUTATION
+        };
+        if full {
+            self.flush_verdicts().await?;
+        } else if !self.flush_scheduled.swap(true, Ordering::SeqCst) {
+            let store = self.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(VERDICT_FLUSH_DELAY).await;
+                store.flush_scheduled.store(false, Ordering::SeqCst);
+                if let Err(e) = store.flush_verdicts().await {
+                    error!("ClickHouse anomaly update failed: {}", e);
+                }
+            });
+        }
+        Ok(true)
+    }
+
+    async fn review_trip(&self, randomized_id: i64, anomaly: bool, _reviewed_by: &str) -> StoreResult<u64> {
+        // The audit trail lives in the primary database; the classifier's columns stay as they are
+        let n = self.count(&PointFilter { randomized_id: Some(randomized_id), ..Default::default() }).await?;
+        if n > 0 {
+            self.execute(&format!("ALTER TABLE points UPDATE anomaly_review = {} WHERE randomized_id = {}", anomaly, randomized_id), None).await?;
+        }
+        Ok(n)
+    }
+
+    async fn delete(&self, filter: &PointFilter) -> StoreResult<u64> {
+        // Count first: lightweight DELETE does not report affected rows either
+        let n = self.count(filter).await?;
+        if n > 0 {
+            let conds = where_clause(filter);
+            let conds = if conds.is_empty() { " WHERE 1".to_string() } else { conds };
+            self.execute(&format!("DELETE FROM points{}", conds), None).await?;
+        }
+        Ok(n)
+    }
+}
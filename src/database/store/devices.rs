@@ -0,0 +1,139 @@
+use chrono::{DateTime, Utc};
+use sea_orm::prelude::async_trait;
+use sea_orm::sea_query::{Alias, Condition, Expr, OnConflict, Query, SelectStatement};
+use sea_orm::{ColumnTrait, ConnectionTrait, DbErr, EntityTrait, FromQueryResult, QueryFilter, Set};
+
+use super::trips::published;
+use super::{DeviceFilter, DeviceStats, DeviceStore, SeaOrmPointStore, StoreResult, TenantScope};
+use crate::database::model::devices::{self, ActiveModel as DeviceActiveModel, Entity as Devices};
+use crate::database::model::trips::{self, Entity as Trips};
+
+fn new_device(device_id: &str, name: Option<&str>, tenant_id: Option<&str>) -> DeviceActiveModel {
+    DeviceActiveModel {
+        tenant_key: Set(tenant_id.unwrap_or_default().to_string()),
+        device_id: Set(device_id.to_string()),
+        name: Set(name.map(str::to_string)),
+        registered_at: Set(Utc::now()),
+        tenant_id: Set(tenant_id.map(str::to_string)),
+    }
+}
+
+fn on_conflict() -> OnConflict {
+    OnConflict::columns([devices::Column::TenantKey, devices::Column::DeviceId]).do_nothing().to_owned()
+}
+
+/// Adds the devices named by trips, as (device, tenant), unless their tenants registered them already
+pub(super) async fn record_devices<C: ConnectionTrait>(conn: &C, named: &[(String, Option<String>)]) -> Result<(), DbErr> {
+    if named.is_empty() {
+        return Ok(());
+    }
+    let rows = named.iter().map(|(device_id, tenant_id)| new_device(device_id, None, tenant_id.as_deref()));
+    Devices::insert_many(rows).on_conflict(on_conflict()).exec_without_returning(conn).await?;
+    Ok(())
+}
+
+#[derive(FromQueryResult)]
+struct Row {
+    device_id: String,
+    name: Option<String>,
+    registered_at: DateTime<Utc>,
+    last_seen: Option<DateTime<Utc>>,
+    trips: i64,
+    points: i64,
+    distance_m: f64,
+}
+
+/// Devices joined with the totals of their trips, those of the same tenant naming them
+fn stats_query(filter: &DeviceFilter) -> SelectStatement {
+    let mut joined = Condition::all()
+        .add(Expr::col((Trips, trips::Column::DeviceId)).equals((Devices, devices::Column::DeviceId)))
+        .add(Expr::cust(r#"COALESCE("trips"."tenant_id", '') = "devices"."tenant_key""#));
+    // Unpublished trips count neither towards the totals nor as activity
+    for rule in &filter.embargo {
+        joined = joined.add(published(rule));
+    }
+    let last_seen = Expr::col((Trips, trips::Column::EndTs)).max();
+    let mut query = Query::select()
+        .column((Devices, devices::Column::DeviceId))
+        .column((Devices, devices::Column::Name))
+        .column((Devices, devices::Column::RegisteredAt))
+        .expr_as(last_seen.clone(), Alias::new("last_seen"))
+        .expr_as(Expr::col((Trips, trips::Column::RandomizedId)).count(), Alias::new("trips"))
+        // Postgres sums bigints into numeric
+        .expr_as(Expr::cust(r#"CAST(COALESCE(SUM("trips"."point_count"), 0) AS BIGINT)"#), Alias::new("points"))
+        .expr_as(Expr::cust(r#"COALESCE(SUM("trips"."distance_m"), 0.0)"#), Alias::new("distance_m"))
+        .from(Devices)
+        .left_join(Trips, joined)
+        .group_by_col((Devices, devices::Column::TenantKey))
+        .group_by_col((Devices, devices::Column::DeviceId))
+        .group_by_col((Devices, devices::Column::Name))
+        .group_by_col((Devices, devices::Column::RegisteredAt))
+        .to_owned();
+    if let Some(id) = &filter.device_id {
+        query.and_where(Expr::col((Devices, devices::Column::DeviceId)).eq(id.as_str()));
+    }
+    match &filter.tenant {
+        Some(TenantScope::Shared) => {
+            query.and_where(Expr::col((Devices, devices::Column::TenantId)).is_null());
+        }
+        Some(TenantScope::Tenant(t)) => {
+            query.and_where(Expr::col((Devices, devices::Column::TenantId)).eq(t.as_str()));
+        }
+        None => {}
+    }
+    if let Some(cutoff) = filter.silent_since {
+        query.cond_having(Condition::any().add(Expr::expr(last_seen.clone()).is_null()).add(Expr::expr(last_seen).lt(cutoff)));
+    }
+    query
+}
+
+#[async_trait::async_trait]
+impl DeviceStore for SeaOrmPointStore {
+    async fn register_device(&self, device_id: &str, name: Option<&str>, tenant_id: Option<&str>) -> StoreResult<bool> {
+        let created = Devices::insert(new_device(device_id, name, tenant_id)).on_conflict(on_conflict()).exec_without_returning(&self.db).await?;
+        if created == 0
+            && let Some(name) = name
+        {
+            Devices::update_many()
+                .col_expr(devices::Column::Name, Expr::value(name))
+                .filter(devices::Column::TenantKey.eq(tenant_id.unwrap_or_default()))
+                .filter(devices::Column::DeviceId.eq(device_id))
+                .exec(&self.db)
+                .await?;
+        }
+        Ok(created > 0)
+    }
+
+    async fn find_devices(&self, filter: &DeviceFilter, limit: u64, offset: u64) -> StoreResult<Vec<DeviceStats>> {
+        let query = stats_query(filter)
+            .order_by((Devices, devices::Column::DeviceId), sea_orm::Order::Asc)
+            .order_by((Devices, devices::Column::TenantKey), sea_orm::Order::Asc)
+            .limit(limit)
+            .offset(offset)
+            .to_owned();
+        let rows = Row::find_by_statement(self.db.get_database_backend().build(&query)).all(&self.db).await?;
+        Ok(rows
+            .into_iter()
+            .map(|r| DeviceStats {
+                device_id: r.device_id,
+                name: r.name,
+                registered_at: r.registered_at,
+                last_seen: r.last_seen,
+                trips: r.trips as u64,
+                points: r.points as u64,
+                distance_m: r.distance_m,
+            })
+            .collect())
+    }
+
+    async fn count_devices(&self, filter: &DeviceFilter) -> StoreResult<u64> {
+        #[derive(FromQueryResult)]
+        struct Count { n: i64 }
+        let query = Query::select()
+            .expr_as(Expr::cust("COUNT(*)"), Alias::new("n"))
+            .from_subquery(stats_query(filter), Alias::new("matching"))
+            .to_owned();
+        let count = Count::find_by_statement(self.db.get_database_backend().build(&query)).one(&self.db).await?;
+        Ok(count.map_or(0, |c| c.n as u64))
+    }
+}
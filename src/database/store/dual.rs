@@ -0,0 +1,143 @@
+use log::{error, warn};
+use sea_orm::prelude::async_trait;
+
+use super::{AnomalyVerdict, ClickHouseStore, NewPointRecord, PointFilter, PointOrder, PointStore, StoreResult, DayWindow, TileGrid, TileSpeeds, TimeBucket, TimelineRow};
+use crate::database::model::points::Model as PointModel;
+
+/// Writes go to the primary store (and optionally mirror to ClickHouse); area scans used by
+/// the aggregation endpoints read from ClickHouse. Per-trip and per-UUID lookups stay on the
+/// primary so classification and retry detection never race the analytics copy.
+pub struct AnalyticsSplitStore {
+    primary: Box<dyn PointStore>,
+    analytics: ClickHouseStore,
+    /// false when ClickHouse is fed externally (CDC), so we must not insert twice
+    dual_write: bool,
+}
+
+impl AnalyticsSplitStore {
+    pub fn new(primary: Box<dyn PointStore>, analytics: ClickHouseStore, dual_write: bool) -> Self {
+        Self { primary, analytics, dual_write }
+    }
+}
+
+#[async_trait::async_trait]
+impl PointStore for AnalyticsSplitStore {
+    fn name(&self) -> &'static str { "postgres+clickhouse" }
+
+    async fn insert(&self, point: NewPointRecord) -> StoreResult<PointModel> {
+        let model = self.primary.insert(point).await?;
+        if self.dual_write
+            && let Err(e) = self.analytics.insert_models(std::slice::from_ref(&model)).await
+        {
+            // The primary row is committed; analytics lag is preferable to failing ingestion
+            error!("ClickHouse mirror insert failed for point {}: {}", model.id, e);
+        }
+        Ok(model)
+    }
+
+    async fn insert_many(&self, points: Vec<NewPointRecord>) -> StoreResult<Vec<PointModel>> {
+        let models = self.primary.insert_many(points).await?;
+        if self.dual_write
+            && let Err(e) = self.analytics.insert_models(&models).await
+        {
+            error!("ClickHouse mirror insert failed for {} points: {}", models.len(), e);
+        }
+        Ok(models)
+    }
+
+    async fn find_page(&self, filter: &PointFilter, order: PointOrder, limit: Option<u64>, offset: u64) -> StoreResult<Vec<PointModel>> {
+        if filter.is_primary_lookup() {
+            return self.primary.find_page(filter, order, limit, offset).await;
+        }
+        match self.analytics.find_page(filter, order, limit, offset).await {
+            Ok(rows) => Ok(rows),
+            Err(e) => {
+                warn!("ClickHouse read failed, falling back to {}: {}", self.primary.name(), e);
+                self.primary.find_page(filter, order, limit, offset).await
+            }
+        }
+    }
+
+    async fn find_after(&self, filter: &PointFilter, after_id: i64, limit: u64) -> StoreResult<Vec<PointModel>> {
+        if filter.is_primary_lookup() {
+            return self.primary.find_after(filter, after_id, limit).await;
+        }
+        match self.analytics.find_after(filter, after_id, limit).await {
+            Ok(rows) => Ok(rows),
+            Err(e) => {
+                warn!("ClickHouse read failed, falling back to {}: {}", self.primary.name(), e);
+                self.primary.find_after(filter, after_id, limit).await
+            }
+        }
+    }
+
+    async fn count(&self, filter: &PointFilter) -> StoreResult<u64> {
+        if filter.is_primary_lookup() {
+            return self.primary.count(filter).await;
+        }
+        match self.analytics.count(filter).await {
+            Ok(n) => Ok(n),
+            Err(e) => {
+                warn!("ClickHouse count failed, falling back to {}: {}", self.primary.name(), e);
+                self.primary.count(filter).await
+            }
+        }
+    }
+
+    async fn timeline(&self, filter: &PointFilter, bucket: TimeBucket) -> StoreResult<Vec<TimelineRow>> {
+        if filter.is_primary_lookup() {
+            return self.primary.timeline(filter, bucket).await;
+        }
+        match self.analytics.timeline(filter, bucket).await {
+            Ok(rows) => Ok(rows),
+            Err(e) => {
+                warn!("ClickHouse timeline failed, falling back to {}: {}", self.primary.name(), e);
+                self.primary.timeline(filter, bucket).await
+            }
+        }
+    }
+
+    async fn tile_speeds(&self, filter: &PointFilter, grid: TileGrid, window: DayWindow) -> StoreResult<Vec<TileSpeeds>> {
+        if filter.is_primary_lookup() {
+            return self.primary.tile_speeds(filter, grid, window).await;
+        }
+        match self.analytics.tile_speeds(filter, grid, window).await {
+            Ok(rows) => Ok(rows),
+            Err(e) => {
+                warn!("ClickHouse tile speeds failed, falling back to {}: {}", self.primary.name(), e);
+                self.primary.tile_speeds(filter, grid, window).await
+            }
+        }
+    }
+
+    async fn set_anomaly(&self, id: i64, verdict: AnomalyVerdict) -> StoreResult<bool> {
+        let found = self.primary.set_anomaly(id, verdict).await?;
+        if found
+            && self.dual_write
+            && let Err(e) = self.analytics.set_anomaly(id, verdict).await
+        {
+            error!("ClickHouse anomaly update failed for point {}: {}", id, e);
+        }
+        Ok(found)
+    }
+
+    async fn review_trip(&self, randomized_id: i64, anomaly: bool, reviewed_by: &str) -> StoreResult<u64> {
+        let n = self.primary.review_trip(randomized_id, anomaly, reviewed_by).await?;
+        if n > 0
+            && self.dual_write
+            && let Err(e) = self.analytics.review_trip(randomized_id, anomaly, reviewed_by).await
+        {
+            error!("ClickHouse anomaly review failed for trip {}: {}", randomized_id, e);
+        }
+        Ok(n)
+    }
+
+    async fn delete(&self, filter: &PointFilter) -> StoreResult<u64> {
+        let n = self.primary.delete(filter).await?;
+        // Erasure must reach the analytics copy as well, whoever feeds it
+        if let Err(e) = self.analytics.delete(filter).await {
+            error!("ClickHouse delete failed, analytics copy still holds the rows: {}", e);
+        }
+        Ok(n)
+    }
+}
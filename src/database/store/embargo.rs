@@ -0,0 +1,247 @@
+use chrono::{DateTime, Duration, Utc};
+use sea_orm::prelude::async_trait;
+use std::env;
+use std::sync::Arc;
+
+use super::{
+    AnomalyVerdict, BBox, DeviceFilter, DeviceStats, DeviceStore, EmbargoRule, GlobalStats, NewPointRecord, PointFilter, PointOrder, PointStore, StoreResult, TenantScope,
+    DayWindow, TileGrid, TileSpeeds, TimeBucket, TimelineRow, TripFilter, TripOrder, TripStore,
+};
+use crate::database::model::matched_trips::Model as MatchedTripModel;
+use crate::database::model::points::Model as PointModel;
+use crate::database::model::trips::Model as TripModel;
+
+/// How old data must be before the public endpoints show it. PUBLICATION_DELAY_SECS applies
+/// everywhere; PUBLICATION_DELAY_REGIONS adds longer delays for areas, as
+/// `lat1,lng1,lat2,lng2=secs` entries separated by `;`. A point is published once it is older
+/// than every delay whose region contains it. Ingestion is not delayed.
+#[derive(Debug, Clone, Default)]
+pub struct PublicationDelay {
+    global: Option<Duration>,
+    regions: Vec<(BBox, Duration)>,
+}
+
+impl PublicationDelay {
+    /// The same delay everywhere
+    pub fn new(global: Duration) -> Self {
+        Self { global: Some(global).filter(|d| *d > Duration::zero()), regions: Vec::new() }
+    }
+
+    pub fn from_env() -> Result<Self, String> {
+        let global = match env::var("PUBLICATION_DELAY_SECS") {
+            Ok(v) if !v.trim().is_empty() => Some(parse_secs(&v).map_err(|e| format!("PUBLICATION_DELAY_SECS: {}", e))?),
+            _ => None,
+        };
+        let regions = match env::var("PUBLICATION_DELAY_REGIONS") {
+            Ok(v) => v
+                .split(';')
+                .map(str::trim)
+                .filter(|e| !e.is_empty())
+                .map(|e| parse_region(e).map_err(|err| format!("PUBLICATION_DELAY_REGIONS entry '{}': {}", e, err)))
+                .collect::<Result<Vec<_>, _>>()?,
+            Err(_) => Vec::new(),
+        };
+        Ok(Self { global: global.filter(|d| *d > Duration::zero()), regions })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.global.is_some() || !self.regions.is_empty()
+    }
+
+    /// The earlier of `before` and the cutoff of the global delay at `now`
+    pub fn before(&self, before: Option<DateTime<Utc>>, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let cutoff = self.global.map(|d| now - d);
+        match (before, cutoff) {
+            (Some(b), Some(c)) => Some(b.min(c)),
+            (b, c) => b.or(c),
+        }
+    }
+
+    /// Rules for a query issued at `now`
+    pub fn rules(&self, now: DateTime<Utc>) -> Vec<EmbargoRule> {
+        self.global
+            .map(|d| EmbargoRule { region: None, cutoff: now - d })
+            .into_iter()
+            .chain(self.regions.iter().map(|(b, d)| EmbargoRule { region: Some(*b), cutoff: now - *d }))
+            .collect()
+    }
+
+    /// When data about `area` up to `latest` may be published: after the longest delay whose
+    /// region meets the area. An unknown area meets every region.
+    pub fn release_at(&self, area: Option<&BBox>, latest: DateTime<Utc>) -> DateTime<Utc> {
+        let regional = self.regions.iter().filter(|(b, _)| area.is_none_or(|a| a.intersects(b))).map(|(_, d)| *d);
+        latest + self.global.into_iter().chain(regional).max().unwrap_or_else(Duration::zero)
+    }
+
+    fn apply_points(&self, filter: &PointFilter) -> PointFilter {
+        let mut filter = filter.clone();
+        filter.embargo.extend(self.rules(Utc::now()));
+        filter
+    }
+
+    fn apply_trips(&self, filter: &TripFilter) -> TripFilter {
+        let mut filter = filter.clone();
+        filter.embargo.extend(self.rules(Utc::now()));
+        filter
+    }
+
+    fn apply_devices(&self, filter: &DeviceFilter) -> DeviceFilter {
+        let mut filter = filter.clone();
+        filter.embargo.extend(self.rules(Utc::now()));
+        filter
+    }
+}
+
+/// Store handed to the HTTP handlers: reads only see published data, writes pass through.
+/// Background workers (classification, map matching, retention, WAL replay) keep the inner
+/// store, so they still work on live data.
+pub struct Embargoed<S: ?Sized> {
+    inner: Arc<S>,
+    delay: PublicationDelay,
+}
+
+impl<S: ?Sized> Embargoed<S> {
+    pub fn new(inner: Arc<S>, delay: PublicationDelay) -> Self {
+        Self { inner, delay }
+    }
+}
+
+#[async_trait::async_trait]
+impl PointStore for Embargoed<dyn PointStore> {
+    fn name(&self) -> &'static str { self.inner.name() }
+
+    async fn insert(&self, point: NewPointRecord) -> StoreResult<PointModel> {
+        self.inner.insert(point).await
+    }
+
+    async fn insert_many(&self, points: Vec<NewPointRecord>) -> StoreResult<Vec<PointModel>> {
+        self.inner.insert_many(points).await
+    }
+
+    async fn find_page(&self, filter: &PointFilter, order: PointOrder, limit: Option<u64>, offset: u64) -> StoreResult<Vec<PointModel>> {
+        // Idempotency lookups by client UUID must see what was just ingested
+        if filter.client_uuid.is_some() {
+            return self.inner.find_page(filter, order, limit, offset).await;
+        }
+        self.inner.find_page(&self.delay.apply_points(filter), order, limit, offset).await
+    }
+
+    async fn find_after(&self, filter: &PointFilter, after_id: i64, limit: u64) -> StoreResult<Vec<PointModel>> {
+        self.inner.find_after(&self.delay.apply_points(filter), after_id, limit).await
+    }
+
+    async fn count(&self, filter: &PointFilter) -> StoreResult<u64> {
+        self.inner.count(&self.delay.apply_points(filter)).await
+    }
+
+    async fn timeline(&self, filter: &PointFilter, bucket: TimeBucket) -> StoreResult<Vec<TimelineRow>> {
+        self.inner.timeline(&self.delay.apply_points(filter), bucket).await
+    }
+
+    async fn tile_speeds(&self, filter: &PointFilter, grid: TileGrid, window: DayWindow) -> StoreResult<Vec<TileSpeeds>> {
+        self.inner.tile_speeds(&self.delay.apply_points(filter), grid, window).await
+    }
+
+    async fn set_anomaly(&self, id: i64, verdict: AnomalyVerdict) -> StoreResult<bool> {
+        self.inner.set_anomaly(id, verdict).await
+    }
+
+    async fn review_trip(&self, randomized_id: i64, anomaly: bool, reviewed_by: &str) -> StoreResult<u64> {
+        self.inner.review_trip(randomized_id, anomaly, reviewed_by).await
+    }
+
+    // Erasure removes unpublished rows too
+    async fn delete(&self, filter: &PointFilter) -> StoreResult<u64> {
+        self.inner.delete(filter).await
+    }
+}
+
+#[async_trait::async_trait]
+impl TripStore for Embargoed<dyn TripStore> {
+    async fn find_trips(&self, filter: &TripFilter, order: TripOrder, limit: u64, offset: u64) -> StoreResult<Vec<TripModel>> {
+        self.inner.find_trips(&self.delay.apply_trips(filter), order, limit, offset).await
+    }
+
+    async fn count_trips(&self, filter: &TripFilter) -> StoreResult<u64> {
+        self.inner.count_trips(&self.delay.apply_trips(filter)).await
+    }
+
+    async fn trips_to_match(&self, ended_before: DateTime<Utc>, limit: u64) -> StoreResult<Vec<TripModel>> {
+        self.inner.trips_to_match(ended_before, limit).await
+    }
+
+    async fn save_matched(&self, matched: MatchedTripModel) -> StoreResult<()> {
+        self.inner.save_matched(matched).await
+    }
+
+    async fn record_match_failure(&self, randomized_id: i64, error: &str, backoff: Duration) -> StoreResult<u32> {
+        self.inner.record_match_failure(randomized_id, error, backoff).await
+    }
+
+    /// Only for trips that are published themselves
+    async fn find_matched(&self, randomized_id: i64) -> StoreResult<Option<MatchedTripModel>> {
+        if self.delay.is_enabled() {
+            let filter = TripFilter { randomized_id: Some(randomized_id), ..Default::default() };
+            if self.count_trips(&filter).await? == 0 {
+                return Ok(None);
+            }
+        }
+        self.inner.find_matched(randomized_id).await
+    }
+
+    /// Totals only count points older than the global delay. Regional delays are left to the
+    /// queries of their areas: the totals do not say where a point is.
+    async fn global_stats(&self, days: u32, before: Option<DateTime<Utc>>) -> StoreResult<GlobalStats> {
+        self.inner.global_stats(days, self.delay.before(before, Utc::now())).await
+    }
+
+    async fn tenant_stats(&self, tenant: &TenantScope, days: u32, before: Option<DateTime<Utc>>) -> StoreResult<GlobalStats> {
+        self.inner.tenant_stats(tenant, days, self.delay.before(before, Utc::now())).await
+    }
+
+    async fn live_stats(&self, days: u32) -> StoreResult<GlobalStats> {
+        self.inner.live_stats(days).await
+    }
+
+    async fn tenants(&self) -> StoreResult<Vec<String>> {
+        self.inner.tenants().await
+    }
+}
+
+/// `lastSeen` and the totals only count published trips, so a device's recent movements do not
+/// show through its stats
+#[async_trait::async_trait]
+impl DeviceStore for Embargoed<dyn DeviceStore> {
+    async fn register_device(&self, device_id: &str, name: Option<&str>, tenant_id: Option<&str>) -> StoreResult<bool> {
+        self.inner.register_device(device_id, name, tenant_id).await
+    }
+
+    async fn find_devices(&self, filter: &DeviceFilter, limit: u64, offset: u64) -> StoreResult<Vec<DeviceStats>> {
+        self.inner.find_devices(&self.delay.apply_devices(filter), limit, offset).await
+    }
+
+    async fn count_devices(&self, filter: &DeviceFilter) -> StoreResult<u64> {
+        self.inner.count_devices(&self.delay.apply_devices(filter)).await
+    }
+}
+
+fn parse_secs(s: &str) -> Result<Duration, String> {
+    s.trim()
+        .parse::<i64>()
+        .ok()
+        .filter(|v| *v >= 0)
+        .map(Duration::seconds)
+        .ok_or_else(|| format!("'{}' is not a number of seconds", s.trim()))
+}
+
+fn parse_region(entry: &str) -> Result<(BBox, Duration), String> {
+    let (corners, secs) = entry.split_once('=').ok_or("expected lat1,lng1,lat2,lng2=secs")?;
+    let c: Vec<f64> = corners
+        .split(',')
+        .map(|v| v.trim().parse::<f64>().map_err(|_| format!("'{}' is not a coordinate", v.trim())))
+        .collect::<Result<_, _>>()?;
+    let [lat1, lng1, lat2, lng2] = c[..] else {
+        return Err("expected four coordinates".to_string());
+    };
+    Ok((BBox::from_corners(lat1, lng1, lat2, lng2), parse_secs(secs)?))
+}
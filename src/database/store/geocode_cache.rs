@@ -0,0 +1,42 @@
+use sea_orm::prelude::async_trait;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::{ActiveModelTrait, ColumnTrait, Condition, EntityTrait, QueryFilter};
+
+use super::{GeocodeCacheStore, SeaOrmPointStore, StoreResult};
+use crate::database::model::geocode_cache::{self, ActiveModel as GeocodeActiveModel, Entity as GeocodeCache, Model as GeocodeModel};
+
+/// Cells per lookup, well below the bind parameter limits of Postgres and SQLite
+const LOOKUP_CHUNK: usize = 500;
+
+#[async_trait::async_trait]
+impl GeocodeCacheStore for SeaOrmPointStore {
+    async fn find_places(&self, cells: &[(i32, i32)]) -> StoreResult<Vec<GeocodeModel>> {
+        let mut found = Vec::new();
+        for chunk in cells.chunks(LOOKUP_CHUNK) {
+            let any = chunk.iter().fold(Condition::any(), |any, &(lat, lng)| {
+                any.add(Condition::all().add(geocode_cache::Column::CellLat.eq(lat)).add(geocode_cache::Column::CellLng.eq(lng)))
+            });
+            found.extend(GeocodeCache::find().filter(any).all(&self.db).await?);
+        }
+        Ok(found)
+    }
+
+    async fn save_place(&self, place: GeocodeModel) -> StoreResult<()> {
+        let active: GeocodeActiveModel = place.into();
+        GeocodeCache::insert(active.reset_all())
+            .on_conflict(
+                OnConflict::columns([geocode_cache::Column::CellLat, geocode_cache::Column::CellLng])
+                    .update_columns([
+                        geocode_cache::Column::Street,
+                        geocode_cache::Column::District,
+                        geocode_cache::Column::City,
+                        geocode_cache::Column::Label,
+                        geocode_cache::Column::CreatedAt,
+                    ])
+                    .to_owned(),
+            )
+            .exec_without_returning(&self.db)
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+use chrono::Utc;
+use sea_orm::prelude::async_trait;
+use sea_orm::sea_query::{Func, Query};
+use sea_orm::{ActiveModelTrait, ColumnTrait, Condition, EntityTrait, QueryFilter, QueryOrder, QuerySelect, Set, TransactionTrait};
+use serde_json::json;
+
+use super::postgres::outside_lng;
+use super::{GeofenceEventFilter, GeofenceSettings, GeofenceStore, SeaOrmPointStore, StoreResult};
+use crate::database::model::geofence_events::{self, ActiveModel as GeofenceEventActiveModel, Entity as GeofenceEvents, Model as GeofenceEventModel};
+use crate::database::model::geofences::{self, ActiveModel as GeofenceActiveModel, Entity as Geofences, Model as GeofenceModel};
+
+fn polygon_json(settings: &GeofenceSettings) -> serde_json::Value {
+    settings.polygon.iter().map(|&(lat, lng)| json!({"lat": lat, "lng": lng})).collect()
+}
+
+#[async_trait::async_trait]
+impl GeofenceStore for SeaOrmPointStore {
+    async fn find_geofences(&self) -> StoreResult<Vec<GeofenceModel>> {
+        Ok(Geofences::find().order_by_asc(geofences::Column::Id).all(&self.db).await?)
+    }
+
+    async fn create_geofence(&self, settings: &GeofenceSettings, tenant_id: Option<String>) -> StoreResult<GeofenceModel> {
+        let now = Utc::now();
+        let active = GeofenceActiveModel {
+            name: Set(settings.name.clone()),
+            polygon: Set(polygon_json(settings)),
+            active: Set(settings.active),
+            tenant_id: Set(tenant_id),
+            created_at: Set(now),
+            updated_at: Set(now),
+            ..Default::default()
+        };
+        Ok(active.insert(&self.db).await?)
+    }
+
+    async fn update_geofence(&self, id: i64, settings: &GeofenceSettings) -> StoreResult<Option<GeofenceModel>> {
+        let Some(existing) = Geofences::find_by_id(id).one(&self.db).await? else {
+            return Ok(None);
+        };
+        let mut active: GeofenceActiveModel = existing.into();
+        active.name = Set(settings.name.clone());
+        active.polygon = Set(polygon_json(settings));
+        active.active = Set(settings.active);
+        active.updated_at = Set(Utc::now());
+        Ok(Some(active.update(&self.db).await?))
+    }
+
+    async fn delete_geofence(&self, id: i64) -> StoreResult<bool> {
+        let txn = self.db.begin().await?;
+        GeofenceEvents::delete_many().filter(geofence_events::Column::GeofenceId.eq(id)).exec(&txn).await?;
+        let deleted = Geofences::delete_by_id(id).exec(&txn).await?.rows_affected > 0;
+        txn.commit().await?;
+        Ok(deleted)
+    }
+
+    async fn record_geofence_event(&self, event: GeofenceEventModel) -> StoreResult<GeofenceEventModel> {
+        let active = GeofenceEventActiveModel {
+            geofence_id: Set(event.geofence_id),
+            randomized_id: Set(event.randomized_id),
+            point_id: Set(event.point_id),
+            kind: Set(event.kind),
+            lat: Set(event.lat),
+            lng: Set(event.lng),
+            timestamp: Set(event.timestamp),
+            created_at: Set(event.created_at),
+            ..Default::default()
+        };
+        Ok(active.insert(&self.db).await?)
+    }
+
+    async fn find_geofence_events(&self, filter: &GeofenceEventFilter, limit: u64) -> StoreResult<Vec<GeofenceEventModel>> {
+        let mut query = GeofenceEvents::find().filter(geofence_events::Column::GeofenceId.eq(filter.geofence_id));
+        if let Some(randomized_id) = filter.randomized_id {
+            query = query.filter(geofence_events::Column::RandomizedId.eq(randomized_id));
+        }
+        if let Some(since) = filter.since {
+            query = query.filter(geofence_events::Column::Timestamp.gte(since));
+        }
+        if let Some(until) = filter.until {
+            query = query.filter(geofence_events::Column::Timestamp.lte(until));
+        }
+        if let Some(after_id) = filter.after_id {
+            query = query.filter(geofence_events::Column::Id.gt(after_id));
+        }
+        for rule in &filter.embargo {
+            // Published when old enough or outside the rule's region
+            let mut published = Condition::any().add(geofence_events::Column::Timestamp.lte(rule.cutoff));
+            if let Some(b) = rule.region {
+                published = published
+                    .add(geofence_events::Column::Lat.lt(b.lat_min))
+                    .add(geofence_events::Column::Lat.gt(b.lat_max))
+                    .add(outside_lng(geofence_events::Column::Lng, &b));
+            }
+            query = query.filter(published);
+        }
+        Ok(query.order_by_asc(geofence_events::Column::Id).limit(limit).all(&self.db).await?)
+    }
+
+    async fn open_geofence_visits(&self, randomized_id: Option<i64>) -> StoreResult<Vec<(i64, i64)>> {
+        let mut latest = Query::select();
+        latest
+            .expr(Func::max(geofence_events::Column::Id.into_expr()))
+            .from(GeofenceEvents)
+            .group_by_columns([geofence_events::Column::GeofenceId, geofence_events::Column::RandomizedId]);
+        if let Some(randomized_id) = randomized_id {
+            latest.and_where(geofence_events::Column::RandomizedId.eq(randomized_id));
+        }
+        let open = GeofenceEvents::find()
+            .filter(geofence_events::Column::Id.in_subquery(latest))
+            .filter(geofence_events::Column::Kind.eq("entry"))
+            .all(&self.db)
+            .await?;
+        Ok(open.into_iter().map(|e| (e.geofence_id, e.randomized_id)).collect())
+    }
+}
@@ -0,0 +1,39 @@
+use chrono::{DateTime, Utc};
+use sea_orm::prelude::async_trait;
+use sea_orm::sea_query::Expr;
+use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, IntoActiveModel, QueryFilter};
+use uuid::Uuid;
+
+use super::{JobStore, SeaOrmPointStore, StoreResult};
+use crate::database::model::jobs::{self, ActiveModel as JobActiveModel, Entity as Jobs, Model as JobModel};
+
+#[async_trait::async_trait]
+impl JobStore for SeaOrmPointStore {
+    async fn create_job(&self, job: JobModel) -> StoreResult<()> {
+        Jobs::insert(JobActiveModel::from(job)).exec_without_returning(&self.db).await?;
+        Ok(())
+    }
+
+    async fn save_job(&self, job: JobModel) -> StoreResult<()> {
+        job.into_active_model().reset_all().update(&self.db).await?;
+        Ok(())
+    }
+
+    async fn find_job(&self, id: Uuid) -> StoreResult<Option<JobModel>> {
+        Ok(Jobs::find_by_id(id).one(&self.db).await?)
+    }
+
+    async fn fail_stale_jobs(&self, before: DateTime<Utc>, error: &str) -> StoreResult<u64> {
+        let now = Utc::now();
+        let result = Jobs::update_many()
+            .col_expr(jobs::Column::Status, Expr::value("failed"))
+            .col_expr(jobs::Column::Error, Expr::value(error))
+            .col_expr(jobs::Column::UpdatedAt, Expr::value(now))
+            .col_expr(jobs::Column::FinishedAt, Expr::value(now))
+            .filter(jobs::Column::Status.is_in(["queued", "running"]))
+            .filter(jobs::Column::UpdatedAt.lt(before))
+            .exec(&self.db)
+            .await?;
+        Ok(result.rows_affected)
+    }
+}
@@ -0,0 +1,770 @@
+mod postgres;
+mod clickhouse;
+mod dual;
+mod trips;
+mod rollup;
+mod breaker;
+mod embargo;
+mod quarantine;
+mod replica;
+mod devices;
+mod tile_stats;
+mod tenant;
+mod webhooks;
+mod geocode_cache;
+mod jobs;
+mod road_segments;
+mod geofences;
+mod alerts;
+
+pub use postgres::SeaOrmPointStore;
+pub use clickhouse::ClickHouseStore;
+pub use dual::AnalyticsSplitStore;
+pub use replica::ReplicaSplitStore;
+pub use breaker::{CircuitBreaker, GuardedStore};
+pub use embargo::{Embargoed, PublicationDelay};
+pub use quarantine::{Quarantine, TimestampWindow, WindowAction};
+pub use tenant::TenantScoped;
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use sea_orm::{sqlx, DatabaseConnection, DbErr, RuntimeErr};
+use serde::{Deserialize, Serialize};
+use sea_orm::prelude::async_trait;
+use std::env;
+use std::fmt;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::database::model::points::Model as PointModel;
+use crate::database::model::trips::Model as TripModel;
+use crate::database::model::matched_trips::Model as MatchedTripModel;
+use crate::database::model::quarantined_points::Model as QuarantinedModel;
+use crate::database::model::webhooks::Model as WebhookModel;
+use crate::database::model::geocode_cache::Model as GeocodeModel;
+use crate::database::model::jobs::Model as JobModel;
+use crate::database::model::road_segments::Model as RoadSegmentModel;
+use crate::database::model::geofences::Model as GeofenceModel;
+use crate::database::model::geofence_events::Model as GeofenceEventModel;
+use crate::database::model::alert_rules::Model as AlertRuleModel;
+use crate::database::model::alerts::Model as AlertModel;
+use crate::geo;
+
+/// Inclusive latitude/longitude bounds. `lng_min > lng_max` means the box crosses the
+/// antimeridian and covers `lng_min..=180` plus `-180..=lng_max`.
+#[derive(Debug, Clone, Copy)]
+pub struct BBox {
+    pub lat_min: f64,
+    pub lat_max: f64,
+    pub lng_min: f64,
+    pub lng_max: f64,
+}
+
+impl BBox {
+    /// Normalizes any two opposite corners into min/max bounds. Longitudes past ±180, as a
+    /// map panned across the antimeridian reports them (`170..190`), give a wrap-around box.
+    pub fn from_corners(lat1: f64, lng1: f64, lat2: f64, lng2: f64) -> Self {
+        let (lat_min, lat_max) = if lat1 <= lat2 { (lat1, lat2) } else { (lat2, lat1) };
+        let (lng_min, lng_max) = if lng1 <= lng2 { (lng1, lng2) } else { (lng2, lng1) };
+        let (lng_min, lng_max) = if lng_max - lng_min >= 360.0 {
+            (-180.0, 180.0)
+        } else {
+            (geo::wrap_lng(lng_min), geo::wrap_lng(lng_max))
+        };
+        Self { lat_min, lat_max, lng_min, lng_max }
+    }
+
+    pub fn crosses_antimeridian(&self) -> bool {
+        self.lng_min > self.lng_max
+    }
+
+    /// Width in degrees of longitude, across the antimeridian when the box wraps
+    pub fn lng_span(&self) -> f64 {
+        if self.crosses_antimeridian() { self.lng_max + 360.0 - self.lng_min } else { (self.lng_max - self.lng_min).max(0.0) }
+    }
+
+    /// The one or two plain `(min, max)` longitude ranges the box covers, for backends that
+    /// can only filter on `BETWEEN`
+    pub fn lng_ranges(&self) -> Vec<(f64, f64)> {
+        if self.crosses_antimeridian() {
+            vec![(self.lng_min, 180.0), (-180.0, self.lng_max)]
+        } else {
+            vec![(self.lng_min, self.lng_max)]
+        }
+    }
+
+    /// Whether the two boxes share any point
+    pub fn intersects(&self, other: &BBox) -> bool {
+        self.lat_min <= other.lat_max
+            && other.lat_min <= self.lat_max
+            && self.lng_ranges().iter().any(|(a_min, a_max)| other.lng_ranges().iter().any(|(b_min, b_max)| a_min <= b_max && b_min <= a_max))
+    }
+
+    /// `lng` shifted by 360 where needed so that it is measured continuously from `lng_min`
+    pub fn unwrap_lng(&self, lng: f64) -> f64 {
+        if self.crosses_antimeridian() && lng < self.lng_min { lng + 360.0 } else { lng }
+    }
+}
+
+/// Rows newer than `cutoff` are withheld, inside `region` or everywhere when it is None
+#[derive(Debug, Clone, Copy)]
+pub struct EmbargoRule {
+    pub region: Option<BBox>,
+    pub cutoff: DateTime<Utc>,
+}
+
+/// Rows a request may see when several deployments share the instance (see `tenant`)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TenantScope {
+    /// Rows stored without a tenant
+    Shared,
+    Tenant(String),
+}
+
+impl TenantScope {
+    /// Tenant that new rows are stored for
+    pub fn tenant_id(&self) -> Option<&str> {
+        match self {
+            TenantScope::Shared => None,
+            TenantScope::Tenant(t) => Some(t),
+        }
+    }
+}
+
+/// Row selection shared by every backend. Unset fields do not filter.
+#[derive(Debug, Clone, Default)]
+pub struct PointFilter {
+    pub bbox: Option<BBox>,
+    /// Timestamp lower bound (inclusive)
+    pub since: Option<DateTime<Utc>>,
+    /// Timestamp upper bound (inclusive)
+    pub until: Option<DateTime<Utc>>,
+    pub randomized_id: Option<i64>,
+    /// Only points of these trips
+    pub randomized_ids: Option<Vec<i64>>,
+    pub anomaly: Option<bool>,
+    /// Only points scored at least this anomalous, 0..1
+    pub min_anomaly_score: Option<f64>,
+    /// Only rows inserted before this id
+    pub before_id: Option<i64>,
+    pub client_uuid: Option<Uuid>,
+    /// Publication delay rules, all of which must let a row through (see `PublicationDelay`)
+    pub embargo: Vec<EmbargoRule>,
+    pub tenant: Option<TenantScope>,
+}
+
+impl PointFilter {
+    /// Lookups that must see rows as soon as the primary has them, so stores that serve area
+    /// scans from a copy (ClickHouse, a read replica) keep them on the primary
+    pub fn is_primary_lookup(&self) -> bool {
+        self.randomized_id.is_some() || self.randomized_ids.is_some() || self.before_id.is_some() || self.client_uuid.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub enum PointOrder {
+    #[default]
+    TimestampAsc,
+    TimestampDesc,
+    /// Grouped by randomized_id, then by timestamp ascending
+    TripThenTimestamp,
+    IdAsc,
+    IdDesc,
+}
+
+/// Width of a timeline bucket; weeks start on Monday, all in UTC
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeBucket {
+    Hour,
+    Day,
+    Week,
+}
+
+impl TimeBucket {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "hour" => Some(TimeBucket::Hour),
+            "day" => Some(TimeBucket::Day),
+            "week" => Some(TimeBucket::Week),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TimeBucket::Hour => "hour",
+            TimeBucket::Day => "day",
+            TimeBucket::Week => "week",
+        }
+    }
+}
+
+/// Point volume of one timeline bucket
+#[derive(Debug, Clone)]
+pub struct TimelineRow {
+    /// Bucket start
+    pub bucket: DateTime<Utc>,
+    pub points: u64,
+    /// Distinct randomized_id values
+    pub trips: u64,
+}
+
+/// Tiles the database groups points into: tile (row, col) covers latitudes from
+/// `lat0 + row * height` and longitudes from `lng0 + col * width`, unbounded in every direction
+#[derive(Debug, Clone, Copy)]
+pub struct TileGrid {
+    pub lat0: f64,
+    pub lng0: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+/// Local times of day, from `start` up to `end` seconds after midnight at `offset` seconds east
+/// of UTC; wraps past midnight unless `start` is before `end`
+#[derive(Debug, Clone, Copy)]
+pub struct DayWindow {
+    pub start: u32,
+    pub end: u32,
+    pub offset: i32,
+}
+
+/// Points of one tile of a `TileGrid`
+#[derive(Debug, Clone)]
+pub struct TileSpeeds {
+    pub row: i64,
+    pub col: i64,
+    pub points: u64,
+    pub speed_sum: f64,
+    /// Mean position of the points
+    pub lat: f64,
+    pub lng: f64,
+}
+
+/// Points ingested on one UTC day
+#[derive(Debug, Clone)]
+pub struct DailyIngest {
+    pub day: NaiveDate,
+    pub points: u64,
+}
+
+/// Whole-dataset totals from the `dataset_stats` rollup
+#[derive(Debug, Clone, Default)]
+pub struct GlobalStats {
+    pub total_points: u64,
+    pub total_trips: u64,
+    /// Bounds of every stored point, None when there are none
+    pub bbox: Option<BBox>,
+    pub first_ts: Option<DateTime<Utc>>,
+    pub last_ts: Option<DateTime<Utc>>,
+    /// When the rollup last changed; None before the first point
+    pub updated_at: Option<DateTime<Utc>>,
+    /// Days with ingested points only, oldest first
+    pub daily_ingest: Vec<DailyIngest>,
+}
+
+/// What the classifier decided about one point
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AnomalyVerdict {
+    pub anomaly: bool,
+    /// 0..1; 0.5 is where a rule starts to flag
+    pub score: f64,
+    /// Rule or classifier behind the score; None when nothing stood out
+    pub reason: Option<&'static str>,
+}
+
+/// A point to be stored; id and a missing timestamp are assigned by the backend.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewPointRecord {
+    pub randomized_id: i64,
+    pub lat: f64,
+    pub lng: f64,
+    pub alt: f64,
+    pub spd: f64,
+    pub azm: f64,
+    pub timestamp: Option<DateTime<Utc>>,
+    pub anomaly: Option<bool>,
+    pub client_uuid: Option<Uuid>,
+    /// Tracker that sent the point; recorded on its trip. Absent in WAL and journal entries
+    /// written before devices were tracked.
+    #[serde(default)]
+    pub device_id: Option<String>,
+    /// Tenant the point is stored for (see `tenant`); set by ingestion from the request
+    #[serde(default)]
+    pub tenant_id: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum StoreError {
+    Db(DbErr),
+    Backend(String),
+    /// The circuit breaker is open; the database was not contacted
+    Unavailable,
+}
+
+impl StoreError {
+    /// True when the database could not be reached at all, as opposed to rejecting the query
+    pub fn is_connectivity(&self) -> bool {
+        match self {
+            StoreError::Unavailable => true,
+            StoreError::Db(DbErr::Conn(_) | DbErr::ConnectionAcquire(_)) => true,
+            StoreError::Db(DbErr::Query(RuntimeErr::SqlxError(e)) | DbErr::Exec(RuntimeErr::SqlxError(e))) => {
+                matches!(e, sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Db(e) => write!(f, "database error: {}", e),
+            StoreError::Backend(msg) => write!(f, "storage backend error: {}", msg),
+            StoreError::Unavailable => write!(f, "database unavailable (circuit breaker open)"),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<DbErr> for StoreError {
+    fn from(e: DbErr) -> Self {
+        StoreError::Db(e)
+    }
+}
+
+pub type StoreResult<T> = Result<T, StoreError>;
+
+/// Storage backend for points. Handlers receive it as `web::Data<dyn PointStore>`,
+/// so ingestion and analytics never talk to a concrete database directly.
+#[async_trait::async_trait]
+pub trait PointStore: Send + Sync {
+    /// Short backend name for logs
+    fn name(&self) -> &'static str;
+
+    async fn insert(&self, point: NewPointRecord) -> StoreResult<PointModel>;
+
+    /// Stores several points at once, returned in the same order. Backends without a bulk
+    /// path insert them one at a time, so a failure may leave the first ones stored.
+    async fn insert_many(&self, points: Vec<NewPointRecord>) -> StoreResult<Vec<PointModel>> {
+        let mut stored = Vec::with_capacity(points.len());
+        for point in points {
+            stored.push(self.insert(point).await?);
+        }
+        Ok(stored)
+    }
+
+    async fn find(&self, filter: &PointFilter, order: PointOrder, limit: Option<u64>) -> StoreResult<Vec<PointModel>> {
+        self.find_page(filter, order, limit, 0).await
+    }
+
+    /// Like `find`, skipping the first `offset` matching rows
+    async fn find_page(&self, filter: &PointFilter, order: PointOrder, limit: Option<u64>, offset: u64) -> StoreResult<Vec<PointModel>>;
+
+    /// Up to `limit` rows matching `filter` with id greater than `after_id`, ascending by id.
+    /// Keyset pagination for exports that walk the whole table.
+    async fn find_after(&self, filter: &PointFilter, after_id: i64, limit: u64) -> StoreResult<Vec<PointModel>>;
+
+    /// Number of rows matching `filter`
+    async fn count(&self, filter: &PointFilter) -> StoreResult<u64>;
+
+    /// Points and distinct trips per time bucket, aggregated by the database; empty buckets
+    /// and rows without a timestamp are left out
+    async fn timeline(&self, filter: &PointFilter, bucket: TimeBucket) -> StoreResult<Vec<TimelineRow>>;
+
+    /// Points and speed sums per tile of `grid` among the points within `window`, aggregated by
+    /// the database; empty tiles and rows without a timestamp are left out
+    async fn tile_speeds(&self, filter: &PointFilter, grid: TileGrid, window: DayWindow) -> StoreResult<Vec<TileSpeeds>>;
+
+    /// Stores the classifier's verdict on one point; returns false when the row does not exist
+    async fn set_anomaly(&self, id: i64, verdict: AnomalyVerdict) -> StoreResult<bool>;
+
+    /// Records an operator's decision on a trip and who made it, which the trip and the
+    /// anomaly filter follow over the classifier's flags on its points; returns the number of
+    /// points of the trip, 0 when there is none
+    async fn review_trip(&self, randomized_id: i64, anomaly: bool, reviewed_by: &str) -> StoreResult<u64>;
+
+    /// Removes every row matching `filter`; returns the number of rows deleted
+    async fn delete(&self, filter: &PointFilter) -> StoreResult<u64>;
+}
+
+/// Selection of trip summaries. Unset fields do not filter.
+#[derive(Debug, Clone, Default)]
+pub struct TripFilter {
+    /// Trips still running at or after this time
+    pub since: Option<DateTime<Utc>>,
+    /// Trips started at or before this time
+    pub until: Option<DateTime<Utc>>,
+    pub randomized_id: Option<i64>,
+    /// Only these trips
+    pub randomized_ids: Option<Vec<i64>>,
+    /// Only trips with a greater randomized_id, for keyset pages in `TripOrder::IdAsc`
+    pub after_randomized_id: Option<i64>,
+    pub anomaly: Option<bool>,
+    pub min_distance_m: Option<f64>,
+    /// Share of the trip's points flagged anomalous, 0..1
+    pub min_anomaly_ratio: Option<f64>,
+    pub min_anomaly_points: Option<i64>,
+    pub device_id: Option<String>,
+    /// Publication delay rules, matched against the trip's end time and start/end points
+    pub embargo: Vec<EmbargoRule>,
+    pub tenant: Option<TenantScope>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub enum TripOrder {
+    StartAsc,
+    #[default]
+    StartDesc,
+    DistanceAsc,
+    DistanceDesc,
+    DurationAsc,
+    DurationDesc,
+    MaxSpeedAsc,
+    MaxSpeedDesc,
+    /// By randomized_id alone, for exports
+    IdAsc,
+}
+
+/// Read side of the summary tables (`trips`, `dataset_stats`, `ingest_daily`), which the
+/// primary store maintains on insert. Always served by the primary database, whatever
+/// ANALYTICS_BACKEND says.
+#[async_trait::async_trait]
+pub trait TripStore: Send + Sync {
+    async fn find_trips(&self, filter: &TripFilter, order: TripOrder, limit: u64, offset: u64) -> StoreResult<Vec<TripModel>>;
+
+    async fn count_trips(&self, filter: &TripFilter) -> StoreResult<u64>;
+
+    /// Trips idle since `ended_before` that were never map-matched or gained points since,
+    /// oldest first; trips whose last attempt failed wait until it says to retry them
+    async fn trips_to_match(&self, ended_before: DateTime<Utc>, limit: u64) -> StoreResult<Vec<TripModel>>;
+
+    /// Inserts or replaces the matched route of a trip and forgets earlier failures
+    async fn save_matched(&self, matched: MatchedTripModel) -> StoreResult<()>;
+
+    /// Records a failed attempt to match a trip, which `trips_to_match` then skips for
+    /// `backoff`, doubled for each failure before it up to a day; returns the failures in a row
+    async fn record_match_failure(&self, randomized_id: i64, error: &str, backoff: Duration) -> StoreResult<u32>;
+
+    async fn find_matched(&self, randomized_id: i64) -> StoreResult<Option<MatchedTripModel>>;
+
+    /// Dataset totals and ingest per day over the last `days` days, read from the rollup. With
+    /// `before`, only of the points timestamped before it, which the rollup cannot tell apart:
+    /// computed like `tenant_stats`.
+    async fn global_stats(&self, days: u32, before: Option<DateTime<Utc>>) -> StoreResult<GlobalStats>;
+
+    /// Like `global_stats` for the rows of one tenant, which the rollup does not break down:
+    /// computed from the trips and points, ingest counted by the day of the point timestamps
+    async fn tenant_stats(&self, tenant: &TenantScope, days: u32, before: Option<DateTime<Utc>>) -> StoreResult<GlobalStats>;
+
+    /// `global_stats` of every row, unpublished ones included, for `/api/admin`
+    async fn live_stats(&self, days: u32) -> StoreResult<GlobalStats>;
+
+    /// Names of the tenants that have trips, sorted
+    async fn tenants(&self) -> StoreResult<Vec<String>>;
+}
+
+/// Side table of points whose timestamps fell outside the acceptance window (see
+/// `TimestampWindow`). Always served by the primary database.
+#[async_trait::async_trait]
+pub trait QuarantineStore: Send + Sync {
+    async fn quarantine(&self, point: &NewPointRecord, reason: &str) -> StoreResult<QuarantinedModel>;
+
+    /// Newest first
+    async fn find_quarantined(&self, limit: u64, offset: u64) -> StoreResult<Vec<QuarantinedModel>>;
+
+    async fn count_quarantined(&self) -> StoreResult<u64>;
+
+    /// Drops a reviewed point; returns false when it does not exist
+    async fn discard_quarantined(&self, id: i64) -> StoreResult<bool>;
+}
+
+/// Selection of devices. Unset fields do not filter.
+#[derive(Debug, Clone, Default)]
+pub struct DeviceFilter {
+    pub device_id: Option<String>,
+    /// Devices whose last trip ended before this time, or that never reported one
+    pub silent_since: Option<DateTime<Utc>>,
+    /// Publication delay rules; trips they hold back do not count (see `PublicationDelay`)
+    pub embargo: Vec<EmbargoRule>,
+    pub tenant: Option<TenantScope>,
+}
+
+/// A registered device with totals over the trips recorded for it by its tenant
+#[derive(Debug, Clone)]
+pub struct DeviceStats {
+    pub device_id: String,
+    pub name: Option<String>,
+    pub registered_at: DateTime<Utc>,
+    /// End of the device's latest trip, None before its first
+    pub last_seen: Option<DateTime<Utc>>,
+    pub trips: u64,
+    pub points: u64,
+    pub distance_m: f64,
+}
+
+/// Registry of trackers. Trips name their device (see `NewPointRecord::device_id`), so the
+/// totals follow the trip summaries. Always served by the primary database.
+#[async_trait::async_trait]
+pub trait DeviceStore: Send + Sync {
+    /// Adds the device for `tenant_id`, or renames it when `name` is given and the tenant has
+    /// it already; returns true when it was new. Tenants have device ids of their own.
+    async fn register_device(&self, device_id: &str, name: Option<&str>, tenant_id: Option<&str>) -> StoreResult<bool>;
+
+    /// Ordered by device id
+    async fn find_devices(&self, filter: &DeviceFilter, limit: u64, offset: u64) -> StoreResult<Vec<DeviceStats>>;
+
+    async fn count_devices(&self, filter: &DeviceFilter) -> StoreResult<u64>;
+}
+
+/// A webhook as the admin API creates or replaces it
+#[derive(Debug, Clone)]
+pub struct WebhookSettings {
+    pub url: String,
+    pub events: Vec<String>,
+    pub secret: Option<String>,
+    pub enabled: bool,
+}
+
+/// Outgoing webhooks (`webhooks`), read into memory by `Webhooks`. Always served by the
+/// primary database.
+#[async_trait::async_trait]
+pub trait WebhookStore: Send + Sync {
+    /// Oldest first
+    async fn find_webhooks(&self) -> StoreResult<Vec<WebhookModel>>;
+
+    async fn create_webhook(&self, settings: &WebhookSettings) -> StoreResult<WebhookModel>;
+
+    /// Replaces the settings of a webhook; None when it does not exist
+    async fn update_webhook(&self, id: i64, settings: &WebhookSettings) -> StoreResult<Option<WebhookModel>>;
+
+    /// Returns false when the webhook does not exist
+    async fn delete_webhook(&self, id: i64) -> StoreResult<bool>;
+}
+
+/// Reverse geocoding answers (`geocode_cache`), kept for good since streets rarely move.
+/// Always served by the primary database.
+#[async_trait::async_trait]
+pub trait GeocodeCacheStore: Send + Sync {
+    /// Cached answers for `cells` (`cell_lat`, `cell_lng`); cells never asked about are missing
+    async fn find_places(&self, cells: &[(i32, i32)]) -> StoreResult<Vec<GeocodeModel>>;
+
+    /// Stores an answer, replacing any earlier one for the cell
+    async fn save_place(&self, place: GeocodeModel) -> StoreResult<()>;
+}
+
+/// Background jobs (`jobs`), so that any instance can report on a job whichever one runs it.
+/// Always served by the primary database.
+#[async_trait::async_trait]
+pub trait JobStore: Send + Sync {
+    async fn create_job(&self, job: JobModel) -> StoreResult<()>;
+
+    /// Writes the progress or outcome of a job
+    async fn save_job(&self, job: JobModel) -> StoreResult<()>;
+
+    async fn find_job(&self, id: Uuid) -> StoreResult<Option<JobModel>>;
+
+    /// Fails queued and running jobs not updated since `before`, left behind by a stopped
+    /// instance; returns how many
+    async fn fail_stale_jobs(&self, before: DateTime<Utc>, error: &str) -> StoreResult<u64>;
+}
+
+/// Speed limits of the road network (`road_segments`), the reference the speeding checks of
+/// `/api/speedmap/violations` compare points with. Always served by the primary database.
+#[async_trait::async_trait]
+pub trait RoadSegmentStore: Send + Sync {
+    /// Replaces every segment with `segments` (their ids are ignored); returns how many were stored
+    async fn replace_road_segments(&self, segments: Vec<RoadSegmentModel>) -> StoreResult<u64>;
+
+    /// Segments with at least a part of their bounds inside `bbox`
+    async fn road_segments_in(&self, bbox: &BBox) -> StoreResult<Vec<RoadSegmentModel>>;
+}
+
+/// A geofence as `/api/geofences` creates or replaces it
+#[derive(Debug, Clone)]
+pub struct GeofenceSettings {
+    pub name: String,
+    /// Vertices as (lat, lng)
+    pub polygon: Vec<(f64, f64)>,
+    pub active: bool,
+}
+
+/// Selection of geofence events. Unset fields do not filter.
+#[derive(Debug, Clone, Default)]
+pub struct GeofenceEventFilter {
+    pub geofence_id: i64,
+    pub randomized_id: Option<i64>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    /// Only events with a greater id, for paging
+    pub after_id: Option<i64>,
+    /// Publication delay rules, judged on the event's timestamp and position
+    pub embargo: Vec<EmbargoRule>,
+}
+
+/// Geofences (`geofences`), read into memory by `Geofences`, and the entries and exits recorded
+/// for them (`geofence_events`). Always served by the primary database.
+#[async_trait::async_trait]
+pub trait GeofenceStore: Send + Sync {
+    /// Every geofence of every tenant, oldest first
+    async fn find_geofences(&self) -> StoreResult<Vec<GeofenceModel>>;
+
+    async fn create_geofence(&self, settings: &GeofenceSettings, tenant_id: Option<String>) -> StoreResult<GeofenceModel>;
+
+    /// Replaces the settings of a geofence; None when it does not exist
+    async fn update_geofence(&self, id: i64, settings: &GeofenceSettings) -> StoreResult<Option<GeofenceModel>>;
+
+    /// Deletes the geofence with its events; returns false when it does not exist
+    async fn delete_geofence(&self, id: i64) -> StoreResult<bool>;
+
+    /// Stores an event (its id is ignored) and returns it as stored
+    async fn record_geofence_event(&self, event: GeofenceEventModel) -> StoreResult<GeofenceEventModel>;
+
+    /// Oldest first
+    async fn find_geofence_events(&self, filter: &GeofenceEventFilter, limit: u64) -> StoreResult<Vec<GeofenceEventModel>>;
+
+    /// (geofence_id, randomized_id) of the trips whose latest event is an entry, those still
+    /// inside as far as the events tell; of one trip, or of every trip for None
+    async fn open_geofence_visits(&self, randomized_id: Option<i64>) -> StoreResult<Vec<(i64, i64)>>;
+}
+
+/// An alert rule as `/api/alerts/rules` creates or replaces it
+#[derive(Debug, Clone)]
+pub struct AlertRuleSettings {
+    pub name: String,
+    pub kind: String,
+    pub params: serde_json::Value,
+    pub enabled: bool,
+}
+
+/// Selection of alerts. Unset fields do not filter.
+#[derive(Debug, Clone, Default)]
+pub struct AlertFilter {
+    pub rule_id: Option<i64>,
+    pub kind: Option<String>,
+    pub randomized_id: Option<i64>,
+    pub device_id: Option<String>,
+    /// Bounds of the alert timestamp, inclusive
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    /// Only alerts with a smaller id, for paging
+    pub before_id: Option<i64>,
+    /// Publication delay rules, judged on the alert's timestamp and position; alerts without
+    /// a position wait for every regional delay
+    pub embargo: Vec<EmbargoRule>,
+    pub tenant: Option<TenantScope>,
+}
+
+/// Alert rules (`alert_rules`), read into memory by `Alerts`, and the alerts they raised
+/// (`alerts`). Always served by the primary database.
+#[async_trait::async_trait]
+pub trait AlertStore: Send + Sync {
+    /// Every rule of every tenant, oldest first
+    async fn find_alert_rules(&self) -> StoreResult<Vec<AlertRuleModel>>;
+
+    async fn create_alert_rule(&self, settings: &AlertRuleSettings, tenant_id: Option<String>) -> StoreResult<AlertRuleModel>;
+
+    /// Replaces the settings of a rule; None when it does not exist
+    async fn update_alert_rule(&self, id: i64, settings: &AlertRuleSettings) -> StoreResult<Option<AlertRuleModel>>;
+
+    /// Returns false when the rule does not exist; its alerts are kept
+    async fn delete_alert_rule(&self, id: i64) -> StoreResult<bool>;
+
+    /// Stores an alert (its id is ignored) and returns it as stored
+    async fn record_alert(&self, alert: AlertModel) -> StoreResult<AlertModel>;
+
+    /// Newest first
+    async fn find_alerts(&self, filter: &AlertFilter, limit: u64) -> StoreResult<Vec<AlertModel>>;
+}
+
+/// Points and summed speeds of one rollup tile, over an hour or summed over several
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TileStat {
+    pub row: i32,
+    pub col: i32,
+    pub points: u64,
+    pub speed_sum: f64,
+}
+
+/// Points and summed speeds of one rollup tile in one hour
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TileHour {
+    pub hour: DateTime<Utc>,
+    pub points: u64,
+    pub speed_sum: f64,
+}
+
+/// Hours `[from, through)` one rollup grid holds; `resolution` is its tile side in micro-degrees
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RollupCoverage {
+    pub resolution: i32,
+    pub from: DateTime<Utc>,
+    pub through: DateTime<Utc>,
+    /// `from` reaches back to the oldest point
+    pub backfilled: bool,
+}
+
+/// Hourly per-tile rollups (`tile_stats`) that the map endpoints read instead of the points
+/// when a request lines up with them. Written by the rollup job; always served by the primary
+/// database.
+#[async_trait::async_trait]
+pub trait TileStatsStore: Send + Sync {
+    async fn rollup_coverage(&self) -> StoreResult<Vec<RollupCoverage>>;
+
+    /// Replaces what grid `resolution` holds for `hour` with `stats`
+    async fn replace_hour(&self, resolution: i32, hour: DateTime<Utc>, stats: Vec<TileStat>) -> StoreResult<()>;
+
+    /// Records the hours a grid holds and drops its stats outside them
+    async fn set_coverage(&self, coverage: RollupCoverage) -> StoreResult<()>;
+
+    /// Forgets a grid that is no longer configured
+    async fn drop_rollup(&self, resolution: i32) -> StoreResult<()>;
+
+    /// Stats of the tiles in rows `rows` and columns `cols` (both inclusive), summed over the
+    /// hours in `[since, until)`; tiles without points are left out
+    async fn sum_tile_stats(
+        &self,
+        resolution: i32,
+        rows: (i32, i32),
+        cols: (i32, i32),
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> StoreResult<Vec<TileStat>>;
+
+    /// Stats of the tile in row `row` and column `col` for each hour in `[since, until)`, in
+    /// time order; hours without points are left out
+    async fn tile_hours(&self, resolution: i32, row: i32, col: i32, since: DateTime<Utc>, until: DateTime<Utc>) -> StoreResult<Vec<TileHour>>;
+}
+
+/// Builds the configured store. ANALYTICS_BACKEND=clickhouse serves area scans from ClickHouse
+/// (see `ClickHouseStore::from_env`); ANALYTICS_DUAL_WRITE=false skips mirroring inserts when
+/// ClickHouse is fed by CDC instead. With a `replica` connection (DATABASE_REPLICA_URL), area
+/// scans that would hit Postgres go to the replica (see `ReplicaSplitStore`). Every call passes
+/// through `breaker`, and reads are retried DB_QUERY_RETRIES times (default 2) when the
+/// connection drops under them; an unparsable DB_QUERY_RETRIES is an error.
+pub async fn from_env(db: DatabaseConnection, replica: Option<DatabaseConnection>, breaker: Arc<CircuitBreaker>) -> Result<Arc<dyn PointStore>, String> {
+    let retries = match env::var("DB_QUERY_RETRIES") {
+        Ok(v) if !v.trim().is_empty() => v
+            .trim()
+            .parse::<u32>()
+            .map_err(|_| format!("DB_QUERY_RETRIES must be a non-negative integer, got '{}'", v))?,
+        _ => 2,
+    };
+    let mut primary: Box<dyn PointStore> = Box::new(SeaOrmPointStore::new(db).detect_postgis().await);
+    if let Some(replica) = replica {
+        let replica = SeaOrmPointStore::new(replica).detect_postgis().await;
+        // A breaker of its own: a lost replica must not fail calls the primary can still serve
+        let replica = GuardedStore::new(Arc::new(replica), Arc::new(CircuitBreaker::from_env()), 0);
+        primary = Box::new(ReplicaSplitStore::new(primary, Box::new(replica)));
+    }
+    let inner: Arc<dyn PointStore> = match env::var("ANALYTICS_BACKEND").as_deref() {
+        Err(_) | Ok("postgres") => Arc::from(primary),
+        Ok("clickhouse") => {
+            let analytics = ClickHouseStore::from_env()?;
+            analytics.ensure_schema().await.map_err(|e| e.to_string())?;
+            let dual_write = env::var("ANALYTICS_DUAL_WRITE").map(|v| v != "false" && v != "0").unwrap_or(true);
+            Arc::new(AnalyticsSplitStore::new(primary, analytics, dual_write))
+        }
+        Ok(other) => return Err(format!("Unknown ANALYTICS_BACKEND '{}'; expected postgres or clickhouse", other)),
+    };
+    Ok(Arc::new(GuardedStore::new(inner, breaker, retries)))
+}
@@ -0,0 +1,356 @@
+use sea_orm::prelude::async_trait;
+use chrono::{DateTime, Utc};
+use sea_orm::sea_query::{Expr, Order, Query};
+use sea_orm::{ActiveModelTrait, ColumnTrait, Condition, ConnectionTrait, DatabaseBackend, DatabaseConnection, EntityTrait, FromQueryResult, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, Set, Statement, TransactionTrait};
+use log::{info, warn};
+use std::collections::HashMap;
+use std::env;
+
+use super::{rollup, trips, AnomalyVerdict, BBox, NewPointRecord, PointFilter, PointOrder, PointStore, DayWindow, StoreResult, TenantScope, TileGrid, TileSpeeds, TimeBucket, TimelineRow};
+use crate::database::model::points::{self, Entity as Points, Model as PointModel, ActiveModel as PointActiveModel};
+use crate::database::model::trips::{Column as TripColumn, Entity as Trips};
+
+/// Rows per INSERT statement of a bulk insert; ten bind parameters each stay well below
+/// PostgreSQL's limit of 65535 per statement
+const MAX_ROWS_PER_INSERT: usize = 5000;
+/// Same for SQLite, which takes at most 32766 per statement
+const MAX_ROWS_PER_INSERT_SQLITE: usize = 3000;
+
+/// Default backend: the `points` table through SeaORM. Also keeps the `trips` summaries and
+/// the dataset rollup current.
+#[derive(Clone)]
+pub struct SeaOrmPointStore {
+    pub(super) db: DatabaseConnection,
+    /// `points.geom` exists (PostGIS), so area filters can use its spatial index
+    postgis: bool,
+}
+
+impl SeaOrmPointStore {
+    pub fn new(db: DatabaseConnection) -> Self {
+        Self { db, postgis: false }
+    }
+
+    /// Switches area filters to PostGIS when the geometry column was created by the
+    /// migration; POSTGIS=off keeps the lat/lng comparisons regardless.
+    pub async fn detect_postgis(mut self) -> Self {
+        if self.db.get_database_backend() != DatabaseBackend::Postgres {
+            return self;
+        }
+        if env::var("POSTGIS").is_ok_and(|v| v == "off") {
+            info!("PostGIS disabled (POSTGIS=off)");
+            return self;
+        }
+        let probe = Statement::from_string(
+            self.db.get_database_backend(),
+            "SELECT 1 FROM information_schema.columns WHERE table_name = 'points' AND column_name = 'geom'",
+        );
+        match self.db.query_one(probe).await {
+            Ok(found) => self.postgis = found.is_some(),
+            Err(e) => warn!("PostGIS detection failed, using lat/lng filters: {}", e),
+        }
+        info!("Spatial filtering: {}", if self.postgis { "PostGIS" } else { "lat/lng ranges" });
+        self
+    }
+}
+
+/// Score of a point that arrived already flagged (or cleared) by its sender
+fn preset_score(anomaly: Option<bool>) -> Option<f64> {
+    anomaly.map(|a| if a { 1.0 } else { 0.0 })
+}
+
+fn apply_filter<Q: QueryFilter>(mut query: Q, filter: &PointFilter, postgis: bool) -> Q {
+    if let Some(b) = filter.bbox {
+        // A box across the antimeridian is matched as its two halves
+        let mut within = Condition::any();
+        for (lng_min, lng_max) in b.lng_ranges() {
+            within = if postgis {
+                within.add(Expr::cust_with_values(
+                    "\"geom\"::geometry && ST_MakeEnvelope($1, $2, $3, $4, 4326)",
+                    [lng_min, b.lat_min, lng_max, b.lat_max],
+                ))
+            } else {
+                within.add(
+                    Condition::all()
+                        .add(points::Column::Lat.between(b.lat_min, b.lat_max))
+                        .add(points::Column::Lng.between(lng_min, lng_max)),
+                )
+            };
+        }
+        query = query.filter(within);
+    }
+    if let Some(ts_start) = filter.since { query = query.filter(points::Column::Timestamp.gte(ts_start)); }
+    if let Some(ts_end) = filter.until { query = query.filter(points::Column::Timestamp.lte(ts_end)); }
+    if let Some(rid) = filter.randomized_id { query = query.filter(points::Column::RandomizedId.eq(rid)); }
+    if let Some(ids) = &filter.randomized_ids { query = query.filter(points::Column::RandomizedId.is_in(ids.iter().copied())); }
+    if let Some(a) = filter.anomaly { query = query.filter(flagged(a)); }
+    if let Some(s) = filter.min_anomaly_score { query = query.filter(points::Column::AnomalyScore.gte(s)); }
+    if let Some(id) = filter.before_id { query = query.filter(points::Column::Id.lt(id)); }
+    if let Some(uuid) = filter.client_uuid { query = query.filter(points::Column::ClientUuid.eq(uuid)); }
+    match &filter.tenant {
+        Some(TenantScope::Shared) => query = query.filter(points::Column::TenantId.is_null()),
+        Some(TenantScope::Tenant(t)) => query = query.filter(points::Column::TenantId.eq(t.as_str())),
+        None => {}
+    }
+    for rule in &filter.embargo {
+        // Published when old enough or outside the rule's region
+        let mut published = Condition::any().add(points::Column::Timestamp.lte(rule.cutoff));
+        if let Some(b) = rule.region {
+            published = published
+                .add(points::Column::Lat.lt(b.lat_min))
+                .add(points::Column::Lat.gt(b.lat_max))
+                .add(outside_lng(points::Column::Lng, &b));
+        }
+        query = query.filter(published);
+    }
+    query
+}
+
+/// Points flagged `a`: by the classifier, unless an operator decided otherwise on the trip
+fn flagged(a: bool) -> Condition {
+    let decided = |anomaly: bool| {
+        Query::select()
+            .column(TripColumn::RandomizedId)
+            .from(Trips)
+            .and_where(TripColumn::AnomalyReview.eq(anomaly))
+            .to_owned()
+    };
+    Condition::any()
+        .add(
+            Condition::all()
+                .add(points::Column::Anomaly.eq(Some(a)))
+                .add(points::Column::RandomizedId.not_in_subquery(decided(!a))),
+        )
+        .add(points::Column::RandomizedId.in_subquery(decided(a)))
+}
+
+/// `lng` lies outside the box's longitudes, which across the antimeridian is the gap between
+/// `lng_max` and `lng_min`
+pub(super) fn outside_lng<C: ColumnTrait>(lng: C, b: &BBox) -> Condition {
+    let outside = if b.crosses_antimeridian() { Condition::all() } else { Condition::any() };
+    outside.add(lng.lt(b.lng_min)).add(lng.gt(b.lng_max))
+}
+
+#[async_trait::async_trait]
+impl PointStore for SeaOrmPointStore {
+    fn name(&self) -> &'static str {
+        match self.db.get_database_backend() {
+            DatabaseBackend::Sqlite => "sqlite",
+            _ => "postgres",
+        }
+    }
+
+    async fn insert(&self, point: NewPointRecord) -> StoreResult<PointModel> {
+        let mut active = PointActiveModel {
+            randomized_id: Set(point.randomized_id),
+            lat: Set(point.lat),
+            lng: Set(point.lng),
+            alt: Set(point.alt),
+            spd: Set(point.spd),
+            azm: Set(point.azm),
+            client_uuid: Set(point.client_uuid),
+            tenant_id: Set(point.tenant_id),
+            ..Default::default()
+        };
+        // Only set timestamp if provided; otherwise, leave NotSet to use DB default
+        if let Some(ts) = point.timestamp {
+            active.timestamp = Set(Some(ts));
+        }
+        if point.anomaly.is_some() {
+            active.anomaly = Set(point.anomaly);
+            active.anomaly_score = Set(preset_score(point.anomaly));
+        }
+        let txn = self.db.begin().await?;
+        let mut model = active.insert(&txn).await?;
+        let devices = point.device_id.map(|d| HashMap::from([(model.randomized_id, d)])).unwrap_or_default();
+        let new_trips = trips::record_points(&txn, std::slice::from_mut(&mut model), &devices).await?;
+        rollup::record_point(&txn, &model, new_trips > 0).await?;
+        txn.commit().await?;
+        Ok(model)
+    }
+
+    async fn insert_many(&self, points: Vec<NewPointRecord>) -> StoreResult<Vec<PointModel>> {
+        if points.is_empty() {
+            return Ok(Vec::new());
+        }
+        // Multi-row VALUES need one column list for every row, so nothing is left to column
+        // defaults: a missing timestamp becomes the time of the insert, as the default would
+        let now = Utc::now();
+        // Device named per trip of the batch; RETURNING does not promise the input order
+        let mut devices: HashMap<i64, String> = HashMap::new();
+        for point in &points {
+            if let Some(device_id) = &point.device_id {
+                devices.entry(point.randomized_id).or_insert_with(|| device_id.clone());
+            }
+        }
+        let rows: Vec<PointActiveModel> = points
+            .into_iter()
+            .map(|point| PointActiveModel {
+                randomized_id: Set(point.randomized_id),
+                lat: Set(point.lat),
+                lng: Set(point.lng),
+                alt: Set(point.alt),
+                spd: Set(point.spd),
+                azm: Set(point.azm),
+                timestamp: Set(Some(point.timestamp.unwrap_or(now))),
+                anomaly: Set(point.anomaly),
+                anomaly_score: Set(preset_score(point.anomaly)),
+                client_uuid: Set(point.client_uuid),
+                tenant_id: Set(point.tenant_id),
+                ..Default::default()
+            })
+            .collect();
+        let txn = self.db.begin().await?;
+        let mut models = Vec::with_capacity(rows.len());
+        let rows_per_insert = match self.db.get_database_backend() {
+            DatabaseBackend::Sqlite => MAX_ROWS_PER_INSERT_SQLITE,
+            _ => MAX_ROWS_PER_INSERT,
+        };
+        for chunk in rows.chunks(rows_per_insert) {
+            // RETURNING does not promise the order of VALUES; the ids do follow it
+            let mut stored = Points::insert_many(chunk.to_vec()).exec_with_returning_many(&txn).await?;
+            stored.sort_by_key(|p| p.id);
+            models.extend(stored);
+        }
+        let new_trips = trips::record_points(&txn, &mut models, &devices).await?;
+        rollup::record_points(&txn, &models, new_trips).await?;
+        txn.commit().await?;
+        Ok(models)
+    }
+
+    async fn find_page(&self, filter: &PointFilter, order: PointOrder, limit: Option<u64>, offset: u64) -> StoreResult<Vec<PointModel>> {
+        let query = apply_filter(Points::find(), filter, self.postgis);
+        let query = match order {
+            PointOrder::TimestampAsc => query.order_by_asc(points::Column::Timestamp),
+            PointOrder::TimestampDesc => query.order_by_desc(points::Column::Timestamp),
+            PointOrder::TripThenTimestamp => query
+                .order_by_asc(points::Column::RandomizedId)
+                .order_by_asc(points::Column::Timestamp),
+            PointOrder::IdAsc => query.order_by_asc(points::Column::Id),
+            PointOrder::IdDesc => query.order_by_desc(points::Column::Id),
+        };
+        let query = if offset > 0 { query.offset(offset) } else { query };
+        Ok(query.limit(limit).all(&self.db).await?)
+    }
+
+    async fn find_after(&self, filter: &PointFilter, after_id: i64, limit: u64) -> StoreResult<Vec<PointModel>> {
+        Ok(apply_filter(Points::find(), filter, self.postgis)
+            .cursor_by(points::Column::Id)
+            .after(after_id)
+            .first(limit)
+            .all(&self.db)
+            .await?)
+    }
+
+    async fn count(&self, filter: &PointFilter) -> StoreResult<u64> {
+        Ok(apply_filter(Points::find(), filter, self.postgis).count(&self.db).await?)
+    }
+
+    async fn timeline(&self, filter: &PointFilter, bucket: TimeBucket) -> StoreResult<Vec<TimelineRow>> {
+        #[derive(FromQueryResult)]
+        struct Row { bucket: DateTime<Utc>, points: i64, trips: i64 }
+        let start = match self.db.get_database_backend() {
+            // Same instants as date_trunc, written the way the driver writes timestamps
+            DatabaseBackend::Sqlite => Expr::cust(match bucket {
+                TimeBucket::Hour => "strftime('%Y-%m-%dT%H:00:00+00:00', \"timestamp\")",
+                TimeBucket::Day => "strftime('%Y-%m-%dT00:00:00+00:00', \"timestamp\")",
+                TimeBucket::Week => "strftime('%Y-%m-%dT00:00:00+00:00', \"timestamp\", '-6 days', 'weekday 1')",
+            }),
+            _ => Expr::cust(format!("date_trunc('{}', \"timestamp\", 'UTC')", bucket.as_str())),
+        };
+        let rows = apply_filter(Points::find(), filter, self.postgis)
+            .filter(points::Column::Timestamp.is_not_null())
+            .select_only()
+            .column_as(start.clone(), "bucket")
+            .column_as(Expr::cust("COUNT(*)"), "points")
+            .column_as(Expr::cust("COUNT(DISTINCT \"randomized_id\")"), "trips")
+            .group_by(start.clone())
+            .order_by(start, Order::Asc)
+            .into_model::<Row>()
+            .all(&self.db)
+            .await?;
+        Ok(rows
+            .into_iter()
+            .map(|r| TimelineRow { bucket: r.bucket, points: r.points as u64, trips: r.trips as u64 })
+            .collect())
+    }
+
+    async fn tile_speeds(&self, filter: &PointFilter, grid: TileGrid, window: DayWindow) -> StoreResult<Vec<TileSpeeds>> {
+        #[derive(FromQueryResult)]
+        struct Row { tile_row: i64, tile_col: i64, points: i64, speed_sum: f64, mean_lat: f64, mean_lng: f64 }
+        let sqlite = self.db.get_database_backend() == DatabaseBackend::Sqlite;
+        // Written out rather than bound, so GROUP BY repeats the selected expressions exactly
+        let tile = |column: &str, origin: f64, size: f64| {
+            let x = format!("((\"{}\" - ({})) / {})", column, origin, size);
+            // SQLite has FLOOR only when built with its math functions
+            if sqlite { format!("(CAST({x} AS INTEGER) - ({x} < CAST({x} AS INTEGER)))") } else { format!("CAST(FLOOR({x}) AS BIGINT)") }
+        };
+        let epoch = if sqlite { "CAST(strftime('%s', \"timestamp\") AS INTEGER)" } else { "CAST(FLOOR(EXTRACT(EPOCH FROM \"timestamp\")) AS BIGINT)" };
+        let t = format!("((({} + {}) % 86400 + 86400) % 86400)", epoch, window.offset);
+        let within = if window.start < window.end {
+            format!("{t} >= {} AND {t} < {}", window.start, window.end)
+        } else {
+            format!("({t} >= {} OR {t} < {})", window.start, window.end)
+        };
+        let (row, col) = (tile("lat", grid.lat0, grid.height), tile("lng", grid.lng0, grid.width));
+        let rows = apply_filter(Points::find(), filter, self.postgis)
+            .filter(points::Column::Timestamp.is_not_null())
+            .filter(Expr::cust(within))
+            .select_only()
+            .column_as(Expr::cust(row.clone()), "tile_row")
+            .column_as(Expr::cust(col.clone()), "tile_col")
+            .column_as(Expr::cust("COUNT(*)"), "points")
+            .column_as(Expr::cust("SUM(\"spd\")"), "speed_sum")
+            .column_as(Expr::cust("AVG(\"lat\")"), "mean_lat")
+            .column_as(Expr::cust("AVG(\"lng\")"), "mean_lng")
+            .group_by(Expr::cust(row))
+            .group_by(Expr::cust(col))
+            .into_model::<Row>()
+            .all(&self.db)
+            .await?;
+        Ok(rows
+            .into_iter()
+            .map(|r| TileSpeeds { row: r.tile_row, col: r.tile_col, points: r.points as u64, speed_sum: r.speed_sum, lat: r.mean_lat, lng: r.mean_lng })
+            .collect())
+    }
+
+    async fn set_anomaly(&self, id: i64, verdict: AnomalyVerdict) -> StoreResult<bool> {
+        let res = Points::update_many()
+            .col_expr(points::Column::Anomaly, Expr::value(verdict.anomaly))
+            .col_expr(points::Column::AnomalyScore, Expr::value(verdict.score))
+            .col_expr(points::Column::AnomalyReason, Expr::value(verdict.reason))
+            .filter(points::Column::Id.eq(id))
+            .exec(&self.db)
+            .await?;
+        if res.rows_affected > 0 {
+            trips::refresh_anomaly(&self.db, id).await?;
+        }
+        Ok(res.rows_affected > 0)
+    }
+
+    async fn review_trip(&self, randomized_id: i64, anomaly: bool, reviewed_by: &str) -> StoreResult<u64> {
+        if !trips::record_review(&self.db, randomized_id, anomaly, reviewed_by).await? {
+            return Ok(0);
+        }
+        self.count(&PointFilter { randomized_id: Some(randomized_id), ..Default::default() }).await
+    }
+
+    async fn delete(&self, filter: &PointFilter) -> StoreResult<u64> {
+        let txn = self.db.begin().await?;
+        let affected: Vec<i64> = apply_filter(Points::find(), filter, self.postgis)
+            .select_only()
+            .column(points::Column::RandomizedId)
+            .distinct()
+            .into_tuple()
+            .all(&txn)
+            .await?;
+        let res = apply_filter(Points::delete_many(), filter, self.postgis).exec(&txn).await?;
+        for rid in affected {
+            trips::rebuild(&txn, rid).await?;
+        }
+        if res.rows_affected > 0 {
+            rollup::rebuild(&txn).await?;
+        }
+        txn.commit().await?;
+        Ok(res.rows_affected)
+    }
+}
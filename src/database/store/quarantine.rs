@@ -0,0 +1,138 @@
+use chrono::{DateTime, Duration, Utc};
+use log::info;
+use sea_orm::prelude::async_trait;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, Set};
+use std::env;
+use std::sync::Arc;
+
+use super::{NewPointRecord, QuarantineStore, SeaOrmPointStore, StoreResult};
+use crate::database::model::quarantined_points::{self, ActiveModel as QuarantinedActiveModel, Entity as QuarantinedPoints, Model as QuarantinedModel};
+
+/// What ingestion does with a point outside the window
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WindowAction {
+    /// The whole request fails with 400
+    Reject,
+    /// The point goes to `quarantined_points` for review instead of `points`
+    Quarantine,
+}
+
+/// Acceptance window for device timestamps. POINTS_MAX_PAST_DAYS and POINTS_MAX_FUTURE_MINUTES
+/// bound how far from the server clock a timestamp may be (each unbounded when unset);
+/// POINTS_TIMESTAMP_ACTION is `quarantine` (default) or `reject`. Built once in `main.rs`.
+#[derive(Debug, Clone, Copy)]
+pub struct TimestampWindow {
+    max_past: Option<Duration>,
+    max_future: Option<Duration>,
+    pub action: WindowAction,
+}
+
+impl TimestampWindow {
+    pub fn from_env() -> Result<Self, String> {
+        let read = |name: &str| -> Result<Option<i64>, String> {
+            match env::var(name) {
+                Ok(v) if !v.trim().is_empty() => v
+                    .trim()
+                    .parse::<i64>()
+                    .ok()
+                    .filter(|n| *n >= 0)
+                    .map(Some)
+                    .ok_or_else(|| format!("{} must be a non-negative integer, got '{}'", name, v)),
+                _ => Ok(None),
+            }
+        };
+        let max_past = read("POINTS_MAX_PAST_DAYS")?.map(Duration::days);
+        let max_future = read("POINTS_MAX_FUTURE_MINUTES")?.map(Duration::minutes);
+        let action = match env::var("POINTS_TIMESTAMP_ACTION").as_deref() {
+            Err(_) | Ok("quarantine") => WindowAction::Quarantine,
+            Ok("reject") => WindowAction::Reject,
+            Ok(other) => return Err(format!("Unknown POINTS_TIMESTAMP_ACTION '{}'; expected quarantine or reject", other)),
+        };
+        if max_past.is_some() || max_future.is_some() {
+            info!("Timestamp window: past {:?}, future {:?}, action {:?}", max_past, max_future, action);
+        }
+        Ok(Self { max_past, max_future, action })
+    }
+
+    /// Why `ts` is outside the window, None when it is fine. Points without a timestamp get
+    /// the server time and always pass.
+    pub fn check(&self, ts: Option<DateTime<Utc>>, now: DateTime<Utc>) -> Option<String> {
+        let ts = ts?;
+        if let Some(d) = self.max_past
+            && ts < now - d
+        {
+            return Some(format!("timestamp {} is more than {} days in the past", ts.to_rfc3339(), d.num_days()));
+        }
+        if let Some(d) = self.max_future
+            && ts > now + d
+        {
+            return Some(format!("timestamp {} is more than {} minutes in the future", ts.to_rfc3339(), d.num_minutes()));
+        }
+        None
+    }
+}
+
+/// The window together with the table it sends points to, shared with the ingestion and
+/// admin handlers as one piece of app data
+pub struct Quarantine {
+    pub window: TimestampWindow,
+    pub store: Arc<dyn QuarantineStore>,
+}
+
+impl Quarantine {
+    pub fn new(window: TimestampWindow, store: Arc<dyn QuarantineStore>) -> Self {
+        Self { window, store }
+    }
+}
+
+#[async_trait::async_trait]
+impl QuarantineStore for SeaOrmPointStore {
+    /// A point whose client UUID is already quarantined, from a retried upload, gets the
+    /// existing row back
+    async fn quarantine(&self, point: &NewPointRecord, reason: &str) -> StoreResult<QuarantinedModel> {
+        let active = QuarantinedActiveModel {
+            randomized_id: Set(point.randomized_id),
+            lat: Set(point.lat),
+            lng: Set(point.lng),
+            alt: Set(point.alt),
+            spd: Set(point.spd),
+            azm: Set(point.azm),
+            timestamp: Set(point.timestamp.unwrap_or_else(Utc::now)),
+            client_uuid: Set(point.client_uuid),
+            reason: Set(reason.to_string()),
+            received_at: Set(Utc::now()),
+            ..Default::default()
+        };
+        let Some(uuid) = point.client_uuid else {
+            return Ok(active.insert(&self.db).await?);
+        };
+        QuarantinedPoints::insert(active)
+            .on_conflict(OnConflict::column(quarantined_points::Column::ClientUuid).do_nothing().to_owned())
+            .exec_without_returning(&self.db)
+            .await?;
+        let row = QuarantinedPoints::find()
+            .filter(quarantined_points::Column::ClientUuid.eq(uuid))
+            .one(&self.db)
+            .await?;
+        row.ok_or_else(|| sea_orm::DbErr::RecordNotFound(format!("quarantined point {}", uuid)).into())
+    }
+
+    async fn find_quarantined(&self, limit: u64, offset: u64) -> StoreResult<Vec<QuarantinedModel>> {
+        Ok(QuarantinedPoints::find()
+            .order_by_desc(quarantined_points::Column::ReceivedAt)
+            .order_by_desc(quarantined_points::Column::Id)
+            .limit(limit)
+            .offset(offset)
+            .all(&self.db)
+            .await?)
+    }
+
+    async fn count_quarantined(&self) -> StoreResult<u64> {
+        Ok(QuarantinedPoints::find().count(&self.db).await?)
+    }
+
+    async fn discard_quarantined(&self, id: i64) -> StoreResult<bool> {
+        Ok(QuarantinedPoints::delete_by_id(id).exec(&self.db).await?.rows_affected > 0)
+    }
+}
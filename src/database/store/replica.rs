@@ -0,0 +1,110 @@
+use log::warn;
+use sea_orm::prelude::async_trait;
+
+use super::{AnomalyVerdict, NewPointRecord, PointFilter, PointOrder, PointStore, StoreResult, DayWindow, TileGrid, TileSpeeds, TimeBucket, TimelineRow};
+use crate::database::model::points::Model as PointModel;
+
+/// Area scans behind the heatmap, traffic, speed and anomaly endpoints read from a Postgres
+/// read replica, so dashboards do not compete with ingestion for the primary. Writes and the
+/// per-trip and per-UUID lookups stay on the primary; a replica read that fails is repeated
+/// there, which the replica's own circuit breaker keeps quick while the replica is down.
+pub struct ReplicaSplitStore {
+    primary: Box<dyn PointStore>,
+    replica: Box<dyn PointStore>,
+}
+
+impl ReplicaSplitStore {
+    pub fn new(primary: Box<dyn PointStore>, replica: Box<dyn PointStore>) -> Self {
+        Self { primary, replica }
+    }
+}
+
+#[async_trait::async_trait]
+impl PointStore for ReplicaSplitStore {
+    fn name(&self) -> &'static str { "postgres+replica" }
+
+    async fn insert(&self, point: NewPointRecord) -> StoreResult<PointModel> {
+        self.primary.insert(point).await
+    }
+
+    async fn insert_many(&self, points: Vec<NewPointRecord>) -> StoreResult<Vec<PointModel>> {
+        self.primary.insert_many(points).await
+    }
+
+    async fn find_page(&self, filter: &PointFilter, order: PointOrder, limit: Option<u64>, offset: u64) -> StoreResult<Vec<PointModel>> {
+        if filter.is_primary_lookup() {
+            return self.primary.find_page(filter, order, limit, offset).await;
+        }
+        match self.replica.find_page(filter, order, limit, offset).await {
+            Ok(rows) => Ok(rows),
+            Err(e) => {
+                warn!("Replica read failed, falling back to the primary: {}", e);
+                self.primary.find_page(filter, order, limit, offset).await
+            }
+        }
+    }
+
+    async fn find_after(&self, filter: &PointFilter, after_id: i64, limit: u64) -> StoreResult<Vec<PointModel>> {
+        if filter.is_primary_lookup() {
+            return self.primary.find_after(filter, after_id, limit).await;
+        }
+        match self.replica.find_after(filter, after_id, limit).await {
+            Ok(rows) => Ok(rows),
+            Err(e) => {
+                warn!("Replica read failed, falling back to the primary: {}", e);
+                self.primary.find_after(filter, after_id, limit).await
+            }
+        }
+    }
+
+    async fn count(&self, filter: &PointFilter) -> StoreResult<u64> {
+        if filter.is_primary_lookup() {
+            return self.primary.count(filter).await;
+        }
+        match self.replica.count(filter).await {
+            Ok(n) => Ok(n),
+            Err(e) => {
+                warn!("Replica count failed, falling back to the primary: {}", e);
+                self.primary.count(filter).await
+            }
+        }
+    }
+
+    async fn timeline(&self, filter: &PointFilter, bucket: TimeBucket) -> StoreResult<Vec<TimelineRow>> {
+        if filter.is_primary_lookup() {
+            return self.primary.timeline(filter, bucket).await;
+        }
+        match self.replica.timeline(filter, bucket).await {
+            Ok(rows) => Ok(rows),
+            Err(e) => {
+                warn!("Replica timeline failed, falling back to the primary: {}", e);
+                self.primary.timeline(filter, bucket).await
+            }
+        }
+    }
+
+    async fn tile_speeds(&self, filter: &PointFilter, grid: TileGrid, window: DayWindow) -> StoreResult<Vec<TileSpeeds>> {
+        if filter.is_primary_lookup() {
+            return self.primary.tile_speeds(filter, grid, window).await;
+        }
+        match self.replica.tile_speeds(filter, grid, window).await {
+            Ok(rows) => Ok(rows),
+            Err(e) => {
+                warn!("Replica tile speeds failed, falling back to the primary: {}", e);
+                self.primary.tile_speeds(filter, grid, window).await
+            }
+        }
+    }
+
+    async fn set_anomaly(&self, id: i64, verdict: AnomalyVerdict) -> StoreResult<bool> {
+        self.primary.set_anomaly(id, verdict).await
+    }
+
+    async fn review_trip(&self, randomized_id: i64, anomaly: bool, reviewed_by: &str) -> StoreResult<u64> {
+        self.primary.review_trip(randomized_id, anomaly, reviewed_by).await
+    }
+
+    async fn delete(&self, filter: &PointFilter) -> StoreResult<u64> {
+        self.primary.delete(filter).await
+    }
+}
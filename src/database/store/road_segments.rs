@@ -0,0 +1,50 @@
+use sea_orm::prelude::async_trait;
+use sea_orm::{ColumnTrait, Condition, EntityTrait, QueryFilter, Set, TransactionTrait};
+
+use super::{BBox, RoadSegmentStore, SeaOrmPointStore, StoreResult};
+use crate::database::model::road_segments::{self, ActiveModel as RoadSegmentActiveModel, Entity as RoadSegments, Model as RoadSegmentModel};
+
+/// Rows per INSERT, well below the bind parameter limits of Postgres and SQLite
+const INSERT_CHUNK: usize = 1000;
+
+#[async_trait::async_trait]
+impl RoadSegmentStore for SeaOrmPointStore {
+    async fn replace_road_segments(&self, segments: Vec<RoadSegmentModel>) -> StoreResult<u64> {
+        let txn = self.db.begin().await?;
+        RoadSegments::delete_many().exec(&txn).await?;
+        for chunk in segments.chunks(INSERT_CHUNK) {
+            RoadSegments::insert_many(chunk.iter().map(|s| RoadSegmentActiveModel {
+                way_id: Set(s.way_id),
+                name: Set(s.name.clone()),
+                highway: Set(s.highway.clone()),
+                speed_limit: Set(s.speed_limit),
+                lat1: Set(s.lat1),
+                lng1: Set(s.lng1),
+                lat2: Set(s.lat2),
+                lng2: Set(s.lng2),
+                ..Default::default()
+            }))
+            .exec_without_returning(&txn)
+            .await?;
+        }
+        txn.commit().await?;
+        Ok(segments.len() as u64)
+    }
+
+    async fn road_segments_in(&self, bbox: &BBox) -> StoreResult<Vec<RoadSegmentModel>> {
+        // The bounds of a segment overlap the box when one end is not above it and one not
+        // below it, and the same across; a box across the antimeridian is matched as its halves
+        let lat = Condition::all()
+            .add(Condition::any().add(road_segments::Column::Lat1.lte(bbox.lat_max)).add(road_segments::Column::Lat2.lte(bbox.lat_max)))
+            .add(Condition::any().add(road_segments::Column::Lat1.gte(bbox.lat_min)).add(road_segments::Column::Lat2.gte(bbox.lat_min)));
+        let mut lng = Condition::any();
+        for (lng_min, lng_max) in bbox.lng_ranges() {
+            lng = lng.add(
+                Condition::all()
+                    .add(Condition::any().add(road_segments::Column::Lng1.lte(lng_max)).add(road_segments::Column::Lng2.lte(lng_max)))
+                    .add(Condition::any().add(road_segments::Column::Lng1.gte(lng_min)).add(road_segments::Column::Lng2.gte(lng_min))),
+            );
+        }
+        Ok(RoadSegments::find().filter(lat).filter(lng).all(&self.db).await?)
+    }
+}
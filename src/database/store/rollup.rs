@@ -0,0 +1,265 @@
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use sea_orm::sea_query::{Expr, Order};
+use sea_orm::{ColumnTrait, Condition, ConnectionTrait, DatabaseBackend, DbErr, EntityTrait, FromQueryResult, QueryFilter, QueryOrder, QuerySelect, Statement};
+
+use super::{BBox, DailyIngest, GlobalStats, TenantScope};
+use crate::database::model::points::{self, Entity as Points, Model as PointModel};
+use crate::database::model::dataset_stats::{self, Entity as DatasetStats};
+use crate::database::model::ingest_daily::{self, Entity as IngestDaily};
+
+/// Rows `dataset_stats` and each day of `ingest_daily` are split over. An insert adds to the
+/// shard of its (first) trip: inserts into one trip wait for each other on the trip summary
+/// anyway, while inserts into different trips mostly touch different rows.
+const SHARDS: i64 = 16;
+
+/// Row a rebuild writes the totals to
+const REBUILT_SHARD: i16 = 1;
+
+fn shard_of(p: &PointModel) -> i16 {
+    1 + p.randomized_id.rem_euclid(SHARDS) as i16
+}
+
+/// Counts a freshly inserted point in the dataset totals and today's ingest.
+/// Call inside the transaction that inserted the point, after the trip summary.
+pub(super) async fn record_point<C: ConnectionTrait>(conn: &C, p: &PointModel, new_trip: bool) -> Result<(), DbErr> {
+    record_points(conn, std::slice::from_ref(p), new_trip as i64).await
+}
+
+/// Like `record_point` for a bulk insert, in two statements whatever the batch size
+pub(super) async fn record_points<C: ConnectionTrait>(conn: &C, points: &[PointModel], new_trips: i64) -> Result<(), DbErr> {
+    let Some(first) = points.first() else {
+        return Ok(());
+    };
+    let shard = shard_of(first);
+    let backend = conn.get_database_backend();
+    let fold = |f: fn(f64, f64) -> f64, v: fn(&PointModel) -> f64| points.iter().map(v).reduce(f).unwrap_or_default();
+    let timestamps = points.iter().filter_map(|p| p.timestamp);
+    // The bounds skip NULLs, so the first point sets them
+    conn.execute(Statement::from_sql_and_values(
+        backend,
+        format!(
+            r#"INSERT INTO dataset_stats (id, total_points, total_trips, lat_min, lat_max, lng_min, lng_max, first_ts, last_ts, updated_at)
+               VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+               ON CONFLICT (id) DO UPDATE SET
+                   total_points = dataset_stats.total_points + EXCLUDED.total_points,
+                   total_trips = dataset_stats.total_trips + EXCLUDED.total_trips,
+                   lat_min = {}, lat_max = {}, lng_min = {}, lng_max = {}, first_ts = {}, last_ts = {},
+                   updated_at = EXCLUDED.updated_at"#,
+            least(backend, "lat_min"),
+            greatest(backend, "lat_max"),
+            least(backend, "lng_min"),
+            greatest(backend, "lng_max"),
+            least(backend, "first_ts"),
+            greatest(backend, "last_ts"),
+        ),
+        [
+            shard.into(),
+            (points.len() as i64).into(),
+            new_trips.into(),
+            fold(f64::min, |p| p.lat).into(),
+            fold(f64::max, |p| p.lat).into(),
+            fold(f64::min, |p| p.lng).into(),
+            fold(f64::max, |p| p.lng).into(),
+            timestamps.clone().min().into(),
+            timestamps.max().into(),
+            Utc::now().into(),
+        ],
+    ))
+    .await?;
+    conn.execute(Statement::from_sql_and_values(
+        backend,
+        r#"INSERT INTO ingest_daily (day, shard, points) VALUES ($1, $2, $3)
+           ON CONFLICT (day, shard) DO UPDATE SET points = ingest_daily.points + EXCLUDED.points"#,
+        [Utc::now().date_naive().into(), shard.into(), (points.len() as i64).into()],
+    ))
+    .await?;
+    Ok(())
+}
+
+/// Recomputes the dataset totals after points were deleted, into one shard. Ingest history is
+/// left alone. The `WHERE TRUE` keeps SQLite from reading ON CONFLICT as a join constraint.
+pub(super) async fn rebuild<C: ConnectionTrait>(conn: &C) -> Result<(), DbErr> {
+    DatasetStats::delete_many().filter(dataset_stats::Column::Id.ne(REBUILT_SHARD)).exec(conn).await?;
+    conn.execute(Statement::from_sql_and_values(
+        conn.get_database_backend(),
+        r#"INSERT INTO dataset_stats (id, total_points, total_trips, lat_min, lat_max, lng_min, lng_max, first_ts, last_ts, updated_at)
+           SELECT $1, COUNT(*), (SELECT COUNT(*) FROM trips),
+                  MIN(lat), MAX(lat), MIN(lng), MAX(lng), MIN(timestamp), MAX(timestamp), $2
+           FROM points
+           WHERE TRUE
+           ON CONFLICT (id) DO UPDATE SET
+               total_points = EXCLUDED.total_points,
+               total_trips = EXCLUDED.total_trips,
+               lat_min = EXCLUDED.lat_min,
+               lat_max = EXCLUDED.lat_max,
+               lng_min = EXCLUDED.lng_min,
+               lng_max = EXCLUDED.lng_max,
+               first_ts = EXCLUDED.first_ts,
+               last_ts = EXCLUDED.last_ts,
+               updated_at = EXCLUDED.updated_at"#,
+        [REBUILT_SHARD.into(), Utc::now().into()],
+    ))
+    .await?;
+    Ok(())
+}
+
+/// The smaller of the stored and the incoming value of a `dataset_stats` column, NULL only
+/// when both are. SQLite's two-argument min() returns NULL as soon as one side is.
+fn least(backend: DatabaseBackend, column: &str) -> String {
+    match backend {
+        DatabaseBackend::Sqlite => format!("min(coalesce(dataset_stats.{c}, EXCLUDED.{c}), coalesce(EXCLUDED.{c}, dataset_stats.{c}))", c = column),
+        _ => format!("LEAST(dataset_stats.{c}, EXCLUDED.{c})", c = column),
+    }
+}
+
+/// Counterpart of `least`
+fn greatest(backend: DatabaseBackend, column: &str) -> String {
+    match backend {
+        DatabaseBackend::Sqlite => format!("max(coalesce(dataset_stats.{c}, EXCLUDED.{c}), coalesce(EXCLUDED.{c}, dataset_stats.{c}))", c = column),
+        _ => format!("GREATEST(dataset_stats.{c}, EXCLUDED.{c})", c = column),
+    }
+}
+
+/// Current totals plus ingest per day for the last `days` days (today included), oldest first
+pub(super) async fn load<C: ConnectionTrait>(conn: &C, days: u32) -> Result<GlobalStats, DbErr> {
+    #[derive(FromQueryResult)]
+    struct Totals {
+        total_points: Option<i64>,
+        total_trips: Option<i64>,
+        lat_min: Option<f64>,
+        lat_max: Option<f64>,
+        lng_min: Option<f64>,
+        lng_max: Option<f64>,
+        first_ts: Option<DateTime<Utc>>,
+        last_ts: Option<DateTime<Utc>>,
+        updated_at: Option<DateTime<Utc>>,
+    }
+    #[derive(FromQueryResult)]
+    struct Day { day: NaiveDate, points: i64 }
+
+    let since = Utc::now().date_naive() - Duration::days(days.saturating_sub(1) as i64);
+    let daily = IngestDaily::find()
+        .filter(ingest_daily::Column::Day.gte(since))
+        .select_only()
+        .column(ingest_daily::Column::Day)
+        .column_as(Expr::cust("CAST(SUM(\"points\") AS BIGINT)"), "points")
+        .group_by(ingest_daily::Column::Day)
+        .order_by_asc(ingest_daily::Column::Day)
+        .into_model::<Day>()
+        .all(conn)
+        .await?
+        .into_iter()
+        .map(|d| DailyIngest { day: d.day, points: d.points as u64 })
+        .collect();
+    let row = DatasetStats::find()
+        .select_only()
+        .column_as(Expr::cust("CAST(SUM(\"total_points\") AS BIGINT)"), "total_points")
+        .column_as(Expr::cust("CAST(SUM(\"total_trips\") AS BIGINT)"), "total_trips")
+        .column_as(dataset_stats::Column::LatMin.min(), "lat_min")
+        .column_as(dataset_stats::Column::LatMax.max(), "lat_max")
+        .column_as(dataset_stats::Column::LngMin.min(), "lng_min")
+        .column_as(dataset_stats::Column::LngMax.max(), "lng_max")
+        .column_as(dataset_stats::Column::FirstTs.min(), "first_ts")
+        .column_as(dataset_stats::Column::LastTs.max(), "last_ts")
+        .column_as(dataset_stats::Column::UpdatedAt.max(), "updated_at")
+        .into_model::<Totals>()
+        .one(conn)
+        .await?;
+    // No shard yet: aggregates over no rows are NULL
+    let Some((row, updated_at)) = row.and_then(|r| r.updated_at.map(|u| (r, u))) else {
+        return Ok(GlobalStats { daily_ingest: daily, ..Default::default() });
+    };
+    let bbox = match (row.lat_min, row.lat_max, row.lng_min, row.lng_max) {
+        (Some(lat_min), Some(lat_max), Some(lng_min), Some(lng_max)) => Some(BBox { lat_min, lat_max, lng_min, lng_max }),
+        _ => None,
+    };
+    Ok(GlobalStats {
+        total_points: row.total_points.unwrap_or_default() as u64,
+        total_trips: row.total_trips.unwrap_or_default() as u64,
+        bbox,
+        first_ts: row.first_ts,
+        last_ts: row.last_ts,
+        updated_at: Some(updated_at),
+        daily_ingest: daily,
+    })
+}
+
+/// Totals and daily ingest of the points of `tenant` (all when None) timestamped before
+/// `before`, aggregated on the spot. Points carry no insertion time, so a day counts the points
+/// timestamped on it.
+pub(super) async fn compute<C: ConnectionTrait>(
+    conn: &C,
+    tenant: Option<&TenantScope>,
+    before: Option<DateTime<Utc>>,
+    days: u32,
+) -> Result<GlobalStats, DbErr> {
+    #[derive(FromQueryResult)]
+    struct Totals {
+        points: i64,
+        trips: i64,
+        lat_min: Option<f64>,
+        lat_max: Option<f64>,
+        lng_min: Option<f64>,
+        lng_max: Option<f64>,
+        first_ts: Option<DateTime<Utc>>,
+        last_ts: Option<DateTime<Utc>>,
+    }
+    #[derive(FromQueryResult)]
+    struct Day { day: NaiveDate, points: i64 }
+
+    let scoped = Condition::all()
+        .add_option(tenant.map(|t| match t {
+            TenantScope::Shared => points::Column::TenantId.is_null(),
+            TenantScope::Tenant(t) => points::Column::TenantId.eq(t.as_str()),
+        }))
+        .add_option(before.map(|b| points::Column::Timestamp.lt(b)));
+    let totals = Points::find()
+        .filter(scoped.clone())
+        .select_only()
+        .column_as(Expr::cust("COUNT(*)"), "points")
+        .column_as(Expr::cust("COUNT(DISTINCT \"randomized_id\")"), "trips")
+        .column_as(points::Column::Lat.min(), "lat_min")
+        .column_as(points::Column::Lat.max(), "lat_max")
+        .column_as(points::Column::Lng.min(), "lng_min")
+        .column_as(points::Column::Lng.max(), "lng_max")
+        .column_as(points::Column::Timestamp.min(), "first_ts")
+        .column_as(points::Column::Timestamp.max(), "last_ts")
+        .into_model::<Totals>()
+        .one(conn)
+        .await?;
+    let day = match conn.get_database_backend() {
+        DatabaseBackend::Sqlite => Expr::cust("date(\"timestamp\")"),
+        _ => Expr::cust("CAST((\"timestamp\" AT TIME ZONE 'UTC') AS DATE)"),
+    };
+    let since = Utc::now().date_naive() - Duration::days(days.saturating_sub(1) as i64);
+    let daily = Points::find()
+        .filter(scoped)
+        .filter(points::Column::Timestamp.gte(since.and_time(Default::default()).and_utc()))
+        .select_only()
+        .column_as(day.clone(), "day")
+        .column_as(Expr::cust("COUNT(*)"), "points")
+        .group_by(day.clone())
+        .order_by(day, Order::Asc)
+        .into_model::<Day>()
+        .all(conn)
+        .await?
+        .into_iter()
+        .map(|d| DailyIngest { day: d.day, points: d.points as u64 })
+        .collect();
+    let Some(totals) = totals.filter(|t| t.points > 0) else {
+        return Ok(GlobalStats { daily_ingest: daily, ..Default::default() });
+    };
+    let bbox = match (totals.lat_min, totals.lat_max, totals.lng_min, totals.lng_max) {
+        (Some(lat_min), Some(lat_max), Some(lng_min), Some(lng_max)) => Some(BBox { lat_min, lat_max, lng_min, lng_max }),
+        _ => None,
+    };
+    Ok(GlobalStats {
+        total_points: totals.points as u64,
+        total_trips: totals.trips as u64,
+        bbox,
+        first_ts: totals.first_ts,
+        last_ts: totals.last_ts,
+        updated_at: Some(Utc::now()),
+        daily_ingest: daily,
+    })
+}
@@ -0,0 +1,179 @@
+use chrono::{DateTime, Duration, Utc};
+use sea_orm::prelude::async_trait;
+use std::sync::Arc;
+
+use super::{
+    AnomalyVerdict, DeviceFilter, DeviceStats, DeviceStore, GlobalStats, NewPointRecord, PointFilter, PointOrder, PointStore,
+    StoreResult, TenantScope, DayWindow, TileGrid, TileSpeeds, TimeBucket, TimelineRow, TripFilter, TripOrder, TripStore,
+};
+use crate::database::model::matched_trips::Model as MatchedTripModel;
+use crate::database::model::points::Model as PointModel;
+use crate::database::model::trips::Model as TripModel;
+use crate::tenant;
+
+/// Store handed to the HTTP handlers when tenants are configured: every read is limited to the
+/// tenant of the request being handled and new rows are stored for it (see `tenant::resolve`).
+/// Outside a request, as in background workers, calls pass through unscoped.
+pub struct TenantScoped<S: ?Sized> {
+    inner: Arc<S>,
+}
+
+impl<S: ?Sized> TenantScoped<S> {
+    pub fn new(inner: Arc<S>) -> Self {
+        Self { inner }
+    }
+}
+
+fn scope_points(filter: &PointFilter) -> PointFilter {
+    let mut filter = filter.clone();
+    if let Some(scope) = tenant::current() {
+        filter.tenant = Some(scope);
+    }
+    filter
+}
+
+fn scope_trips(filter: &TripFilter) -> TripFilter {
+    let mut filter = filter.clone();
+    if let Some(scope) = tenant::current() {
+        filter.tenant = Some(scope);
+    }
+    filter
+}
+
+fn scope_devices(filter: &DeviceFilter) -> DeviceFilter {
+    let mut filter = filter.clone();
+    if let Some(scope) = tenant::current() {
+        filter.tenant = Some(scope);
+    }
+    filter
+}
+
+/// Points arrive tagged by ingestion; anything else stored during a request is the request's
+fn tag(mut point: NewPointRecord) -> NewPointRecord {
+    if point.tenant_id.is_none() {
+        point.tenant_id = tenant::current_id();
+    }
+    point
+}
+
+#[async_trait::async_trait]
+impl PointStore for TenantScoped<dyn PointStore> {
+    fn name(&self) -> &'static str { self.inner.name() }
+
+    async fn insert(&self, point: NewPointRecord) -> StoreResult<PointModel> {
+        self.inner.insert(tag(point)).await
+    }
+
+    async fn insert_many(&self, points: Vec<NewPointRecord>) -> StoreResult<Vec<PointModel>> {
+        self.inner.insert_many(points.into_iter().map(tag).collect()).await
+    }
+
+    async fn find_page(&self, filter: &PointFilter, order: PointOrder, limit: Option<u64>, offset: u64) -> StoreResult<Vec<PointModel>> {
+        self.inner.find_page(&scope_points(filter), order, limit, offset).await
+    }
+
+    async fn find_after(&self, filter: &PointFilter, after_id: i64, limit: u64) -> StoreResult<Vec<PointModel>> {
+        self.inner.find_after(&scope_points(filter), after_id, limit).await
+    }
+
+    async fn count(&self, filter: &PointFilter) -> StoreResult<u64> {
+        self.inner.count(&scope_points(filter)).await
+    }
+
+    async fn timeline(&self, filter: &PointFilter, bucket: TimeBucket) -> StoreResult<Vec<TimelineRow>> {
+        self.inner.timeline(&scope_points(filter), bucket).await
+    }
+
+    async fn tile_speeds(&self, filter: &PointFilter, grid: TileGrid, window: DayWindow) -> StoreResult<Vec<TileSpeeds>> {
+        self.inner.tile_speeds(&scope_points(filter), grid, window).await
+    }
+
+    // Called by the classification worker on points it got from ingestion
+    async fn set_anomaly(&self, id: i64, verdict: AnomalyVerdict) -> StoreResult<bool> {
+        self.inner.set_anomaly(id, verdict).await
+    }
+
+    /// Another tenant's trip of the same id is left alone
+    async fn review_trip(&self, randomized_id: i64, anomaly: bool, reviewed_by: &str) -> StoreResult<u64> {
+        if tenant::current().is_some() && self.count(&PointFilter { randomized_id: Some(randomized_id), ..Default::default() }).await? == 0 {
+            return Ok(0);
+        }
+        self.inner.review_trip(randomized_id, anomaly, reviewed_by).await
+    }
+
+    async fn delete(&self, filter: &PointFilter) -> StoreResult<u64> {
+        self.inner.delete(&scope_points(filter)).await
+    }
+}
+
+#[async_trait::async_trait]
+impl TripStore for TenantScoped<dyn TripStore> {
+    async fn find_trips(&self, filter: &TripFilter, order: TripOrder, limit: u64, offset: u64) -> StoreResult<Vec<TripModel>> {
+        self.inner.find_trips(&scope_trips(filter), order, limit, offset).await
+    }
+
+    async fn count_trips(&self, filter: &TripFilter) -> StoreResult<u64> {
+        self.inner.count_trips(&scope_trips(filter)).await
+    }
+
+    async fn trips_to_match(&self, ended_before: DateTime<Utc>, limit: u64) -> StoreResult<Vec<TripModel>> {
+        self.inner.trips_to_match(ended_before, limit).await
+    }
+
+    async fn save_matched(&self, matched: MatchedTripModel) -> StoreResult<()> {
+        self.inner.save_matched(matched).await
+    }
+
+    async fn record_match_failure(&self, randomized_id: i64, error: &str, backoff: Duration) -> StoreResult<u32> {
+        self.inner.record_match_failure(randomized_id, error, backoff).await
+    }
+
+    /// Only for trips of the tenant
+    async fn find_matched(&self, randomized_id: i64) -> StoreResult<Option<MatchedTripModel>> {
+        if tenant::current().is_some() && self.count_trips(&TripFilter { randomized_id: Some(randomized_id), ..Default::default() }).await? == 0 {
+            return Ok(None);
+        }
+        self.inner.find_matched(randomized_id).await
+    }
+
+    /// The rollup covers every tenant, so a scoped request gets its own totals computed
+    async fn global_stats(&self, days: u32, before: Option<DateTime<Utc>>) -> StoreResult<GlobalStats> {
+        match tenant::current() {
+            Some(scope) => self.inner.tenant_stats(&scope, days, before).await,
+            None => self.inner.global_stats(days, before).await,
+        }
+    }
+
+    async fn tenant_stats(&self, tenant: &TenantScope, days: u32, before: Option<DateTime<Utc>>) -> StoreResult<GlobalStats> {
+        self.inner.tenant_stats(tenant, days, before).await
+    }
+
+    /// The admin API is not scoped to a tenant
+    async fn live_stats(&self, days: u32) -> StoreResult<GlobalStats> {
+        self.inner.live_stats(days).await
+    }
+
+    /// A scoped request only knows its own tenant
+    async fn tenants(&self) -> StoreResult<Vec<String>> {
+        match tenant::current() {
+            Some(scope) => Ok(scope.tenant_id().map(str::to_string).into_iter().collect()),
+            None => self.inner.tenants().await,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl DeviceStore for TenantScoped<dyn DeviceStore> {
+    async fn register_device(&self, device_id: &str, name: Option<&str>, tenant_id: Option<&str>) -> StoreResult<bool> {
+        let tenant_id = tenant_id.map(str::to_string).or_else(tenant::current_id);
+        self.inner.register_device(device_id, name, tenant_id.as_deref()).await
+    }
+
+    async fn find_devices(&self, filter: &DeviceFilter, limit: u64, offset: u64) -> StoreResult<Vec<DeviceStats>> {
+        self.inner.find_devices(&scope_devices(filter), limit, offset).await
+    }
+
+    async fn count_devices(&self, filter: &DeviceFilter) -> StoreResult<u64> {
+        self.inner.count_devices(&scope_devices(filter)).await
+    }
+}
@@ -0,0 +1,137 @@
+use chrono::{DateTime, Utc};
+use sea_orm::prelude::async_trait;
+use sea_orm::sea_query::{Alias, Expr, OnConflict, Query};
+use sea_orm::{ColumnTrait, ConnectionTrait, EntityTrait, FromQueryResult, QueryFilter, QueryOrder, Set, TransactionTrait};
+
+use super::{RollupCoverage, SeaOrmPointStore, StoreResult, TileHour, TileStat, TileStatsStore};
+use crate::database::model::tile_rollups::{self, ActiveModel as RollupActiveModel, Entity as TileRollups};
+use crate::database::model::tile_stats::{self, ActiveModel as TileStatActiveModel, Entity as TileStats};
+
+/// Rows per INSERT, well below the bind parameter limits of Postgres and SQLite
+const INSERT_CHUNK: usize = 1000;
+
+#[derive(FromQueryResult)]
+struct SumRow {
+    tile_row: i32,
+    tile_col: i32,
+    points: i64,
+    speed_sum: f64,
+}
+
+#[async_trait::async_trait]
+impl TileStatsStore for SeaOrmPointStore {
+    async fn rollup_coverage(&self) -> StoreResult<Vec<RollupCoverage>> {
+        Ok(TileRollups::find()
+            .all(&self.db)
+            .await?
+            .into_iter()
+            .map(|r| RollupCoverage { resolution: r.resolution, from: r.covered_from, through: r.covered_through, backfilled: r.backfilled })
+            .collect())
+    }
+
+    async fn replace_hour(&self, resolution: i32, hour: DateTime<Utc>, stats: Vec<TileStat>) -> StoreResult<()> {
+        let txn = self.db.begin().await?;
+        TileStats::delete_many()
+            .filter(tile_stats::Column::Resolution.eq(resolution))
+            .filter(tile_stats::Column::Hour.eq(hour))
+            .exec(&txn)
+            .await?;
+        for chunk in stats.chunks(INSERT_CHUNK) {
+            TileStats::insert_many(chunk.iter().map(|s| TileStatActiveModel {
+                resolution: Set(resolution),
+                hour: Set(hour),
+                tile_row: Set(s.row),
+                tile_col: Set(s.col),
+                points: Set(s.points as i64),
+                speed_sum: Set(s.speed_sum),
+            }))
+            .exec_without_returning(&txn)
+            .await?;
+        }
+        txn.commit().await?;
+        Ok(())
+    }
+
+    async fn set_coverage(&self, coverage: RollupCoverage) -> StoreResult<()> {
+        let txn = self.db.begin().await?;
+        TileStats::delete_many()
+            .filter(tile_stats::Column::Resolution.eq(coverage.resolution))
+            .filter(tile_stats::Column::Hour.lt(coverage.from).or(tile_stats::Column::Hour.gte(coverage.through)))
+            .exec(&txn)
+            .await?;
+        TileRollups::insert(RollupActiveModel {
+            resolution: Set(coverage.resolution),
+            covered_from: Set(coverage.from),
+            covered_through: Set(coverage.through),
+            backfilled: Set(coverage.backfilled),
+            refreshed_at: Set(Utc::now()),
+        })
+        .on_conflict(
+            OnConflict::column(tile_rollups::Column::Resolution)
+                .update_columns([
+                    tile_rollups::Column::CoveredFrom,
+                    tile_rollups::Column::CoveredThrough,
+                    tile_rollups::Column::Backfilled,
+                    tile_rollups::Column::RefreshedAt,
+                ])
+                .to_owned(),
+        )
+        .exec_without_returning(&txn)
+        .await?;
+        txn.commit().await?;
+        Ok(())
+    }
+
+    async fn drop_rollup(&self, resolution: i32) -> StoreResult<()> {
+        let txn = self.db.begin().await?;
+        TileStats::delete_many().filter(tile_stats::Column::Resolution.eq(resolution)).exec(&txn).await?;
+        TileRollups::delete_by_id(resolution).exec(&txn).await?;
+        txn.commit().await?;
+        Ok(())
+    }
+
+    async fn sum_tile_stats(
+        &self,
+        resolution: i32,
+        rows: (i32, i32),
+        cols: (i32, i32),
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> StoreResult<Vec<TileStat>> {
+        let query = Query::select()
+            .column(tile_stats::Column::TileRow)
+            .column(tile_stats::Column::TileCol)
+            // Postgres sums bigints into numeric
+            .expr_as(Expr::cust(r#"CAST(SUM("points") AS BIGINT)"#), Alias::new("points"))
+            .expr_as(Expr::cust(r#"SUM("speed_sum")"#), Alias::new("speed_sum"))
+            .from(TileStats)
+            .and_where(Expr::col(tile_stats::Column::Resolution).eq(resolution))
+            .and_where(Expr::col(tile_stats::Column::Hour).gte(since))
+            .and_where(Expr::col(tile_stats::Column::Hour).lt(until))
+            .and_where(Expr::col(tile_stats::Column::TileRow).between(rows.0, rows.1))
+            .and_where(Expr::col(tile_stats::Column::TileCol).between(cols.0, cols.1))
+            .group_by_col(tile_stats::Column::TileRow)
+            .group_by_col(tile_stats::Column::TileCol)
+            .to_owned();
+        let rows = SumRow::find_by_statement(self.db.get_database_backend().build(&query)).all(&self.db).await?;
+        Ok(rows
+            .into_iter()
+            .map(|r| TileStat { row: r.tile_row, col: r.tile_col, points: r.points as u64, speed_sum: r.speed_sum })
+            .collect())
+    }
+
+    async fn tile_hours(&self, resolution: i32, row: i32, col: i32, since: DateTime<Utc>, until: DateTime<Utc>) -> StoreResult<Vec<TileHour>> {
+        Ok(TileStats::find()
+            .filter(tile_stats::Column::Resolution.eq(resolution))
+            .filter(tile_stats::Column::TileRow.eq(row))
+            .filter(tile_stats::Column::TileCol.eq(col))
+            .filter(tile_stats::Column::Hour.gte(since))
+            .filter(tile_stats::Column::Hour.lt(until))
+            .order_by_asc(tile_stats::Column::Hour)
+            .all(&self.db)
+            .await?
+            .into_iter()
+            .map(|s| TileHour { hour: s.hour, points: s.points as u64, speed_sum: s.speed_sum })
+            .collect())
+    }
+}
@@ -0,0 +1,512 @@
+use chrono::{DateTime, Duration, Utc};
+use sea_orm::prelude::async_trait;
+use sea_orm::sea_query::{Expr, OnConflict, Query, SimpleExpr};
+use sea_orm::{ColumnTrait, Condition, ConnectionTrait, DbErr, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, Set, Statement, TransactionTrait};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use super::postgres::outside_lng;
+use super::{devices, rollup, EmbargoRule, GlobalStats, SeaOrmPointStore, StoreResult, TenantScope, TripFilter, TripOrder, TripStore};
+use crate::geo::haversine_m;
+use crate::database::model::points::{self, Entity as Points, Model as PointModel};
+use crate::database::model::trips::{self, ActiveModel as TripActiveModel, Entity as Trips, Model as TripModel};
+use crate::database::model::matched_trips::{self, ActiveModel as MatchedTripActiveModel, Entity as MatchedTrips, Model as MatchedTripModel};
+use crate::database::model::match_failures::{self, ActiveModel as MatchFailureActiveModel, Entity as MatchFailures};
+
+/// Rows per UPDATE of point deltas; four bind parameters each stay within the limits of both
+/// PostgreSQL and SQLite
+const DELTAS_PER_UPDATE: usize = 5000;
+/// Trips per lookup or upsert of trip summaries
+const TRIPS_PER_STATEMENT: usize = 1000;
+
+/// Folds freshly inserted points into their trip summaries and stores on them the deltas from
+/// the point before each (see `set_deltas`), which `points` are updated with as well. Each trip
+/// is worked out in memory and the batch written in a handful of statements: points arriving
+/// in time order extend their trip, a late point rebuilds the trip from its points. A trip
+/// takes the device `devices` names for it unless it has one, and a device seen for the first
+/// time joins the registry. Returns how many trips the points started.
+/// Call inside the transaction that inserted the points.
+pub(super) async fn record_points<C: ConnectionTrait>(conn: &C, points: &mut [PointModel], devices: &HashMap<i64, String>) -> Result<i64, DbErr> {
+    let now = Utc::now();
+    // Each trip's points in time order
+    let mut by_trip: BTreeMap<i64, Vec<usize>> = BTreeMap::new();
+    for (i, p) in points.iter().enumerate() {
+        by_trip.entry(p.randomized_id).or_default().push(i);
+    }
+    for idx in by_trip.values_mut() {
+        idx.sort_by_key(|&i| (points[i].timestamp.unwrap_or(now), points[i].id));
+    }
+
+    // Row locks serialize concurrent uploads of the same trip; taken in id order, two
+    // batches sharing trips cannot deadlock
+    let ids: Vec<i64> = by_trip.keys().copied().collect();
+    let mut existing = HashMap::new();
+    for chunk in ids.chunks(TRIPS_PER_STATEMENT) {
+        let found = Trips::find()
+            .filter(trips::Column::RandomizedId.is_in(chunk.iter().copied()))
+            .order_by_asc(trips::Column::RandomizedId)
+            .lock_exclusive()
+            .all(conn)
+            .await?;
+        existing.extend(found.into_iter().map(|t| (t.randomized_id, t)));
+    }
+
+    let mut changed = Vec::new();
+    let mut named = Vec::new();
+    let mut late = BTreeSet::new();
+
+    let mut fresh = Vec::new();
+    for (&randomized_id, idx) in by_trip.iter().filter(|(id, _)| !existing.contains_key(id)) {
+        let device_id = devices.get(&randomized_id);
+        chain_deltas(points, idx, None, &mut changed);
+        let pts: Vec<PointModel> = idx.iter().map(|&i| points[i].clone()).collect();
+        let Some(mut summary) = summarize(randomized_id, &pts) else { continue };
+        summary.device_id = Set(device_id.cloned());
+        fresh.push(summary);
+    }
+    let mut created = HashSet::new();
+    for chunk in fresh.chunks(TRIPS_PER_STATEMENT) {
+        let keys = Trips::insert_many(chunk.to_vec())
+            .on_conflict(OnConflict::column(trips::Column::RandomizedId).do_nothing().to_owned())
+            .exec_with_returning_keys(conn)
+            .await?;
+        created.extend(keys);
+    }
+    for (&randomized_id, idx) in by_trip.iter().filter(|(id, _)| !existing.contains_key(id)) {
+        if !created.contains(&randomized_id) {
+            // Another upload started the trip meanwhile
+            late.insert(randomized_id);
+        } else if let Some(device_id) = devices.get(&randomized_id) {
+            named.push((device_id.clone(), points[idx[0]].tenant_id.clone()));
+        }
+    }
+
+    let mut extended = Vec::new();
+    for (&randomized_id, idx) in &by_trip {
+        let Some(trip) = existing.remove(&randomized_id) else { continue };
+        if points[idx[0]].timestamp.unwrap_or(now) < trip.end_ts {
+            late.insert(randomized_id);
+            continue;
+        }
+        chain_deltas(points, idx, Some((trip.end_lat, trip.end_lng, trip.end_ts)), &mut changed);
+        let pts: Vec<&PointModel> = idx.iter().map(|&i| &points[i]).collect();
+        let last = &pts[pts.len() - 1];
+        let end_ts = last.timestamp.unwrap_or(now);
+        let n = trip.point_count as f64;
+        let point_count = trip.point_count + pts.len() as i64;
+        let speed_sum: f64 = pts.iter().map(|p| p.spd).sum();
+        let flagged_now = pts.iter().filter(|p| p.anomaly == Some(true)).count() as i64;
+        let flagged = (trip.anomaly || flagged_now > 0, trip.anomaly_count + flagged_now);
+        let (anomaly, anomaly_count) = decided(trip.anomaly_review, point_count, flagged);
+        let device_id = match (&trip.device_id, devices.get(&randomized_id)) {
+            (None, Some(device_id)) => {
+                named.push((device_id.clone(), last.tenant_id.clone()));
+                Some(device_id.clone())
+            }
+            (current, _) => current.clone(),
+        };
+        let mut active: TripActiveModel = trip.clone().into();
+        active.end_ts = Set(end_ts);
+        active.end_lat = Set(last.lat);
+        active.end_lng = Set(last.lng);
+        active.distance_m = Set(trip.distance_m + pts.iter().filter_map(|p| p.prev_distance_m).sum::<f64>());
+        active.duration_s = Set(seconds_between(trip.start_ts, end_ts));
+        active.avg_speed = Set((trip.avg_speed * n + speed_sum) / point_count as f64);
+        active.max_speed = Set(pts.iter().map(|p| p.spd).fold(trip.max_speed, f64::max));
+        active.point_count = Set(point_count);
+        active.anomaly = Set(anomaly);
+        active.anomaly_count = Set(anomaly_count);
+        active.device_id = Set(device_id);
+        extended.push(active);
+    }
+    for chunk in extended.chunks(TRIPS_PER_STATEMENT) {
+        Trips::insert_many(chunk.to_vec())
+            .on_conflict(
+                OnConflict::column(trips::Column::RandomizedId)
+                    .update_columns([
+                        trips::Column::EndTs,
+                        trips::Column::EndLat,
+                        trips::Column::EndLng,
+                        trips::Column::DistanceM,
+                        trips::Column::DurationS,
+                        trips::Column::AvgSpeed,
+                        trips::Column::MaxSpeed,
+                        trips::Column::PointCount,
+                        trips::Column::Anomaly,
+                        trips::Column::AnomalyCount,
+                        trips::Column::DeviceId,
+                    ])
+                    .to_owned(),
+            )
+            .exec_without_returning(conn)
+            .await?;
+    }
+
+    let settled = changed.iter().map(|&i| &points[i]).filter(|p| !late.contains(&p.randomized_id));
+    save_deltas(conn, settled).await?;
+    devices::record_devices(conn, &named).await?;
+
+    // A rebuild takes in every point of the batch stored for its trip
+    for randomized_id in late {
+        let rebuilt: HashMap<i64, PointModel> = rebuild(conn, randomized_id).await?.into_iter().map(|p| (p.id, p)).collect();
+        for &i in &by_trip[&randomized_id] {
+            let p = &mut points[i];
+            if let Some(r) = rebuilt.get(&p.id) {
+                (p.prev_distance_m, p.prev_interval_s, p.derived_speed) = (r.prev_distance_m, r.prev_interval_s, r.derived_speed);
+            }
+        }
+    }
+    Ok(created.len() as i64)
+}
+
+/// Sets the deltas of the points at `idx`, in time order, going on from `prev`; notes in
+/// `changed` those that moved
+fn chain_deltas(points: &mut [PointModel], idx: &[usize], mut prev: Option<(f64, f64, DateTime<Utc>)>, changed: &mut Vec<usize>) {
+    for &i in idx {
+        let p = &mut points[i];
+        if set_deltas(p, prev) {
+            changed.push(i);
+        }
+        prev = Some((p.lat, p.lng, p.timestamp.unwrap_or_else(Utc::now)));
+    }
+}
+
+/// Stores on `p` its distance, interval and speed from `prev` (lat, lng, timestamp), the point
+/// before it in time order; the first point of a trip has none. Returns true when they changed.
+fn set_deltas(p: &mut PointModel, prev: Option<(f64, f64, DateTime<Utc>)>) -> bool {
+    let deltas = match prev {
+        Some((lat, lng, ts)) => {
+            let distance = haversine_m(lat, lng, p.lat, p.lng);
+            let interval = seconds_between(ts, p.timestamp.unwrap_or_else(Utc::now));
+            (Some(distance), Some(interval), (interval > 0.0).then(|| distance / interval))
+        }
+        None => (None, None, None),
+    };
+    let changed = deltas != (p.prev_distance_m, p.prev_interval_s, p.derived_speed);
+    (p.prev_distance_m, p.prev_interval_s, p.derived_speed) = deltas;
+    changed
+}
+
+/// Writes the deltas of `pts`, one UPDATE … FROM (VALUES …) per chunk
+async fn save_deltas<'a, C: ConnectionTrait>(conn: &C, pts: impl Iterator<Item = &'a PointModel>) -> Result<(), DbErr> {
+    let pts: Vec<&PointModel> = pts.collect();
+    for chunk in pts.chunks(DELTAS_PER_UPDATE) {
+        let rows = (0..chunk.len()).map(|i| format!("(${}, ${}, ${}, ${})", 4 * i + 1, 4 * i + 2, 4 * i + 3, 4 * i + 4)).collect::<Vec<_>>();
+        let values = chunk
+            .iter()
+            .flat_map(|p| [p.id.into(), p.prev_distance_m.into(), p.prev_interval_s.into(), p.derived_speed.into()]);
+        // A CTE names the VALUES columns on SQLite as well
+        conn.execute(Statement::from_sql_and_values(
+            conn.get_database_backend(),
+            format!(
+                r#"WITH v (id, prev_distance_m, prev_interval_s, derived_speed) AS (VALUES {})
+                   UPDATE points SET prev_distance_m = v.prev_distance_m, prev_interval_s = v.prev_interval_s, derived_speed = v.derived_speed
+                   FROM v WHERE points.id = v.id"#,
+                rows.join(", ")
+            ),
+            values,
+        ))
+        .await?;
+    }
+    Ok(())
+}
+
+/// Recomputes one trip and the deltas of its points from the points; removes the row when no
+/// points are left. Returns the trip's points in time order.
+pub(super) async fn rebuild<C: ConnectionTrait>(conn: &C, randomized_id: i64) -> Result<Vec<PointModel>, DbErr> {
+    let mut pts = Points::find()
+        .filter(points::Column::RandomizedId.eq(randomized_id))
+        .order_by_asc(points::Column::Timestamp)
+        .order_by_asc(points::Column::Id)
+        .all(conn)
+        .await?;
+    // Only the neighbours of a late or deleted point move
+    let mut changed = Vec::new();
+    let idx: Vec<usize> = (0..pts.len()).collect();
+    chain_deltas(&mut pts, &idx, None, &mut changed);
+    save_deltas(conn, changed.iter().map(|&i| &pts[i])).await?;
+    let Some(mut summary) = summarize(randomized_id, &pts) else {
+        Trips::delete_by_id(randomized_id).exec(conn).await?;
+        return Ok(pts);
+    };
+    let review = Trips::find_by_id(randomized_id).one(conn).await?.and_then(|t| t.anomaly_review);
+    if review.is_some() {
+        let flagged = (*summary.anomaly.as_ref(), *summary.anomaly_count.as_ref());
+        let (anomaly, anomaly_count) = decided(review, pts.len() as i64, flagged);
+        (summary.anomaly, summary.anomaly_count) = (Set(anomaly), Set(anomaly_count));
+    }
+    Trips::insert(summary)
+        .on_conflict(
+            OnConflict::column(trips::Column::RandomizedId)
+                .update_columns([
+                    trips::Column::StartTs,
+                    trips::Column::EndTs,
+                    trips::Column::StartLat,
+                    trips::Column::StartLng,
+                    trips::Column::EndLat,
+                    trips::Column::EndLng,
+                    trips::Column::DistanceM,
+                    trips::Column::DurationS,
+                    trips::Column::AvgSpeed,
+                    trips::Column::MaxSpeed,
+                    trips::Column::PointCount,
+                    trips::Column::Anomaly,
+                    trips::Column::AnomalyCount,
+                ])
+                .to_owned(),
+        )
+        .exec_without_returning(conn)
+        .await?;
+    Ok(pts)
+}
+
+/// Re-derives the trip anomaly flag and count after one of its points was (re)classified;
+/// reviewed trips keep the operator's decision
+pub(super) async fn refresh_anomaly<C: ConnectionTrait>(conn: &C, point_id: i64) -> Result<(), DbErr> {
+    let flagged = Query::select()
+        .expr(Expr::col(points::Column::Id).count())
+        .from(Points)
+        .and_where(Expr::col((Points, points::Column::RandomizedId)).equals((Trips, trips::Column::RandomizedId)))
+        .and_where(Expr::col((Points, points::Column::Anomaly)).eq(true))
+        .to_owned();
+    let owner = Query::select()
+        .column(points::Column::RandomizedId)
+        .from(Points)
+        .and_where(points::Column::Id.eq(point_id))
+        .to_owned();
+    let flagged = SimpleExpr::SubQuery(None, Box::new(flagged.into_sub_query_statement()));
+    Trips::update_many()
+        .col_expr(trips::Column::AnomalyCount, flagged.clone())
+        .col_expr(trips::Column::Anomaly, Expr::expr(flagged).gt(0))
+        .filter(trips::Column::RandomizedId.in_subquery(owner))
+        .filter(trips::Column::AnomalyReview.is_null())
+        .exec(conn)
+        .await?;
+    Ok(())
+}
+
+/// Records an operator decision on a trip. The points keep the classifier's flags; the trip
+/// follows the decision from now on, through later points and re-classification alike.
+/// Returns false when there is no such trip.
+pub(super) async fn record_review<C: ConnectionTrait>(conn: &C, randomized_id: i64, anomaly: bool, reviewed_by: &str) -> Result<bool, DbErr> {
+    let res = Trips::update_many()
+        .col_expr(trips::Column::AnomalyReview, Expr::value(anomaly))
+        .col_expr(trips::Column::Anomaly, Expr::value(anomaly))
+        .col_expr(trips::Column::AnomalyCount, if anomaly { Expr::col(trips::Column::PointCount).into() } else { Expr::value(0i64) })
+        .col_expr(trips::Column::AnomalyReviewedBy, Expr::value(reviewed_by))
+        .col_expr(trips::Column::AnomalyReviewedAt, Expr::value(Utc::now()))
+        .filter(trips::Column::RandomizedId.eq(randomized_id))
+        .exec(conn)
+        .await?;
+    Ok(res.rows_affected > 0)
+}
+
+/// Trip flag and anomalous point count: the classifier's `flagged` unless an operator decided
+fn decided(review: Option<bool>, point_count: i64, flagged: (bool, i64)) -> (bool, i64) {
+    match review {
+        Some(true) => (true, point_count),
+        Some(false) => (false, 0),
+        None => flagged,
+    }
+}
+
+/// Summary of time-ordered points of one trip, their deltas already set
+fn summarize(randomized_id: i64, pts: &[PointModel]) -> Option<TripActiveModel> {
+    let first = pts.first()?;
+    let last = pts.last()?;
+    let now = Utc::now();
+    let start_ts = first.timestamp.unwrap_or(now);
+    let end_ts = last.timestamp.unwrap_or(now);
+    let distance_m = pts.iter().filter_map(|p| p.prev_distance_m).sum();
+    let speed_sum: f64 = pts.iter().map(|p| p.spd).sum();
+    Some(TripActiveModel {
+        randomized_id: Set(randomized_id),
+        start_ts: Set(start_ts),
+        end_ts: Set(end_ts),
+        start_lat: Set(first.lat),
+        start_lng: Set(first.lng),
+        end_lat: Set(last.lat),
+        end_lng: Set(last.lng),
+        distance_m: Set(distance_m),
+        duration_s: Set(seconds_between(start_ts, end_ts)),
+        avg_speed: Set(speed_sum / pts.len() as f64),
+        max_speed: Set(pts.iter().map(|p| p.spd).fold(f64::MIN, f64::max)),
+        point_count: Set(pts.len() as i64),
+        anomaly: Set(pts.iter().any(|p| p.anomaly == Some(true))),
+        anomaly_count: Set(pts.iter().filter(|p| p.anomaly == Some(true)).count() as i64),
+        tenant_id: Set(first.tenant_id.clone()),
+        // Review columns are left alone by the upsert
+        ..Default::default()
+    })
+}
+
+fn seconds_between(a: DateTime<Utc>, b: DateTime<Utc>) -> f64 {
+    (b - a).num_milliseconds() as f64 / 1000.0
+}
+
+fn apply_trip_filter<Q: QueryFilter>(mut query: Q, filter: &TripFilter) -> Q {
+    // Trips overlapping [since, until]
+    if let Some(ts) = filter.since { query = query.filter(trips::Column::EndTs.gte(ts)); }
+    if let Some(ts) = filter.until { query = query.filter(trips::Column::StartTs.lte(ts)); }
+    if let Some(id) = filter.randomized_id { query = query.filter(trips::Column::RandomizedId.eq(id)); }
+    if let Some(a) = filter.anomaly { query = query.filter(trips::Column::Anomaly.eq(a)); }
+    if let Some(d) = filter.min_distance_m { query = query.filter(trips::Column::DistanceM.gte(d)); }
+    if let Some(ids) = &filter.randomized_ids { query = query.filter(trips::Column::RandomizedId.is_in(ids.iter().copied())); }
+    if let Some(id) = filter.after_randomized_id { query = query.filter(trips::Column::RandomizedId.gt(id)); }
+    if let Some(n) = filter.min_anomaly_points { query = query.filter(trips::Column::AnomalyCount.gte(n)); }
+    if let Some(d) = &filter.device_id { query = query.filter(trips::Column::DeviceId.eq(d.as_str())); }
+    match &filter.tenant {
+        Some(TenantScope::Shared) => query = query.filter(trips::Column::TenantId.is_null()),
+        Some(TenantScope::Tenant(t)) => query = query.filter(trips::Column::TenantId.eq(t.as_str())),
+        None => {}
+    }
+    if let Some(r) = filter.min_anomaly_ratio {
+        query = query.filter(Expr::col(trips::Column::AnomalyCount).gte(Expr::col(trips::Column::PointCount).mul(r)));
+    }
+    for rule in &filter.embargo {
+        query = query.filter(published(rule));
+    }
+    query
+}
+
+/// A trip is published once its last point is, and neither end lies in a region still embargoed
+pub(super) fn published(rule: &EmbargoRule) -> Condition {
+    let mut published = Condition::any().add(trips::Column::EndTs.lte(rule.cutoff));
+    if let Some(b) = rule.region {
+        let outside = |lat: trips::Column, lng: trips::Column| {
+            Condition::any()
+                .add(lat.lt(b.lat_min))
+                .add(lat.gt(b.lat_max))
+                .add(outside_lng(lng, &b))
+        };
+        published = published.add(
+            Condition::all()
+                .add(outside(trips::Column::StartLat, trips::Column::StartLng))
+                .add(outside(trips::Column::EndLat, trips::Column::EndLng)),
+        );
+    }
+    published
+}
+
+#[async_trait::async_trait]
+impl TripStore for SeaOrmPointStore {
+    async fn find_trips(&self, filter: &TripFilter, order: TripOrder, limit: u64, offset: u64) -> StoreResult<Vec<TripModel>> {
+        let query = apply_trip_filter(Trips::find(), filter);
+        let query = match order {
+            TripOrder::StartAsc => query.order_by_asc(trips::Column::StartTs),
+            TripOrder::StartDesc => query.order_by_desc(trips::Column::StartTs),
+            TripOrder::DistanceAsc => query.order_by_asc(trips::Column::DistanceM),
+            TripOrder::DistanceDesc => query.order_by_desc(trips::Column::DistanceM),
+            TripOrder::DurationAsc => query.order_by_asc(trips::Column::DurationS),
+            TripOrder::DurationDesc => query.order_by_desc(trips::Column::DurationS),
+            TripOrder::MaxSpeedAsc => query.order_by_asc(trips::Column::MaxSpeed),
+            TripOrder::MaxSpeedDesc => query.order_by_desc(trips::Column::MaxSpeed),
+            TripOrder::IdAsc => query,
+        };
+        // Stable pages when the sort key ties
+        let query = query.order_by_asc(trips::Column::RandomizedId);
+        Ok(query.offset(offset).limit(limit).all(&self.db).await?)
+    }
+
+    async fn count_trips(&self, filter: &TripFilter) -> StoreResult<u64> {
+        Ok(apply_trip_filter(Trips::find(), filter).count(&self.db).await?)
+    }
+
+    async fn trips_to_match(&self, ended_before: DateTime<Utc>, limit: u64) -> StoreResult<Vec<TripModel>> {
+        let up_to_date = Query::select()
+            .expr(Expr::val(1))
+            .from(MatchedTrips)
+            .and_where(Expr::col((MatchedTrips, matched_trips::Column::RandomizedId)).equals((Trips, trips::Column::RandomizedId)))
+            .and_where(Expr::col((MatchedTrips, matched_trips::Column::SourcePointCount)).equals((Trips, trips::Column::PointCount)))
+            .to_owned();
+        let backing_off = Query::select()
+            .expr(Expr::val(1))
+            .from(MatchFailures)
+            .and_where(Expr::col((MatchFailures, match_failures::Column::RandomizedId)).equals((Trips, trips::Column::RandomizedId)))
+            .and_where(Expr::col((MatchFailures, match_failures::Column::RetryAt)).gt(Utc::now()))
+            .to_owned();
+        Ok(Trips::find()
+            .filter(trips::Column::EndTs.lt(ended_before))
+            .filter(Expr::exists(up_to_date).not())
+            .filter(Expr::exists(backing_off).not())
+            .order_by_asc(trips::Column::EndTs)
+            .limit(limit)
+            .all(&self.db)
+            .await?)
+    }
+
+    async fn save_matched(&self, matched: MatchedTripModel) -> StoreResult<()> {
+        let randomized_id = matched.randomized_id;
+        let active: MatchedTripActiveModel = matched.into();
+        MatchedTrips::insert(active)
+            .on_conflict(
+                OnConflict::column(matched_trips::Column::RandomizedId)
+                    .update_columns([
+                        matched_trips::Column::MatchedAt,
+                        matched_trips::Column::SourcePointCount,
+                        matched_trips::Column::Confidence,
+                        matched_trips::Column::DistanceM,
+                        matched_trips::Column::Geometry,
+                        matched_trips::Column::RoadNodeIds,
+                    ])
+                    .to_owned(),
+            )
+            .exec_without_returning(&self.db)
+            .await?;
+        MatchFailures::delete_by_id(randomized_id).exec(&self.db).await?;
+        Ok(())
+    }
+
+    async fn record_match_failure(&self, randomized_id: i64, error: &str, backoff: Duration) -> StoreResult<u32> {
+        let txn = self.db.begin().await?;
+        let before = MatchFailures::find_by_id(randomized_id).one(&txn).await?.map_or(0, |f| f.attempts);
+        let delay = (0..before.min(16)).fold(backoff, |d, _| d * 2).min(Duration::days(1));
+        let failure = MatchFailureActiveModel {
+            randomized_id: Set(randomized_id),
+            attempts: Set(before + 1),
+            last_error: Set(error.to_string()),
+            retry_at: Set(Utc::now() + delay),
+        };
+        MatchFailures::insert(failure)
+            .on_conflict(
+                OnConflict::column(match_failures::Column::RandomizedId)
+                    .update_columns([match_failures::Column::Attempts, match_failures::Column::LastError, match_failures::Column::RetryAt])
+                    .to_owned(),
+            )
+            .exec_without_returning(&txn)
+            .await?;
+        txn.commit().await?;
+        Ok((before + 1) as u32)
+    }
+
+    async fn find_matched(&self, randomized_id: i64) -> StoreResult<Option<MatchedTripModel>> {
+        Ok(MatchedTrips::find_by_id(randomized_id).one(&self.db).await?)
+    }
+
+    async fn global_stats(&self, days: u32, before: Option<DateTime<Utc>>) -> StoreResult<GlobalStats> {
+        match before {
+            Some(_) => Ok(rollup::compute(&self.db, None, before, days).await?),
+            None => Ok(rollup::load(&self.db, days).await?),
+        }
+    }
+
+    async fn tenant_stats(&self, tenant: &TenantScope, days: u32, before: Option<DateTime<Utc>>) -> StoreResult<GlobalStats> {
+        Ok(rollup::compute(&self.db, Some(tenant), before, days).await?)
+    }
+
+    async fn live_stats(&self, days: u32) -> StoreResult<GlobalStats> {
+        Ok(rollup::load(&self.db, days).await?)
+    }
+
+    async fn tenants(&self) -> StoreResult<Vec<String>> {
+        let names: Vec<Option<String>> = Trips::find()
+            .select_only()
+            .column(trips::Column::TenantId)
+            .distinct()
+            .filter(trips::Column::TenantId.is_not_null())
+            .order_by_asc(trips::Column::TenantId)
+            .into_tuple()
+            .all(&self.db)
+            .await?;
+        Ok(names.into_iter().flatten().collect())
+    }
+}
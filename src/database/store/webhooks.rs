@@ -0,0 +1,44 @@
+use chrono::Utc;
+use sea_orm::prelude::async_trait;
+use sea_orm::{ActiveModelTrait, EntityTrait, QueryOrder, Set};
+
+use super::{SeaOrmPointStore, StoreResult, WebhookSettings, WebhookStore};
+use crate::database::model::webhooks::{self, ActiveModel as WebhookActiveModel, Entity as Webhooks, Model as WebhookModel};
+
+#[async_trait::async_trait]
+impl WebhookStore for SeaOrmPointStore {
+    async fn find_webhooks(&self) -> StoreResult<Vec<WebhookModel>> {
+        Ok(Webhooks::find().order_by_asc(webhooks::Column::Id).all(&self.db).await?)
+    }
+
+    async fn create_webhook(&self, settings: &WebhookSettings) -> StoreResult<WebhookModel> {
+        let now = Utc::now();
+        let active = WebhookActiveModel {
+            url: Set(settings.url.clone()),
+            events: Set(settings.events.join(",")),
+            secret: Set(settings.secret.clone()),
+            enabled: Set(settings.enabled),
+            created_at: Set(now),
+            updated_at: Set(now),
+            ..Default::default()
+        };
+        Ok(active.insert(&self.db).await?)
+    }
+
+    async fn update_webhook(&self, id: i64, settings: &WebhookSettings) -> StoreResult<Option<WebhookModel>> {
+        let Some(existing) = Webhooks::find_by_id(id).one(&self.db).await? else {
+            return Ok(None);
+        };
+        let mut active: WebhookActiveModel = existing.into();
+        active.url = Set(settings.url.clone());
+        active.events = Set(settings.events.join(","));
+        active.secret = Set(settings.secret.clone());
+        active.enabled = Set(settings.enabled);
+        active.updated_at = Set(Utc::now());
+        Ok(Some(active.update(&self.db).await?))
+    }
+
+    async fn delete_webhook(&self, id: i64) -> StoreResult<bool> {
+        Ok(Webhooks::delete_by_id(id).exec(&self.db).await?.rows_affected > 0)
+    }
+}
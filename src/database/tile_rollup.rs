@@ -0,0 +1,215 @@
+//! Hourly per-tile rollups (`tile_stats`): points and speed sums per tile of a few fixed
+//! degree grids, refreshed in the background so that map requests on the same grid and whole
+//! hours add up stored rows instead of scanning points.
+
+use chrono::{DateTime, Duration, DurationRound, TimeZone, Utc};
+use log::{error, info, warn};
+use std::collections::{BTreeSet, HashMap};
+use std::env;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use super::store::{PointFilter, PointOrder, PointStore, RollupCoverage, StoreResult, TileStat, TileStatsStore};
+use crate::metrics::metrics;
+
+/// Name of the worker on the status page
+const JOB_NAME: &str = "tile rollup";
+
+/// Held by the pass in progress, and by `invalidate`
+static RUNNING: Mutex<()> = Mutex::const_new(());
+
+/// Rollup grids are keyed by their tile side in micro-degrees
+pub const MICRODEGREES: f64 = 1_000_000.0;
+
+/// Deleted hours recomputed at once by `invalidate`; a longer range is handed to the backfill
+const MAX_RECOMPUTED_HOURS: usize = 168;
+
+#[derive(Debug, Clone)]
+pub struct RollupConfig {
+    /// Tile sides in micro-degrees; tiles are anchored at 0°,0°
+    pub sides: Vec<i32>,
+    pub interval_secs: u64,
+    /// Hours before the current one that every run recomputes, so that late uploads and
+    /// points whose publication delay ran out still make it into their hour
+    pub lookback_hours: i64,
+    /// Older hours computed per run until the rollup reaches the first point
+    pub backfill_hours: i64,
+}
+
+impl RollupConfig {
+    /// TILE_ROLLUP_DEGREES lists the tile sides in degrees, e.g. `0.01,0.002`; unset disables
+    /// the rollups. TILE_ROLLUP_INTERVAL_SECS (default 300), TILE_ROLLUP_LOOKBACK_HOURS
+    /// (default 48) and TILE_ROLLUP_BACKFILL_HOURS (default 168) tune the job.
+    pub fn from_env() -> Option<Self> {
+        let sides: Vec<i32> = env::var("TILE_ROLLUP_DEGREES")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .filter_map(|s| {
+                let side = s.parse::<f64>().ok().filter(|d| *d > 0.0 && *d <= 90.0).map(|d| (d * MICRODEGREES).round() as i32);
+                if side.is_none_or(|s| s == 0) {
+                    warn!("Ignoring invalid TILE_ROLLUP_DEGREES entry '{}'", s);
+                    return None;
+                }
+                side
+            })
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        if sides.is_empty() {
+            return None;
+        }
+        let number = |name: &str, default: i64| env::var(name).ok().and_then(|v| v.parse::<i64>().ok()).filter(|n| *n > 0).unwrap_or(default);
+        Some(Self {
+            sides,
+            interval_secs: number("TILE_ROLLUP_INTERVAL_SECS", 300) as u64,
+            lookback_hours: number("TILE_ROLLUP_LOOKBACK_HOURS", 48),
+            backfill_hours: number("TILE_ROLLUP_BACKFILL_HOURS", 168),
+        })
+    }
+}
+
+/// Starts the rollup job when TILE_ROLLUP_DEGREES is set (`config`); returns whether it runs.
+/// `store` is the one the map endpoints read, publication delay included, so the rollups never
+/// hold points the API would hide.
+pub fn spawn(store: Arc<dyn PointStore>, rollups: Arc<dyn TileStatsStore>, config: Option<RollupConfig>) -> bool {
+    let Some(config) = config else {
+        info!("Tile rollups disabled (TILE_ROLLUP_DEGREES not set)");
+        return false;
+    };
+    let interval = config.interval_secs;
+    let sides = config.sides.iter().map(|s| (*s as f64 / MICRODEGREES).to_string()).collect::<Vec<_>>().join(", ");
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(std::time::Duration::from_secs(interval));
+        loop {
+            ticker.tick().await;
+            match run_once(store.as_ref(), rollups.as_ref(), &config).await {
+                Ok(hours) => metrics().record_job(JOB_NAME, true, format!("computed {} tile hours", hours)),
+                Err(e) => {
+                    error!("Tile rollup failed: {}", e);
+                    metrics().record_job(JOB_NAME, false, e.to_string());
+                }
+            }
+        }
+    });
+    info!("Tile rollups of {}° tiles every {}s", sides, interval);
+    true
+}
+
+/// What one run does for one grid
+struct Plan {
+    side: i32,
+    from: DateTime<Utc>,
+    /// Hours recomputed: the lookback window and the ones since the last run, then older ones
+    hours: BTreeSet<DateTime<Utc>>,
+    backfilled: bool,
+}
+
+/// One pass over every configured grid; returns the number of (grid, hour) pairs computed.
+/// Each hour's points are read once for all grids, and passes started while another runs, by
+/// the timer or `POST /api/admin/rollups`, wait for it.
+pub async fn run_once(store: &dyn PointStore, rollups: &dyn TileStatsStore, config: &RollupConfig) -> StoreResult<usize> {
+    let _running = RUNNING.lock().await;
+    let coverage = rollups.rollup_coverage().await?;
+    for stale in coverage.iter().filter(|c| !config.sides.contains(&c.resolution)) {
+        info!("Dropping the rollup of {}° tiles, no longer in TILE_ROLLUP_DEGREES", stale.resolution as f64 / MICRODEGREES);
+        rollups.drop_rollup(stale.resolution).await?;
+    }
+
+    let now = floor_hour(Utc::now());
+    let first = PointFilter { since: Some(Utc.timestamp_opt(0, 0).unwrap()), ..Default::default() };
+    let oldest = store.find(&first, PointOrder::TimestampAsc, Some(1)).await?.into_iter().next().and_then(|p| p.timestamp).map(floor_hour);
+    let recent = now - Duration::hours(config.lookback_hours);
+
+    let mut plans = Vec::new();
+    for &side in &config.sides {
+        let known = coverage.iter().find(|c| c.resolution == side);
+        let Some(oldest) = oldest else {
+            // Nothing to roll up yet: an empty rollup of everything before this hour
+            plans.push(Plan { side, from: now, hours: BTreeSet::new(), backfilled: true });
+            continue;
+        };
+        let (mut from, start) = match known {
+            Some(c) => (c.from.max(oldest), c.through.min(recent).max(c.from)),
+            None => {
+                let start = recent.max(oldest).min(now);
+                (start, start)
+            }
+        };
+        let mut hours: BTreeSet<_> = hour_range(start, now).collect();
+        if from > oldest {
+            let back = (from - Duration::hours(config.backfill_hours)).max(oldest);
+            hours.extend(hour_range(back, from));
+            from = back;
+        }
+        plans.push(Plan { side, from, hours, backfilled: from <= oldest });
+    }
+
+    let computed = compute(store, rollups, plans.iter().map(|p| (p.side, &p.hours))).await?;
+    for plan in plans {
+        rollups.set_coverage(RollupCoverage { resolution: plan.side, from: plan.from, through: now, backfilled: plan.backfilled }).await?;
+    }
+    Ok(computed)
+}
+
+/// Brings the rollups in line after points between `since` and `until` (both inclusive) were
+/// deleted: the covered hours among them are recomputed, or, when there are too many, dropped
+/// from the coverage for the backfill to redo. Map requests over them scan the points until then.
+pub async fn invalidate(store: &dyn PointStore, rollups: &dyn TileStatsStore, since: DateTime<Utc>, until: DateTime<Utc>) -> StoreResult<usize> {
+    let _running = RUNNING.lock().await;
+    let mut grids = Vec::new();
+    for c in rollups.rollup_coverage().await? {
+        let hours: BTreeSet<_> = hour_range(floor_hour(since).max(c.from), (floor_hour(until) + Duration::hours(1)).min(c.through)).collect();
+        if hours.is_empty() {
+            continue;
+        }
+        if hours.len() > MAX_RECOMPUTED_HOURS {
+            let from = (floor_hour(until) + Duration::hours(1)).min(c.through);
+            info!("Dropping {} deleted hours from the rollup of {}° tiles", hours.len(), c.resolution as f64 / MICRODEGREES);
+            rollups.set_coverage(RollupCoverage { from, backfilled: false, ..c }).await?;
+        } else {
+            grids.push((c.resolution, hours));
+        }
+    }
+    compute(store, rollups, grids.iter().map(|(side, hours)| (*side, hours))).await
+}
+
+/// Recomputes `hours` of each grid; returns the number of (grid, hour) pairs computed. Each
+/// hour's points are read once for all grids.
+async fn compute<'a>(
+    store: &dyn PointStore,
+    rollups: &dyn TileStatsStore,
+    grids: impl Iterator<Item = (i32, &'a BTreeSet<DateTime<Utc>>)>,
+) -> StoreResult<usize> {
+    let grids: Vec<_> = grids.collect();
+    let all_hours: BTreeSet<_> = grids.iter().flat_map(|(_, hours)| hours.iter().copied()).collect();
+    let mut computed = 0;
+    for hour in all_hours {
+        let filter = PointFilter { since: Some(hour), until: Some(hour + Duration::hours(1) - Duration::microseconds(1)), ..Default::default() };
+        let points = store.find(&filter, PointOrder::TimestampAsc, None).await?;
+        for &(resolution, _) in grids.iter().filter(|(_, hours)| hours.contains(&hour)) {
+            let side = resolution as f64 / MICRODEGREES;
+            let mut tiles: HashMap<(i32, i32), TileStat> = HashMap::new();
+            for p in &points {
+                let (row, col) = ((p.lat / side).floor() as i32, (p.lng / side).floor() as i32);
+                let stat = tiles.entry((row, col)).or_insert(TileStat { row, col, points: 0, speed_sum: 0.0 });
+                stat.points += 1;
+                stat.speed_sum += p.spd;
+            }
+            rollups.replace_hour(resolution, hour, tiles.into_values().collect()).await?;
+            computed += 1;
+        }
+    }
+    Ok(computed)
+}
+
+pub fn floor_hour(t: DateTime<Utc>) -> DateTime<Utc> {
+    t.duration_trunc(Duration::hours(1)).unwrap_or(t)
+}
+
+/// Whole hours in `[from, until)`
+fn hour_range(from: DateTime<Utc>, until: DateTime<Utc>) -> impl Iterator<Item = DateTime<Utc>> {
+    std::iter::successors(Some(from), |h| Some(*h + Duration::hours(1))).take_while(move |h| *h < until)
+}
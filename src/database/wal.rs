@@ -0,0 +1,157 @@
+use log::{error, info, warn};
+use std::env;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+use crate::anomaly::ClassificationQueue;
+use crate::metrics::metrics;
+use crate::database::store::{CircuitBreaker, NewPointRecord, PointFilter, PointOrder, PointStore, StoreResult};
+
+/// How often buffered points are retried against the database
+const REPLAY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Points accepted while the database was unreachable, one JSON record per line.
+/// Lives at INGEST_WAL_PATH (default `data/ingest-wal.ndjson`, `off` disables buffering), next
+/// to the `.replaying` file of a replay in progress.
+pub struct Wal {
+    path: PathBuf,
+    /// Records being replayed: replay moves the WAL here, so appends go on into a fresh file.
+    /// Left over from an interrupted replay, it is finished before the WAL is moved again.
+    replaying: PathBuf,
+    /// Serializes appends against replay moving the file
+    lock: Mutex<()>,
+    /// One replay at a time
+    replay: Mutex<()>,
+}
+
+impl Wal {
+    pub fn from_env() -> io::Result<Option<Self>> {
+        let path = env::var("INGEST_WAL_PATH").unwrap_or_else(|_| "data/ingest-wal.ndjson".to_string());
+        if path.is_empty() || path == "off" {
+            return Ok(None);
+        }
+        Self::open(PathBuf::from(path)).map(Some)
+    }
+
+    /// A WAL at `path`, creating its directory
+    pub fn open(path: PathBuf) -> io::Result<Self> {
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        Ok(Self { replaying: path.with_extension("replaying"), path, lock: Mutex::new(()), replay: Mutex::new(()) })
+    }
+
+    pub fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+
+    /// Appends records and syncs them to disk before returning
+    pub async fn append(&self, records: &[NewPointRecord]) -> io::Result<()> {
+        let mut out = String::new();
+        for record in records {
+            out.push_str(&serde_json::to_string(record)?);
+            out.push('\n');
+        }
+        let _guard = self.lock.lock().await;
+        let mut file = fs::OpenOptions::new().create(true).append(true).open(&self.path).await?;
+        file.write_all(out.as_bytes()).await?;
+        file.sync_data().await
+    }
+
+    /// Inserts buffered records in order. Stops at the first failure and keeps that record
+    /// and everything after it for the next attempt. Returns the number of records stored.
+    /// Appends only wait for the WAL to be moved aside, not for the inserts.
+    pub async fn replay(&self, store: &dyn PointStore, queue: &ClassificationQueue) -> io::Result<usize> {
+        let _replay = self.replay.lock().await;
+        if !fs::try_exists(&self.replaying).await? {
+            let _guard = self.lock.lock().await;
+            match fs::rename(&self.path, &self.replaying).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+                Err(e) => return Err(e),
+            }
+        }
+        let text = fs::read_to_string(&self.replaying).await?;
+        let lines: Vec<&str> = text.lines().filter(|l| !l.trim().is_empty()).collect();
+
+        let mut done = 0;
+        for line in &lines {
+            let record: NewPointRecord = match serde_json::from_str(line) {
+                Ok(r) => r,
+                Err(e) => {
+                    // A torn write from a crash; nothing to recover from it
+                    warn!("Dropping unreadable WAL line: {}", e);
+                    done += 1;
+                    continue;
+                }
+            };
+            match is_stored(store, &record).await {
+                Ok(true) => {
+                    done += 1;
+                    continue;
+                }
+                Ok(false) => {}
+                Err(e) => {
+                    warn!("WAL replay paused: {}", e);
+                    break;
+                }
+            }
+            if let Err(e) = queue.ingest(store, record).await {
+                warn!("WAL replay paused: {}", e);
+                break;
+            }
+            done += 1;
+        }
+
+        let rest = &lines[done..];
+        if rest.is_empty() {
+            fs::remove_file(&self.replaying).await?;
+        } else if done > 0 {
+            // Rewrite through a temp file so a crash here leaves either version intact
+            let tmp = self.path.with_extension("tmp");
+            let mut body = rest.join("\n");
+            body.push('\n');
+            fs::write(&tmp, body).await?;
+            fs::rename(&tmp, &self.replaying).await?;
+        }
+        Ok(done)
+    }
+}
+
+/// Whether a record carrying a client UUID made it into the store already
+pub(crate) async fn is_stored(store: &dyn PointStore, record: &NewPointRecord) -> StoreResult<bool> {
+    let Some(uuid) = record.client_uuid else {
+        return Ok(false);
+    };
+    let filter = PointFilter { client_uuid: Some(uuid), ..Default::default() };
+    Ok(!store.find(&filter, PointOrder::IdAsc, Some(1)).await?.is_empty())
+}
+
+/// Retries buffered points every few seconds while the breaker lets calls through
+pub fn spawn_replay(wal: Arc<Wal>, store: Arc<dyn PointStore>, queue: ClassificationQueue, breaker: Arc<CircuitBreaker>) {
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(REPLAY_INTERVAL);
+        loop {
+            tick.tick().await;
+            if breaker.is_open() {
+                continue;
+            }
+            match wal.replay(store.as_ref(), &queue).await {
+                Ok(0) => {}
+                Ok(n) => {
+                    info!("Replayed {} buffered points from {}", n, wal.path.display());
+                    metrics().record_job("WAL replay", true, format!("replayed {} buffered points", n));
+                }
+                Err(e) => {
+                    error!("WAL replay failed on {}: {}", wal.path.display(), e);
+                    metrics().record_job("WAL replay", false, e.to_string());
+                }
+            }
+        }
+    });
+}
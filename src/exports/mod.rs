@@ -0,0 +1,261 @@
+//! Exports of stored points to S3-compatible object storage. `POST /api/exports` starts a job
+//! (`jobs::Jobs`) and returns at once; the job writes the rows to a temporary file, uploads it
+//! and records where it went, so that large exports need not stream through one long HTTP
+//! response.
+
+pub mod parquet;
+pub mod s3;
+
+use log::{info, warn};
+use serde_json::{json, Value};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use uuid::Uuid;
+
+use crate::api::points::{write_export_row, ExportFormat, StoredPoint, EXPORT_BATCH};
+use crate::database::model::jobs::Model as JobModel;
+use crate::database::store::{PointFilter, PointStore, StoreResult};
+use crate::jobs::{Jobs, RunningJob};
+use crate::metrics::metrics;
+use self::parquet::{PointsParquet, ROW_GROUP};
+use self::s3::{S3Client, S3Config, MIN_PART_SIZE};
+
+/// Name of the worker on the status page, and kind of its jobs
+pub const JOB_NAME: &str = "export";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileFormat {
+    Csv,
+    Ndjson,
+    Parquet,
+}
+
+impl FileFormat {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "csv" => Some(FileFormat::Csv),
+            "ndjson" => Some(FileFormat::Ndjson),
+            "parquet" => Some(FileFormat::Parquet),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FileFormat::Csv => "csv",
+            FileFormat::Ndjson => "ndjson",
+            FileFormat::Parquet => "parquet",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            FileFormat::Csv => "text/csv",
+            FileFormat::Ndjson => "application/x-ndjson",
+            FileFormat::Parquet => "application/vnd.apache.parquet",
+        }
+    }
+}
+
+/// EXPORT_S3_BUCKET enables the subsystem (see `S3Config`). EXPORT_S3_PREFIX (default
+/// `exports/`) starts every object key, EXPORT_S3_PART_MB (default 16, at least 5) sizes the
+/// parts of multipart uploads, EXPORT_S3_URL_TTL_SECS (default 3600, at most 7 days) bounds the
+/// download links, and EXPORT_TMP_DIR (default the system temporary directory) is where files
+/// wait for the upload. How many run at once is up to JOBS_MAX_RUNNING.
+#[derive(Debug, Clone)]
+pub struct ExportConfig {
+    pub s3: S3Config,
+    pub prefix: String,
+    pub part_size: usize,
+    pub url_ttl: Duration,
+    pub tmp_dir: PathBuf,
+}
+
+impl ExportConfig {
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let Some(s3) = S3Config::from_env()? else {
+            return Ok(None);
+        };
+        let number = |name: &str, default: u64| env::var(name).ok().and_then(|v| v.trim().parse::<u64>().ok()).filter(|n| *n > 0).unwrap_or(default);
+        let prefix = env::var("EXPORT_S3_PREFIX").unwrap_or_else(|_| "exports/".to_string());
+        let prefix = prefix.trim().trim_start_matches('/').to_string();
+        let tmp_dir = env::var("EXPORT_TMP_DIR").ok().filter(|d| !d.trim().is_empty()).map(PathBuf::from).unwrap_or_else(env::temp_dir);
+        std::fs::create_dir_all(&tmp_dir).map_err(|e| format!("EXPORT_TMP_DIR {}: {}", tmp_dir.display(), e))?;
+        Ok(Some(Self {
+            s3,
+            prefix,
+            part_size: (number("EXPORT_S3_PART_MB", 16) as usize * 1024 * 1024).max(MIN_PART_SIZE),
+            url_ttl: Duration::from_secs(number("EXPORT_S3_URL_TTL_SECS", 3600).min(7 * 24 * 3600)),
+            tmp_dir,
+        }))
+    }
+}
+
+/// Starts export jobs and reads their outcome; one per process, shared by the handlers
+#[derive(Clone)]
+pub struct Exporter {
+    store: Arc<dyn PointStore>,
+    jobs: Jobs,
+    s3: Arc<S3Client>,
+    config: Arc<ExportConfig>,
+}
+
+impl Exporter {
+    /// `store` is the one the handlers read, so an export holds what `GET /api/points/export`
+    /// would
+    pub fn new(config: ExportConfig, store: Arc<dyn PointStore>, jobs: Jobs) -> Self {
+        info!("Exports to {}", config.s3.bucket);
+        Self { store, jobs, s3: Arc::new(S3Client::new(config.s3.clone())), config: Arc::new(config) }
+    }
+
+    /// Starts a job exporting the points matching `filter`; `query` is the request, recorded
+    /// with the format as the job's parameters
+    pub async fn start(&self, format: FileFormat, filter: PointFilter, mut query: Value) -> StoreResult<JobModel> {
+        if let Some(query) = query.as_object_mut() {
+            query.insert("format".to_string(), json!(format.as_str()));
+        }
+        let exporter = self.clone();
+        self.jobs.start(JOB_NAME, query, move |job| async move { exporter.run(job, format, filter).await }).await
+    }
+
+    /// The job `id` when it is an export
+    pub async fn find(&self, id: Uuid) -> StoreResult<Option<JobModel>> {
+        Ok(self.jobs.find(id).await?.filter(|job| job.kind == JOB_NAME))
+    }
+
+    /// `s3://bucket/key` of a finished job
+    pub fn location(&self, job: &JobModel) -> Option<String> {
+        object_key(job).map(|key| self.s3.location(key))
+    }
+
+    /// A presigned download link of a finished job, valid for EXPORT_S3_URL_TTL_SECS
+    pub fn download_url(&self, job: &JobModel) -> Option<String> {
+        object_key(job).filter(|_| job.status == "done").map(|key| self.s3.presigned_get(key, self.config.url_ttl))
+    }
+
+    /// Writes and uploads the file; the result names the object and its size
+    async fn run(self, job: RunningJob, format: FileFormat, filter: PointFilter) -> Result<Value, String> {
+        let started = Instant::now();
+        let path = self.config.tmp_dir.join(format!("indrive-export-{}.{}", job.id, format.as_str()));
+        let key = format!("{}{}/{}.{}", self.config.prefix, job.created_at.format("%Y-%m-%d"), job.id, format.as_str());
+        let result = async {
+            self.write_file(&job, format, &filter, &path).await?;
+            self.upload(format, &path, &key).await
+        }
+        .await;
+        if let Err(e) = tokio::fs::remove_file(&path).await
+            && e.kind() != std::io::ErrorKind::NotFound
+        {
+            warn!("Removing {} failed: {}", path.display(), e);
+        }
+
+        match result {
+            Ok(bytes) => {
+                info!("Export {} done: {} rows, {} bytes to {} in {:?}", job.id, job.progress(), bytes, self.s3.location(&key), started.elapsed());
+                metrics().record_job(JOB_NAME, true, format!("{} rows to {}", job.progress(), self.s3.location(&key)));
+                Ok(json!({"objectKey": key, "bytes": bytes}))
+            }
+            Err(e) => {
+                metrics().record_job(JOB_NAME, false, e.clone());
+                Err(e)
+            }
+        }
+    }
+
+    /// Writes every matching row to `path` in keyset pages, as `GET /api/points/export` reads them
+    async fn write_file(&self, job: &RunningJob, format: FileFormat, filter: &PointFilter, path: &Path) -> Result<(), String> {
+        let io = |e: std::io::Error| format!("{}: {}", path.display(), e);
+        let mut text = match format {
+            FileFormat::Csv | FileFormat::Ndjson => Some(tokio::io::BufWriter::new(tokio::fs::File::create(path).await.map_err(io)?)),
+            FileFormat::Parquet => None,
+        };
+        let mut columns = match format {
+            FileFormat::Parquet => Some(PointsParquet::new(std::fs::File::create(path).map_err(io)?).map_err(|e| e.to_string())?),
+            _ => None,
+        };
+        let line_format = if format == FileFormat::Csv { ExportFormat::Csv } else { ExportFormat::Ndjson };
+        if let Some(out) = &mut text {
+            out.write_all(line_format.header().as_bytes()).await.map_err(io)?;
+        }
+
+        let mut group = Vec::new();
+        let mut after_id = 0;
+        loop {
+            let rows = self.store.find_after(filter, after_id, EXPORT_BATCH).await.map_err(|e| e.to_string())?;
+            let Some(last_id) = rows.last().map(|r| r.id) else { break };
+            let full = rows.len() as u64 == EXPORT_BATCH;
+            job.advance(rows.len() as i64);
+            if let Some(out) = &mut text {
+                let mut buf = String::new();
+                for row in rows {
+                    write_export_row(&mut buf, line_format, &StoredPoint::from(row)).map_err(|e| e.to_string())?;
+                }
+                out.write_all(buf.as_bytes()).await.map_err(io)?;
+            } else {
+                group.extend(rows);
+            }
+            if let Some(columns) = &mut columns
+                && group.len() >= ROW_GROUP
+            {
+                columns.write(&group).map_err(|e| e.to_string())?;
+                group.clear();
+            }
+            if !full {
+                break;
+            }
+            after_id = last_id;
+        }
+
+        if let Some(mut out) = text {
+            out.flush().await.map_err(io)?;
+        }
+        if let Some(mut columns) = columns {
+            if !group.is_empty() {
+                columns.write(&group).map_err(|e| e.to_string())?;
+            }
+            columns.finish().map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// Uploads `path` in one request when it fits a part, else in parts; returns its size
+    async fn upload(&self, format: FileFormat, path: &Path, key: &str) -> Result<u64, String> {
+        let io = |e: std::io::Error| format!("{}: {}", path.display(), e);
+        let size = tokio::fs::metadata(path).await.map_err(io)?.len();
+        if size as usize <= self.config.part_size {
+            let body = tokio::fs::read(path).await.map_err(io)?;
+            self.s3.put_object(key, body, format.content_type()).await?;
+            return Ok(size);
+        }
+
+        let upload_id = self.s3.create_multipart(key, format.content_type()).await?;
+        let result = async {
+            let mut file = tokio::fs::File::open(path).await.map_err(io)?;
+            let mut etags = Vec::new();
+            loop {
+                let mut part = Vec::with_capacity(self.config.part_size);
+                (&mut file).take(self.config.part_size as u64).read_to_end(&mut part).await.map_err(io)?;
+                if part.is_empty() {
+                    break;
+                }
+                etags.push(self.s3.upload_part(key, &upload_id, etags.len() + 1, part).await?);
+            }
+            self.s3.complete_multipart(key, &upload_id, &etags).await
+        }
+        .await;
+        if result.is_err()
+            && let Err(e) = self.s3.abort_multipart(key, &upload_id).await
+        {
+            warn!("Aborting the upload of {} failed: {}", key, e);
+        }
+        result.map(|_| size)
+    }
+}
+
+/// Key of the object a finished export wrote
+fn object_key(job: &JobModel) -> Option<&str> {
+    job.result.as_ref().and_then(|r| r["objectKey"].as_str())
+}
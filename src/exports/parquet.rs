@@ -0,0 +1,178 @@
+//! Points and trips as Parquet files: one row group per call to `write`, columns named like the
+//! model fields (and like the CSV export for points), timestamps in UTC microseconds and
+//! compressed with zstd, so that Spark and pandas load them without parsing text.
+
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::data_type::{BoolType, ByteArray, ByteArrayType, DataType, DoubleType, Int64Type};
+use parquet::errors::Result;
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::{SerializedColumnWriter, SerializedFileWriter, SerializedRowGroupWriter};
+use parquet::schema::parser::parse_message_type;
+use std::io::Write;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use crate::database::model::points::Model as PointModel;
+use crate::database::model::trips::Model as TripModel;
+
+/// Rows per row group: enough for the columns to compress well, few enough to hold in memory
+/// while a response streams
+pub const ROW_GROUP: usize = 50_000;
+
+/// Rows that can be written as a Parquet file
+pub trait ParquetRow: Sized {
+    const SCHEMA: &'static str;
+
+    /// Writes the columns of `rows` in the order of SCHEMA
+    fn write_columns<W: Write + Send>(rows: &[Self], group: &mut SerializedRowGroupWriter<'_, W>) -> Result<()>;
+}
+
+pub struct ParquetWriter<W: Write + Send, R: ParquetRow> {
+    writer: SerializedFileWriter<W>,
+    rows: PhantomData<R>,
+}
+
+pub type PointsParquet<W> = ParquetWriter<W, PointModel>;
+pub type TripsParquet<W> = ParquetWriter<W, TripModel>;
+
+impl<W: Write + Send, R: ParquetRow> ParquetWriter<W, R> {
+    pub fn new(out: W) -> Result<Self> {
+        let schema = Arc::new(parse_message_type(R::SCHEMA)?);
+        let props = WriterProperties::builder().set_compression(Compression::ZSTD(ZstdLevel::default())).build();
+        Ok(Self { writer: SerializedFileWriter::new(out, schema, Arc::new(props))?, rows: PhantomData })
+    }
+
+    /// Writes `rows` as one row group
+    pub fn write(&mut self, rows: &[R]) -> Result<()> {
+        let mut group = self.writer.next_row_group()?;
+        R::write_columns(rows, &mut group)?;
+        group.close()?;
+        Ok(())
+    }
+
+    /// Writes the footer, without which the file is unreadable, and hands back the output
+    pub fn finish(self) -> Result<W> {
+        self.writer.into_inner()
+    }
+}
+
+impl<R: ParquetRow> ParquetWriter<Vec<u8>, R> {
+    /// Bytes written since the last call, for sending the file while it is written
+    pub fn take_written(&mut self) -> Result<Vec<u8>> {
+        self.writer.flush()?;
+        Ok(std::mem::take(self.writer.inner_mut()))
+    }
+}
+
+impl ParquetRow for PointModel {
+    const SCHEMA: &'static str = "
+        message point {
+            REQUIRED INT64 id;
+            REQUIRED INT64 randomized_id;
+            REQUIRED DOUBLE lat;
+            REQUIRED DOUBLE lng;
+            REQUIRED DOUBLE alt;
+            REQUIRED DOUBLE spd;
+            REQUIRED DOUBLE azm;
+            OPTIONAL INT64 timestamp (TIMESTAMP(MICROS,true));
+            OPTIONAL BOOLEAN anomaly;
+            OPTIONAL BYTE_ARRAY uuid (STRING);
+            OPTIONAL DOUBLE anomaly_score;
+            OPTIONAL BYTE_ARRAY anomaly_reason (STRING);
+            OPTIONAL DOUBLE prev_distance_m;
+            OPTIONAL DOUBLE prev_interval_s;
+            OPTIONAL DOUBLE derived_speed;
+        }
+    ";
+
+    fn write_columns<W: Write + Send>(rows: &[Self], group: &mut SerializedRowGroupWriter<'_, W>) -> Result<()> {
+        column(group, |c| required::<Int64Type>(c, rows.iter().map(|p| p.id).collect()))?;
+        column(group, |c| required::<Int64Type>(c, rows.iter().map(|p| p.randomized_id).collect()))?;
+        let measured: [fn(&PointModel) -> f64; 5] = [|p| p.lat, |p| p.lng, |p| p.alt, |p| p.spd, |p| p.azm];
+        for field in measured {
+            column(group, |c| required::<DoubleType>(c, rows.iter().map(field).collect()))?;
+        }
+        column(group, |c| optional::<Int64Type>(c, rows.iter().map(|p| p.timestamp.map(|t| t.timestamp_micros())).collect()))?;
+        column(group, |c| optional::<BoolType>(c, rows.iter().map(|p| p.anomaly).collect()))?;
+        column(group, |c| optional::<ByteArrayType>(c, rows.iter().map(|p| p.client_uuid.map(|u| ByteArray::from(u.to_string().as_str()))).collect()))?;
+        column(group, |c| optional::<DoubleType>(c, rows.iter().map(|p| p.anomaly_score).collect()))?;
+        column(group, |c| optional::<ByteArrayType>(c, rows.iter().map(|p| p.anomaly_reason.as_deref().map(ByteArray::from)).collect()))?;
+        let derived: [fn(&PointModel) -> Option<f64>; 3] = [|p| p.prev_distance_m, |p| p.prev_interval_s, |p| p.derived_speed];
+        for field in derived {
+            column(group, |c| optional::<DoubleType>(c, rows.iter().map(field).collect()))?;
+        }
+        Ok(())
+    }
+}
+
+impl ParquetRow for TripModel {
+    const SCHEMA: &'static str = "
+        message trip {
+            REQUIRED INT64 randomized_id;
+            REQUIRED INT64 start_ts (TIMESTAMP(MICROS,true));
+            REQUIRED INT64 end_ts (TIMESTAMP(MICROS,true));
+            REQUIRED DOUBLE start_lat;
+            REQUIRED DOUBLE start_lng;
+            REQUIRED DOUBLE end_lat;
+            REQUIRED DOUBLE end_lng;
+            REQUIRED DOUBLE distance_m;
+            REQUIRED DOUBLE duration_s;
+            REQUIRED DOUBLE avg_speed;
+            REQUIRED DOUBLE max_speed;
+            REQUIRED INT64 point_count;
+            REQUIRED BOOLEAN anomaly;
+            REQUIRED INT64 anomaly_count;
+            OPTIONAL BOOLEAN anomaly_review;
+            OPTIONAL BYTE_ARRAY anomaly_reviewed_by (STRING);
+            OPTIONAL INT64 anomaly_reviewed_at (TIMESTAMP(MICROS,true));
+            OPTIONAL BYTE_ARRAY device_id (STRING);
+        }
+    ";
+
+    fn write_columns<W: Write + Send>(rows: &[Self], group: &mut SerializedRowGroupWriter<'_, W>) -> Result<()> {
+        column(group, |c| required::<Int64Type>(c, rows.iter().map(|t| t.randomized_id).collect()))?;
+        column(group, |c| required::<Int64Type>(c, rows.iter().map(|t| t.start_ts.timestamp_micros()).collect()))?;
+        column(group, |c| required::<Int64Type>(c, rows.iter().map(|t| t.end_ts.timestamp_micros()).collect()))?;
+        let measured: [fn(&TripModel) -> f64; 8] = [
+            |t| t.start_lat,
+            |t| t.start_lng,
+            |t| t.end_lat,
+            |t| t.end_lng,
+            |t| t.distance_m,
+            |t| t.duration_s,
+            |t| t.avg_speed,
+            |t| t.max_speed,
+        ];
+        for field in measured {
+            column(group, |c| required::<DoubleType>(c, rows.iter().map(field).collect()))?;
+        }
+        column(group, |c| required::<Int64Type>(c, rows.iter().map(|t| t.point_count).collect()))?;
+        column(group, |c| required::<BoolType>(c, rows.iter().map(|t| t.anomaly).collect()))?;
+        column(group, |c| required::<Int64Type>(c, rows.iter().map(|t| t.anomaly_count).collect()))?;
+        column(group, |c| optional::<BoolType>(c, rows.iter().map(|t| t.anomaly_review).collect()))?;
+        column(group, |c| optional::<ByteArrayType>(c, rows.iter().map(|t| t.anomaly_reviewed_by.as_deref().map(ByteArray::from)).collect()))?;
+        column(group, |c| optional::<Int64Type>(c, rows.iter().map(|t| t.anomaly_reviewed_at.map(|at| at.timestamp_micros())).collect()))?;
+        column(group, |c| optional::<ByteArrayType>(c, rows.iter().map(|t| t.device_id.as_deref().map(ByteArray::from)).collect()))?;
+        Ok(())
+    }
+}
+
+/// Writes the next column of the row group
+fn column<W: Write + Send>(group: &mut SerializedRowGroupWriter<'_, W>, write: impl FnOnce(&mut SerializedColumnWriter<'_>) -> Result<()>) -> Result<()> {
+    let mut column = group.next_column()?.expect("a writer for every column of SCHEMA");
+    write(&mut column)?;
+    column.close()
+}
+
+fn required<T: DataType>(column: &mut SerializedColumnWriter<'_>, values: Vec<T::T>) -> Result<()> {
+    column.typed::<T>().write_batch(&values, None, None)?;
+    Ok(())
+}
+
+/// Nulls are left out of the values and marked by a definition level of 0
+fn optional<T: DataType>(column: &mut SerializedColumnWriter<'_>, values: Vec<Option<T::T>>) -> Result<()> {
+    let levels: Vec<i16> = values.iter().map(|v| v.is_some() as i16).collect();
+    let present: Vec<T::T> = values.into_iter().flatten().collect();
+    column.typed::<T>().write_batch(&present, Some(&levels), None)?;
+    Ok(())
+}
@@ -0,0 +1,269 @@
+//! Just enough of the S3 API for exports: single and multipart uploads and presigned downloads,
+//! signed with AWS Signature Version 4 so that AWS, MinIO, Ceph and other compatible stores
+//! accept them.
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use reqwest::{Method, Url};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::time::Duration;
+
+/// Smallest part S3 accepts in a multipart upload, except for the last one
+pub const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+
+/// EXPORT_S3_BUCKET names the bucket. EXPORT_S3_ENDPOINT (e.g. `http://minio:9000`) points at a
+/// store other than AWS, which is then addressed path-style (`/<bucket>/<key>`) unless
+/// EXPORT_S3_PATH_STYLE=false; on AWS the bucket goes into the host name. EXPORT_S3_REGION
+/// (default `us-east-1`), EXPORT_S3_ACCESS_KEY_ID and EXPORT_S3_SECRET_ACCESS_KEY (falling back
+/// to AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY) sign the requests.
+#[derive(Debug, Clone)]
+pub struct S3Config {
+    pub endpoint: Url,
+    pub bucket: String,
+    pub region: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub path_style: bool,
+}
+
+impl S3Config {
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let var = |name: &str| env::var(name).ok().map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
+        let Some(bucket) = var("EXPORT_S3_BUCKET") else {
+            return Ok(None);
+        };
+        let region = var("EXPORT_S3_REGION").unwrap_or_else(|| "us-east-1".to_string());
+        let custom = var("EXPORT_S3_ENDPOINT");
+        let endpoint = custom.clone().unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", region));
+        let endpoint = Url::parse(endpoint.trim_end_matches('/')).map_err(|e| format!("EXPORT_S3_ENDPOINT '{}': {}", endpoint, e))?;
+        let path_style = match var("EXPORT_S3_PATH_STYLE").as_deref() {
+            None => custom.is_some(),
+            Some("true") => true,
+            Some("false") => false,
+            Some(other) => return Err(format!("EXPORT_S3_PATH_STYLE '{}': expected true or false", other)),
+        };
+        let access_key_id = var("EXPORT_S3_ACCESS_KEY_ID").or_else(|| var("AWS_ACCESS_KEY_ID"));
+        let secret_access_key = var("EXPORT_S3_SECRET_ACCESS_KEY").or_else(|| var("AWS_SECRET_ACCESS_KEY"));
+        let (Some(access_key_id), Some(secret_access_key)) = (access_key_id, secret_access_key) else {
+            return Err("EXPORT_S3_BUCKET needs EXPORT_S3_ACCESS_KEY_ID and EXPORT_S3_SECRET_ACCESS_KEY".to_string());
+        };
+        Ok(Some(Self { endpoint, bucket, region, access_key_id, secret_access_key, path_style }))
+    }
+}
+
+/// Credentials and scope of SigV4 signatures
+#[derive(Debug, Clone)]
+pub struct Signer {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub region: String,
+}
+
+impl Signer {
+    /// `Authorization` header of a request. `headers` are the headers to sign, lowercase, `host`
+    /// and `x-amz-date` included; `path` and `query` as they go on the wire.
+    pub fn authorization(&self, method: &str, path: &str, query: &[(String, String)], headers: &[(&str, String)], payload_sha256: &str, at: DateTime<Utc>) -> String {
+        let mut headers = headers.to_vec();
+        headers.sort_by(|a, b| a.0.cmp(b.0));
+        let signed_headers = headers.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(";");
+        let canonical_headers: String = headers.iter().map(|(name, value)| format!("{}:{}\n", name, value.trim())).collect();
+        let canonical = format!("{}\n{}\n{}\n{}\n{}\n{}", method, path, canonical_query(query), canonical_headers, signed_headers, payload_sha256);
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key_id,
+            self.scope(at),
+            signed_headers,
+            self.signature(&canonical, at),
+        )
+    }
+
+    /// Query string of a presigned GET of `path` on `host`, valid for `expires`
+    pub fn presigned_query(&self, path: &str, host: &str, at: DateTime<Utc>, expires: Duration) -> String {
+        let query = [
+            ("X-Amz-Algorithm".to_string(), "AWS4-HMAC-SHA256".to_string()),
+            ("X-Amz-Credential".to_string(), format!("{}/{}", self.access_key_id, self.scope(at))),
+            ("X-Amz-Date".to_string(), amz_date(at)),
+            ("X-Amz-Expires".to_string(), expires.as_secs().to_string()),
+            ("X-Amz-SignedHeaders".to_string(), "host".to_string()),
+        ];
+        let canonical = format!("GET\n{}\n{}\nhost:{}\n\nhost\nUNSIGNED-PAYLOAD", path, canonical_query(&query), host);
+        format!("{}&X-Amz-Signature={}", canonical_query(&query), self.signature(&canonical, at))
+    }
+
+    fn scope(&self, at: DateTime<Utc>) -> String {
+        format!("{}/{}/s3/aws4_request", at.format("%Y%m%d"), self.region)
+    }
+
+    fn signature(&self, canonical_request: &str, at: DateTime<Utc>) -> String {
+        let string_to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}", amz_date(at), self.scope(at), sha256_hex(canonical_request.as_bytes()));
+        let mut key = hmac(format!("AWS4{}", self.secret_access_key).as_bytes(), at.format("%Y%m%d").to_string().as_bytes());
+        for part in [self.region.as_str(), "s3", "aws4_request"] {
+            key = hmac(&key, part.as_bytes());
+        }
+        hex::encode(hmac(&key, string_to_sign.as_bytes()))
+    }
+}
+
+pub struct S3Client {
+    config: S3Config,
+    signer: Signer,
+    client: reqwest::Client,
+}
+
+impl S3Client {
+    pub fn new(config: S3Config) -> Self {
+        let signer = Signer {
+            access_key_id: config.access_key_id.clone(),
+            secret_access_key: config.secret_access_key.clone(),
+            region: config.region.clone(),
+        };
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(300))
+            .build()
+            .expect("Failed to build S3 HTTP client");
+        Self { config, signer, client }
+    }
+
+    /// `s3://bucket/key`, for display
+    pub fn location(&self, key: &str) -> String {
+        format!("s3://{}/{}", self.config.bucket, key)
+    }
+
+    pub async fn put_object(&self, key: &str, body: Vec<u8>, content_type: &str) -> Result<(), String> {
+        self.send(Method::PUT, key, &[], body, Some(content_type)).await.map(drop)
+    }
+
+    /// Starts a multipart upload; returns its upload id
+    pub async fn create_multipart(&self, key: &str, content_type: &str) -> Result<String, String> {
+        let query = [("uploads".to_string(), String::new())];
+        let resp = self.send(Method::POST, key, &query, Vec::new(), Some(content_type)).await?;
+        let body = resp.text().await.map_err(|e| e.to_string())?;
+        xml_text(&body, "UploadId").ok_or_else(|| format!("no UploadId in {}", body))
+    }
+
+    /// Uploads part `number` (from 1); returns its ETag
+    pub async fn upload_part(&self, key: &str, upload_id: &str, number: usize, body: Vec<u8>) -> Result<String, String> {
+        let query = [("partNumber".to_string(), number.to_string()), ("uploadId".to_string(), upload_id.to_string())];
+        let resp = self.send(Method::PUT, key, &query, body, None).await?;
+        let etag = resp.headers().get("etag").and_then(|e| e.to_str().ok()).map(str::to_string);
+        etag.ok_or_else(|| format!("no ETag for part {}", number))
+    }
+
+    pub async fn complete_multipart(&self, key: &str, upload_id: &str, etags: &[String]) -> Result<(), String> {
+        let parts: String = etags
+            .iter()
+            .enumerate()
+            .map(|(i, etag)| format!("<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>", i + 1, xml_escape(etag)))
+            .collect();
+        let body = format!("<CompleteMultipartUpload>{}</CompleteMultipartUpload>", parts);
+        let query = [("uploadId".to_string(), upload_id.to_string())];
+        let resp = self.send(Method::POST, key, &query, body.into_bytes(), Some("application/xml")).await?;
+        // S3 may answer 200 and report the failure in the body
+        let text = resp.text().await.map_err(|e| e.to_string())?;
+        if text.contains("<Error>") {
+            return Err(format!("completing the upload failed: {}", text));
+        }
+        Ok(())
+    }
+
+    /// Drops the parts of an upload that will not be completed
+    pub async fn abort_multipart(&self, key: &str, upload_id: &str) -> Result<(), String> {
+        let query = [("uploadId".to_string(), upload_id.to_string())];
+        self.send(Method::DELETE, key, &query, Vec::new(), None).await.map(drop)
+    }
+
+    /// A URL that downloads `key` without credentials until `expires` has passed
+    pub fn presigned_get(&self, key: &str, expires: Duration) -> String {
+        let (url, host, path) = self.object_url(key);
+        let query = self.signer.presigned_query(&path, &host, Utc::now(), expires);
+        format!("{}?{}", url, query)
+    }
+
+    /// URL without query, `host` header and canonical path of an object
+    fn object_url(&self, key: &str) -> (String, String, String) {
+        let endpoint = &self.config.endpoint;
+        let mut host = endpoint.host_str().unwrap_or_default().to_string();
+        let mut path = endpoint.path().trim_end_matches('/').to_string();
+        if self.config.path_style {
+            path.push('/');
+            path.push_str(&uri_encode(&self.config.bucket, true));
+        } else {
+            host = format!("{}.{}", self.config.bucket, host);
+        }
+        path.push('/');
+        path.push_str(&uri_encode(key, false));
+        if let Some(port) = endpoint.port() {
+            host = format!("{}:{}", host, port);
+        }
+        (format!("{}://{}{}", endpoint.scheme(), host, path), host, path)
+    }
+
+    async fn send(&self, method: Method, key: &str, query: &[(String, String)], body: Vec<u8>, content_type: Option<&str>) -> Result<reqwest::Response, String> {
+        let (url, host, path) = self.object_url(key);
+        let now = Utc::now();
+        let payload = sha256_hex(&body);
+        let mut headers = vec![("host", host), ("x-amz-content-sha256", payload.clone()), ("x-amz-date", amz_date(now))];
+        if let Some(content_type) = content_type {
+            headers.push(("content-type", content_type.to_string()));
+        }
+        let authorization = self.signer.authorization(method.as_str(), &path, query, &headers, &payload, now);
+        let url = if query.is_empty() { url } else { format!("{}?{}", url, canonical_query(query)) };
+        let mut req = self.client.request(method.clone(), &url).header("authorization", authorization).body(body);
+        for (name, value) in headers.into_iter().filter(|(name, _)| *name != "host") {
+            req = req.header(name, value);
+        }
+        let resp = req.send().await.map_err(|e| format!("{} {}: {}", method, url, e))?;
+        let status = resp.status();
+        if !status.is_success() {
+            let text = resp.text().await.unwrap_or_default();
+            return Err(format!("{} {} answered {}: {}", method, url, status, text.trim()));
+        }
+        Ok(resp)
+    }
+}
+
+fn amz_date(at: DateTime<Utc>) -> String {
+    at.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent-encoding of SigV4: everything but `A-Za-z0-9-_.~`, and `/` too unless it is a path
+fn uri_encode(s: &str, encode_slash: bool) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => out.push(b as char),
+            b'/' if !encode_slash => out.push('/'),
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+/// Sorted and encoded, as SigV4 signs it and as it is sent
+fn canonical_query(query: &[(String, String)]) -> String {
+    let mut pairs: Vec<(String, String)> = query.iter().map(|(k, v)| (uri_encode(k, true), uri_encode(v, true))).collect();
+    pairs.sort();
+    pairs.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>().join("&")
+}
+
+/// Text of the first `<tag>` element
+fn xml_text(xml: &str, tag: &str) -> Option<String> {
+    let start = xml.find(&format!("<{}>", tag))? + tag.len() + 2;
+    let end = start + xml[start..].find(&format!("</{}>", tag))?;
+    Some(xml[start..end].replace("&quot;", "\"").replace("&lt;", "<").replace("&gt;", ">").replace("&amp;", "&"))
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
@@ -0,0 +1,135 @@
+//! Fan-out of events to the Server-Sent Events streams (`api::sse`), with the latest ones kept
+//! so that a client reconnecting with `Last-Event-ID` gets what it missed. Held in memory only:
+//! after a restart there is nothing to resume from. The streams are public, so with a
+//! publication delay an event waits until the data it reveals may be published.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+use crate::database::store::{BBox, PublicationDelay, TenantScope};
+
+/// How often held events are checked for release
+const RELEASE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What a feed carries
+pub trait FeedEvent: Serialize + Send + Sync + 'static {
+    /// Grows with every event, so it doubles as the event id of the stream
+    fn event_id(&self) -> i64;
+
+    /// Whether a client in `scope` may see the event; None sees everything
+    fn visible_in(&self, scope: Option<&TenantScope>) -> bool;
+
+    /// Area and time of the latest data the event reveals, for the publication delay; None
+    /// for an area the event does not tie to one, None for a time when it happened just now
+    fn reveals(&self) -> (Option<BBox>, Option<DateTime<Utc>>);
+}
+
+pub struct Feed<T> {
+    tx: broadcast::Sender<Arc<T>>,
+    recent: Mutex<VecDeque<Arc<T>>>,
+    capacity: usize,
+    heartbeat: Duration,
+    delay: PublicationDelay,
+    /// Events published but not yet released, in publish order with the time each may go
+    held: Mutex<VecDeque<(DateTime<Utc>, T)>>,
+}
+
+impl<T: FeedEvent> Feed<T> {
+    /// Keeps `capacity` events for resuming; `heartbeat` spaces the keep-alive comments of the
+    /// streams
+    pub fn new(capacity: usize, heartbeat: Duration) -> Self {
+        let (tx, _) = broadcast::channel(capacity.max(1));
+        Self {
+            tx,
+            recent: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+            heartbeat,
+            delay: PublicationDelay::default(),
+            held: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Holds every event back by `delay` (see `release`)
+    pub fn with_delay(mut self, delay: PublicationDelay) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    pub fn heartbeat(&self) -> Duration {
+        self.heartbeat
+    }
+
+    pub fn publish(&self, event: T) {
+        if !self.delay.is_enabled() {
+            self.send(event);
+            return;
+        }
+        let (area, latest) = event.reveals();
+        let at = self.delay.release_at(area.as_ref(), latest.unwrap_or_else(Utc::now));
+        self.held.lock().unwrap_or_else(|e| e.into_inner()).push_back((at, event));
+        self.release(Utc::now());
+    }
+
+    /// Sends the held events whose delay is over at `now`. Events go out in the order they were
+    /// published, as event ids must grow along the stream, so one under a longer regional delay
+    /// keeps those behind it waiting. Returns how many were sent.
+    pub fn release(&self, now: DateTime<Utc>) -> usize {
+        let mut held = self.held.lock().unwrap_or_else(|e| e.into_inner());
+        let mut sent = 0;
+        // Sent under the lock, so concurrent releases keep the order
+        while held.front().is_some_and(|(at, _)| *at <= now) {
+            if let Some((_, event)) = held.pop_front() {
+                self.send(event);
+                sent += 1;
+            }
+        }
+        sent
+    }
+
+    /// Releases held events as their delay runs out, when there is a delay
+    pub fn spawn_releases(feed: Arc<Self>) {
+        if !feed.delay.is_enabled() {
+            return;
+        }
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(RELEASE_INTERVAL);
+            loop {
+                ticker.tick().await;
+                feed.release(Utc::now());
+            }
+        });
+    }
+
+    fn send(&self, event: T) {
+        let event = Arc::new(event);
+        let mut recent = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+        recent.push_back(event.clone());
+        while recent.len() > self.capacity {
+            recent.pop_front();
+        }
+        // Sent under the lock, so `subscribe` never sees an event both kept and on the way
+        let _ = self.tx.send(event);
+    }
+
+    /// Kept events after event id `after` (all of them for None), oldest first
+    pub fn since(&self, after: Option<i64>) -> Vec<Arc<T>> {
+        let recent = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+        recent.iter().filter(|e| after.is_none_or(|a| e.event_id() > a)).cloned().collect()
+    }
+
+    /// Events to replay for a client that last saw `after` (none for a new client) and a
+    /// receiver of the ones published from then on
+    pub fn subscribe(&self, after: Option<i64>) -> (Vec<Arc<T>>, broadcast::Receiver<Arc<T>>) {
+        let recent = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+        let rx = self.tx.subscribe();
+        let replay = match after {
+            Some(a) => recent.iter().filter(|e| e.event_id() > a).cloned().collect(),
+            None => Vec::new(),
+        };
+        (replay, rx)
+    }
+}
@@ -0,0 +1,51 @@
+//! Distances and degree/meter conversions on a spherical Earth
+
+/// Mean Earth radius used by every distance in the service
+pub const EARTH_RADIUS_M: f64 = 6_371_000.0;
+
+/// Meters per degree of latitude (and of longitude at the equator)
+pub const METERS_PER_DEGREE: f64 = EARTH_RADIUS_M * std::f64::consts::PI / 180.0;
+
+/// Great-circle distance in meters
+pub fn haversine_m(lat1: f64, lng1: f64, lat2: f64, lng2: f64) -> f64 {
+    let (phi1, phi2) = (lat1.to_radians(), lat2.to_radians());
+    let d_phi = (lat2 - lat1).to_radians();
+    let d_lambda = (lng2 - lng1).to_radians();
+    let a = (d_phi / 2.0).sin().powi(2) + phi1.cos() * phi2.cos() * (d_lambda / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_M * a.sqrt().asin()
+}
+
+/// Degrees of latitude spanning `meters` north-south
+pub fn meters_to_lat_deg(meters: f64) -> f64 {
+    meters / METERS_PER_DEGREE
+}
+
+/// Degrees of longitude spanning `meters` east-west at latitude `lat`.
+/// Capped at 360 near the poles where a degree of longitude shrinks to nothing.
+pub fn meters_to_lng_deg(meters: f64, lat: f64) -> f64 {
+    let scale = lat.to_radians().cos().abs();
+    if scale * 360.0 * METERS_PER_DEGREE <= meters {
+        return 360.0;
+    }
+    meters / (METERS_PER_DEGREE * scale)
+}
+
+/// Longitude past ±180 (at most one turn) brought back into [-180, 180]
+pub fn wrap_lng(lng: f64) -> f64 {
+    if lng > 180.0 {
+        lng - 360.0
+    } else if lng < -180.0 {
+        lng + 360.0
+    } else {
+        lng
+    }
+}
+
+/// Initial great-circle bearing from the first point to the second, degrees clockwise from north in [0, 360)
+pub fn bearing_deg(lat1: f64, lng1: f64, lat2: f64, lng2: f64) -> f64 {
+    let (phi1, phi2) = (lat1.to_radians(), lat2.to_radians());
+    let d_lambda = (lng2 - lng1).to_radians();
+    let y = d_lambda.sin() * phi2.cos();
+    let x = phi1.cos() * phi2.sin() - phi1.sin() * phi2.cos() * d_lambda.cos();
+    y.atan2(x).to_degrees().rem_euclid(360.0)
+}
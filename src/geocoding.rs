@@ -0,0 +1,254 @@
+//! Street, district and city names of positions, asked of a Nominatim or Photon server and kept
+//! in `geocode_cache`, so that anomaly routes and stop hotspots (`geocode=true`) read without
+//! opening the map.
+
+use chrono::Utc;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use utoipa::ToSchema;
+
+use crate::database::model::geocode_cache::Model as GeocodeModel;
+use crate::database::store::GeocodeCacheStore;
+
+/// Cache cells per degree: positions within about 11 m share an answer
+const CELLS_PER_DEGREE: f64 = 10_000.0;
+
+/// Address keys of a Nominatim answer, most specific first
+const NOMINATIM_STREET: [&str; 4] = ["road", "pedestrian", "square", "footway"];
+const NOMINATIM_DISTRICT: [&str; 6] = ["city_district", "district", "borough", "suburb", "quarter", "neighbourhood"];
+const NOMINATIM_CITY: [&str; 4] = ["city", "town", "village", "municipality"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Provider {
+    /// `GET /reverse?format=jsonv2` (https://nominatim.org/release-docs/latest/api/Reverse/)
+    Nominatim,
+    /// `GET /reverse`, answering GeoJSON (https://github.com/komoot/photon)
+    Photon,
+}
+
+/// Names of a position, as far as the geocoder knows them
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Place {
+    pub street: Option<String>,
+    pub district: Option<String>,
+    pub city: Option<String>,
+    /// The known names joined, e.g. `проспект Абая, Алмалинский район, Алматы`
+    pub label: String,
+}
+
+impl Place {
+    /// None when none of the names is known
+    fn new(street: Option<String>, district: Option<String>, city: Option<String>) -> Option<Self> {
+        let clean = |n: Option<String>| n.map(|n| n.trim().to_string()).filter(|n| !n.is_empty());
+        let (street, district, city) = (clean(street), clean(district), clean(city));
+        let mut names: Vec<&str> = Vec::new();
+        for name in [&street, &district, &city].into_iter().flatten() {
+            if !names.contains(&name.as_str()) {
+                names.push(name);
+            }
+        }
+        if names.is_empty() {
+            return None;
+        }
+        let label = names.join(", ");
+        Some(Self { street, district, city, label })
+    }
+
+    fn of_cache(row: &GeocodeModel) -> Option<Self> {
+        let label = row.label.clone()?;
+        Some(Self { street: row.street.clone(), district: row.district.clone(), city: row.city.clone(), label })
+    }
+}
+
+/// GEOCODER_URL (e.g. `http://nominatim:8080`) enables the subsystem; GEOCODER_PROVIDER is
+/// `nominatim` (default) or `photon`, GEOCODER_LANGUAGE the preferred language of the names.
+/// GEOCODER_MIN_INTERVAL_MS (default 1000, the usage policy of the public Nominatim) spaces
+/// the requests, GEOCODER_MAX_LOOKUPS (default 20) bounds the positions missing from the cache
+/// that one response asks about, and GEOCODER_TIMEOUT_SECS (default 5) limits each request.
+#[derive(Debug, Clone)]
+pub struct GeocoderConfig {
+    pub url: String,
+    pub provider: Provider,
+    pub language: Option<String>,
+    pub min_interval: Duration,
+    pub max_lookups: usize,
+    pub timeout: Duration,
+}
+
+impl GeocoderConfig {
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let Some(url) = env::var("GEOCODER_URL").ok().map(|u| u.trim().to_string()).filter(|u| !u.is_empty()) else {
+            return Ok(None);
+        };
+        let provider = match env::var("GEOCODER_PROVIDER").as_deref().map(str::trim) {
+            Err(_) | Ok("") | Ok("nominatim") => Provider::Nominatim,
+            Ok("photon") => Provider::Photon,
+            Ok(other) => return Err(format!("GEOCODER_PROVIDER '{}': expected nominatim or photon", other)),
+        };
+        let number = |name: &str, default: u64| env::var(name).ok().and_then(|v| v.parse::<u64>().ok()).unwrap_or(default);
+        Ok(Some(Self {
+            url: url.trim_end_matches('/').to_string(),
+            provider,
+            language: env::var("GEOCODER_LANGUAGE").ok().map(|l| l.trim().to_string()).filter(|l| !l.is_empty()),
+            min_interval: Duration::from_millis(number("GEOCODER_MIN_INTERVAL_MS", 1000)),
+            max_lookups: number("GEOCODER_MAX_LOOKUPS", 20) as usize,
+            timeout: Duration::from_secs(number("GEOCODER_TIMEOUT_SECS", 5).max(1)),
+        }))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct NominatimAnswer {
+    /// `Unable to geocode` where there is nothing to name
+    error: Option<String>,
+    #[serde(default)]
+    address: HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PhotonAnswer {
+    #[serde(default)]
+    features: Vec<PhotonFeature>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PhotonFeature {
+    properties: PhotonProperties,
+}
+
+#[derive(Debug, Deserialize)]
+struct PhotonProperties {
+    name: Option<String>,
+    street: Option<String>,
+    district: Option<String>,
+    locality: Option<String>,
+    city: Option<String>,
+}
+
+/// Reverse geocoder in front of `geocode_cache`; one per process, shared by the handlers
+pub struct Geocoder {
+    config: GeocoderConfig,
+    client: reqwest::Client,
+    cache: Arc<dyn GeocodeCacheStore>,
+    /// When the last request went out; held across a request, so requests go one at a time
+    last_request: Mutex<Option<Instant>>,
+}
+
+impl Geocoder {
+    pub fn new(config: GeocoderConfig, cache: Arc<dyn GeocodeCacheStore>) -> Self {
+        // Nominatim turns away clients that do not name themselves
+        let client = reqwest::Client::builder()
+            .timeout(config.timeout)
+            .user_agent(concat!("indrive/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .expect("Failed to build geocoder HTTP client");
+        info!("Reverse geocoding via {} ({:?})", config.url, config.provider);
+        Self { config, client, cache, last_request: Mutex::new(None) }
+    }
+
+    /// Places of `positions` (lat, lng), in the same order. Positions missing from the cache are
+    /// asked about in order until GEOCODER_MAX_LOOKUPS is spent or the geocoder fails, so the
+    /// most important ones go first; the rest stay None until a later request.
+    pub async fn places(&self, positions: &[(f64, f64)]) -> Vec<Option<Place>> {
+        let cells: Vec<(i32, i32)> = positions.iter().map(|&(lat, lng)| cell(lat, lng)).collect();
+        let mut unique = cells.clone();
+        unique.sort_unstable();
+        unique.dedup();
+        let mut known: HashMap<(i32, i32), Option<Place>> = match self.cache.find_places(&unique).await {
+            Ok(rows) => rows.iter().map(|r| ((r.cell_lat, r.cell_lng), Place::of_cache(r))).collect(),
+            Err(e) => {
+                warn!("Geocode cache lookup failed: {}", e);
+                HashMap::new()
+            }
+        };
+        let mut budget = self.config.max_lookups;
+        for &c in &cells {
+            if known.contains_key(&c) {
+                continue;
+            }
+            if budget == 0 {
+                break;
+            }
+            budget -= 1;
+            let place = match self.reverse(c).await {
+                Ok(p) => p,
+                Err(e) => {
+                    warn!("Reverse geocoding of {:.4},{:.4} failed: {}", c.0 as f64 / CELLS_PER_DEGREE, c.1 as f64 / CELLS_PER_DEGREE, e);
+                    break;
+                }
+            };
+            let row = GeocodeModel {
+                cell_lat: c.0,
+                cell_lng: c.1,
+                street: place.as_ref().and_then(|p| p.street.clone()),
+                district: place.as_ref().and_then(|p| p.district.clone()),
+                city: place.as_ref().and_then(|p| p.city.clone()),
+                label: place.as_ref().map(|p| p.label.clone()),
+                created_at: Utc::now(),
+            };
+            if let Err(e) = self.cache.save_place(row).await {
+                warn!("Geocode cache write failed: {}", e);
+            }
+            known.insert(c, place);
+        }
+        cells.iter().map(|c| known.get(c).cloned().flatten()).collect()
+    }
+
+    /// Asks the geocoder about the center of a cache cell, no sooner than GEOCODER_MIN_INTERVAL_MS
+    /// after the previous request
+    async fn reverse(&self, (row, col): (i32, i32)) -> Result<Option<Place>, String> {
+        let mut last = self.last_request.lock().await;
+        if let Some(wait) = last.map(|t| self.config.min_interval.saturating_sub(t.elapsed())) {
+            tokio::time::sleep(wait).await;
+        }
+        let (lat, lng) = (format!("{:.4}", row as f64 / CELLS_PER_DEGREE), format!("{:.4}", col as f64 / CELLS_PER_DEGREE));
+        let url = format!("{}/reverse", self.config.url);
+        let result = match self.config.provider {
+            Provider::Nominatim => {
+                let mut query = vec![("format", "jsonv2"), ("lat", lat.as_str()), ("lon", lng.as_str()), ("zoom", "18"), ("addressdetails", "1")];
+                if let Some(language) = &self.config.language {
+                    query.push(("accept-language", language));
+                }
+                self.get::<NominatimAnswer>(&url, &query).await.map(|a| {
+                    let pick = |keys: &[&str]| keys.iter().find_map(|k| a.address.get(*k).cloned());
+                    if a.error.is_some() {
+                        return None;
+                    }
+                    Place::new(pick(&NOMINATIM_STREET), pick(&NOMINATIM_DISTRICT), pick(&NOMINATIM_CITY))
+                })
+            }
+            Provider::Photon => {
+                let mut query = vec![("lat", lat.as_str()), ("lon", lng.as_str()), ("limit", "1")];
+                if let Some(language) = &self.config.language {
+                    query.push(("lang", language));
+                }
+                self.get::<PhotonAnswer>(&url, &query).await.map(|a| {
+                    let p = a.features.into_iter().next()?.properties;
+                    Place::new(p.street.or(p.name), p.district.or(p.locality), p.city)
+                })
+            }
+        };
+        *last = Some(Instant::now());
+        result
+    }
+
+    async fn get<T: for<'de> Deserialize<'de>>(&self, url: &str, query: &[(&str, &str)]) -> Result<T, String> {
+        let resp = self.client.get(url).query(query).send().await.map_err(|e| e.to_string())?;
+        let status = resp.status();
+        if !status.is_success() {
+            return Err(format!("{} answered {}", url, status));
+        }
+        resp.json().await.map_err(|e| format!("unreadable answer: {}", e))
+    }
+}
+
+/// Cache cell of a position
+fn cell(lat: f64, lng: f64) -> (i32, i32) {
+    ((lat * CELLS_PER_DEGREE).round() as i32, (lng * CELLS_PER_DEGREE).round() as i32)
+}
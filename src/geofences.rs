@@ -0,0 +1,261 @@
+//! Geofences: named polygons managed through `/api/geofences`. The classification worker checks
+//! every stored point against the active geofences of its tenant, and a trip crossing the
+//! boundary of one is recorded in `geofence_events` as an `entry` or an `exit`.
+
+use chrono::{DateTime, Utc};
+use log::{debug, error};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::anomaly::PointSample;
+use crate::database::model::geofence_events::Model as GeofenceEventModel;
+use crate::database::model::geofences::Model as GeofenceModel;
+use crate::database::store::{GeofenceEventFilter, GeofenceSettings, GeofenceStore, PublicationDelay, StoreResult};
+
+pub const ENTRY: &str = "entry";
+pub const EXIT: &str = "exit";
+
+/// How often trips idle for GEOFENCE_IDLE_SECS are forgotten
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A geofence with its vertices read out of the stored JSON and their bounds, checked first
+struct Fence {
+    model: GeofenceModel,
+    vertices: Vec<(f64, f64)>,
+    lat_min: f64,
+    lat_max: f64,
+    lng_min: f64,
+    lng_max: f64,
+}
+
+impl Fence {
+    fn new(model: GeofenceModel) -> Self {
+        let vertices = vertices_of(&model);
+        let (mut lat_min, mut lat_max, mut lng_min, mut lng_max) = (f64::MAX, f64::MIN, f64::MAX, f64::MIN);
+        for &(lat, lng) in &vertices {
+            lat_min = lat_min.min(lat);
+            lat_max = lat_max.max(lat);
+            lng_min = lng_min.min(lng);
+            lng_max = lng_max.max(lng);
+        }
+        Self { model, vertices, lat_min, lat_max, lng_min, lng_max }
+    }
+
+    fn contains(&self, lat: f64, lng: f64) -> bool {
+        lat >= self.lat_min && lat <= self.lat_max && lng >= self.lng_min && lng <= self.lng_max && contains(&self.vertices, lat, lng)
+    }
+}
+
+/// Vertices (lat, lng) of a stored geofence; malformed ones are skipped
+pub fn vertices_of(fence: &GeofenceModel) -> Vec<(f64, f64)> {
+    let Some(vertices) = fence.polygon.as_array() else { return Vec::new() };
+    vertices.iter().filter_map(|v| Some((v.get("lat")?.as_f64()?, v.get("lng")?.as_f64()?))).collect()
+}
+
+/// Whether the point lies inside the polygon, by the even-odd rule on plain degrees. Polygons
+/// are expected not to cross the antimeridian.
+pub fn contains(polygon: &[(f64, f64)], lat: f64, lng: f64) -> bool {
+    let mut inside = false;
+    let mut j = polygon.len().wrapping_sub(1);
+    for (i, &(lat_i, lng_i)) in polygon.iter().enumerate() {
+        let (lat_j, lng_j) = polygon[j];
+        if (lat_i > lat) != (lat_j > lat) && lng < (lng_j - lng_i) * (lat - lat_i) / (lat_j - lat_i) + lng_i {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+/// Where a trip with recent points is: the geofences it was last seen inside
+struct Visit {
+    fences: HashSet<i64>,
+    /// Timestamp of the latest point checked; earlier points arriving late are not
+    latest: DateTime<Utc>,
+    /// When that point was checked, for forgetting idle trips
+    seen: Instant,
+}
+
+/// The geofences, read once at startup and again after every change made through the API,
+/// and where the trips with recent points are. A trip idle for GEOFENCE_IDLE_SECS (default
+/// 3600) is forgotten; should it go on, where it was is read back from its events.
+pub struct Geofences {
+    store: Arc<dyn GeofenceStore>,
+    fences: RwLock<Vec<Fence>>,
+    /// By randomized_id
+    visits: Mutex<HashMap<i64, Visit>>,
+    idle: Duration,
+    swept: Mutex<Instant>,
+    /// Applied to `events`; the worker records them live
+    delay: PublicationDelay,
+}
+
+impl Geofences {
+    pub async fn load(store: Arc<dyn GeofenceStore>) -> Self {
+        let idle = env::var("GEOFENCE_IDLE_SECS").ok().and_then(|v| v.parse::<u64>().ok()).filter(|s| *s > 0).unwrap_or(3600);
+        let geofences = Self {
+            store,
+            fences: RwLock::new(Vec::new()),
+            visits: Mutex::new(HashMap::new()),
+            idle: Duration::from_secs(idle),
+            swept: Mutex::new(Instant::now()),
+            delay: PublicationDelay::default(),
+        };
+        if let Err(e) = geofences.reload().await {
+            error!("Could not read geofences, starting without any: {}", e);
+        }
+        geofences
+    }
+
+    /// Leaves events younger than `delay` out of `events`, like the handlers' stores do with
+    /// points
+    pub fn with_delay(mut self, delay: PublicationDelay) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    pub async fn reload(&self) -> StoreResult<()> {
+        let fences = self.store.find_geofences().await?.into_iter().map(Fence::new).collect();
+        *self.fences.write().unwrap_or_else(|e| e.into_inner()) = fences;
+        Ok(())
+    }
+
+    /// Every geofence of every tenant, inactive ones included, oldest first
+    pub fn all(&self) -> Vec<GeofenceModel> {
+        self.fences.read().unwrap_or_else(|e| e.into_inner()).iter().map(|f| f.model.clone()).collect()
+    }
+
+    pub fn find(&self, id: i64) -> Option<GeofenceModel> {
+        self.fences.read().unwrap_or_else(|e| e.into_inner()).iter().find(|f| f.model.id == id).map(|f| f.model.clone())
+    }
+
+    /// Whether the point lies inside the geofence, active or not; false when there is none
+    pub fn covers(&self, id: i64, lat: f64, lng: f64) -> bool {
+        self.fences.read().unwrap_or_else(|e| e.into_inner()).iter().any(|f| f.model.id == id && f.contains(lat, lng))
+    }
+
+    /// Trips with recent points whose latest point was inside the geofence
+    pub fn trips_inside(&self, id: i64) -> usize {
+        self.visits.lock().unwrap_or_else(|e| e.into_inner()).values().filter(|v| v.fences.contains(&id)).count()
+    }
+
+    /// Forgets the trips without a point for `idle`; returns how many
+    pub fn forget_idle(&self, idle: Duration) -> usize {
+        let mut visits = self.visits.lock().unwrap_or_else(|e| e.into_inner());
+        let before = visits.len();
+        visits.retain(|_, v| v.seen.elapsed() < idle);
+        before - visits.len()
+    }
+
+    pub async fn create(&self, settings: &GeofenceSettings, tenant_id: Option<String>) -> StoreResult<GeofenceModel> {
+        let fence = self.store.create_geofence(settings, tenant_id).await?;
+        self.reload().await?;
+        Ok(fence)
+    }
+
+    /// Trips inside stay so until a point of theirs falls outside the new polygon
+    pub async fn update(&self, id: i64, settings: &GeofenceSettings) -> StoreResult<Option<GeofenceModel>> {
+        let fence = self.store.update_geofence(id, settings).await?;
+        self.reload().await?;
+        Ok(fence)
+    }
+
+    pub async fn delete(&self, id: i64) -> StoreResult<bool> {
+        let deleted = self.store.delete_geofence(id).await?;
+        for visit in self.visits.lock().unwrap_or_else(|e| e.into_inner()).values_mut() {
+            visit.fences.remove(&id);
+        }
+        self.reload().await?;
+        Ok(deleted)
+    }
+
+    /// Events as the API shows them, only those about published data
+    pub async fn events(&self, filter: &GeofenceEventFilter, limit: u64) -> StoreResult<Vec<GeofenceEventModel>> {
+        let mut filter = filter.clone();
+        filter.embargo.extend(self.delay.rules(Utc::now()));
+        self.store.find_geofence_events(&filter, limit).await
+    }
+
+    /// Checks a stored point against the active geofences of its tenant and records the
+    /// boundaries its trip crossed; the first point of a trip found inside counts as an entry.
+    /// A point older than one of its trip already checked arrived late and crosses nothing.
+    pub async fn evaluate(&self, tenant_id: Option<&str>, randomized_id: i64, point_id: i64, sample: &PointSample) -> Vec<GeofenceEventModel> {
+        self.sweep();
+        let known = self.visits.lock().unwrap_or_else(|e| e.into_inner()).contains_key(&randomized_id);
+        if !known {
+            // New or forgotten: where it was is in its events
+            let fences = match self.store.open_geofence_visits(Some(randomized_id)).await {
+                Ok(open) => open.into_iter().map(|(fence, _)| fence).collect(),
+                Err(e) => {
+                    error!("Could not read open geofence visits of trip {}; it may enter again: {}", randomized_id, e);
+                    HashSet::new()
+                }
+            };
+            let visit = Visit { fences, latest: DateTime::<Utc>::MIN_UTC, seen: Instant::now() };
+            self.visits.lock().unwrap_or_else(|e| e.into_inner()).entry(randomized_id).or_insert(visit);
+        }
+
+        let crossings: Vec<(i64, &'static str)> = {
+            let fences = self.fences.read().unwrap_or_else(|e| e.into_inner());
+            let mut visits = self.visits.lock().unwrap_or_else(|e| e.into_inner());
+            let Some(visit) = visits.get_mut(&randomized_id) else { return Vec::new() };
+            visit.seen = Instant::now();
+            if sample.timestamp < visit.latest {
+                debug!("Point {} of trip {} arrived late; not checked against the geofences", point_id, randomized_id);
+                return Vec::new();
+            }
+            visit.latest = sample.timestamp;
+            fences
+                .iter()
+                .filter(|f| f.model.active && f.model.tenant_id.as_deref() == tenant_id)
+                .filter_map(|f| {
+                    let id = f.model.id;
+                    match (f.contains(sample.lat, sample.lng), visit.fences.contains(&id)) {
+                        (true, false) => visit.fences.insert(id).then_some((id, ENTRY)),
+                        (false, true) => visit.fences.remove(&id).then_some((id, EXIT)),
+                        _ => None,
+                    }
+                })
+                .collect()
+        };
+
+        let mut recorded = Vec::with_capacity(crossings.len());
+        for (geofence_id, kind) in crossings {
+            let event = GeofenceEventModel {
+                id: 0,
+                geofence_id,
+                randomized_id,
+                point_id,
+                kind: kind.to_string(),
+                lat: sample.lat,
+                lng: sample.lng,
+                timestamp: sample.timestamp,
+                created_at: Utc::now(),
+            };
+            match self.store.record_geofence_event(event).await {
+                Ok(event) => {
+                    debug!("Trip {} {} geofence {} at point {}", randomized_id, kind, geofence_id, point_id);
+                    recorded.push(event);
+                }
+                Err(e) => error!("Could not record {} of trip {} for geofence {}: {}", kind, randomized_id, geofence_id, e),
+            }
+        }
+        recorded
+    }
+
+    /// Forgets idle trips every SWEEP_INTERVAL
+    fn sweep(&self) {
+        let mut swept = self.swept.lock().unwrap_or_else(|e| e.into_inner());
+        if swept.elapsed() < SWEEP_INTERVAL {
+            return;
+        }
+        *swept = Instant::now();
+        drop(swept);
+        let forgotten = self.forget_idle(self.idle);
+        if forgotten > 0 {
+            debug!("Forgot {} trips idle for {:?}", forgotten, self.idle);
+        }
+    }
+}
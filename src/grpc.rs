@@ -0,0 +1,244 @@
+use chrono::{DateTime, Utc};
+use log::{debug, error, info, warn};
+use std::env;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::Stream;
+use tonic::metadata::{MetadataMap, MetadataValue};
+use tonic::{Request, Response, Status, Streaming};
+use uuid::Uuid;
+
+use crate::api::error::ApiError;
+use crate::api::points::{Ack, Ingestion, PushPointsResponse};
+use crate::database::store::{NewPointRecord, TenantScope};
+use crate::rate_limit::RateLimiter;
+use crate::request_id;
+use crate::tenant::{self, TenantConfig};
+
+pub mod proto {
+    tonic::include_proto!("indrive.ingest.v1");
+}
+
+use proto::ingest_server::{Ingest, IngestServer};
+
+/// Largest message taken by default; a batch of 10 000 points is about 600 KB
+const DEFAULT_MAX_MESSAGE_BYTES: usize = 16 * 1024 * 1024;
+/// Metadata twin of the `X-Request-Id` header
+const REQUEST_ID_KEY: &str = "x-request-id";
+/// Replies of a stream waiting for the client to read them before batches stop being taken
+const STREAM_REPLY_BUFFER: usize = 16;
+
+/// gRPC twin of `POST /api/points` (`proto/ingest.proto`) for vehicle gateways that speak
+/// protobuf. Batches go through the same `Ingestion` pipeline and count against the same
+/// INGEST_RATE_LIMIT buckets, one token per batch.
+struct IngestService {
+    ingestion: Ingestion,
+    limiter: Arc<RateLimiter>,
+    tenants: Arc<TenantConfig>,
+}
+
+/// Starts the gRPC server on GRPC_PORT when it is set; returns whether it was started.
+/// GRPC_MAX_MESSAGE_BYTES caps the size of one batch (default 16 MiB). Points are stored for the
+/// tenant of the call's API key (see `TenantConfig`).
+pub fn spawn(ingestion: Ingestion, limiter: Arc<RateLimiter>, tenants: Arc<TenantConfig>) -> bool {
+    let Some(port) = env::var("GRPC_PORT").ok().and_then(|v| v.trim().parse::<u16>().ok()) else {
+        return false;
+    };
+    let max_message = env::var("GRPC_MAX_MESSAGE_BYTES")
+        .ok()
+        .and_then(|v| v.trim().parse::<usize>().ok())
+        .filter(|v| *v > 0)
+        .unwrap_or(DEFAULT_MAX_MESSAGE_BYTES);
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let service = IngestServer::new(IngestService { ingestion, limiter, tenants }).max_decoding_message_size(max_message);
+    tokio::spawn(async move {
+        info!("gRPC ingestion running at {}", addr);
+        if let Err(e) = tonic::transport::Server::builder().add_service(service).serve(addr).await {
+            error!("gRPC server on {} stopped: {}", addr, e);
+        }
+    });
+    true
+}
+
+#[tonic::async_trait]
+impl Ingest for IngestService {
+    async fn push_points(&self, req: Request<proto::PushPointsRequest>) -> Result<Response<proto::PushPointsReply>, Status> {
+        let (client, peer) = client_key(&self.limiter, &req);
+        let id = request_id::from_incoming(req.metadata().get(REQUEST_ID_KEY).and_then(|v| v.to_str().ok()));
+        let scope = self.scope(&req);
+        let batch = req.into_inner();
+        let res = request_id::scope(id.clone(), tenant::scope(scope, async {
+            if let Err(retry_after) = self.limiter.check(&client) {
+                warn!("Rate limit hit on gRPC PushPoints by {}", peer);
+                let mut status = Status::resource_exhausted("Rate limit exceeded");
+                status.metadata_mut().insert("retry-after", MetadataValue::from(retry_after));
+                return Err(status);
+            }
+            push(&self.ingestion, batch).await.map_err(status)
+        }))
+        .await;
+
+        let mut resp = Response::new(res?);
+        if let Ok(value) = id.parse() {
+            resp.metadata_mut().insert(REQUEST_ID_KEY, value);
+        }
+        Ok(resp)
+    }
+
+    type StreamPointsStream = Pin<Box<dyn Stream<Item = Result<proto::StreamPointsReply, Status>> + Send>>;
+
+    async fn stream_points(&self, req: Request<Streaming<proto::PushPointsRequest>>) -> Result<Response<Self::StreamPointsStream>, Status> {
+        let (client, peer) = client_key(&self.limiter, &req);
+        let id = request_id::from_incoming(req.metadata().get(REQUEST_ID_KEY).and_then(|v| v.to_str().ok()));
+        let scope = self.scope(&req);
+        let mut batches = req.into_inner();
+        let (ingestion, limiter) = (self.ingestion.clone(), self.limiter.clone());
+        let (tx, rx) = mpsc::channel(STREAM_REPLY_BUFFER);
+
+        // One batch at a time, so replies and stored points keep the order batches came in
+        tokio::spawn(request_id::scope(id, tenant::scope(scope, async move {
+            info!("gRPC point stream opened by {}", peer);
+            let mut count = 0u64;
+            loop {
+                let batch = match batches.message().await {
+                    Ok(Some(batch)) => batch,
+                    Ok(None) => break,
+                    Err(e) => {
+                        debug!("gRPC point stream of {} broke off: {}", peer, e);
+                        break;
+                    }
+                };
+                let sequence = batch.sequence;
+                let outcome = match limiter.check(&client) {
+                    Err(retry_after) => {
+                        warn!("Rate limit hit on gRPC StreamPoints by {}", peer);
+                        proto::stream_points_reply::Outcome::Error(proto::BatchError {
+                            code: "too_many_requests".to_string(),
+                            message: format!("Rate limit exceeded, retry in {} s", retry_after),
+                        })
+                    }
+                    Ok(()) => match push(&ingestion, batch).await {
+                        Ok(reply) => proto::stream_points_reply::Outcome::Stored(reply),
+                        Err(e) => proto::stream_points_reply::Outcome::Error(proto::BatchError { code: e.code().to_string(), message: e.to_string() }),
+                    },
+                };
+                count += 1;
+                let reply = proto::StreamPointsReply { sequence, outcome: Some(outcome) };
+                // The client stopped reading replies
+                if tx.send(Ok(reply)).await.is_err() {
+                    break;
+                }
+            }
+            info!("gRPC point stream of {} closed after {} batches", peer, count);
+        })));
+
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
+}
+
+impl IngestService {
+    /// Tenant of a call; without tenants configured the points stay unscoped like HTTP ones
+    fn scope<T>(&self, req: &Request<T>) -> Option<TenantScope> {
+        self.tenants.is_enabled().then(|| self.tenants.for_api_key(api_key(req.metadata())))
+    }
+}
+
+/// One batch through the pipeline, as `POST /api/points` would store it
+async fn push(ingestion: &Ingestion, batch: proto::PushPointsRequest) -> Result<proto::PushPointsReply, ApiError> {
+    let started = Instant::now();
+    let ack = match proto::Ack::try_from(batch.ack) {
+        Ok(proto::Ack::Unspecified) => Ack::default(),
+        Ok(proto::Ack::Received) => Ack::Received,
+        Ok(proto::Ack::Persisted) => Ack::Persisted,
+        Ok(proto::Ack::Classified) => Ack::Classified,
+        Err(_) => return Err(ApiError::bad_param("ack", format!("Unknown ack {}", batch.ack))),
+    };
+    info!("Received {} points to insert over gRPC (ack={:?})", batch.points.len(), ack);
+    let records = batch.points.into_iter().enumerate().map(|(i, p)| record(i, p)).collect::<Result<Vec<_>, _>>()?;
+    let res = ingestion.ingest(records, ack).await;
+    info!("Processed points in {:?}", started.elapsed());
+    res.map(reply)
+}
+
+fn record(index: usize, p: proto::Point) -> Result<NewPointRecord, ApiError> {
+    let timestamp = match p.timestamp_ms {
+        Some(ms) => Some(
+            DateTime::<Utc>::from_timestamp_millis(ms)
+                .ok_or_else(|| ApiError::bad_request(format!("point {}: timestamp_ms {} is out of range", index, ms)))?,
+        ),
+        None => None,
+    };
+    let client_uuid = match p.uuid {
+        Some(uuid) => Some(Uuid::parse_str(&uuid).map_err(|_| ApiError::bad_request(format!("point {}: {:?} is not a UUID", index, uuid)))?),
+        None => None,
+    };
+    Ok(NewPointRecord {
+        randomized_id: p.randomized_id,
+        lat: p.lat,
+        lng: p.lng,
+        alt: p.alt.unwrap_or(0.0),
+        spd: p.spd,
+        azm: p.azm,
+        timestamp,
+        anomaly: None,
+        client_uuid,
+        device_id: p.device_id,
+        tenant_id: None,
+    })
+}
+
+fn reply(resp: PushPointsResponse) -> proto::PushPointsReply {
+    proto::PushPointsReply {
+        points: resp
+            .points
+            .into_iter()
+            .map(|p| proto::InsertedPoint {
+                id: p.id,
+                timestamp_ms: p.timestamp.map(|t| t.timestamp_millis()),
+                uuid: p.uuid.map(|u| u.to_string()),
+                duplicate: p.duplicate,
+                anomaly: p.anomaly,
+            })
+            .collect(),
+        buffered: resp.buffered as u32,
+        quarantined: resp
+            .quarantined
+            .into_iter()
+            .map(|q| proto::QuarantinedPoint { index: q.index as u32, id: q.id, reason: q.reason })
+            .collect(),
+    }
+}
+
+/// The HTTP status of an `ApiError`, translated to its gRPC counterpart
+fn status(e: ApiError) -> Status {
+    let message = e.to_string();
+    match e {
+        ApiError::BadRequest { .. } => Status::invalid_argument(message),
+        ApiError::Unauthorized(_) => Status::unauthenticated(message),
+        ApiError::NotFound(_) => Status::not_found(message),
+        ApiError::PayloadTooLarge(_) => Status::resource_exhausted(message),
+        ApiError::RangeNotSatisfiable(_) => Status::out_of_range(message),
+        ApiError::ServiceUnavailable(_) => Status::unavailable(message),
+        ApiError::Internal => Status::internal(message),
+    }
+}
+
+/// Rate limit bucket and peer address of a call. Same buckets as the HTTP ingestion routes:
+/// a known API key from `x-api-key` or a Bearer token, else the peer address.
+fn client_key<T>(limiter: &RateLimiter, req: &Request<T>) -> (String, String) {
+    let peer = req.remote_addr().map(|a| a.ip().to_string()).unwrap_or_default();
+    (limiter.client_for(api_key(req.metadata()), &peer), peer)
+}
+
+fn api_key(metadata: &MetadataMap) -> Option<&str> {
+    metadata
+        .get("x-api-key")
+        .and_then(|v| v.to_str().ok())
+        .or_else(|| metadata.get("authorization").and_then(|v| v.to_str().ok()).and_then(|v| v.strip_prefix("Bearer ")))
+        .map(str::trim)
+        .filter(|k| !k.is_empty())
+}
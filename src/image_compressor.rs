@@ -1,17 +1,119 @@
-use actix_web::{HttpRequest, HttpResponse, Result, web, http::header};
+use actix_web::{HttpMessage, HttpRequest, HttpResponse, Result, web, http::header};
 use actix_files::NamedFile;
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
 use dashmap::DashMap;
-use std::sync::Arc;
-use std::fs;
-use std::time::{SystemTime, UNIX_EPOCH};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use utoipa::ToSchema;
+use std::{env, fs};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-// Структура для кэша сжатых изображений
+// Каталог с изображениями, смонтированный под URL-префиксом
+#[derive(Debug, Clone)]
+pub struct ImageRoot {
+    pub mount: String,
+    // Канонический (абсолютный, без симлинков) путь к каталогу
+    pub dir: PathBuf,
+}
+
+impl ImageRoot {
+    pub fn new(mount: &str, dir: impl AsRef<Path>) -> std::io::Result<Self> {
+        Ok(Self {
+            mount: format!("/{}", mount.trim_matches('/')),
+            dir: dir.as_ref().canonicalize()?,
+        })
+    }
+
+    // Путь к файлу внутри корня или None, если запрос выходит за его пределы
+    pub fn resolve(&self, rel: &str) -> Option<PathBuf> {
+        let rel = Path::new(rel);
+        // Запрещаем "..", абсолютные пути и префиксы дисков ещё до обращения к ФС
+        if !rel.components().all(|c| matches!(c, Component::Normal(_))) {
+            return None;
+        }
+        // canonicalize раскрывает симлинки, поэтому повторно проверяем, что остались внутри корня
+        let full = self.dir.join(rel).canonicalize().ok()?;
+        (full.starts_with(&self.dir) && full.is_file()).then_some(full)
+    }
+}
+
+const DEFAULT_IMAGE_ROOTS: &str = "/static/assets/img=web/out/static/assets/img";
+
+// Корни из IMAGE_ROOTS: пары `префикс=каталог` через запятую.
+// Несуществующие каталоги пропускаются с предупреждением.
+pub fn roots_from_env() -> Vec<ImageRoot> {
+    let spec = env::var("IMAGE_ROOTS").unwrap_or_else(|_| DEFAULT_IMAGE_ROOTS.to_string());
+    spec.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .filter_map(|entry| {
+            let Some((mount, dir)) = entry.split_once('=') else {
+                warn!("Ignoring IMAGE_ROOTS entry '{}': expected mount=dir", entry);
+                return None;
+            };
+            match ImageRoot::new(mount.trim(), dir.trim()) {
+                Ok(root) => {
+                    info!("Serving images under {} from {}", root.mount, root.dir.display());
+                    Some(root)
+                }
+                Err(e) => {
+                    warn!("Ignoring image root {} ({}): {}", mount, dir, e);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+// Регистрирует обработчик оптимизированных изображений для каждого корня
+pub fn init_routes(cfg: &mut web::ServiceConfig, roots: &[ImageRoot]) {
+    for root in roots {
+        cfg.service(
+            web::resource(format!("{}/{{filename:.*}}", root.mount))
+                .app_data(web::Data::new(root.clone()))
+                .route(web::get().to(serve_optimized_image)),
+        );
+    }
+}
+
+// Структура для кэша сжатых изображений. Создаётся в main.rs и передаётся
+// обработчикам как web::Data, так что у каждого экземпляра своё состояние.
 #[derive(Clone)]
 pub struct ImageCache {
     cache: Arc<DashMap<String, CachedImage>>,
     max_size: usize, // Максимальный размер кэша в байтах
-    current_size: Arc<std::sync::atomic::AtomicUsize>,
+    current_size: Arc<AtomicUsize>,
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
+    // Второй уровень на диске, переживает перезапуск
+    disk: Option<Arc<DiskCache>>,
+}
+
+// Состояние кэша для `GET /api/admin/image-cache`
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ImageCacheStats {
+    pub entries: usize,
+    pub bytes: usize,
+    #[serde(rename = "maxBytes")]
+    pub max_bytes: usize,
+    // Попадания в память или на диск с момента запуска
+    pub hits: u64,
+    // Конвертации с момента запуска
+    pub misses: u64,
+    // None, если дисковый кэш отключён
+    pub disk: Option<DiskCacheStats>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DiskCacheStats {
+    pub dir: String,
+    pub entries: usize,
+    pub bytes: u64,
+    #[serde(rename = "maxBytes")]
+    pub max_bytes: u64,
 }
 
 #[derive(Clone)]
@@ -27,11 +129,74 @@ impl ImageCache {
         Self {
             cache: Arc::new(DashMap::new()),
             max_size: max_size_mb * 1024 * 1024,
-            current_size: Arc::new(std::sync::atomic::AtomicUsize::new(0)),
+            current_size: Arc::new(AtomicUsize::new(0)),
+            hits: Arc::new(AtomicU64::new(0)),
+            misses: Arc::new(AtomicU64::new(0)),
+            disk: None,
+        }
+    }
+
+    // IMAGE_CACHE_MB (по умолчанию 100) плюс дисковый кэш из DiskCache::from_env
+    pub fn from_env() -> Self {
+        let max_mb = env::var("IMAGE_CACHE_MB")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(100);
+        Self::new(max_mb).with_disk(DiskCache::from_env())
+    }
+
+    pub fn with_disk(mut self, disk: Option<DiskCache>) -> Self {
+        self.disk = disk.map(Arc::new);
+        self
+    }
+
+    // Каталог дискового кэша, если он включён
+    pub fn disk_dir(&self) -> Option<&Path> {
+        self.disk.as_ref().map(|d| d.dir())
+    }
+
+    // Заполняет память из дискового кэша в фоне
+    pub fn warm_in_background(&self) {
+        if self.disk.is_some() {
+            let cache = self.clone();
+            tokio::task::spawn_blocking(move || cache.warm());
+        }
+    }
+
+    pub fn stats(&self) -> ImageCacheStats {
+        ImageCacheStats {
+            entries: self.cache.len(),
+            bytes: self.current_size.load(Ordering::Relaxed),
+            max_bytes: self.max_size,
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            disk: self.disk.as_ref().map(|d| d.stats()),
+        }
+    }
+
+    fn record(&self, hit: bool) {
+        let counter = if hit { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        crate::metrics::metrics().record_image_cache(hit);
+    }
+
+    // Поднимает в память самые свежие по обращениям записи с диска, пока хватает места
+    fn warm(&self) {
+        let Some(disk) = &self.disk else { return };
+        let mut loaded = 0;
+        for (key, image) in disk.recent(self.max_size) {
+            self.current_size.fetch_add(image.data.len(), Ordering::Relaxed);
+            self.cache.insert(key, image);
+            loaded += 1;
         }
+        info!("Image cache warmed with {} images from {}", loaded, disk.dir.display());
     }
 
-    async fn get_or_create_webp(&self, image_path: &PathBuf, cache_key: &str) -> Result<CachedImage> {
+    async fn get_or_convert(&self, image_path: &Path, format: OutputFormat) -> Result<CachedImage> {
+        // После смены качества старые записи дискового кэша не подходят
+        let cache_key = format!("{}:{}", format.variant(), image_path.display());
+        let cache_key = cache_key.as_str();
         // Проверяем время модификации файла
         let metadata = fs::metadata(image_path)
             .map_err(|_| actix_web::error::ErrorNotFound("Image not found"))?;
@@ -43,53 +208,79 @@ impl ImageCache {
             .as_secs();
 
         // Проверяем кэш
-        if let Some(cached) = self.cache.get(cache_key) {
-            if cached.original_modified >= modified_time {
-                return Ok(cached.clone());
-            }
+        if let Some(cached) = self.cache.get(cache_key)
+            && cached.original_modified >= modified_time
+        {
+            self.record(true);
+            return Ok(cached.clone());
         }
 
-        // Читаем и конвертируем изображение
-        let webp_data = self.convert_to_webp(image_path).await?;
-        
-        let cached_image = CachedImage {
-            data: webp_data,
-            content_type: "image/webp".to_string(),
-            last_modified: SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap_or_default()
-                .as_secs(),
-            original_modified: modified_time,
+        // Затем диск: после перезапуска конвертации не повторяются
+        let from_disk = match &self.disk {
+            Some(disk) => disk.get(cache_key, modified_time).await,
+            None => None,
+        };
+        let cached_image = match from_disk {
+            Some(image) => {
+                self.record(true);
+                image
+            }
+            None => {
+                self.record(false);
+                // Читаем и конвертируем изображение
+                let data = self.convert(image_path, format).await?;
+                let image = CachedImage {
+                    data,
+                    content_type: format.content_type().to_string(),
+                    last_modified: unix_now(),
+                    original_modified: modified_time,
+                };
+                if let Some(disk) = &self.disk {
+                    disk.put(cache_key, &image).await;
+                }
+                image
+            }
         };
 
         // Проверяем размер кэша перед добавлением
         let data_size = cached_image.data.len();
-        let current = self.current_size.load(std::sync::atomic::Ordering::Relaxed);
+        let current = self.current_size.load(Ordering::Relaxed);
         
         if current + data_size > self.max_size {
             self.cleanup_cache().await;
         }
 
-        self.current_size.fetch_add(data_size, std::sync::atomic::Ordering::Relaxed);
-        self.cache.insert(cache_key.to_string(), cached_image.clone());
+        // Повторная конвертация того же ключа заменяет запись, старый размер вычитаем
+        self.current_size.fetch_add(data_size, Ordering::Relaxed);
+        if let Some(old) = self.cache.insert(cache_key.to_string(), cached_image.clone()) {
+            self.current_size.fetch_sub(old.data.len(), Ordering::Relaxed);
+        }
 
         Ok(cached_image)
     }
 
-    async fn convert_to_webp(&self, image_path: &PathBuf) -> Result<Vec<u8>> {
+    async fn convert(&self, image_path: &Path, format: OutputFormat) -> Result<Vec<u8>> {
         // Используем tokio::task::spawn_blocking для CPU-интенсивной операции
-        let path = image_path.clone();
+        let path = image_path.to_path_buf();
+        let quality = quality();
         tokio::task::spawn_blocking(move || -> std::result::Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
             let img = image::open(&path)?;
-            
-            // Конвертируем в WebP с качеством 85%
-            let encoder = webp::Encoder::from_image(&img)?;
-            let webp_data = encoder.encode(85.0);
-            Ok(webp_data.to_vec())
+            match format {
+                OutputFormat::Webp => {
+                    let encoder = webp::Encoder::from_image(&img)?;
+                    Ok(encoder.encode(quality.webp).to_vec())
+                }
+                OutputFormat::Avif => {
+                    let mut out = Vec::new();
+                    let encoder = image::codecs::avif::AvifEncoder::new_with_speed_quality(&mut out, quality.avif_speed, quality.avif);
+                    img.write_with_encoder(encoder)?;
+                    Ok(out)
+                }
+            }
         })
         .await
         .map_err(|_| actix_web::error::ErrorInternalServerError("Task join error"))?
-        .map_err(|_| actix_web::error::ErrorInternalServerError("WebP conversion failed"))
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Image conversion failed"))
     }
 
     async fn cleanup_cache(&self) {
@@ -103,32 +294,341 @@ impl ImageCache {
         
         for (key, _) in entries.into_iter().take(to_remove) {
             if let Some((_, cached)) = self.cache.remove(&key) {
-                self.current_size.fetch_sub(cached.data.len(), std::sync::atomic::Ordering::Relaxed);
+                self.current_size.fetch_sub(cached.data.len(), Ordering::Relaxed);
             }
         }
     }
 }
 
-// Глобальный кэш изображений
-static IMAGE_CACHE: once_cell::sync::Lazy<ImageCache> = once_cell::sync::Lazy::new(|| {
-    ImageCache::new(100) // 100 MB кэш
-});
+// Форматы, в которые конвертируются изображения
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OutputFormat {
+    Avif,
+    Webp,
+}
 
-pub async fn serve_image(req: HttpRequest, path: web::Path<String>) -> Result<HttpResponse> {
-    let image_path = PathBuf::from("web/out/static/assets/img").join(path.as_str());
-    
-    // Проверяем, существует ли файл
-    if !image_path.exists() {
-        return Ok(HttpResponse::NotFound().finish());
+impl OutputFormat {
+    // Формат, явно названный в Accept с наибольшим q; q=0 означает отказ. При равных q
+    // предпочтительнее AVIF: он меньше по размеру
+    fn negotiate(accept: &str) -> Option<Self> {
+        let weight = |media: &str| {
+            accept
+                .split(',')
+                .filter_map(|item| {
+                    let mut parts = item.split(';').map(str::trim);
+                    if !parts.next()?.eq_ignore_ascii_case(media) {
+                        return None;
+                    }
+                    let q = parts
+                        .filter_map(|p| p.strip_prefix("q=").or_else(|| p.strip_prefix("Q=")))
+                        .find_map(|q| q.trim().parse::<f32>().ok())
+                        .unwrap_or(1.0);
+                    Some(q)
+                })
+                .fold(None, |best: Option<f32>, q| Some(best.map_or(q, |b| b.max(q))))
+                .filter(|q| *q > 0.0)
+        };
+        match (weight("image/avif"), weight("image/webp")) {
+            (Some(avif), Some(webp)) if webp > avif => Some(OutputFormat::Webp),
+            (Some(_), _) => Some(OutputFormat::Avif),
+            (None, Some(_)) => Some(OutputFormat::Webp),
+            (None, None) => None,
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Avif => "avif",
+            OutputFormat::Webp => "webp",
+        }
+    }
+
+    // Формат и его настройки качества: от них зависят байты, поэтому и ETag, и ключ кэша
+    fn variant(&self) -> String {
+        let quality = quality();
+        match self {
+            OutputFormat::Avif => format!("avif-q{}-s{}", quality.avif, quality.avif_speed),
+            OutputFormat::Webp => format!("webp-q{}", quality.webp),
+        }
     }
 
+    fn content_type(&self) -> &'static str {
+        match self {
+            OutputFormat::Avif => "image/avif",
+            OutputFormat::Webp => "image/webp",
+        }
+    }
+}
+
+// Качество сжатия по форматам
+#[derive(Debug, Clone, Copy)]
+struct Quality {
+    webp: f32,
+    avif: u8,
+    avif_speed: u8,
+}
+
+// IMAGE_WEBP_QUALITY (0-100, по умолчанию 85), IMAGE_AVIF_QUALITY (1-100, по умолчанию 70)
+// и IMAGE_AVIF_SPEED (1-10, по умолчанию 8: кодирование AVIF заметно дольше WebP)
+fn quality() -> Quality {
+    static QUALITY: once_cell::sync::Lazy<Quality> = once_cell::sync::Lazy::new(|| {
+        fn read<T: std::str::FromStr + PartialOrd>(name: &str, min: T, max: T, default: T) -> T {
+            env::var(name).ok().and_then(|v| v.parse::<T>().ok()).filter(|v| *v >= min && *v <= max).unwrap_or(default)
+        }
+        Quality {
+            webp: read("IMAGE_WEBP_QUALITY", 0.0, 100.0, 85.0),
+            avif: read("IMAGE_AVIF_QUALITY", 1, 100, 70),
+            avif_speed: read("IMAGE_AVIF_SPEED", 1, 10, 8),
+        }
+    });
+    *QUALITY
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+// Запись индекса дискового кэша
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct DiskEntry {
+    file: String,
+    content_type: String,
+    size: u64,
+    original_modified: u64,
+    created: u64,
+    last_access: u64,
+}
+
+const DISK_INDEX_FILE: &str = "index.json";
+
+// Индекс сохраняется не чаще раза в этот интервал; записи, не попавшие в него до остановки,
+// удаляются при следующем открытии как неизвестные
+const DISK_INDEX_SAVE_DELAY: Duration = Duration::from_secs(2);
+
+// Дисковый кэш сконвертированных изображений: файлы плюс индекс `index.json`
+// (ключ -> файл, размер, время исходника). При превышении лимита удаляются
+// давно не запрашивавшиеся записи.
+pub struct DiskCache {
+    dir: PathBuf,
+    max_size: u64,
+    index: Mutex<HashMap<String, DiskEntry>>,
+    save_scheduled: AtomicBool,
+}
+
+impl DiskCache {
+    // IMAGE_DISK_CACHE_DIR (по умолчанию data/image-cache, `off` — отключить)
+    // и IMAGE_DISK_CACHE_MB (по умолчанию 500)
+    pub fn from_env() -> Option<Self> {
+        let dir = env::var("IMAGE_DISK_CACHE_DIR").unwrap_or_else(|_| "data/image-cache".to_string());
+        if dir.is_empty() || dir == "off" {
+            info!("Image disk cache disabled");
+            return None;
+        }
+        let max_mb = env::var("IMAGE_DISK_CACHE_MB")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(500);
+        match Self::open(PathBuf::from(&dir), max_mb * 1024 * 1024) {
+            Ok(cache) => Some(cache),
+            Err(e) => {
+                warn!("Image disk cache disabled, cannot use {}: {}", dir, e);
+                None
+            }
+        }
+    }
+
+    fn open(dir: PathBuf, max_size: u64) -> std::io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        // Битый или отсутствующий индекс — начинаем с пустого
+        let mut index: HashMap<String, DiskEntry> = fs::read(dir.join(DISK_INDEX_FILE))
+            .ok()
+            .and_then(|raw| serde_json::from_slice(&raw).ok())
+            .unwrap_or_default();
+        index.retain(|_, e| dir.join(&e.file).is_file());
+        // Файлы, которых нет в индексе (например, запись прервалась), удаляем
+        let known: std::collections::HashSet<&str> = index.values().map(|e| e.file.as_str()).collect();
+        for entry in fs::read_dir(&dir)?.flatten() {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if name != DISK_INDEX_FILE && !known.contains(name.as_ref()) {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+        let used: u64 = index.values().map(|e| e.size).sum();
+        info!("Image disk cache at {}: {} images, {} of {} MB", dir.display(), index.len(), used / (1024 * 1024), max_size / (1024 * 1024));
+        let cache = Self { dir, max_size, index: Mutex::new(index), save_scheduled: AtomicBool::new(false) };
+        cache.evict_and_save();
+        Ok(cache)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn stats(&self) -> DiskCacheStats {
+        let index = self.index.lock().unwrap();
+        DiskCacheStats {
+            dir: self.dir.display().to_string(),
+            entries: index.len(),
+            bytes: index.values().map(|e| e.size).sum(),
+            max_bytes: self.max_size,
+        }
+    }
+
+    async fn get(&self, key: &str, original_modified: u64) -> Option<CachedImage> {
+        let entry = {
+            let mut index = self.index.lock().unwrap();
+            let entry = index.get_mut(key).filter(|e| e.original_modified >= original_modified)?;
+            entry.last_access = unix_now();
+            entry.clone()
+        };
+        match tokio::fs::read(self.dir.join(&entry.file)).await {
+            Ok(data) => Some(CachedImage {
+                data,
+                content_type: entry.content_type,
+                last_modified: entry.created,
+                original_modified: entry.original_modified,
+            }),
+            Err(e) => {
+                warn!("Image disk cache entry {} unreadable: {}", entry.file, e);
+                self.index.lock().unwrap().remove(key);
+                None
+            }
+        }
+    }
+
+    async fn put(self: &Arc<Self>, key: &str, image: &CachedImage) {
+        let file = format!("{:016x}.bin", fnv1a(key));
+        // Сначала во временный файл, чтобы не оставить обрезанную запись
+        let tmp = self.dir.join(format!("{}.tmp", file));
+        let written = match tokio::fs::write(&tmp, &image.data).await {
+            Ok(()) => tokio::fs::rename(&tmp, self.dir.join(&file)).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = written {
+            warn!("Image disk cache write failed for {}: {}", key, e);
+            let _ = tokio::fs::remove_file(&tmp).await;
+            return;
+        }
+        let now = unix_now();
+        {
+            let mut index = self.index.lock().unwrap();
+            // Коллизия хэша: другой ключ с тем же файлом больше не действителен
+            index.retain(|k, e| k == key || e.file != file);
+            index.insert(key.to_string(), DiskEntry {
+                file,
+                content_type: image.content_type.clone(),
+                size: image.data.len() as u64,
+                original_modified: image.original_modified,
+                created: image.last_modified,
+                last_access: now,
+            });
+        }
+        self.schedule_save();
+    }
+
+    // Одно сохранение на все записи за DISK_INDEX_SAVE_DELAY, вне потоков обработки запросов
+    fn schedule_save(self: &Arc<Self>) {
+        if self.save_scheduled.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let cache = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(DISK_INDEX_SAVE_DELAY).await;
+            // Записи, добавленные после этого места, запланируют следующее сохранение
+            cache.save_scheduled.store(false, Ordering::Release);
+            let _ = tokio::task::spawn_blocking(move || cache.evict_and_save()).await;
+        });
+    }
+
+    // Удаляет давно не запрашивавшиеся записи до 90% лимита и сохраняет индекс
+    fn evict_and_save(&self) {
+        let (evicted, raw) = {
+            let mut index = self.index.lock().unwrap();
+            let mut evicted = Vec::new();
+            let mut used: u64 = index.values().map(|e| e.size).sum();
+            if used > self.max_size {
+                let target = self.max_size / 10 * 9;
+                let mut by_access: Vec<(String, u64)> = index.iter().map(|(k, e)| (k.clone(), e.last_access)).collect();
+                by_access.sort_by_key(|(_, t)| *t);
+                for (key, _) in by_access {
+                    if used <= target {
+                        break;
+                    }
+                    if let Some(e) = index.remove(&key) {
+                        used -= e.size;
+                        evicted.push(e.file);
+                    }
+                }
+                debug!("Image disk cache evicted down to {} bytes", used);
+            }
+            (evicted, serde_json::to_vec(&*index))
+        };
+        // Файлы и индекс пишутся уже без блокировки
+        for file in evicted {
+            let _ = fs::remove_file(self.dir.join(file));
+        }
+        let tmp = self.dir.join(format!("{}.tmp", DISK_INDEX_FILE));
+        let saved = raw
+            .map_err(std::io::Error::other)
+            .and_then(|raw| fs::write(&tmp, raw))
+            .and_then(|()| fs::rename(&tmp, self.dir.join(DISK_INDEX_FILE)));
+        if let Err(e) = saved {
+            warn!("Image disk cache index not saved: {}", e);
+        }
+    }
+
+    // Записи, к которым обращались последними, в пределах `budget` байт
+    fn recent(&self, budget: usize) -> Vec<(String, CachedImage)> {
+        let mut entries: Vec<(String, DiskEntry)> = self.index.lock().unwrap().iter().map(|(k, e)| (k.clone(), e.clone())).collect();
+        entries.sort_by_key(|(_, e)| std::cmp::Reverse(e.last_access));
+        let mut used = 0usize;
+        let mut out = Vec::new();
+        for (key, e) in entries {
+            if used + e.size as usize > budget {
+                break;
+            }
+            let Ok(data) = fs::read(self.dir.join(&e.file)) else { continue };
+            used += data.len();
+            out.push((key, CachedImage {
+                data,
+                content_type: e.content_type,
+                last_modified: e.created,
+                original_modified: e.original_modified,
+            }));
+        }
+        out
+    }
+}
+
+// Стабильный между запусками хэш для имён файлов (DefaultHasher этого не обещает)
+fn fnv1a(s: &str) -> u64 {
+    s.bytes().fold(0xcbf29ce484222325, |h, b| (h ^ b as u64).wrapping_mul(0x100000001b3))
+}
+
+// If-None-Match важнее If-Modified-Since (RFC 9110, 13.1.3)
+fn not_modified(req: &HttpRequest, etag: &header::EntityTag, last_modified: header::HttpDate) -> bool {
+    if let Some(inm) = req.get_header::<header::IfNoneMatch>() {
+        return match inm {
+            header::IfNoneMatch::Any => true,
+            header::IfNoneMatch::Items(tags) => tags.iter().any(|t| t.weak_eq(etag)),
+        };
+    }
+    match req.get_header::<header::IfModifiedSince>() {
+        Some(header::IfModifiedSince(since)) => SystemTime::from(last_modified) <= SystemTime::from(since),
+        None => false,
+    }
+}
+
+fn serve_image(req: &HttpRequest, image_path: &Path) -> Result<HttpResponse> {
     // Создаем NamedFile с оптимизированными заголовками
     let file = NamedFile::open(image_path)?
         .use_etag(true)
         .use_last_modified(true);
 
     // Добавляем заголовки кэширования для изображений
-    let mut response = file.into_response(&req);
+    let mut response = file.into_response(req);
     
     // Кэшируем изображения на 1 год
     response.headers_mut().insert(
@@ -140,42 +640,60 @@ pub async fn serve_image(req: HttpRequest, path: web::Path<String>) -> Result<Ht
 }
 
 pub async fn serve_optimized_image(
-    req: HttpRequest, 
+    req: HttpRequest,
+    root: web::Data<ImageRoot>,
+    cache: web::Data<ImageCache>,
     path: web::Path<String>
 ) -> Result<HttpResponse> {
-    let image_path = PathBuf::from("web/out/static/assets/img").join(path.as_str());
-    
-    if !image_path.exists() {
+    // Одинаковый 404 для отсутствующих файлов и попыток выйти за корень
+    let Some(image_path) = root.resolve(path.as_str()) else {
         return Ok(HttpResponse::NotFound().finish());
-    }
+    };
 
-    // Проверяем Accept заголовок для WebP поддержки
-    let accepts_webp = req
+    // По заголовку Accept выбираем AVIF или WebP
+    let format = req
         .headers()
         .get("accept")
         .and_then(|h| h.to_str().ok())
-        .map(|accept| accept.contains("image/webp"))
-        .unwrap_or(false);
+        .and_then(OutputFormat::negotiate);
 
-    // Если браузер поддерживает WebP, конвертируем на лету
-    if accepts_webp {
-        let cache_key = format!("webp:{}", path.as_str());
-        
-        match IMAGE_CACHE.get_or_create_webp(&image_path, &cache_key).await {
+    // Если браузер поддерживает один из форматов, конвертируем на лету
+    if let Some(format) = format {
+        // ETag и Last-Modified зависят только от формата с его настройками качества и времени
+        // изменения исходника, поэтому условный запрос решается до конвертации
+        let modified = fs::metadata(&image_path)
+            .and_then(|m| m.modified())
+            .unwrap_or(UNIX_EPOCH);
+        let modified_secs = modified.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let etag = header::EntityTag::new_strong(format!("{}-{}", format.variant(), modified_secs));
+        // Last-Modified с точностью до секунды, как и If-Modified-Since
+        let last_modified = header::HttpDate::from(UNIX_EPOCH + std::time::Duration::from_secs(modified_secs));
+        if not_modified(&req, &etag, last_modified) {
+            return Ok(HttpResponse::NotModified()
+                .insert_header((header::CACHE_CONTROL, "public, max-age=31536000, immutable"))
+                .insert_header((header::VARY, "Accept"))
+                .insert_header(header::ETag(etag))
+                .insert_header(header::LastModified(last_modified))
+                .finish());
+        }
+        match cache.get_or_convert(&image_path, format).await {
             Ok(cached_image) => {
                 return Ok(HttpResponse::Ok()
                     .content_type(cached_image.content_type.as_str())
                     .insert_header((header::CACHE_CONTROL, "public, max-age=31536000, immutable"))
-                    .insert_header((header::ETAG, format!("\"{}\"", cached_image.last_modified)))
+                    // Разные форматы по одному URL: кэши должны учитывать Accept
+                    .insert_header((header::VARY, "Accept"))
+                    .insert_header(header::ETag(etag))
+                    .insert_header(header::LastModified(last_modified))
                     .body(cached_image.data));
             }
             Err(e) => {
-                println!("Failed to convert to WebP: {:?}", e);
+                warn!("Failed to convert {} to {}: {}", image_path.display(), format.extension(), e);
                 // Fallback к оригинальному изображению
             }
         }
     }
 
     // Возвращаем оригинальное изображение
-    serve_image(req, path).await
+    serve_image(&req, &image_path)
 }
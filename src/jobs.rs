@@ -0,0 +1,165 @@
+//! Long-running operations run in the background: the request that starts one records a job
+//! (`jobs` table) and returns at once, and clients poll `GET /api/jobs/{id}` until it is done,
+//! so that exports, imports, reports and rollup refreshes neither tie up a request nor time
+//! out behind a proxy.
+
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use serde_json::Value;
+use std::env;
+use std::future::Future;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use uuid::Uuid;
+
+use crate::database::model::jobs::Model as JobModel;
+use crate::database::store::{JobStore, StoreResult};
+use crate::tenant;
+
+/// A job records its progress at least this often, queued or running; one silent for longer
+/// than STALE_AFTER belonged to an instance that stopped
+const PROGRESS_EVERY: Duration = Duration::from_secs(30);
+const STALE_AFTER: chrono::Duration = chrono::Duration::minutes(10);
+
+/// JOBS_MAX_RUNNING (default 2) bounds the jobs running at once per instance; later ones wait
+/// queued for a slot
+#[derive(Debug, Clone)]
+pub struct JobsConfig {
+    pub max_running: usize,
+}
+
+impl JobsConfig {
+    pub fn from_env() -> Self {
+        let max_running = env::var("JOBS_MAX_RUNNING").ok().and_then(|v| v.trim().parse::<usize>().ok()).filter(|n| *n > 0).unwrap_or(2);
+        Self { max_running }
+    }
+}
+
+/// Starts jobs and reports on them; one per process, shared by the handlers
+#[derive(Clone)]
+pub struct Jobs {
+    store: Arc<dyn JobStore>,
+    slots: Arc<Semaphore>,
+}
+
+/// What a job's work knows about the job it runs for
+#[derive(Debug, Clone)]
+pub struct RunningJob {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    progress: Arc<AtomicI64>,
+}
+
+impl RunningJob {
+    /// Counts `n` more units of work done; recorded with the next save of the job
+    pub fn advance(&self, n: i64) {
+        self.progress.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn progress(&self) -> i64 {
+        self.progress.load(Ordering::Relaxed)
+    }
+}
+
+impl Jobs {
+    /// Jobs left unfinished by a stopped instance are failed here
+    pub async fn new(config: JobsConfig, store: Arc<dyn JobStore>) -> Self {
+        match store.fail_stale_jobs(Utc::now() - STALE_AFTER, "interrupted: the server stopped during the job").await {
+            Ok(0) => {}
+            Ok(n) => warn!("Marked {} interrupted job(s) as failed", n),
+            Err(e) => warn!("Looking for interrupted jobs failed: {}", e),
+        }
+        Self { store, slots: Arc::new(Semaphore::new(config.max_running.max(1))) }
+    }
+
+    /// Records a queued job of `kind` for the current tenant and runs `work` in the background,
+    /// in that tenant's scope. `work` returns the result of the job or why it failed.
+    pub async fn start<W, F>(&self, kind: &str, params: Value, work: W) -> StoreResult<JobModel>
+    where
+        W: FnOnce(RunningJob) -> F + Send + 'static,
+        F: Future<Output = Result<Value, String>> + Send + 'static,
+    {
+        let scope = tenant::current();
+        let now = Utc::now();
+        let job = JobModel {
+            id: Uuid::new_v4(),
+            kind: kind.to_string(),
+            tenant_id: scope.as_ref().and_then(|t| t.tenant_id().map(str::to_string)),
+            status: "queued".to_string(),
+            params,
+            progress: 0,
+            result: None,
+            error: None,
+            created_at: now,
+            updated_at: now,
+            started_at: None,
+            finished_at: None,
+        };
+        self.store.create_job(job.clone()).await?;
+        let jobs = self.clone();
+        let queued = job.clone();
+        tokio::spawn(tenant::scope(scope, async move { jobs.run(queued, work).await }));
+        Ok(job)
+    }
+
+    pub async fn find(&self, id: Uuid) -> StoreResult<Option<JobModel>> {
+        self.store.find_job(id).await
+    }
+
+    async fn run<W, F>(self, mut job: JobModel, work: W)
+    where
+        W: FnOnce(RunningJob) -> F,
+        F: Future<Output = Result<Value, String>>,
+    {
+        // Waiting for a slot counts as progress, or another instance starting up would fail the job
+        let _slot = loop {
+            tokio::select! {
+                slot = self.slots.clone().acquire_owned() => break slot.expect("the job semaphore is never closed"),
+                _ = tokio::time::sleep(PROGRESS_EVERY) => self.save(&mut job).await,
+            }
+        };
+        let started = Instant::now();
+        job.status = "running".to_string();
+        job.started_at = Some(Utc::now());
+        self.save(&mut job).await;
+
+        let running = RunningJob { id: job.id, created_at: job.created_at, progress: Arc::new(AtomicI64::new(0)) };
+        let work = work(running.clone());
+        tokio::pin!(work);
+        let result = loop {
+            tokio::select! {
+                result = &mut work => break result,
+                _ = tokio::time::sleep(PROGRESS_EVERY) => {
+                    job.progress = running.progress();
+                    self.save(&mut job).await;
+                }
+            }
+        };
+
+        job.progress = running.progress();
+        job.finished_at = Some(Utc::now());
+        match result {
+            Ok(value) => {
+                info!("{} job {} done in {:?}", job.kind, job.id, started.elapsed());
+                job.status = "done".to_string();
+                job.result = Some(value);
+            }
+            Err(e) => {
+                error!("{} job {} failed: {}", job.kind, job.id, e);
+                job.status = "failed".to_string();
+                job.error = Some(e);
+            }
+        }
+        self.save(&mut job).await;
+    }
+
+    /// Records the progress of a job; a failure only costs the status endpoint some freshness
+    async fn save(&self, job: &mut JobModel) {
+        job.updated_at = Utc::now();
+        if let Err(e) = self.store.save_job(job.clone()).await {
+            warn!("Saving job {} failed: {}", job.id, e);
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use chrono::{DateTime, Utc};
+use log::{debug, error, info, warn};
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{Consumer, StreamConsumer};
+use rdkafka::message::{BorrowedMessage, Message};
+use std::env;
+use std::time::Duration;
+
+use crate::api::error::ApiError;
+use crate::api::points::{self, Ack, Ingestion};
+use crate::database::store::{NewPointRecord, TenantScope};
+use crate::request_id;
+use crate::tenant::{self, TenantConfig};
+
+const DEFAULT_TOPIC: &str = "points";
+const DEFAULT_GROUP: &str = "indrive-ingest";
+/// Wait after a consumer error, and between retries of a message the pipeline turned away
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Consumes point batches from KAFKA_TOPIC on KAFKA_BROKERS as consumer group KAFKA_GROUP_ID
+/// and stores them through the same pipeline as `POST /api/points`; returns whether it was
+/// started. Delivery is at least once: a message's offset is committed only after its points
+/// are stored, and a redelivered point is not stored twice because its UUID, when it has none,
+/// is derived from (randomized_id, timestamp) and caught by the unique index on `client_uuid`.
+/// Points belong to the tenant KAFKA_TENANT names (see `TenantConfig::for_transport`).
+pub fn spawn(ingestion: Ingestion, tenants: &TenantConfig) -> Result<bool, String> {
+    let Some(brokers) = env::var("KAFKA_BROKERS").ok().filter(|v| !v.trim().is_empty()) else {
+        return Ok(false);
+    };
+    let topic = env::var("KAFKA_TOPIC").ok().filter(|v| !v.trim().is_empty()).unwrap_or_else(|| DEFAULT_TOPIC.to_string());
+    let group = env::var("KAFKA_GROUP_ID").ok().filter(|v| !v.trim().is_empty()).unwrap_or_else(|| DEFAULT_GROUP.to_string());
+    let tenant = tenants.for_transport("KAFKA_TENANT")?;
+
+    let mut config = ClientConfig::new();
+    config
+        .set("bootstrap.servers", brokers.trim())
+        .set("group.id", &group)
+        .set("auto.offset.reset", "earliest")
+        // Offsets are stored by hand once a message is processed and committed in the background
+        .set("enable.auto.commit", "true")
+        .set("enable.auto.offset.store", "false");
+    // Security and tuning settings of librdkafka, e.g. `security.protocol=SASL_SSL,sasl.mechanism=PLAIN`
+    if let Ok(options) = env::var("KAFKA_OPTIONS") {
+        for option in options.split(',').map(str::trim).filter(|o| !o.is_empty()) {
+            let (key, value) = option.split_once('=').ok_or_else(|| format!("KAFKA_OPTIONS: expected key=value, got {:?}", option))?;
+            config.set(key.trim(), value.trim());
+        }
+    }
+    let consumer: StreamConsumer = config.create().map_err(|e| format!("Kafka consumer: {}", e))?;
+    consumer.subscribe(&[&topic]).map_err(|e| format!("Kafka topic {}: {}", topic, e))?;
+
+    info!("Kafka ingestion from topic {} as group {}", topic, group);
+    tokio::spawn(consume(consumer, ingestion, tenant));
+    Ok(true)
+}
+
+/// Processes messages one at a time; librdkafka reconnects on its own, so errors only pause
+async fn consume(consumer: StreamConsumer, ingestion: Ingestion, tenant: Option<TenantScope>) {
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        let message = match consumer.recv().await {
+            Ok(message) => message,
+            Err(e) => {
+                warn!("Kafka consumer error: {}; retrying in {:?}", e, backoff);
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+                continue;
+            }
+        };
+        backoff = MIN_BACKOFF;
+        let stored = tenant::scope(tenant.clone(), store_message(&ingestion, &message));
+        request_id::scope(request_id::from_incoming(None), stored).await;
+        if let Err(e) = consumer.store_offset_from_message(&message) {
+            error!("Could not store Kafka offset {} of {}/{}: {}", message.offset(), message.topic(), message.partition(), e);
+        }
+    }
+}
+
+/// Malformed messages are logged and skipped; a full batcher or a database outage without a
+/// WAL is waited out, so the offset does not move past points that were not kept
+async fn store_message(ingestion: &Ingestion, message: &BorrowedMessage<'_>) {
+    let at = format!("{}/{}@{}", message.topic(), message.partition(), message.offset());
+    let Some(payload) = message.payload() else {
+        debug!("Skipping empty Kafka message {}", at);
+        return;
+    };
+    // A numeric key names the device, as it does when the stream is partitioned by device
+    let device = message
+        .key()
+        .and_then(|k| std::str::from_utf8(k).ok())
+        .and_then(|k| k.trim().parse::<i64>().ok());
+    let points = match points::decode_message(payload, device) {
+        Ok(points) => points,
+        Err(e) => {
+            warn!("Skipping Kafka message {}: {}", at, e);
+            return;
+        }
+    };
+    // Without a device timestamp the message's own stands in, so a redelivery gets the same one
+    let sent = message.timestamp().to_millis().and_then(DateTime::<Utc>::from_timestamp_millis);
+    let records: Vec<NewPointRecord> = points
+        .into_iter()
+        .map(|p| {
+            let mut record = NewPointRecord::from(p);
+            record.timestamp = record.timestamp.or(sent);
+            record.client_uuid = record.client_uuid.or_else(|| points::dedup_uuid(&record));
+            record
+        })
+        .collect();
+
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        match ingestion.ingest(records.clone(), Ack::Persisted).await {
+            Ok(resp) => {
+                let duplicates = resp.points.iter().filter(|p| p.duplicate).count();
+                debug!(
+                    "Stored {} points from {} ({} already stored, {} buffered, {} quarantined)",
+                    resp.points.len() - duplicates, at, duplicates, resp.buffered, resp.quarantined.len()
+                );
+                return;
+            }
+            Err(ApiError::ServiceUnavailable(reason)) => {
+                warn!("Could not store {} points from {}: {}; retrying in {:?}", records.len(), at, reason, backoff);
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+            Err(e) => {
+                error!("Skipping {} points from {}: {}", records.len(), at, e);
+                return;
+            }
+        }
+    }
+}
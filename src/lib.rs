@@ -0,0 +1,37 @@
+//! Server library: everything `main.rs` wires together, also used by the integration tests
+//! under `tests/`
+
+pub mod routes;
+pub mod templates;
+pub mod image_compressor;
+pub mod database;
+pub mod api;
+pub mod migration;
+pub mod anomaly;
+pub mod telemetry;
+pub mod mvt;
+pub mod client_ip;
+pub mod cors;
+pub mod geo;
+pub mod stale;
+pub mod rate_limit;
+pub mod self_check;
+pub mod metrics;
+pub mod map_matching;
+pub mod geocoding;
+pub mod roads;
+pub mod reports;
+pub mod exports;
+pub mod jobs;
+pub mod request_id;
+pub mod tenant;
+pub mod webhooks;
+pub mod geofences;
+pub mod alerts;
+pub mod feed;
+pub mod cli;
+pub mod seed;
+pub mod grpc;
+pub mod mqtt;
+#[cfg(feature = "kafka")]
+pub mod kafka;
@@ -0,0 +1,238 @@
+use chrono::{Duration, Utc};
+use log::{debug, error, info, warn};
+use serde::Deserialize;
+use std::env;
+use std::sync::Arc;
+
+use crate::database::model::matched_trips::Model as MatchedTripModel;
+use crate::database::model::points::Model as PointModel;
+use crate::database::model::trips::Model as TripModel;
+use crate::database::store::{PointFilter, PointOrder, PointStore, TripStore};
+use crate::metrics::metrics;
+
+/// Name of the worker on the status page
+const JOB_NAME: &str = "map matching";
+
+/// Coordinates per `match` request; OSRM refuses more than its --max-matching-size (100 by default)
+const DEFAULT_MAX_POINTS: usize = 100;
+
+#[derive(Debug, Deserialize)]
+struct OsrmResponse {
+    code: String,
+    message: Option<String>,
+    #[serde(default)]
+    matchings: Vec<OsrmMatching>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OsrmMatching {
+    confidence: f64,
+    distance: f64,
+    geometry: OsrmGeometry,
+    #[serde(default)]
+    legs: Vec<OsrmLeg>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OsrmGeometry {
+    coordinates: Vec<[f64; 2]>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OsrmLeg {
+    annotation: Option<OsrmAnnotation>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OsrmAnnotation {
+    #[serde(default)]
+    nodes: Vec<i64>,
+}
+
+/// Route of one trip on the road network
+#[derive(Debug, Default)]
+pub struct MatchedRoute {
+    pub confidence: f64,
+    pub distance_m: f64,
+    /// `[lng, lat]` pairs
+    pub geometry: Vec<[f64; 2]>,
+    pub road_node_ids: Vec<i64>,
+}
+
+/// Client of an OSRM `match` service (http://project-osrm.org/docs/v5.24.0/api/#match-service)
+pub struct MapMatcher {
+    client: reqwest::Client,
+    base_url: String,
+    profile: String,
+    max_points: usize,
+}
+
+impl MapMatcher {
+    /// MAP_MATCHING_URL (e.g. `http://osrm:5000`) enables the subsystem; MAP_MATCHING_PROFILE
+    /// (default `driving`), MAP_MATCHING_MAX_POINTS (default 100) and MAP_MATCHING_TIMEOUT_SECS
+    /// (default 10) tune the requests.
+    pub fn from_env() -> Option<Self> {
+        let base_url = env::var("MAP_MATCHING_URL").ok().filter(|u| !u.is_empty())?;
+        let profile = env::var("MAP_MATCHING_PROFILE").unwrap_or_else(|_| "driving".to_string());
+        let max_points = env::var("MAP_MATCHING_MAX_POINTS")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .filter(|n| *n >= 2)
+            .unwrap_or(DEFAULT_MAX_POINTS);
+        let timeout = env::var("MAP_MATCHING_TIMEOUT_SECS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .filter(|s| *s > 0)
+            .unwrap_or(10);
+        let client = reqwest::Client::builder()
+            .timeout(std::time::Duration::from_secs(timeout))
+            .build()
+            .expect("Failed to build map matching HTTP client");
+        Some(Self { client, base_url: base_url.trim_end_matches('/').to_string(), profile, max_points })
+    }
+
+    /// Matches time-ordered points. Long trips go out in chunks that share their boundary point,
+    /// so the pieces join up. Err means the service could not be asked; a trace OSRM cannot place
+    /// on any road is an empty route.
+    pub async fn match_points(&self, points: &[PointModel]) -> Result<MatchedRoute, String> {
+        let mut route = MatchedRoute::default();
+        if points.len() < 2 {
+            return Ok(route);
+        }
+        let mut weighted_confidence = 0.0;
+        let mut start = 0;
+        while start + 1 < points.len() {
+            let end = (start + self.max_points).min(points.len());
+            for m in self.request(&points[start..end]).await? {
+                weighted_confidence += m.confidence * m.distance;
+                route.distance_m += m.distance;
+                append_dedup(&mut route.geometry, m.geometry.coordinates);
+                for leg in m.legs {
+                    if let Some(a) = leg.annotation {
+                        append_dedup(&mut route.road_node_ids, a.nodes);
+                    }
+                }
+            }
+            start = end - 1;
+        }
+        if route.distance_m > 0.0 {
+            route.confidence = weighted_confidence / route.distance_m;
+        }
+        Ok(route)
+    }
+
+    async fn request(&self, points: &[PointModel]) -> Result<Vec<OsrmMatching>, String> {
+        let coords: Vec<String> = points.iter().map(|p| format!("{:.6},{:.6}", p.lng, p.lat)).collect();
+        let url = format!("{}/match/v1/{}/{}", self.base_url, self.profile, coords.join(";"));
+        let mut query = vec![
+            ("overview", "full".to_string()),
+            ("geometries", "geojson".to_string()),
+            ("annotations", "nodes".to_string()),
+            ("gaps", "split".to_string()),
+            ("tidy", "true".to_string()),
+        ];
+        // Timestamps help OSRM tell a U-turn from noise; all or nothing
+        if let Some(ts) = points.iter().map(|p| p.timestamp.map(|t| t.timestamp().to_string())).collect::<Option<Vec<_>>>() {
+            query.push(("timestamps", ts.join(";")));
+        }
+
+        let resp = self.client.get(&url).query(&query).send().await.map_err(|e| e.to_string())?;
+        let status = resp.status();
+        let body: OsrmResponse = resp.json().await.map_err(|e| format!("unreadable response ({}): {}", status, e))?;
+        match body.code.as_str() {
+            "Ok" => Ok(body.matchings),
+            "NoMatch" | "NoSegment" => Ok(Vec::new()),
+            _ => Err(format!("{}: {}", body.code, body.message.unwrap_or_default())),
+        }
+    }
+}
+
+/// Starts the background worker when MAP_MATCHING_URL is set. Every MAP_MATCHING_INTERVAL_SECS
+/// (default 300) it matches up to MAP_MATCHING_BATCH (default 50) trips without a point for
+/// MAP_MATCHING_IDLE_SECS (default 600) that were never matched or grew since. A trip the
+/// service fails on waits MAP_MATCHING_RETRY_SECS (default 300), twice as long after each
+/// further failure, before it is tried again. Returns whether the worker was started.
+pub fn spawn(trips: Arc<dyn TripStore>, store: Arc<dyn PointStore>) -> bool {
+    let Some(matcher) = MapMatcher::from_env() else {
+        info!("Map matching disabled (MAP_MATCHING_URL not set)");
+        return false;
+    };
+    let env_u64 = |name: &str, default: u64| {
+        env::var(name).ok().and_then(|v| v.parse::<u64>().ok()).filter(|v| *v > 0).unwrap_or(default)
+    };
+    let interval = env_u64("MAP_MATCHING_INTERVAL_SECS", 300);
+    let batch = env_u64("MAP_MATCHING_BATCH", 50);
+    let idle = env_u64("MAP_MATCHING_IDLE_SECS", 600);
+    let retry = Duration::seconds(env_u64("MAP_MATCHING_RETRY_SECS", 300) as i64);
+    info!(
+        "Map matching via {} (profile {}): every {}s, {} trips idle for {}s",
+        matcher.base_url, matcher.profile, interval, batch, idle
+    );
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(std::time::Duration::from_secs(interval));
+        loop {
+            ticker.tick().await;
+            let ended_before = Utc::now() - Duration::seconds(idle as i64);
+            let pending = match trips.trips_to_match(ended_before, batch).await {
+                Ok(t) => t,
+                Err(e) => {
+                    error!("Map matching: trip lookup failed: {}", e);
+                    metrics().record_job(JOB_NAME, false, e.to_string());
+                    continue;
+                }
+            };
+            if pending.is_empty() {
+                continue;
+            }
+            let (mut matched, mut failed) = (0usize, 0usize);
+            for trip in &pending {
+                match match_trip(&matcher, trips.as_ref(), store.as_ref(), trip).await {
+                    Ok(()) => matched += 1,
+                    Err(e) => {
+                        failed += 1;
+                        match trips.record_match_failure(trip.randomized_id, &e, retry).await {
+                            Ok(attempts) => warn!("Map matching failed for trip {} (attempt {}): {}", trip.randomized_id, attempts, e),
+                            Err(err) => error!("Map matching failed for trip {}: {}; recording the failure failed too: {}", trip.randomized_id, e, err),
+                        }
+                    }
+                }
+            }
+            info!("Map matching: {} trips matched, {} failed", matched, failed);
+            metrics().record_job(JOB_NAME, failed == 0, format!("{} trips matched, {} failed", matched, failed));
+        }
+    });
+    true
+}
+
+async fn match_trip(matcher: &MapMatcher, trips: &dyn TripStore, store: &dyn PointStore, trip: &TripModel) -> Result<(), String> {
+    let filter = PointFilter { randomized_id: Some(trip.randomized_id), ..Default::default() };
+    let points = store.find(&filter, PointOrder::TimestampAsc, None).await.map_err(|e| e.to_string())?;
+    let route = matcher.match_points(&points).await?;
+    debug!(
+        "Trip {}: {} points matched to {} coordinates, confidence {:.2}",
+        trip.randomized_id, points.len(), route.geometry.len(), route.confidence
+    );
+    // Unmatched trips are stored too, so they are not retried until they change
+    let matched = MatchedTripModel {
+        randomized_id: trip.randomized_id,
+        matched_at: Utc::now(),
+        source_point_count: trip.point_count,
+        confidence: route.confidence,
+        distance_m: route.distance_m,
+        geometry: serde_json::json!(route.geometry),
+        road_node_ids: serde_json::json!(route.road_node_ids),
+    };
+    trips.save_matched(matched).await.map_err(|e| e.to_string())
+}
+
+/// Appends `more`, dropping its first item when it repeats the current last one
+fn append_dedup<T: PartialEq>(acc: &mut Vec<T>, more: Vec<T>) {
+    let mut more = more.into_iter().peekable();
+    if let (Some(last), Some(first)) = (acc.last(), more.peek())
+        && last == first
+    {
+        more.next();
+    }
+    acc.extend(more);
+}
@@ -0,0 +1,185 @@
+use actix_web::dev::ServiceResponse;
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Window the ingestion rate is averaged over
+const RATE_WINDOW_SECS: u64 = 60;
+/// How long per-second ingestion counts are kept, for the hourly total
+const HISTORY_SECS: u64 = 3600;
+
+/// In-process counters behind the `/status` page. Reset on restart.
+pub struct Metrics {
+    started: Instant,
+    started_at: DateTime<Utc>,
+    points_ingested: AtomicU64,
+    /// (second since start, points stored in that second), oldest first
+    recent_ingest: Mutex<VecDeque<(u64, u64)>>,
+    image_cache_hits: AtomicU64,
+    image_cache_misses: AtomicU64,
+    stale_responses: AtomicU64,
+    endpoints: Mutex<BTreeMap<String, EndpointStats>>,
+    jobs: Mutex<BTreeMap<&'static str, JobStatus>>,
+}
+
+#[derive(Debug, Default, Clone)]
+struct EndpointStats {
+    requests: u64,
+    errors: u64,
+    total_ms: f64,
+}
+
+/// Outcome of the last run of a background job or outbound dependency
+#[derive(Debug, Clone, Serialize)]
+pub struct JobStatus {
+    pub last_run: DateTime<Utc>,
+    pub ok: bool,
+    pub detail: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EndpointSnapshot {
+    pub pattern: String,
+    pub requests: u64,
+    /// Responses with a 5xx status
+    pub errors: u64,
+    pub avg_ms: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JobSnapshot {
+    pub name: &'static str,
+    #[serde(flatten)]
+    pub status: JobStatus,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Snapshot {
+    pub started_at: DateTime<Utc>,
+    pub uptime_secs: u64,
+    pub points_ingested: u64,
+    /// Points stored per minute, averaged over the last minute
+    pub ingest_per_minute: u64,
+    /// Points stored in the last hour, or since startup when that was more recent
+    pub ingested_last_hour: u64,
+    pub image_cache_hits: u64,
+    pub image_cache_misses: u64,
+    /// Share of optimized images served from memory, None before the first request
+    pub image_cache_hit_rate: Option<f64>,
+    /// Responses answered from the stale cache during a database outage
+    pub stale_responses: u64,
+    pub endpoints: Vec<EndpointSnapshot>,
+    pub jobs: Vec<JobSnapshot>,
+}
+
+static METRICS: Lazy<Metrics> = Lazy::new(|| Metrics {
+    started: Instant::now(),
+    started_at: Utc::now(),
+    points_ingested: AtomicU64::new(0),
+    recent_ingest: Mutex::new(VecDeque::new()),
+    image_cache_hits: AtomicU64::new(0),
+    image_cache_misses: AtomicU64::new(0),
+    stale_responses: AtomicU64::new(0),
+    endpoints: Mutex::new(BTreeMap::new()),
+    jobs: Mutex::new(BTreeMap::new()),
+});
+
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+impl Metrics {
+    pub fn record_ingested(&self, points: u64) {
+        self.points_ingested.fetch_add(points, Ordering::Relaxed);
+        let now = self.started.elapsed().as_secs();
+        let mut recent = self.recent_ingest.lock().unwrap();
+        match recent.back_mut() {
+            Some((second, count)) if *second == now => *count += points,
+            _ => recent.push_back((now, points)),
+        }
+        prune(&mut recent, now);
+    }
+
+    pub fn record_image_cache(&self, hit: bool) {
+        let counter = if hit { &self.image_cache_hits } else { &self.image_cache_misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_stale_response(&self) {
+        self.stale_responses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a request under its route pattern, so path parameters do not multiply entries
+    pub fn record_request<B>(&self, res: &ServiceResponse<B>, took: Duration) {
+        let pattern = res.request().match_pattern().unwrap_or_else(|| "(unmatched)".to_string());
+        let key = format!("{} {}", res.request().method(), pattern);
+        let mut endpoints = self.endpoints.lock().unwrap();
+        let stats = endpoints.entry(key).or_default();
+        stats.requests += 1;
+        if res.status().is_server_error() {
+            stats.errors += 1;
+        }
+        stats.total_ms += took.as_secs_f64() * 1000.0;
+    }
+
+    pub fn record_job(&self, name: &'static str, ok: bool, detail: impl Into<String>) {
+        let status = JobStatus { last_run: Utc::now(), ok, detail: detail.into() };
+        self.jobs.lock().unwrap().insert(name, status);
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        let now = self.started.elapsed().as_secs();
+        let (ingest_per_minute, ingested_last_hour) = {
+            let mut recent = self.recent_ingest.lock().unwrap();
+            prune(&mut recent, now);
+            let last_minute: u64 = recent.iter().filter(|(second, _)| second + RATE_WINDOW_SECS > now).map(|(_, n)| n).sum();
+            // Right after startup the window is shorter than a minute
+            let window = (now + 1).min(RATE_WINDOW_SECS);
+            (last_minute * 60 / window, recent.iter().map(|(_, n)| n).sum())
+        };
+        let hits = self.image_cache_hits.load(Ordering::Relaxed);
+        let misses = self.image_cache_misses.load(Ordering::Relaxed);
+        let endpoints = self
+            .endpoints
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(pattern, s)| EndpointSnapshot {
+                pattern: pattern.clone(),
+                requests: s.requests,
+                errors: s.errors,
+                avg_ms: s.total_ms / s.requests.max(1) as f64,
+            })
+            .collect();
+        let jobs = self
+            .jobs
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, status)| JobSnapshot { name, status: status.clone() })
+            .collect();
+        Snapshot {
+            started_at: self.started_at,
+            uptime_secs: now,
+            points_ingested: self.points_ingested.load(Ordering::Relaxed),
+            ingest_per_minute,
+            ingested_last_hour,
+            image_cache_hits: hits,
+            image_cache_misses: misses,
+            image_cache_hit_rate: (hits + misses > 0).then(|| hits as f64 / (hits + misses) as f64),
+            stale_responses: self.stale_responses.load(Ordering::Relaxed),
+            endpoints,
+            jobs,
+        }
+    }
+}
+
+fn prune(recent: &mut VecDeque<(u64, u64)>, now: u64) {
+    while recent.front().is_some_and(|(second, _)| second + HISTORY_SECS <= now) {
+        recent.pop_front();
+    }
+}
@@ -1,4 +1,5 @@
 use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::DatabaseBackend;
 
 #[derive(DeriveMigrationName)]
 pub struct Migration;
@@ -6,6 +7,12 @@ pub struct Migration;
 #[async_trait::async_trait]
 impl MigrationTrait for Migration {
     async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // SQLite's CURRENT_TIMESTAMP is zoneless text with a space, which does not sort with
+        // the RFC 3339 values the driver binds, so range filters would skip defaulted rows
+        let now = match manager.get_database_backend() {
+            DatabaseBackend::Sqlite => Expr::cust("(strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now'))"),
+            _ => Expr::current_timestamp().into(),
+        };
         manager
             .create_table(
                 Table::create()
@@ -27,7 +34,7 @@ impl MigrationTrait for Migration {
                     .col(
                         ColumnDef::new(Points::Timestamp)
                             .timestamp_with_time_zone()
-                            .default(Expr::current_timestamp()),
+                            .default(now),
                     )
                     .col(ColumnDef::new(Points::Anomaly).boolean())
                     .to_owned(),
@@ -0,0 +1,45 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Points::Table)
+                    .add_column_if_not_exists(ColumnDef::new(Points::ClientUuid).uuid().null())
+                    .to_owned(),
+            )
+            .await?;
+        // Unique index doubles as the lookup path for retried uploads; NULLs never collide
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_points_client_uuid")
+                    .table(Points::Table)
+                    .col(Points::ClientUuid)
+                    .unique()
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(Index::drop().name("idx_points_client_uuid").table(Points::Table).to_owned())
+            .await?;
+        manager
+            .alter_table(Table::alter().table(Points::Table).drop_column(Points::ClientUuid).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Points {
+    Table,
+    ClientUuid,
+}
@@ -0,0 +1,99 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::DatabaseBackend;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Trips::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(Trips::RandomizedId).big_integer().not_null().primary_key())
+                    .col(ColumnDef::new(Trips::StartTs).timestamp_with_time_zone().not_null())
+                    .col(ColumnDef::new(Trips::EndTs).timestamp_with_time_zone().not_null())
+                    .col(ColumnDef::new(Trips::StartLat).double().not_null())
+                    .col(ColumnDef::new(Trips::StartLng).double().not_null())
+                    .col(ColumnDef::new(Trips::EndLat).double().not_null())
+                    .col(ColumnDef::new(Trips::EndLng).double().not_null())
+                    .col(ColumnDef::new(Trips::DistanceM).double().not_null())
+                    .col(ColumnDef::new(Trips::DurationS).double().not_null())
+                    .col(ColumnDef::new(Trips::AvgSpeed).double().not_null())
+                    .col(ColumnDef::new(Trips::MaxSpeed).double().not_null())
+                    .col(ColumnDef::new(Trips::PointCount).big_integer().not_null())
+                    .col(ColumnDef::new(Trips::Anomaly).boolean().not_null().default(false))
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_trips_start_ts")
+                    .table(Trips::Table)
+                    .col(Trips::StartTs)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+
+        // Summarize points stored before this table existed; later inserts maintain it
+        if manager.get_database_backend() == DatabaseBackend::Postgres {
+            manager.get_connection().execute_unprepared(BACKFILL_SQL).await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Trips::Table).to_owned())
+            .await
+    }
+}
+
+/// Haversine with the same 6371 km radius as the application code
+const BACKFILL_SQL: &str = r#"
+INSERT INTO trips (randomized_id, start_ts, end_ts, start_lat, start_lng, end_lat, end_lng,
+                   distance_m, duration_s, avg_speed, max_speed, point_count, anomaly)
+SELECT randomized_id,
+       MIN(ts), MAX(ts),
+       MAX(lat) FILTER (WHERE rn_first = 1), MAX(lng) FILTER (WHERE rn_first = 1),
+       MAX(lat) FILTER (WHERE rn_last = 1), MAX(lng) FILTER (WHERE rn_last = 1),
+       COALESCE(SUM(2 * 6371000.0 * asin(sqrt(
+           power(sin(radians(lat - prev_lat) / 2), 2)
+           + cos(radians(prev_lat)) * cos(radians(lat)) * power(sin(radians(lng - prev_lng) / 2), 2)
+       ))), 0),
+       EXTRACT(EPOCH FROM MAX(ts) - MIN(ts)),
+       AVG(spd), MAX(spd), COUNT(*),
+       COALESCE(BOOL_OR(anomaly), FALSE)
+FROM (
+    SELECT randomized_id, lat, lng, spd, anomaly, COALESCE(timestamp, now()) AS ts,
+           LAG(lat) OVER w AS prev_lat, LAG(lng) OVER w AS prev_lng,
+           ROW_NUMBER() OVER w AS rn_first,
+           ROW_NUMBER() OVER (PARTITION BY randomized_id ORDER BY timestamp DESC, id DESC) AS rn_last
+    FROM points
+    WINDOW w AS (PARTITION BY randomized_id ORDER BY timestamp, id)
+) ordered
+GROUP BY randomized_id
+ON CONFLICT (randomized_id) DO NOTHING
+"#;
+
+#[derive(DeriveIden)]
+enum Trips {
+    Table,
+    RandomizedId,
+    StartTs,
+    EndTs,
+    StartLat,
+    StartLng,
+    EndLat,
+    EndLng,
+    DistanceM,
+    DurationS,
+    AvgSpeed,
+    MaxSpeed,
+    PointCount,
+    Anomaly,
+}
@@ -0,0 +1,43 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // One column per statement: SQLite alters a table one change at a time
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Trips::Table)
+                    .add_column_if_not_exists(ColumnDef::new(Trips::AnomalyReviewedBy).string().null())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Trips::Table)
+                    .add_column_if_not_exists(ColumnDef::new(Trips::AnomalyReviewedAt).timestamp_with_time_zone().null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(Table::alter().table(Trips::Table).drop_column(Trips::AnomalyReviewedAt).to_owned())
+            .await?;
+        manager
+            .alter_table(Table::alter().table(Trips::Table).drop_column(Trips::AnomalyReviewedBy).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Trips {
+    Table,
+    AnomalyReviewedBy,
+    AnomalyReviewedAt,
+}
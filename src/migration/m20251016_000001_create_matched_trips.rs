@@ -0,0 +1,57 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(MatchedTrips::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(MatchedTrips::RandomizedId).big_integer().not_null().primary_key())
+                    .col(ColumnDef::new(MatchedTrips::MatchedAt).timestamp_with_time_zone().not_null())
+                    .col(ColumnDef::new(MatchedTrips::SourcePointCount).big_integer().not_null())
+                    .col(ColumnDef::new(MatchedTrips::Confidence).double().not_null())
+                    .col(ColumnDef::new(MatchedTrips::DistanceM).double().not_null())
+                    .col(ColumnDef::new(MatchedTrips::Geometry).json_binary().not_null())
+                    .col(ColumnDef::new(MatchedTrips::RoadNodeIds).json_binary().not_null())
+                    // Goes away with the trip when its points are purged
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_matched_trips_trip")
+                            .from(MatchedTrips::Table, MatchedTrips::RandomizedId)
+                            .to(Trips::Table, Trips::RandomizedId)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(MatchedTrips::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum MatchedTrips {
+    Table,
+    RandomizedId,
+    MatchedAt,
+    SourcePointCount,
+    Confidence,
+    DistanceM,
+    Geometry,
+    RoadNodeIds,
+}
+
+#[derive(DeriveIden)]
+enum Trips {
+    Table,
+    RandomizedId,
+}
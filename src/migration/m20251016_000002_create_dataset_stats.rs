@@ -0,0 +1,92 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::DatabaseBackend;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(DatasetStats::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(DatasetStats::Id).small_integer().not_null().primary_key())
+                    .col(ColumnDef::new(DatasetStats::TotalPoints).big_integer().not_null())
+                    .col(ColumnDef::new(DatasetStats::TotalTrips).big_integer().not_null())
+                    .col(ColumnDef::new(DatasetStats::LatMin).double().null())
+                    .col(ColumnDef::new(DatasetStats::LatMax).double().null())
+                    .col(ColumnDef::new(DatasetStats::LngMin).double().null())
+                    .col(ColumnDef::new(DatasetStats::LngMax).double().null())
+                    .col(ColumnDef::new(DatasetStats::FirstTs).timestamp_with_time_zone().null())
+                    .col(ColumnDef::new(DatasetStats::LastTs).timestamp_with_time_zone().null())
+                    .col(ColumnDef::new(DatasetStats::UpdatedAt).timestamp_with_time_zone().not_null())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_table(
+                Table::create()
+                    .table(IngestDaily::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(IngestDaily::Day).date().not_null().primary_key())
+                    .col(ColumnDef::new(IngestDaily::Points).big_integer().not_null())
+                    .to_owned(),
+            )
+            .await?;
+
+        // Totals for points stored before these tables existed; later inserts maintain them
+        if manager.get_database_backend() == DatabaseBackend::Postgres {
+            manager.get_connection().execute_unprepared(BACKFILL_SQL).await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(IngestDaily::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(DatasetStats::Table).to_owned())
+            .await
+    }
+}
+
+/// Ingestion time was never recorded, so old points count on the day of their timestamp
+const BACKFILL_SQL: &str = r#"
+INSERT INTO dataset_stats (id, total_points, total_trips, lat_min, lat_max, lng_min, lng_max, first_ts, last_ts, updated_at)
+SELECT 1, COUNT(*), (SELECT COUNT(*) FROM trips),
+       MIN(lat), MAX(lat), MIN(lng), MAX(lng), MIN(timestamp), MAX(timestamp), now()
+FROM points
+ON CONFLICT (id) DO NOTHING;
+
+INSERT INTO ingest_daily (day, points)
+SELECT (timestamp AT TIME ZONE 'UTC')::date, COUNT(*)
+FROM points
+WHERE timestamp IS NOT NULL
+GROUP BY 1
+ON CONFLICT (day) DO NOTHING;
+"#;
+
+#[derive(DeriveIden)]
+enum DatasetStats {
+    Table,
+    Id,
+    TotalPoints,
+    TotalTrips,
+    LatMin,
+    LatMax,
+    LngMin,
+    LngMax,
+    FirstTs,
+    LastTs,
+    UpdatedAt,
+}
+
+#[derive(DeriveIden)]
+enum IngestDaily {
+    Table,
+    Day,
+    Points,
+}
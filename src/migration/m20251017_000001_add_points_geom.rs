@@ -0,0 +1,73 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::{DatabaseBackend, Statement};
+
+/// Adds `points.geom` when the database has PostGIS, installing the extension only when the
+/// migrating role is a superuser (PostGIS is not a trusted extension); a no-op otherwise, so
+/// deployments without the extension keep the plain lat/lng filters. POSTGIS=off skips it as
+/// well.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        if manager.get_database_backend() != DatabaseBackend::Postgres || std::env::var("POSTGIS").is_ok_and(|v| v == "off") {
+            return Ok(());
+        }
+        let conn = manager.get_connection();
+        let exists = |sql: &str| conn.query_one(Statement::from_string(DatabaseBackend::Postgres, sql.to_string()));
+        if exists("SELECT 1 FROM pg_extension WHERE extname = 'postgis'").await?.is_none() {
+            if exists("SELECT 1 FROM pg_available_extensions WHERE name = 'postgis'").await?.is_none() {
+                return Ok(());
+            }
+            // A failed CREATE EXTENSION would abort the migration's transaction
+            if exists("SELECT 1 FROM pg_roles WHERE rolname = current_user AND rolsuper").await?.is_none() {
+                log::warn!("PostGIS is available but not installed, and only a superuser can install it; skipping points.geom");
+                return Ok(());
+            }
+            conn.execute_unprepared("CREATE EXTENSION IF NOT EXISTS postgis").await?;
+        }
+        conn.execute_unprepared(UP_SQL).await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        if manager.get_database_backend() != DatabaseBackend::Postgres {
+            return Ok(());
+        }
+        // The extension stays; other schemas may use it
+        manager.get_connection().execute_unprepared(DOWN_SQL).await?;
+        Ok(())
+    }
+}
+
+/// The trigger keeps `geom` in step with lat/lng for every writer, including raw SQL imports.
+/// Bbox filters go through the geometry cast, which has its own index, so their result is
+/// exactly the lat/lng ranges; the geography index serves distance queries.
+const UP_SQL: &str = r#"
+ALTER TABLE points ADD COLUMN IF NOT EXISTS geom geography(Point, 4326);
+
+CREATE OR REPLACE FUNCTION points_set_geom() RETURNS trigger AS $$
+BEGIN
+    NEW.geom := ST_SetSRID(ST_MakePoint(NEW.lng, NEW.lat), 4326)::geography;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS points_set_geom ON points;
+CREATE TRIGGER points_set_geom BEFORE INSERT OR UPDATE OF lat, lng ON points
+    FOR EACH ROW EXECUTE FUNCTION points_set_geom();
+
+UPDATE points SET geom = ST_SetSRID(ST_MakePoint(lng, lat), 4326)::geography WHERE geom IS NULL;
+
+CREATE INDEX IF NOT EXISTS idx_points_geom ON points USING GIST (geom);
+CREATE INDEX IF NOT EXISTS idx_points_geom_geometry ON points USING GIST ((geom::geometry));
+"#;
+
+const DOWN_SQL: &str = r#"
+DROP TRIGGER IF EXISTS points_set_geom ON points;
+DROP FUNCTION IF EXISTS points_set_geom();
+DROP INDEX IF EXISTS idx_points_geom_geometry;
+DROP INDEX IF EXISTS idx_points_geom;
+ALTER TABLE points DROP COLUMN IF EXISTS geom;
+"#;
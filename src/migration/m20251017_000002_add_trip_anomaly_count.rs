@@ -0,0 +1,46 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::DatabaseBackend;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Trips::Table)
+                    .add_column_if_not_exists(ColumnDef::new(Trips::AnomalyCount).big_integer().not_null().default(0))
+                    .to_owned(),
+            )
+            .await?;
+        if manager.get_database_backend() == DatabaseBackend::Postgres {
+            manager.get_connection().execute_unprepared(BACKFILL_SQL).await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Trips::Table)
+                    .drop_column(Trips::AnomalyCount)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+const BACKFILL_SQL: &str = r#"
+UPDATE trips SET anomaly_count = flagged.n
+FROM (SELECT randomized_id, COUNT(*) AS n FROM points WHERE anomaly GROUP BY randomized_id) flagged
+WHERE trips.randomized_id = flagged.randomized_id
+"#;
+
+#[derive(DeriveIden)]
+enum Trips {
+    Table,
+    AnomalyCount,
+}
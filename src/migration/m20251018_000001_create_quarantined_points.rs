@@ -0,0 +1,61 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(QuarantinedPoints::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(QuarantinedPoints::Id).big_integer().not_null().auto_increment().primary_key())
+                    .col(ColumnDef::new(QuarantinedPoints::RandomizedId).big_integer().not_null())
+                    .col(ColumnDef::new(QuarantinedPoints::Lat).double().not_null())
+                    .col(ColumnDef::new(QuarantinedPoints::Lng).double().not_null())
+                    .col(ColumnDef::new(QuarantinedPoints::Alt).double().not_null())
+                    .col(ColumnDef::new(QuarantinedPoints::Spd).double().not_null())
+                    .col(ColumnDef::new(QuarantinedPoints::Azm).double().not_null())
+                    .col(ColumnDef::new(QuarantinedPoints::Timestamp).timestamp_with_time_zone().not_null())
+                    .col(ColumnDef::new(QuarantinedPoints::ClientUuid).uuid().null())
+                    .col(ColumnDef::new(QuarantinedPoints::Reason).string().not_null())
+                    .col(ColumnDef::new(QuarantinedPoints::ReceivedAt).timestamp_with_time_zone().not_null())
+                    .to_owned(),
+            )
+            .await?;
+        // Review lists newest first
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_quarantined_points_received_at")
+                    .table(QuarantinedPoints::Table)
+                    .col(QuarantinedPoints::ReceivedAt)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(QuarantinedPoints::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum QuarantinedPoints {
+    Table,
+    Id,
+    RandomizedId,
+    Lat,
+    Lng,
+    Alt,
+    Spd,
+    Azm,
+    Timestamp,
+    ClientUuid,
+    Reason,
+    ReceivedAt,
+}
@@ -0,0 +1,67 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Devices::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(Devices::DeviceId).string().not_null().primary_key())
+                    .col(ColumnDef::new(Devices::Name).string().null())
+                    .col(ColumnDef::new(Devices::RegisteredAt).timestamp_with_time_zone().not_null())
+                    .to_owned(),
+            )
+            .await?;
+        // Trips recorded before devices were reported stay without one
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Trips::Table)
+                    .add_column_if_not_exists(ColumnDef::new(Trips::DeviceId).string().null())
+                    .to_owned(),
+            )
+            .await?;
+        // Per-device totals and `GET /api/trips?deviceId=`
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_trips_device_id")
+                    .table(Trips::Table)
+                    .col(Trips::DeviceId)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(Index::drop().name("idx_trips_device_id").table(Trips::Table).to_owned())
+            .await?;
+        manager
+            .alter_table(Table::alter().table(Trips::Table).drop_column(Trips::DeviceId).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(Devices::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Devices {
+    Table,
+    DeviceId,
+    Name,
+    RegisteredAt,
+}
+
+#[derive(DeriveIden)]
+enum Trips {
+    Table,
+    DeviceId,
+}
@@ -0,0 +1,49 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // SQLite takes one column per ALTER
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Points::Table)
+                    .add_column_if_not_exists(ColumnDef::new(Points::AnomalyScore).double().null())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Points::Table)
+                    .add_column_if_not_exists(ColumnDef::new(Points::AnomalyReason).string().null())
+                    .to_owned(),
+            )
+            .await?;
+        // Points classified before scores existed get the extremes of the scale, without a reason
+        manager
+            .get_connection()
+            .execute_unprepared("UPDATE points SET anomaly_score = CASE WHEN anomaly THEN 1.0 ELSE 0.0 END WHERE anomaly IS NOT NULL")
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(Table::alter().table(Points::Table).drop_column(Points::AnomalyReason).to_owned())
+            .await?;
+        manager
+            .alter_table(Table::alter().table(Points::Table).drop_column(Points::AnomalyScore).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Points {
+    Table,
+    AnomalyScore,
+    AnomalyReason,
+}
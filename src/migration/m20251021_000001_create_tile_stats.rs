@@ -0,0 +1,76 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(TileStats::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(TileStats::Resolution).integer().not_null())
+                    .col(ColumnDef::new(TileStats::Hour).timestamp_with_time_zone().not_null())
+                    .col(ColumnDef::new(TileStats::TileRow).integer().not_null())
+                    .col(ColumnDef::new(TileStats::TileCol).integer().not_null())
+                    .col(ColumnDef::new(TileStats::Points).big_integer().not_null())
+                    .col(ColumnDef::new(TileStats::SpeedSum).double().not_null())
+                    // Map reads select a grid and a range of hours, then tiles
+                    .primary_key(
+                        Index::create()
+                            .col(TileStats::Resolution)
+                            .col(TileStats::Hour)
+                            .col(TileStats::TileRow)
+                            .col(TileStats::TileCol),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        // Hours each grid holds; the rollup job extends them on every run
+        manager
+            .create_table(
+                Table::create()
+                    .table(TileRollups::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(TileRollups::Resolution).integer().not_null().primary_key())
+                    .col(ColumnDef::new(TileRollups::CoveredFrom).timestamp_with_time_zone().not_null())
+                    .col(ColumnDef::new(TileRollups::CoveredThrough).timestamp_with_time_zone().not_null())
+                    .col(ColumnDef::new(TileRollups::Backfilled).boolean().not_null())
+                    .col(ColumnDef::new(TileRollups::RefreshedAt).timestamp_with_time_zone().not_null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(TileRollups::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(TileStats::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum TileStats {
+    Table,
+    Resolution,
+    Hour,
+    TileRow,
+    TileCol,
+    Points,
+    SpeedSum,
+}
+
+#[derive(DeriveIden)]
+enum TileRollups {
+    Table,
+    Resolution,
+    CoveredFrom,
+    CoveredThrough,
+    Backfilled,
+    RefreshedAt,
+}
@@ -0,0 +1,96 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Rows stored before tenants existed stay shared (NULL)
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Points::Table)
+                    .add_column_if_not_exists(ColumnDef::new(Points::TenantId).string_len(64).null())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Trips::Table)
+                    .add_column_if_not_exists(ColumnDef::new(Trips::TenantId).string_len(64).null())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Devices::Table)
+                    .add_column_if_not_exists(ColumnDef::new(Devices::TenantId).string_len(64).null())
+                    .to_owned(),
+            )
+            .await?;
+        // Every query of a tenant starts with its id, area scans then go by time
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_points_tenant_timestamp")
+                    .table(Points::Table)
+                    .col(Points::TenantId)
+                    .col(Points::Timestamp)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_trips_tenant_start_ts")
+                    .table(Trips::Table)
+                    .col(Trips::TenantId)
+                    .col(Trips::StartTs)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(Index::drop().name("idx_trips_tenant_start_ts").table(Trips::Table).to_owned())
+            .await?;
+        manager
+            .drop_index(Index::drop().name("idx_points_tenant_timestamp").table(Points::Table).to_owned())
+            .await?;
+        manager
+            .alter_table(Table::alter().table(Devices::Table).drop_column(Devices::TenantId).to_owned())
+            .await?;
+        manager
+            .alter_table(Table::alter().table(Trips::Table).drop_column(Trips::TenantId).to_owned())
+            .await?;
+        manager
+            .alter_table(Table::alter().table(Points::Table).drop_column(Points::TenantId).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Points {
+    Table,
+    TenantId,
+    Timestamp,
+}
+
+#[derive(DeriveIden)]
+enum Trips {
+    Table,
+    TenantId,
+    StartTs,
+}
+
+#[derive(DeriveIden)]
+enum Devices {
+    Table,
+    TenantId,
+}
@@ -0,0 +1,72 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::DatabaseBackend;
+
+/// Distance, time and speed from the previous point of the same trip, kept by ingestion
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // SQLite takes one column per ALTER
+        for column in [Points::PrevDistanceM, Points::PrevIntervalS, Points::DerivedSpeed] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Points::Table)
+                        .add_column_if_not_exists(ColumnDef::new(column).double().null())
+                        .to_owned(),
+                )
+                .await?;
+        }
+        // Points stored before the columns existed; SQLite lacks the trigonometry, and its trips
+        // fill in as they are rebuilt
+        if manager.get_database_backend() == DatabaseBackend::Postgres {
+            manager.get_connection().execute_unprepared(BACKFILL_SQL).await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for column in [Points::DerivedSpeed, Points::PrevIntervalS, Points::PrevDistanceM] {
+            manager
+                .alter_table(Table::alter().table(Points::Table).drop_column(column).to_owned())
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+/// Same haversine and ordering (timestamp, then id) as `trips::rebuild`
+const BACKFILL_SQL: &str = r#"
+UPDATE points SET
+    prev_distance_m = d.distance,
+    prev_interval_s = d.interval,
+    derived_speed = CASE WHEN d.interval > 0 THEN d.distance / d.interval END
+FROM (
+    SELECT id,
+        2 * 6371000 * asin(sqrt(
+            power(sin(radians(lat - prev_lat) / 2), 2)
+            + cos(radians(prev_lat)) * cos(radians(lat)) * power(sin(radians(lng - prev_lng) / 2), 2)
+        )) AS distance,
+        extract(epoch FROM timestamp - prev_ts)::double precision AS interval
+    FROM (
+        SELECT id, lat, lng, timestamp,
+            lag(lat) OVER w AS prev_lat,
+            lag(lng) OVER w AS prev_lng,
+            lag(timestamp) OVER w AS prev_ts
+        FROM points
+        WINDOW w AS (PARTITION BY randomized_id ORDER BY timestamp, id)
+    ) p
+    WHERE prev_lat IS NOT NULL
+) d
+WHERE points.id = d.id
+"#;
+
+#[derive(DeriveIden)]
+enum Points {
+    Table,
+    PrevDistanceM,
+    PrevIntervalS,
+    DerivedSpeed,
+}
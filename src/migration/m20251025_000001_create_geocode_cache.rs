@@ -0,0 +1,44 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(GeocodeCache::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(GeocodeCache::CellLat).integer().not_null())
+                    .col(ColumnDef::new(GeocodeCache::CellLng).integer().not_null())
+                    .col(ColumnDef::new(GeocodeCache::Street).string().null())
+                    .col(ColumnDef::new(GeocodeCache::District).string().null())
+                    .col(ColumnDef::new(GeocodeCache::City).string().null())
+                    .col(ColumnDef::new(GeocodeCache::Label).string().null())
+                    .col(ColumnDef::new(GeocodeCache::CreatedAt).timestamp_with_time_zone().not_null())
+                    .primary_key(Index::create().col(GeocodeCache::CellLat).col(GeocodeCache::CellLng))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(GeocodeCache::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum GeocodeCache {
+    Table,
+    CellLat,
+    CellLng,
+    Street,
+    District,
+    City,
+    Label,
+    CreatedAt,
+}
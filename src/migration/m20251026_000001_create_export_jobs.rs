@@ -0,0 +1,53 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(ExportJobs::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(ExportJobs::Id).uuid().not_null().primary_key())
+                    .col(ColumnDef::new(ExportJobs::TenantId).string().null())
+                    .col(ColumnDef::new(ExportJobs::Format).string().not_null())
+                    .col(ColumnDef::new(ExportJobs::Query).json().not_null())
+                    .col(ColumnDef::new(ExportJobs::Status).string().not_null())
+                    .col(ColumnDef::new(ExportJobs::Rows).big_integer().not_null().default(0))
+                    .col(ColumnDef::new(ExportJobs::Bytes).big_integer().not_null().default(0))
+                    .col(ColumnDef::new(ExportJobs::ObjectKey).string().null())
+                    .col(ColumnDef::new(ExportJobs::Error).string().null())
+                    .col(ColumnDef::new(ExportJobs::CreatedAt).timestamp_with_time_zone().not_null())
+                    .col(ColumnDef::new(ExportJobs::UpdatedAt).timestamp_with_time_zone().not_null())
+                    .col(ColumnDef::new(ExportJobs::FinishedAt).timestamp_with_time_zone().null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ExportJobs::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum ExportJobs {
+    Table,
+    Id,
+    TenantId,
+    Format,
+    Query,
+    Status,
+    Rows,
+    Bytes,
+    ObjectKey,
+    Error,
+    CreatedAt,
+    UpdatedAt,
+    FinishedAt,
+}
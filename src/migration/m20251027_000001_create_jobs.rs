@@ -0,0 +1,78 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::DatabaseBackend;
+
+use super::m20251026_000001_create_export_jobs::Migration as CreateExportJobs;
+
+/// Background jobs of every kind, replacing `export_jobs`
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Jobs::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(Jobs::Id).uuid().not_null().primary_key())
+                    .col(ColumnDef::new(Jobs::Kind).string().not_null())
+                    .col(ColumnDef::new(Jobs::TenantId).string().null())
+                    .col(ColumnDef::new(Jobs::Status).string().not_null())
+                    .col(ColumnDef::new(Jobs::Params).json().not_null())
+                    .col(ColumnDef::new(Jobs::Progress).big_integer().not_null().default(0))
+                    .col(ColumnDef::new(Jobs::Result).json().null())
+                    .col(ColumnDef::new(Jobs::Error).string().null())
+                    .col(ColumnDef::new(Jobs::CreatedAt).timestamp_with_time_zone().not_null())
+                    .col(ColumnDef::new(Jobs::UpdatedAt).timestamp_with_time_zone().not_null())
+                    .col(ColumnDef::new(Jobs::StartedAt).timestamp_with_time_zone().null())
+                    .col(ColumnDef::new(Jobs::FinishedAt).timestamp_with_time_zone().null())
+                    .to_owned(),
+            )
+            .await?;
+        // Exports started before, so that their status and download links keep working; SQLite
+        // databases are only used by tests and start empty
+        if manager.get_database_backend() == DatabaseBackend::Postgres {
+            manager.get_connection().execute_unprepared(COPY_EXPORTS_SQL).await?;
+        }
+        CreateExportJobs.down(manager).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Exports recorded since are not copied back
+        CreateExportJobs.up(manager).await?;
+        manager
+            .drop_table(Table::drop().table(Jobs::Table).to_owned())
+            .await
+    }
+}
+
+/// `params` is the request with its format, `result` the object written, as `exports` keeps them
+const COPY_EXPORTS_SQL: &str = r#"
+INSERT INTO jobs (id, kind, tenant_id, status, params, progress, result, error, created_at, updated_at, started_at, finished_at)
+SELECT id, 'export', tenant_id, status,
+       jsonb_set(query::jsonb, '{format}', to_jsonb(format))::json,
+       "rows",
+       CASE WHEN object_key IS NULL THEN NULL ELSE json_build_object('objectKey', object_key, 'bytes', bytes) END,
+       error, created_at, updated_at,
+       CASE WHEN status = 'queued' THEN NULL ELSE created_at END,
+       finished_at
+FROM export_jobs
+"#;
+
+#[derive(DeriveIden)]
+enum Jobs {
+    Table,
+    Id,
+    Kind,
+    TenantId,
+    Status,
+    Params,
+    Progress,
+    Result,
+    Error,
+    CreatedAt,
+    UpdatedAt,
+    StartedAt,
+    FinishedAt,
+}
@@ -0,0 +1,61 @@
+use sea_orm_migration::prelude::*;
+
+/// Speed limits of the road network, one row per stretch between two consecutive nodes of an
+/// OSM way, loaded by `indrive import-roads`
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(RoadSegments::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(RoadSegments::Id).big_integer().not_null().auto_increment().primary_key())
+                    .col(ColumnDef::new(RoadSegments::WayId).big_integer().not_null())
+                    .col(ColumnDef::new(RoadSegments::Name).string().null())
+                    .col(ColumnDef::new(RoadSegments::Highway).string().not_null())
+                    .col(ColumnDef::new(RoadSegments::SpeedLimit).double().not_null())
+                    .col(ColumnDef::new(RoadSegments::Lat1).double().not_null())
+                    .col(ColumnDef::new(RoadSegments::Lng1).double().not_null())
+                    .col(ColumnDef::new(RoadSegments::Lat2).double().not_null())
+                    .col(ColumnDef::new(RoadSegments::Lng2).double().not_null())
+                    .to_owned(),
+            )
+            .await?;
+        // Segments are looked up by area; either end narrows the scan
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_road_segments_lat1_lng1")
+                    .table(RoadSegments::Table)
+                    .col(RoadSegments::Lat1)
+                    .col(RoadSegments::Lng1)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(RoadSegments::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum RoadSegments {
+    Table,
+    Id,
+    WayId,
+    Name,
+    Highway,
+    SpeedLimit,
+    Lat1,
+    Lng1,
+    Lat2,
+    Lng2,
+}
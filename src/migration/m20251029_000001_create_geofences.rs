@@ -0,0 +1,91 @@
+use sea_orm_migration::prelude::*;
+
+/// Named polygons managed through `/api/geofences`, and the entries into and exits out of them
+/// detected as points arrive
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Geofences::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(Geofences::Id).big_integer().not_null().auto_increment().primary_key())
+                    .col(ColumnDef::new(Geofences::Name).string().not_null())
+                    .col(ColumnDef::new(Geofences::Polygon).json().not_null())
+                    .col(ColumnDef::new(Geofences::Active).boolean().not_null().default(true))
+                    .col(ColumnDef::new(Geofences::TenantId).string().null())
+                    .col(ColumnDef::new(Geofences::CreatedAt).timestamp_with_time_zone().not_null())
+                    .col(ColumnDef::new(Geofences::UpdatedAt).timestamp_with_time_zone().not_null())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_table(
+                Table::create()
+                    .table(GeofenceEvents::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(GeofenceEvents::Id).big_integer().not_null().auto_increment().primary_key())
+                    .col(ColumnDef::new(GeofenceEvents::GeofenceId).big_integer().not_null())
+                    .col(ColumnDef::new(GeofenceEvents::RandomizedId).big_integer().not_null())
+                    .col(ColumnDef::new(GeofenceEvents::PointId).big_integer().not_null())
+                    .col(ColumnDef::new(GeofenceEvents::Kind).string().not_null())
+                    .col(ColumnDef::new(GeofenceEvents::Lat).double().not_null())
+                    .col(ColumnDef::new(GeofenceEvents::Lng).double().not_null())
+                    .col(ColumnDef::new(GeofenceEvents::Timestamp).timestamp_with_time_zone().not_null())
+                    .col(ColumnDef::new(GeofenceEvents::CreatedAt).timestamp_with_time_zone().not_null())
+                    .to_owned(),
+            )
+            .await?;
+        // Events are listed per geofence in the order they were recorded
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_geofence_events_geofence_id_id")
+                    .table(GeofenceEvents::Table)
+                    .col(GeofenceEvents::GeofenceId)
+                    .col(GeofenceEvents::Id)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(GeofenceEvents::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(Geofences::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Geofences {
+    Table,
+    Id,
+    Name,
+    Polygon,
+    Active,
+    TenantId,
+    CreatedAt,
+    UpdatedAt,
+}
+
+#[derive(DeriveIden)]
+enum GeofenceEvents {
+    Table,
+    Id,
+    GeofenceId,
+    RandomizedId,
+    PointId,
+    Kind,
+    Lat,
+    Lng,
+    Timestamp,
+    CreatedAt,
+}
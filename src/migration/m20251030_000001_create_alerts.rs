@@ -0,0 +1,100 @@
+use sea_orm_migration::prelude::*;
+
+/// Alert rules managed through `/api/alerts/rules`, and the alerts they raised
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(AlertRules::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(AlertRules::Id).big_integer().not_null().auto_increment().primary_key())
+                    .col(ColumnDef::new(AlertRules::Name).string().not_null())
+                    .col(ColumnDef::new(AlertRules::Kind).string().not_null())
+                    .col(ColumnDef::new(AlertRules::Params).json().not_null())
+                    .col(ColumnDef::new(AlertRules::Enabled).boolean().not_null().default(true))
+                    .col(ColumnDef::new(AlertRules::TenantId).string().null())
+                    .col(ColumnDef::new(AlertRules::CreatedAt).timestamp_with_time_zone().not_null())
+                    .col(ColumnDef::new(AlertRules::UpdatedAt).timestamp_with_time_zone().not_null())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_table(
+                Table::create()
+                    .table(Alerts::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(Alerts::Id).big_integer().not_null().auto_increment().primary_key())
+                    .col(ColumnDef::new(Alerts::RuleId).big_integer().not_null())
+                    .col(ColumnDef::new(Alerts::Kind).string().not_null())
+                    .col(ColumnDef::new(Alerts::TenantId).string().null())
+                    .col(ColumnDef::new(Alerts::RandomizedId).big_integer().null())
+                    .col(ColumnDef::new(Alerts::DeviceId).string().null())
+                    .col(ColumnDef::new(Alerts::PointId).big_integer().null())
+                    .col(ColumnDef::new(Alerts::Lat).double().null())
+                    .col(ColumnDef::new(Alerts::Lng).double().null())
+                    .col(ColumnDef::new(Alerts::Value).double().null())
+                    .col(ColumnDef::new(Alerts::Message).string().not_null())
+                    .col(ColumnDef::new(Alerts::Timestamp).timestamp_with_time_zone().not_null())
+                    .col(ColumnDef::new(Alerts::CreatedAt).timestamp_with_time_zone().not_null())
+                    .to_owned(),
+            )
+            .await?;
+        // History is read per rule, newest first
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_alerts_rule_id_id")
+                    .table(Alerts::Table)
+                    .col(Alerts::RuleId)
+                    .col(Alerts::Id)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Alerts::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(AlertRules::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum AlertRules {
+    Table,
+    Id,
+    Name,
+    Kind,
+    Params,
+    Enabled,
+    TenantId,
+    CreatedAt,
+    UpdatedAt,
+}
+
+#[derive(DeriveIden)]
+enum Alerts {
+    Table,
+    Id,
+    RuleId,
+    Kind,
+    TenantId,
+    RandomizedId,
+    DeviceId,
+    PointId,
+    Lat,
+    Lng,
+    Value,
+    Message,
+    Timestamp,
+    CreatedAt,
+}
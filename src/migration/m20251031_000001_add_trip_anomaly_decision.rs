@@ -0,0 +1,40 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::DatabaseBackend;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Trips::Table)
+                    .add_column_if_not_exists(ColumnDef::new(Trips::AnomalyReview).boolean().null())
+                    .to_owned(),
+            )
+            .await?;
+        if manager.get_database_backend() == DatabaseBackend::Postgres {
+            manager.get_connection().execute_unprepared(BACKFILL_SQL).await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(Table::alter().table(Trips::Table).drop_column(Trips::AnomalyReview).to_owned())
+            .await
+    }
+}
+
+/// Reviews made so far overwrote the points, so the trip flag they left is the decision
+const BACKFILL_SQL: &str = r#"
+UPDATE trips SET anomaly_review = anomaly WHERE anomaly_reviewed_by IS NOT NULL
+"#;
+
+#[derive(DeriveIden)]
+enum Trips {
+    Table,
+    AnomalyReview,
+}
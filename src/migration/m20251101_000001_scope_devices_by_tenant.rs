@@ -0,0 +1,83 @@
+use sea_orm_migration::prelude::*;
+
+/// Device ids were unique across the instance, so a tenant could not register an id another
+/// one used. The table is rebuilt keyed by tenant and device id; `tenant_key` is the tenant, or
+/// '' for the shared scope, since a primary key cannot hold NULLs.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(DevicesScoped::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(Devices::TenantKey).string_len(64).not_null())
+                    .col(ColumnDef::new(Devices::DeviceId).string().not_null())
+                    .col(ColumnDef::new(Devices::Name).string().null())
+                    .col(ColumnDef::new(Devices::RegisteredAt).timestamp_with_time_zone().not_null())
+                    .col(ColumnDef::new(Devices::TenantId).string_len(64).null())
+                    .primary_key(Index::create().col(Devices::TenantKey).col(Devices::DeviceId))
+                    .to_owned(),
+            )
+            .await?;
+        manager.get_connection().execute_unprepared(COPY_SQL).await?;
+        manager.drop_table(Table::drop().table(Devices::Table).to_owned()).await?;
+        manager
+            .rename_table(Table::rename().table(DevicesScoped::Table, Devices::Table).to_owned())
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(DevicesScoped::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(Devices::DeviceId).string().not_null().primary_key())
+                    .col(ColumnDef::new(Devices::Name).string().null())
+                    .col(ColumnDef::new(Devices::RegisteredAt).timestamp_with_time_zone().not_null())
+                    .col(ColumnDef::new(Devices::TenantId).string_len(64).null())
+                    .to_owned(),
+            )
+            .await?;
+        // Of ids several tenants registered, the earliest registration stays
+        manager.get_connection().execute_unprepared(COPY_BACK_SQL).await?;
+        manager.drop_table(Table::drop().table(Devices::Table).to_owned()).await?;
+        manager
+            .rename_table(Table::rename().table(DevicesScoped::Table, Devices::Table).to_owned())
+            .await
+    }
+}
+
+const COPY_SQL: &str = r#"
+INSERT INTO devices_scoped (tenant_key, device_id, name, registered_at, tenant_id)
+SELECT COALESCE(tenant_id, ''), device_id, name, registered_at, tenant_id FROM devices
+"#;
+
+const COPY_BACK_SQL: &str = r#"
+INSERT INTO devices_scoped (device_id, name, registered_at, tenant_id)
+SELECT d.device_id, d.name, d.registered_at, d.tenant_id FROM devices d
+WHERE NOT EXISTS (
+    SELECT 1 FROM devices e
+    WHERE e.device_id = d.device_id
+      AND (e.registered_at < d.registered_at OR (e.registered_at = d.registered_at AND e.tenant_key < d.tenant_key))
+)
+"#;
+
+#[derive(DeriveIden)]
+enum Devices {
+    Table,
+    TenantKey,
+    DeviceId,
+    Name,
+    RegisteredAt,
+    TenantId,
+}
+
+#[derive(DeriveIden)]
+enum DevicesScoped {
+    Table,
+}
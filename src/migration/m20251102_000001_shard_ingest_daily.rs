@@ -0,0 +1,93 @@
+use sea_orm_migration::prelude::*;
+
+/// Every insert added to the one `ingest_daily` row of its day, so concurrent inserts queued on
+/// its lock. The table is rebuilt keyed by day and shard, like the shards of `dataset_stats`;
+/// existing days go to shard 1.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(IngestDailySharded::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(IngestDaily::Day).date().not_null())
+                    .col(ColumnDef::new(IngestDaily::Shard).small_integer().not_null())
+                    .col(ColumnDef::new(IngestDaily::Points).big_integer().not_null())
+                    .primary_key(Index::create().col(IngestDaily::Day).col(IngestDaily::Shard))
+                    .to_owned(),
+            )
+            .await?;
+        manager.get_connection().execute_unprepared(COPY_SQL).await?;
+        manager.drop_table(Table::drop().table(IngestDaily::Table).to_owned()).await?;
+        manager
+            .rename_table(Table::rename().table(IngestDailySharded::Table, IngestDaily::Table).to_owned())
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(IngestDailySharded::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(IngestDaily::Day).date().not_null().primary_key())
+                    .col(ColumnDef::new(IngestDaily::Points).big_integer().not_null())
+                    .to_owned(),
+            )
+            .await?;
+        manager.get_connection().execute_unprepared(COPY_BACK_SQL).await?;
+        manager.drop_table(Table::drop().table(IngestDaily::Table).to_owned()).await?;
+        manager
+            .rename_table(Table::rename().table(IngestDailySharded::Table, IngestDaily::Table).to_owned())
+            .await?;
+        // The old code only reads the row with id 1
+        manager.get_connection().execute_unprepared(FOLD_STATS_SQL).await?;
+        manager.get_connection().execute_unprepared("DELETE FROM dataset_stats WHERE id <> 1").await?;
+        Ok(())
+    }
+}
+
+const COPY_SQL: &str = r#"
+INSERT INTO ingest_daily_sharded (day, shard, points)
+SELECT day, 1, points FROM ingest_daily
+"#;
+
+const COPY_BACK_SQL: &str = r#"
+INSERT INTO ingest_daily_sharded (day, points)
+SELECT day, SUM(points) FROM ingest_daily GROUP BY day
+"#;
+
+const FOLD_STATS_SQL: &str = r#"
+INSERT INTO dataset_stats (id, total_points, total_trips, lat_min, lat_max, lng_min, lng_max, first_ts, last_ts, updated_at)
+SELECT 1, SUM(total_points), SUM(total_trips), MIN(lat_min), MAX(lat_max), MIN(lng_min), MAX(lng_max),
+       MIN(first_ts), MAX(last_ts), MAX(updated_at)
+FROM dataset_stats
+HAVING COUNT(*) > 0
+ON CONFLICT (id) DO UPDATE SET
+    total_points = EXCLUDED.total_points,
+    total_trips = EXCLUDED.total_trips,
+    lat_min = EXCLUDED.lat_min,
+    lat_max = EXCLUDED.lat_max,
+    lng_min = EXCLUDED.lng_min,
+    lng_max = EXCLUDED.lng_max,
+    first_ts = EXCLUDED.first_ts,
+    last_ts = EXCLUDED.last_ts,
+    updated_at = EXCLUDED.updated_at
+"#;
+
+#[derive(DeriveIden)]
+enum IngestDaily {
+    Table,
+    Day,
+    Shard,
+    Points,
+}
+
+#[derive(DeriveIden)]
+enum IngestDailySharded {
+    Table,
+}
@@ -0,0 +1,52 @@
+use sea_orm_migration::prelude::*;
+
+/// Trips the map-matching worker could not get matched, so they wait before being tried again
+/// instead of heading every batch
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(MatchFailures::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(MatchFailures::RandomizedId).big_integer().not_null().primary_key())
+                    .col(ColumnDef::new(MatchFailures::Attempts).integer().not_null())
+                    .col(ColumnDef::new(MatchFailures::LastError).string().not_null())
+                    .col(ColumnDef::new(MatchFailures::RetryAt).timestamp_with_time_zone().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_match_failures_trip")
+                            .from(MatchFailures::Table, MatchFailures::RandomizedId)
+                            .to(Trips::Table, Trips::RandomizedId)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(MatchFailures::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum MatchFailures {
+    Table,
+    RandomizedId,
+    Attempts,
+    LastError,
+    RetryAt,
+}
+
+#[derive(DeriveIden)]
+enum Trips {
+    Table,
+    RandomizedId,
+}
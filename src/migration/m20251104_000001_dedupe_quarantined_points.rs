@@ -0,0 +1,46 @@
+use sea_orm_migration::prelude::*;
+
+/// Quarantine rows were written before the insert, so a retried upload added its points again.
+/// Duplicates keep their first row, and a unique index on `client_uuid` stops new ones.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.get_connection().execute_unprepared(DEDUPE_SQL).await?;
+        // NULLs never collide, so points sent without a UUID are unaffected
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_quarantined_points_client_uuid")
+                    .table(QuarantinedPoints::Table)
+                    .col(QuarantinedPoints::ClientUuid)
+                    .unique()
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(Index::drop().name("idx_quarantined_points_client_uuid").table(QuarantinedPoints::Table).to_owned())
+            .await
+    }
+}
+
+const DEDUPE_SQL: &str = r#"
+DELETE FROM quarantined_points
+WHERE client_uuid IS NOT NULL
+  AND EXISTS (
+    SELECT 1 FROM quarantined_points q
+    WHERE q.client_uuid = quarantined_points.client_uuid AND q.id < quarantined_points.id
+)
+"#;
+
+#[derive(DeriveIden)]
+enum QuarantinedPoints {
+    Table,
+    ClientUuid,
+}
@@ -1,12 +1,64 @@
 pub use sea_orm_migration::prelude::*;
 
 mod m20250913_000001_create_points;
+mod m20251014_000001_add_points_client_uuid;
+mod m20251014_000002_create_trips;
+mod m20251015_000001_add_trip_anomaly_review;
+mod m20251016_000001_create_matched_trips;
+mod m20251016_000002_create_dataset_stats;
+mod m20251017_000001_add_points_geom;
+mod m20251017_000002_add_trip_anomaly_count;
+mod m20251018_000001_create_quarantined_points;
+mod m20251019_000001_create_devices;
+mod m20251020_000001_add_points_anomaly_score;
+mod m20251021_000001_create_tile_stats;
+mod m20251022_000001_add_tenant_id;
+mod m20251023_000001_create_webhooks;
+mod m20251024_000001_add_points_deltas;
+mod m20251025_000001_create_geocode_cache;
+mod m20251026_000001_create_export_jobs;
+mod m20251027_000001_create_jobs;
+mod m20251028_000001_create_road_segments;
+mod m20251029_000001_create_geofences;
+mod m20251030_000001_create_alerts;
+mod m20251031_000001_add_trip_anomaly_decision;
+mod m20251101_000001_scope_devices_by_tenant;
+mod m20251102_000001_shard_ingest_daily;
+mod m20251103_000001_create_match_failures;
+mod m20251104_000001_dedupe_quarantined_points;
 
 pub struct Migrator;
 
 #[async_trait::async_trait]
 impl MigratorTrait for Migrator {
     fn migrations() -> Vec<Box<dyn MigrationTrait>> {
-        vec![Box::new(m20250913_000001_create_points::Migration)]
+        vec![
+            Box::new(m20250913_000001_create_points::Migration),
+            Box::new(m20251014_000001_add_points_client_uuid::Migration),
+            Box::new(m20251014_000002_create_trips::Migration),
+            Box::new(m20251015_000001_add_trip_anomaly_review::Migration),
+            Box::new(m20251016_000001_create_matched_trips::Migration),
+            Box::new(m20251016_000002_create_dataset_stats::Migration),
+            Box::new(m20251017_000001_add_points_geom::Migration),
+            Box::new(m20251017_000002_add_trip_anomaly_count::Migration),
+            Box::new(m20251018_000001_create_quarantined_points::Migration),
+            Box::new(m20251019_000001_create_devices::Migration),
+            Box::new(m20251020_000001_add_points_anomaly_score::Migration),
+            Box::new(m20251021_000001_create_tile_stats::Migration),
+            Box::new(m20251022_000001_add_tenant_id::Migration),
+            Box::new(m20251023_000001_create_webhooks::Migration),
+            Box::new(m20251024_000001_add_points_deltas::Migration),
+            Box::new(m20251025_000001_create_geocode_cache::Migration),
+            Box::new(m20251026_000001_create_export_jobs::Migration),
+            Box::new(m20251027_000001_create_jobs::Migration),
+            Box::new(m20251028_000001_create_road_segments::Migration),
+            Box::new(m20251029_000001_create_geofences::Migration),
+            Box::new(m20251030_000001_create_alerts::Migration),
+            Box::new(m20251031_000001_add_trip_anomaly_decision::Migration),
+            Box::new(m20251101_000001_scope_devices_by_tenant::Migration),
+            Box::new(m20251102_000001_shard_ingest_daily::Migration),
+            Box::new(m20251103_000001_create_match_failures::Migration),
+            Box::new(m20251104_000001_dedupe_quarantined_points::Migration),
+        ]
     }
 }
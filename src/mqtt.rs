@@ -0,0 +1,172 @@
+use log::{debug, error, info, warn};
+use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Packet, Publish, QoS};
+use std::env;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::api::error::ApiError;
+use crate::api::points::{self, Ack, Ingestion};
+use crate::database::store::{NewPointRecord, TenantScope};
+use crate::request_id;
+use crate::tenant::{self, TenantConfig};
+
+const DEFAULT_TOPIC: &str = "devices/+/telemetry";
+/// Requests to the broker (acks, subscriptions) waiting to be sent
+const CLIENT_CAPACITY: usize = 64;
+/// Messages received and not stored yet; once full, the broker is not read until one is stored
+const MESSAGE_BUFFER: usize = 64;
+/// Wait between reconnects, and between retries of a message the pipeline turned away
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+struct Subscription {
+    topic: String,
+    qos: QoS,
+    /// Level of the topic naming the device, for points without `randomized_id`
+    device_level: Option<usize>,
+}
+
+/// Subscribes to MQTT_TOPIC on the broker at MQTT_URL and stores the device messages through
+/// the same pipeline as `POST /api/points`; returns whether it was started. A message is
+/// acknowledged only once its points are stored (or buffered), so with QoS 1 the broker
+/// redelivers what a crash cut short; as with Kafka, a redelivered point is not stored twice
+/// because its UUID, when it has none, is derived from (randomized_id, timestamp). Points belong
+/// to the tenant MQTT_TENANT names (see `TenantConfig::for_transport`).
+pub fn spawn(ingestion: Ingestion, tenants: &TenantConfig) -> Result<bool, String> {
+    let Some(url) = env::var("MQTT_URL").ok().filter(|v| !v.trim().is_empty()) else {
+        return Ok(false);
+    };
+    let mut url = url.trim().to_string();
+    // Replicas must not share a client id, or the broker disconnects one for the other
+    if !url.contains("client_id=") {
+        let separator = if url.contains('?') { '&' } else { '?' };
+        url = format!("{}{}client_id=indrive-{}", url, separator, &Uuid::new_v4().simple().to_string()[..8]);
+    }
+    let mut options = MqttOptions::parse_url(url).map_err(|e| format!("MQTT_URL: {}", e))?;
+    options.set_manual_acks(true);
+    if let Ok(user) = env::var("MQTT_USERNAME") {
+        options.set_credentials(user, env::var("MQTT_PASSWORD").unwrap_or_default());
+    }
+
+    let topic = env::var("MQTT_TOPIC").ok().filter(|v| !v.trim().is_empty()).unwrap_or_else(|| DEFAULT_TOPIC.to_string());
+    let qos = match env::var("MQTT_QOS").as_deref().map(str::trim) {
+        Err(_) | Ok("1") => QoS::AtLeastOnce,
+        Ok("0") => QoS::AtMostOnce,
+        Ok("2") => QoS::ExactlyOnce,
+        Ok(other) => return Err(format!("MQTT_QOS must be 0, 1 or 2, got {:?}", other)),
+    };
+    let device_level = match env::var("MQTT_DEVICE_ID_LEVEL") {
+        Ok(v) => Some(v.trim().parse::<usize>().map_err(|_| format!("MQTT_DEVICE_ID_LEVEL must be a topic level number, got {:?}", v))?),
+        Err(_) => first_wildcard(&topic),
+    };
+
+    let tenant = tenants.for_transport("MQTT_TENANT")?;
+
+    let (host, port) = options.broker_address();
+    info!("MQTT ingestion from {}:{}, topic {} ({:?})", host, port, topic, qos);
+    let (client, eventloop) = AsyncClient::new(options, CLIENT_CAPACITY);
+    let (tx, rx) = mpsc::channel(MESSAGE_BUFFER);
+    tokio::spawn(store_messages(ingestion, client.clone(), rx, device_level, tenant));
+    tokio::spawn(receive(client, eventloop, Subscription { topic, qos, device_level }, tx));
+    Ok(true)
+}
+
+/// Drives the connection: reconnects with exponential backoff and subscribes again on every
+/// connect, since a clean session forgets the subscription
+async fn receive(client: AsyncClient, mut eventloop: EventLoop, subscription: Subscription, tx: mpsc::Sender<Publish>) {
+    let (host, port) = eventloop.mqtt_options.broker_address();
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        match eventloop.poll().await {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                info!("Connected to MQTT broker {}:{}", host, port);
+                backoff = MIN_BACKOFF;
+                if let Err(e) = client.subscribe(subscription.topic.clone(), subscription.qos).await {
+                    error!("Could not subscribe to {}: {}", subscription.topic, e);
+                }
+            }
+            Ok(Event::Incoming(Packet::SubAck(ack))) => {
+                debug!("Subscribed to {} (device id at level {:?}): {:?}", subscription.topic, subscription.device_level, ack.return_codes);
+            }
+            Ok(Event::Incoming(Packet::Publish(publish))) => {
+                if tx.send(publish).await.is_err() {
+                    break;
+                }
+            }
+            Ok(_) => {}
+            Err(e) => {
+                warn!("MQTT connection to {}:{} failed: {}; reconnecting in {:?}", host, port, e, backoff);
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        }
+    }
+    info!("MQTT ingestion stopped");
+}
+
+/// Stores messages one at a time in the order they arrived and acknowledges each afterwards
+async fn store_messages(ingestion: Ingestion, client: AsyncClient, mut rx: mpsc::Receiver<Publish>, device_level: Option<usize>, tenant: Option<TenantScope>) {
+    while let Some(publish) = rx.recv().await {
+        let stored = tenant::scope(tenant.clone(), store_message(&ingestion, &publish, device_level));
+        request_id::scope(request_id::from_incoming(None), stored).await;
+        if let Err(e) = client.ack(&publish).await {
+            debug!("Could not acknowledge MQTT message on {}: {}", publish.topic, e);
+        }
+    }
+}
+
+/// Malformed messages are logged and dropped; a full batcher or a database outage without a
+/// WAL is waited out, so the message is not acknowledged until its points are kept
+async fn store_message(ingestion: &Ingestion, publish: &Publish, device_level: Option<usize>) {
+    let device = device_level
+        .and_then(|level| publish.topic.split('/').nth(level))
+        .and_then(|id| id.parse::<i64>().ok());
+    let points = match points::decode_message(&publish.payload, device) {
+        Ok(points) => points,
+        Err(e) => {
+            warn!("Dropping MQTT message on {}: {}", publish.topic, e);
+            return;
+        }
+    };
+    // A message redelivered after a crash must not store its points twice
+    let records: Vec<NewPointRecord> = points
+        .into_iter()
+        .map(|p| {
+            let mut record = NewPointRecord::from(p);
+            record.client_uuid = record.client_uuid.or_else(|| points::dedup_uuid(&record));
+            record
+        })
+        .collect();
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        match ingestion.ingest(records.clone(), Ack::Persisted).await {
+            Ok(resp) => {
+                debug!(
+                    "Stored {} points from {} ({} buffered, {} quarantined)",
+                    resp.points.len(), publish.topic, resp.buffered, resp.quarantined.len()
+                );
+                return;
+            }
+            Err(ApiError::ServiceUnavailable(reason)) => {
+                warn!("Could not store {} points from {}: {}; retrying in {:?}", records.len(), publish.topic, reason, backoff);
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+            Err(e) => {
+                error!("Dropping {} points from {}: {}", records.len(), publish.topic, e);
+                return;
+            }
+        }
+    }
+}
+
+/// Level of the first `+` in a topic filter, counted in the topic of the messages: the
+/// `$share/<group>/` prefix of a shared subscription is not part of it
+fn first_wildcard(filter: &str) -> Option<usize> {
+    let filter = match filter.strip_prefix("$share/") {
+        Some(rest) => rest.split_once('/').map(|(_, topic)| topic)?,
+        None => filter,
+    };
+    filter.split('/').position(|level| level == "+")
+}
@@ -0,0 +1,168 @@
+// Minimal Mapbox Vector Tile (spec v2.1) encoder: one or more layers of polygon
+// features with unsigned integer / double properties. Hand-rolled protobuf wire format,
+// covering only what the tile endpoints need.
+
+use std::collections::HashMap;
+
+pub const DEFAULT_EXTENT: u32 = 4096;
+
+/// Property value of a feature
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Uint(u64),
+    Double(f64),
+}
+
+/// Axis-aligned rectangle in tile coordinates (0..extent, y pointing down)
+pub struct RectFeature {
+    pub x0: i32,
+    pub y0: i32,
+    pub x1: i32,
+    pub y1: i32,
+    pub properties: Vec<(&'static str, Value)>,
+}
+
+pub struct Layer {
+    pub name: String,
+    pub extent: u32,
+    pub features: Vec<RectFeature>,
+}
+
+/// Encodes layers into a complete tile message.
+pub fn encode_tile(layers: &[Layer]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for layer in layers {
+        let bytes = encode_layer(layer);
+        write_len_delimited(&mut out, 3, &bytes);
+    }
+    out
+}
+
+fn encode_layer(layer: &Layer) -> Vec<u8> {
+    let mut keys: Vec<&'static str> = Vec::new();
+    let mut key_idx: HashMap<&'static str, u32> = HashMap::new();
+    let mut values: Vec<Value> = Vec::new();
+    let mut value_idx: HashMap<ValueKey, u32> = HashMap::new();
+    let mut features_buf: Vec<Vec<u8>> = Vec::with_capacity(layer.features.len());
+
+    for (id, f) in layer.features.iter().enumerate() {
+        let mut tags = Vec::with_capacity(f.properties.len() * 2);
+        for (k, v) in &f.properties {
+            let ki = *key_idx.entry(k).or_insert_with(|| {
+                keys.push(k);
+                (keys.len() - 1) as u32
+            });
+            let vi = *value_idx.entry(ValueKey::of(v)).or_insert_with(|| {
+                values.push(*v);
+                (values.len() - 1) as u32
+            });
+            tags.push(ki);
+            tags.push(vi);
+        }
+
+        let mut feat = Vec::new();
+        write_varint_field(&mut feat, 1, id as u64 + 1);
+        write_packed(&mut feat, 2, &tags);
+        write_varint_field(&mut feat, 3, 3); // POLYGON
+        write_packed(&mut feat, 4, &rect_geometry(f));
+        features_buf.push(feat);
+    }
+
+    let mut out = Vec::new();
+    write_varint_field(&mut out, 15, 2);
+    write_len_delimited(&mut out, 1, layer.name.as_bytes());
+    for feat in &features_buf {
+        write_len_delimited(&mut out, 2, feat);
+    }
+    for k in &keys {
+        write_len_delimited(&mut out, 3, k.as_bytes());
+    }
+    for v in &values {
+        let mut val = Vec::new();
+        match v {
+            Value::Uint(n) => write_varint_field(&mut val, 5, *n),
+            Value::Double(d) => {
+                write_key(&mut val, 3, 1);
+                val.extend_from_slice(&d.to_le_bytes());
+            }
+        }
+        write_len_delimited(&mut out, 4, &val);
+    }
+    write_varint_field(&mut out, 5, layer.extent as u64);
+    out
+}
+
+/// Values are deduplicated by their exact bits, as doubles are not `Hash`
+#[derive(PartialEq, Eq, Hash)]
+enum ValueKey {
+    Uint(u64),
+    Double(u64),
+}
+
+impl ValueKey {
+    fn of(v: &Value) -> Self {
+        match v {
+            Value::Uint(n) => Self::Uint(*n),
+            Value::Double(d) => Self::Double(d.to_bits()),
+        }
+    }
+}
+
+/// MoveTo + 3 LineTo + ClosePath; clockwise in screen space, i.e. an exterior ring
+fn rect_geometry(f: &RectFeature) -> Vec<u32> {
+    let corners = [(f.x0, f.y0), (f.x1, f.y0), (f.x1, f.y1), (f.x0, f.y1)];
+    let mut geom = Vec::with_capacity(11);
+    let (mut cx, mut cy) = (0i32, 0i32);
+    for (i, (x, y)) in corners.iter().enumerate() {
+        if i == 0 {
+            geom.push(command(1, 1));
+        } else if i == 1 {
+            geom.push(command(2, 3));
+        }
+        geom.push(zigzag(x - cx));
+        geom.push(zigzag(y - cy));
+        cx = *x;
+        cy = *y;
+    }
+    geom.push(command(7, 1));
+    geom
+}
+
+fn command(id: u32, count: u32) -> u32 {
+    (id & 0x7) | (count << 3)
+}
+
+fn zigzag(n: i32) -> u32 {
+    ((n << 1) ^ (n >> 31)) as u32
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        buf.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+fn write_key(buf: &mut Vec<u8>, field: u32, wire_type: u32) {
+    write_varint(buf, ((field << 3) | wire_type) as u64);
+}
+
+fn write_varint_field(buf: &mut Vec<u8>, field: u32, v: u64) {
+    write_key(buf, field, 0);
+    write_varint(buf, v);
+}
+
+fn write_len_delimited(buf: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    write_key(buf, field, 2);
+    write_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+fn write_packed(buf: &mut Vec<u8>, field: u32, values: &[u32]) {
+    let mut packed = Vec::with_capacity(values.len());
+    for v in values {
+        write_varint(&mut packed, *v as u64);
+    }
+    write_len_delimited(buf, field, &packed);
+}
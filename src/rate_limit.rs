@@ -0,0 +1,129 @@
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{http::header, http::Method, Error, HttpResponse};
+use log::warn;
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::tenant::TenantConfig;
+
+/// Most clients tracked at once. Past it, buckets that refilled completely are forgotten, and
+/// while none has, new clients share one bucket.
+pub const MAX_CLIENTS: usize = 10_000;
+
+/// Bucket of the clients that found no room
+const OVERFLOW: &str = "overflow";
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+struct Buckets {
+    clients: HashMap<String, Bucket>,
+    pruned: Instant,
+}
+
+/// Token bucket per client for the ingestion endpoints. A client is its API key (`X-API-Key`
+/// or a Bearer token) when TENANT_API_KEYS knows it, or else its address behind trusted
+/// proxies, so made-up keys do not get buckets of their own. INGEST_RATE_LIMIT requests per
+/// second refill the bucket (default 10, 0 disables), INGEST_RATE_BURST is its size (default
+/// twice the rate).
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    tenants: Arc<TenantConfig>,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: f64, burst: f64, tenants: Arc<TenantConfig>) -> Self {
+        let buckets = Buckets { clients: HashMap::new(), pruned: Instant::now() };
+        Self { rate, burst, tenants, buckets: Mutex::new(buckets) }
+    }
+
+    pub fn from_env(tenants: Arc<TenantConfig>) -> Self {
+        let rate = env::var("INGEST_RATE_LIMIT").ok().and_then(|v| v.parse::<f64>().ok()).filter(|v| *v >= 0.0).unwrap_or(10.0);
+        let burst = env::var("INGEST_RATE_BURST").ok().and_then(|v| v.parse::<f64>().ok()).filter(|v| *v >= 1.0).unwrap_or((rate * 2.0).max(1.0));
+        Self::new(rate, burst, tenants)
+    }
+
+    /// Bucket name of a client: its API key when it is a known one, else its address
+    pub fn client_for(&self, api_key: Option<&str>, address: &str) -> String {
+        match api_key.filter(|k| self.tenants.knows_key(k)) {
+            Some(key) => format!("key:{}", key),
+            None => format!("ip:{}", address),
+        }
+    }
+
+    /// Takes one token unless limiting is off (INGEST_RATE_LIMIT=0), or returns how many
+    /// seconds until one is available
+    pub fn check(&self, client: &str) -> Result<(), u64> {
+        if self.rate > 0.0 { self.acquire(client) } else { Ok(()) }
+    }
+
+    /// Takes one token, or returns how many seconds until one is available
+    fn acquire(&self, client: &str) -> Result<(), u64> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let mut client = client;
+        if buckets.clients.len() >= MAX_CLIENTS && !buckets.clients.contains_key(client) {
+            // At most once per refill time, after which any bucket left alone since is full
+            let (rate, burst) = (self.rate, self.burst);
+            if now.duration_since(buckets.pruned).as_secs_f64() * rate >= burst {
+                buckets.clients.retain(|_, b| b.tokens + now.duration_since(b.updated).as_secs_f64() * rate < burst);
+                buckets.pruned = now;
+            }
+            if buckets.clients.len() >= MAX_CLIENTS {
+                client = OVERFLOW;
+            }
+        }
+        let bucket = buckets.clients.entry(client.to_string()).or_insert(Bucket { tokens: self.burst, updated: now });
+        bucket.tokens = (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * self.rate).min(self.burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - bucket.tokens) / self.rate).ceil().max(1.0) as u64)
+        }
+    }
+}
+
+/// Ingestion routes guarded by the limiter; paths are already normalized by this point
+fn is_ingestion(req: &ServiceRequest) -> bool {
+    req.method() == Method::POST && (req.path() == "/api/points" || req.path().starts_with("/api/points/import/"))
+}
+
+fn client_key(limiter: &RateLimiter, req: &ServiceRequest) -> String {
+    let headers = req.headers();
+    let api_key = headers
+        .get("x-api-key")
+        .and_then(|v| v.to_str().ok())
+        .or_else(|| headers.get(header::AUTHORIZATION).and_then(|v| v.to_str().ok()).and_then(|v| v.strip_prefix("Bearer ")))
+        .map(str::trim)
+        .filter(|k| !k.is_empty());
+    limiter.client_for(api_key, &crate::client_ip::of_request(req))
+}
+
+/// Answers 429 with Retry-After once a client has used up its bucket
+pub async fn limit_ingestion(
+    limiter: Arc<RateLimiter>,
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    if limiter.rate > 0.0 && is_ingestion(&req) {
+        let client = client_key(&limiter, &req);
+        if let Err(retry_after) = limiter.acquire(&client) {
+            warn!("Rate limit hit on {} by {}", req.path(), crate::client_ip::of_request(&req));
+            let resp = HttpResponse::TooManyRequests()
+                .insert_header((header::RETRY_AFTER, retry_after.to_string()))
+                .body("Rate limit exceeded");
+            return Ok(req.into_response(resp));
+        }
+    }
+    Ok(next.call(req).await?.map_into_boxed_body())
+}
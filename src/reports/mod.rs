@@ -0,0 +1,430 @@
+//! Weekly traffic reports: the most congested tiles, anomaly counts and the day-by-day volume of
+//! each finished week, rendered to a standalone HTML file (and optionally PDF) in REPORTS_DIR and
+//! listed by `/api/reports`. With tenants each one gets reports of its own rows, and the rows
+//! without a tenant get the reports at the top of REPORTS_DIR.
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Utc};
+use log::{error, info, warn};
+use minijinja::{context, Environment};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::sync::Mutex;
+
+use crate::database::store::{PointFilter, PointOrder, PointStore, StoreResult, TenantScope, TimeBucket, TripStore};
+use crate::metrics::metrics;
+
+/// Name of the worker on the status page
+const JOB_NAME: &str = "weekly report";
+
+/// Embedded rather than read from the frontend build, so a report renders the same wherever it
+/// is generated and opens without the server's stylesheets
+const WEEKLY_TEMPLATE: &str = include_str!("weekly.html");
+
+/// Held by the report being generated
+static GENERATING: Mutex<()> = Mutex::const_new(());
+
+/// Report files are named `weekly-<monday>.<format>`
+const PREFIX: &str = "weekly-";
+
+/// REPORTS_DIR enables the subsystem. REPORTS_TILE_DEGREES (default 0.01) is the side of the
+/// tiles ranked by congestion, REPORTS_TOP_TILES (default 10) how many make the report, and
+/// REPORTS_SLOW_SPEED (m/s, default 3) the speed below which a point counts as stuck in traffic.
+/// REPORTS_PDF_COMMAND, e.g. `chromium --headless --no-sandbox --print-to-pdf={pdf} {html}`, also
+/// prints each report to PDF. REPORTS_CHECK_SECS (default 3600) is how often the job looks for a
+/// finished week without a report.
+#[derive(Debug, Clone)]
+pub struct ReportConfig {
+    pub dir: PathBuf,
+    pub tile_degrees: f64,
+    pub top_tiles: usize,
+    pub slow_speed: f64,
+    /// Program and arguments; `{html}` and `{pdf}` stand for the file paths
+    pub pdf_command: Option<Vec<String>>,
+    pub check_secs: u64,
+    /// Reports per tenant, set when tenancy is on; the job then never reports on every tenant
+    /// at once
+    pub tenants: bool,
+}
+
+impl ReportConfig {
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let Some(dir) = env::var("REPORTS_DIR").ok().map(|d| d.trim().to_string()).filter(|d| !d.is_empty()) else {
+            return Ok(None);
+        };
+        let number = |name: &str, default: f64| -> Result<f64, String> {
+            match env::var(name) {
+                Err(_) => Ok(default),
+                Ok(v) => v.trim().parse::<f64>().ok().filter(|n| n.is_finite() && *n > 0.0).ok_or_else(|| format!("{} '{}': expected a positive number", name, v)),
+            }
+        };
+        let tile_degrees = number("REPORTS_TILE_DEGREES", 0.01)?;
+        if tile_degrees > 90.0 {
+            return Err(format!("REPORTS_TILE_DEGREES {}: expected at most 90", tile_degrees));
+        }
+        let pdf_command = env::var("REPORTS_PDF_COMMAND")
+            .ok()
+            .map(|c| c.split_whitespace().map(str::to_string).collect::<Vec<_>>())
+            .filter(|c| !c.is_empty());
+        if let Some(command) = &pdf_command
+            && !command.iter().any(|a| a.contains("{pdf}"))
+        {
+            return Err("REPORTS_PDF_COMMAND must name the output file as {pdf}".to_string());
+        }
+        let dir = PathBuf::from(dir);
+        std::fs::create_dir_all(&dir).map_err(|e| format!("REPORTS_DIR {}: {}", dir.display(), e))?;
+        Ok(Some(Self {
+            dir,
+            tile_degrees,
+            top_tiles: number("REPORTS_TOP_TILES", 10.0)? as usize,
+            slow_speed: number("REPORTS_SLOW_SPEED", 3.0)?,
+            pdf_command,
+            check_secs: (number("REPORTS_CHECK_SECS", 3600.0)? as u64).max(1),
+            tenants: false,
+        }))
+    }
+
+    /// Reports each tenant on its own, for when tenancy is on
+    pub fn with_tenants(mut self, tenants: bool) -> Self {
+        self.tenants = tenants;
+        self
+    }
+
+    /// Scope of the reports of `tenant`, or of the rows without one; None, every row, only
+    /// while tenancy is off
+    pub fn scope(&self, tenant: Option<&str>) -> Option<TenantScope> {
+        match tenant {
+            Some(t) => Some(TenantScope::Tenant(t.to_string())),
+            None => self.tenants.then_some(TenantScope::Shared),
+        }
+    }
+
+    /// Directory of the reports of `scope`: `tenants/<name>` under REPORTS_DIR for a tenant,
+    /// REPORTS_DIR itself otherwise. Tenant names are plain tokens (see
+    /// `tenant::is_valid_name`), so they stay inside it.
+    pub fn dir_of(&self, scope: Option<&TenantScope>) -> PathBuf {
+        match scope.and_then(TenantScope::tenant_id) {
+            Some(t) => self.dir.join("tenants").join(t),
+            None => self.dir.clone(),
+        }
+    }
+}
+
+/// A report on disk
+#[derive(Debug, Clone, Serialize)]
+pub struct StoredReport {
+    /// Monday the week starts on, in UTC
+    pub week_start: NaiveDate,
+    /// Files of the report, HTML first
+    pub files: Vec<ReportFile>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReportFile {
+    pub name: String,
+    /// `html` or `pdf`
+    pub format: &'static str,
+    pub bytes: u64,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+/// Reports in `dir`, newest week first. Files not named like a report are left out.
+pub fn list(dir: &Path) -> std::io::Result<Vec<StoredReport>> {
+    let mut weeks: HashMap<NaiveDate, Vec<ReportFile>> = HashMap::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let Some((week_start, format)) = parse_name(&name) else {
+            continue;
+        };
+        let meta = entry.metadata()?;
+        if !meta.is_file() {
+            continue;
+        }
+        let created_at = meta.modified().ok().map(DateTime::<Utc>::from);
+        weeks.entry(week_start).or_default().push(ReportFile { name, format, bytes: meta.len(), created_at });
+    }
+    let mut reports: Vec<StoredReport> = weeks
+        .into_iter()
+        .map(|(week_start, mut files)| {
+            files.sort_by_key(|f| f.format != "html");
+            StoredReport { week_start, files }
+        })
+        .collect();
+    reports.sort_by_key(|r| std::cmp::Reverse(r.week_start));
+    Ok(reports)
+}
+
+/// Week and format of a report file name; None for anything else, so that a name taken from a
+/// request can only point at a report
+pub fn parse_name(name: &str) -> Option<(NaiveDate, &'static str)> {
+    let (stem, ext) = name.strip_prefix(PREFIX)?.rsplit_once('.')?;
+    let format = match ext {
+        "html" => "html",
+        "pdf" => "pdf",
+        _ => return None,
+    };
+    let week_start = NaiveDate::parse_from_str(stem, "%Y-%m-%d").ok().filter(|d| d.weekday().num_days_from_monday() == 0)?;
+    (stem == week_start.format("%Y-%m-%d").to_string()).then_some((week_start, format))
+}
+
+/// Monday of the last week that has ended by `now`
+pub fn last_finished_week(now: DateTime<Utc>) -> NaiveDate {
+    let today = now.date_naive();
+    today - Duration::days(today.weekday().num_days_from_monday() as i64 + 7)
+}
+
+/// Starts the report job when REPORTS_DIR is set; returns whether it runs. `store` is the one the
+/// map endpoints read, so a report never shows points the API would still hide; `trips` names
+/// the tenants to report on.
+pub fn spawn(store: Arc<dyn PointStore>, trips: Arc<dyn TripStore>, config: Option<ReportConfig>) -> bool {
+    let Some(config) = config else {
+        info!("Weekly reports disabled (REPORTS_DIR not set)");
+        return false;
+    };
+    info!("Weekly reports in {} (PDF: {})", config.dir.display(), if config.pdf_command.is_some() { "yes" } else { "no" });
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(std::time::Duration::from_secs(config.check_secs));
+        loop {
+            ticker.tick().await;
+            let week = last_finished_week(Utc::now());
+            let mut scopes = vec![config.scope(None)];
+            if config.tenants {
+                match trips.tenants().await {
+                    Ok(names) => scopes.extend(names.iter().map(|t| config.scope(Some(t)))),
+                    Err(e) => {
+                        error!("Could not list tenants for the weekly reports: {}", e);
+                        metrics().record_job(JOB_NAME, false, e.to_string());
+                        continue;
+                    }
+                }
+            }
+            for scope in scopes {
+                let html = config.dir_of(scope.as_ref()).join(file_name(week, "html"));
+                if html.exists() {
+                    continue;
+                }
+                match generate(store.as_ref(), &config, week, scope.as_ref()).await {
+                    Ok(report) => metrics().record_job(JOB_NAME, true, format!("week of {}{}: {} file(s)", week, label(scope.as_ref()), report.files.len())),
+                    Err(e) => {
+                        error!("Weekly report of {}{} failed: {}", week, label(scope.as_ref()), e);
+                        metrics().record_job(JOB_NAME, false, e);
+                    }
+                }
+            }
+        }
+    });
+    true
+}
+
+#[derive(Debug, Default, Serialize)]
+struct DayVolume {
+    day: NaiveDate,
+    points: u64,
+    trips: u64,
+    /// Share of the busiest day, for the bars
+    percent: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct CongestedTile {
+    lat_min: f64,
+    lng_min: f64,
+    lat_max: f64,
+    lng_max: f64,
+    slow_points: u64,
+    points: u64,
+    trips: usize,
+    avg_speed_kmh: f64,
+}
+
+#[derive(Debug, Default)]
+struct TileTotals {
+    slow_points: u64,
+    points: u64,
+    speed_sum: f64,
+    trips: HashSet<i64>,
+}
+
+/// Renders the report of the week starting on Monday `week_start` (UTC) on the rows of `scope`
+/// (every row for None) into its directory (see `ReportConfig::dir_of`), replacing an earlier
+/// one. The PDF is best effort: when the renderer fails the
+/// report stays HTML only. One report is generated at a time, whether by the timer or by
+/// `POST /api/admin/reports`, so two never write the same files.
+pub async fn generate(store: &dyn PointStore, config: &ReportConfig, week_start: NaiveDate, scope: Option<&TenantScope>) -> Result<StoredReport, String> {
+    let _generating = GENERATING.lock().await;
+    let started = std::time::Instant::now();
+    let dir = config.dir_of(scope);
+    tokio::fs::create_dir_all(&dir).await.map_err(|e| format!("{}: {}", dir.display(), e))?;
+    let since = Utc.from_utc_datetime(&week_start.and_hms_opt(0, 0, 0).unwrap());
+
+    // A day at a time, so a busy week is never held in memory at once
+    let mut days = Vec::with_capacity(7);
+    let mut trips = HashSet::new();
+    let (mut anomalous_points, mut anomalous_trips) = (0u64, HashSet::new());
+    let mut reasons: HashMap<String, u64> = HashMap::new();
+    let mut tiles: HashMap<(i64, i64), TileTotals> = HashMap::new();
+    let side = config.tile_degrees;
+    for d in 0..7 {
+        let from = since + Duration::days(d);
+        let filter = PointFilter {
+            since: Some(from),
+            until: Some(from + Duration::days(1) - Duration::microseconds(1)),
+            tenant: scope.cloned(),
+            ..Default::default()
+        };
+        let points = store.find(&filter, PointOrder::TimestampAsc, None).await.map_err(|e| e.to_string())?;
+        let mut day_trips = HashSet::new();
+        for p in &points {
+            day_trips.insert(p.randomized_id);
+            if p.anomaly == Some(true) {
+                anomalous_points += 1;
+                anomalous_trips.insert(p.randomized_id);
+                *reasons.entry(p.anomaly_reason.clone().unwrap_or_default()).or_default() += 1;
+            }
+            let tile = tiles.entry(((p.lat / side).floor() as i64, (p.lng / side).floor() as i64)).or_default();
+            tile.points += 1;
+            tile.speed_sum += p.spd;
+            tile.trips.insert(p.randomized_id);
+            if p.spd < config.slow_speed {
+                tile.slow_points += 1;
+            }
+        }
+        days.push(DayVolume { day: from.date_naive(), points: points.len() as u64, trips: day_trips.len() as u64, percent: 0.0 });
+        trips.extend(day_trips);
+    }
+    let busiest = days.iter().map(|d| d.points).max().unwrap_or(0);
+    for day in &mut days {
+        day.percent = if busiest > 0 { day.points as f64 * 100.0 / busiest as f64 } else { 0.0 };
+    }
+    let points: u64 = days.iter().map(|d| d.points).sum();
+
+    let mut ranked: Vec<((i64, i64), TileTotals)> = tiles.into_iter().filter(|(_, t)| t.slow_points > 0).collect();
+    ranked.sort_by(|a, b| b.1.slow_points.cmp(&a.1.slow_points).then(b.1.points.cmp(&a.1.points)).then(a.0.cmp(&b.0)));
+    let top_tiles: Vec<CongestedTile> = ranked
+        .into_iter()
+        .take(config.top_tiles)
+        .map(|((row, col), t)| CongestedTile {
+            lat_min: row as f64 * side,
+            lng_min: col as f64 * side,
+            lat_max: (row + 1) as f64 * side,
+            lng_max: (col + 1) as f64 * side,
+            slow_points: t.slow_points,
+            points: t.points,
+            trips: t.trips.len(),
+            avg_speed_kmh: t.speed_sum / t.points as f64 * 3.6,
+        })
+        .collect();
+    let mut reasons: Vec<(String, u64)> = reasons.into_iter().collect();
+    reasons.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+    let previous = previous_week(store, since, scope).await.map_err(|e| e.to_string())?;
+    let change = |now: u64, before: u64| (before > 0).then(|| (now as f64 - before as f64) * 100.0 / before as f64);
+
+    let mut env = Environment::new();
+    env.add_template("weekly.html", WEEKLY_TEMPLATE).map_err(|e| e.to_string())?;
+    let html = env
+        .get_template("weekly.html")
+        .and_then(|t| {
+            t.render(context! {
+                week_start => week_start,
+                week_end => week_start + Duration::days(6),
+                generated_at => Utc::now().format("%Y-%m-%d %H:%M UTC").to_string(),
+                points => points,
+                trips => trips.len(),
+                previous => previous,
+                points_change => change(points, previous.points),
+                trips_change => change(trips.len() as u64, previous.trips),
+                days => days,
+                anomalous_points => anomalous_points,
+                anomalous_trips => anomalous_trips.len(),
+                anomalous_points_change => change(anomalous_points, previous.anomalous_points),
+                reasons => reasons,
+                top_tiles => top_tiles,
+                tile_degrees => side,
+                slow_kmh => config.slow_speed * 3.6,
+            })
+        })
+        .map_err(|e| format!("template: {}", e))?;
+
+    let html_path = dir.join(file_name(week_start, "html"));
+    tokio::fs::write(&html_path, html).await.map_err(|e| format!("{}: {}", html_path.display(), e))?;
+    if let Some(command) = &config.pdf_command {
+        let pdf_path = dir.join(file_name(week_start, "pdf"));
+        if let Err(e) = print_pdf(command, &html_path, &pdf_path).await {
+            warn!("PDF of the weekly report {} failed: {}", week_start, e);
+        }
+    }
+    info!(
+        "Weekly report of {}{}: {} points, {} trips, {} anomalous points, took {:?}",
+        week_start,
+        label(scope),
+        points,
+        trips.len(),
+        anomalous_points,
+        started.elapsed()
+    );
+    let report = list(&dir).map_err(|e| e.to_string())?.into_iter().find(|r| r.week_start == week_start);
+    report.ok_or_else(|| format!("{} vanished after writing", html_path.display()))
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+struct WeekTotals {
+    points: u64,
+    trips: u64,
+    anomalous_points: u64,
+}
+
+/// Totals of the week before the one starting at `since`, to compare against
+async fn previous_week(store: &dyn PointStore, since: DateTime<Utc>, scope: Option<&TenantScope>) -> StoreResult<WeekTotals> {
+    let filter = PointFilter {
+        since: Some(since - Duration::weeks(1)),
+        until: Some(since - Duration::microseconds(1)),
+        tenant: scope.cloned(),
+        ..Default::default()
+    };
+    let week = store.timeline(&filter, TimeBucket::Week).await?;
+    let anomalous_points = store.count(&PointFilter { anomaly: Some(true), ..filter }).await?;
+    Ok(WeekTotals {
+        points: week.iter().map(|w| w.points).sum(),
+        trips: week.iter().map(|w| w.trips).sum(),
+        anomalous_points,
+    })
+}
+
+/// Runs REPORTS_PDF_COMMAND, waiting at most a minute
+async fn print_pdf(command: &[String], html: &Path, pdf: &Path) -> Result<(), String> {
+    let html = std::path::absolute(html).map_err(|e| e.to_string())?;
+    let pdf = std::path::absolute(pdf).map_err(|e| e.to_string())?;
+    let args: Vec<String> = command
+        .iter()
+        .map(|a| a.replace("{html}", &html.to_string_lossy()).replace("{pdf}", &pdf.to_string_lossy()))
+        .collect();
+    let started = SystemTime::now();
+    let run = tokio::process::Command::new(&args[0]).args(&args[1..]).kill_on_drop(true).output();
+    let output = tokio::time::timeout(std::time::Duration::from_secs(60), run)
+        .await
+        .map_err(|_| "timed out after 60s".to_string())?
+        .map_err(|e| format!("{}: {}", args[0], e))?;
+    if !output.status.success() {
+        return Err(format!("{} exited with {}: {}", args[0], output.status, String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    // Some renderers exit 0 without writing anything
+    let fresh = std::fs::metadata(&pdf).and_then(|m| m.modified()).is_ok_and(|m| m >= started);
+    if !fresh {
+        return Err(format!("{} did not write {}", args[0], pdf.display()));
+    }
+    Ok(())
+}
+
+/// ` for <tenant>` in log lines, nothing for the other scopes
+fn label(scope: Option<&TenantScope>) -> String {
+    scope.and_then(TenantScope::tenant_id).map(|t| format!(" for {}", t)).unwrap_or_default()
+}
+
+fn file_name(week_start: NaiveDate, format: &str) -> String {
+    format!("{}{}.{}", PREFIX, week_start.format("%Y-%m-%d"), format)
+}
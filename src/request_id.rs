@@ -0,0 +1,64 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::Error;
+use std::future::Future;
+use uuid::Uuid;
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest incoming id that is propagated instead of replaced
+const MAX_INCOMING_LEN: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// Id of the request whose future is running, for log lines
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok().filter(|id| !id.is_empty())
+}
+
+/// Carries the current request id into a spawned task, so its log lines stay correlated
+pub fn inherit<F: Future>(fut: F) -> impl Future<Output = F::Output> {
+    let id = current().unwrap_or_default();
+    REQUEST_ID.scope(id, fut)
+}
+
+/// Runs `fut` as the request `id`, for transports other than the HTTP server
+pub fn scope<F: Future>(id: String, fut: F) -> impl Future<Output = F::Output> {
+    REQUEST_ID.scope(id, fut)
+}
+
+/// The id sent by the client when it is usable, or a fresh UUID
+pub fn from_incoming(incoming: Option<&str>) -> String {
+    incoming
+        .map(str::trim)
+        .filter(|v| is_valid(v))
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string())
+}
+
+/// Middleware assigning every request an id: the one sent by an upstream proxy in
+/// `X-Request-Id`, or a fresh UUID. Log lines written while the request is handled carry it
+/// (see the log format in `main.rs`), and the response echoes it back in `X-Request-Id`.
+pub async fn assign(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let id = from_incoming(req.headers().get(&REQUEST_ID_HEADER).and_then(|h| h.to_str().ok()));
+
+    let mut res = REQUEST_ID.scope(id.clone(), next.call(req)).await?;
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        res.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    Ok(res)
+}
+
+/// Ids end up in log lines, so only short printable tokens are taken over
+fn is_valid(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_INCOMING_LEN
+        && id.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b':' | b'/' | b'+' | b'='))
+}
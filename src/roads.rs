@@ -0,0 +1,262 @@
+//! Speed limits of the road network. `indrive import-roads` loads them from an OSM extract into
+//! `road_segments`, and `/api/speedmap/violations` checks points against the segment they lie
+//! on.
+
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use crate::database::model::road_segments::Model as RoadSegmentModel;
+use crate::database::store::BBox;
+use crate::geo::METERS_PER_DEGREE;
+
+const KMH_PER_MPH: f64 = 1.609_344;
+
+/// km/h of the implicit limits of the countries around the service area, by the zone of their
+/// `maxspeed` codes (`RU:urban`, `KZ:rural`)
+const ZONE_LIMITS: &[(&str, &[(&str, f64)])] = &[
+    ("RU", &[("living_street", 20.0), ("urban", 60.0), ("rural", 90.0), ("motorway", 110.0)]),
+    ("KZ", &[("living_street", 20.0), ("urban", 60.0), ("rural", 90.0), ("motorway", 140.0)]),
+    ("BY", &[("living_street", 20.0), ("urban", 60.0), ("rural", 90.0), ("motorway", 110.0)]),
+    ("UA", &[("living_street", 20.0), ("urban", 50.0), ("rural", 90.0), ("motorway", 130.0)]),
+    ("KG", &[("living_street", 20.0), ("urban", 60.0), ("rural", 90.0)]),
+    ("UZ", &[("living_street", 20.0), ("urban", 70.0), ("rural", 100.0)]),
+];
+
+/// What an OSM extract held
+#[derive(Debug, Default)]
+pub struct OsmRoads {
+    pub segments: Vec<RoadSegmentModel>,
+    /// Ways with a `highway` tag and a speed limit
+    pub ways: usize,
+    /// Of `ways`, those whose limit is implicit: a zone code or their highway class
+    pub implicit: usize,
+    /// Ways with a `highway` tag but no limit to be read: no `maxspeed` and a class that does
+    /// not tell, or a value such as `walk`, `none` or the zone of another country
+    pub skipped: usize,
+}
+
+/// A way kept from the first pass, waiting for the coordinates of its nodes
+struct Way {
+    id: i64,
+    name: Option<String>,
+    highway: String,
+    speed_limit: f64,
+    nodes: Vec<i64>,
+}
+
+/// The roads of an OSM XML file (`.osm`) with a speed limit, a segment per pair of consecutive
+/// nodes. Besides numeric values, `maxspeed` (or `maxspeed:type`, `source:maxspeed`) may be a
+/// zone code of ZONE_LIMITS; given a `country` of those, roads without one get the limit of
+/// their class (see `class_zone`). The file is read twice, ways first, so that only the
+/// coordinates of road nodes are kept however large the extract.
+pub fn read_osm(path: &Path, country: Option<&str>) -> Result<OsmRoads, String> {
+    let mut roads = OsmRoads::default();
+    let mut ways = Vec::new();
+    let mut needed = HashSet::new();
+    let mut current: Option<(i64, Vec<i64>, HashMap<String, String>)> = None;
+    scan(path, |event| {
+        match event {
+            Event::Start(e) if e.local_name().as_ref() == b"way" => {
+                current = attr(e, "id").and_then(|id| id.parse().ok()).map(|id| (id, Vec::new(), HashMap::new()));
+            }
+            Event::Empty(e) => match (e.local_name().as_ref(), current.as_mut()) {
+                (b"nd", Some((_, nodes, _))) => nodes.extend(attr(e, "ref").and_then(|r| r.parse::<i64>().ok())),
+                (b"tag", Some((_, _, tags))) => {
+                    if let (Some(k), Some(v)) = (attr(e, "k"), attr(e, "v")) {
+                        tags.insert(k, v);
+                    }
+                }
+                _ => {}
+            },
+            Event::End(e) if e.local_name().as_ref() == b"way" => {
+                let Some((id, nodes, mut tags)) = current.take() else { return };
+                let Some(highway) = tags.remove("highway") else { return };
+                match speed_limit(&tags, &highway, country) {
+                    Some((speed_limit, implicit)) if nodes.len() >= 2 => {
+                        needed.extend(nodes.iter().copied());
+                        roads.implicit += implicit as usize;
+                        ways.push(Way { id, name: tags.remove("name"), highway, speed_limit, nodes });
+                    }
+                    _ => roads.skipped += 1,
+                }
+            }
+            _ => {}
+        }
+    })?;
+
+    let mut coords: HashMap<i64, (f64, f64)> = HashMap::with_capacity(needed.len());
+    scan(path, |event| {
+        if let Event::Start(e) | Event::Empty(e) = event
+            && e.local_name().as_ref() == b"node"
+            && let Some(id) = attr(e, "id").and_then(|id| id.parse::<i64>().ok())
+            && needed.contains(&id)
+            && let (Some(lat), Some(lng)) = (attr(e, "lat").and_then(|v| v.parse().ok()), attr(e, "lon").and_then(|v| v.parse().ok()))
+        {
+            coords.insert(id, (lat, lng));
+        }
+    })?;
+
+    roads.ways = ways.len();
+    for way in ways {
+        // Nodes cut off by the extract boundary break the way into the stretches that are left
+        for pair in way.nodes.windows(2) {
+            let (Some(&(lat1, lng1)), Some(&(lat2, lng2))) = (coords.get(&pair[0]), coords.get(&pair[1])) else { continue };
+            roads.segments.push(RoadSegmentModel {
+                id: 0,
+                way_id: way.id,
+                name: way.name.clone(),
+                highway: way.highway.clone(),
+                speed_limit: way.speed_limit,
+                lat1,
+                lng1,
+                lat2,
+                lng2,
+            });
+        }
+    }
+    Ok(roads)
+}
+
+/// Feeds every XML event of the file at `path` to `on_event`
+fn scan(path: &Path, mut on_event: impl FnMut(&Event)) -> Result<(), String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut reader = Reader::from_reader(BufReader::new(file));
+    reader.config_mut().trim_text(true);
+    let mut buf = Vec::new();
+    loop {
+        let event = reader.read_event_into(&mut buf).map_err(|e| format!("at byte {}: {}", reader.buffer_position(), e))?;
+        if let Event::Eof = event {
+            return Ok(());
+        }
+        on_event(&event);
+        buf.clear();
+    }
+}
+
+fn attr(e: &BytesStart, name: &str) -> Option<String> {
+    e.try_get_attribute(name).ok().flatten().and_then(|a| a.unescape_value().ok()).map(|v| v.into_owned())
+}
+
+/// km/h of a way and whether it is implicit. A `maxspeed` that is neither a number nor a known
+/// zone leaves the way out rather than falling back to its class.
+fn speed_limit(tags: &HashMap<String, String>, highway: &str, country: Option<&str>) -> Option<(f64, bool)> {
+    if let Some(value) = tags.get("maxspeed") {
+        return parse_maxspeed(value).map(|s| (s, false)).or_else(|| zone_limit(value).map(|s| (s, true)));
+    }
+    if let Some(limit) = ["maxspeed:type", "source:maxspeed"].iter().filter_map(|k| tags.get(*k)).find_map(|v| zone_limit(v)) {
+        return Some((limit, true));
+    }
+    let zone = class_zone(highway)?;
+    zone_limit(&format!("{}:{}", country?, zone)).map(|s| (s, true))
+}
+
+/// km/h of a zone code: `RU:urban`, `kz:rural`. Codes of countries missing from ZONE_LIMITS give
+/// None.
+pub fn zone_limit(code: &str) -> Option<f64> {
+    let (country, zone) = code.split(';').next()?.trim().split_once(':')?;
+    let (_, zones) = ZONE_LIMITS.iter().find(|(c, _)| c.eq_ignore_ascii_case(country.trim()))?;
+    zones.iter().find(|(z, _)| z.eq_ignore_ascii_case(zone.trim())).map(|(_, limit)| *limit)
+}
+
+/// Whether implicit limits of `country` are known
+pub fn is_known_country(country: &str) -> bool {
+    ZONE_LIMITS.iter().any(|(c, _)| c.eq_ignore_ascii_case(country))
+}
+
+/// The zone a road without a `maxspeed` is in by its class alone. Trunk, primary and lesser
+/// roads run both through towns and between them, so they are left out.
+fn class_zone(highway: &str) -> Option<&'static str> {
+    match highway {
+        "motorway" | "motorway_link" => Some("motorway"),
+        "residential" | "service" => Some("urban"),
+        "living_street" => Some("living_street"),
+        _ => None,
+    }
+}
+
+/// km/h of an OSM `maxspeed` value: `60`, `60 km/h`, `30 mph`, or the first of `50;70`. Zone
+/// names (`RU:urban`), `walk`, `none` and `signals` give None.
+pub fn parse_maxspeed(value: &str) -> Option<f64> {
+    let first = value.split(';').next()?.trim();
+    let (number, mph) = match first.strip_suffix("mph") {
+        Some(n) => (n, true),
+        None => (first.strip_suffix("km/h").unwrap_or(first), false),
+    };
+    let speed: f64 = number.trim().parse().ok().filter(|s: &f64| s.is_finite() && *s > 0.0)?;
+    Some(if mph { speed * KMH_PER_MPH } else { speed })
+}
+
+/// The segments of an area on a grid of cells, for finding the one a point lies on. Distances
+/// are planar in meters around the middle of the area, fine at the scale of a snap distance.
+pub struct SegmentIndex {
+    segments: Vec<RoadSegmentModel>,
+    bbox: BBox,
+    /// Meters per degree of longitude at the middle latitude
+    lng_scale: f64,
+    cell_m: f64,
+    snap_m: f64,
+    cells: HashMap<(i64, i64), Vec<usize>>,
+}
+
+impl SegmentIndex {
+    /// Indexes `segments` for points of `bbox` within `snap_m` meters of one
+    pub fn new(segments: Vec<RoadSegmentModel>, bbox: BBox, snap_m: f64) -> Self {
+        let lng_scale = METERS_PER_DEGREE * ((bbox.lat_min + bbox.lat_max) / 2.0).to_radians().cos().max(0.01);
+        let cell_m = snap_m.max(50.0);
+        let mut index = Self { segments, bbox, lng_scale, cell_m, snap_m, cells: HashMap::new() };
+        for (i, s) in index.segments.iter().enumerate() {
+            let (x1, y1) = index.project(s.lat1, s.lng1);
+            let (x2, y2) = index.project(s.lat2, s.lng2);
+            let (c0, r0) = index.cell(x1.min(x2) - snap_m, y1.min(y2) - snap_m);
+            let (c1, r1) = index.cell(x1.max(x2) + snap_m, y1.max(y2) + snap_m);
+            for col in c0..=c1 {
+                for row in r0..=r1 {
+                    index.cells.entry((col, row)).or_default().push(i);
+                }
+            }
+        }
+        index
+    }
+
+    pub fn len(&self) -> usize {
+        self.segments.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    /// The closest segment within the snap distance of the point, if any
+    pub fn nearest(&self, lat: f64, lng: f64) -> Option<&RoadSegmentModel> {
+        let (x, y) = self.project(lat, lng);
+        let candidates = self.cells.get(&self.cell(x, y))?;
+        candidates
+            .iter()
+            .map(|&i| {
+                let s = &self.segments[i];
+                (distance_to_segment((x, y), self.project(s.lat1, s.lng1), self.project(s.lat2, s.lng2)), s)
+            })
+            .filter(|(d, _)| *d <= self.snap_m)
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, s)| s)
+    }
+
+    fn project(&self, lat: f64, lng: f64) -> (f64, f64) {
+        (self.bbox.unwrap_lng(lng) * self.lng_scale, lat * METERS_PER_DEGREE)
+    }
+
+    fn cell(&self, x: f64, y: f64) -> (i64, i64) {
+        ((x / self.cell_m).floor() as i64, (y / self.cell_m).floor() as i64)
+    }
+}
+
+fn distance_to_segment(p: (f64, f64), a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let len2 = dx * dx + dy * dy;
+    let t = if len2 > 0.0 { (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / len2).clamp(0.0, 1.0) } else { 0.0 };
+    ((p.0 - a.0 - t * dx).powi(2) + (p.1 - a.1 - t * dy).powi(2)).sqrt()
+}
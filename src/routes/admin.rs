@@ -0,0 +1,11 @@
+use actix_web::{HttpResponse, Error};
+use minijinja::context;
+
+/// Admin dashboard. The page itself holds no data: its script asks for the ADMIN_TOKEN and
+/// loads `/api/admin/stats` and `/api/admin/stats/anomalies` with it.
+pub async fn admin() -> Result<HttpResponse, Error> {
+    crate::templates::render_template(
+        "admin",
+        context! {},
+    )
+}
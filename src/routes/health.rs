@@ -0,0 +1,49 @@
+use actix_web::{web, HttpResponse};
+use log::warn;
+use sea_orm::DatabaseConnection;
+use sea_orm_migration::MigratorTrait;
+use serde::Serialize;
+
+use crate::migration::Migrator;
+use crate::self_check::{Check, Report};
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Readiness {
+    ready: bool,
+    database: String,
+    pending_migrations: Option<usize>,
+    /// Startup self-check results
+    checks: Vec<Check>,
+}
+
+/// Liveness: the process is up and serving requests; never touches the database.
+pub async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().body("ok")
+}
+
+/// Readiness: the database answers, every migration has been applied and the startup
+/// self-check passed. Returns 503 otherwise so the pod is taken out of rotation.
+pub async fn readyz(db: web::Data<DatabaseConnection>, report: web::Data<Report>) -> HttpResponse {
+    let (database, pending_migrations) = match db.ping().await {
+        Ok(()) => match Migrator::get_pending_migrations(db.get_ref()).await {
+            Ok(pending) => ("ok".to_string(), Some(pending.len())),
+            Err(e) => (format!("migration status unavailable: {}", e), None),
+        },
+        Err(e) => (format!("unreachable: {}", e), None),
+    };
+    let ready = database == "ok" && pending_migrations == Some(0) && report.ok();
+    if !ready {
+        warn!(
+            "Readiness check failed: database={} pending_migrations={:?} failed_checks={}",
+            database, pending_migrations, report.failures().count()
+        );
+    }
+
+    let body = Readiness { ready, database, pending_migrations, checks: report.checks.clone() };
+    if ready {
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::ServiceUnavailable().json(body)
+    }
+}
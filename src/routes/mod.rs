@@ -2,8 +2,14 @@ mod index;
 mod paint;
 mod not_found;
 mod map;
+mod health;
+mod status;
+mod admin;
 
 pub use index::index;
 pub use paint::paint;
 pub use not_found::not_found;
-pub use map::map;
\ No newline at end of file
+pub use map::map;
+pub use health::{healthz, readyz};
+pub use status::status;
+pub use admin::admin;
\ No newline at end of file
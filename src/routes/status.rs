@@ -0,0 +1,25 @@
+use actix_web::{web, HttpResponse, Error};
+use minijinja::context;
+
+use crate::anomaly::ClassificationQueue;
+use crate::database::batch::IngestBatcher;
+use crate::database::store::CircuitBreaker;
+use crate::metrics::metrics;
+
+/// Built-in status page: ingestion rate, cache hit rate, per-endpoint traffic and the last
+/// run of each background job, as counted since the process started.
+pub async fn status(
+    queue: web::Data<ClassificationQueue>,
+    batcher: Option<web::Data<IngestBatcher>>,
+    breaker: web::Data<CircuitBreaker>,
+) -> Result<HttpResponse, Error> {
+    crate::templates::render_template(
+        "status",
+        context! {
+            metrics => metrics().snapshot(),
+            queue_depth => queue.depth(),
+            batch_pending => batcher.map(|b| b.pending()),
+            database_degraded => breaker.is_degraded(),
+        },
+    )
+}
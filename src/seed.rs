@@ -0,0 +1,185 @@
+//! `indrive seed`: fills the database with synthetic trips, so a fresh checkout has something to
+//! show on the maps without a production dump.
+//!
+//! Every trip is a random walk: it starts at a random spot in the area, drives along a heading
+//! that drifts and now and then turns, speeds up and slows down around a cruising speed, stops
+//! for a while at times and turns back at the edges of the area. A few trips get a burst of
+//! implausible speed, flagged as anomalous, so the anomaly views are not empty either.
+
+use chrono::{DateTime, Duration, Utc};
+use log::info;
+
+use crate::api::defaults::{defaults, parse_bbox};
+use crate::database::store::{BBox, NewPointRecord, PointStore, StoreResult};
+use crate::geo;
+
+/// Seconds between two fixes of a trip
+const FIX_INTERVAL_SECS: i64 = 5;
+/// Trip length in fixes: 5 to 30 minutes
+const MIN_FIXES: f64 = 60.0;
+const MAX_FIXES: f64 = 360.0;
+/// Cruising speed range in m/s (roughly 30 to 60 km/h)
+const MIN_CRUISE: f64 = 8.0;
+const MAX_CRUISE: f64 = 17.0;
+/// Chance per fix of stopping (a light, a pickup) and of turning at a crossing
+const STOP_CHANCE: f64 = 0.01;
+const TURN_CHANCE: f64 = 0.02;
+/// Share of trips with an anomalous speed burst
+const ANOMALOUS_SHARE: f64 = 0.05;
+/// Points handed to the store at once
+const BATCH_POINTS: usize = 5000;
+/// Used when neither --bbox nor DEFAULT_BBOX is given: central Kostanay
+const FALLBACK_BBOX: &str = "53.15,63.50,53.28,63.72";
+
+#[derive(Debug, Clone)]
+pub struct SeedConfig {
+    pub trips: u32,
+    pub bbox: BBox,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    /// The same seed gives the same trips
+    pub rng_seed: u64,
+}
+
+impl SeedConfig {
+    /// Fills in what was left out: the area defaults to DEFAULT_BBOX, the period to the last
+    /// 7 days and the seed to the current time
+    pub fn new(trips: u32, bbox: Option<BBox>, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>, rng_seed: Option<u64>) -> Result<Self, String> {
+        let now = Utc::now();
+        let config = Self {
+            trips,
+            bbox: bbox.or(defaults().bbox).unwrap_or_else(|| parse_bbox(FALLBACK_BBOX).expect("valid fallback bbox")),
+            from: from.unwrap_or(now - Duration::days(7)),
+            to: to.unwrap_or(now),
+            rng_seed: rng_seed.unwrap_or_else(|| now.timestamp_nanos_opt().unwrap_or_default() as u64),
+        };
+        if config.trips == 0 {
+            return Err("--trips must be a positive integer".to_string());
+        }
+        let b = config.bbox;
+        if b.lat_min < -85.0 || b.lat_max > 85.0 || b.lat_min >= b.lat_max || b.crosses_antimeridian() || b.lng_min >= b.lng_max {
+            return Err("--bbox must be a non-empty area between 85° S and 85° N that does not cross the antimeridian".to_string());
+        }
+        if config.from >= config.to {
+            return Err(format!("--from ({}) must be before --to ({})", config.from, config.to));
+        }
+        Ok(config)
+    }
+}
+
+/// Writes the configured trips through `store` and returns the number of points stored
+pub async fn run(store: &dyn PointStore, config: &SeedConfig) -> StoreResult<u64> {
+    let mut rng = Rng(config.rng_seed);
+    let mut batch = Vec::with_capacity(BATCH_POINTS);
+    let mut stored = 0;
+    for n in 1..=config.trips {
+        batch.extend(trip(&mut rng, config));
+        if batch.len() >= BATCH_POINTS || n == config.trips {
+            stored += store.insert_many(std::mem::take(&mut batch)).await?.len() as u64;
+            info!("Seeded {}/{} trips ({} points)", n, config.trips, stored);
+        }
+    }
+    Ok(stored)
+}
+
+/// Fixes of one random-walk trip inside `config.bbox`
+fn trip(rng: &mut Rng, config: &SeedConfig) -> Vec<NewPointRecord> {
+    let b = config.bbox;
+    let randomized_id = (rng.next_u64() >> 1) as i64;
+    let fixes = rng.range(MIN_FIXES, MAX_FIXES) as i64;
+    let latest_start = (config.to - config.from).num_seconds() - fixes * FIX_INTERVAL_SECS;
+    let start = config.from + Duration::seconds((rng.unit() * latest_start.max(0) as f64) as i64);
+    let (mut lat, mut lng) = (rng.range(b.lat_min, b.lat_max), rng.range(b.lng_min, b.lng_max));
+    let mut heading = rng.range(0.0, 360.0);
+    let cruise = rng.range(MIN_CRUISE, MAX_CRUISE);
+    let alt = rng.range(150.0, 250.0);
+    // Fixes [burst_start, burst_end) of an anomalous trip are driven at several times the cruising speed
+    let (burst_start, burst_end) = if rng.unit() < ANOMALOUS_SHARE {
+        let s = rng.range(0.0, (fixes - 12) as f64) as i64;
+        (s, s + rng.range(6.0, 12.0) as i64)
+    } else {
+        (0, 0)
+    };
+    let mut spd: f64 = 0.0;
+    let mut stopped_for = 0;
+    let mut points = Vec::with_capacity(fixes as usize);
+    for i in 0..fixes {
+        let burst = (burst_start..burst_end).contains(&i);
+        if stopped_for > 0 && !burst {
+            stopped_for -= 1;
+            spd = 0.0;
+        } else if rng.unit() < STOP_CHANCE && !burst {
+            stopped_for = rng.range(3.0, 24.0) as i64;
+            spd = 0.0;
+        } else {
+            let target = if burst { cruise * 4.0 } else { cruise };
+            spd = (spd + (target - spd) * 0.3 + rng.normal() * 0.8).max(0.0);
+            heading += rng.normal() * 4.0;
+            if rng.unit() < TURN_CHANCE {
+                heading += if rng.unit() < 0.5 { 90.0 } else { -90.0 };
+            }
+            heading = heading.rem_euclid(360.0);
+        }
+        let step = spd * FIX_INTERVAL_SECS as f64;
+        let (mut next_lat, mut next_lng) = advance(lat, lng, heading, step);
+        if !inside(&b, next_lat, next_lng) {
+            heading = (heading + 180.0).rem_euclid(360.0);
+            (next_lat, next_lng) = advance(lat, lng, heading, step);
+            if !inside(&b, next_lat, next_lng) {
+                (next_lat, next_lng) = (lat, lng);
+            }
+        }
+        (lat, lng) = (next_lat, next_lng);
+        points.push(NewPointRecord {
+            randomized_id,
+            lat,
+            lng,
+            alt: alt + rng.normal() * 2.0,
+            spd,
+            azm: heading,
+            timestamp: Some(start + Duration::seconds(i * FIX_INTERVAL_SECS)),
+            anomaly: Some(burst),
+            client_uuid: None,
+            device_id: None,
+            tenant_id: None,
+        });
+    }
+    points
+}
+
+fn advance(lat: f64, lng: f64, heading: f64, meters: f64) -> (f64, f64) {
+    let h = heading.to_radians();
+    (lat + geo::meters_to_lat_deg(meters * h.cos()), lng + geo::meters_to_lng_deg(meters * h.sin(), lat))
+}
+
+fn inside(b: &BBox, lat: f64, lng: f64) -> bool {
+    (b.lat_min..=b.lat_max).contains(&lat) && (b.lng_min..=b.lng_max).contains(&lng)
+}
+
+/// SplitMix64: small and reproducible from a seed, which is all the generator needs
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in [0, 1)
+    fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn range(&mut self, lo: f64, hi: f64) -> f64 {
+        lo + (hi - lo) * self.unit()
+    }
+
+    /// Standard normal (Box-Muller)
+    fn normal(&mut self) -> f64 {
+        let u = 1.0 - self.unit();
+        (-2.0 * u.ln()).sqrt() * (std::f64::consts::TAU * self.unit()).cos()
+    }
+}
@@ -0,0 +1,94 @@
+use log::{error, info};
+use serde::Serialize;
+use std::env;
+use std::path::Path;
+use std::time::Duration;
+
+/// How long the classifier webhook gets to answer at boot
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub name: String,
+    pub ok: bool,
+    pub detail: String,
+}
+
+/// Results of the startup self-check, kept for `/readyz`
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Report {
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    pub fn record(&mut self, name: impl Into<String>, res: Result<String, String>) {
+        let (ok, detail) = match res {
+            Ok(d) => (true, d),
+            Err(d) => (false, d),
+        };
+        self.checks.push(Check { name: name.into(), ok, detail });
+    }
+
+    pub fn ok(&self) -> bool {
+        self.checks.iter().all(|c| c.ok)
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &Check> {
+        self.checks.iter().filter(|c| !c.ok)
+    }
+
+    /// Classifier webhook (the url of the one subscribed to `classify`), directories the app
+    /// reads from and directories it writes to
+    pub async fn check_environment(&mut self, classifier: Option<&str>, required: &[(&str, &Path)], writable: &[(&str, &Path)]) {
+        if env::var("ANOMALY_CLASSIFIER").is_ok_and(|v| v == "webhook") {
+            self.record("anomaly webhook", check_webhook(classifier).await);
+        }
+        for (name, dir) in required {
+            self.record(*name, check_dir(dir));
+        }
+        for (name, dir) in writable {
+            self.record(*name, check_writable(dir).await);
+        }
+    }
+
+    pub fn log(&self) {
+        for check in &self.checks {
+            if check.ok {
+                info!("Self-check {}: {}", check.name, check.detail);
+            } else {
+                error!("Self-check {} FAILED: {}", check.name, check.detail);
+            }
+        }
+    }
+}
+
+/// STARTUP_CHECK=`fail` (default) refuses to start on a failed check;
+/// `report` starts anyway and lets `/readyz` answer 503 with the report.
+pub fn fail_fast() -> bool {
+    env::var("STARTUP_CHECK").map(|v| v != "report").unwrap_or(true)
+}
+
+async fn check_webhook(url: Option<&str>) -> Result<String, String> {
+    let url = url.ok_or_else(|| "no enabled webhook subscribes to classify".to_string())?;
+    let client = reqwest::Client::builder().timeout(WEBHOOK_TIMEOUT).build().map_err(|e| e.to_string())?;
+    // Any HTTP answer proves the service is there; it may well refuse HEAD
+    match client.head(url).send().await {
+        Ok(resp) => Ok(format!("{} answered {}", url, resp.status())),
+        Err(e) => Err(format!("{} unreachable: {}", url, e)),
+    }
+}
+
+fn check_dir(dir: &Path) -> Result<String, String> {
+    if dir.is_dir() {
+        Ok(format!("{} exists", dir.display()))
+    } else {
+        Err(format!("{} is missing or not a directory", dir.display()))
+    }
+}
+
+async fn check_writable(dir: &Path) -> Result<String, String> {
+    let probe = dir.join(format!(".self-check-{}", std::process::id()));
+    tokio::fs::write(&probe, b"ok").await.map_err(|e| format!("{} is not writable: {}", dir.display(), e))?;
+    let _ = tokio::fs::remove_file(&probe).await;
+    Ok(format!("{} is writable", dir.display()))
+}
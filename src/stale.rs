@@ -0,0 +1,126 @@
+use actix_web::body::{self, BodySize, BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::web::Bytes;
+use actix_web::{error, http::header, http::Method, http::StatusCode, Error, HttpResponse};
+use log::warn;
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::sync::{Arc, Mutex};
+
+use crate::database::store::{CircuitBreaker, TenantScope};
+
+/// Bodies larger than this are not worth keeping for outages
+const MAX_ENTRY_BYTES: u64 = 2 * 1024 * 1024;
+
+/// Last good JSON body per GET URI under `/api`, kept for STALE_CACHE_ENTRIES URIs
+/// (default 256, 0 disables). Oldest entries are evicted first.
+pub struct StaleCache {
+    capacity: usize,
+    entries: Mutex<Entries>,
+}
+
+#[derive(Default)]
+struct Entries {
+    bodies: HashMap<String, Bytes>,
+    order: VecDeque<String>,
+}
+
+impl StaleCache {
+    pub fn from_env() -> Self {
+        let capacity = env::var("STALE_CACHE_ENTRIES").ok().and_then(|v| v.parse().ok()).unwrap_or(256);
+        Self { capacity, entries: Mutex::new(Entries::default()) }
+    }
+
+    /// URIs with a remembered body
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn get(&self, key: &str) -> Option<Bytes> {
+        self.entries.lock().unwrap().bodies.get(key).cloned()
+    }
+
+    fn put(&self, key: String, body: Bytes) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.bodies.insert(key.clone(), body).is_none() {
+            entries.order.push_back(key);
+        }
+        while entries.order.len() > self.capacity {
+            if let Some(old) = entries.order.pop_front() {
+                entries.bodies.remove(&old);
+            }
+        }
+    }
+}
+
+/// Remembers successful GET responses and, while the database is unreachable, answers failed
+/// ones with the remembered body plus `"stale": true` and a `Warning: 110` header.
+pub async fn serve_stale_on_outage(
+    cache: Arc<StaleCache>,
+    breaker: Arc<CircuitBreaker>,
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    if req.method() != Method::GET || cache.capacity == 0 {
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    }
+    // Runs inside the tenant scope, so tenants never get each other's responses
+    let key = match crate::tenant::current() {
+        Some(TenantScope::Tenant(t)) => format!("{} {}", t, req.uri()),
+        _ => req.uri().to_string(),
+    };
+    let res = next.call(req).await?;
+
+    if res.status().is_server_error() && breaker.is_degraded() {
+        let Some(body) = cache.get(&key) else {
+            return Ok(res.map_into_boxed_body());
+        };
+        warn!("Database unreachable; serving stale response for {}", key);
+        crate::metrics::metrics().record_stale_response();
+        let (req, _) = res.into_parts();
+        let resp = HttpResponse::Ok()
+            .content_type("application/json")
+            .insert_header((header::WARNING, "110 - \"Response is Stale\""))
+            .body(mark_stale(&body));
+        return Ok(ServiceResponse::new(req, resp));
+    }
+
+    let is_json = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"));
+    let small = matches!(res.response().body().size(), BodySize::Sized(n) if n <= MAX_ENTRY_BYTES);
+    if res.status() != StatusCode::OK || !is_json || !small {
+        return Ok(res.map_into_boxed_body());
+    }
+
+    let (req, resp) = res.into_parts();
+    let (resp, body) = resp.into_parts();
+    let bytes = body::to_bytes(body).await.map_err(|e| {
+        let e: Box<dyn std::error::Error> = e.into();
+        error::ErrorInternalServerError(e.to_string())
+    })?;
+    cache.put(key, bytes.clone());
+    Ok(ServiceResponse::new(req, resp.set_body(bytes).map_into_boxed_body()))
+}
+
+/// Adds `"stale": true` to an object body; other bodies are served as they were
+fn mark_stale(body: &Bytes) -> Bytes {
+    match serde_json::from_slice::<serde_json::Value>(body) {
+        Ok(serde_json::Value::Object(mut map)) => {
+            map.insert("stale".to_string(), serde_json::Value::Bool(true));
+            serde_json::to_vec(&map).map(Bytes::from).unwrap_or_else(|_| body.clone())
+        }
+        _ => body.clone(),
+    }
+}
@@ -0,0 +1,145 @@
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{http::header, Error, HttpResponse};
+use chrono::{DateTime, Utc};
+use log::{log, Level};
+use once_cell::sync::Lazy;
+use std::collections::{BTreeSet, VecDeque};
+use std::env;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Slow analytics requests kept for the index advisor
+const SLOW_QUERY_CAPACITY: usize = 1000;
+
+/// Log level for per-request telemetry lines from TELEMETRY_LOG (off|info|debug, default info).
+static TELEMETRY_LEVEL: Lazy<Option<Level>> = Lazy::new(|| {
+    match env::var("TELEMETRY_LOG").unwrap_or_else(|_| "info".to_string()).to_lowercase().as_str() {
+        "off" | "false" | "0" => None,
+        "debug" => Some(Level::Debug),
+        "trace" => Some(Level::Trace),
+        _ => Some(Level::Info),
+    }
+});
+
+/// Analytics requests at least this slow are kept in the slow query log (SLOW_QUERY_MS,
+/// default 500; 0 disables it)
+static SLOW_QUERY_THRESHOLD: Lazy<Option<Duration>> = Lazy::new(|| {
+    let ms = env::var("SLOW_QUERY_MS").ok().and_then(|v| v.parse::<u64>().ok()).unwrap_or(500);
+    (ms > 0).then(|| Duration::from_millis(ms))
+});
+
+static SLOW_QUERIES: Lazy<Mutex<VecDeque<SlowQuery>>> = Lazy::new(|| Mutex::new(VecDeque::new()));
+
+/// One slow analytics request. Only parameter names are kept, not their values.
+#[derive(Debug, Clone)]
+pub struct SlowQuery {
+    pub at: DateTime<Utc>,
+    /// Route pattern, e.g. `/api/trips/{randomized_id}/matched`
+    pub pattern: String,
+    pub params: BTreeSet<String>,
+    pub took_ms: f64,
+    pub rows_scanned: usize,
+}
+
+pub fn slow_query_threshold() -> Option<Duration> {
+    *SLOW_QUERY_THRESHOLD
+}
+
+/// The slow query log, oldest first
+pub fn slow_queries() -> Vec<SlowQuery> {
+    SLOW_QUERIES.lock().unwrap().iter().cloned().collect()
+}
+
+/// Keeps requests that attached `QueryStats` and took at least SLOW_QUERY_MS
+fn record_slow_query<B>(res: &ServiceResponse<B>, took: Duration) {
+    let Some(threshold) = *SLOW_QUERY_THRESHOLD else { return };
+    if took < threshold {
+        return;
+    }
+    let Some(stats) = res.response().extensions().get::<QueryStats>().copied() else { return };
+    let request = res.request();
+    let params = request
+        .query_string()
+        .split('&')
+        .filter_map(|pair| pair.split('=').next())
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect();
+    let entry = SlowQuery {
+        at: Utc::now(),
+        pattern: request.match_pattern().unwrap_or_else(|| request.path().to_string()),
+        params,
+        took_ms: took.as_secs_f64() * 1000.0,
+        rows_scanned: stats.rows_scanned,
+    };
+    let mut log = SLOW_QUERIES.lock().unwrap();
+    if log.len() == SLOW_QUERY_CAPACITY {
+        log.pop_front();
+    }
+    log.push_back(entry);
+}
+
+/// Work counters an analytics handler attaches to its response for the telemetry log line.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QueryStats {
+    /// Rows returned by the store before in-memory filtering
+    pub rows_scanned: usize,
+    /// Tiles (or routes/buckets) in the response payload
+    pub tiles: usize,
+}
+
+impl QueryStats {
+    pub fn attach(self, mut resp: HttpResponse) -> HttpResponse {
+        resp.extensions_mut().insert(self);
+        resp
+    }
+}
+
+/// Middleware emitting one key=value line per request under the `telemetry` log target:
+/// request/response payload sizes (before compression), tile count, rows scanned and latency.
+pub async fn log_request_telemetry(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let started = Instant::now();
+    let Some(level) = *TELEMETRY_LEVEL else {
+        let res = next.call(req).await?;
+        crate::metrics::metrics().record_request(&res, started.elapsed());
+        record_slow_query(&res, started.elapsed());
+        return Ok(res);
+    };
+    let client = crate::client_ip::of_request(&req);
+    let method = req.method().to_string();
+    let path = req.path().to_string();
+    let request_bytes = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(0);
+
+    let res = next.call(req).await?;
+    crate::metrics::metrics().record_request(&res, started.elapsed());
+    record_slow_query(&res, started.elapsed());
+
+    let response_bytes = match res.response().body().size() {
+        BodySize::Sized(n) => n.to_string(),
+        BodySize::None => "0".to_string(),
+        BodySize::Stream => "stream".to_string(),
+    };
+    let stats = res.response().extensions().get::<QueryStats>().copied();
+    let (tiles, rows) = match stats {
+        Some(s) => (s.tiles.to_string(), s.rows_scanned.to_string()),
+        None => ("-".to_string(), "-".to_string()),
+    };
+    log!(
+        target: "telemetry",
+        level,
+        "client={} method={} path={} status={} request_bytes={} response_bytes={} tiles={} rows_scanned={} took_ms={:.3}",
+        client, method, path, res.status().as_u16(), request_bytes, response_bytes, tiles, rows,
+        started.elapsed().as_secs_f64() * 1000.0
+    );
+    Ok(res)
+}
@@ -7,10 +7,13 @@ use std::path::Path;
 use serde::Serialize;
 use actix_web::{Error, HttpResponse};
 
+/// Built frontend; every `.html` file in it is a template
+pub const TEMPLATE_DIR: &str = "web/out";
+
 pub static TEMPLATES: Lazy<AutoReloader> = Lazy::new(|| {
     AutoReloader::new(|notifier| {
         let mut env = Environment::new();
-        let template_path = "web/out";
+        let template_path = TEMPLATE_DIR;
         env.set_loader(path_loader(template_path));
         notifier.watch_path(template_path, true);
         Ok(env)
@@ -21,6 +24,12 @@ pub struct TemplateManager {
     templates: HashMap<String, String>,
 }
 
+impl Default for TemplateManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl TemplateManager {
     pub fn new() -> Self {
         let mut manager = Self {
@@ -31,22 +40,20 @@ impl TemplateManager {
     }
 
     fn load_templates(&mut self) {
-        let template_dir = Path::new("web/out");
+        let template_dir = Path::new(TEMPLATE_DIR);
         
         if let Ok(entries) = fs::read_dir(template_dir) {
             for entry in entries.flatten() {
                 let path = entry.path();
                 
-                if path.is_file() && path.extension().map_or(false, |ext| ext == "html") {
-                    if let Some(file_stem) = path.file_stem() {
-                        if let Some(template_name) = file_stem.to_str() {
-                            let template_path = path.file_name()
-                                .and_then(|name| name.to_str())
-                                .unwrap_or("")
-                                .to_string();
-                            self.templates.insert(template_name.to_string(), template_path);
-                        }
-                    }
+                if path.is_file() && path.extension().is_some_and(|ext| ext == "html")
+                    && let Some(template_name) = path.file_stem().and_then(|stem| stem.to_str())
+                {
+                    let template_path = path.file_name()
+                        .and_then(|name| name.to_str())
+                        .unwrap_or("")
+                        .to_string();
+                    self.templates.insert(template_name.to_string(), template_path);
                 }
             }
         }
@@ -80,7 +87,7 @@ impl TemplateManager {
     }
 }
 
-pub static TEMPLATE_MANAGER: Lazy<TemplateManager> = Lazy::new(|| TemplateManager::new());
+pub static TEMPLATE_MANAGER: Lazy<TemplateManager> = Lazy::new(TemplateManager::new);
 
 pub fn render_template<T: Serialize>(template_name: &str, ctx: T) -> Result<HttpResponse, Error> {
     TEMPLATE_MANAGER.render(template_name, ctx)
@@ -0,0 +1,152 @@
+//! Several deployments (cities) served by one instance. Every `/api` request is resolved to a
+//! tenant, and the stores handed to the handlers (`TenantScoped`) only read that tenant's rows
+//! and store new ones for it. Rows stored without a tenant, such as those from before tenants
+//! were configured, form the shared scope that requests naming no tenant see.
+
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderName};
+use actix_web::middleware::Next;
+use actix_web::{Error, ResponseError};
+use log::warn;
+use std::collections::HashMap;
+use std::env;
+use std::future::Future;
+use std::sync::Arc;
+
+use crate::api::error::ApiError;
+use crate::database::store::TenantScope;
+
+/// Longest tenant name, the width of the `tenant_id` columns
+const MAX_NAME_LEN: usize = 64;
+
+tokio::task_local! {
+    static CURRENT: TenantScope;
+}
+
+/// TENANT_API_KEYS maps API keys to tenants as `key=tenant` entries separated by `,`; the key
+/// is sent like the rate limiter's (`X-API-Key` or a Bearer token). TENANT_HEADER names a
+/// header carrying the tenant, for gateways that authenticate clients themselves; only set it
+/// when clients cannot reach the server past that gateway. Tenancy is off while both are unset.
+#[derive(Debug, Clone, Default)]
+pub struct TenantConfig {
+    keys: HashMap<String, String>,
+    header: Option<HeaderName>,
+}
+
+impl TenantConfig {
+    pub fn from_env() -> Result<Self, String> {
+        let mut keys = HashMap::new();
+        for entry in env::var("TENANT_API_KEYS").unwrap_or_default().split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let Some((key, tenant)) = entry.split_once('=').map(|(k, t)| (k.trim(), t.trim())) else {
+                return Err(format!("TENANT_API_KEYS entry '{}': expected key=tenant", entry));
+            };
+            if key.is_empty() || !is_valid_name(tenant) {
+                return Err(format!("TENANT_API_KEYS entry for tenant '{}': {}", tenant, NAME_RULE));
+            }
+            keys.insert(key.to_string(), tenant.to_string());
+        }
+        let header = match env::var("TENANT_HEADER") {
+            Ok(v) if !v.trim().is_empty() => {
+                Some(HeaderName::try_from(v.trim()).map_err(|_| format!("TENANT_HEADER '{}' is not a header name", v.trim()))?)
+            }
+            _ => None,
+        };
+        Ok(Self { keys, header })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.keys.is_empty() || self.header.is_some()
+    }
+
+    /// Whether `api_key` is one of TENANT_API_KEYS
+    pub fn knows_key(&self, api_key: &str) -> bool {
+        self.keys.contains_key(api_key)
+    }
+
+    /// Scope of a client that sent `api_key`, for transports without HTTP headers (gRPC)
+    pub fn for_api_key(&self, api_key: Option<&str>) -> TenantScope {
+        match api_key.and_then(|k| self.keys.get(k)) {
+            Some(tenant) => TenantScope::Tenant(tenant.clone()),
+            None => TenantScope::Shared,
+        }
+    }
+
+    /// Scope of the points a broker transport (MQTT, Kafka) stores: the tenant its setting
+    /// `var` names. The setting is required while tenancy is on, as a broker message cannot
+    /// say which tenant it comes from; with tenancy off an unset one leaves points unscoped.
+    pub fn for_transport(&self, var: &str) -> Result<Option<TenantScope>, String> {
+        match env::var(var).ok().map(|v| v.trim().to_string()).filter(|v| !v.is_empty()) {
+            Some(tenant) if is_valid_name(&tenant) => Ok(Some(TenantScope::Tenant(tenant))),
+            Some(_) => Err(format!("{}: {}", var, NAME_RULE)),
+            None if self.is_enabled() => Err(format!("{} must name the tenant of its points while tenants are configured", var)),
+            None => Ok(None),
+        }
+    }
+
+    /// A known API key wins over the header; neither gives the shared scope
+    fn of_request(&self, req: &ServiceRequest) -> Result<TenantScope, String> {
+        let headers = req.headers();
+        let api_key = headers
+            .get("x-api-key")
+            .and_then(|v| v.to_str().ok())
+            .or_else(|| headers.get(header::AUTHORIZATION).and_then(|v| v.to_str().ok()).and_then(|v| v.strip_prefix("Bearer ")))
+            .map(str::trim);
+        if let Some(tenant) = api_key.and_then(|k| self.keys.get(k)) {
+            return Ok(TenantScope::Tenant(tenant.clone()));
+        }
+        let Some(value) = self.header.as_ref().and_then(|h| headers.get(h)) else {
+            return Ok(TenantScope::Shared);
+        };
+        match value.to_str().map(str::trim) {
+            Ok(tenant) if is_valid_name(tenant) => Ok(TenantScope::Tenant(tenant.to_string())),
+            _ => Err(format!("Invalid tenant header: {}", NAME_RULE)),
+        }
+    }
+}
+
+const NAME_RULE: &str = "tenant names are 1 to 64 letters, digits, '-' or '_'";
+
+/// Tenant names end up in queries and cache keys, so only short plain tokens are accepted
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= MAX_NAME_LEN && name.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_'))
+}
+
+/// Scope of the request whose future is running; None outside requests and when tenancy is off
+pub fn current() -> Option<TenantScope> {
+    CURRENT.try_with(TenantScope::clone).ok()
+}
+
+/// Tenant that rows stored by the running request belong to
+pub fn current_id() -> Option<String> {
+    current().and_then(|s| s.tenant_id().map(str::to_string))
+}
+
+/// Runs `fut` for `scope`, for transports other than the HTTP server; None leaves it unscoped
+pub async fn scope<F: Future>(scope: Option<TenantScope>, fut: F) -> F::Output {
+    match scope {
+        Some(scope) => CURRENT.scope(scope, fut).await,
+        None => fut.await,
+    }
+}
+
+/// Middleware running each `/api` request in the scope of its tenant. Admin endpoints, which
+/// check ADMIN_TOKEN themselves, see the whole instance.
+pub async fn resolve(
+    config: Arc<TenantConfig>,
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    if !config.is_enabled() || req.path() == "/api/admin" || req.path().starts_with("/api/admin/") {
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    }
+    let scope = match config.of_request(&req) {
+        Ok(scope) => scope,
+        Err(message) => {
+            warn!("Rejected {} from {}: {}", req.path(), crate::client_ip::of_request(&req), message);
+            let resp = ApiError::bad_request(message).error_response();
+            return Ok(req.into_response(resp));
+        }
+    };
+    Ok(CURRENT.scope(scope, next.call(req)).await?.map_into_boxed_body())
+}
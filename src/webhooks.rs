@@ -0,0 +1,253 @@
+//! Outgoing webhooks, configured in the `webhooks` table through `/api/admin/webhooks`. Each
+//! webhook subscribes to events: `classify` asks an external classifier about incoming points
+//! (ANOMALY_CLASSIFIER=webhook), `anomaly` is told about every point classified anomalous,
+//! `trip_anomaly` about every trip once its first point is and `alert` about every alert the
+//! rules of `/api/alerts/rules` raise.
+//! Deliveries are retried with exponential backoff and signed with the webhook's secret.
+
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use log::{error, info, warn};
+use serde::Serialize;
+use sha2::Sha256;
+use std::env;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::database::model::webhooks::Model as WebhookModel;
+use crate::database::store::{StoreResult, WebhookSettings, WebhookStore};
+use crate::metrics::metrics;
+
+/// Name of the notifications on the status page
+const JOB_NAME: &str = "webhook notifications";
+
+/// Longest wait between two attempts, however many there are
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebhookEvent {
+    /// Asks for the anomaly verdict of an incoming point; the answer body is the verdict
+    Classify,
+    /// A point was classified anomalous
+    Anomaly,
+    /// A trip got its first anomalous point
+    TripAnomaly,
+    /// An alert rule fired
+    Alert,
+}
+
+impl WebhookEvent {
+    pub const ALL: [WebhookEvent; 4] = [WebhookEvent::Classify, WebhookEvent::Anomaly, WebhookEvent::TripAnomaly, WebhookEvent::Alert];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WebhookEvent::Classify => "classify",
+            WebhookEvent::Anomaly => "anomaly",
+            WebhookEvent::TripAnomaly => "trip_anomaly",
+            WebhookEvent::Alert => "alert",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|e| e.as_str() == name.trim())
+    }
+}
+
+/// Events a stored webhook subscribes to; unknown names are skipped
+pub fn events_of(hook: &WebhookModel) -> Vec<WebhookEvent> {
+    hook.events.split(',').filter_map(WebhookEvent::parse).collect()
+}
+
+/// WEBHOOK_MAX_ATTEMPTS (default 4) bounds the attempts per delivery, WEBHOOK_BACKOFF_MS
+/// (default 500) is the wait before the first retry, doubled after each one, and
+/// WEBHOOK_TIMEOUT_SECS (default 10) limits each attempt.
+#[derive(Debug, Clone)]
+pub struct RetryConfig {
+    pub max_attempts: u32,
+    pub backoff: Duration,
+    pub timeout: Duration,
+}
+
+impl RetryConfig {
+    pub fn from_env() -> Self {
+        let number = |name: &str, default: u64| env::var(name).ok().and_then(|v| v.parse::<u64>().ok()).filter(|n| *n > 0).unwrap_or(default);
+        Self {
+            max_attempts: number("WEBHOOK_MAX_ATTEMPTS", 4) as u32,
+            backoff: Duration::from_millis(number("WEBHOOK_BACKOFF_MS", 500)),
+            timeout: Duration::from_secs(number("WEBHOOK_TIMEOUT_SECS", 10)),
+        }
+    }
+}
+
+/// Why an attempt failed, and whether another one may do better
+struct Failure {
+    message: String,
+    retry: bool,
+}
+
+/// The configured webhooks, read once at startup and again after every change made through
+/// the admin API, and the client delivering to them
+pub struct Webhooks {
+    store: Arc<dyn WebhookStore>,
+    hooks: RwLock<Vec<WebhookModel>>,
+    client: reqwest::Client,
+    retry: RetryConfig,
+}
+
+impl Webhooks {
+    /// Reads the table. A POINTS_WEBHOOK_URL from before the table existed is added to it once
+    /// as a `classify` webhook.
+    pub async fn load(store: Arc<dyn WebhookStore>, retry: RetryConfig) -> Self {
+        let client = reqwest::Client::builder().timeout(retry.timeout).build().expect("Failed to build webhook client");
+        let webhooks = Self { store, hooks: RwLock::new(Vec::new()), client, retry };
+        if let Err(e) = webhooks.reload().await {
+            error!("Could not read webhooks, starting without any: {}", e);
+            return webhooks;
+        }
+        if let Some(url) = env::var("POINTS_WEBHOOK_URL").ok().map(|u| u.trim().to_string()).filter(|u| !u.is_empty()) {
+            warn!("POINTS_WEBHOOK_URL is deprecated; manage webhooks through /api/admin/webhooks");
+            if !webhooks.all().iter().any(|h| h.url == url) {
+                let settings = WebhookSettings { url: url.clone(), events: vec![WebhookEvent::Classify.as_str().to_string()], secret: None, enabled: true };
+                match webhooks.create(&settings).await {
+                    Ok(hook) => info!("Added POINTS_WEBHOOK_URL {} as classify webhook {}", url, hook.id),
+                    Err(e) => error!("Could not add POINTS_WEBHOOK_URL as a webhook: {}", e),
+                }
+            }
+        }
+        webhooks
+    }
+
+    pub async fn reload(&self) -> StoreResult<()> {
+        let hooks = self.store.find_webhooks().await?;
+        *self.hooks.write().unwrap_or_else(|e| e.into_inner()) = hooks;
+        Ok(())
+    }
+
+    /// Every webhook, disabled ones included, oldest first
+    pub fn all(&self) -> Vec<WebhookModel> {
+        self.hooks.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn find(&self, id: i64) -> Option<WebhookModel> {
+        self.all().into_iter().find(|h| h.id == id)
+    }
+
+    /// Enabled webhooks subscribed to `event`
+    pub fn subscribers(&self, event: WebhookEvent) -> Vec<WebhookModel> {
+        self.all().into_iter().filter(|h| h.enabled && events_of(h).contains(&event)).collect()
+    }
+
+    pub async fn create(&self, settings: &WebhookSettings) -> StoreResult<WebhookModel> {
+        let hook = self.store.create_webhook(settings).await?;
+        self.reload().await?;
+        Ok(hook)
+    }
+
+    pub async fn update(&self, id: i64, settings: &WebhookSettings) -> StoreResult<Option<WebhookModel>> {
+        let hook = self.store.update_webhook(id, settings).await?;
+        self.reload().await?;
+        Ok(hook)
+    }
+
+    pub async fn delete(&self, id: i64) -> StoreResult<bool> {
+        let deleted = self.store.delete_webhook(id).await?;
+        self.reload().await?;
+        Ok(deleted)
+    }
+
+    pub fn has_subscribers(&self, event: WebhookEvent) -> bool {
+        !self.subscribers(event).is_empty()
+    }
+
+    /// Delivers `payload` to the first webhook subscribed to `event` and returns the body of its
+    /// answer; None when no enabled webhook subscribes
+    pub async fn call(&self, event: WebhookEvent, payload: &impl Serialize) -> Option<Result<String, String>> {
+        let hook = self.subscribers(event).into_iter().next()?;
+        let body = match serde_json::to_vec(payload) {
+            Ok(b) => b,
+            Err(e) => return Some(Err(format!("payload serialization failed: {}", e))),
+        };
+        Some(deliver(&self.client, &self.retry, &hook, event, body).await)
+    }
+
+    /// Delivers `payload` to every webhook subscribed to `event` in the background
+    pub fn notify(&self, event: WebhookEvent, payload: &impl Serialize) {
+        let hooks = self.subscribers(event);
+        if hooks.is_empty() {
+            return;
+        }
+        let body = match serde_json::to_vec(payload) {
+            Ok(b) => b,
+            Err(e) => {
+                error!("Webhook payload serialization failed: {}", e);
+                return;
+            }
+        };
+        for hook in hooks {
+            let (client, retry, body) = (self.client.clone(), self.retry.clone(), body.clone());
+            tokio::spawn(async move {
+                match deliver(&client, &retry, &hook, event, body).await {
+                    Ok(_) => metrics().record_job(JOB_NAME, true, format!("{} delivered to {}", event.as_str(), hook.url)),
+                    Err(e) => {
+                        error!("Webhook {} gave up on {}: {}", hook.id, event.as_str(), e);
+                        metrics().record_job(JOB_NAME, false, format!("{} to {}: {}", event.as_str(), hook.url, e));
+                    }
+                }
+            });
+        }
+    }
+}
+
+/// `sha256=` and the hex HMAC-SHA256 of `{timestamp}.{body}` under `secret`, the value of
+/// `X-Webhook-Signature`
+pub fn signature(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Posts `body` until an attempt succeeds, fails for good (4xx other than 429) or the attempts
+/// run out. Every attempt carries the same `X-Webhook-Delivery` id and a fresh timestamp and
+/// signature.
+async fn deliver(client: &reqwest::Client, retry: &RetryConfig, hook: &WebhookModel, event: WebhookEvent, body: Vec<u8>) -> Result<String, String> {
+    let delivery = Uuid::new_v4().to_string();
+    let mut backoff = retry.backoff;
+    let mut attempt = 1;
+    loop {
+        match attempt_once(client, hook, event, &delivery, &body).await {
+            Ok(answer) => return Ok(answer),
+            Err(f) if f.retry && attempt < retry.max_attempts => {
+                warn!("Webhook {} attempt {}/{} failed: {}; retrying in {:?}", hook.id, attempt, retry.max_attempts, f.message, backoff);
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+                attempt += 1;
+            }
+            Err(f) => return Err(format!("{} (attempt {}/{})", f.message, attempt, retry.max_attempts)),
+        }
+    }
+}
+
+async fn attempt_once(client: &reqwest::Client, hook: &WebhookModel, event: WebhookEvent, delivery: &str, body: &[u8]) -> Result<String, Failure> {
+    let timestamp = Utc::now().timestamp();
+    let mut req = client
+        .post(&hook.url)
+        .header("Content-Type", "application/json")
+        .header("X-Webhook-Event", event.as_str())
+        .header("X-Webhook-Delivery", delivery)
+        .header("X-Webhook-Timestamp", timestamp.to_string());
+    if let Some(secret) = hook.secret.as_deref() {
+        req = req.header("X-Webhook-Signature", signature(secret, timestamp, body));
+    }
+    let resp = req.body(body.to_vec()).send().await.map_err(|e| Failure { message: e.to_string(), retry: true })?;
+    let status = resp.status();
+    if !status.is_success() {
+        return Err(Failure {
+            message: format!("{} answered {}", hook.url, status),
+            retry: status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS,
+        });
+    }
+    resp.text().await.map_err(|e| Failure { message: e.to_string(), retry: true })
+}
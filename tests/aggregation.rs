@@ -0,0 +1,557 @@
+//! Aggregation endpoints against seeded points. The area 50..52 N, 70..72 E cut into 1° tiles
+//! is a 2x2 grid, so every tile neighbours the other three.
+
+mod common;
+
+use chrono::{Duration, Utc};
+use common::{anomalous, point, tile, TestDb, REVIEWER_TOKEN};
+use indrive::database::store::{AnomalyVerdict, Embargoed, PointFilter, PointOrder, PublicationDelay, TripStore};
+use serde_json::json;
+use indrive::database::tile_rollup::{self, RollupConfig};
+
+const AREA: &str = "lat1=52&lng1=70&lat2=50&lng2=72&tileWidth=1&tileHeight=1";
+
+/// Trip 1 crosses three tiles, trip 2 stays in the south-west one, trip 3 is flagged in the
+/// north-west one, and trip 4 lies outside the area
+async fn seeded() -> TestDb {
+    let db = TestDb::new().await;
+    db.seed(vec![
+        point(1, 50.5, 70.5, 10.0, "2025-01-06T08:00:00Z"),
+        point(1, 50.5, 71.5, 20.0, "2025-01-06T08:01:00Z"),
+        point(1, 51.5, 71.5, 30.0, "2025-01-06T08:02:00Z"),
+        point(2, 50.5, 70.5, 4.0, "2025-01-07T09:00:00Z"),
+        point(2, 50.6, 70.6, 6.0, "2025-01-07T09:01:00Z"),
+        anomalous(point(3, 51.5, 70.5, 50.0, "2025-01-08T10:00:00Z")),
+        anomalous(point(3, 51.6, 70.6, 60.0, "2025-01-08T10:01:00Z")),
+        point(3, 51.7, 70.7, 5.0, "2025-01-08T10:02:00Z"),
+        point(4, 55.0, 75.0, 15.0, "2025-01-06T08:00:00Z"),
+    ])
+    .await;
+    db
+}
+
+#[actix_web::test]
+async fn trafficmap_counts_points_per_tile() {
+    let db = seeded().await;
+    let (status, body) = db.get(&format!("/api/trafficmap?{}", AREA)).await;
+    assert_eq!(status, 200, "{}", body);
+    let data = &body["traficmap"]["data"];
+    assert_eq!(data.as_array().unwrap().len(), 4);
+    let expected = [((50.0, 70.0), 3, 5), ((50.0, 71.0), 1, 7), ((51.0, 70.0), 3, 5), ((51.0, 71.0), 1, 7)];
+    for ((lat, lng), count, neighbors) in expected {
+        let t = tile(data, lat, lng);
+        assert_eq!(t["count"], count, "tile ({}, {})", lat, lng);
+        assert_eq!(t["neighborCount"], neighbors, "tile ({}, {})", lat, lng);
+    }
+    assert_eq!(body["traficmap"]["tileSize"]["width"], 1.0);
+    let meta = &body["meta"];
+    assert_eq!((meta["rows"].as_u64(), meta["cols"].as_u64(), meta["cells"].as_u64()), (Some(2), Some(2), Some(4)), "{}", meta);
+    assert_eq!((meta["points"].as_u64(), meta["trips"].as_u64()), (Some(8), Some(3)), "{}", meta);
+    assert_eq!(meta["bounds"]["latMin"], 50.0);
+}
+
+#[actix_web::test]
+async fn trafficmap_counts_unique_trips_per_tile() {
+    let db = seeded().await;
+    let (status, body) = db.get(&format!("/api/trafficmap?{}&mode=uniqueTrips", AREA)).await;
+    assert_eq!(status, 200, "{}", body);
+    let data = &body["traficmap"]["data"];
+    // Trips 1 and 2 in the south-west tile, trip 3's three points in the north-west one
+    assert_eq!(tile(data, 50.0, 70.0)["count"], 2);
+    assert_eq!(tile(data, 51.0, 70.0)["count"], 1);
+    assert_eq!(tile(data, 51.0, 71.0)["count"], 1);
+}
+
+#[actix_web::test]
+async fn trafficmap_sends_only_the_selected_fields() {
+    let db = seeded().await;
+    let (status, body) = db.get(&format!("/api/trafficmap?{}&fields=count,topLeft", AREA)).await;
+    assert_eq!(status, 200, "{}", body);
+    let t = tile(&body["traficmap"]["data"], 50.0, 70.0);
+    assert_eq!(t.as_object().unwrap().len(), 2, "{}", t);
+    assert_eq!(t["count"], 3);
+
+    let (status, body) = db.get(&format!("/api/trafficmap?{}&fields=topLeft,count&encoding=rows", AREA)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["traficmap"]["columns"], serde_json::json!(["topLeft", "count"]));
+    let rows = body["traficmap"]["data"].as_array().unwrap();
+    assert!(rows.contains(&serde_json::json!([[50.0, 70.0], 3])), "{:?}", rows);
+
+    let (status, body) = db.get(&format!("/api/trafficmap?{}&fields=count,speed", AREA)).await;
+    assert_eq!((status, body["details"]["param"].as_str()), (400, Some("fields")), "{}", body);
+}
+
+#[actix_web::test]
+async fn misspelled_trafficmap_path_is_a_deprecated_alias() {
+    let db = seeded().await;
+    let (_, canonical) = db.get(&format!("/api/trafficmap?{}", AREA)).await;
+    let (status, alias) = db.get(&format!("/api/traficmap?{}", AREA)).await;
+    assert_eq!(status, 200, "{}", alias);
+    assert_eq!(alias["traficmap"], canonical["traficmap"]);
+    let doc = serde_json::to_value(indrive::api::openapi()).unwrap();
+    assert_eq!(doc["paths"]["/api/trafficmap"]["get"]["deprecated"], serde_json::Value::Null);
+    assert_eq!(doc["paths"]["/api/traficmap"]["get"]["deprecated"], true);
+}
+
+#[actix_web::test]
+async fn trafficmap_honours_the_date_range() {
+    let db = seeded().await;
+    let (status, body) = db.get(&format!("/api/trafficmap?{}&dateStart=2025-01-07T00:00:00Z", AREA)).await;
+    assert_eq!(status, 200, "{}", body);
+    let data = &body["traficmap"]["data"];
+    assert_eq!(tile(data, 50.0, 70.0)["count"], 2);
+    assert_eq!(tile(data, 51.0, 70.0)["count"], 3);
+    // Trip 1 is gone; empty tiles stay because their neighbours have points
+    assert_eq!(tile(data, 50.0, 71.0)["count"], 0);
+    assert_eq!(tile(data, 51.0, 71.0)["neighborCount"], 5);
+}
+
+#[actix_web::test]
+async fn heatmap_counts_trip_origins() {
+    let db = seeded().await;
+    let (status, body) = db.get(&format!("/api/heatmap?{}", AREA)).await;
+    assert_eq!(status, 200, "{}", body);
+    let data = &body["heatmap"]["data"];
+    assert_eq!(tile(data, 50.0, 70.0)["count"], 2);
+    assert_eq!(tile(data, 51.0, 70.0)["count"], 1);
+    assert_eq!(tile(data, 50.0, 71.0)["count"], 0);
+    assert_eq!(tile(data, 51.0, 71.0)["neighborCount"], 3);
+}
+
+#[actix_web::test]
+async fn heatmap_smoothing_weighs_the_surrounding_tiles() {
+    let db = seeded().await;
+    // Over a 2x2 grid every 3x3 box covers all four tiles: 3 trips / 4
+    let (status, body) = db.get(&format!("/api/heatmap?{}&smoothing=box", AREA)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(tile(&body["heatmap"]["data"], 51.0, 71.0)["smoothed"], 0.75);
+    // Self 2 at weight 1, the tile north 1 at 1/2, the one east 0 at 1/2; the diagonal is out of reach
+    let (_, body) = db.get(&format!("/api/heatmap?{}&smoothing=distance", AREA)).await;
+    assert_eq!(tile(&body["heatmap"]["data"], 50.0, 70.0)["smoothed"], 1.25);
+
+    let (status, _) = db.get(&format!("/api/heatmap?{}&smoothing=median", AREA)).await;
+    assert_eq!(status, 400);
+}
+
+#[actix_web::test]
+async fn speedmap_averages_speeds_per_tile() {
+    let db = seeded().await;
+    let (status, body) = db.get(&format!("/api/speedmap?{}&stats=full", AREA)).await;
+    assert_eq!(status, 200, "{}", body);
+    let data = &body["speedmap"]["data"];
+    let expected = [((50.0, 70.0), 20.0 / 3.0), ((50.0, 71.0), 20.0), ((51.0, 70.0), 115.0 / 3.0), ((51.0, 71.0), 30.0)];
+    for ((lat, lng), avg) in expected {
+        let got = tile(data, lat, lng)["count"].as_f64().unwrap();
+        assert!((got - avg).abs() < 1e-9, "tile ({}, {}): {} != {}", lat, lng, got, avg);
+    }
+    // Average over the points of the three other tiles: (20 + 30 + 115) / 5
+    let neighbors = tile(data, 50.0, 70.0)["neighborCount"].as_f64().unwrap();
+    assert!((neighbors - 33.0).abs() < 1e-9, "{}", neighbors);
+    let stats = &tile(data, 50.0, 70.0)["stats"];
+    assert_eq!(stats["min"], 4.0);
+    assert_eq!(stats["max"], 10.0);
+}
+
+#[actix_web::test]
+async fn anomalies_group_flagged_points_by_trip() {
+    let db = seeded().await;
+    let (status, body) = db.get("/api/anomalies?lat1=52&lng1=70&lat2=50&lng2=72").await;
+    assert_eq!(status, 200, "{}", body);
+    let routes = body["anomalies"].as_array().unwrap();
+    assert_eq!(routes.len(), 1, "{}", body);
+    assert_eq!(routes[0]["randomized_id"], 3);
+    let points = routes[0]["points"].as_array().unwrap();
+    assert_eq!(points.len(), 2);
+    assert_eq!(points[0]["lat"], 51.5);
+    assert_eq!(points[1]["lat"], 51.6);
+
+    // Two of the trip's three points are flagged
+    let (_, body) = db.get("/api/anomalies?lat1=52&lng1=70&lat2=50&lng2=72&minAnomalyRatio=0.9").await;
+    assert_eq!(body["anomalies"].as_array().unwrap().len(), 0, "{}", body);
+    let (_, body) = db.get("/api/anomalies?lat1=52&lng1=70&lat2=50&lng2=72&minAnomalyRatio=0.5").await;
+    assert_eq!(body["anomalies"].as_array().unwrap().len(), 1, "{}", body);
+
+    // Points stored already flagged score 1
+    assert_eq!(points[0]["score"], 1.0);
+    let (_, body) = db.get("/api/anomalies?lat1=52&lng1=70&lat2=50&lng2=72&minScore=0.9").await;
+    assert_eq!(body["anomalies"].as_array().unwrap().len(), 1, "{}", body);
+}
+
+#[actix_web::test]
+async fn reviews_override_the_classifier_without_erasing_it() {
+    let db = seeded().await;
+    let (status, _) = db.patch_as("/api/anomalies/3", None, json!({"decision": "dismiss", "reviewer": "mallory"})).await;
+    assert_eq!(status, 401);
+    let (status, _) = db.patch_as("/api/anomalies/3", Some("guess"), json!({"decision": "dismiss"})).await;
+    assert_eq!(status, 401);
+
+    let (status, body) = db.patch_as("/api/anomalies/3", Some(REVIEWER_TOKEN), json!({"decision": "dismiss", "reviewer": "mallory"})).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["reviewed_by"], "alice");
+    assert_eq!(body["points"], 3);
+    let (_, body) = db.get("/api/anomalies?lat1=52&lng1=70&lat2=50&lng2=72").await;
+    assert_eq!(body["anomalies"].as_array().unwrap().len(), 0, "{}", body);
+
+    // The classifier's flags stay on the points, and flagging one again changes nothing
+    let filter = PointFilter { randomized_id: Some(3), ..Default::default() };
+    let points = db.store.find(&filter, PointOrder::TimestampAsc, None).await.unwrap();
+    let flags: Vec<Option<bool>> = points.iter().map(|p| p.anomaly).collect();
+    assert_eq!(flags, [Some(true), Some(true), Some(false)]);
+    let verdict = AnomalyVerdict { anomaly: true, score: 0.9, reason: Some("speed_spike") };
+    db.store.set_anomaly(points[2].id, verdict).await.unwrap();
+    let (_, body) = db.get("/api/trips?randomizedId=3").await;
+    let trip = &body["trips"][0];
+    assert_eq!((trip["anomaly"].as_bool(), trip["anomalyPoints"].as_i64()), (Some(false), Some(0)), "{}", body);
+    assert_eq!(trip["review"], "dismiss");
+    assert_eq!(trip["reviewedBy"], "alice");
+
+    // Confirming covers the whole trip, points added later included
+    let (status, _) = db.patch_as("/api/anomalies/2", Some(REVIEWER_TOKEN), json!({"decision": "confirm"})).await;
+    assert_eq!(status, 200);
+    db.seed(vec![point(2, 50.7, 70.7, 6.0, "2025-01-07T09:02:00Z")]).await;
+    let (_, body) = db.get("/api/trips?randomizedId=2").await;
+    assert_eq!(body["trips"][0]["anomalyPoints"], 3, "{}", body);
+    let (_, body) = db.get("/api/anomalies?lat1=52&lng1=70&lat2=50&lng2=72").await;
+    let ids: Vec<i64> = body["anomalies"].as_array().unwrap().iter().map(|r| r["randomized_id"].as_i64().unwrap()).collect();
+    assert_eq!(ids, [2], "{}", body);
+    assert_eq!(db.patch_as("/api/anomalies/99", Some(REVIEWER_TOKEN), json!({"decision": "confirm"})).await.0, 404);
+}
+
+#[actix_web::test]
+async fn anomalies_in_trips_mode_return_whole_routes() {
+    let db = seeded().await;
+    // Only the second flagged point of trip 3 is in the area
+    let (status, body) = db.get("/api/anomalies?lat1=51.65&lng1=70.55&lat2=51.55&lng2=70.65&mode=trips").await;
+    assert_eq!(status, 200, "{}", body);
+    let routes = body["anomalies"].as_array().unwrap();
+    assert_eq!(routes.len(), 1, "{}", body);
+    let points = routes[0]["points"].as_array().unwrap();
+    let flags: Vec<_> = points.iter().map(|p| (p["lat"].as_f64().unwrap(), p["anomaly"].as_bool().unwrap())).collect();
+    assert_eq!(flags, vec![(51.5, true), (51.6, true), (51.7, false)]);
+
+    let (status, _) = db.get("/api/anomalies?lat1=52&lng1=70&lat2=50&lng2=72&mode=trips&sample=10").await;
+    assert_eq!(status, 400);
+}
+
+#[actix_web::test]
+async fn anomalies_are_paged_by_route() {
+    let db = seeded().await;
+    db.seed(vec![
+        anomalous(point(5, 51.2, 70.2, 70.0, "2025-01-09T10:00:00Z")),
+        anomalous(point(6, 51.3, 70.3, 70.0, "2025-01-09T11:00:00Z")),
+    ])
+    .await;
+    let (status, body) = db.get("/api/anomalies?lat1=52&lng1=70&lat2=50&lng2=72&limit=2&maxPointsPerRoute=1").await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["total"], 3);
+    assert_eq!(body["nextCursor"], 5);
+    let routes = body["anomalies"].as_array().unwrap();
+    assert_eq!(routes.iter().map(|r| r["randomized_id"].as_i64().unwrap()).collect::<Vec<_>>(), vec![3, 5]);
+    assert_eq!(routes[0]["points"].as_array().unwrap().len(), 1);
+
+    let (_, body) = db.get("/api/anomalies?lat1=52&lng1=70&lat2=50&lng2=72&limit=2&cursor=5").await;
+    assert_eq!(body["anomalies"][0]["randomized_id"], 6, "{}", body);
+    assert!(body["nextCursor"].is_null());
+}
+
+#[actix_web::test]
+async fn routes_are_simplified_within_the_tolerance() {
+    let db = seeded().await;
+    // A straight trip east whose middle point is flagged
+    db.seed(
+        (1..=9)
+            .map(|i| {
+                let p = point(7, 50.9, 70.0 + i as f64 / 10.0, 10.0, &format!("2025-01-10T10:0{}:00Z", i));
+                if i == 5 { anomalous(p) } else { p }
+            })
+            .collect(),
+    )
+    .await;
+    let (status, body) = db.get("/api/anomalies?lat1=50.95&lng1=70&lat2=50.85&lng2=71&mode=trips&tolerance=100").await;
+    assert_eq!(status, 200, "{}", body);
+    let lngs: Vec<f64> = body["anomalies"][0]["points"].as_array().unwrap().iter().map(|p| p["lng"].as_f64().unwrap()).collect();
+    // The ends of the trip and of its anomalous stretch stay, the collinear rest goes
+    assert_eq!(lngs, vec![70.1, 70.4, 70.5, 70.6, 70.9]);
+
+    let (status, body) = db.get("/api/anomalies?lat1=50.95&lng1=70&lat2=50.85&lng2=71&tolerance=-1").await;
+    assert_eq!(status, 400, "{}", body);
+    assert_eq!(body["details"]["param"], "tolerance");
+}
+
+#[actix_web::test]
+async fn invalid_parameters_are_rejected() {
+    let db = seeded().await;
+    let (status, body) = db.get("/api/trafficmap?lat1=52&lng1=70&lat2=50&lng2=72&tileWidth=-1&tileHeight=1").await;
+    assert_eq!(status, 400, "{}", body);
+    let (status, body) = db.get(&format!("/api/speedmap?{}&stats=everything", AREA)).await;
+    assert_eq!(status, 400, "{}", body);
+    assert_eq!(body["details"]["param"], "stats");
+    let (status, body) = db.get("/api/heatmap?lat1=95&lng1=70&lat2=50&lng2=72&tileWidth=1&tileHeight=1").await;
+    assert_eq!(status, 400, "{}", body);
+}
+
+#[actix_web::test]
+async fn analytics_endpoints_reject_parameters_with_the_error_body() {
+    let db = seeded().await;
+    for (uri, param) in [
+        ("/api/anomalymap?lat1=95&lng1=70&lat2=50&lng2=72&tileWidth=1&tileHeight=1", "lat1"),
+        ("/api/stats/timeline?bucket=year", "bucket"),
+        (&format!("/api/grid?{}&metric=nope", AREA), "metric"),
+        ("/api/forecast?lat1=50&lng1=70&lat2=51&lng2=71&tileWidth=1&tileHeight=1&hours=0", "hours"),
+        ("/api/trips?sort=fastest", "sort"),
+        ("/api/timeseries/decompose?lat1=50&lng1=70&lat2=51&lng2=71&bucket=year", "bucket"),
+    ] {
+        let (status, body) = db.get(uri).await;
+        assert_eq!(status, 400, "{}: {}", uri, body);
+        assert_eq!(body["code"], "bad_request", "{}: {}", uri, body);
+        assert_eq!(body["details"]["param"], param, "{}: {}", uri, body);
+    }
+}
+
+#[actix_web::test]
+async fn aligned_trafficmap_is_added_up_from_tile_rollups() {
+    let db = seeded().await;
+    // Within the last second of the range: counted by the scan as by the rollup of its hour
+    db.seed(vec![point(5, 50.5, 70.5, 10.0, "2025-01-08T23:59:59.500Z")]).await;
+    let hours = format!("{}&dateStart=2025-01-06T00:00:00Z&dateEnd=2025-01-08T23:59:59Z", AREA);
+    let (_, live) = db.get(&format!("/api/trafficmap?{}", hours)).await;
+    assert!(live["meta"].get("rollups").is_none(), "{}", live["meta"]);
+    assert_eq!(live["meta"]["points"], 9, "{}", live["meta"]);
+
+    let config = RollupConfig { sides: vec![1_000_000], interval_secs: 300, lookback_hours: 48, backfill_hours: 24 * 365 * 10 };
+    tile_rollup::run_once(db.store.as_ref(), db.tile_stats.as_ref(), &config).await.expect("rollup");
+    let (status, rolled) = db.get(&format!("/api/trafficmap?{}", hours)).await;
+    assert_eq!(status, 200, "{}", rolled);
+    assert_eq!(rolled["meta"]["rollups"], true, "{}", rolled["meta"]);
+    assert!(rolled["meta"]["trips"].is_null());
+    assert_eq!(rolled["traficmap"], live["traficmap"]);
+
+    // Half-hour bounds and uniqueTrips still scan the points
+    let (_, body) = db.get(&format!("/api/trafficmap?{}&dateStart=2025-01-06T00:30:00Z", AREA)).await;
+    assert!(body["meta"].get("rollups").is_none());
+    let (_, body) = db.get(&format!("/api/trafficmap?{}&mode=uniqueTrips", hours)).await;
+    assert!(body["meta"].get("rollups").is_none());
+    // An inclusive end on a whole hour takes in trip 2's first point, at 09:00:00
+    let (_, body) = db.get(&format!("/api/trafficmap?{}&dateStart=2025-01-06T00:00:00Z&dateEnd=2025-01-07T09:00:00Z", AREA)).await;
+    assert!(body["meta"].get("rollups").is_none());
+    assert_eq!(body["meta"]["points"], 4, "{}", body["meta"]);
+}
+
+/// What the retention job does with POINTS_RETENTION_DAYS
+async fn purge(db: &TestDb, cutoff: &str) {
+    let cutoff = cutoff.parse().unwrap();
+    db.store.delete(&PointFilter { until: Some(cutoff), ..Default::default() }).await.unwrap();
+    tile_rollup::invalidate(db.store.as_ref(), db.tile_stats.as_ref(), chrono::DateTime::<chrono::Utc>::MIN_UTC, cutoff).await.unwrap();
+}
+
+#[actix_web::test]
+async fn deleted_points_leave_the_tile_rollups() {
+    let db = seeded().await;
+    let hours = format!("{}&dateStart=2025-01-06T00:00:00Z&dateEnd=2025-01-08T23:59:59Z", AREA);
+    let config = RollupConfig { sides: vec![1_000_000], interval_secs: 300, lookback_hours: 48, backfill_hours: 24 * 365 * 10 };
+    tile_rollup::run_once(db.store.as_ref(), db.tile_stats.as_ref(), &config).await.expect("rollup");
+    let (_, before) = db.get(&format!("/api/trafficmap?{}", hours)).await;
+    assert_eq!(before["meta"]["points"], 8, "{}", before["meta"]);
+
+    let (status, body) = db.delete_admin("/api/points?randomizedId=2").await;
+    assert_eq!(status, 200, "{}", body);
+    let (_, after) = db.get(&format!("/api/trafficmap?{}", hours)).await;
+    assert_eq!(after["meta"]["rollups"], true, "{}", after["meta"]);
+    assert_eq!(after["meta"]["points"], 6, "{}", after["meta"]);
+
+    // A retention purge: the covered hours before the cutoff are recomputed...
+    purge(&db, "2025-01-07T00:00:00Z").await;
+    let (_, body) = db.get(&format!("/api/trafficmap?{}", hours)).await;
+    assert_eq!(body["meta"]["rollups"], true, "{}", body["meta"]);
+    assert_eq!(body["meta"]["points"], 3, "{}", body["meta"]);
+    // ... or, past a week of them, left to the backfill
+    purge(&db, "2025-02-01T00:00:00Z").await;
+    let (_, body) = db.get(&format!("/api/trafficmap?{}", hours)).await;
+    assert!(body["meta"].get("rollups").is_none(), "{}", body["meta"]);
+    assert_eq!(body["meta"]["points"], 0, "{}", body["meta"]);
+}
+
+#[actix_web::test]
+async fn heatmap_png_paints_the_busy_tiles() {
+    let db = seeded().await;
+    let (status, png) = db.get_bytes(&format!("/api/heatmap.png?{}&width=20&height=20&palette=greys&alpha=1", AREA)).await;
+    assert_eq!(status, 200);
+    let img = image::load_from_memory_with_format(&png, image::ImageFormat::Png).unwrap().to_rgba8();
+    assert_eq!(img.dimensions(), (20, 20));
+    // Row 0 is north: the south-west tile has the most trips, the south-east one none
+    assert_eq!(img.get_pixel(5, 15).0, [0, 0, 0, 255]);
+    assert_eq!(img.get_pixel(5, 5).0[3], 255);
+    assert!(img.get_pixel(5, 5).0[0] > 0);
+    assert_eq!(img.get_pixel(15, 15).0[3], 0);
+
+    let (status, _) = db.get(&format!("/api/heatmap.png?{}&palette=rainbow", AREA)).await;
+    assert_eq!(status, 400);
+}
+
+#[actix_web::test]
+async fn replay_groups_points_into_frames() {
+    let db = seeded().await;
+    let range = "lat1=52&lng1=70&lat2=50&lng2=72&dateStart=2025-01-06T08:00:00Z&dateEnd=2025-01-06T08:03:30Z";
+    let (status, body) = db.get(&format!("/api/replay?{}&step=120", range)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["stepSeconds"], 120);
+    let frames = body["frames"].as_array().unwrap();
+    // Frames start at 08:00 and 08:02; trip 4 lies outside the area
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0]["start"], "2025-01-06T08:00:00Z");
+    assert_eq!(frames[0]["points"].as_array().unwrap().len(), 2);
+    assert_eq!(frames[1]["points"][0]["lat"], 51.5);
+
+    let (status, _) = db.get(&format!("/api/replay?{}&step=0", range)).await;
+    assert_eq!(status, 400);
+    let (status, _) = db.get(&format!("/api/replay?{}&step=1&dateEnd=2025-02-06T08:00:00Z", &range[..range.find("&dateEnd").unwrap()])).await;
+    assert_eq!(status, 400, "a month of seconds is too many frames");
+}
+
+#[actix_web::test]
+async fn tile_timeseries_profiles_one_tile_per_hour() {
+    let db = seeded().await;
+    let tile = "lat1=50&lng1=70&lat2=51&lng2=71&dateStart=2025-01-07T08:00:00Z&dateEnd=2025-01-07T10:59:59Z";
+    let (status, live) = db.get(&format!("/api/tile/timeseries?{}", tile)).await;
+    assert_eq!(status, 200, "{}", live);
+    assert_eq!(live["rollups"], false);
+    let hours = live["data"].as_array().unwrap();
+    assert_eq!(hours.len(), 3);
+    assert_eq!(hours[0]["count"], 0);
+    assert!(hours[0]["avgSpeed"].is_null());
+    assert_eq!(hours[1]["timestamp"], "2025-01-07T09:00:00Z");
+    assert_eq!((hours[1]["count"].as_u64(), hours[1]["trips"].as_u64(), hours[1]["avgSpeed"].as_f64()), (Some(2), Some(1), Some(5.0)));
+
+    let config = RollupConfig { sides: vec![1_000_000], interval_secs: 300, lookback_hours: 48, backfill_hours: 24 * 365 * 10 };
+    tile_rollup::run_once(db.store.as_ref(), db.tile_stats.as_ref(), &config).await.expect("rollup");
+    let (status, rolled) = db.get(&format!("/api/tile/timeseries?{}", tile)).await;
+    assert_eq!(status, 200, "{}", rolled);
+    assert_eq!(rolled["rollups"], true);
+    let counts = |body: &serde_json::Value| body["data"].as_array().unwrap().iter().map(|h| (h["count"].clone(), h["avgSpeed"].clone())).collect::<Vec<_>>();
+    assert_eq!(counts(&rolled), counts(&live));
+    assert!(rolled["data"][1]["trips"].is_null());
+
+    let (status, _) = db.get("/api/tile/timeseries?lat1=50&lng1=70&lat2=51&lng2=71&dateStart=2024-01-01T00:00:00Z&dateEnd=2025-06-01T00:00:00Z").await;
+    assert_eq!(status, 400, "more than a year of hours");
+}
+
+#[actix_web::test]
+async fn global_stats_add_up_their_shards() {
+    let db = seeded().await;
+    let (status, body) = db.get("/api/stats/global").await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!((body["totalPoints"].as_u64(), body["totalTrips"].as_u64()), (Some(9), Some(4)), "{}", body);
+    assert_eq!(body["bbox"]["topLeft"]["lat"], 50.5);
+    assert_eq!(body["bbox"]["bottomRight"]["lat"], 55.0);
+    assert_eq!(body["firstTimestamp"], "2025-01-06T08:00:00Z");
+    assert_eq!(body["lastTimestamp"], "2025-01-08T10:02:00Z");
+    let ingested: u64 = body["dailyIngest"].as_array().unwrap().iter().map(|d| d["points"].as_u64().unwrap()).sum();
+    assert_eq!(ingested, 9);
+
+    // A delete folds the shards into one
+    let (status, _) = db.delete_admin("/api/points?randomizedId=4").await;
+    assert_eq!(status, 200);
+    let (_, body) = db.get("/api/stats/global").await;
+    assert_eq!((body["totalPoints"].as_u64(), body["totalTrips"].as_u64()), (Some(8), Some(3)), "{}", body);
+    assert_eq!(body["bbox"]["bottomRight"]["lat"], 51.7);
+}
+
+#[actix_web::test]
+async fn decomposition_counts_points_per_bucket() {
+    let db = TestDb::new().await;
+    // Two weeks of the same weekly pattern: 1 point on Mondays up to 7 on Sundays
+    let mut points = Vec::new();
+    for day in 0..14 {
+        let date = chrono::NaiveDate::from_ymd_opt(2025, 1, 6).unwrap() + Duration::days(day);
+        for i in 0..=day % 7 {
+            points.push(point(day * 10 + i, 50.5, 70.5, 10.0, &format!("{}T{:02}:30:00Z", date, 8 + i)));
+        }
+    }
+    db.seed(points).await;
+
+    let (status, body) = db.get("/api/timeseries/decompose?lat1=50&lng1=70&lat2=51&lng2=71").await;
+    assert_eq!(status, 200, "{}", body);
+    let data = body["decomposition"]["data"].as_array().unwrap();
+    assert_eq!(data.len(), 14);
+    assert_eq!(data[0]["timestamp"], "2025-01-06T00:00:00Z");
+    let values: Vec<f64> = data.iter().map(|b| b["value"].as_f64().unwrap()).collect();
+    assert_eq!(values, (0..14).map(|d| (1 + d % 7) as f64).collect::<Vec<_>>());
+    // A pure weekly pattern leaves a flat trend and nothing unexplained
+    assert!((data[5]["trend"].as_f64().unwrap() - 4.0).abs() < 1e-9, "{}", data[5]);
+    assert!(data.iter().filter_map(|b| b["residual"].as_f64()).all(|r| r.abs() < 1e-9), "{}", body);
+}
+
+#[actix_web::test]
+async fn forecast_reads_only_the_shifted_hours_of_each_week() {
+    let db = TestDb::new().await;
+    db.seed(vec![
+        // One and two weeks before the forecast from 2025-01-20T08:00:00Z
+        point(1, 50.5, 70.5, 10.0, "2025-01-13T08:10:00Z"),
+        point(1, 50.5, 70.5, 20.0, "2025-01-13T08:40:00Z"),
+        point(2, 50.5, 70.5, 30.0, "2025-01-06T09:15:00Z"),
+        // Between the weeks and right after the horizon of the first one
+        point(3, 50.5, 70.5, 10.0, "2025-01-10T08:30:00Z"),
+        point(4, 50.5, 70.5, 10.0, "2025-01-13T10:00:00Z"),
+    ])
+    .await;
+
+    let (status, body) = db.get("/api/forecast?lat1=50&lng1=70&lat2=51&lng2=71&tileWidth=1&tileHeight=1&hours=2&weeks=2&from=2025-01-20T08:00:00Z").await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!((body["meta"]["points"].as_u64(), body["meta"]["trips"].as_u64()), (Some(3), Some(2)), "{}", body["meta"]);
+    let frames = body["forecast"]["data"].as_array().unwrap();
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0]["time"], "2025-01-20T08:00:00Z");
+    // Two points a week ago and none the week before; one point two weeks ago an hour later
+    assert_eq!(frames[0]["tiles"][0]["count"], 1.0);
+    assert_eq!(frames[0]["tiles"][0]["speed"], 15.0);
+    assert_eq!(frames[1]["tiles"][0]["count"], 0.5);
+    assert_eq!(frames[1]["tiles"][0]["speed"], 30.0);
+}
+
+#[actix_web::test]
+async fn global_stats_leave_out_unpublished_points() {
+    let db = TestDb::new().await;
+    let recent = (Utc::now() - Duration::minutes(5)).to_rfc3339();
+    db.seed(vec![point(1, 50.0, 70.0, 10.0, "2025-01-06T08:00:00Z"), point(2, 60.0, 80.0, 10.0, &recent)]).await;
+    let delayed = Embargoed::new(db.trips.clone(), PublicationDelay::new(Duration::minutes(10)));
+
+    let stats = delayed.global_stats(90, None).await.unwrap();
+    assert_eq!((stats.total_points, stats.total_trips), (1, 1));
+    assert_eq!(stats.last_ts.unwrap().to_rfc3339(), "2025-01-06T08:00:00+00:00");
+    assert_eq!(stats.bbox.unwrap().lat_max, 50.0);
+    assert!(stats.daily_ingest.is_empty(), "{:?}", stats.daily_ingest);
+
+    // The admin totals are live
+    let live = delayed.live_stats(90).await.unwrap();
+    assert_eq!(live.total_points, 2);
+    assert_eq!(live.bbox.unwrap().lat_max, 60.0);
+}
+
+#[actix_web::test]
+async fn freeflow_baseline_averages_local_night_speeds() {
+    let db = TestDb::new().await;
+    let mut points = Vec::new();
+    for day in 1..=5 {
+        // 02:00 and 15:00 at +05:00 in the south-west tile, 08:00 in the north-east one
+        points.push(point(1, 50.5, 70.5, 20.0, &format!("2025-01-0{}T21:00:00Z", day)));
+        points.push(point(1, 50.5, 70.5, 10.0, &format!("2025-01-0{}T10:00:00Z", day)));
+        points.push(point(2, 51.5, 71.5, 30.0, &format!("2025-01-0{}T03:00:00Z", day)));
+    }
+    db.seed(points).await;
+    let query = format!("/api/speedmap?{}&baseline=freeflow&dateEnd=2025-01-10T00:00:00Z", AREA);
+
+    let (status, body) = db.get(&format!("{}&timezone=%2B05:00", query)).await;
+    assert_eq!(status, 200, "{}", body);
+    let south_west = tile(&body["speedmap"]["data"], 50.0, 70.0);
+    assert_eq!(south_west["freeflowSpeed"], 20.0, "{}", south_west);
+    assert_eq!(south_west["congestion"], 0.75, "{}", south_west);
+    assert!(tile(&body["speedmap"]["data"], 51.0, 71.0).get("freeflowSpeed").is_none());
+
+    let (_, body) = db.get(&format!("{}&timezone=%2B00:00", query)).await;
+    assert_eq!(tile(&body["speedmap"]["data"], 51.0, 71.0)["freeflowSpeed"], 30.0);
+
+    let (status, body) = db.get(&format!("{}&timezone=%2B05:00&binning=h3&resolution=7", query.replace("&tileWidth=1&tileHeight=1", ""))).await;
+    assert_eq!(status, 200, "{}", body);
+    let baselines: Vec<f64> = body["speedmap"]["data"].as_array().unwrap().iter().filter_map(|t| t["freeflowSpeed"].as_f64()).collect();
+    assert_eq!(baselines, vec![20.0], "{}", body);
+}
@@ -0,0 +1,129 @@
+//! Alert rules at `/api/alerts/rules`: speed and geofence rules raised by the classification
+//! worker, inactivity rules by the periodic check, all kept in `/api/alerts`
+
+mod common;
+
+use chrono::{Duration, Utc};
+use common::{point, TestDb};
+use indrive::alerts::{AlertFeed, Alerts};
+use indrive::database::store::{AlertFilter, PublicationDelay};
+use indrive::webhooks::{RetryConfig, Webhooks};
+use serde_json::json;
+use std::sync::Arc;
+
+
+#[actix_web::test]
+async fn rules_raise_alerts() {
+    let db = TestDb::new().await.with_ingestion().await;
+    let (status, _) = db.post("/api/alerts/rules", json!({"name": "Too fast", "kind": "speed", "maxSpeed": 80})).await;
+    assert_eq!(status, 401);
+    let (status, body) = db.post_admin("/api/alerts/rules", json!({"name": "Too fast", "kind": "speed"})).await;
+    assert_eq!(status, 400, "{}", body);
+    assert_eq!(body["details"]["param"], "maxSpeed");
+    let (status, body) = db.post_admin("/api/alerts/rules", json!({"name": "Depot", "kind": "geofence", "geofenceId": 42})).await;
+    assert_eq!(status, 400, "{}", body);
+    assert_eq!(body["details"]["param"], "geofenceId");
+
+    let (status, speed) = db.post_admin("/api/alerts/rules", json!({"name": "Too fast", "kind": "speed", "maxSpeed": 80, "silentMinutes": 5})).await;
+    assert_eq!(status, 201, "{}", speed);
+    assert_eq!(speed["maxSpeed"], 80.0);
+    assert!(speed.get("silentMinutes").is_none(), "{}", speed);
+    let speed_id = speed["id"].as_i64().unwrap();
+
+    let square = json!([{"lat": 43.0, "lng": 76.5}, {"lat": 43.0, "lng": 77.0}, {"lat": 43.5, "lng": 77.0}, {"lat": 43.5, "lng": 76.5}]);
+    let (_, fence) = db.post_admin("/api/geofences", json!({"name": "Depot", "polygon": square})).await;
+    let fence_id = fence["id"].as_i64().unwrap();
+    let (status, body) = db.post_admin("/api/alerts/rules", json!({"name": "Arrivals", "kind": "geofence", "geofenceId": fence_id, "on": ["arrive"]})).await;
+    assert_eq!(status, 400, "{}", body);
+    assert_eq!(body["details"]["param"], "on");
+    let (status, arrivals) = db.post_admin("/api/alerts/rules", json!({"name": "Arrivals", "kind": "geofence", "geofenceId": fence_id, "on": ["entry"]})).await;
+    assert_eq!(status, 201, "{}", arrivals);
+
+    // 25 m/s is 90 km/h: trip 7 speeds twice but is reported once
+    db.drive(7, &[
+        (42.9, 76.7, 10.0, "2025-01-06T08:00:00Z"),
+        (43.2, 76.7, 25.0, "2025-01-06T08:01:00Z"),
+        (43.3, 76.8, 26.0, "2025-01-06T08:02:00Z"),
+        (43.6, 76.8, 10.0, "2025-01-06T08:03:00Z"),
+    ])
+    .await;
+    db.drive(8, &[(42.0, 76.0, 20.0, "2025-01-06T09:00:00Z")]).await;
+    // A forgotten trip speeding again is not reported twice either
+    assert_eq!(db.alerts.forget_idle(std::time::Duration::ZERO), 1);
+    db.drive(7, &[(43.7, 76.8, 30.0, "2025-01-06T08:04:00Z")]).await;
+
+    let (status, page) = db.get("/api/alerts").await;
+    assert_eq!(status, 200, "{}", page);
+    let alerts = page["alerts"].as_array().unwrap();
+    let raised: Vec<(&str, i64)> = alerts.iter().map(|a| (a["kind"].as_str().unwrap(), a["randomizedId"].as_i64().unwrap())).collect();
+    assert_eq!(raised.len(), 2, "{}", page);
+    assert!(raised.contains(&("speed", 7)) && raised.contains(&("geofence", 7)), "{}", page);
+    let (_, page) = db.get(&format!("/api/alerts?ruleId={}", speed_id)).await;
+    assert_eq!(page["alerts"][0]["timestamp"], "2025-01-06T08:01:00Z");
+    assert!((page["alerts"][0]["value"].as_f64().unwrap() - 90.0).abs() < 0.01, "{}", page);
+
+    // A disabled rule stays quiet
+    let (status, body) = db.put_admin(&format!("/api/alerts/rules/{}", speed_id), json!({"name": "Too fast", "kind": "speed", "maxSpeed": 80, "enabled": false})).await;
+    assert_eq!(status, 200, "{}", body);
+    db.drive(9, &[(42.0, 76.0, 30.0, "2025-01-06T10:00:00Z")]).await;
+    let (_, page) = db.get("/api/alerts?kind=speed").await;
+    assert_eq!(page["alerts"].as_array().unwrap().len(), 1);
+
+    assert_eq!(db.delete(&format!("/api/alerts/rules/{}", speed_id)).await.0, 401);
+    let (status, _) = db.delete_admin(&format!("/api/alerts/rules/{}", speed_id)).await;
+    assert_eq!(status, 204);
+    assert_eq!(db.get(&format!("/api/alerts/rules/{}", speed_id)).await.0, 404);
+    let (_, list) = db.get("/api/alerts/rules").await;
+    assert_eq!(list["rules"].as_array().unwrap().len(), 1);
+    let (_, page) = db.get("/api/alerts?limit=1").await;
+    assert_eq!(page["alerts"].as_array().unwrap().len(), 1);
+    let cursor = page["nextCursor"].as_i64().unwrap();
+    let (_, page) = db.get(&format!("/api/alerts?cursor={}", cursor)).await;
+    assert_eq!(page["alerts"].as_array().unwrap().len(), 1);
+    assert_eq!(page["nextCursor"], serde_json::Value::Null);
+}
+
+#[actix_web::test]
+async fn silent_devices_are_reported_once() {
+    let db = TestDb::new().await;
+    let (status, rule) = db.post_admin("/api/alerts/rules", json!({"name": "Lost", "kind": "inactivity", "silentMinutes": 60})).await;
+    assert_eq!(status, 201, "{}", rule);
+
+    let recent = (Utc::now() - Duration::minutes(5)).to_rfc3339();
+    let mut old = point(1, 50.0, 70.0, 10.0, "2025-01-06T08:00:00Z");
+    old.device_id = Some("tracker-1".to_string());
+    let mut fresh = point(2, 50.0, 70.0, 10.0, &recent);
+    fresh.device_id = Some("tracker-2".to_string());
+    db.seed(vec![old, fresh]).await;
+
+    assert_eq!(db.alerts.check_inactivity(db.devices.as_ref(), Utc::now()).await.unwrap(), 1);
+    assert_eq!(db.alerts.check_inactivity(db.devices.as_ref(), Utc::now()).await.unwrap(), 0);
+
+    let (_, page) = db.get("/api/alerts?deviceId=tracker-1").await;
+    assert_eq!(page["alerts"].as_array().unwrap().len(), 1, "{}", page);
+    assert_eq!(page["alerts"][0]["kind"], "inactivity");
+    assert_eq!(page["alerts"][0]["timestamp"], "2025-01-06T09:00:00Z");
+}
+
+#[actix_web::test]
+async fn history_leaves_out_unpublished_alerts() {
+    let db = TestDb::new().await;
+    db.post_admin("/api/alerts/rules", json!({"name": "Lost", "kind": "inactivity", "silentMinutes": 1})).await;
+    let recent = (Utc::now() - Duration::minutes(3)).to_rfc3339();
+    let mut old = point(1, 50.0, 70.0, 10.0, "2025-01-06T08:00:00Z");
+    old.device_id = Some("tracker-1".to_string());
+    let mut fresh = point(2, 50.0, 70.0, 10.0, &recent);
+    fresh.device_id = Some("tracker-2".to_string());
+    db.seed(vec![old, fresh]).await;
+    assert_eq!(db.alerts.check_inactivity(db.devices.as_ref(), Utc::now()).await.unwrap(), 2);
+
+    // The second alert is about a point 3 minutes old, under a 10 minute delay
+    let retry = RetryConfig { max_attempts: 1, backoff: std::time::Duration::from_millis(10), timeout: std::time::Duration::from_secs(5) };
+    let hooks = Arc::new(Webhooks::load(db.webhooks.clone(), retry).await);
+    let feed = Arc::new(AlertFeed::new(10, std::time::Duration::from_secs(15)));
+    let delayed = Alerts::load(db.alert_store.clone(), db.geofences.clone(), hooks, feed)
+        .await
+        .with_delay(PublicationDelay::new(Duration::minutes(10)));
+    let shown = delayed.history(&AlertFilter::default(), 10).await.unwrap();
+    assert_eq!(shown.iter().map(|a| a.device_id.as_deref()).collect::<Vec<_>>(), vec![Some("tracker-1")]);
+}
@@ -0,0 +1,102 @@
+//! Native anomaly rules, the verdicts built from them and how they are read from the environment
+
+mod common;
+
+use chrono::{DateTime, Duration, Utc};
+use common::{point, TestDb};
+use indrive::anomaly::rules::{self, AnomalyRule, AzimuthReversal, SpeedSpike, TeleportJump};
+use indrive::anomaly::{AnomalyDetector, PointSample};
+use indrive::database::store::{PointFilter, PointOrder};
+
+fn sample(lat: f64, lng: f64, spd: f64, azm: f64, timestamp: DateTime<Utc>) -> PointSample {
+    PointSample { lat, lng, spd, azm, timestamp, prev_distance_m: None, prev_interval_s: None }
+}
+
+#[test]
+fn speed_spike_compares_consecutive_speeds() {
+    let rule = SpeedSpike { max_delta: 15.0 };
+    let now = Utc::now();
+    let previous = sample(50.0, 70.0, 10.0, 0.0, now);
+    assert!(!rule.check(&previous, &sample(50.0, 70.0, 25.0, 0.0, now)));
+    assert!(rule.check(&previous, &sample(50.0, 70.0, 26.0, 0.0, now)));
+    // Braking counts the same as speeding up
+    assert!(SpeedSpike { max_delta: 5.0 }.check(&previous, &sample(50.0, 70.0, 0.0, 0.0, now)));
+}
+
+#[test]
+fn teleport_uses_stored_deltas_before_coordinates() {
+    let rule = TeleportJump { max_implied_speed: 70.0 };
+    let now = Utc::now();
+    let previous = sample(50.0, 70.0, 10.0, 0.0, now);
+    // About 1.1 km in 10 s is 111 m/s
+    let jump = sample(50.01, 70.0, 10.0, 0.0, now + Duration::seconds(10));
+    assert!(rule.check(&previous, &jump));
+    // The same fix a minute later is 18.5 m/s
+    assert!(!rule.check(&previous, &sample(50.01, 70.0, 10.0, 0.0, now + Duration::seconds(60))));
+    // Stored deltas win over the coordinates
+    let stored = PointSample { prev_distance_m: Some(100.0), prev_interval_s: Some(10.0), ..jump };
+    assert!(!rule.check(&previous, &stored));
+    // Simultaneous fixes count as one second apart
+    assert!((rule.ratio(&previous, &sample(50.0, 70.0007, 10.0, 0.0, now)) - 50.0 / 70.0).abs() < 0.05);
+}
+
+#[test]
+fn azimuth_reversal_needs_speed_and_a_wide_turn() {
+    let rule = AzimuthReversal { min_turn_deg: 150.0, min_speed: 2.0 };
+    let now = Utc::now();
+    let previous = sample(50.0, 70.0, 10.0, 350.0, now);
+    // 350° to 170° is a 180° turn across north
+    assert!(rule.check(&previous, &sample(50.0, 70.0, 10.0, 170.0, now)));
+    assert!(rule.check(&previous, &sample(50.0, 70.0, 10.0, 140.0, now)));
+    assert!(!rule.check(&previous, &sample(50.0, 70.0, 10.0, 100.0, now)));
+    // A parked vehicle spins freely
+    assert!(!rule.check(&previous, &sample(50.0, 70.0, 1.0, 170.0, now)));
+}
+
+#[actix_web::test]
+async fn verdicts_are_scored_by_the_rule_furthest_past_its_threshold() {
+    let db = TestDb::new().await;
+    db.seed(vec![point(1, 50.0, 70.0, 10.0, "2025-01-06T08:00:00Z")]).await;
+    let previous = db.store.find(&PointFilter::default(), PointOrder::IdAsc, None).await.unwrap();
+    let detector = AnomalyDetector::native(rules::default_rules());
+    let at = "2025-01-06T08:00:10Z".parse::<DateTime<Utc>>().unwrap();
+
+    // 45 m/s faster than the point before: three times the allowed change
+    let spike = detector.classify(db.store.as_ref(), 1, previous[0].id + 1, &sample(50.0, 70.0, 55.0, 0.0, at)).await.unwrap();
+    assert!(spike.anomaly);
+    assert_eq!(spike.reason, Some("speed_spike"));
+    assert!((spike.score - 0.75).abs() < 1e-9);
+
+    let calm = detector.classify(db.store.as_ref(), 1, previous[0].id + 1, &sample(50.0, 70.0005, 11.0, 0.0, at)).await.unwrap();
+    assert!(!calm.anomaly);
+    assert_eq!(calm.reason, None);
+    assert!(calm.score < 0.5);
+
+    // The first point of a trip has nothing to compare with
+    assert!(detector.classify(db.store.as_ref(), 2, previous[0].id + 1, &sample(50.0, 70.0, 55.0, 0.0, at)).await.is_none());
+}
+
+/// The only test in this binary reading the anomaly settings from the environment
+#[test]
+fn invalid_rule_settings_are_errors() {
+    let set = |key: &str, value: &str| unsafe { std::env::set_var(key, value) };
+    let clear = |key: &str| unsafe { std::env::remove_var(key) };
+    let names = |rules: Vec<Box<dyn AnomalyRule>>| rules.iter().map(|r| r.name()).collect::<Vec<_>>();
+
+    assert_eq!(names(rules::rules_from_env().unwrap()), ["speed_spike", "teleport", "azimuth_reversal"]);
+    set("ANOMALY_RULES", "teleport, speed_spike");
+    assert_eq!(names(rules::rules_from_env().unwrap()), ["teleport", "speed_spike"]);
+
+    set("ANOMALY_RULES", "teleport,warp");
+    assert!(rules::rules_from_env().err().unwrap().contains("warp"));
+    set("ANOMALY_RULES", " , ");
+    assert!(rules::rules_from_env().is_err());
+
+    set("ANOMALY_RULES", "teleport");
+    set("ANOMALY_MAX_IMPLIED_SPEED", "fast");
+    assert!(rules::rules_from_env().err().unwrap().contains("ANOMALY_MAX_IMPLIED_SPEED"));
+    set("ANOMALY_MAX_IMPLIED_SPEED", "-5");
+    assert!(rules::rules_from_env().is_err());
+    clear("ANOMALY_MAX_IMPLIED_SPEED");
+    clear("ANOMALY_RULES");
+}
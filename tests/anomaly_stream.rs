@@ -0,0 +1,84 @@
+//! `GET /api/anomalies/stream` sends anomalous trips as Server-Sent Events and resumes after
+//! `Last-Event-ID`
+
+use actix_web::body::MessageBody;
+use actix_web::{test, web, App};
+use chrono::Utc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use indrive::anomaly::feed::TripBounds;
+use indrive::anomaly::{AnomalousTrip, AnomalyFeed};
+use indrive::api;
+use indrive::database::store::PublicationDelay;
+
+fn trip(point_id: i64, randomized_id: i64) -> AnomalousTrip {
+    AnomalousTrip {
+        point_id,
+        randomized_id,
+        tenant_id: None,
+        score: 0.9,
+        reason: Some("teleport".to_string()),
+        bbox: TripBounds { lat_min: 43.2, lng_min: 76.9, lat_max: 44.2, lng_max: 76.9 },
+        start: Some(Utc::now()),
+        end: Some(Utc::now()),
+        points: 2,
+    }
+}
+
+/// Body chunks until one carries an event
+async fn next_event(body: &mut (impl MessageBody + Unpin)) -> String {
+    loop {
+        let chunk = tokio::time::timeout(Duration::from_secs(5), std::future::poll_fn(|cx| std::pin::Pin::new(&mut *body).poll_next(cx)))
+            .await
+            .expect("an event in time")
+            .expect("the stream stays open")
+            .unwrap_or_else(|_| panic!("stream failed"));
+        let text = String::from_utf8(chunk.to_vec()).unwrap();
+        if !text.starts_with(':') {
+            return text;
+        }
+    }
+}
+
+#[actix_web::test]
+async fn stream_resumes_after_the_last_event() {
+    let feed = Arc::new(AnomalyFeed::new(10, Duration::from_secs(60)));
+    feed.publish(trip(10, 1));
+    feed.publish(trip(20, 2));
+    let app = test::init_service(
+        App::new().app_data(web::Data::from(feed.clone())).service(web::scope("/api").configure(api::configure)),
+    )
+    .await;
+
+    let req = test::TestRequest::get().uri("/api/anomalies/stream").insert_header(("Last-Event-ID", "10")).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("content-type").unwrap(), "text/event-stream");
+    let mut body = resp.into_body();
+    let first = next_event(&mut body).await;
+    assert!(first.starts_with("id: 20\ndata: {"), "{}", first);
+    assert!(first.contains(r#""randomizedId":2"#), "{}", first);
+
+    feed.publish(trip(30, 3));
+    let live = next_event(&mut body).await;
+    assert!(live.starts_with("id: 30\n"), "{}", live);
+}
+
+#[actix_web::test]
+async fn publication_delay_holds_events_back() {
+    let feed = AnomalyFeed::new(10, Duration::from_secs(60)).with_delay(PublicationDelay::new(chrono::Duration::minutes(10)));
+    let now = Utc::now();
+    let mut early = trip(10, 1);
+    early.end = Some(now - chrono::Duration::minutes(15));
+    let mut late = trip(20, 2);
+    late.end = Some(now - chrono::Duration::minutes(5));
+    feed.publish(early);
+    feed.publish(late);
+
+    let ids = |feed: &AnomalyFeed| feed.since(None).iter().map(|t| t.point_id).collect::<Vec<_>>();
+    assert_eq!(ids(&feed), vec![10]);
+    assert_eq!(feed.release(now + chrono::Duration::minutes(4)), 0);
+    assert_eq!(feed.release(now + chrono::Duration::minutes(6)), 1);
+    assert_eq!(ids(&feed), vec![10, 20]);
+}
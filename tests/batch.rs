@@ -0,0 +1,88 @@
+//! Batched ingestion: requests sharing one bulk insert each get their own rows back
+
+mod common;
+
+use common::{point, TestDb};
+use indrive::database::batch::IngestBatcher;
+use indrive::database::model::points::Model as PointModel;
+use indrive::database::store::{
+    AnomalyVerdict, NewPointRecord, PointFilter, PointOrder, PointStore, StoreResult, DayWindow, TileGrid, TileSpeeds, TimeBucket, TimelineRow,
+};
+use sea_orm::prelude::async_trait;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Returns bulk-inserted rows in reverse, as RETURNING is free to
+struct Reversed(Arc<dyn PointStore>);
+
+#[async_trait::async_trait]
+impl PointStore for Reversed {
+    fn name(&self) -> &'static str { self.0.name() }
+
+    async fn insert(&self, point: NewPointRecord) -> StoreResult<PointModel> {
+        self.0.insert(point).await
+    }
+
+    async fn insert_many(&self, points: Vec<NewPointRecord>) -> StoreResult<Vec<PointModel>> {
+        let mut stored = self.0.insert_many(points).await?;
+        stored.reverse();
+        Ok(stored)
+    }
+
+    async fn find_page(&self, filter: &PointFilter, order: PointOrder, limit: Option<u64>, offset: u64) -> StoreResult<Vec<PointModel>> {
+        self.0.find_page(filter, order, limit, offset).await
+    }
+
+    async fn find_after(&self, filter: &PointFilter, after_id: i64, limit: u64) -> StoreResult<Vec<PointModel>> {
+        self.0.find_after(filter, after_id, limit).await
+    }
+
+    async fn count(&self, filter: &PointFilter) -> StoreResult<u64> {
+        self.0.count(filter).await
+    }
+
+    async fn timeline(&self, filter: &PointFilter, bucket: TimeBucket) -> StoreResult<Vec<TimelineRow>> {
+        self.0.timeline(filter, bucket).await
+    }
+
+    async fn tile_speeds(&self, filter: &PointFilter, grid: TileGrid, window: DayWindow) -> StoreResult<Vec<TileSpeeds>> {
+        self.0.tile_speeds(filter, grid, window).await
+    }
+
+    async fn set_anomaly(&self, id: i64, verdict: AnomalyVerdict) -> StoreResult<bool> {
+        self.0.set_anomaly(id, verdict).await
+    }
+
+    async fn review_trip(&self, randomized_id: i64, anomaly: bool, reviewed_by: &str) -> StoreResult<u64> {
+        self.0.review_trip(randomized_id, anomaly, reviewed_by).await
+    }
+
+    async fn delete(&self, filter: &PointFilter) -> StoreResult<u64> {
+        self.0.delete(filter).await
+    }
+}
+
+#[actix_web::test]
+async fn concurrent_requests_get_their_own_rows() {
+    let db = TestDb::new().await.with_ingestion().await;
+    let queue = db.ingestion.as_ref().unwrap().queue.clone();
+    let store: Arc<dyn PointStore> = Arc::new(Reversed(db.store.clone()));
+    // Large enough that every request below lands in the same flush
+    let batcher = Arc::new(IngestBatcher::new(store, queue, 1000, Duration::from_millis(200), 1000));
+
+    let requests = (1..=8).map(|trip| {
+        let batcher = batcher.clone();
+        tokio::spawn(async move {
+            let records = (0..5).map(|i| point(trip, 50.0 + i as f64 / 100.0, 70.0, 10.0, &format!("2025-01-06T08:0{}:00Z", i))).collect();
+            (trip, batcher.submit(records, false).await.unwrap())
+        })
+    });
+    for request in requests {
+        let (trip, stored) = request.await.unwrap();
+        assert_eq!(stored.len(), 5);
+        for (i, (model, _)) in stored.iter().enumerate() {
+            assert_eq!(model.randomized_id, trip);
+            assert_eq!(model.lat, 50.0 + i as f64 / 100.0);
+        }
+    }
+}
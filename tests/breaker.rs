@@ -0,0 +1,89 @@
+//! Calls through the circuit breaker: reads are retried after a dropped connection, writes
+//! that may have committed are not
+
+mod common;
+
+use common::{point, TestDb};
+use indrive::database::model::points::Model as PointModel;
+use indrive::database::store::{
+    AnomalyVerdict, CircuitBreaker, DayWindow, GuardedStore, NewPointRecord, PointFilter, PointOrder, PointStore, StoreError, StoreResult, TileGrid, TileSpeeds,
+    TimeBucket, TimelineRow,
+};
+use sea_orm::prelude::async_trait;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+/// Runs every count and delete, then loses the reply of the first one of each
+struct LostReplies {
+    inner: Arc<dyn PointStore>,
+    counts: AtomicU32,
+    deletes: AtomicU32,
+}
+
+impl LostReplies {
+    fn lose_first<T>(calls: &AtomicU32, res: StoreResult<T>) -> StoreResult<T> {
+        if calls.fetch_add(1, Ordering::SeqCst) == 0 { Err(StoreError::Unavailable) } else { res }
+    }
+}
+
+#[async_trait::async_trait]
+impl PointStore for LostReplies {
+    fn name(&self) -> &'static str { self.inner.name() }
+
+    async fn insert(&self, point: NewPointRecord) -> StoreResult<PointModel> {
+        self.inner.insert(point).await
+    }
+
+    async fn insert_many(&self, points: Vec<NewPointRecord>) -> StoreResult<Vec<PointModel>> {
+        self.inner.insert_many(points).await
+    }
+
+    async fn find_page(&self, filter: &PointFilter, order: PointOrder, limit: Option<u64>, offset: u64) -> StoreResult<Vec<PointModel>> {
+        self.inner.find_page(filter, order, limit, offset).await
+    }
+
+    async fn find_after(&self, filter: &PointFilter, after_id: i64, limit: u64) -> StoreResult<Vec<PointModel>> {
+        self.inner.find_after(filter, after_id, limit).await
+    }
+
+    async fn count(&self, filter: &PointFilter) -> StoreResult<u64> {
+        Self::lose_first(&self.counts, self.inner.count(filter).await)
+    }
+
+    async fn timeline(&self, filter: &PointFilter, bucket: TimeBucket) -> StoreResult<Vec<TimelineRow>> {
+        self.inner.timeline(filter, bucket).await
+    }
+
+    async fn tile_speeds(&self, filter: &PointFilter, grid: TileGrid, window: DayWindow) -> StoreResult<Vec<TileSpeeds>> {
+        self.inner.tile_speeds(filter, grid, window).await
+    }
+
+    async fn set_anomaly(&self, id: i64, verdict: AnomalyVerdict) -> StoreResult<bool> {
+        self.inner.set_anomaly(id, verdict).await
+    }
+
+    async fn review_trip(&self, randomized_id: i64, anomaly: bool, reviewed_by: &str) -> StoreResult<u64> {
+        self.inner.review_trip(randomized_id, anomaly, reviewed_by).await
+    }
+
+    async fn delete(&self, filter: &PointFilter) -> StoreResult<u64> {
+        Self::lose_first(&self.deletes, self.inner.delete(filter).await)
+    }
+}
+
+#[actix_web::test]
+async fn reads_are_retried_but_deletes_are_not() {
+    let db = TestDb::new().await;
+    db.seed(vec![point(1, 50.0, 70.0, 10.0, "2025-01-06T08:00:00Z"), point(2, 50.0, 70.0, 10.0, "2025-01-06T08:00:00Z")]).await;
+    let flaky = Arc::new(LostReplies { inner: db.store.clone(), counts: AtomicU32::new(0), deletes: AtomicU32::new(0) });
+    let store = GuardedStore::new(flaky.clone(), Arc::new(CircuitBreaker::from_env()), 2);
+
+    assert_eq!(store.count(&PointFilter::default()).await.unwrap(), 2);
+    assert_eq!(flaky.counts.load(Ordering::SeqCst), 2);
+
+    // A retry would find the committed delete's rows gone and answer 0
+    let trip = PointFilter { randomized_id: Some(1), ..Default::default() };
+    assert!(matches!(store.delete(&trip).await, Err(StoreError::Unavailable)));
+    assert_eq!(flaky.deletes.load(Ordering::SeqCst), 1);
+    assert_eq!(db.store.count(&PointFilter::default()).await.unwrap(), 1);
+}
@@ -0,0 +1,430 @@
+//! Shared setup of the integration tests: a migrated in-memory SQLite database per test and
+//! the `/api` routes mounted on it, the way `main.rs` mounts them
+
+#![allow(dead_code)]
+
+use actix_web::{test, web, App};
+use chrono::{DateTime, Utc};
+use sea_orm::Database;
+use sea_orm_migration::MigratorTrait;
+use serde_json::Value;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use indrive::alerts::{AlertFeed, Alerts};
+use indrive::anomaly::{AnomalyDetector, AnomalyFeed, ClassificationQueue};
+use indrive::api;
+use indrive::api::admin::AdminConfig;
+use indrive::api::points::Ingestion;
+use indrive::database::journal::Journal;
+use indrive::database::wal::Wal;
+use indrive::database::store::{AlertStore, DeviceStore, GeocodeCacheStore, GeofenceStore, NewPointRecord, PointStore, Quarantine, QuarantineStore, RoadSegmentStore, SeaOrmPointStore, TenantScoped, TileStatsStore, TimestampWindow, TripStore, WebhookStore};
+use indrive::exports::{ExportConfig, Exporter};
+use indrive::geocoding::{Geocoder, GeocoderConfig};
+use indrive::geofences::Geofences;
+use indrive::jobs::{Jobs, JobsConfig};
+use indrive::migration::Migrator;
+use indrive::reports::ReportConfig;
+use indrive::webhooks::{RetryConfig, Webhooks};
+use std::time::Duration;
+
+/// ADMIN_TOKEN of the test server
+pub const ADMIN_TOKEN: &str = "test-admin-token";
+/// REVIEWER_TOKENS entry of the test server, for the reviewer `alice`
+pub const REVIEWER_TOKEN: &str = "test-reviewer-token";
+
+pub struct TestDb {
+    pub store: Arc<dyn PointStore>,
+    pub trips: Arc<dyn TripStore>,
+    pub devices: Arc<dyn DeviceStore>,
+    pub tile_stats: Arc<dyn TileStatsStore>,
+    pub webhooks: Arc<dyn WebhookStore>,
+    pub geocode_cache: Arc<dyn GeocodeCacheStore>,
+    pub quarantined_points: Arc<dyn QuarantineStore>,
+    pub road_segments: Arc<dyn RoadSegmentStore>,
+    pub alert_store: Arc<dyn AlertStore>,
+    pub geofence_store: Arc<dyn GeofenceStore>,
+    pub jobs: Jobs,
+    pub geofences: Arc<Geofences>,
+    pub alerts: Arc<Alerts>,
+    pub alert_feed: Arc<AlertFeed>,
+    /// Registered for the handlers when set, like GEOCODER_URL does
+    pub geocoder: Option<Arc<Geocoder>>,
+    /// Registered for the handlers when set, like REPORTS_DIR does
+    pub reports: Option<ReportConfig>,
+    /// Registered for the handlers when set, like EXPORT_S3_BUCKET does
+    pub exporter: Option<Exporter>,
+    /// Registered for the handlers when set, for `POST /api/points` and the upload and import
+    /// endpoints
+    pub ingestion: Option<Ingestion>,
+}
+
+impl TestDb {
+    /// Every call gets a database of its own
+    pub async fn new() -> Self {
+        let db = Database::connect("sqlite::memory:").await.expect("in-memory SQLite");
+        Migrator::up(&db, None).await.expect("migrations");
+        let store = SeaOrmPointStore::new(db);
+        let geofences = Arc::new(Geofences::load(Arc::new(store.clone())).await);
+        let retry = RetryConfig { max_attempts: 1, backoff: Duration::from_millis(10), timeout: Duration::from_secs(5) };
+        let hooks = Arc::new(Webhooks::load(Arc::new(store.clone()), retry).await);
+        let alert_feed = Arc::new(AlertFeed::new(10, Duration::from_secs(15)));
+        let alerts = Arc::new(Alerts::load(Arc::new(store.clone()), geofences.clone(), hooks, alert_feed.clone()).await);
+        Self {
+            store: Arc::new(store.clone()),
+            trips: Arc::new(store.clone()),
+            devices: Arc::new(store.clone()),
+            tile_stats: Arc::new(store.clone()),
+            webhooks: Arc::new(store.clone()),
+            geocode_cache: Arc::new(store.clone()),
+            quarantined_points: Arc::new(store.clone()),
+            road_segments: Arc::new(store.clone()),
+            alert_store: Arc::new(store.clone()),
+            geofence_store: Arc::new(store.clone()),
+            geofences,
+            alerts,
+            alert_feed,
+            jobs: Jobs::new(JobsConfig { max_running: 2 }, Arc::new(store)).await,
+            geocoder: None,
+            reports: None,
+            exporter: None,
+            ingestion: None,
+        }
+    }
+
+    /// Names places through the geocoder `config` points at
+    pub fn with_geocoder(mut self, config: GeocoderConfig) -> Self {
+        self.geocoder = Some(Arc::new(Geocoder::new(config, self.geocode_cache.clone())));
+        self
+    }
+
+    /// Serves the reports of `config.dir`
+    pub fn with_reports(mut self, config: ReportConfig) -> Self {
+        self.reports = Some(config);
+        self
+    }
+
+    /// Exports to the bucket `config.s3` points at
+    pub async fn with_exporter(mut self, config: ExportConfig) -> Self {
+        self.exporter = Some(Exporter::new(config, self.store.clone(), self.jobs.clone()));
+        self
+    }
+
+    /// Classifies and stores ingested points, with no timestamp window and no webhooks
+    pub async fn with_ingestion(mut self) -> Self {
+        let retry = RetryConfig { max_attempts: 1, backoff: Duration::from_millis(10), timeout: Duration::from_secs(5) };
+        let hooks = Arc::new(Webhooks::load(self.webhooks.clone(), retry).await);
+        let detector = Arc::new(AnomalyDetector::from_env(hooks.clone()).expect("anomaly detector"));
+        let feed = Arc::new(AnomalyFeed::new(10, Duration::from_secs(15)));
+        let queue = ClassificationQueue::spawn(self.store.clone(), detector, hooks, feed, self.geofences.clone(), self.alerts.clone());
+        let window = TimestampWindow::from_env().expect("timestamp window");
+        let quarantine = Quarantine::new(window, self.quarantined_points.clone());
+        self.ingestion = Some(Ingestion {
+            store: self.store.clone(),
+            queue,
+            wal: None,
+            journal: None,
+            batcher: None,
+            quarantine: Arc::new(quarantine),
+        });
+        self
+    }
+
+    /// Buffers ingestion in a WAL at `path`, like INGEST_WAL_PATH does; after `with_ingestion`
+    pub fn with_wal(mut self, path: PathBuf) -> Self {
+        let ingestion = self.ingestion.as_mut().expect("with_ingestion first");
+        ingestion.wal = Some(Arc::new(Wal::open(path).expect("WAL")));
+        self
+    }
+
+    /// Journals ingestion in `dir`, like INGEST_JOURNAL_DIR does; after `with_ingestion`
+    pub fn with_journal(mut self, dir: PathBuf) -> Self {
+        let ingestion = self.ingestion.as_mut().expect("with_ingestion first");
+        ingestion.journal = Some(Arc::new(Journal::open(dir).expect("journal")));
+        self
+    }
+
+    /// The stores as `main.rs` hands them to the handlers when tenants are configured
+    pub fn tenant_scoped(self) -> Self {
+        Self {
+            store: Arc::new(TenantScoped::new(self.store)),
+            trips: Arc::new(TenantScoped::new(self.trips)),
+            devices: Arc::new(TenantScoped::new(self.devices)),
+            tile_stats: self.tile_stats,
+            webhooks: self.webhooks,
+            geocode_cache: self.geocode_cache,
+            quarantined_points: self.quarantined_points,
+            road_segments: self.road_segments,
+            alert_store: self.alert_store,
+            geofence_store: self.geofence_store,
+            jobs: self.jobs,
+            geofences: self.geofences,
+            alerts: self.alerts,
+            alert_feed: self.alert_feed,
+            geocoder: self.geocoder,
+            reports: self.reports,
+            exporter: self.exporter,
+            ingestion: self.ingestion,
+        }
+    }
+
+    pub async fn seed(&self, points: Vec<NewPointRecord>) {
+        self.store.insert_many(points).await.expect("seed points");
+    }
+
+    /// GET on the `/api` routes; returns the status and the JSON body
+    pub async fn get(&self, uri: &str) -> (u16, Value) {
+        self.call(test::TestRequest::get().uri(uri)).await
+    }
+
+    /// POST of a JSON body on the `/api` routes
+    pub async fn post(&self, uri: &str, body: Value) -> (u16, Value) {
+        self.call(test::TestRequest::post().uri(uri).set_json(body)).await
+    }
+
+    /// PUT of a JSON body on the `/api` routes
+    pub async fn put(&self, uri: &str, body: Value) -> (u16, Value) {
+        self.call(test::TestRequest::put().uri(uri).set_json(body)).await
+    }
+
+    pub async fn delete(&self, uri: &str) -> (u16, Value) {
+        self.call(test::TestRequest::delete().uri(uri)).await
+    }
+
+    /// PATCH of a JSON body, with `token` as the Bearer token when given
+    pub async fn patch_as(&self, uri: &str, token: Option<&str>, body: Value) -> (u16, Value) {
+        let mut req = test::TestRequest::patch().uri(uri).set_json(body);
+        if let Some(token) = token {
+            req = req.insert_header(("authorization", format!("Bearer {}", token)));
+        }
+        self.call(req).await
+    }
+
+    /// POST with the admin token
+    pub async fn post_admin(&self, uri: &str, body: Value) -> (u16, Value) {
+        self.call(admin(test::TestRequest::post().uri(uri).set_json(body))).await
+    }
+
+    /// PUT with the admin token
+    pub async fn put_admin(&self, uri: &str, body: Value) -> (u16, Value) {
+        self.call(admin(test::TestRequest::put().uri(uri).set_json(body))).await
+    }
+
+    /// DELETE with the admin token
+    pub async fn delete_admin(&self, uri: &str) -> (u16, Value) {
+        self.call(admin(test::TestRequest::delete().uri(uri))).await
+    }
+
+    /// POST of a raw body, such as a track file
+    pub async fn post_raw(&self, uri: &str, content_type: &str, body: &str) -> (u16, Value) {
+        self.call(test::TestRequest::post().uri(uri).insert_header(("content-type", content_type)).set_payload(body.to_string())).await
+    }
+
+    /// GET returning the raw body, for responses that are not JSON
+    pub async fn get_bytes(&self, uri: &str) -> (u16, Vec<u8>) {
+        self.send(test::TestRequest::get().uri(uri)).await
+    }
+
+    /// Stores the points of a trip one by one, each classified, checked against the geofences and
+    /// the alert rules before the next
+    pub async fn drive(&self, trip: i64, points: &[(f64, f64, f64, &str)]) {
+        let queue = &self.ingestion.as_ref().expect("ingestion").queue;
+        for &(lat, lng, spd, ts) in points {
+            let (_, decided) = queue.ingest_watched(self.store.as_ref(), point(trip, lat, lng, spd, ts)).await.unwrap();
+            decided.await.unwrap();
+        }
+    }
+
+    /// Polls a job at `uri` until it is no longer queued or running
+    pub async fn finished(&self, uri: &str) -> Value {
+        for _ in 0..200 {
+            let (status, job) = self.get(uri).await;
+            assert_eq!(status, 200, "{}", job);
+            if job["status"] != "queued" && job["status"] != "running" {
+                return job;
+            }
+            tokio::time::sleep(Duration::from_millis(25)).await;
+        }
+        panic!("{} did not finish", uri);
+    }
+
+    async fn call(&self, req: test::TestRequest) -> (u16, Value) {
+        let (status, body) = self.send(req).await;
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    async fn send(&self, req: test::TestRequest) -> (u16, Vec<u8>) {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::from(self.store.clone()))
+                .app_data(web::Data::from(self.trips.clone()))
+                .app_data(web::Data::from(self.devices.clone()))
+                .app_data(web::Data::from(self.road_segments.clone()))
+                .app_data(web::Data::from(self.tile_stats.clone()))
+                .app_data(web::Data::new(self.jobs.clone()))
+                .app_data(web::Data::new(AdminConfig::new(Some(ADMIN_TOKEN.to_string())).with_reviewer(REVIEWER_TOKEN, "alice")))
+                .app_data(web::Data::from(self.geofences.clone()))
+                .app_data(web::Data::from(self.alerts.clone()))
+                .app_data(web::Data::from(self.alert_feed.clone()))
+                .app_data(web::Data::new(indrive::api::tile_metrics::registry()))
+                .configure(|cfg| {
+                    if let Some(geocoder) = &self.geocoder { cfg.app_data(web::Data::from(geocoder.clone())); }
+                    if let Some(reports) = &self.reports { cfg.app_data(web::Data::new(reports.clone())); }
+                    if let Some(exporter) = &self.exporter { cfg.app_data(web::Data::new(exporter.clone())); }
+                    if let Some(ingestion) = &self.ingestion {
+                        cfg.app_data(web::Data::new(ingestion.clone()))
+                            .app_data(web::Data::new(ingestion.queue.clone()))
+                            .app_data(web::Data::from(ingestion.quarantine.clone()));
+                    }
+                })
+                .service(web::scope("/api").configure(api::configure)),
+        )
+        .await;
+        let resp = test::call_service(&app, req.to_request()).await;
+        let status = resp.status().as_u16();
+        (status, test::read_body(resp).await.to_vec())
+    }
+}
+
+fn admin(req: test::TestRequest) -> test::TestRequest {
+    req.insert_header(("authorization", format!("Bearer {}", ADMIN_TOKEN)))
+}
+
+pub fn point(trip: i64, lat: f64, lng: f64, spd: f64, timestamp: &str) -> NewPointRecord {
+    NewPointRecord {
+        randomized_id: trip,
+        lat,
+        lng,
+        alt: 0.0,
+        spd,
+        azm: 0.0,
+        timestamp: Some(timestamp.parse::<DateTime<Utc>>().expect("RFC 3339 timestamp")),
+        anomaly: Some(false),
+        client_uuid: None,
+        device_id: None,
+        tenant_id: None,
+    }
+}
+
+pub fn anomalous(mut p: NewPointRecord) -> NewPointRecord {
+    p.anomaly = Some(true);
+    p
+}
+
+/// The tile of a map response whose top-left corner is (lat, lng)
+pub fn tile(data: &Value, lat: f64, lng: f64) -> &Value {
+    data.as_array()
+        .expect("tile array")
+        .iter()
+        .find(|t| {
+            let corner = &t["topLeft"];
+            (corner["lat"].as_f64().unwrap() - lat).abs() < 1e-9 && (corner["lng"].as_f64().unwrap() - lng).abs() < 1e-9
+        })
+        .unwrap_or_else(|| panic!("no tile at ({}, {}) in {}", lat, lng, data))
+}
+
+/// One answer of a `MockServer`: status, extra headers and body
+#[derive(Debug, Clone)]
+pub struct MockAnswer {
+    pub status: u16,
+    pub headers: Vec<(&'static str, &'static str)>,
+    pub body: String,
+}
+
+impl MockAnswer {
+    pub fn new(status: u16, body: impl Into<String>) -> Self {
+        Self { status, headers: Vec::new(), body: body.into() }
+    }
+
+    pub fn header(mut self, name: &'static str, value: &'static str) -> Self {
+        self.headers.push((name, value));
+        self
+    }
+}
+
+/// A request a `MockServer` got
+#[derive(Debug, Clone)]
+pub struct MockRequest {
+    pub method: String,
+    /// Path with the query string
+    pub path: String,
+    /// Request line and headers as sent
+    pub head: String,
+    pub body: Vec<u8>,
+}
+
+impl MockRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.head.lines().skip(1).find_map(|l| {
+            let (n, v) = l.split_once(':')?;
+            n.trim().eq_ignore_ascii_case(name).then(|| v.trim())
+        })
+    }
+}
+
+/// A local HTTP server standing in for the services the app calls out to (webhook receivers,
+/// the geocoder, the export bucket). Request n gets `answers[n]`, every later one the last
+/// answer; each request is recorded.
+pub struct MockServer {
+    /// `http://127.0.0.1:port`, without a trailing slash
+    pub url: String,
+    requests: Arc<std::sync::Mutex<Vec<MockRequest>>>,
+}
+
+impl MockServer {
+    pub async fn start(answers: Vec<MockAnswer>) -> Self {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        assert!(!answers.is_empty(), "a mock server needs at least one answer");
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = requests.clone();
+        tokio::spawn(async move {
+            for n in 0.. {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buf = Vec::new();
+                let mut chunk = [0u8; 4096];
+                let head_end = loop {
+                    if let Some(at) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                        break Some(at + 4);
+                    }
+                    match socket.read(&mut chunk).await {
+                        Ok(0) | Err(_) => break None,
+                        Ok(read) => buf.extend_from_slice(&chunk[..read]),
+                    }
+                };
+                let Some(head_end) = head_end else { continue };
+                let head = String::from_utf8_lossy(&buf[..head_end - 4]).to_string();
+                let mut request = MockRequest { method: String::new(), path: String::new(), head, body: Vec::new() };
+                let length: usize = request.header("content-length").map_or(0, |v| v.parse().unwrap());
+                while buf.len() < head_end + length {
+                    match socket.read(&mut chunk).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(read) => buf.extend_from_slice(&chunk[..read]),
+                    }
+                }
+                let mut line = request.head.lines().next().unwrap_or_default().split(' ');
+                request.method = line.next().unwrap_or_default().to_string();
+                request.path = line.next().unwrap_or_default().to_string();
+                request.body = buf[head_end..].to_vec();
+                log.lock().unwrap().push(request);
+
+                let answer = &answers[n.min(answers.len() - 1)];
+                let mut resp = format!("HTTP/1.1 {} X\r\ncontent-length: {}\r\nconnection: close\r\n", answer.status, answer.body.len());
+                for (name, value) in &answer.headers {
+                    resp.push_str(&format!("{}: {}\r\n", name, value));
+                }
+                resp.push_str("\r\n");
+                resp.push_str(&answer.body);
+                let _ = socket.write_all(resp.as_bytes()).await;
+            }
+        });
+        Self { url, requests }
+    }
+
+    /// Every request so far, in arrival order
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
@@ -0,0 +1,84 @@
+//! Device registry: trips naming a device count towards it
+
+mod common;
+
+use chrono::{Duration, Utc};
+use common::{point, TestDb};
+use indrive::database::store::{DeviceFilter, DeviceStore, Embargoed, PublicationDelay};
+use serde_json::json;
+
+fn on_device(device: &str, mut p: indrive::database::store::NewPointRecord) -> indrive::database::store::NewPointRecord {
+    p.device_id = Some(device.to_string());
+    p
+}
+
+#[actix_web::test]
+async fn devices_sum_their_trips() {
+    let db = TestDb::new().await;
+    let (status, body) = db.post_admin("/api/devices", json!({"deviceId": "tracker-1", "name": "Van 1"})).await;
+    assert_eq!(status, 201, "{}", body);
+    assert_eq!(body["trips"], 0);
+    assert!(body["lastSeen"].is_null());
+    let (status, _) = db.post_admin("/api/devices", json!({"deviceId": "idle"})).await;
+    assert_eq!(status, 201);
+
+    let recent = (Utc::now() - Duration::minutes(5)).to_rfc3339();
+    db.seed(vec![
+        on_device("tracker-1", point(1, 50.0, 70.0, 10.0, "2025-01-06T08:00:00Z")),
+        point(1, 50.0, 70.01, 10.0, "2025-01-06T08:01:00Z"),
+        on_device("tracker-1", point(2, 50.0, 70.0, 10.0, &recent)),
+        // Trip 3 registers a device nobody announced
+        on_device("tracker-2", point(3, 51.0, 71.0, 5.0, "2025-01-07T09:00:00Z")),
+        point(4, 51.0, 71.0, 5.0, "2025-01-07T09:00:00Z"),
+    ])
+    .await;
+
+    let (status, body) = db.get("/api/devices/tracker-1").await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["name"], "Van 1");
+    assert_eq!(body["trips"], 2);
+    assert_eq!(body["totalPoints"], 3);
+    let distance = body["totalDistance"].as_f64().unwrap();
+    assert!((distance - 715.0).abs() < 5.0, "{}", distance);
+
+    let (_, body) = db.get("/api/devices").await;
+    assert_eq!(body["total"], 3, "{}", body);
+    let ids: Vec<&str> = body["devices"].as_array().unwrap().iter().map(|d| d["deviceId"].as_str().unwrap()).collect();
+    assert_eq!(ids, ["idle", "tracker-1", "tracker-2"]);
+
+    // tracker-1 reported five minutes ago; the others are silent
+    let (_, body) = db.get("/api/devices?silentFor=3600").await;
+    let ids: Vec<&str> = body["devices"].as_array().unwrap().iter().map(|d| d["deviceId"].as_str().unwrap()).collect();
+    assert_eq!(ids, ["idle", "tracker-2"], "{}", body);
+
+    let (_, body) = db.get("/api/trips?deviceId=tracker-1").await;
+    assert_eq!(body["total"], 2, "{}", body);
+
+    let (status, body) = db.post_admin("/api/devices", json!({"deviceId": "tracker-1", "name": "Van 7"})).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["name"], "Van 7");
+    assert_eq!(db.get("/api/devices/nope").await.0, 404);
+    assert_eq!(db.post_admin("/api/devices", json!({"deviceId": " "})).await.0, 400);
+    assert_eq!(db.post("/api/devices", json!({"deviceId": "tracker-9"})).await.0, 401);
+}
+
+#[actix_web::test]
+async fn unpublished_trips_do_not_count() {
+    let db = TestDb::new().await;
+    let recent = (Utc::now() - Duration::minutes(5)).to_rfc3339();
+    db.seed(vec![
+        on_device("tracker-1", point(1, 50.0, 70.0, 10.0, "2025-01-06T08:00:00Z")),
+        on_device("tracker-1", point(2, 50.0, 70.0, 10.0, &recent)),
+    ])
+    .await;
+
+    let delayed = Embargoed::new(db.devices.clone(), PublicationDelay::new(Duration::minutes(10)));
+    let filter = DeviceFilter { device_id: Some("tracker-1".to_string()), ..Default::default() };
+    let device = delayed.find_devices(&filter, 1, 0).await.unwrap().remove(0);
+    assert_eq!(device.trips, 1);
+    assert_eq!(device.last_seen.unwrap().to_rfc3339(), "2025-01-06T08:00:00+00:00");
+    // Trip 2 is still unpublished, so the device looks silent
+    let filter = DeviceFilter { silent_since: Some(Utc::now() - Duration::minutes(30)), ..Default::default() };
+    assert_eq!(delayed.count_devices(&filter).await.unwrap(), 1);
+    assert_eq!(db.devices.count_devices(&filter).await.unwrap(), 0);
+}
@@ -0,0 +1,73 @@
+//! `geocode=true` names anomaly routes through the reverse geocoder, asking it about each place
+//! once and reading `geocode_cache` afterwards
+
+mod common;
+
+use common::{anomalous, point, MockAnswer, MockServer, TestDb};
+use indrive::geocoding::{GeocoderConfig, Provider};
+use std::time::Duration;
+
+const ANSWER: &str = r#"{"display_name":"x","address":{"road":"проспект Абая","city_district":"Алмалинский район","city":"Алматы","country":"Казахстан"}}"#;
+
+/// A Nominatim answering every request with `status` and `ANSWER`; returns its config and the
+/// server, which records the requests
+async fn nominatim(status: u16) -> (GeocoderConfig, MockServer) {
+    let server = MockServer::start(vec![MockAnswer::new(status, ANSWER).header("content-type", "application/json")]).await;
+    let config = GeocoderConfig {
+        url: server.url.clone(),
+        provider: Provider::Nominatim,
+        language: Some("ru".to_string()),
+        min_interval: Duration::ZERO,
+        max_lookups: 20,
+        timeout: Duration::from_secs(5),
+    };
+    (config, server)
+}
+
+async fn seeded(config: GeocoderConfig) -> TestDb {
+    let db = TestDb::new().await.with_geocoder(config);
+    db.seed(vec![
+        point(3, 51.49, 70.49, 5.0, "2025-01-08T09:59:00Z"),
+        anomalous(point(3, 51.5, 70.5, 50.0, "2025-01-08T10:00:00Z")),
+        anomalous(point(3, 51.6, 70.6, 60.0, "2025-01-08T10:01:00Z")),
+    ])
+    .await;
+    db
+}
+
+#[actix_web::test]
+async fn anomaly_routes_are_named_once() {
+    let (config, server) = nominatim(200).await;
+    let db = seeded(config).await;
+    let (status, body) = db.get("/api/anomalies?lat1=52&lng1=70&lat2=50&lng2=72").await;
+    assert_eq!(status, 200, "{}", body);
+    assert!(body["anomalies"][0].get("place").is_none(), "{}", body);
+    assert!(server.requests().is_empty());
+
+    for _ in 0..2 {
+        let (status, body) = db.get("/api/anomalies?lat1=52&lng1=70&lat2=50&lng2=72&mode=trips&geocode=true").await;
+        assert_eq!(status, 200, "{}", body);
+        let place = &body["anomalies"][0]["place"];
+        assert_eq!(place["street"], "проспект Абая", "{}", body);
+        assert_eq!(place["district"], "Алмалинский район");
+        assert_eq!(place["label"], "проспект Абая, Алмалинский район, Алматы");
+    }
+    // Asked about the first anomalous point, not the first of the route, and only once
+    let seen = server.requests();
+    assert_eq!(seen.len(), 1, "{:?}", seen);
+    assert!(seen[0].path.contains("lat=51.5000") && seen[0].path.contains("lon=70.5000"), "{}", seen[0].path);
+    assert!(seen[0].path.contains("accept-language=ru"), "{}", seen[0].path);
+}
+
+#[actix_web::test]
+async fn a_failing_geocoder_leaves_routes_unnamed() {
+    let (config, server) = nominatim(503).await;
+    let db = seeded(config).await;
+    for expected_requests in [1, 2] {
+        let (status, body) = db.get("/api/anomalies?lat1=52&lng1=70&lat2=50&lng2=72&geocode=true").await;
+        assert_eq!(status, 200, "{}", body);
+        assert!(body["anomalies"][0].get("place").is_none(), "{}", body);
+        // Failures are not cached
+        assert_eq!(server.requests().len(), expected_requests);
+    }
+}
@@ -0,0 +1,115 @@
+//! Geofences managed at `/api/geofences`; the classification worker records the trips crossing
+//! their boundaries as entry and exit events
+
+mod common;
+
+use chrono::{DateTime, Utc};
+use common::TestDb;
+use indrive::anomaly::PointSample;
+use indrive::database::store::{GeofenceEventFilter, PublicationDelay};
+use indrive::geofences::Geofences;
+use serde_json::json;
+
+const SQUARE: [(f64, f64); 4] = [(43.0, 76.5), (43.0, 77.0), (43.5, 77.0), (43.5, 76.5)];
+
+fn square() -> serde_json::Value {
+    SQUARE.iter().map(|&(lat, lng)| json!({"lat": lat, "lng": lng})).collect()
+}
+
+fn sample(lat: f64, lng: f64, timestamp: DateTime<Utc>) -> PointSample {
+    PointSample { lat, lng, spd: 10.0, azm: 0.0, timestamp, prev_distance_m: None, prev_interval_s: None }
+}
+
+
+#[actix_web::test]
+async fn trips_crossing_a_geofence_are_recorded() {
+    let db = TestDb::new().await.with_ingestion().await;
+    let square = square();
+    let (status, _) = db.post("/api/geofences", json!({"name": "Depot", "polygon": square})).await;
+    assert_eq!(status, 401);
+    let (status, fence) = db.post_admin("/api/geofences", json!({"name": "Depot", "polygon": square})).await;
+    assert_eq!(status, 201, "{}", fence);
+    assert_eq!(fence["active"], true);
+    assert_eq!(fence["polygon"].as_array().unwrap().len(), 4);
+    let id = fence["id"].as_i64().unwrap();
+
+    let (status, body) = db.post_admin("/api/geofences", json!({"name": "Line", "polygon": [{"lat": 43.0, "lng": 76.5}, {"lat": 43.5, "lng": 77.0}]})).await;
+    assert_eq!(status, 400, "{}", body);
+    assert_eq!(body["details"]["param"], "polygon");
+
+    db.drive(7, &[
+        (42.9, 76.7, 10.0, "2025-01-06T08:00:00Z"),
+        (43.2, 76.7, 10.0, "2025-01-06T08:01:00Z"),
+        (43.3, 76.8, 10.0, "2025-01-06T08:02:00Z"),
+        (43.6, 76.8, 10.0, "2025-01-06T08:03:00Z"),
+    ])
+    .await;
+    db.drive(8, &[(43.1, 76.9, 10.0, "2025-01-06T09:00:00Z")]).await;
+
+    let (status, page) = db.get(&format!("/api/geofences/{}/events", id)).await;
+    assert_eq!(status, 200, "{}", page);
+    let events = page["events"].as_array().unwrap();
+    let kinds: Vec<(i64, &str)> = events.iter().map(|e| (e["randomizedId"].as_i64().unwrap(), e["kind"].as_str().unwrap())).collect();
+    assert_eq!(kinds, vec![(7, "entry"), (7, "exit"), (8, "entry")]);
+    assert_eq!(events[0]["timestamp"], "2025-01-06T08:01:00Z");
+    assert_eq!(page["nextCursor"], serde_json::Value::Null);
+
+    let (_, page) = db.get(&format!("/api/geofences/{}/events?randomizedId=7&limit=1", id)).await;
+    assert_eq!(page["events"][0]["kind"], "entry");
+    let cursor = page["nextCursor"].as_i64().unwrap();
+    let (_, page) = db.get(&format!("/api/geofences/{}/events?randomizedId=7&cursor={}", id, cursor)).await;
+    assert_eq!(page["events"].as_array().unwrap().len(), 1);
+    assert_eq!(page["events"][0]["kind"], "exit");
+
+    let (_, fence) = db.get(&format!("/api/geofences/{}", id)).await;
+    assert_eq!(fence["tripsInside"], 1);
+
+    // An inactive geofence sees nothing leave
+    let (status, fence) = db.put_admin(&format!("/api/geofences/{}", id), json!({"name": "Depot", "polygon": square, "active": false})).await;
+    assert_eq!(status, 200, "{}", fence);
+    db.drive(8, &[(42.0, 76.9, 10.0, "2025-01-06T09:01:00Z")]).await;
+    let (_, page) = db.get(&format!("/api/geofences/{}/events?randomizedId=8", id)).await;
+    assert_eq!(page["events"].as_array().unwrap().len(), 1);
+
+    let (status, _) = db.delete_admin(&format!("/api/geofences/{}", id)).await;
+    assert_eq!(status, 204);
+    let (status, _) = db.get(&format!("/api/geofences/{}/events", id)).await;
+    assert_eq!(status, 404);
+    let (_, list) = db.get("/api/geofences").await;
+    assert_eq!(list["geofences"], json!([]));
+}
+
+#[actix_web::test]
+async fn forgotten_trips_resume_from_their_events() {
+    let db = TestDb::new().await.with_ingestion().await;
+    let (_, fence) = db.post_admin("/api/geofences", json!({"name": "Depot", "polygon": square()})).await;
+    let id = fence["id"].as_i64().unwrap();
+    db.drive(7, &[(42.9, 76.7, 10.0, "2025-01-06T08:00:00Z"), (43.2, 76.7, 10.0, "2025-01-06T08:01:00Z")]).await;
+
+    // Idle trips are forgotten; the next point of one finds it inside from its entry
+    assert_eq!(db.geofences.forget_idle(std::time::Duration::ZERO), 1);
+    assert_eq!(db.get(&format!("/api/geofences/{}", id)).await.1["tripsInside"], 0);
+    db.drive(7, &[(43.6, 76.8, 10.0, "2025-01-06T09:00:00Z")]).await;
+    // A point from before that one arrives late and crosses nothing
+    db.drive(7, &[(43.2, 76.8, 10.0, "2025-01-06T08:30:00Z")]).await;
+
+    let (_, page) = db.get(&format!("/api/geofences/{}/events", id)).await;
+    let kinds: Vec<&str> = page["events"].as_array().unwrap().iter().map(|e| e["kind"].as_str().unwrap()).collect();
+    assert_eq!(kinds, vec!["entry", "exit"]);
+    assert_eq!(db.get(&format!("/api/geofences/{}", id)).await.1["tripsInside"], 0);
+}
+
+#[actix_web::test]
+async fn events_wait_for_the_publication_delay() {
+    let db = TestDb::new().await;
+    db.post_admin("/api/geofences", json!({"name": "Depot", "polygon": square()})).await;
+    let fences = Geofences::load(db.geofence_store.clone()).await.with_delay(PublicationDelay::new(chrono::Duration::minutes(10)));
+    let id = fences.all()[0].id;
+    let now = Utc::now();
+    fences.evaluate(None, 1, 1, &sample(43.2, 76.7, now - chrono::Duration::minutes(30))).await;
+    fences.evaluate(None, 2, 2, &sample(43.2, 76.7, now - chrono::Duration::minutes(5))).await;
+
+    let filter = GeofenceEventFilter { geofence_id: id, ..Default::default() };
+    let shown = fences.events(&filter, 10).await.unwrap();
+    assert_eq!(shown.iter().map(|e| e.randomized_id).collect::<Vec<_>>(), vec![1]);
+}
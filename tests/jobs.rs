@@ -0,0 +1,53 @@
+//! Long operations run as background jobs polled at `GET /api/jobs/{id}`; an import with
+//! `async=true` is one
+
+mod common;
+
+use common::TestDb;
+
+const GPX: &str = r#"<?xml version="1.0"?>
+<gpx version="1.1" xmlns="http://www.topografix.com/GPX/1/1">
+  <trk><trkseg>
+    <trkpt lat="51.10" lon="71.40"><time>2025-01-08T10:00:00Z</time></trkpt>
+    <trkpt lat="51.11" lon="71.41"><time>2025-01-08T10:01:00Z</time></trkpt>
+    <trkpt lat="51.12" lon="71.42"><time>2025-01-08T10:02:00Z</time></trkpt>
+  </trkseg></trk>
+</gpx>"#;
+
+#[actix_web::test]
+async fn async_import_is_polled_until_done() {
+    let db = TestDb::new().await.with_ingestion().await;
+    let (status, job) = db.post_raw("/api/points/import/gpx?async=true", "application/gpx+xml", GPX).await;
+    assert_eq!(status, 202, "{}", job);
+    assert_eq!(job["kind"], "import");
+    assert_eq!(job["params"]["format"], "gpx");
+
+    let job = db.finished(&format!("/api/jobs/{}", job["id"].as_str().unwrap())).await;
+    assert_eq!(job["status"], "done", "{}", job);
+    assert_eq!(job["progress"], 3);
+    assert!(job["startedAt"].is_string() && job["finishedAt"].is_string(), "{}", job);
+    let tracks = job["result"]["tracks"].as_array().unwrap();
+    assert_eq!(tracks.len(), 1);
+    assert_eq!(tracks[0]["points"], 3);
+    let (_, page) = db.get(&format!("/api/points?randomizedId={}", tracks[0]["randomized_id"])).await;
+    assert_eq!(page["total"], 3, "{}", page);
+
+    let (status, body) = db.get("/api/jobs/00000000-0000-0000-0000-000000000000").await;
+    assert_eq!(status, 404, "{}", body);
+}
+
+#[actix_web::test]
+async fn unreadable_files_fail_the_job() {
+    let db = TestDb::new().await.with_ingestion().await;
+    let (status, job) = db.post_raw("/api/points/import/kml?async=true", "application/vnd.google-earth.kml+xml", "<kml></kml>").await;
+    assert_eq!(status, 202, "{}", job);
+    let job = db.finished(&format!("/api/jobs/{}", job["id"].as_str().unwrap())).await;
+    assert_eq!(job["status"], "failed", "{}", job);
+    assert_eq!(job["error"], "No track points found");
+    assert!(job["result"].is_null());
+
+    // Without async=true the request still answers itself
+    let (status, body) = db.post_raw("/api/points/import/gpx", "application/gpx+xml", GPX).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["tracks"][0]["points"], 3);
+}
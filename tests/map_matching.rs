@@ -0,0 +1,41 @@
+//! Which trips the map-matching worker picks up, and how failed ones wait
+
+mod common;
+
+use chrono::{Duration, Utc};
+use common::{point, TestDb};
+use indrive::database::model::matched_trips::Model as MatchedTripModel;
+
+#[actix_web::test]
+async fn failed_trips_back_off_until_matched() {
+    let db = TestDb::new().await;
+    db.seed(vec![
+        point(1, 50.0, 70.0, 10.0, "2025-01-06T08:00:00Z"),
+        point(1, 50.1, 70.0, 10.0, "2025-01-06T08:01:00Z"),
+        point(2, 50.0, 70.0, 10.0, "2025-01-07T08:00:00Z"),
+        point(2, 50.1, 70.0, 10.0, "2025-01-07T08:01:00Z"),
+    ])
+    .await;
+    let pending = || async { db.trips.trips_to_match(Utc::now(), 1).await.unwrap().iter().map(|t| t.randomized_id).collect::<Vec<_>>() };
+    assert_eq!(pending().await, vec![1]);
+
+    // The oldest trip no longer heads every batch once it failed
+    assert_eq!(db.trips.record_match_failure(1, "InvalidUrl: too long", Duration::hours(1)).await.unwrap(), 1);
+    assert_eq!(pending().await, vec![2]);
+
+    // Due again: the count goes on
+    assert_eq!(db.trips.record_match_failure(1, "InvalidUrl: too long", Duration::zero()).await.unwrap(), 2);
+    assert_eq!(pending().await, vec![1]);
+
+    let matched = MatchedTripModel {
+        randomized_id: 1,
+        matched_at: Utc::now(),
+        source_point_count: 2,
+        confidence: 0.0,
+        distance_m: 0.0,
+        geometry: serde_json::json!([]),
+        road_node_ids: serde_json::json!([]),
+    };
+    db.trips.save_matched(matched).await.unwrap();
+    assert_eq!(db.trips.record_match_failure(1, "NoTrips", Duration::zero()).await.unwrap(), 1);
+}
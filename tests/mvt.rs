@@ -0,0 +1,116 @@
+//! Vector tiles from `indrive::mvt` decode back to their layers with the messages of the
+//! Mapbox Vector Tile spec
+
+use indrive::mvt::{self, Layer, RectFeature, Value};
+use prost::Message;
+
+#[derive(Clone, PartialEq, Message)]
+struct Tile {
+    #[prost(message, repeated, tag = "3")]
+    layers: Vec<TileLayer>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct TileLayer {
+    #[prost(uint32, required, tag = "15")]
+    version: u32,
+    #[prost(string, required, tag = "1")]
+    name: String,
+    #[prost(message, repeated, tag = "2")]
+    features: Vec<Feature>,
+    #[prost(string, repeated, tag = "3")]
+    keys: Vec<String>,
+    #[prost(message, repeated, tag = "4")]
+    values: Vec<TileValue>,
+    #[prost(uint32, optional, tag = "5")]
+    extent: Option<u32>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Feature {
+    #[prost(uint64, optional, tag = "1")]
+    id: Option<u64>,
+    #[prost(uint32, repeated, tag = "2")]
+    tags: Vec<u32>,
+    #[prost(int32, optional, tag = "3")]
+    geom_type: Option<i32>,
+    #[prost(uint32, repeated, tag = "4")]
+    geometry: Vec<u32>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct TileValue {
+    #[prost(double, optional, tag = "3")]
+    double_value: Option<f64>,
+    #[prost(uint64, optional, tag = "5")]
+    uint_value: Option<u64>,
+}
+
+fn rect(x0: i32, y0: i32, x1: i32, y1: i32, count: u64, speed: f64) -> RectFeature {
+    RectFeature { x0, y0, x1, y1, properties: vec![("count", Value::Uint(count)), ("speed", Value::Double(speed))] }
+}
+
+/// Corners of a polygon geometry: one MoveTo, one LineTo of three points, ClosePath
+fn corners(geometry: &[u32]) -> Vec<(i32, i32)> {
+    assert_eq!(geometry.len(), 11, "{:?}", geometry);
+    assert_eq!((geometry[0], geometry[3], geometry[10]), (1 | 1 << 3, 2 | 3 << 3, 7 | 1 << 3));
+    let unzigzag = |n: u32| ((n >> 1) as i32) ^ -((n & 1) as i32);
+    let deltas = [&geometry[1..3], &geometry[4..6], &geometry[6..8], &geometry[8..10]];
+    let (mut x, mut y) = (0, 0);
+    deltas
+        .iter()
+        .map(|d| {
+            x += unzigzag(d[0]);
+            y += unzigzag(d[1]);
+            (x, y)
+        })
+        .collect()
+}
+
+#[test]
+fn tiles_decode_back_to_their_features() {
+    let features = vec![rect(0, 0, 64, 64, 3, 12.5), rect(64, 0, 128, 64, 3, 40.0), rect(64, 64, 4096, 4096, 7, 12.5)];
+    let bytes = mvt::encode_tile(&[Layer { name: "heatmap".to_string(), extent: mvt::DEFAULT_EXTENT, features }]);
+
+    let tile = Tile::decode(bytes.as_slice()).unwrap();
+    assert_eq!(tile.layers.len(), 1);
+    let layer = &tile.layers[0];
+    assert_eq!((layer.version, layer.name.as_str(), layer.extent), (2, "heatmap", Some(4096)));
+    assert_eq!(layer.keys, ["count", "speed"]);
+    // Repeated values are stored once
+    assert_eq!(layer.values.len(), 4, "{:?}", layer.values);
+
+    let decoded: Vec<_> = layer
+        .features
+        .iter()
+        .map(|f| {
+            assert_eq!(f.geom_type, Some(3));
+            let property = |key: &str| {
+                let pair = f.tags.chunks(2).find(|t| layer.keys[t[0] as usize] == key).unwrap();
+                layer.values[pair[1] as usize].clone()
+            };
+            (corners(&f.geometry), property("count").uint_value.unwrap(), property("speed").double_value.unwrap())
+        })
+        .collect();
+    assert_eq!(decoded, [
+        (vec![(0, 0), (64, 0), (64, 64), (0, 64)], 3, 12.5),
+        (vec![(64, 0), (128, 0), (128, 64), (64, 64)], 3, 40.0),
+        (vec![(64, 64), (4096, 64), (4096, 4096), (64, 4096)], 7, 12.5),
+    ]);
+    assert_eq!(layer.features.iter().map(|f| f.id).collect::<Vec<_>>(), [Some(1), Some(2), Some(3)]);
+}
+
+#[test]
+fn uints_and_doubles_with_the_same_bits_stay_apart() {
+    let features = vec![RectFeature {
+        x0: 0,
+        y0: 0,
+        x1: 1,
+        y1: 1,
+        properties: vec![("a", Value::Uint(1.0f64.to_bits())), ("b", Value::Double(1.0))],
+    }];
+    let bytes = mvt::encode_tile(&[Layer { name: "l".to_string(), extent: 16, features }]);
+    let layer = &Tile::decode(bytes.as_slice()).unwrap().layers[0];
+    assert_eq!(layer.values.len(), 2);
+    assert_eq!(layer.features[0].tags, [0, 0, 1, 1]);
+}
@@ -0,0 +1,73 @@
+//! Ingestion stores on every point its distance, interval and speed from the previous point of
+//! its trip, and keeps them right when points arrive out of order
+
+mod common;
+
+use common::{point, TestDb};
+use indrive::database::store::{PointFilter, PointOrder, TripFilter, TripOrder};
+
+#[actix_web::test]
+async fn deltas_follow_the_time_order_of_the_trip() {
+    let db = TestDb::new().await;
+    // 0.01° of longitude at 50°N is about 715 m
+    db.seed(vec![
+        point(1, 50.0, 70.02, 10.0, "2025-01-06T08:02:00Z"),
+        point(1, 50.0, 70.0, 10.0, "2025-01-06T08:00:00Z"),
+        point(1, 50.0, 70.04, 10.0, "2025-01-06T08:04:00Z"),
+    ])
+    .await;
+    let late = db.store.insert(point(1, 50.0, 70.01, 10.0, "2025-01-06T08:01:00Z")).await.unwrap();
+    assert!((late.prev_distance_m.unwrap() - 715.0).abs() < 5.0, "{:?}", late);
+    assert_eq!(late.prev_interval_s, Some(60.0));
+    let next = db.store.insert(point(1, 50.0, 70.05, 10.0, "2025-01-06T08:05:00Z")).await.unwrap();
+    assert_eq!(next.prev_interval_s, Some(60.0));
+
+    let filter = PointFilter { randomized_id: Some(1), ..Default::default() };
+    let points = db.store.find(&filter, PointOrder::TimestampAsc, None).await.unwrap();
+    let intervals: Vec<Option<f64>> = points.iter().map(|p| p.prev_interval_s).collect();
+    assert_eq!(intervals, [None, Some(60.0), Some(60.0), Some(120.0), Some(60.0)]);
+    assert!(points[0].prev_distance_m.is_none() && points[0].derived_speed.is_none());
+    // The point after the late one now starts from it
+    assert!((points[2].prev_distance_m.unwrap() - 715.0).abs() < 5.0, "{:?}", points[2]);
+    for p in &points[1..] {
+        let speed = p.prev_distance_m.unwrap() / p.prev_interval_s.unwrap();
+        assert!((p.derived_speed.unwrap() - speed).abs() < 1e-9, "{:?}", p);
+    }
+
+    let trips = db.trips.find_trips(&TripFilter::default(), TripOrder::StartAsc, 10, 0).await.unwrap();
+    let total: f64 = points.iter().filter_map(|p| p.prev_distance_m).sum();
+    assert!((trips[0].distance_m - total).abs() < 1e-6, "{} != {}", trips[0].distance_m, total);
+}
+
+#[actix_web::test]
+async fn a_batch_extends_existing_and_new_trips() {
+    let db = TestDb::new().await;
+    db.seed(vec![
+        point(1, 50.0, 70.0, 10.0, "2025-01-06T08:00:00Z"),
+        point(1, 50.0, 70.01, 20.0, "2025-01-06T08:01:00Z"),
+    ])
+    .await;
+    // Trip 1 goes on, out of order within the batch, and trip 2 starts
+    db.seed(vec![
+        point(1, 50.0, 70.03, 40.0, "2025-01-06T08:03:00Z"),
+        point(2, 51.0, 71.0, 10.0, "2025-01-06T09:00:00Z"),
+        point(1, 50.0, 70.02, 30.0, "2025-01-06T08:02:00Z"),
+        point(2, 51.0, 71.01, 10.0, "2025-01-06T09:02:00Z"),
+    ])
+    .await;
+
+    for (trip, expected) in [(1, vec![None, Some(60.0), Some(60.0), Some(60.0)]), (2, vec![None, Some(120.0)])] {
+        let filter = PointFilter { randomized_id: Some(trip), ..Default::default() };
+        let points = db.store.find(&filter, PointOrder::TimestampAsc, None).await.unwrap();
+        let intervals: Vec<Option<f64>> = points.iter().map(|p| p.prev_interval_s).collect();
+        assert_eq!(intervals, expected, "trip {}", trip);
+    }
+
+    let trips = db.trips.find_trips(&TripFilter::default(), TripOrder::StartAsc, 10, 0).await.unwrap();
+    assert_eq!(trips.len(), 2);
+    assert_eq!((trips[0].point_count, trips[0].end_lng, trips[0].max_speed), (4, 70.03, 40.0));
+    assert_eq!(trips[0].avg_speed, 25.0);
+    assert_eq!(trips[0].duration_s, 180.0);
+    assert!((trips[0].distance_m - 3.0 * 715.0).abs() < 15.0, "{:?}", trips[0]);
+    assert_eq!(trips[1].point_count, 2);
+}
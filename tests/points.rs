@@ -0,0 +1,131 @@
+//! Ingestion at `POST /api/points`, and deleting points there, which takes the admin token
+
+mod common;
+
+use common::{point, TestDb};
+use indrive::api::points::{self, Ack};
+use indrive::database::store::NewPointRecord;
+use serde_json::json;
+
+#[actix_web::test]
+async fn deleting_points_takes_the_admin_token() {
+    let db = TestDb::new().await;
+    db.seed(vec![
+        point(1, 50.0, 70.0, 10.0, "2025-01-06T08:00:00Z"),
+        point(1, 50.0, 70.01, 10.0, "2025-01-06T08:01:00Z"),
+        point(2, 50.0, 70.0, 10.0, "2025-01-06T09:00:00Z"),
+    ])
+    .await;
+
+    let (status, _) = db.delete("/api/points?randomizedId=1").await;
+    assert_eq!(status, 401);
+    let (_, body) = db.get("/api/points?lat1=49&lng1=69&lat2=51&lng2=71").await;
+    assert_eq!(body["total"], 3, "{}", body);
+
+    let (status, body) = db.delete_admin("/api/points?randomizedId=1").await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["deleted"], 2);
+    let (_, body) = db.get("/api/points?lat1=49&lng1=69&lat2=51&lng2=71").await;
+    assert_eq!(body["total"], 1, "{}", body);
+}
+
+#[actix_web::test]
+async fn received_points_wait_in_the_wal() {
+    let path = std::env::temp_dir().join(format!("indrive-wal-{}.ndjson", uuid::Uuid::new_v4()));
+    let db = TestDb::new().await.with_ingestion().await.with_wal(path.clone());
+    let points = json!({"points": [
+        {"randomized_id": 1, "lat": 50.0, "lng": 70.0, "spd": 10.0, "azm": 0.0, "timestamp": "2025-01-06T08:00:00Z"},
+        {"randomized_id": 1, "lat": 50.0, "lng": 70.01, "spd": 10.0, "azm": 0.0, "timestamp": "2025-01-06T08:01:00Z"},
+    ]});
+    let (status, body) = db.post("/api/points?ack=received", points).await;
+    assert_eq!(status, 202, "{}", body);
+    assert_eq!(body["buffered"], 2);
+    assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 2);
+    let (_, body) = db.get("/api/points?lat1=49&lng1=69&lat2=51&lng2=71").await;
+    assert_eq!(body["total"], 0, "{}", body);
+
+    let ingestion = db.ingestion.as_ref().unwrap();
+    assert_eq!(ingestion.wal.as_ref().unwrap().replay(db.store.as_ref(), &ingestion.queue).await.unwrap(), 2);
+    assert!(!path.exists());
+    let (_, body) = db.get("/api/points?lat1=49&lng1=69&lat2=51&lng2=71").await;
+    assert_eq!(body["total"], 2, "{}", body);
+}
+
+#[actix_web::test]
+async fn received_points_need_a_wal_or_journal() {
+    let db = TestDb::new().await.with_ingestion().await;
+    let points = json!({"points": [{"randomized_id": 1, "lat": 50.0, "lng": 70.0, "spd": 10.0, "azm": 0.0, "timestamp": "2025-01-06T08:00:00Z"}]});
+    let (status, body) = db.post("/api/points?ack=received", points.clone()).await;
+    assert_eq!(status, 400, "{}", body);
+    let (status, body) = db.post("/api/points", points).await;
+    assert_eq!(status, 200, "{}", body);
+}
+
+#[actix_web::test]
+async fn batches_go_through_the_journal() {
+    let dir = std::env::temp_dir().join(format!("indrive-journal-{}", uuid::Uuid::new_v4()));
+    let db = TestDb::new().await.with_ingestion().await.with_journal(dir.clone());
+    let gpx = r#"<?xml version="1.0"?>
+<gpx version="1.1" xmlns="http://www.topografix.com/GPX/1/1">
+  <trk><trkseg>
+    <trkpt lat="50.00" lon="70.00"><time>2025-01-06T08:00:00Z</time></trkpt>
+    <trkpt lat="50.00" lon="70.01"><time>2025-01-06T08:01:00Z</time></trkpt>
+  </trkseg></trk>
+</gpx>"#;
+    let points = json!({"points": [{"randomized_id": 1, "lat": 50.0, "lng": 70.0, "spd": 10.0, "azm": 0.0, "timestamp": "2025-01-06T09:00:00Z"}]});
+
+    // Nothing is stored unless the batch is journaled first
+    std::fs::remove_dir(&dir).unwrap();
+    assert_eq!(db.post_raw("/api/points/import/gpx", "application/gpx+xml", gpx).await.0, 500);
+    assert_eq!(db.post("/api/points", points.clone()).await.0, 500);
+    let (_, body) = db.get("/api/points?lat1=49&lng1=69&lat2=51&lng2=71").await;
+    assert_eq!(body["total"], 0, "{}", body);
+
+    // Stored batches are trimmed from it
+    std::fs::create_dir(&dir).unwrap();
+    let (status, body) = db.post_raw("/api/points/import/gpx", "application/gpx+xml", gpx).await;
+    assert_eq!(status, 200, "{}", body);
+    let (status, body) = db.post("/api/points", points).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    let (_, body) = db.get("/api/points?lat1=49&lng1=69&lat2=51&lng2=71").await;
+    assert_eq!(body["total"], 3, "{}", body);
+    std::fs::remove_dir(&dir).unwrap();
+}
+
+#[actix_web::test]
+async fn redelivered_broker_messages_are_stored_once() {
+    let db = TestDb::new().await.with_ingestion().await;
+    let ingestion = db.ingestion.as_ref().unwrap();
+    let payload = br#"[{"lat": 50.0, "lng": 70.0, "spd": 10.0, "azm": 0.0, "timestamp": "2025-01-06T08:00:00Z"}]"#;
+    for _ in 0..2 {
+        let records = points::decode_message(payload, Some(7))
+            .unwrap()
+            .into_iter()
+            .map(|p| {
+                let mut record = NewPointRecord::from(p);
+                record.client_uuid = points::dedup_uuid(&record);
+                record
+            })
+            .collect();
+        ingestion.ingest(records, Ack::Persisted).await.unwrap();
+    }
+    let (_, body) = db.get("/api/points?lat1=49&lng1=69&lat2=51&lng2=71").await;
+    assert_eq!(body["total"], 1, "{}", body);
+}
+
+#[actix_web::test]
+async fn retried_quarantined_points_keep_one_row() {
+    let db = TestDb::new().await;
+    let mut record = point(1, 50.0, 70.0, 10.0, "2020-01-06T08:00:00Z");
+    record.client_uuid = Some(uuid::Uuid::new_v4());
+
+    let first = db.quarantined_points.quarantine(&record, "too old").await.unwrap();
+    let retried = db.quarantined_points.quarantine(&record, "too old").await.unwrap();
+    assert_eq!(retried.id, first.id);
+    // Points sent without a UUID cannot be told apart and are each kept
+    record.client_uuid = None;
+    db.quarantined_points.quarantine(&record, "too old").await.unwrap();
+    db.quarantined_points.quarantine(&record, "too old").await.unwrap();
+    assert_eq!(db.quarantined_points.count_quarantined().await.unwrap(), 3);
+}
@@ -0,0 +1,28 @@
+//! Ingestion rate limit buckets
+
+use indrive::rate_limit::{RateLimiter, MAX_CLIENTS};
+use indrive::tenant::TenantConfig;
+use std::sync::Arc;
+
+#[test]
+fn made_up_keys_share_the_address_bucket() {
+    let limiter = RateLimiter::new(1.0, 2.0, Arc::new(TenantConfig::default()));
+    for key in ["first", "second"] {
+        assert!(limiter.check(&limiter.client_for(Some(key), "10.0.0.1")).is_ok());
+    }
+    assert!(limiter.check(&limiter.client_for(Some("third"), "10.0.0.1")).is_err());
+    assert!(limiter.check(&limiter.client_for(None, "10.0.0.2")).is_ok());
+}
+
+#[test]
+fn clients_past_the_cap_share_one_bucket() {
+    let limiter = RateLimiter::new(1.0, 2.0, Arc::new(TenantConfig::default()));
+    for i in 0..MAX_CLIENTS {
+        assert!(limiter.check(&format!("ip:{}", i)).is_ok());
+    }
+    // None of those has refilled, so the newcomers take turns in the overflow bucket
+    assert!(limiter.check("ip:new-1").is_ok());
+    assert!(limiter.check("ip:new-2").is_ok());
+    assert!(limiter.check("ip:new-3").is_err());
+    assert!(limiter.check("ip:0").is_ok());
+}
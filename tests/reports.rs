@@ -0,0 +1,109 @@
+//! The weekly report of a finished week, written to disk and served by `/api/reports`
+
+mod common;
+
+use chrono::NaiveDate;
+use common::{anomalous, point, TestDb};
+use indrive::database::store::TenantScope;
+use indrive::reports::{self, ReportConfig};
+use indrive::tenant;
+
+fn config() -> ReportConfig {
+    let dir = std::env::temp_dir().join(format!("indrive-reports-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    ReportConfig { dir, tile_degrees: 0.01, top_tiles: 10, slow_speed: 3.0, pdf_command: None, check_secs: 3600, tenants: false }
+}
+
+#[actix_web::test]
+async fn a_finished_week_is_summarized_and_listed() {
+    let config = config();
+    let db = TestDb::new().await.with_reports(config.clone());
+    db.seed(vec![
+        // The week before: one trip
+        point(1, 50.005, 70.005, 10.0, "2024-12-31T08:00:00Z"),
+        // The week of 2025-01-06: two trips crawling through the same tile, one flagged
+        point(2, 50.005, 70.005, 1.0, "2025-01-06T08:00:00Z"),
+        point(2, 50.006, 70.006, 2.0, "2025-01-06T08:01:00Z"),
+        point(3, 50.005, 70.005, 1.0, "2025-01-08T09:00:00Z"),
+        anomalous(point(3, 50.5, 70.5, 50.0, "2025-01-08T09:05:00Z")),
+        // Sunday evening still belongs to the week
+        point(4, 51.005, 71.005, 20.0, "2025-01-12T23:59:00Z"),
+        point(5, 50.005, 70.005, 1.0, "2025-01-13T00:00:00Z"),
+    ])
+    .await;
+
+    let week = NaiveDate::from_ymd_opt(2025, 1, 6).unwrap();
+    assert_eq!(reports::last_finished_week("2025-01-13T00:00:00Z".parse().unwrap()), week);
+    assert_eq!(reports::last_finished_week("2025-01-19T23:59:59Z".parse().unwrap()), week);
+    let report = reports::generate(db.store.as_ref(), &config, week, None).await.unwrap();
+    assert_eq!(report.files.len(), 1);
+
+    let (status, body) = db.get("/api/reports").await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["reports"][0]["weekStart"], "2025-01-06");
+    assert_eq!(body["reports"][0]["weekEnd"], "2025-01-12");
+    let file = &body["reports"][0]["files"][0];
+    assert_eq!(file["format"], "html");
+
+    let (status, html) = db.get_bytes(file["url"].as_str().unwrap()).await;
+    assert_eq!(status, 200);
+    let html = String::from_utf8(html).unwrap();
+    // 5 points of 3 trips, up from 1 point of 1 trip
+    assert!(html.contains(r#"<div class="value">5</div> <span class="up">+400.0%</span>"#), "{}", html);
+    assert!(html.contains(r#"<div class="value">3</div> <span class="up">+200.0%</span>"#), "{}", html);
+    // The crawling tile leads with its 3 slow points of 2 trips
+    assert!(html.contains("50.0…50.01, 70.0…70.01"), "{}", html);
+    assert!(html.contains(r#"<td class="num">3</td>"#), "{}", html);
+    assert!(html.contains("<tr><td>не указана</td><td class=\"num\">1</td></tr>"), "{}", html);
+
+    let (status, _) = db.get("/api/reports/..%2Fsecret.html").await;
+    assert_eq!(status, 404);
+    let (status, _) = db.get("/api/reports/weekly-2025-01-13.html").await;
+    assert_eq!(status, 404);
+    std::fs::remove_dir_all(&config.dir).unwrap();
+}
+
+#[actix_web::test]
+async fn each_tenant_gets_reports_of_its_own_rows() {
+    let config = config().with_tenants(true);
+    let db = TestDb::new().await.tenant_scoped().with_reports(config.clone());
+    let almaty = Some(TenantScope::Tenant("almaty".to_string()));
+    tenant::scope(almaty.clone(), db.seed(vec![
+        point(1, 43.20, 76.90, 1.0, "2025-01-06T08:00:00Z"),
+        point(1, 43.21, 76.91, 1.0, "2025-01-06T08:01:00Z"),
+    ]))
+    .await;
+    tenant::scope(Some(TenantScope::Shared), db.seed(vec![point(2, 50.0, 70.0, 1.0, "2025-01-06T10:00:00Z")])).await;
+    assert_eq!(db.trips.tenants().await.unwrap(), vec!["almaty"]);
+
+    let week = NaiveDate::from_ymd_opt(2025, 1, 6).unwrap();
+    for scope in [config.scope(None), config.scope(Some("almaty"))] {
+        reports::generate(db.store.as_ref(), &config, week, scope.as_ref()).await.unwrap();
+    }
+
+    let (_, body) = tenant::scope(almaty.clone(), db.get("/api/reports")).await;
+    assert_eq!(body["reports"].as_array().unwrap().len(), 1, "{}", body);
+    let (status, html) = tenant::scope(almaty, db.get_bytes("/api/reports/weekly-2025-01-06.html")).await;
+    assert_eq!(status, 200);
+    let html = String::from_utf8(html).unwrap();
+    assert!(html.contains("43.2…43.21, 76.9…76.91"), "{}", html);
+    assert!(!html.contains("50.0…50.01"), "{}", html);
+
+    // Rows without a tenant have the reports at the top of the directory
+    let (_, html) = tenant::scope(Some(TenantScope::Shared), db.get_bytes("/api/reports/weekly-2025-01-06.html")).await;
+    let html = String::from_utf8(html).unwrap();
+    assert!(html.contains("50.0…50.01, 70.0…70.01"), "{}", html);
+    assert!(!html.contains("43.2…43.21"), "{}", html);
+    // A tenant without reports has none yet
+    let (status, body) = tenant::scope(Some(TenantScope::Tenant("astana".to_string())), db.get("/api/reports")).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["reports"].as_array().unwrap().len(), 0, "{}", body);
+    std::fs::remove_dir_all(&config.dir).unwrap();
+}
+
+#[actix_web::test]
+async fn reports_are_unavailable_without_a_directory() {
+    let db = TestDb::new().await;
+    let (status, body) = db.get("/api/reports").await;
+    assert_eq!(status, 503, "{}", body);
+}
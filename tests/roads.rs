@@ -0,0 +1,121 @@
+//! Speed limits from an OSM extract (`indrive import-roads`) and the speeding checks of
+//! `/api/speedmap/violations` against them
+
+mod common;
+
+use common::{point, TestDb};
+use indrive::roads::{parse_maxspeed, read_osm, zone_limit};
+
+/// A 60 km/h avenue along 50.5 N, a street with a zone limit and a footway without one
+const EXTRACT: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="50.5" lon="70.0"/>
+  <node id="2" lat="50.5" lon="70.5"/>
+  <node id="3" lat="50.5" lon="70.9"><tag k="highway" v="traffic_signals"/></node>
+  <node id="4" lat="51.5" lon="71.5"/>
+  <way id="10">
+    <nd ref="1"/><nd ref="2"/><nd ref="3"/>
+    <tag k="highway" v="primary"/><tag k="maxspeed" v="60"/><tag k="name" v="Abay Avenue"/>
+  </way>
+  <way id="11">
+    <nd ref="2"/><nd ref="4"/>
+    <tag k="highway" v="residential"/><tag k="maxspeed" v="RU:urban"/>
+  </way>
+  <way id="12"><nd ref="3"/><nd ref="4"/><tag k="highway" v="footway"/></way>
+  <way id="13"><nd ref="1"/><nd ref="4"/><tag k="building" v="yes"/></way>
+</osm>
+"#;
+
+fn read(extract: &str, country: Option<&str>) -> indrive::roads::OsmRoads {
+    let path = std::env::temp_dir().join(format!("indrive-roads-{}.osm", uuid::Uuid::new_v4()));
+    std::fs::write(&path, extract).unwrap();
+    let roads = read_osm(&path, country).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    roads
+}
+
+#[actix_web::test]
+async fn points_over_the_limit_are_flagged_by_tile_and_trip() {
+    let roads = read(EXTRACT, None);
+    assert_eq!((roads.ways, roads.implicit, roads.skipped, roads.segments.len()), (2, 1, 1, 3));
+    assert_eq!(roads.segments[1].name.as_deref(), Some("Abay Avenue"));
+    assert_eq!(roads.segments[2].speed_limit, 60.0);
+
+    let db = TestDb::new().await;
+    db.road_segments.replace_road_segments(roads.segments).await.unwrap();
+    db.seed(vec![
+        // 90 km/h on the avenue, 54 km/h next to it, and a point far from any road
+        point(1, 50.5, 70.25, 25.0, "2025-01-06T08:00:00Z"),
+        point(2, 50.5001, 70.6, 15.0, "2025-01-06T08:00:00Z"),
+        point(3, 51.2, 71.5, 30.0, "2025-01-06T08:00:00Z"),
+    ])
+    .await;
+
+    let area = "lat1=52&lng1=70&lat2=50&lng2=72";
+    let (status, body) = db.get(&format!("/api/speedmap/violations?{}&tileWidth=1&tileHeight=1", area)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!((body["points"].as_u64(), body["checked"].as_u64(), body["segments"].as_u64()), (Some(3), Some(2), Some(3)));
+    let tiles = body["tiles"].as_array().unwrap();
+    assert_eq!(tiles.len(), 1, "{}", body);
+    assert_eq!((tiles[0]["checked"].as_u64(), tiles[0]["violations"].as_u64()), (Some(2), Some(1)));
+    assert_eq!((tiles[0]["avgSpeed"].as_f64(), tiles[0]["speedLimit"].as_f64()), (Some(72.0), Some(60.0)));
+    assert_eq!(tiles[0]["exceeds"], true);
+
+    let (_, body) = db.get(&format!("/api/speedmap/violations?{}&tileWidth=1&tileHeight=1&margin=20", area)).await;
+    assert_eq!(body["tiles"][0]["exceeds"], false);
+    assert_eq!(body["tiles"][0]["violations"], 1);
+
+    let (status, body) = db.get(&format!("/api/speedmap/violations?{}&mode=trips", area)).await;
+    assert_eq!(status, 200, "{}", body);
+    let trips = body["trips"].as_array().unwrap();
+    assert_eq!(trips.len(), 1, "{}", body);
+    assert_eq!(trips[0]["randomizedId"], 1);
+    assert_eq!(trips[0]["maxExcess"].as_f64(), Some(30.0));
+    assert_eq!(trips[0]["worst"]["road"], "Abay Avenue");
+
+    let (status, _) = db.get(&format!("/api/speedmap/violations?{}&mode=roads", area)).await;
+    assert_eq!(status, 400);
+}
+
+#[test]
+fn maxspeed_values_are_read_in_km_h() {
+    assert_eq!(parse_maxspeed("60"), Some(60.0));
+    assert_eq!(parse_maxspeed("50;70"), Some(50.0));
+    assert_eq!(parse_maxspeed("80 km/h"), Some(80.0));
+    assert!((parse_maxspeed("30 mph").unwrap() - 48.28).abs() < 0.01);
+    assert_eq!(parse_maxspeed("RU:urban"), None);
+    assert_eq!(parse_maxspeed("none"), None);
+}
+
+#[test]
+fn roads_without_a_number_get_the_limit_of_their_zone_or_class() {
+    assert_eq!(zone_limit("RU:urban"), Some(60.0));
+    assert_eq!(zone_limit("kz:motorway"), Some(140.0));
+    assert_eq!(zone_limit("KZ:rural;RU:rural"), Some(90.0));
+    assert_eq!(zone_limit("DE:urban"), None);
+    assert_eq!(zone_limit("urban"), None);
+
+    let extract = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="43.20" lon="76.90"/>
+  <node id="2" lat="43.21" lon="76.91"/>
+  <way id="20"><nd ref="1"/><nd ref="2"/><tag k="highway" v="trunk"/><tag k="maxspeed" v="KZ:rural"/></way>
+  <way id="21"><nd ref="1"/><nd ref="2"/><tag k="highway" v="tertiary"/><tag k="maxspeed:type" v="KZ:urban"/></way>
+  <way id="22"><nd ref="1"/><nd ref="2"/><tag k="highway" v="motorway"/></way>
+  <way id="23"><nd ref="1"/><nd ref="2"/><tag k="highway" v="residential"/></way>
+  <way id="24"><nd ref="1"/><nd ref="2"/><tag k="highway" v="primary"/></way>
+  <way id="25"><nd ref="1"/><nd ref="2"/><tag k="highway" v="residential"/><tag k="maxspeed" v="walk"/></way>
+</osm>
+"#;
+    let limits = |roads: &indrive::roads::OsmRoads| roads.segments.iter().map(|s| (s.way_id, s.speed_limit)).collect::<Vec<_>>();
+
+    // Zone codes are read without a country; classes only with one
+    let roads = read(extract, None);
+    assert_eq!(limits(&roads), [(20, 90.0), (21, 60.0)]);
+    assert_eq!((roads.ways, roads.implicit, roads.skipped), (2, 2, 4));
+
+    // A primary road may be in town or not; `walk` is not a limit to check against
+    let roads = read(extract, Some("KZ"));
+    assert_eq!(limits(&roads), [(20, 90.0), (21, 60.0), (22, 140.0), (23, 60.0)]);
+    assert_eq!((roads.ways, roads.implicit, roads.skipped), (4, 4, 2));
+}
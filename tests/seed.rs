@@ -0,0 +1,57 @@
+//! `indrive seed` trips written through the store
+
+mod common;
+
+use chrono::{DateTime, Utc};
+use common::TestDb;
+use indrive::database::store::{BBox, PointFilter, PointOrder};
+use indrive::seed::{self, SeedConfig};
+
+const AREA: (f64, f64, f64, f64) = (53.15, 63.50, 53.28, 63.72);
+
+fn time(s: &str) -> Option<DateTime<Utc>> {
+    Some(s.parse().expect("RFC 3339 timestamp"))
+}
+
+fn config(rng_seed: u64) -> SeedConfig {
+    let bbox = BBox::from_corners(AREA.0, AREA.1, AREA.2, AREA.3);
+    SeedConfig::new(12, Some(bbox), time("2025-01-06T00:00:00Z"), time("2025-01-08T00:00:00Z"), Some(rng_seed)).expect("valid seed options")
+}
+
+#[actix_web::test]
+async fn seeded_trips_stay_in_the_area_and_period() {
+    let db = TestDb::new().await;
+    let config = config(7);
+    let stored = seed::run(db.store.as_ref(), &config).await.expect("seed");
+    assert!(stored >= 12 * 60, "{}", stored);
+
+    let all = db.store.find(&PointFilter::default(), PointOrder::TripThenTimestamp, None).await.unwrap();
+    assert_eq!(all.len() as u64, stored);
+    let inside = PointFilter { bbox: Some(BBox::from_corners(AREA.0, AREA.1, AREA.2, AREA.3)), since: Some(config.from), until: Some(config.to), ..Default::default() };
+    assert_eq!(db.store.count(&inside).await.unwrap(), stored);
+
+    let mut trips: Vec<i64> = all.iter().map(|p| p.randomized_id).collect();
+    trips.dedup();
+    assert_eq!(trips.len(), 12);
+    assert!(all.iter().all(|p| (0.0..70.0).contains(&p.spd)), "implausible speed");
+}
+
+#[actix_web::test]
+async fn the_same_rng_seed_gives_the_same_trips() {
+    let (a, b) = (TestDb::new().await, TestDb::new().await);
+    seed::run(a.store.as_ref(), &config(42)).await.expect("seed");
+    seed::run(b.store.as_ref(), &config(42)).await.expect("seed");
+    let rows = |db: &TestDb| {
+        let store = db.store.clone();
+        async move {
+            store.find(&PointFilter::default(), PointOrder::IdAsc, None).await.unwrap()
+                .into_iter()
+                .map(|p| (p.randomized_id, p.lat, p.lng, p.spd, p.timestamp))
+                .collect::<Vec<_>>()
+        }
+    };
+    assert_eq!(rows(&a).await, rows(&b).await);
+
+    assert!(SeedConfig::new(0, None, None, None, None).is_err());
+    assert!(SeedConfig::new(12, None, time("2025-01-08T00:00:00Z"), time("2025-01-06T00:00:00Z"), None).is_err());
+}
@@ -0,0 +1,54 @@
+//! Stop detection and clustering at `GET /api/stops`
+
+mod common;
+
+use common::{point, TestDb};
+
+const AREA: &str = "lat1=43.1&lng1=76.8&lat2=43.3&lng2=77.0";
+
+#[actix_web::test]
+async fn stops_are_standing_runs_that_last() {
+    let db = TestDb::new().await;
+    db.seed(vec![
+        point(1, 43.2, 76.9, 10.0, "2025-01-06T08:00:00Z"),
+        // Three minutes standing: a stop
+        point(1, 43.201, 76.9, 0.0, "2025-01-06T08:01:00Z"),
+        point(1, 43.201, 76.9, 0.5, "2025-01-06T08:04:00Z"),
+        point(1, 43.202, 76.9, 10.0, "2025-01-06T08:05:00Z"),
+        // One minute: too short
+        point(1, 43.203, 76.9, 0.0, "2025-01-06T08:06:00Z"),
+        point(1, 43.203, 76.9, 0.0, "2025-01-06T08:07:00Z"),
+        point(1, 43.204, 76.9, 10.0, "2025-01-06T08:08:00Z"),
+        // The tracker was off in between, so this is not a wait
+        point(1, 43.205, 76.9, 0.0, "2025-01-06T08:09:00Z"),
+        point(1, 43.205, 76.9, 0.0, "2025-01-06T08:30:00Z"),
+    ])
+    .await;
+
+    let (status, body) = db.get(&format!("/api/stops?{}&aggregate=clusters", AREA)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["stops"]["totalStops"], 1, "{}", body);
+    let cluster = &body["stops"]["clusters"][0];
+    assert_eq!(cluster["totalDwell"], 180.0);
+    assert_eq!(cluster["center"]["lat"], 43.201);
+}
+
+#[actix_web::test]
+async fn stops_within_the_radius_share_a_cluster() {
+    let db = TestDb::new().await;
+    // 119 m apart; sized by each stop's own latitude, their cells were two columns apart
+    let (a, b) = ((43.2, 76.90087), (43.2009, 76.90007));
+    db.seed(vec![
+        point(1, a.0, a.1, 0.0, "2025-01-06T08:00:00Z"),
+        point(1, a.0, a.1, 0.0, "2025-01-06T08:03:00Z"),
+        point(2, b.0, b.1, 0.0, "2025-01-06T09:00:00Z"),
+        point(2, b.0, b.1, 0.0, "2025-01-06T09:03:00Z"),
+    ])
+    .await;
+
+    let (status, body) = db.get(&format!("/api/stops?{}&aggregate=clusters&clusterRadiusMeters=150", AREA)).await;
+    assert_eq!(status, 200, "{}", body);
+    let clusters = body["stops"]["clusters"].as_array().unwrap();
+    assert_eq!(clusters.len(), 1, "{}", body);
+    assert_eq!((clusters[0]["count"].as_u64(), clusters[0]["trips"].as_u64()), (Some(2), Some(2)));
+}
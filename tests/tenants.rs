@@ -0,0 +1,57 @@
+//! Tenants sharing an instance only see their own points, trips and devices
+
+mod common;
+
+use common::{point, TestDb};
+use indrive::database::store::TenantScope;
+use indrive::tenant;
+use serde_json::json;
+
+fn of(name: &str) -> Option<TenantScope> {
+    Some(TenantScope::Tenant(name.to_string()))
+}
+
+#[actix_web::test]
+async fn tenants_only_see_their_own_rows() {
+    let db = TestDb::new().await.tenant_scoped();
+    tenant::scope(of("almaty"), db.seed(vec![
+        point(1, 43.20, 76.90, 10.0, "2025-01-06T08:00:00Z"),
+        point(1, 43.21, 76.91, 12.0, "2025-01-06T08:01:00Z"),
+    ]))
+    .await;
+    tenant::scope(of("astana"), db.seed(vec![point(2, 51.10, 71.40, 8.0, "2025-01-06T09:00:00Z")])).await;
+    tenant::scope(Some(TenantScope::Shared), db.seed(vec![point(3, 50.0, 70.0, 5.0, "2025-01-06T10:00:00Z")])).await;
+
+    let (status, body) = tenant::scope(of("almaty"), db.get("/api/trips")).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["total"], 1, "{}", body);
+    assert_eq!(body["trips"][0]["randomizedId"], 1);
+    let (_, body) = tenant::scope(of("almaty"), db.get("/api/points?lat1=40&lng1=60&lat2=60&lng2=80")).await;
+    assert_eq!(body["total"], 2, "{}", body);
+    let (_, body) = tenant::scope(of("almaty"), db.get("/api/stats/global")).await;
+    assert_eq!(body["totalPoints"], 2, "{}", body);
+
+    // Another tenant's trip is not there, not even by id
+    let (_, body) = tenant::scope(of("astana"), db.get("/api/trips?randomizedId=1")).await;
+    assert_eq!(body["total"], 0, "{}", body);
+    let (_, body) = tenant::scope(of("astana"), db.get("/api/trips")).await;
+    assert_eq!(body["total"], 1, "{}", body);
+    let (_, body) = tenant::scope(Some(TenantScope::Shared), db.get("/api/trips")).await;
+    assert_eq!(body["total"], 1, "{}", body);
+    assert_eq!(body["trips"][0]["randomizedId"], 3);
+
+    let (status, _) = tenant::scope(of("almaty"), db.post_admin("/api/devices", json!({"deviceId": "van-1"}))).await;
+    assert_eq!(status, 201);
+    // Each tenant has device ids of its own
+    let (status, body) = tenant::scope(of("astana"), db.post_admin("/api/devices", json!({"deviceId": "van-1", "name": "Van"}))).await;
+    assert_eq!(status, 201, "{}", body);
+    let (_, body) = tenant::scope(of("astana"), db.get("/api/devices")).await;
+    assert_eq!(body["total"], 1, "{}", body);
+    assert_eq!(body["devices"][0]["name"], "Van");
+    let (_, body) = tenant::scope(of("almaty"), db.get("/api/devices/van-1")).await;
+    assert!(body["name"].is_null(), "{}", body);
+
+    // Outside a request, as in the background workers, every row counts
+    let (_, body) = db.get("/api/trips").await;
+    assert_eq!(body["total"], 3, "{}", body);
+}
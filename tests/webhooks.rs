@@ -0,0 +1,92 @@
+//! Webhook deliveries are retried after server errors and signed with the webhook's secret,
+//! and anomalies reach the webhooks subscribed to them
+
+mod common;
+
+use common::{point, MockAnswer, MockServer, TestDb};
+use indrive::anomaly::{AnomalyDetector, AnomalyFeed, ClassificationQueue};
+use indrive::database::store::WebhookSettings;
+use indrive::webhooks::{self, RetryConfig, WebhookEvent, Webhooks};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A receiver answering each request with the next of `answers`
+async fn receiver(answers: &[(u16, &str)]) -> MockServer {
+    MockServer::start(answers.iter().map(|(status, body)| MockAnswer::new(*status, *body)).collect()).await
+}
+
+#[tokio::test]
+async fn deliveries_are_retried_and_signed() {
+    let db = TestDb::new().await;
+    let receiver = receiver(&[(503, ""), (500, ""), (200, "-1")]).await;
+    let retry = RetryConfig { max_attempts: 3, backoff: Duration::from_millis(10), timeout: Duration::from_secs(5) };
+    let hooks = Webhooks::load(db.webhooks.clone(), retry).await;
+    let settings = WebhookSettings { url: format!("{}/hook", receiver.url), events: vec!["classify".into()], secret: Some("s3cret".into()), enabled: true };
+    hooks.create(&settings).await.unwrap();
+    assert!(hooks.call(WebhookEvent::Anomaly, &json!({})).await.is_none(), "nobody subscribes to anomaly");
+
+    let answer = hooks.call(WebhookEvent::Classify, &json!({"point": 1})).await;
+    assert_eq!(answer, Some(Ok("-1".to_string())));
+    let seen = receiver.requests();
+    assert_eq!(seen.len(), 3);
+    let deliveries: Vec<_> = seen.iter().map(|r| r.header("x-webhook-delivery").unwrap()).collect();
+    assert!(deliveries.iter().all(|d| *d == deliveries[0]), "one delivery id across retries: {:?}", deliveries);
+    for request in &seen {
+        assert_eq!((request.method.as_str(), request.path.as_str()), ("POST", "/hook"));
+        assert_eq!(request.header("x-webhook-event"), Some("classify"));
+        let timestamp: i64 = request.header("x-webhook-timestamp").unwrap().parse().unwrap();
+        assert_eq!(request.header("x-webhook-signature"), Some(webhooks::signature("s3cret", timestamp, &request.body).as_str()));
+    }
+}
+
+#[tokio::test]
+async fn client_errors_are_not_retried() {
+    let db = TestDb::new().await;
+    let receiver = receiver(&[(404, ""), (200, "1")]).await;
+    let retry = RetryConfig { max_attempts: 3, backoff: Duration::from_millis(10), timeout: Duration::from_secs(5) };
+    let hooks = Webhooks::load(db.webhooks.clone(), retry).await;
+    hooks.create(&WebhookSettings { url: receiver.url.clone(), events: vec!["classify".into()], secret: None, enabled: true }).await.unwrap();
+
+    assert!(matches!(hooks.call(WebhookEvent::Classify, &json!({})).await, Some(Err(_))));
+    let seen = receiver.requests();
+    assert_eq!(seen.len(), 1);
+    assert_eq!(seen[0].header("x-webhook-signature"), None, "unsigned without a secret");
+}
+
+#[tokio::test]
+async fn a_trip_is_reported_once_on_its_first_anomaly() {
+    let db = TestDb::new().await;
+    let receiver = receiver(&[(200, "")]).await;
+    let retry = RetryConfig { max_attempts: 1, backoff: Duration::from_millis(10), timeout: Duration::from_secs(5) };
+    let hooks = Arc::new(Webhooks::load(db.webhooks.clone(), retry).await);
+    let events = vec!["anomaly".into(), "trip_anomaly".into()];
+    hooks.create(&WebhookSettings { url: receiver.url.clone(), events, secret: None, enabled: true }).await.unwrap();
+    let detector = Arc::new(AnomalyDetector::from_env(hooks.clone()).expect("anomaly detector"));
+    let feed = Arc::new(AnomalyFeed::new(10, Duration::from_secs(15)));
+    let queue = ClassificationQueue::spawn(db.store.clone(), detector, hooks, feed.clone(), db.geofences.clone(), db.alerts.clone());
+
+    // Two jumps of a degree within a minute
+    for (lat, ts) in [(43.20, "2025-01-06T08:00:00Z"), (44.20, "2025-01-06T08:01:00Z"), (43.20, "2025-01-06T08:02:00Z")] {
+        let (_, decided) = queue.ingest_watched(db.store.as_ref(), point(7, lat, 76.90, 10.0, ts)).await.unwrap();
+        decided.await.unwrap();
+    }
+    for _ in 0..100 {
+        if receiver.requests().len() == 3 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let seen = receiver.requests();
+    let of = |event: &str| seen.iter().filter(|r| r.header("x-webhook-event") == Some(event)).collect::<Vec<_>>();
+    assert_eq!(of("anomaly").len(), 2);
+    let trips = of("trip_anomaly");
+    assert_eq!(trips.len(), 1);
+    let trip: Value = serde_json::from_slice(&trips[0].body).unwrap();
+    assert_eq!(trip["randomizedId"], 7, "{}", trip);
+    assert_eq!(trip["reason"], "teleport", "{}", trip);
+    assert_eq!(trip["bbox"]["latMax"], 44.2, "{}", trip);
+    assert_eq!(trip["start"], "2025-01-06T08:00:00Z", "{}", trip);
+    assert_eq!(trip["end"], "2025-01-06T08:01:00Z", "{}", trip);
+    assert_eq!(feed.since(None).len(), 1, "the stream gets the trip too");
+}
@@ -5,31 +5,36 @@ import {
 	Heatmap,
 	HeatmapResponse,
 } from "../types/heatmap";
+import { ApiError, getHeatmap as fetchHeatmap, getSpeedmap, getTraficmap } from "./client";
+
+const endpoints = {
+	heatmap: fetchHeatmap,
+	trafficmap: getTraficmap,
+	speedmap: getSpeedmap,
+};
 
 export default async function getHeatmap(
 	req: HeatmapRequest
 ): Promise<HeatmapResponse | { error: string }> {
-	const params = new URLSearchParams();
-	params.set("lat1", req.area.topLeft.lat.toString());
-	params.set("lng1", req.area.topLeft.lng.toString());
-	params.set("lat2", req.area.bottomRight.lat.toString());
-	params.set("lng2", req.area.bottomRight.lng.toString());
-	params.set("tileWidth", req.tileWidth.toString());
-	params.set("tileHeight", req.tileHeight.toString());
-	if (req.timeStart) params.set("timeStart", req.timeStart);
-	if (req.timeEnd) params.set("timeEnd", req.timeEnd);
-	if (req.dateStart) params.set("dateStart", req.dateStart);
-	if (req.dateEnd) params.set("dateEnd", req.dateEnd);
-	if (req.daysOfWeek && req.daysOfWeek.length > 0)
-		params.set("daysOfWeek", req.daysOfWeek.join(","));
-	const response = await fetch(`/api/${req.heatmapType}/?` + params.toString(), {
-		method: "GET",
-	});
-	if (!response.ok) {
-		return { error: "Failed to fetch heatmap" };
+	try {
+		const data = await endpoints[req.heatmapType]({
+			lat1: req.area.topLeft.lat,
+			lng1: req.area.topLeft.lng,
+			lat2: req.area.bottomRight.lat,
+			lng2: req.area.bottomRight.lng,
+			tileWidth: req.tileWidth,
+			tileHeight: req.tileHeight,
+			timeStart: req.timeStart,
+			timeEnd: req.timeEnd,
+			dateStart: req.dateStart,
+			dateEnd: req.dateEnd,
+			// The API numbers weekdays from 1
+			days: req.daysOfWeek?.map((d) => d + 1).join(","),
+		});
+		return data as unknown as HeatmapResponse;
+	} catch (e) {
+		return { error: e instanceof ApiError ? e.message : "Failed to fetch heatmap" };
 	}
-	const data = await response.json();
-	return data as HeatmapResponse;
 }
 
 export function makeRequest(
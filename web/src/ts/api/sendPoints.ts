@@ -1,13 +1,7 @@
 import { MapPoint } from "../types/common";
+import { ApiError, PointListRequest, pushPoints } from "./client";
 
-export type TimedPoint = {
-	randomized_id: number;
-	timestamp: Date;
-	spd: number;
-	azm: number;
-} & MapPoint;
-
-export type PostPoints = { points: TimedPoint[] };
+export type PostPoints = PointListRequest;
 
 export function makeRequest(
 	points: MapPoint[],
@@ -39,7 +33,7 @@ export function makeRequest(
 			return {
 				randomized_id: id,
 				...point,
-				timestamp: curDate,
+				timestamp: curDate.toISOString(),
 				spd:velocity,
 				azm:azimuth,
 			};
@@ -50,15 +44,10 @@ export function makeRequest(
 export async function sendPoints(
 	data: PostPoints
 ): Promise<{ success: boolean; error?: string }> {
-	const response = await fetch("/api/points/", {
-		method: "POST",
-		headers: {
-			"Content-Type": "application/json",
-		},
-		body: JSON.stringify(data),
-	});
-	if (!response.ok) {
-		return { success: false, error: "Failed to send points" };
+	try {
+		await pushPoints({}, data);
+	} catch (e) {
+		return { success: false, error: e instanceof ApiError ? e.message : "Failed to send points" };
 	}
 	return { success: true };
 }